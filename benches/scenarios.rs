@@ -0,0 +1,81 @@
+//! Benchmarks on the generated scenarios of `transport::planning::scenarios`: how long
+//! materializing their plans takes, and how long the tick loop takes once they are
+//! loaded, with the same setup as the game itself (which, like comparison runs, opens
+//! a window). Run with `cargo bench --bench scenarios`.
+extern crate citybound;
+extern crate kay;
+#[macro_use]
+extern crate criterion;
+
+use citybound::core;
+use citybound::core::simulation::SimulationConfig;
+use citybound::transport::event_log;
+use citybound::transport::construction::materialized_reality::{self, MaterializedRealityID};
+use citybound::transport::planning::current_plan::CurrentPlanID;
+use citybound::transport::planning::plan::PlanDelta;
+use citybound::transport::planning::scenarios::Scenario;
+use criterion::Criterion;
+use kay::ActorSystem;
+
+/// Ticks simulated after loading and before measuring, so that routes are learned
+/// and the first trips are on their way
+const WARMUP_TICKS: usize = 600;
+
+const GRID: Scenario = Scenario::Grid { blocks: 4, block_size: 100.0 };
+const RADIAL: Scenario = Scenario::Radial { rings: 3, spokes: 8, ring_spacing: 120.0 };
+const SUBURBS: Scenario = Scenario::Suburbs { streets: 10, extent: 600.0, seed: 42 };
+
+fn bench_materialize(c: &mut Criterion) {
+    for &(name, scenario) in &[("grid", GRID), ("radial", RADIAL), ("suburbs", SUBURBS)] {
+        let plan = scenario.generate();
+        c.bench_function(&format!("materialize {}", name), move |b| {
+            b.iter(|| plan.get_result())
+        });
+    }
+}
+
+/// Sets up the game, loads `scenario` and warms it up, then measures single ticks
+/// like the main loop does them
+fn bench_ticks_of(c: &mut Criterion, name: &str, scenario: Scenario) {
+    let mut system = Box::new(ActorSystem::new(
+        core::init::create_init_callback(),
+        kay::Networking::new(0, Vec::new()),
+    ));
+    let world = &mut system.world();
+    let (simulation, _, _) = citybound::setup(&mut system, core::init::build_window(0));
+    system.process_all_messages();
+
+    MaterializedRealityID::global_first(world).stream(
+        CurrentPlanID::local_first(world),
+        PlanDelta { new_strokes: scenario.generate().strokes, ..PlanDelta::default() },
+        world,
+    );
+    system.process_all_messages();
+    while materialized_reality::is_loading() {
+        MaterializedRealityID::global_first(world).build_streamed(world);
+        system.process_all_messages();
+    }
+
+    system.set_worker_threads(SimulationConfig::current().tick_threads);
+
+    let mut tick = move || {
+        simulation.do_tick(world);
+        system.process_all_messages();
+        event_log::write_logged();
+    };
+
+    for _ in 0..WARMUP_TICKS {
+        tick();
+    }
+
+    c.bench_function(&format!("tick {}", name), move |b| b.iter(|| tick()));
+}
+
+fn bench_ticks(c: &mut Criterion) {
+    bench_ticks_of(c, "grid", GRID);
+    bench_ticks_of(c, "radial", RADIAL);
+    bench_ticks_of(c, "suburbs", SUBURBS);
+}
+
+criterion_group!(benches, bench_materialize, bench_ticks);
+criterion_main!(benches);
@@ -294,6 +294,14 @@ impl SizedChunkedCollection for SizedChunkedArena {
     }
 }
 
+impl Drop for SizedChunkedArena {
+    fn drop(&mut self) {
+        while !self.chunks.is_empty() {
+            self.pop_chunk();
+        }
+    }
+}
+
 /// A vector which stores items of a known type in a `SizedChunkedArena`
 pub struct ChunkedVec<Item: Clone> {
     arena: SizedChunkedArena,
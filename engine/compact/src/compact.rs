@@ -15,6 +15,12 @@ pub trait Compact: Sized + Clone {
         self.dynamic_size_bytes() + mem::size_of::<Self>()
     }
 
+    /// Bytes of the dynamic part that are reserved but not used (yet),
+    /// like the spare capacity of vectors
+    fn unused_dynamic_bytes(&self) -> usize {
+        0
+    }
+
     /// Copy the static part of `source` to `dest` and compactly store
     /// the dynamic part of `source` as the new dynamic part of `dest` at `new_dynamic_part`.
     /// This semantically moves source into dest.
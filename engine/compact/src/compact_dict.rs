@@ -174,6 +174,10 @@ impl<K: Copy, V: Compact + Clone, A: Allocator> Compact for CompactDict<K, V, A>
         self.keys.dynamic_size_bytes() + self.values.dynamic_size_bytes()
     }
 
+    fn unused_dynamic_bytes(&self) -> usize {
+        self.keys.unused_dynamic_bytes() + self.values.unused_dynamic_bytes()
+    }
+
     unsafe fn compact(source: *mut Self, dest: *mut Self, new_dynamic_part: *mut u8) {
         let values_offset = (*source).keys.dynamic_size_bytes() as isize;
         Compact::compact(&mut (*source).keys, &mut (*dest).keys, new_dynamic_part);
@@ -162,6 +162,12 @@ impl<K: Copy, V: Compact> Compact for Entry<K, V> {
         })
     }
 
+    default fn unused_dynamic_bytes(&self) -> usize {
+        self.inner.as_ref().map_or(0, |kv_tuple| {
+            kv_tuple.1.unused_dynamic_bytes()
+        })
+    }
+
     default unsafe fn compact(source: *mut Self, dest: *mut Self, new_dynamic_part: *mut u8) {
         (*dest).hash = (*source).hash;
         (*dest).tombstoned = (*source).tombstoned;
@@ -376,6 +382,10 @@ impl<T: Compact + Sized, A: Allocator> Compact for CompactArray<T, A> {
                 .sum::<usize>()
     }
 
+    default fn unused_dynamic_bytes(&self) -> usize {
+        self.iter().map(|elem| elem.unused_dynamic_bytes()).sum::<usize>()
+    }
+
     default unsafe fn compact(source: *mut Self, dest: *mut Self, new_dynamic_part: *mut u8) {
         (*dest).cap = (*source).cap;
         (*dest).ptr.set_to_compact(new_dynamic_part as *mut T);
@@ -692,6 +702,12 @@ impl<K: Copy + Eq + Hash, V: Compact, A: Allocator> Compact for OpenAddressingMa
         self.entries.dynamic_size_bytes()
     }
 
+    default fn unused_dynamic_bytes(&self) -> usize {
+        // free entries are reserved for future insertions
+        (self.entries.cap - self.size) * ::std::mem::size_of::<Entry<K, V>>() +
+            self.entries.unused_dynamic_bytes()
+    }
+
     default unsafe fn compact(source: *mut Self, dest: *mut Self, new_dynamic_part: *mut u8) {
         (*dest).size = (*source).size;
         Compact::compact(
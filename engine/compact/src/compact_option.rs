@@ -31,6 +31,10 @@ impl<T: Clone + Compact> Compact for CompactOption<T> {
         self.0.as_ref().map(|t| t.dynamic_size_bytes()).unwrap_or(0)
     }
 
+    fn unused_dynamic_bytes(&self) -> usize {
+        self.0.as_ref().map(|t| t.unused_dynamic_bytes()).unwrap_or(0)
+    }
+
     unsafe fn compact(source: *mut Self, dest: *mut Self, new_dynamic_part: *mut u8) {
         if let CompactOption(Some(ref mut s)) = *source {
             ::std::ptr::write(dest, CompactOption(Some(::std::mem::uninitialized())));
@@ -365,6 +365,13 @@ impl<T: Compact + Clone, A: Allocator> Compact for CompactVec<T, A> {
                 .sum::<usize>()
     }
 
+    default fn unused_dynamic_bytes(&self) -> usize {
+        (self.cap - self.len) * ::std::mem::size_of::<T>() +
+            self.iter()
+                .map(|elem| elem.unused_dynamic_bytes())
+                .sum::<usize>()
+    }
+
     default unsafe fn compact(source: *mut Self, dest: *mut Self, new_dynamic_part: *mut u8) {
         (*dest).len = (*source).len;
         (*dest).cap = (*source).cap;
@@ -416,6 +423,10 @@ impl<T: Copy, A: Allocator> Compact for CompactVec<T, A> {
         self.cap * ::std::mem::size_of::<T>()
     }
 
+    fn unused_dynamic_bytes(&self) -> usize {
+        (self.cap - self.len) * ::std::mem::size_of::<T>()
+    }
+
     unsafe fn compact(source: *mut Self, dest: *mut Self, new_dynamic_part: *mut u8) {
         (*dest).len = (*source).len;
         (*dest).cap = (*source).cap;
@@ -535,3 +546,12 @@ fn nested_vector() {
         DefaultHeap::deallocate(storage, bytes);
     }
 }
+
+#[test]
+fn unused_capacity() {
+    let mut list: CompactVec<u32> = CompactVec::with_capacity(8);
+    list.push(1);
+    list.push(2);
+
+    assert_eq!(6 * ::std::mem::size_of::<u32>(), list.unused_dynamic_bytes());
+}
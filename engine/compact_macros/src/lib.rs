@@ -92,6 +92,10 @@ fn expand_derive_compact(ast: &syn::MacroInput) -> quote::Tokens {
                         #(self.#fields_ref.dynamic_size_bytes())+*
                     }
 
+                    fn unused_dynamic_bytes(&self) -> usize {
+                        #(self.#fields_ref.unused_dynamic_bytes())+*
+                    }
+
                     #[allow(unused_assignments)]
                     unsafe fn compact(
                         source: *mut Self, dest: *mut Self, new_dynamic_part: *mut u8
@@ -156,6 +160,26 @@ fn expand_derive_compact(ast: &syn::MacroInput) -> quote::Tokens {
                 })
                 .collect();
 
+            let variants_unused_dynamic: &Vec<_> = &data.iter()
+                .map(|variant| {
+                    let ident = &variant.ident;
+                    let fields = get_field_idents(&variant.data, "f");
+                    let fields_ref = &fields;
+
+                    if fields.is_empty() {
+                        quote! {
+                            #name::#ident => 0
+                        }
+                    } else {
+                        quote! {
+                            #name::#ident(#(ref #fields_ref),*) => {
+                                #(#fields_ref.unused_dynamic_bytes())+*
+                            }
+                        }
+                    }
+                })
+                .collect();
+
             let variants_compact_to: &Vec<_> = &data.iter()
                 .map(|variant| {
                     let ident = &variant.ident;
@@ -228,6 +252,13 @@ fn expand_derive_compact(ast: &syn::MacroInput) -> quote::Tokens {
                         }
                     }
 
+                    #[allow(match_same_arms)]
+                    fn unused_dynamic_bytes(&self) -> usize {
+                        match *self {
+                            #(#variants_unused_dynamic),*
+                        }
+                    }
+
                     #[allow(unused_assignments)]
                     #[allow(match_same_arms)]
                     unsafe fn compact(
@@ -279,6 +310,12 @@ fn basic_struct() {
                     self.truth.dynamic_size_bytes()
             }
 
+            fn unused_dynamic_bytes(&self) -> usize {
+                self.number.unused_dynamic_bytes() +
+                    self.list.unused_dynamic_bytes() +
+                    self.truth.unused_dynamic_bytes()
+            }
+
             #[allow(unused_assignments)]
             unsafe fn compact(source: *mut Self, dest: *mut Self, new_dynamic_part: *mut u8) {
                 let mut offset: isize = 0;
@@ -364,6 +401,20 @@ fn basic_enum() {
                 }
             }
 
+            #[allow(match_same_arms)]
+            fn unused_dynamic_bytes(&self) -> usize {
+                match *self {
+                    Test2::A(ref f0, ref f1, ref f2) => {
+                        f0.unused_dynamic_bytes()
+                            + f1.unused_dynamic_bytes() + f2.unused_dynamic_bytes()
+                    },
+                    Test2::B(ref f0) => {
+                        f0.unused_dynamic_bytes()
+                    },
+                    Test2::C => 0
+                }
+            }
+
             #[allow(unused_assignments)]
             #[allow(match_same_arms)]
             unsafe fn compact(source: *mut Self, dest: *mut Self, new_dynamic_part: *mut u8) {
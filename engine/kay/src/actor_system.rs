@@ -2,11 +2,15 @@ use compact::Compact;
 use std::mem::size_of;
 use super::messaging::{Message, Packet, Fate};
 use super::inbox::{Inbox, DispatchablePacket};
-use super::id::ID;
+use super::id::{ID, broadcast_instance_id};
 use super::type_registry::{ShortTypeId, TypeRegistry};
-use super::swarm::Swarm;
+use super::swarm::{Swarm, SwarmSnapshot};
 use super::networking::Networking;
+use super::worker_pool::WorkerPool;
+use super::migration::{Schema, MigrationError};
 use std::any::Any;
+use std::cell::Cell;
+use std::collections::HashMap;
 use std::panic::{AssertUnwindSafe, catch_unwind};
 
 /// Trait that allows dynamically sized `Actor` instances to provide
@@ -40,6 +44,54 @@ struct Dispatcher {
     critical: bool,
 }
 
+struct Snapshotter {
+    type_id: ShortTypeId,
+    take: Box<Fn() -> Box<Any>>,
+    restore: Box<Fn(&Any)>,
+}
+
+/// Copies of the states of all restorable actor instances at one point in time,
+/// see [`ActorSystem::snapshot`](struct.ActorSystem.html#method.snapshot)
+pub struct Snapshot {
+    swarms: Vec<(ShortTypeId, Box<Any>)>,
+}
+
+impl Snapshot {
+    /// The copied instances of a restorable Actor type, in no particular order.
+    /// Empty for Actor types that are not restorable.
+    pub fn instances<A: Actor>(&self) -> &[A] {
+        self.swarms
+            .iter()
+            .filter_map(|&(_, ref swarm_snapshot)| {
+                swarm_snapshot.downcast_ref::<SwarmSnapshot<A>>()
+            })
+            .next()
+            .map_or(&[], |swarm_snapshot| swarm_snapshot.instances())
+    }
+}
+
+/// Memory used by the instances of an Actor type,
+/// see [`ActorSystem::get_memory_usage`](struct.ActorSystem.html#method.get_memory_usage)
+#[derive(Clone, Debug)]
+pub struct MemoryUsage {
+    pub actor_type: String,
+    pub instances: usize,
+    /// Static and dynamic parts of all instances
+    pub total_bytes: usize,
+    /// Reserved but unused space in the instances' vectors and maps
+    pub unused_bytes: usize,
+}
+
+impl MemoryUsage {
+    pub fn bytes_per_instance(&self) -> usize {
+        if self.instances == 0 {
+            0
+        } else {
+            self.total_bytes / self.instances
+        }
+    }
+}
+
 const MAX_RECIPIENT_TYPES: usize = 64;
 const MAX_MESSAGE_TYPES: usize = 256;
 
@@ -54,11 +106,19 @@ pub struct ActorSystem {
     panic_happened: bool,
     panic_callback: Box<Fn(Box<Any>, &mut World)>,
     inboxes: [Option<Inbox>; MAX_RECIPIENT_TYPES],
+    /// For prioritized messages, created when the first one is sent to an Actor type
+    priority_inboxes: [Option<Inbox>; MAX_RECIPIENT_TYPES],
     actor_registry: TypeRegistry,
     swarms: [Option<*mut u8>; MAX_RECIPIENT_TYPES],
     message_registry: TypeRegistry,
     dispatchers: [[Option<Dispatcher>; MAX_MESSAGE_TYPES]; MAX_RECIPIENT_TYPES],
     actors_as_countables: Vec<(String, *const InstancesCountable)>,
+    snapshotters: Vec<Snapshotter>,
+    id_cast_counts: Option<HashMap<&'static str, usize>>,
+    /// Handles parallelized broadcasts, if there should be more than one thread for them
+    worker_pool: Option<WorkerPool>,
+    parallel_handlers: Vec<(ShortTypeId, ShortTypeId)>,
+    priority_messages: [bool; MAX_MESSAGE_TYPES],
     networking: Networking,
 }
 
@@ -89,6 +149,7 @@ impl ActorSystem {
             panic_happened: false,
             panic_callback: panic_callback,
             inboxes: unsafe { make_array!(MAX_RECIPIENT_TYPES, |_| None) },
+            priority_inboxes: unsafe { make_array!(MAX_RECIPIENT_TYPES, |_| None) },
             actor_registry: TypeRegistry::new(),
             message_registry: TypeRegistry::new(),
             swarms: [None; MAX_RECIPIENT_TYPES],
@@ -98,6 +159,11 @@ impl ActorSystem {
                 })
             },
             actors_as_countables: Vec::new(),
+            snapshotters: Vec::new(),
+            id_cast_counts: None,
+            worker_pool: None,
+            parallel_handlers: Vec::new(),
+            priority_messages: [false; MAX_MESSAGE_TYPES],
             networking,
         }
     }
@@ -121,6 +187,71 @@ impl ActorSystem {
         ));
     }
 
+    /// Register the current schema version of an already registered Actor type and
+    /// how to upgrade instances from older versions. If the Actor's instances were
+    /// persisted with an older schema version, they are upgraded right away.
+    pub fn register_schema<A: Actor + Clone>(
+        &mut self,
+        schema: Schema,
+    ) -> Result<(), MigrationError> {
+        let actor_id = self.actor_registry.get::<A>();
+        let swarm = self.swarms[actor_id.as_usize()].expect(
+            "Actor type has to be registered before its schema",
+        ) as *mut Swarm<A>;
+        unsafe { (*swarm).migrate(&schema) }
+    }
+
+    /// Include the instances of an already registered Actor type in snapshots,
+    /// see [`snapshot`](#method.snapshot). Actor types with `External` state
+    /// can't be made restorable, since their state can't be copied.
+    pub fn make_restorable<A: Actor + Clone>(&mut self) {
+        let actor_id = self.actor_registry.get::<A>();
+        let swarm = self.swarms[actor_id.as_usize()].expect(
+            "Actor type has to be registered before it can be made restorable",
+        ) as *mut Swarm<A>;
+        self.snapshotters.push(Snapshotter {
+            type_id: actor_id,
+            take: Box::new(move || unsafe { Box::new((*swarm).snapshot()) as Box<Any> }),
+            restore: Box::new(move |snapshot: &Any| unsafe {
+                let snapshot = snapshot.downcast_ref::<SwarmSnapshot<A>>().expect(
+                    "Snapshot of wrong actor type",
+                );
+                (*swarm).restore(snapshot)
+            }),
+        });
+    }
+
+    /// Copy the states of all instances of restorable actor types (see
+    /// [`make_restorable`](#method.make_restorable)), so they can be reset to
+    /// this point in time later with [`restore`](#method.restore).
+    ///
+    /// Messages in flight are not part of a snapshot, so this should only be called
+    /// after `process_all_messages`.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            swarms: self.snapshotters
+                .iter()
+                .map(|snapshotter| (snapshotter.type_id, (snapshotter.take)()))
+                .collect(),
+        }
+    }
+
+    /// Reset all instances of restorable actor types to their states in `snapshot`.
+    /// Actor types that are not restorable keep their current state.
+    ///
+    /// This should only be called after `process_all_messages`, otherwise pending messages
+    /// might be delivered to instances that don't exist anymore.
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        for &(type_id, ref swarm_snapshot) in &snapshot.swarms {
+            if let Some(snapshotter) = self.snapshotters.iter().find(|snapshotter| {
+                snapshotter.type_id == type_id
+            })
+            {
+                (snapshotter.restore)(&**swarm_snapshot);
+            }
+        }
+    }
+
     /// Register a handler for an Actor type and Message type.
     pub fn add_handler<A: Actor, M: Message, F: Fn(&M, &mut A, &mut World) -> Fate + 'static>(
         &mut self,
@@ -141,7 +272,13 @@ impl ActorSystem {
             function: Box::new(move |packet_ptr: *const (), world: &mut World| unsafe {
                 let packet = &*(packet_ptr as *const Packet<M>);
 
-                (*swarm_ptr).dispatch_packet(packet, &handler, world);
+                let system = world.0;
+                match (*system).pool_for(actor_id, message_id) {
+                    Some(pool) if packet.recipient_id.instance_id == broadcast_instance_id() => {
+                        (*swarm_ptr).receive_broadcast_in_parallel(packet, &handler, world, pool)
+                    }
+                    _ => (*swarm_ptr).dispatch_packet(packet, &handler, world),
+                }
 
                 // TODO: not sure if this is the best place to drop the message
                 ::std::ptr::drop_in_place(packet_ptr as *mut Packet<M>);
@@ -150,6 +287,60 @@ impl ActorSystem {
         });
     }
 
+    /// Let broadcasts of a Message type to an already registered Actor type be handled
+    /// by several threads at once, each taking a share of the instances (see
+    /// [`set_worker_threads`](#method.set_worker_threads)).
+    ///
+    /// Messages sent by the handlers are only delivered once all threads are done,
+    /// but in the same order as without threads. The handlers may only change the
+    /// instance that received the broadcast and must not spawn actors or rely on
+    /// thread-local state. Untyped ID casts are not audited on the worker threads.
+    pub fn parallelize<A: Actor, M: Message>(&mut self) {
+        let actor_id = self.actor_registry.get::<A>();
+        let message_id = self.message_registry.get_or_register::<M>();
+        self.parallel_handlers.push((actor_id, message_id));
+    }
+
+    /// How many threads handle parallelized broadcasts (see
+    /// [`parallelize`](#method.parallelize)), 1 means they are handled like all others.
+    ///
+    /// The threads are started right away and kept for all following broadcasts,
+    /// until the number of threads is changed.
+    pub fn set_worker_threads(&mut self, n_threads: usize) {
+        let n_threads = n_threads.max(1);
+        if n_threads != self.worker_threads() {
+            self.worker_pool = if n_threads > 1 {
+                Some(WorkerPool::new(n_threads))
+            } else {
+                None
+            };
+        }
+    }
+
+    /// How many threads handle parallelized broadcasts
+    pub fn worker_threads(&self) -> usize {
+        self.worker_pool.as_ref().map(|pool| pool.n_threads()).unwrap_or(1)
+    }
+
+    fn pool_for(&self, actor_id: ShortTypeId, message_id: ShortTypeId) -> Option<&WorkerPool> {
+        if self.parallel_handlers.contains(&(actor_id, message_id)) {
+            self.worker_pool.as_ref()
+        } else {
+            None
+        }
+    }
+
+    /// Let messages of a type, usually interactive queries from the UI, skip the queue:
+    /// in each message cycle, they are handled before all other messages, and
+    /// [`process_priority_messages`](#method.process_priority_messages) handles only them.
+    ///
+    /// Prioritized messages can overtake other messages sent to the same instance earlier.
+    /// Messages received from other machines are always handled like all others.
+    pub fn prioritize<M: Message>(&mut self) {
+        let message_id = self.message_registry.get_or_register::<M>();
+        self.priority_messages[message_id.as_usize()] = true;
+    }
+
     /// Register a handler that constructs an instance of an Actor type, given an ID
     pub fn add_spawner<A: Actor, M: Message, F: Fn(&M, &mut World) -> A + 'static>(
         &mut self,
@@ -194,24 +385,29 @@ impl ActorSystem {
 
         let to_here = recipient.machine == self.networking.machine_id;
         let global = recipient.is_global_broadcast();
+        // looked up only once, since this happens for every single message
+        let message_id = self.message_registry.get::<M>();
 
         if !to_here || global {
-            self.networking.enqueue(
-                self.message_registry.get::<M>(),
-                packet.clone(),
-            );
+            self.networking.enqueue(message_id, packet.clone());
         }
 
         if to_here || global {
-            if let Some(inbox) = self.inboxes[recipient.type_id.as_usize()].as_mut() {
-                inbox.put(packet, &self.message_registry);
+            let type_idx = recipient.type_id.as_usize();
+            if self.priority_messages[message_id.as_usize()] && self.inboxes[type_idx].is_some() {
+                if self.priority_inboxes[type_idx].is_none() {
+                    self.priority_inboxes[type_idx] = Some(Inbox::new());
+                }
+                if let Some(inbox) = self.priority_inboxes[type_idx].as_mut() {
+                    inbox.put(packet, message_id);
+                }
+            } else if let Some(inbox) = self.inboxes[type_idx].as_mut() {
+                inbox.put(packet, message_id);
             } else {
                 panic!(
                     "{} has no inbox for {}",
                     self.actor_registry.get_name(recipient.type_id),
-                    self.message_registry.get_name(
-                        self.message_registry.get::<M>(),
-                    )
+                    self.message_registry.get_name(message_id)
                 );
             }
         }
@@ -226,12 +422,25 @@ impl ActorSystem {
         self.actor_registry.get_or_register::<A>()
     }
 
-    fn single_message_cycle(&mut self) {
+    fn single_message_cycle(&mut self, priority_only: bool) {
+        self.dispatch_inboxes(true);
+        if !priority_only {
+            self.dispatch_inboxes(false);
+        }
+    }
+
+    fn dispatch_inboxes(&mut self, priority: bool) {
         // TODO: separate inbox reading end from writing end
         //       to be able to use (several) mut refs here
-        let mut world = World(self as *const Self as *mut Self);
+        let mut world = World(self as *const Self as *mut Self, ::std::ptr::null_mut());
+
+        let inboxes = if priority {
+            &mut self.priority_inboxes
+        } else {
+            &mut self.inboxes
+        };
 
-        for (recipient_type_idx, maybe_inbox) in self.inboxes.iter_mut().enumerate() {
+        for (recipient_type_idx, maybe_inbox) in inboxes.iter_mut().enumerate() {
             if let Some(recipient_type) = ShortTypeId::new(recipient_type_idx as u16) {
                 if let Some(inbox) = maybe_inbox.as_mut() {
                     for DispatchablePacket { message_type, packet_ptr } in inbox.empty() {
@@ -265,22 +474,42 @@ impl ActorSystem {
     /// (for example, UI, simulation, rendering) can be run isolated from each other,
     /// in a fixed order of "turns" during each main-loop iteration.
     pub fn process_all_messages(&mut self) {
+        self.process_messages(false);
+    }
+
+    /// Processes only prioritized messages (see [`prioritize`](#method.prioritize)),
+    /// and prioritized messages which are in turn sent during their handling.
+    ///
+    /// This can be called between expensive turns, like simulation steps, so
+    /// interactive queries are answered right away, even if the other messages
+    /// pile up.
+    pub fn process_priority_messages(&mut self) {
+        self.process_messages(true);
+    }
+
+    fn process_messages(&mut self, priority_only: bool) {
+        if self.id_cast_counts.is_some() {
+            AUDITED_SYSTEM.with(|system| system.set(self as *mut Self));
+        }
+
         let result = catch_unwind(AssertUnwindSafe(|| for _i in 0..1000 {
-            self.single_message_cycle();
+            self.single_message_cycle(priority_only);
         }));
 
+        AUDITED_SYSTEM.with(|system| system.set(::std::ptr::null_mut()));
+
         if result.is_err() {
             self.panic_happened = true;
             (self.panic_callback)(
                 result.unwrap_err(),
-                &mut World(self as *const Self as *mut Self),
+                &mut World(self as *const Self as *mut Self, ::std::ptr::null_mut()),
             );
         }
     }
 
     /// Get a world context directly from the system, typically to send messages from outside
     pub fn world(&mut self) -> World {
-        World(self as *mut Self)
+        World(self as *mut Self, ::std::ptr::null_mut())
     }
 
     /// Connect to all peers in the network
@@ -317,6 +546,51 @@ impl ActorSystem {
         self.networking.debug_all_n_turns()
     }
 
+    /// Enable or disable the auditing of untyped ID casts made with
+    /// [`cast_id_to_actor!`](macro.cast_id_to_actor.html) or
+    /// [`cast_id_to_handler!`](macro.cast_id_to_handler.html) while handling messages.
+    /// Audited casts are counted per call site and panic if the actor an ID refers to
+    /// doesn't have the expected type, instead of messages silently going to the wrong actor.
+    pub fn audit_id_casts(&mut self, enabled: bool) {
+        self.id_cast_counts = if enabled { Some(HashMap::new()) } else { None };
+    }
+
+    /// How often each call site of an audited ID cast was passed, most frequent first
+    pub fn get_id_cast_counts(&self) -> Vec<(&'static str, usize)> {
+        let mut counts = self.id_cast_counts
+            .iter()
+            .flat_map(|counts| counts.iter().map(|(&site, &count)| (site, count)))
+            .collect::<Vec<_>>();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        counts
+    }
+
+    fn count_id_cast(&mut self, site: &'static str) {
+        if let Some(counts) = self.id_cast_counts.as_mut() {
+            *counts.entry(site).or_insert(0) += 1;
+        }
+    }
+
+    /// How much memory the instances of each Actor type use, largest first.
+    /// This has to look at every instance, so it shouldn't be called every frame.
+    pub fn get_memory_usage(&self) -> Vec<MemoryUsage> {
+        let mut usages = self.actors_as_countables
+            .iter()
+            .map(|&(ref actor_name, countable_ptr)| {
+                let countable = unsafe { &*countable_ptr };
+                let (total_bytes, unused_bytes) = countable.instance_bytes();
+                MemoryUsage {
+                    actor_type: actor_name.split("::").last().unwrap().replace(">", ""),
+                    instances: countable.instance_count(),
+                    total_bytes,
+                    unused_bytes,
+                }
+            })
+            .collect::<Vec<_>>();
+        usages.sort_by(|a, b| b.total_bytes.cmp(&a.total_bytes));
+        usages
+    }
+
     /// Access to debugging statistics
     pub fn get_instance_counts(&self) -> String {
         self.actors_as_countables
@@ -333,9 +607,78 @@ impl ActorSystem {
     }
 }
 
+thread_local!(
+    static AUDITED_SYSTEM: Cell<*mut ActorSystem> = Cell::new(::std::ptr::null_mut());
+);
+
+/// Reinterpret `id` as the ID of an actor of type `A`, checking that it actually is one
+/// if ID casts are audited (see [`ActorSystem::audit_id_casts`](struct.ActorSystem.html)).
+/// Usually called through [`cast_id_to_actor!`](macro.cast_id_to_actor.html).
+pub fn audit_cast_to_actor<A: Actor>(id: ID, site: &'static str) -> ID {
+    AUDITED_SYSTEM.with(|system| {
+        let system = system.get();
+        if !system.is_null() {
+            let system = unsafe { &mut *system };
+            system.count_id_cast(site);
+            let expected = system.actor_registry.get::<A>();
+            if id.type_id != expected {
+                panic!(
+                    "Untyped ID cast at {}: expected a {}, but {:?} is a {}",
+                    site,
+                    system.actor_registry.get_name(expected),
+                    id,
+                    system.actor_registry.get_name(id.type_id)
+                );
+            }
+        }
+    });
+    id
+}
+
+/// Reinterpret `id` as the ID of an actor that handles messages of type `M` (for example
+/// the messages of a trait it is expected to implement), checking that it actually does
+/// if ID casts are audited (see [`ActorSystem::audit_id_casts`](struct.ActorSystem.html)).
+/// Usually called through [`cast_id_to_handler!`](macro.cast_id_to_handler.html).
+pub fn audit_cast_to_handler<M: Message>(id: ID, site: &'static str) -> ID {
+    AUDITED_SYSTEM.with(|system| {
+        let system = system.get();
+        if !system.is_null() {
+            let system = unsafe { &mut *system };
+            system.count_id_cast(site);
+            let message_id = system.message_registry.get_or_register::<M>();
+            if system.dispatchers[id.type_id.as_usize()][message_id.as_usize()].is_none() {
+                panic!(
+                    "Untyped ID cast at {}: {:?} is a {}, which doesn't handle {}",
+                    site,
+                    id,
+                    system.actor_registry.get_name(id.type_id),
+                    system.message_registry.get_name(message_id)
+                );
+            }
+        }
+    });
+    id
+}
+
 /// Gives limited access to an [`ActorSystem`](struct.ActorSystem.html) (typically
 /// from inside, in a message handler) to identify other actors and send messages to them.
-pub struct World(*mut ActorSystem);
+pub struct World(*mut ActorSystem, *mut DeferredSends);
+
+/// Messages sent from a worker thread, to be sent for real once all threads are done
+pub type DeferredSends = Vec<Box<FnMut(&mut ActorSystem)>>;
+
+/// A world for a worker thread that collects sent messages in `deferred`
+pub fn deferring_world(world: &World, deferred: *mut DeferredSends) -> World {
+    World(world.0, deferred)
+}
+
+/// Send messages that were collected by a worker thread's world
+pub fn send_deferred(world: &mut World, deferred: DeferredSends) {
+    let system = unsafe { &mut *world.0 };
+    for mut send in deferred {
+        send(system);
+    }
+}
 
 impl World {
     /// Send a message to a (sub-)actor with the given ID.
@@ -344,7 +687,14 @@ impl World {
     /// world.send(child_id, Update {dt: 1.0});
     /// ```
     pub fn send<M: Message>(&mut self, receiver: ID, message: M) {
-        unsafe { &mut *self.0 }.send(receiver, message);
+        if self.1.is_null() {
+            unsafe { &mut *self.0 }.send(receiver, message);
+        } else {
+            let mut message = Some(message);
+            unsafe { &mut *self.1 }.push(Box::new(move |system: &mut ActorSystem| {
+                system.send(receiver, message.take().expect("Deferred message sent twice"))
+            }));
+        }
     }
 
     /// Get the ID of the first machine-local instance of an actor.
@@ -372,6 +722,7 @@ impl World {
     /// Synchronously allocate a instance id for a instance
     /// that will later manually be added to a Swarm
     pub fn allocate_instance_id<A: 'static + Actor>(&mut self) -> ID {
+        assert!(self.1.is_null(), "Can't spawn actors from parallelized handlers");
         let system: &mut ActorSystem = unsafe { &mut *self.0 };
         let swarm = unsafe {
             &mut *(system.swarms[system.actor_registry.get::<A>().as_usize()]
@@ -389,10 +740,16 @@ impl World {
 
 pub trait InstancesCountable {
     fn instance_count(&self) -> usize;
+    /// Total and unused bytes of all instances, see `Compact::unused_dynamic_bytes`
+    fn instance_bytes(&self) -> (usize, usize);
 }
 
 impl<T> InstancesCountable for T {
     default fn instance_count(&self) -> usize {
         1
     }
+
+    default fn instance_bytes(&self) -> (usize, usize) {
+        (size_of::<T>(), 0)
+    }
 }
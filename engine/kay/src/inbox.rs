@@ -1,7 +1,7 @@
 use super::compact::Compact;
 use super::messaging::{Packet, Message};
 use super::chunked::{MemChunker, ChunkedQueue};
-use super::type_registry::{ShortTypeId, TypeRegistry};
+use super::type_registry::ShortTypeId;
 
 pub struct Inbox {
     queue: ChunkedQueue,
@@ -15,7 +15,7 @@ impl Inbox {
         Inbox { queue: ChunkedQueue::new(chunker) }
     }
 
-    pub fn put<M: Message>(&mut self, mut packet: Packet<M>, message_registry: &TypeRegistry) {
+    pub fn put<M: Message>(&mut self, mut packet: Packet<M>, message_type: ShortTypeId) {
         let packet_size = packet.total_size_bytes();
         let total_size = ::std::mem::size_of::<ShortTypeId>() + packet_size;
 
@@ -25,7 +25,7 @@ impl Inbox {
             let queue_ptr = self.queue.enqueue(total_size);
 
             // Write message type
-            *(queue_ptr as *mut ShortTypeId) = message_registry.get::<M>();
+            *(queue_ptr as *mut ShortTypeId) = message_type;
 
             let payload_ptr = queue_ptr.offset(::std::mem::size_of::<ShortTypeId>() as isize);
 
@@ -27,6 +27,26 @@ extern crate compact_macros;
 extern crate core;
 extern crate byteorder;
 
+/// Reinterpret the raw `ID` `$id` as the ID of an actor of type `$actor`,
+/// see [`audit_cast_to_actor`](fn.audit_cast_to_actor.html).
+///
+/// IDs that are only used as keys, never to send messages, don't need this.
+#[macro_export]
+macro_rules! cast_id_to_actor {
+    ($id:expr, $actor:ty) => {
+        $crate::audit_cast_to_actor::<$actor>($id, concat!(file!(), ":", line!()))
+    };
+}
+
+/// Reinterpret the raw `ID` `$id` as the ID of an actor handling messages of type `$message`,
+/// see [`audit_cast_to_handler`](fn.audit_cast_to_handler.html).
+#[macro_export]
+macro_rules! cast_id_to_handler {
+    ($id:expr, $message:ty) => {
+        $crate::audit_cast_to_handler::<$message>($id, concat!(file!(), ":", line!()))
+    };
+}
+
 mod inbox;
 mod slot_map;
 mod swarm;
@@ -36,9 +56,12 @@ mod id;
 mod actor_system;
 mod networking;
 mod external;
+mod worker_pool;
+pub mod migration;
 
 pub use self::messaging::{Message, Packet, Fate};
 pub use self::id::ID;
-pub use self::actor_system::{Actor, ActorSystem, World};
+pub use self::actor_system::{Actor, ActorSystem, World, Snapshot, MemoryUsage,
+                             audit_cast_to_actor, audit_cast_to_handler};
 pub use self::networking::Networking;
 pub use self::external::External;
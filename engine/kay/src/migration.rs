@@ -0,0 +1,138 @@
+//! Upgrading persisted actor state that was saved with an older layout of an `Actor` type
+//!
+//! Each `Actor` type can register a [`Schema`](struct.Schema.html) with the `ActorSystem`,
+//! which knows the current schema version of the type and how to upgrade the compact
+//! representation of one instance from any older version to the next.
+//! When a `Swarm` holds instances with an older schema version, all its instances are
+//! upgraded by running the chain of upgrades, one version at a time.
+//!
+//! *Note:* `chunked` doesn't load persisted chunks yet (`MemChunker` only uses heap
+//! memory), so for now swarms always start out empty and with the current version.
+use compact::Compact;
+use std::mem;
+use std::ptr;
+
+/// Version of the memory layout of an `Actor` type, starts at 0 and should be
+/// increased by one every time the fields of the type change
+pub type SchemaVersion = u32;
+
+/// Turns the compact bytes of an instance in the layout of one schema version
+/// into the compact bytes of the same instance in the layout of the next version.
+/// The upgrade takes over the old instance and is responsible for dropping it,
+/// since only the upgrade knows its layout (see `upgrade_via`)
+pub type Upgrade = Box<Fn(&[u8]) -> Vec<u8>>;
+
+/// Reasons why persisted actor state could not be upgraded
+#[derive(Debug)]
+pub enum MigrationError {
+    /// The state was persisted with a newer version of the game
+    FromTheFuture {
+        /// Version the state was persisted with
+        stored: SchemaVersion,
+        /// Newest version known to this version of the game
+        current: SchemaVersion,
+    },
+    /// There is no upgrade registered that starts at the given version
+    MissingUpgrade(SchemaVersion),
+}
+
+/// The current schema version of an `Actor` type and the upgrades from all older versions
+pub struct Schema {
+    /// The schema version of the current layout of the `Actor` type
+    pub version: SchemaVersion,
+    upgrades: Vec<(SchemaVersion, Upgrade)>,
+}
+
+impl Schema {
+    /// Create a schema with the given current version and no upgrades yet
+    pub fn new(version: SchemaVersion) -> Schema {
+        Schema { version: version, upgrades: Vec::new() }
+    }
+
+    /// Add an upgrade from version `from` to version `from + 1`
+    pub fn upgrade_from(mut self, from: SchemaVersion, upgrade: Upgrade) -> Schema {
+        assert!(
+            from < self.version,
+            "Upgrade from version {} is not older than current version {}",
+            from,
+            self.version
+        );
+        self.upgrades.retain(|&(existing_from, _)| existing_from != from);
+        self.upgrades.push((from, upgrade));
+        self
+    }
+
+    /// Run the chain of upgrades on the bytes of one instance
+    /// that were persisted with schema version `stored`
+    pub fn migrate(
+        &self,
+        stored: SchemaVersion,
+        bytes: &[u8],
+    ) -> Result<Vec<u8>, MigrationError> {
+        if stored > self.version {
+            return Err(MigrationError::FromTheFuture { stored: stored, current: self.version });
+        }
+
+        // find the whole chain first, so no upgrade takes over the instance
+        // if it can't be upgraded all the way in the end
+        let mut chain = Vec::new();
+        for version in stored..self.version {
+            let upgrade = self.upgrades
+                .iter()
+                .find(|&&(from, _)| from == version)
+                .map(|&(_, ref upgrade)| upgrade)
+                .ok_or(MigrationError::MissingUpgrade(version))?;
+            chain.push(upgrade);
+        }
+
+        let mut bytes = bytes.to_vec();
+        for upgrade in chain {
+            bytes = upgrade(&bytes);
+        }
+        Ok(bytes)
+    }
+}
+
+/// Copy `bytes` into a buffer that is aligned well enough to reinterpret it as actor state
+pub fn aligned_copy(bytes: &[u8]) -> Vec<u64> {
+    let mut buffer = vec![0u64; (bytes.len() + 7) / 8];
+    unsafe {
+        ptr::copy_nonoverlapping(bytes.as_ptr(), buffer.as_mut_ptr() as *mut u8, bytes.len());
+    }
+    buffer
+}
+
+/// Create an `Upgrade` from a typed conversion between the old layout of a type
+/// (usually a copy of the old struct definition kept around for this purpose)
+/// and the new one
+/// The old instance is dropped after the conversion
+pub fn upgrade_via<Old, New, F>(convert: F) -> Upgrade
+where
+    Old: Compact,
+    New: Compact,
+    F: Fn(&Old) -> New + 'static,
+{
+    Box::new(move |old_bytes: &[u8]| {
+        let mut old_buffer = aligned_copy(old_bytes);
+        let old_ptr = old_buffer.as_mut_ptr() as *mut Old;
+        let mut new = unsafe { convert(&*old_ptr) };
+        unsafe { ptr::drop_in_place(old_ptr) };
+
+        let new_size = new.total_size_bytes();
+        let mut new_buffer = vec![0u64; (new_size + 7) / 8];
+        unsafe {
+            Compact::compact_behind(&mut new, new_buffer.as_mut_ptr() as *mut New);
+        }
+        mem::forget(new);
+
+        let mut new_bytes = vec![0u8; new_size];
+        unsafe {
+            ptr::copy_nonoverlapping(
+                new_buffer.as_ptr() as *const u8,
+                new_bytes.as_mut_ptr(),
+                new_size,
+            );
+        }
+        new_bytes
+    })
+}
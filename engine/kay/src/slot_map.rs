@@ -62,4 +62,26 @@ impl SlotMap {
     pub fn free(&mut self, id: usize, version: usize) {
         self.free_ids_with_versions.push((id, version + 1));
     }
+
+    /// The number of IDs allocated so far and the currently free ones (see `reset`)
+    pub fn allocation_state(&self) -> (usize, Vec<(usize, usize)>) {
+        let free_ids = (0..self.free_ids_with_versions.len())
+            .map(|i| *self.free_ids_with_versions.at(i))
+            .collect();
+        (self.entries.len(), free_ids)
+    }
+
+    /// Go back to an earlier allocation state, forgetting about all slot associations
+    pub fn reset(&mut self, n_ids: usize, free_ids: &[(usize, usize)]) {
+        while self.entries.len() > n_ids {
+            self.entries.pop();
+        }
+        while self.entries.len() < n_ids {
+            self.entries.push(SlotIndices::invalid());
+        }
+        while self.free_ids_with_versions.pop().is_some() {}
+        for &free_id in free_ids {
+            self.free_ids_with_versions.push(free_id);
+        }
+    }
 }
@@ -3,8 +3,10 @@ use compact::Compact;
 use super::chunked::{MemChunker, ValueInChunk, SizedChunkedArena, MultiSized};
 use super::slot_map::{SlotIndices, SlotMap};
 use super::messaging::{Message, Packet, Fate};
-use super::actor_system::{World, Actor};
+use super::actor_system::{World, Actor, DeferredSends, deferring_world, send_deferred};
 use super::id::{ID, broadcast_instance_id};
+use super::migration::{Schema, SchemaVersion, MigrationError, aligned_copy};
+use super::worker_pool::WorkerPool;
 use std::marker::PhantomData;
 
 /// A container-like actor, housing many instances of identical behaviour.
@@ -17,9 +19,25 @@ pub struct Swarm<Actor> {
     instances: MultiSized<SizedChunkedArena>,
     slot_map: SlotMap,
     n_instances: ValueInChunk<usize>,
+    schema_version: ValueInChunk<SchemaVersion>,
     _marker: PhantomData<[Actor]>,
 }
 
+/// Copies of all instances of a `Swarm` at one point in time,
+/// see [`Swarm::snapshot`](struct.Swarm.html#method.snapshot)
+pub struct SwarmSnapshot<Actor> {
+    instances: Vec<Actor>,
+    n_ids: usize,
+    free_ids: Vec<(usize, usize)>,
+}
+
+impl<Actor> SwarmSnapshot<Actor> {
+    /// The copied instances, in no particular order
+    pub fn instances(&self) -> &[Actor] {
+        &self.instances
+    }
+}
+
 const CHUNK_SIZE: usize = 4096 * 4096 * 16;
 
 impl<A: Actor + Clone> Swarm<A> {
@@ -30,6 +48,7 @@ impl<A: Actor + Clone> Swarm<A> {
         Swarm {
             instances: MultiSized::new(chunker.child("_instances"), A::typical_size()),
             n_instances: ValueInChunk::new(chunker.child("_n_instances"), 0),
+            schema_version: ValueInChunk::new(chunker.child("_schema_version"), 0),
             slot_map: SlotMap::new(chunker.child("_slot_map")),
             _marker: PhantomData,
         }
@@ -234,6 +253,157 @@ impl<A: Actor + Clone> Swarm<A> {
         }
     }
 
+    /// Like `receive_broadcast`, but with the instances split up between the threads
+    /// of `pool`. Resizing and removing instances, as well as sending the messages the
+    /// handlers sent, happens only after all threads are done.
+    pub fn receive_broadcast_in_parallel<M: Message, H>(
+        &mut self,
+        packet: &Packet<M>,
+        handler: &H,
+        world: &mut World,
+        pool: &WorkerPool,
+    ) where
+        H: Fn(&M, &mut A, &mut World) -> Fate + 'static,
+    {
+        let mut recipients = Vec::with_capacity(*self.n_instances);
+        for bin in &mut self.instances.bins {
+            for slot in 0..bin.len() {
+                recipients.push(unsafe { bin.at_mut(slot) } as usize);
+            }
+        }
+        if recipients.is_empty() {
+            return;
+        }
+        let chunk_size = (recipients.len() + pool.n_threads() - 1) / pool.n_threads();
+
+        // raw pointers can't be sent to other threads, so they are passed as addresses
+        let message_address = &packet.message as *const M as usize;
+        let handler_address = handler as *const H as usize;
+
+        let jobs = recipients
+            .chunks(chunk_size)
+            .map(|chunk| {
+                let mut deferred = Box::new(DeferredSends::new());
+                let thread_world = deferring_world(world, &mut *deferred);
+                let work = AssumeSend((chunk.to_vec(), deferred, thread_world));
+
+                move || {
+                    let AssumeSend((chunk, deferred, mut thread_world)) = work;
+                    let message = unsafe { &*(message_address as *const M) };
+                    let handler = unsafe { &*(handler_address as *const H) };
+                    let mut outcomes = Vec::new();
+
+                    for actor_address in chunk {
+                        let actor = unsafe { &mut *(actor_address as *mut A) };
+                        let fate = handler(message, actor, &mut thread_world);
+                        match fate {
+                            Fate::Live if actor.is_still_compact() => {}
+                            _ => outcomes.push((actor.id(), fate)),
+                        }
+                    }
+
+                    AssumeSend((deferred, outcomes))
+                }
+            })
+            .collect::<Vec<_>>();
+
+        // all jobs are done once their results are in, so a panic can be passed on.
+        // Going through the chunks in order keeps the order of sent messages
+        // the same as when handling the broadcast on one thread
+        for result in pool.run_all(jobs) {
+            let AssumeSend((deferred, outcomes)) = match result {
+                Ok(result) => result,
+                Err(panic) => ::std::panic::resume_unwind(panic),
+            };
+            for (id, fate) in outcomes {
+                match fate {
+                    Fate::Live => {
+                        self.resize(id.instance_id as usize);
+                    }
+                    Fate::Die => self.remove(id),
+                }
+            }
+            send_deferred(world, *deferred);
+        }
+    }
+
+    /// Upgrade all instances that were persisted with an older schema version
+    /// to the current schema version, see [`Schema`](../migration/struct.Schema.html)
+    pub fn migrate(&mut self, schema: &Schema) -> Result<(), MigrationError> {
+        let stored = *self.schema_version;
+        if stored == schema.version {
+            return Ok(());
+        }
+
+        let mut migrated = Vec::with_capacity(*self.n_instances);
+        for bin in &self.instances.bins {
+            for slot in 0..bin.len() {
+                let old_bytes =
+                    unsafe { ::std::slice::from_raw_parts(bin.at(slot), bin.item_size) };
+                migrated.push(schema.migrate(stored, old_bytes)?);
+            }
+        }
+
+        // the upgrades already dropped the old instances, since their layout doesn't
+        // match `A` anymore. The bins are sized for the old layout, so they are
+        // replaced by bins sized for the current one
+        self.instances = MultiSized::new(
+            MemChunker::from_settings("", CHUNK_SIZE).child("_instances"),
+            A::typical_size(),
+        );
+
+        for new_bytes in migrated {
+            let mut buffer = aligned_copy(&new_bytes);
+            let actor_ptr = buffer.as_mut_ptr() as *mut A;
+            unsafe {
+                let id = (*actor_ptr).id();
+                self.add_with_id(actor_ptr, id);
+            }
+        }
+
+        *self.schema_version = schema.version;
+        Ok(())
+    }
+
+    /// Copy all instances, so the `Swarm` can be reset to its current state later
+    pub fn snapshot(&self) -> SwarmSnapshot<A> {
+        let mut instances = Vec::with_capacity(*self.n_instances);
+        for bin in &self.instances.bins {
+            for slot in 0..bin.len() {
+                instances.push(unsafe { (*(bin.at(slot) as *const A)).clone() });
+            }
+        }
+        let (n_ids, free_ids) = self.slot_map.allocation_state();
+        SwarmSnapshot { instances, n_ids, free_ids }
+    }
+
+    /// Drop all current instances and replace them with copies of the ones in `snapshot`.
+    /// IDs of instances created after the snapshot become invalid and will be reused.
+    pub fn restore(&mut self, snapshot: &SwarmSnapshot<A>) {
+        for bin in &mut self.instances.bins {
+            while !bin.is_empty() {
+                let last = bin.len() - 1;
+                unsafe {
+                    ::std::ptr::drop_in_place(bin.at_mut(last) as *mut A);
+                }
+                bin.pop_away();
+            }
+        }
+
+        self.slot_map.reset(snapshot.n_ids, &snapshot.free_ids);
+
+        for instance in &snapshot.instances {
+            let mut instance = instance.clone();
+            unsafe {
+                let id = instance.id();
+                self.add_with_id(&mut instance, id);
+            }
+            ::std::mem::forget(instance);
+        }
+
+        *self.n_instances = snapshot.instances.len();
+    }
+
     pub fn dispatch_packet<M: Message, F>(
         &mut self,
         packet: &Packet<M>,
@@ -250,9 +420,123 @@ impl<A: Actor + Clone> Swarm<A> {
     }
 }
 
+/// Lets actor instances, their handlers and worlds cross into worker threads,
+/// see `receive_broadcast_in_parallel` for why this is fine
+struct AssumeSend<T>(T);
+unsafe impl<T> Send for AssumeSend<T> {}
+
 use super::actor_system::InstancesCountable;
 impl<A: Actor> InstancesCountable for Swarm<A> {
     fn instance_count(&self) -> usize {
         *self.n_instances
     }
+
+    fn instance_bytes(&self) -> (usize, usize) {
+        let mut total_bytes = 0;
+        let mut unused_bytes = 0;
+        for bin in &self.instances.bins {
+            for slot in 0..bin.len() {
+                let actor = unsafe { &*(bin.at(slot) as *const A) };
+                total_bytes += actor.total_size_bytes();
+                unused_bytes += actor.unused_dynamic_bytes();
+            }
+        }
+        (total_bytes, unused_bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Swarm;
+    use super::super::actor_system::Actor;
+    use super::super::id::ID;
+    use super::super::type_registry::ShortTypeId;
+    use super::super::migration::{Schema, upgrade_via};
+    use compact::{Compact, CVec};
+    use std::marker::PhantomData;
+    use std::mem::size_of;
+
+    #[derive(Compact, Clone)]
+    struct RouteV0 {
+        id: ID,
+        stops: CVec<u16>,
+    }
+
+    impl Actor for RouteV0 {
+        fn id(&self) -> ID {
+            self.id
+        }
+
+        unsafe fn set_id(&mut self, id: ID) {
+            self.id = id;
+        }
+    }
+
+    #[derive(Compact, Clone)]
+    struct RouteV1 {
+        id: ID,
+        stops: CVec<u64>,
+        n_stops: u64,
+    }
+
+    impl Actor for RouteV1 {
+        fn id(&self) -> ID {
+            self.id
+        }
+
+        unsafe fn set_id(&mut self, id: ID) {
+            self.id = id;
+        }
+    }
+
+    #[test]
+    fn migrating_rebuilds_bins_for_the_new_layout() {
+        let mut old_swarm = Swarm::<RouteV0>::new();
+        let base_id = ID::new(ShortTypeId::new(1).unwrap(), 0, 0, 0);
+        let mut ids = Vec::new();
+        for n_stops in 0..100 {
+            unsafe {
+                let id = old_swarm.allocate_id(base_id);
+                let mut route = RouteV0 {
+                    id: id,
+                    stops: (0..n_stops).collect::<Vec<u16>>().into(),
+                };
+                old_swarm.add_manually_with_id(&mut route, id);
+                ::std::mem::forget(route);
+                ids.push(id);
+            }
+        }
+
+        // what loading a swarm that was persisted with the old layout amounts to
+        let mut swarm = Swarm::<RouteV1> {
+            instances: old_swarm.instances,
+            slot_map: old_swarm.slot_map,
+            n_instances: old_swarm.n_instances,
+            schema_version: old_swarm.schema_version,
+            _marker: PhantomData,
+        };
+
+        let schema = Schema::new(1).upgrade_from(
+            0,
+            upgrade_via(|old: &RouteV0| {
+                RouteV1 {
+                    id: old.id,
+                    stops: old.stops.iter().map(|&stop| u64::from(stop)).collect(),
+                    n_stops: old.stops.len() as u64,
+                }
+            }),
+        );
+        swarm.migrate(&schema).unwrap();
+
+        assert_eq!(*swarm.schema_version, 1);
+        assert_eq!(*swarm.n_instances, 100);
+        assert_eq!(swarm.instances.bins[0].item_size, size_of::<RouteV1>());
+        for (n_stops, id) in ids.into_iter().enumerate() {
+            let route = swarm.at_mut(id.instance_id as usize);
+            assert!(route.id == id);
+            assert!(route.is_still_compact());
+            assert_eq!(route.n_stops, n_stops as u64);
+            assert_eq!(&route.stops[..], &(0..n_stops as u64).collect::<Vec<_>>()[..]);
+        }
+    }
 }
@@ -0,0 +1,81 @@
+//! Threads that are started once and then handle all parallelized broadcasts (see
+//! [`ActorSystem::parallelize`](../struct.ActorSystem.html#method.parallelize)),
+//! so a broadcast doesn't have to pay for starting threads every time.
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::sync::mpsc::{channel, Sender};
+use std::thread::{self, JoinHandle};
+
+type Job = Box<FnMut() + Send>;
+
+/// A fixed number of threads, each running the jobs it gets one after the other
+pub struct WorkerPool {
+    workers: Vec<(Sender<Job>, JoinHandle<()>)>,
+}
+
+impl WorkerPool {
+    /// Start `n_threads` threads that wait for jobs
+    pub fn new(n_threads: usize) -> WorkerPool {
+        WorkerPool {
+            workers: (0..n_threads.max(1))
+                .map(|i| {
+                    let (jobs, incoming_jobs) = channel::<Job>();
+                    let handle = thread::Builder::new()
+                        .name(format!("kay worker {}", i))
+                        .spawn(move || for mut job in incoming_jobs {
+                            job();
+                        })
+                        .expect("Couldn't start worker thread");
+                    (jobs, handle)
+                })
+                .collect(),
+        }
+    }
+
+    /// The number of threads in the pool
+    pub fn n_threads(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Run `jobs`, spread evenly between the threads, and wait until all of them are done.
+    /// Returns their results in the order of `jobs`, with the panic of each job that
+    /// panicked in place of its result.
+    pub fn run_all<R, F>(&self, jobs: Vec<F>) -> Vec<thread::Result<R>>
+    where
+        R: Send + 'static,
+        F: FnOnce() -> R + Send + 'static,
+    {
+        let n_jobs = jobs.len();
+        let (results, incoming_results) = channel();
+
+        for (i, job) in jobs.into_iter().enumerate() {
+            let results = results.clone();
+            let mut job = Some(job);
+            let job: Job = Box::new(move || {
+                let job = job.take().expect("Job was run twice");
+                let result = catch_unwind(AssertUnwindSafe(job));
+                // the receiving end only goes away once all results are in
+                let _ = results.send((i, result));
+            });
+            self.workers[i % self.workers.len()].0.send(job).expect(
+                "Worker thread is gone",
+            );
+        }
+
+        let mut results_in_order = incoming_results.iter().take(n_jobs).collect::<Vec<_>>();
+        results_in_order.sort_by_key(|&(i, _)| i);
+        results_in_order
+            .into_iter()
+            .map(|(_, result)| result)
+            .collect()
+    }
+}
+
+impl Drop for WorkerPool {
+    fn drop(&mut self) {
+        for (jobs, handle) in self.workers.drain(..) {
+            // without anybody to send jobs, the thread stops waiting for them
+            drop(jobs);
+            let _ = handle.join();
+        }
+    }
+}
@@ -0,0 +1,95 @@
+extern crate kay;
+
+use kay::migration::{Schema, MigrationError, upgrade_via, aligned_copy};
+use std::mem;
+use std::slice;
+
+#[derive(Copy, Clone)]
+struct CounterV0 {
+    count: u32,
+}
+
+#[derive(Copy, Clone)]
+struct CounterV1 {
+    count: u32,
+    step: u32,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+struct CounterV2 {
+    count: u64,
+    step: u32,
+    resets: u32,
+}
+
+fn bytes_of<T: Copy>(value: &T) -> Vec<u8> {
+    unsafe { slice::from_raw_parts(value as *const T as *const u8, mem::size_of::<T>()) }
+        .to_vec()
+}
+
+fn value_from<T: Copy>(bytes: &[u8]) -> T {
+    assert_eq!(bytes.len(), mem::size_of::<T>());
+    unsafe { *(aligned_copy(bytes).as_ptr() as *const T) }
+}
+
+fn counter_schema() -> Schema {
+    Schema::new(2)
+        .upgrade_from(
+            0,
+            upgrade_via(|old: &CounterV0| CounterV1 { count: old.count, step: 1 }),
+        )
+        .upgrade_from(
+            1,
+            upgrade_via(|old: &CounterV1| {
+                CounterV2 { count: u64::from(old.count), step: old.step, resets: 0 }
+            }),
+        )
+}
+
+#[test]
+fn upgrades_are_chained_from_the_stored_version() {
+    let schema = counter_schema();
+
+    let from_v0 = schema.migrate(0, &bytes_of(&CounterV0 { count: 7 })).unwrap();
+    assert_eq!(
+        value_from::<CounterV2>(&from_v0),
+        CounterV2 { count: 7, step: 1, resets: 0 }
+    );
+
+    let from_v1 = schema.migrate(1, &bytes_of(&CounterV1 { count: 7, step: 3 })).unwrap();
+    assert_eq!(
+        value_from::<CounterV2>(&from_v1),
+        CounterV2 { count: 7, step: 3, resets: 0 }
+    );
+}
+
+#[test]
+fn current_version_is_left_as_is() {
+    let current = CounterV2 { count: 12, step: 2, resets: 1 };
+    let migrated = counter_schema().migrate(2, &bytes_of(&current)).unwrap();
+    assert_eq!(migrated, bytes_of(&current));
+}
+
+#[test]
+fn gap_in_the_chain_is_a_missing_upgrade() {
+    let schema = Schema::new(2).upgrade_from(
+        1,
+        upgrade_via(|old: &CounterV1| {
+            CounterV2 { count: u64::from(old.count), step: old.step, resets: 0 }
+        }),
+    );
+
+    match schema.migrate(0, &bytes_of(&CounterV0 { count: 7 })) {
+        Err(MigrationError::MissingUpgrade(0)) => {}
+        other => panic!("Expected a missing upgrade from version 0, got {:?}", other),
+    }
+}
+
+#[test]
+fn newer_state_is_from_the_future() {
+    let newer = CounterV2 { count: 12, step: 2, resets: 1 };
+    match counter_schema().migrate(3, &bytes_of(&newer)) {
+        Err(MigrationError::FromTheFuture { stored: 3, current: 2 }) => {}
+        other => panic!("Expected state from the future, got {:?}", other),
+    }
+}
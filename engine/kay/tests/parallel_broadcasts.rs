@@ -0,0 +1,93 @@
+extern crate kay;
+
+use kay::{ActorSystem, Actor, Fate, ID, Networking, World};
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+
+const N_COUNTERS: usize = 1000;
+const N_ROUNDS: usize = 3;
+
+static PONGS: AtomicUsize = ATOMIC_USIZE_INIT;
+
+#[derive(Copy, Clone)]
+struct Counter {
+    id: ID,
+    pings: usize,
+    pongs: usize,
+}
+
+impl Actor for Counter {
+    fn id(&self) -> ID {
+        self.id
+    }
+
+    unsafe fn set_id(&mut self, id: ID) {
+        self.id = id;
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Spawn(ID);
+
+#[derive(Copy, Clone)]
+struct Ping;
+
+#[derive(Copy, Clone)]
+struct Pong;
+
+fn setup(n_threads: usize) -> Box<ActorSystem> {
+    let mut system = Box::new(ActorSystem::new(
+        Box::new(|_, _: &mut World| panic!("A handler panicked")),
+        Networking::new(0, Vec::new()),
+    ));
+
+    system.register::<Counter>();
+    system.add_spawner::<Counter, _, _>(
+        |&Spawn(id), _| Counter { id, pings: 0, pongs: 0 },
+        false,
+    );
+    system.add_handler::<Counter, _, _>(
+        |&Ping, counter, world| {
+            counter.pings += 1;
+            // sent from a worker thread, but only delivered once all threads are done
+            world.send(counter.id, Pong);
+            Fate::Live
+        },
+        false,
+    );
+    system.add_handler::<Counter, _, _>(
+        |&Pong, counter, _| {
+            counter.pongs += 1;
+            assert!(counter.pongs <= counter.pings, "Pong delivered more than once");
+            PONGS.fetch_add(1, Ordering::SeqCst);
+            Fate::Live
+        },
+        false,
+    );
+    system.parallelize::<Counter, Ping>();
+    system.set_worker_threads(n_threads);
+
+    let world = &mut system.world();
+    for _ in 0..N_COUNTERS {
+        let id = world.allocate_instance_id::<Counter>();
+        let swarm = world.local_broadcast::<Counter>();
+        world.send(swarm, Spawn(id));
+    }
+    system.process_all_messages();
+
+    system
+}
+
+#[test]
+fn messages_sent_from_worker_threads_are_delivered_exactly_once() {
+    let mut system = setup(4);
+    assert_eq!(system.worker_threads(), 4);
+
+    for round in 1..(N_ROUNDS + 1) {
+        let world = &mut system.world();
+        let counters = world.local_broadcast::<Counter>();
+        world.send(counters, Ping);
+        system.process_all_messages();
+
+        assert_eq!(PONGS.load(Ordering::SeqCst), round * N_COUNTERS);
+    }
+}
@@ -287,8 +287,9 @@ impl Model {
                 actor_def.impls.iter().map(trait_name_to_id).collect()
             })
             .collect();
-        let actor_trait_ids_2 = actor_trait_ids_1.clone();
-        let actor_ids_for_traits: Vec<Vec<_>> = self.actors
+        let (actor_trait_ids_2, actor_trait_ids_3) =
+            (actor_trait_ids_1.clone(), actor_trait_ids_1.clone());
+        let actor_ids_for_traits_1: Vec<Vec<_>> = self.actors
             .iter()
             .map(|(actor_name, actor_def)| {
                 actor_def
@@ -298,6 +299,7 @@ impl Model {
                     .collect()
             })
             .collect();
+        let actor_ids_for_traits_2 = actor_ids_for_traits_1.clone();
 
         quote!(
             #(
@@ -379,11 +381,9 @@ impl Model {
 
             #(
                 #(
-                impl Into<#actor_trait_ids_1> for #actor_ids_for_traits {
-                    fn into(self) -> #actor_trait_ids_2 {
-                        unsafe {
-                            ::std::mem::transmute(self)
-                        }
+                impl From<#actor_ids_for_traits_1> for #actor_trait_ids_1 {
+                    fn from(id: #actor_ids_for_traits_2) -> #actor_trait_ids_2 {
+                        #actor_trait_ids_3 { _raw_id: id._raw_id }
                     }
                 }
                 )*
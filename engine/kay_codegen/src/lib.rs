@@ -343,15 +343,15 @@ fn trait_and_impl() {
 
         impl SomeActorID { }
 
-        impl Into<SomeTraitID> for SomeActorID {
-            fn into(self) -> SomeTraitID {
-                unsafe {::std::mem::transmute(self)}
+        impl From<SomeActorID> for SomeTraitID {
+            fn from(id: SomeActorID) -> SomeTraitID {
+                SomeTraitID { _raw_id: id._raw_id }
             }
         }
 
-        impl Into<ForeignTraitID> for SomeActorID {
-            fn into(self) -> ForeignTraitID {
-                unsafe {::std::mem::transmute(self)}
+        impl From<SomeActorID> for ForeignTraitID {
+            fn from(id: SomeActorID) -> ForeignTraitID {
+                ForeignTraitID { _raw_id: id._raw_id }
             }
         }
 
@@ -0,0 +1,80 @@
+//! Animations deform the geometry of renderables depending on how far along they are,
+//! so things like buildings can rise or collapse gradually instead of popping in or out.
+//!
+//! How far along an animation is comes from whoever drives it, either from messages
+//! about the progress of what is animated (like a construction) or from the frames
+//! passed since it started (see `frame_progress`). Renderables keep the undeformed
+//! geometry and only upload an animated copy when the progress changed noticeably
+//! (see `Animation::changed_noticeably`).
+use descartes::N;
+use {Geometry, Vertex};
+
+/// Animations only update their geometry when progress changed by at least this much
+const PROGRESS_STEP: N = 0.02;
+/// How far collapsing geometry spreads out on the ground, relative to its height
+const COLLAPSE_SPREAD: N = 0.3;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Animation {
+    /// Grows up from the ground, like a building under construction
+    Rise,
+    /// Sinks to the ground while spreading out, like a building being demolished
+    Collapse,
+}
+
+impl Animation {
+    /// The geometry at `progress` (between 0 and 1) of the animation, `geometry`
+    /// being what it looks like when not animated
+    pub fn apply(&self, geometry: &Geometry, progress: N) -> Geometry {
+        let progress = progress.max(0.0).min(1.0);
+        let max_z = geometry
+            .vertices
+            .iter()
+            .map(|vertex| vertex.position[2])
+            .fold(0.0, N::max);
+        let n_vertices = geometry.vertices.len().max(1) as N;
+        let (center_x, center_y) = geometry.vertices.iter().fold((0.0, 0.0), |(x, y), vertex| {
+            (x + vertex.position[0] / n_vertices, y + vertex.position[1] / n_vertices)
+        });
+
+        let vertices = geometry
+            .vertices
+            .iter()
+            .map(|vertex| {
+                let (x, y, z) = (vertex.position[0], vertex.position[1], vertex.position[2]);
+                match *self {
+                    Animation::Rise => Vertex { position: [x, y, z * progress] },
+                    Animation::Collapse => {
+                        let remaining = (1.0 - progress) * (1.0 - progress);
+                        // what was high up ends up further away
+                        let spread = if max_z > 0.0 {
+                            1.0 + progress * COLLAPSE_SPREAD * z / max_z
+                        } else {
+                            1.0
+                        };
+                        Vertex {
+                            position: [
+                                center_x + (x - center_x) * spread,
+                                center_y + (y - center_y) * spread,
+                                z * remaining,
+                            ],
+                        }
+                    }
+                }
+            })
+            .collect::<Vec<_>>();
+
+        Geometry::new(vertices, geometry.indices.to_vec())
+    }
+
+    /// Whether going from `rendered_progress` to `progress` is worth a new geometry
+    pub fn changed_noticeably(rendered_progress: N, progress: N) -> bool {
+        (progress - rendered_progress).abs() >= PROGRESS_STEP ||
+            (progress >= 1.0 && rendered_progress < 1.0)
+    }
+}
+
+/// Progress of an animation that started in `start_frame` and takes `n_frames`
+pub fn frame_progress(start_frame: usize, current_frame: usize, n_frames: usize) -> N {
+    (current_frame.saturating_sub(start_frame) as N / n_frames.max(1) as N).min(1.0)
+}
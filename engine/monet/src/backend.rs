@@ -0,0 +1,117 @@
+use glium::Frame;
+use glium::backend::glutin::Display;
+use kay::External;
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+
+use Scene;
+use glium_backend::GliumBackend;
+
+/// A window together with what a backend needs to draw into it
+pub enum RenderWindow {
+    OpenGL(Display),
+}
+
+impl RenderWindow {
+    pub fn kind(&self) -> BackendKind {
+        match *self {
+            RenderWindow::OpenGL(_) => BackendKind::OpenGL,
+        }
+    }
+
+    /// The target for the next frame, to be handed to the renderer
+    pub fn start_drawing(&self) -> RenderTarget {
+        match *self {
+            RenderWindow::OpenGL(ref display) => RenderTarget::OpenGL(display.draw()),
+        }
+    }
+}
+
+/// What a frame is drawn into, passed from the window to the renderer and back
+pub enum RenderTarget {
+    OpenGL(Frame),
+}
+
+impl RenderTarget {
+    /// Shows the frame in its window
+    pub fn finish(self) {
+        match self {
+            RenderTarget::OpenGL(frame) => frame.finish().expect("Couldn't finish frame"),
+        }
+    }
+}
+
+/// Everything the renderer needs from a graphics API.
+///
+/// Scenes only contain backend-independent batches, each backend keeps
+/// its own GPU-side copies of their geometry.
+pub trait RenderBackend {
+    /// Called once per frame, before any scene is submitted
+    fn start_frame(&mut self) {}
+    /// Draw all batches of the scene with index `scene_id` into `target`,
+    /// which always comes from the window the backend was created for
+    fn submit(&mut self, scene_id: usize, scene: &Scene, target: &mut RenderTarget);
+    /// Size of the window's framebuffer in pixels
+    fn framebuffer_dimensions(&self) -> (u32, u32);
+}
+
+static LAST_DRAW_CALLS: AtomicUsize = ATOMIC_USIZE_INIT;
+static LAST_STATE_CHANGES: AtomicUsize = ATOMIC_USIZE_INIT;
+static LAST_CULLED_INSTANCES: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// What drawing a frame took, counted by backends while submitting scenes.
+///
+/// State changes are switches of the shader program or draw parameters between
+/// consecutive draw calls, which backends keep few by drawing batches in groups.
+#[derive(Copy, Clone, Default, Debug)]
+pub struct FrameStats {
+    pub draw_calls: usize,
+    pub state_changes: usize,
+    pub culled_instances: usize,
+}
+
+impl FrameStats {
+    pub fn add(&mut self, other: &FrameStats) {
+        self.draw_calls += other.draw_calls;
+        self.state_changes += other.state_changes;
+        self.culled_instances += other.culled_instances;
+    }
+
+    /// Makes these the stats of the last frame, for anyone to show
+    pub fn publish(&self) {
+        LAST_DRAW_CALLS.store(self.draw_calls, Ordering::Relaxed);
+        LAST_STATE_CHANGES.store(self.state_changes, Ordering::Relaxed);
+        LAST_CULLED_INSTANCES.store(self.culled_instances, Ordering::Relaxed);
+    }
+
+    pub fn last_frame() -> FrameStats {
+        FrameStats {
+            draw_calls: LAST_DRAW_CALLS.load(Ordering::Relaxed),
+            state_changes: LAST_STATE_CHANGES.load(Ordering::Relaxed),
+            culled_instances: LAST_CULLED_INSTANCES.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// The graphics APIs that a `RenderBackend` exists for
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BackendKind {
+    OpenGL,
+}
+
+impl Default for BackendKind {
+    fn default() -> Self {
+        BackendKind::OpenGL
+    }
+}
+
+/// The backend for the graphics API of `window`
+pub fn create_backend(
+    window: RenderWindow,
+    clear_color: (f32, f32, f32, f32),
+) -> Box<RenderBackend> {
+    match window {
+        RenderWindow::OpenGL(display) => {
+            Box::new(GliumBackend::new(External::new(display), clear_color))
+        }
+    }
+}
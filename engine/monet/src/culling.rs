@@ -0,0 +1,102 @@
+//! Backends skip instances that are outside of what an eye can see, before uploading
+//! them for a draw call, and use the distance from the eye to draw see-through batches
+//! from back to front. Both only need a rough idea of where each instance is, so every
+//! batch keeps a sphere around its geometry (see `Batch::bounds`).
+use descartes::{N, P3, V3, Norm, Dot};
+use {Eye, Instance, Vertex};
+
+/// A sphere around all vertices of a geometry, in the coordinates of its instances
+#[derive(Copy, Clone, Debug)]
+pub struct BoundingSphere {
+    pub center: P3,
+    pub radius: N,
+}
+
+impl BoundingSphere {
+    pub fn around(vertices: &[Vertex]) -> BoundingSphere {
+        let n_vertices = vertices.len().max(1) as N;
+        let center = vertices.iter().fold(P3::new(0.0, 0.0, 0.0), |center, vertex| {
+            P3::new(
+                center.x + vertex.position[0] / n_vertices,
+                center.y + vertex.position[1] / n_vertices,
+                center.z + vertex.position[2] / n_vertices,
+            )
+        });
+        let radius = vertices
+            .iter()
+            .map(|vertex| {
+                (P3::new(vertex.position[0], vertex.position[1], vertex.position[2]) - center)
+                    .norm()
+            })
+            .fold(0.0, N::max);
+        BoundingSphere { center, radius }
+    }
+
+    /// Where the sphere ends up for `instance`, turned and moved the way the vertex
+    /// shader does it with the geometry
+    pub fn of_instance(&self, instance: &Instance) -> BoundingSphere {
+        let direction = instance.instance_direction;
+        let position = instance.instance_position;
+        BoundingSphere {
+            center: P3::new(
+                self.center.x * direction[0] - self.center.y * direction[1] + position[0],
+                self.center.x * direction[1] + self.center.y * direction[0] + position[1],
+                self.center.z + position[2],
+            ),
+            radius: self.radius,
+        }
+    }
+}
+
+/// The part of a scene that an eye can see
+pub struct Frustum {
+    position: P3,
+    forward: V3,
+    right: V3,
+    up: V3,
+    near: N,
+    far: N,
+    /// Slopes of the sides, relative to the view direction
+    horizontal_slope: N,
+    vertical_slope: N,
+}
+
+fn cross(a: V3, b: V3) -> V3 {
+    V3::new(a.y * b.z - a.z * b.y, a.z * b.x - a.x * b.z, a.x * b.y - a.y * b.x)
+}
+
+impl Frustum {
+    /// Matches the perspective that backends draw with for `eye`
+    pub fn new(eye: &Eye, aspect_ratio: N, near: N, far: N) -> Frustum {
+        let forward = (eye.target - eye.position).normalize();
+        let right = cross(forward, eye.up).normalize();
+        let vertical_slope = (eye.field_of_view / 2.0).tan();
+        Frustum {
+            position: eye.position,
+            forward,
+            right,
+            up: cross(right, forward),
+            near,
+            far,
+            horizontal_slope: vertical_slope * aspect_ratio,
+            vertical_slope,
+        }
+    }
+
+    /// How far `point` is in front of the eye
+    pub fn depth(&self, point: P3) -> N {
+        (point - self.position).dot(&self.forward)
+    }
+
+    pub fn can_see(&self, sphere: &BoundingSphere) -> bool {
+        let relative = sphere.center - self.position;
+        let depth = relative.dot(&self.forward);
+        let outside_side = |along: N, slope: N| {
+            (along.abs() - slope * depth) / (1.0 + slope * slope).sqrt() > sphere.radius
+        };
+
+        depth + sphere.radius >= self.near && depth - sphere.radius <= self.far &&
+            !outside_side(relative.dot(&self.right), self.horizontal_slope) &&
+            !outside_side(relative.dot(&self.up), self.vertical_slope)
+    }
+}
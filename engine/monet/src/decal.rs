@@ -0,0 +1,108 @@
+//! Decals are flat patterns projected from above onto whatever is drawn below them, like
+//! markings painted on roads, dirt, construction zones or selection highlights.
+//!
+//! They have no geometry of their own: each decal is an oriented rectangle with a pattern
+//! that the decal shader fills in, kept per scene under the layer and owner that set it
+//! (see `Renderer::set_decals`). Changing how something looks this way only replaces a
+//! few decals, instead of regenerating the meshes it lies on.
+use descartes::{N, P2, V2, Norm};
+
+/// Decals are drawn at this height, only where nothing is closer to the eye,
+/// so they cover the ground and everything flat on it, but not cars or buildings
+pub const DECAL_HEIGHT: N = 0.35;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum DecalPattern {
+    Solid,
+    /// Stripes across the length of the decal, `period` meters apart,
+    /// each covering `fill` of that
+    Stripes { period: N, fill: N },
+    /// Diagonal stripes, half of each `period` covered
+    Hazard { period: N },
+    /// Square specks covering about `density` of the decal
+    Speckles { density: N },
+    /// Only a border of `width` along the edges
+    Outline { width: N },
+}
+
+impl DecalPattern {
+    /// As passed to the decal shader: kind and up to two parameters
+    fn encode(&self) -> [f32; 3] {
+        match *self {
+            DecalPattern::Solid => [0.0, 0.0, 0.0],
+            DecalPattern::Stripes { period, fill } => [1.0, period, fill],
+            DecalPattern::Hazard { period } => [2.0, period, 0.0],
+            DecalPattern::Speckles { density } => [3.0, density, 0.0],
+            DecalPattern::Outline { width } => [4.0, width, 0.0],
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Decal {
+    pub center: P2,
+    /// Along the length of the decal, normalized
+    pub direction: V2,
+    pub length: N,
+    pub width: N,
+    pub color: [f32; 3],
+    pub opacity: f32,
+    pub pattern: DecalPattern,
+}
+
+impl Decal {
+    /// A solid, opaque decal of `width` from `start` to `end`
+    pub fn between(start: P2, end: P2, width: N, color: [f32; 3]) -> Decal {
+        let length = (end - start).norm();
+        Decal {
+            center: start + (end - start) / 2.0,
+            direction: if length > 0.0 {
+                (end - start) / length
+            } else {
+                V2::new(1.0, 0.0)
+            },
+            length,
+            width,
+            color,
+            opacity: 1.0,
+            pattern: DecalPattern::Solid,
+        }
+    }
+
+    pub fn with_pattern(self, pattern: DecalPattern) -> Decal {
+        Decal { pattern, ..self }
+    }
+
+    pub fn with_opacity(self, opacity: f32) -> Decal {
+        Decal { opacity, ..self }
+    }
+
+    pub fn to_instance(&self) -> DecalInstance {
+        DecalInstance {
+            decal_center: [self.center.x, self.center.y],
+            decal_direction: [self.direction.x, self.direction.y],
+            decal_size: [self.length, self.width],
+            decal_color: [self.color[0], self.color[1], self.color[2], self.opacity],
+            decal_pattern: self.pattern.encode(),
+        }
+    }
+}
+
+/// Per-instance data of the unit quad that decals are drawn with
+#[derive(Copy, Clone)]
+pub struct DecalInstance {
+    pub decal_center: [f32; 2],
+    pub decal_direction: [f32; 2],
+    pub decal_size: [f32; 2],
+    pub decal_color: [f32; 4],
+    pub decal_pattern: [f32; 3],
+}
+
+implement_vertex!(
+    DecalInstance,
+    decal_center,
+    decal_direction,
+    decal_size,
+    decal_color,
+    decal_pattern
+);
@@ -2,11 +2,10 @@ pub use kay::External;
 pub use descartes::{N, P3, P2, V3, V4, M4, Iso3, Persp3, ToHomogeneous, Norm, Into2d, Into3d,
                     WithUniqueOrthogonal, Inverse, Rotate};
 
-use glium::{self, index};
-use glium::backend::glutin::Display;
-
 use compact::CVec;
+use culling::BoundingSphere;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
 
 #[derive(Copy, Clone, Debug)]
 pub struct Vertex {
@@ -372,53 +371,58 @@ impl Renderable for Grouper {
     }
 }
 
+static NEXT_GEOMETRY_VERSION: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Backend-independent description of what to draw: one geometry, drawn once per instance
 pub struct Batch {
-    pub vertices: glium::VertexBuffer<Vertex>,
-    pub indices: glium::IndexBuffer<u16>,
+    pub geometry: Geometry,
+    /// Unique for each geometry ever put into a batch,
+    /// so backends know when they need to upload it again
+    pub geometry_version: usize,
     pub instances: Vec<Instance>,
     pub clear_every_frame: bool,
     pub full_frame_instance_end: Option<usize>,
     pub is_decal: bool,
+    /// Below 1, the batch is drawn see-through, after everything opaque
+    pub opacity: f32,
+    /// Around the geometry, for culling instances that can't be seen
+    pub bounds: BoundingSphere,
     pub frame: usize,
 }
 
 impl Batch {
-    pub fn new(prototype: Geometry, window: &Display) -> Batch {
+    pub fn new(prototype: Geometry) -> Batch {
         Batch {
-            vertices: glium::VertexBuffer::new(window, &prototype.vertices).unwrap(),
-            indices: glium::IndexBuffer::new(
-                window,
-                index::PrimitiveType::TrianglesList,
-                &prototype.indices,
-            ).unwrap(),
+            bounds: BoundingSphere::around(&prototype.vertices),
+            geometry: prototype,
+            geometry_version: NEXT_GEOMETRY_VERSION.fetch_add(1, Ordering::Relaxed),
             instances: Vec::new(),
             full_frame_instance_end: None,
             clear_every_frame: true,
             is_decal: false,
+            opacity: 1.0,
             frame: 0,
         }
     }
 
-    pub fn new_individual(
-        geometry: Geometry,
-        instance: Instance,
-        is_decal: bool,
-        window: &Display,
-    ) -> Batch {
+    pub fn new_individual(geometry: Geometry, instance: Instance, is_decal: bool) -> Batch {
         Batch {
-            vertices: glium::VertexBuffer::new(window, &geometry.vertices).unwrap(),
-            indices: glium::IndexBuffer::new(
-                window,
-                index::PrimitiveType::TrianglesList,
-                &geometry.indices,
-            ).unwrap(),
+            bounds: BoundingSphere::around(&geometry.vertices),
+            geometry: geometry,
+            geometry_version: NEXT_GEOMETRY_VERSION.fetch_add(1, Ordering::Relaxed),
             instances: vec![instance],
             clear_every_frame: false,
             full_frame_instance_end: None,
             is_decal: is_decal,
+            opacity: 1.0,
             frame: 0,
         }
     }
+
+    /// The instances to draw, leaving out those of a frame still being added
+    pub fn last_full_frame_instances(&self) -> &[Instance] {
+        &self.instances[..self.full_frame_instance_end.unwrap_or_else(|| self.instances.len())]
+    }
 }
 
 pub fn setup(system: &mut ActorSystem) {
@@ -0,0 +1,491 @@
+
+use glium;
+
+pub use descartes::{N, P3, P2, V3, V4, M4, Iso3, Persp3, ToHomogeneous, Norm, Into2d, Into3d,
+                    WithUniqueOrthogonal, Inverse, Rotate};
+
+use glium::{Surface, BlitTarget, index};
+use glium::texture::{Texture2d, DepthFormat};
+use glium::framebuffer::{SimpleFrameBuffer, DepthRenderBuffer};
+use glium::uniforms::MagnifySamplerFilter;
+use glium::draw_parameters::DepthTest;
+use glium::backend::glutin::Display;
+use kay::External;
+use fnv::FnvHashMap;
+use std::path::PathBuf;
+
+use {Batch, Scene, Eye, Vertex, Viewport, DecalInstance, DECAL_HEIGHT, Frustum};
+use backend::{RenderBackend, RenderTarget, FrameStats};
+use shader_watcher::ShaderWatcher;
+
+fn shader_path(file_name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("src/shader").join(file_name)
+}
+
+struct UploadedGeometry {
+    geometry_version: usize,
+    vertices: glium::VertexBuffer<Vertex>,
+    indices: glium::IndexBuffer<u16>,
+}
+
+/// What a viewport was last rendered into
+struct ViewportTexture {
+    color: Texture2d,
+    depth: DepthRenderBuffer,
+    rendered_in_frame: usize,
+}
+
+/// Program and draw parameters of a draw call. Batches are drawn grouped by these, in
+/// this order, so the backend switches between them as rarely as possible. There is only
+/// one program for batches and nothing is textured yet, so these are all the groups
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+enum DrawState {
+    Opaque,
+    /// Drawn over whatever is below them, so they keep the order of their ids
+    OpaqueDecal,
+    Decals,
+    /// Blended without hiding each other, from back to front
+    Transparent,
+    /// Blended over everything, in the order of their ids
+    TransparentDecal,
+}
+
+impl DrawState {
+    fn of(batch: &Batch) -> DrawState {
+        match (batch.opacity < 1.0, batch.is_decal) {
+            (false, false) => DrawState::Opaque,
+            (false, true) => DrawState::OpaqueDecal,
+            (true, false) => DrawState::Transparent,
+            (true, true) => DrawState::TransparentDecal,
+        }
+    }
+
+    fn params(&self) -> glium::DrawParameters<'static> {
+        let (test, write, blended) = match *self {
+            DrawState::Opaque => (DepthTest::IfLess, true, false),
+            DrawState::OpaqueDecal => (DepthTest::Overwrite, false, false),
+            DrawState::Decals => (DepthTest::IfLessOrEqual, false, true),
+            DrawState::Transparent => (DepthTest::IfLess, false, true),
+            DrawState::TransparentDecal => (DepthTest::Overwrite, false, true),
+        };
+        glium::DrawParameters {
+            depth: glium::Depth { test, write, ..Default::default() },
+            blend: if blended {
+                glium::Blend::alpha_blending()
+            } else {
+                Default::default()
+            },
+            ..Default::default()
+        }
+    }
+}
+
+/// Counts draw calls and how often their state changes in between
+#[derive(Default)]
+struct DrawCounter {
+    stats: FrameStats,
+    last_state: Option<DrawState>,
+}
+
+impl DrawCounter {
+    fn count(&mut self, state: DrawState) {
+        self.stats.draw_calls += 1;
+        if self.last_state != Some(state) {
+            self.stats.state_changes += 1;
+            self.last_state = Some(state);
+        }
+    }
+}
+
+/// How the scene is seen from an eye
+struct Camera {
+    view: [[f32; 4]; 4],
+    perspective: [[f32; 4]; 4],
+    frustum: Frustum,
+}
+
+const NEAR_PLANE: f32 = 0.1;
+const FAR_PLANE: f32 = 50000.0;
+
+impl Camera {
+    fn new(eye: &Eye, aspect_ratio: f32) -> Camera {
+        Camera {
+            view: *Iso3::look_at_rh(&eye.position, &eye.target, &eye.up)
+                .to_homogeneous()
+                .as_ref(),
+            perspective: *Persp3::new(aspect_ratio, eye.field_of_view, NEAR_PLANE, FAR_PLANE)
+                .to_matrix()
+                .as_ref(),
+            frustum: Frustum::new(eye, aspect_ratio, NEAR_PLANE, FAR_PLANE),
+        }
+    }
+}
+
+/// The OpenGL backend, using glium
+pub struct GliumBackend {
+    window: External<Display>,
+    batch_program: glium::Program,
+    batch_program_watcher: ShaderWatcher,
+    decal_program: glium::Program,
+    decal_program_watcher: ShaderWatcher,
+    /// Unit square around the origin, which all decals are instances of
+    decal_quad: UploadedGeometry,
+    clear_color: (f32, f32, f32, f32),
+    /// GPU copies of batch geometries, by scene id and batch id
+    uploaded: FnvHashMap<(usize, u16), UploadedGeometry>,
+    /// By scene id and viewport id
+    viewport_textures: FnvHashMap<(usize, u16), ViewportTexture>,
+    frame: usize,
+    /// Of the frame being drawn
+    stats: FrameStats,
+}
+
+impl GliumBackend {
+    #[allow(redundant_closure)]
+    pub fn new(window: External<Display>, clear_color: (f32, f32, f32, f32)) -> GliumBackend {
+        let decal_quad = UploadedGeometry {
+            geometry_version: 0,
+            vertices: glium::VertexBuffer::new(
+                &*window,
+                &[
+                    Vertex { position: [-0.5, -0.5, 0.0] },
+                    Vertex { position: [0.5, -0.5, 0.0] },
+                    Vertex { position: [0.5, 0.5, 0.0] },
+                    Vertex { position: [-0.5, 0.5, 0.0] },
+                ],
+            ).unwrap(),
+            indices: glium::IndexBuffer::new(
+                &*window,
+                index::PrimitiveType::TrianglesList,
+                &[0, 1, 2, 2, 3, 0],
+            ).unwrap(),
+        };
+
+        GliumBackend {
+            batch_program: program!(&*window, 140 => {
+                vertex: include_str!("shader/solid_140.glslv"),
+                fragment: include_str!("shader/solid_140.glslf")
+            }).unwrap(),
+            batch_program_watcher: ShaderWatcher::new(
+                shader_path("solid_140.glslv"),
+                shader_path("solid_140.glslf"),
+            ),
+            decal_program: program!(&*window, 140 => {
+                vertex: include_str!("shader/decal_140.glslv"),
+                fragment: include_str!("shader/decal_140.glslf")
+            }).unwrap(),
+            decal_program_watcher: ShaderWatcher::new(
+                shader_path("decal_140.glslv"),
+                shader_path("decal_140.glslf"),
+            ),
+            decal_quad: decal_quad,
+            window: window.steal(),
+            clear_color: clear_color,
+            uploaded: FnvHashMap::default(),
+            viewport_textures: FnvHashMap::default(),
+            frame: 0,
+            stats: FrameStats::default(),
+        }
+    }
+
+    /// Draws all batches of the scene as seen from `eye` into `target`: opaque batches
+    /// first, then decals over them and see-through batches last
+    fn draw_from_eye<S: Surface>(
+        &self,
+        scene_id: usize,
+        scene: &Scene,
+        eye: &Eye,
+        target: &mut S,
+    ) -> FrameStats {
+        let (width, height) = target.get_dimensions();
+        let camera = Camera::new(eye, width as f32 / height as f32);
+        let mut counter = DrawCounter::default();
+
+        let mut render_debug_text = String::from("Renderer:\n");
+
+        let mut batches_todo = scene
+            .batches
+            .iter()
+            .map(|(&batch_id, batch)| {
+                let state = DrawState::of(batch);
+                // only see-through batches need to be ordered by depth,
+                // all others keep the order of their ids within their group
+                let order = if state == DrawState::Transparent {
+                    -batch
+                        .last_full_frame_instances()
+                        .first()
+                        .map(|instance| {
+                            camera.frustum.depth(batch.bounds.of_instance(instance).center)
+                        })
+                        .unwrap_or(0.0)
+                } else {
+                    f32::from(batch_id)
+                };
+                (state, order, batch_id, batch)
+            })
+            .collect::<Vec<_>>();
+        batches_todo.sort_by(|&(state_a, order_a, _, _), &(state_b, order_b, _, _)| {
+            state_a.cmp(&state_b).then_with(|| {
+                order_a.partial_cmp(&order_b).unwrap_or(::std::cmp::Ordering::Equal)
+            })
+        });
+
+        let mut decals_drawn = false;
+        for (state, _, batch_id, batch) in batches_todo {
+            let n_instances = batch.last_full_frame_instances().len();
+            if n_instances > 1 {
+                render_debug_text
+                    .push_str(&format!("batch{}: {} instances\n", batch_id, n_instances));
+            }
+            if state > DrawState::Decals && !decals_drawn {
+                self.draw_decals(scene, &camera, &mut counter, target);
+                decals_drawn = true;
+            }
+            self.draw_batch(
+                &self.uploaded[&(scene_id, batch_id)],
+                batch,
+                state,
+                &camera,
+                &mut counter,
+                target,
+            );
+        }
+        if !decals_drawn {
+            self.draw_decals(scene, &camera, &mut counter, target);
+        }
+
+        counter.stats
+    }
+
+    /// Draws those instances of the batch that belong to the last full frame
+    /// and can be seen, if any
+    fn draw_batch<S: Surface>(
+        &self,
+        uploaded: &UploadedGeometry,
+        batch: &Batch,
+        state: DrawState,
+        camera: &Camera,
+        counter: &mut DrawCounter,
+        target: &mut S,
+    ) {
+        let all_instances = batch.last_full_frame_instances();
+        let visible_instances = all_instances
+            .iter()
+            .filter(|instance| camera.frustum.can_see(&batch.bounds.of_instance(instance)))
+            .cloned()
+            .collect::<Vec<_>>();
+        counter.stats.culled_instances += all_instances.len() - visible_instances.len();
+        if visible_instances.is_empty() {
+            return;
+        }
+
+        let uniforms =
+            uniform! {
+            view: camera.view,
+            perspective: camera.perspective,
+            opacity: batch.opacity
+        };
+
+        let instance_buffer = glium::VertexBuffer::new(&*self.window, &visible_instances).unwrap();
+        target
+            .draw(
+                (&uploaded.vertices, instance_buffer.per_instance().unwrap()),
+                &uploaded.indices,
+                &self.batch_program,
+                &uniforms,
+                &state.params(),
+            )
+            .unwrap();
+        counter.count(state);
+    }
+
+    /// Draws all decals of the scene over what is already drawn, in a single instanced call
+    fn draw_decals<S: Surface>(
+        &self,
+        scene: &Scene,
+        camera: &Camera,
+        counter: &mut DrawCounter,
+        target: &mut S,
+    ) {
+        let mut decals_todo = scene.decals.iter().collect::<Vec<_>>();
+        if decals_todo.is_empty() {
+            return;
+        }
+        decals_todo.sort_by_key(|&(key, _)| key);
+        let instances = decals_todo
+            .into_iter()
+            .flat_map(|(_, decals)| decals.iter().map(|decal| decal.to_instance()))
+            .collect::<Vec<DecalInstance>>();
+
+        let uniforms =
+            uniform! {
+            view: camera.view,
+            perspective: camera.perspective,
+            decal_height: DECAL_HEIGHT
+        };
+
+        let instance_buffer = glium::VertexBuffer::new(&*self.window, &instances).unwrap();
+        target
+            .draw(
+                (&self.decal_quad.vertices, instance_buffer.per_instance().unwrap()),
+                &self.decal_quad.indices,
+                &self.decal_program,
+                &uniforms,
+                &DrawState::Decals.params(),
+            )
+            .unwrap();
+        counter.count(DrawState::Decals);
+    }
+
+    /// Renders the viewport into its texture, if it is due for that or changed its size
+    fn render_viewport(
+        &mut self,
+        scene_id: usize,
+        viewport_id: u16,
+        scene: &Scene,
+        viewport: &Viewport,
+        (width, height): (u32, u32),
+    ) {
+        let key = (scene_id, viewport_id);
+        let due = self.viewport_textures
+            .get(&key)
+            .map(|texture| {
+                texture.color.dimensions() != (width, height) ||
+                    self.frame >= texture.rendered_in_frame + viewport.refresh_interval.max(1)
+            })
+            .unwrap_or(true);
+        if !due {
+            return;
+        }
+
+        let reusable = self.viewport_textures.remove(&key).and_then(|texture| {
+            if texture.color.dimensions() == (width, height) {
+                Some(texture)
+            } else {
+                None
+            }
+        });
+        let texture = reusable.unwrap_or_else(|| {
+            ViewportTexture {
+                color: Texture2d::empty(&*self.window, width, height).unwrap(),
+                depth: DepthRenderBuffer::new(&*self.window, DepthFormat::I24, width, height)
+                    .unwrap(),
+                rendered_in_frame: 0,
+            }
+        });
+
+        let stats = {
+            let mut framebuffer =
+                SimpleFrameBuffer::with_depth_buffer(&*self.window, &texture.color, &texture.depth)
+                    .unwrap();
+            framebuffer.clear_color_and_depth(self.clear_color, 1.0);
+            self.draw_from_eye(scene_id, scene, &viewport.eye, &mut framebuffer)
+        };
+        self.stats.add(&stats);
+
+        self.viewport_textures.insert(
+            key,
+            ViewportTexture { rendered_in_frame: self.frame, ..texture },
+        );
+    }
+
+    /// Makes sure the GPU copies of all batch geometries of the scene are up to date
+    /// and forgets those of batches that don't exist anymore
+    fn upload_geometries(&mut self, scene_id: usize, scene: &Scene) {
+        for (&batch_id, batch) in &scene.batches {
+            let up_to_date = self.uploaded
+                .get(&(scene_id, batch_id))
+                .map(|uploaded| uploaded.geometry_version == batch.geometry_version)
+                .unwrap_or(false);
+
+            if !up_to_date {
+                let uploaded = UploadedGeometry {
+                    geometry_version: batch.geometry_version,
+                    vertices: glium::VertexBuffer::new(&*self.window, &batch.geometry.vertices)
+                        .unwrap(),
+                    indices: glium::IndexBuffer::new(
+                        &*self.window,
+                        index::PrimitiveType::TrianglesList,
+                        &batch.geometry.indices,
+                    ).unwrap(),
+                };
+                self.uploaded.insert((scene_id, batch_id), uploaded);
+            }
+        }
+
+        self.uploaded.retain(|&(uploaded_scene_id, batch_id), _| {
+            uploaded_scene_id != scene_id || scene.batches.contains_key(&batch_id)
+        });
+    }
+}
+
+impl RenderBackend for GliumBackend {
+    /// Swaps in recompiled shader programs if their sources changed
+    fn start_frame(&mut self) {
+        self.frame += 1;
+        self.stats.publish();
+        self.stats = FrameStats::default();
+        if let Some(program) = self.batch_program_watcher.recompile_if_changed(&*self.window) {
+            self.batch_program = program;
+        }
+        if let Some(program) = self.decal_program_watcher.recompile_if_changed(&*self.window) {
+            self.decal_program = program;
+        }
+    }
+
+    fn framebuffer_dimensions(&self) -> (u32, u32) {
+        self.window.get_framebuffer_dimensions()
+    }
+
+    fn submit(&mut self, scene_id: usize, scene: &Scene, target: &mut RenderTarget) {
+        let RenderTarget::OpenGL(ref mut target) = *target;
+        self.upload_geometries(scene_id, scene);
+
+        // draw a frame
+        target.clear_color_and_depth(self.clear_color, 1.0);
+        let stats = self.draw_from_eye(scene_id, scene, &scene.eye, target);
+        self.stats.add(&stats);
+
+        let mut viewports = scene.viewports.iter().collect::<Vec<_>>();
+        viewports.sort_by_key(|&(viewport_id, _)| viewport_id);
+        let (frame_width, frame_height) = target.get_dimensions();
+
+        for (&viewport_id, viewport) in viewports {
+            let width = (viewport.region[2] * frame_width as f32) as u32;
+            let height = (viewport.region[3] * frame_height as f32) as u32;
+            if width == 0 || height == 0 {
+                continue;
+            }
+            self.render_viewport(scene_id, viewport_id, scene, viewport, (width, height));
+
+            let texture = &self.viewport_textures[&(scene_id, viewport_id)];
+            texture.color.as_surface().blit_whole_color_to(
+                &*target,
+                &BlitTarget {
+                    left: (viewport.region[0] * frame_width as f32) as u32,
+                    bottom: (viewport.region[1] * frame_height as f32) as u32,
+                    width: width as i32,
+                    height: height as i32,
+                },
+                MagnifySamplerFilter::Linear,
+            );
+        }
+
+        self.viewport_textures.retain(|&(texture_scene_id, viewport_id), _| {
+            texture_scene_id != scene_id || scene.viewports.contains_key(&viewport_id)
+        });
+
+        // let size_points = self.window.get_window().unwrap().get_inner_size_points().unwrap();
+        // let size_pixels = self.window.get_window().unwrap().get_inner_size_pixels().unwrap();
+        // let ui = self.imgui.frame(size_points, size_pixels, 1.0 / 60.0);
+
+        // ui.window(im_str!("Debug Info"))
+        //     .size((600.0, 200.0), ImGuiSetCond_FirstUseEver)
+        //     .build(|| for (key, &(ref text, ref color)) in
+        //         scene.persistent_debug_text.iter().chain(scene.debug_text.iter()) {
+        //         ui.text_colored(*color, im_str!("{}:\n{}", key, text));
+        //     });
+
+        // self.imgui_renderer.render(target, ui).unwrap();
+    }
+}
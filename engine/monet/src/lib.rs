@@ -11,10 +11,18 @@ extern crate compact;
 extern crate compact_macros;
 extern crate fnv;
 extern crate itertools;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
 
 mod geometry;
+mod decal;
+mod animation;
+mod culling;
 mod renderer;
-mod render_context;
+mod backend;
+mod glium_backend;
+mod shader_watcher;
 mod scene;
 
 pub use glium::backend::glutin::Display;
@@ -26,5 +34,9 @@ pub use renderer::{setup, Renderer, RendererID, Renderable, RenderableID, Target
                    EyeListenerID, MSG_EyeListener_eye_moved, MSG_Renderable_setup_in_scene,
                    MSG_Renderable_render_to_scene, ProjectionRequester, ProjectionRequesterID,
                    MSG_ProjectionRequester_projected_3d};
-pub use render_context::RenderContext;
-pub use scene::{Eye, Scene, SceneDescription};
+pub use decal::{Decal, DecalPattern, DecalInstance, DECAL_HEIGHT};
+pub use animation::{Animation, frame_progress};
+pub use culling::{BoundingSphere, Frustum};
+pub use backend::{RenderBackend, RenderWindow, RenderTarget, BackendKind, FrameStats};
+pub use glium_backend::GliumBackend;
+pub use scene::{Eye, Scene, SceneDescription, Viewport};
@@ -2,7 +2,8 @@
 pub use descartes::{N, P3, P2, V3, V4, M4, Iso3, Persp3, ToHomogeneous, Norm, Into2d, Into3d,
                     WithUniqueOrthogonal, Inverse, Rotate};
 use kay::{World, External};
-use glium::Frame;
+
+use RenderTarget;
 
 use super::{Renderer, RendererID};
 
@@ -31,13 +32,15 @@ impl Renderer {
     /// Critical
     pub fn submit(
         &mut self,
-        given_target: &External<Frame>,
+        given_target: &External<RenderTarget>,
         return_to: TargetProviderID,
         world: &mut World,
     ) {
         let mut target = given_target.steal();
-        for scene in &self.scenes {
-            self.render_context.submit(scene, &mut *target);
+        let state = &mut *self.inner;
+        state.backend.start_frame();
+        for (scene_id, scene) in state.scenes.iter().enumerate() {
+            state.backend.submit(scene_id, scene, &mut *target);
         }
 
         return_to.submitted(target, world);
@@ -45,7 +48,7 @@ impl Renderer {
 }
 
 pub trait TargetProvider {
-    fn submitted(&mut self, target: &External<Frame>, world: &mut World);
+    fn submitted(&mut self, target: &External<RenderTarget>, world: &mut World);
 }
 
 mod kay_auto;
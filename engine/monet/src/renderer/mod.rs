@@ -4,9 +4,9 @@ pub use descartes::{N, P3, P2, V3, V4, M4, Iso3, Persp3, ToHomogeneous, Norm, In
 use compact::CVec;
 use kay::{World, ActorSystem, External};
 
-use glium::backend::glutin::Display;
-
-use {Batch, Instance, Scene, SceneDescription, Geometry, RenderContext};
+use {Batch, Instance, Scene, SceneDescription, Geometry, RenderBackend, RenderWindow, Viewport,
+     Decal};
+use backend::create_backend;
 
 mod control;
 pub mod movement;
@@ -26,7 +26,7 @@ pub struct Renderer {
 pub struct RendererState {
     pub current_frame: usize,
     pub scenes: Vec<Scene>,
-    pub render_context: RenderContext,
+    pub backend: Box<RenderBackend>,
 }
 
 impl ::std::ops::Deref for Renderer {
@@ -46,7 +46,7 @@ impl ::std::ops::DerefMut for Renderer {
 impl Renderer {
     pub fn spawn(
         id: RendererID,
-        window: &External<Display>,
+        window: &External<RenderWindow>,
         scenes: &CVec<SceneDescription>,
         clear_color: (f32, f32, f32, f32),
         world: &mut World,
@@ -60,7 +60,7 @@ impl Renderer {
                     .iter()
                     .map(|description| description.to_scene())
                     .collect(),
-                render_context: RenderContext::new(window.clone(), clear_color),
+                backend: create_backend(*window.steal().into_box(), clear_color),
             }),
         }
     }
@@ -72,6 +72,39 @@ impl Renderer {
         self.scenes[scene_id].eye_listeners.push(listener);
     }
 
+    /// Critical
+    pub fn set_viewport(
+        &mut self,
+        scene_id: usize,
+        viewport_id: u16,
+        viewport: Viewport,
+        _: &mut World,
+    ) {
+        self.scenes[scene_id].viewports.insert(viewport_id, viewport);
+    }
+
+    /// Critical
+    pub fn remove_viewport(&mut self, scene_id: usize, viewport_id: u16, _: &mut World) {
+        self.scenes[scene_id].viewports.remove(&viewport_id);
+    }
+
+    /// Replaces all decals that `owner` has in `layer`, removing them if `decals` is empty.
+    /// Critical
+    pub fn set_decals(
+        &mut self,
+        scene_id: usize,
+        layer: u16,
+        owner: u32,
+        decals: &CVec<Decal>,
+        _: &mut World,
+    ) {
+        if decals.is_empty() {
+            self.scenes[scene_id].decals.remove(&(layer, owner));
+        } else {
+            self.scenes[scene_id].decals.insert((layer, owner), decals.to_vec());
+        }
+    }
+
     /// Critical
     pub fn add_batch(
         &mut self,
@@ -80,7 +113,7 @@ impl Renderer {
         prototype: &Geometry,
         _: &mut World,
     ) {
-        let batch = Batch::new(prototype.clone(), &self.render_context.window);
+        let batch = Batch::new(prototype.clone());
         self.scenes[scene_id].batches.insert(batch_id, batch);
     }
 
@@ -94,12 +127,30 @@ impl Renderer {
         is_decal: bool,
         _: &mut World,
     ) {
-        let individual = Batch::new_individual(
-            geometry.clone(),
-            *instance_info,
-            is_decal,
-            &self.render_context.window,
+        let individual = Batch::new_individual(geometry.clone(), *instance_info, is_decal);
+        self.scenes[scene_id].batches.insert(
+            individual_id,
+            individual,
         );
+    }
+
+    /// Like `update_individual` for a decal, but see-through with the given `opacity`.
+    /// Such individuals are drawn after everything opaque, in the order of their ids,
+    /// so one can be highlighted by putting a more opaque one with a higher id over it
+    /// Critical
+    pub fn update_transparent_individual(
+        &mut self,
+        scene_id: usize,
+        individual_id: u16,
+        geometry: &Geometry,
+        instance_info: &Instance,
+        opacity: f32,
+        _: &mut World,
+    ) {
+        let individual = Batch {
+            opacity: opacity,
+            ..Batch::new_individual(geometry.clone(), *instance_info, true)
+        };
         self.scenes[scene_id].batches.insert(
             individual_id,
             individual,
@@ -171,6 +222,10 @@ pub fn setup(system: &mut ActorSystem) {
     control::auto_setup(system);
     movement::auto_setup(system);
     project::auto_setup(system);
+    // picking what's under the cursor stays responsive, however busy the simulation is.
+    // Frame submits aren't prioritized, so they never overtake scene updates sent before
+    system.prioritize::<project::MSG_Renderer_project_2d_to_3d>();
+    system.prioritize::<project::MSG_ProjectionRequester_projected_3d>();
     super::geometry::setup(system);
 }
 
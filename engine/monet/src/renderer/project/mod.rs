@@ -19,7 +19,7 @@ impl Renderer {
         world: &mut World,
     ) {
         let eye = &self.scenes[scene_id].eye;
-        let frame_size = self.render_context.window.get_framebuffer_dimensions();
+        let frame_size = self.backend.framebuffer_dimensions();
 
         // mouse is on the close plane of the frustum
         let normalized_2d_position = V4::new(
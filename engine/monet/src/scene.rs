@@ -6,7 +6,7 @@ use fnv::FnvHashMap;
 use renderer::RenderableID;
 use renderer::movement::EyeListenerID;
 
-use Batch;
+use {Batch, Decal};
 
 #[derive(Copy, Clone)]
 pub struct Eye {
@@ -16,6 +16,18 @@ pub struct Eye {
     pub field_of_view: f32,
 }
 
+/// An additional view into a scene with its own camera, rendered into a texture
+/// that is drawn over a region of the frame
+#[derive(Copy, Clone)]
+pub struct Viewport {
+    pub eye: Eye,
+    /// Left, bottom, width and height of the region, as fractions of the frame size
+    pub region: [f32; 4],
+    /// The texture is only rendered again every this many frames and reused in between,
+    /// so several insets that don't need to be fluid stay cheap
+    pub refresh_interval: usize,
+}
+
 #[derive(Compact, Clone)]
 pub struct SceneDescription {
     pub eye: Eye,
@@ -40,6 +52,8 @@ impl SceneDescription {
             description: self.clone(),
             eye_listeners: CVec::new(),
             batches: FnvHashMap::default(),
+            viewports: FnvHashMap::default(),
+            decals: FnvHashMap::default(),
         }
     }
 }
@@ -48,6 +62,10 @@ pub struct Scene {
     description: SceneDescription,
     pub eye_listeners: CVec<EyeListenerID>,
     pub batches: FnvHashMap<u16, Batch>,
+    /// Drawn over the main view, in the order of their ids
+    pub viewports: FnvHashMap<u16, Viewport>,
+    /// By layer and owner, drawn after all batches in that order
+    pub decals: FnvHashMap<(u16, u32), Vec<Decal>>,
 }
 
 impl ::std::ops::Deref for Scene {
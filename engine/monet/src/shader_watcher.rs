@@ -0,0 +1,84 @@
+use glium;
+use glium::backend::glutin::Display;
+use std::fs::{File, metadata};
+use std::io::Read;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+// checking file modification times every frame would be wasteful
+const FRAMES_BETWEEN_CHECKS: usize = 30;
+
+/// Watches the source files of a shader program and recompiles it when they change,
+/// so shaders can be iterated on without restarting the game (only in debug builds)
+pub struct ShaderWatcher {
+    vertex_path: PathBuf,
+    fragment_path: PathBuf,
+    last_modified: Option<SystemTime>,
+    frames_until_check: usize,
+}
+
+impl ShaderWatcher {
+    pub fn new(vertex_path: PathBuf, fragment_path: PathBuf) -> ShaderWatcher {
+        let mut watcher = ShaderWatcher {
+            vertex_path: vertex_path,
+            fragment_path: fragment_path,
+            last_modified: None,
+            frames_until_check: FRAMES_BETWEEN_CHECKS,
+        };
+        watcher.last_modified = watcher.sources_modified();
+        watcher
+    }
+
+    fn sources_modified(&self) -> Option<SystemTime> {
+        let vertex_modified = metadata(&self.vertex_path).and_then(|m| m.modified()).ok();
+        let fragment_modified = metadata(&self.fragment_path).and_then(|m| m.modified()).ok();
+        vertex_modified.into_iter().chain(fragment_modified).max()
+    }
+
+    fn read_source(path: &PathBuf) -> Result<String, String> {
+        let mut source = String::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut source))
+            .map_err(|err| format!("{:?}: {}", path, err))?;
+        Ok(source)
+    }
+
+    /// Returns a freshly compiled program if the shader sources changed since
+    /// the last successful check. Compilation errors are printed and the
+    /// current program should be kept.
+    pub fn recompile_if_changed(&mut self, window: &Display) -> Option<glium::Program> {
+        if !cfg!(debug_assertions) {
+            return None;
+        }
+
+        if self.frames_until_check > 0 {
+            self.frames_until_check -= 1;
+            return None;
+        }
+        self.frames_until_check = FRAMES_BETWEEN_CHECKS;
+
+        let modified = self.sources_modified();
+        if modified.is_none() || modified == self.last_modified {
+            return None;
+        }
+        self.last_modified = modified;
+
+        let compiled = Self::read_source(&self.vertex_path).and_then(|vertex| {
+            Self::read_source(&self.fragment_path).and_then(|fragment| {
+                glium::Program::from_source(window, &vertex, &fragment, None)
+                    .map_err(|err| format!("{}", err))
+            })
+        });
+
+        match compiled {
+            Ok(program) => {
+                println!("Reloaded shader {:?}", self.fragment_path);
+                Some(program)
+            }
+            Err(err) => {
+                println!("Error reloading shader: {}", err);
+                None
+            }
+        }
+    }
+}
@@ -0,0 +1,165 @@
+use kay::{ActorSystem, World, External};
+use compact::CVec;
+use combo::{Bindings, Combo2};
+use environment::Environment;
+use user_interface::{UserInterfaceID, Event3d, Interactable3d, Interactable3dID,
+                     MSG_Interactable3d_on_event, Interactable2d, Interactable2dID,
+                     MSG_Interactable2d_draw_ui_2d};
+use imgui_sys::ImGuiSetCond_FirstUseEver;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ActionPhase {
+    Started,
+    Stopped,
+}
+
+/// Identifies an action without its name, so dispatching and comparing actions doesn't
+/// have to touch strings. The same name always results in the same ID, so listeners
+/// can check which action they got with `action == ActionId::named("...")`
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct ActionId(u64);
+
+impl ActionId {
+    pub fn named(name: &str) -> ActionId {
+        // FNV-1a
+        let hash = name.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+        });
+        ActionId(hash)
+    }
+}
+
+pub trait ActionListener {
+    fn on_action(&mut self, action: ActionId, phase: ActionPhase, world: &mut World);
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ActionBindings(Bindings);
+
+impl Default for ActionBindings {
+    fn default() -> Self {
+        ActionBindings(Bindings::new(vec![]))
+    }
+}
+
+/// Maps raw input (combos of keys and mouse buttons) to named actions,
+/// which are delivered as messages to everyone who registered for them.
+/// The bindings of all actions live in one user-editable "Actions" settings file,
+/// actions that are missing from it are added with their default bindings.
+#[derive(Compact, Clone)]
+pub struct Actions {
+    id: ActionsID,
+    env: Environment,
+    bindings: External<ActionBindings>,
+    /// With the position of the action's binding, which never changes
+    listeners: CVec<(ActionId, usize, ActionListenerID)>,
+}
+
+impl Actions {
+    pub fn spawn(
+        id: ActionsID,
+        user_interface: UserInterfaceID,
+        env: Environment,
+        world: &mut World,
+    ) -> Actions {
+        user_interface.focus(id.into(), world);
+        user_interface.add_2d(id.into(), world);
+
+        Actions {
+            id,
+            env,
+            bindings: External::new(env.load_settings("Actions")),
+            listeners: CVec::new(),
+        }
+    }
+
+    pub fn register_action(
+        &mut self,
+        action: &CVec<char>,
+        default_binding: Combo2,
+        listener: ActionListenerID,
+        _: &mut World,
+    ) {
+        let name = action.iter().collect::<String>();
+        if !self.bindings.0.contains(&name) {
+            self.bindings.0.add(&name, default_binding);
+            self.env.write_settings("Actions", &*self.bindings);
+        }
+        let binding_position = self.bindings.0.position(&name);
+        self.listeners.push((ActionId::named(&name), binding_position, listener));
+    }
+}
+
+impl Interactable3d for Actions {
+    fn on_event(&mut self, event: Event3d, world: &mut World) {
+        if let Event3d::Combos(combos) = event {
+            self.bindings.0.do_rebinding(&combos.current);
+
+            for &(action, binding_position, listener) in self.listeners.iter() {
+                let binding = &self.bindings.0[binding_position];
+                if binding.is_freshly_in(&combos) {
+                    listener.on_action(action, ActionPhase::Started, world);
+                } else if binding.is_freshly_out(&combos) {
+                    listener.on_action(action, ActionPhase::Stopped, world);
+                }
+            }
+        }
+    }
+}
+
+impl Interactable2d for Actions {
+    fn draw_ui_2d(
+        &mut self,
+        imgui_ui: &External<::imgui::Ui<'static>>,
+        return_to: UserInterfaceID,
+        world: &mut World,
+    ) {
+        let ui = imgui_ui.steal();
+
+        let mut settings_changed = false;
+
+        // shares the window with the camera control settings
+        ui.window(im_str!("Controls"))
+            .size((600.0, 200.0), ImGuiSetCond_FirstUseEver)
+            .collapsible(false)
+            .build(|| {
+                ui.text(im_str!("Actions"));
+                ui.separator();
+
+                settings_changed = self.bindings.0.settings_ui(&ui);
+
+                ui.spacing();
+            });
+
+        if settings_changed {
+            self.env.write_settings("Actions", &*self.bindings);
+        }
+
+        return_to.ui_drawn(ui, world);
+    }
+}
+
+/// Makes `listener` receive `action` whenever its binding is pressed or released
+pub fn register_action(
+    action: &str,
+    default_binding: Combo2,
+    listener: ActionListenerID,
+    world: &mut World,
+) {
+    ActionsID::local_first(world).register_action(
+        action.chars().collect(),
+        default_binding,
+        listener,
+        world,
+    );
+}
+
+pub fn setup(system: &mut ActorSystem, user_interface: UserInterfaceID, env: Environment) {
+    system.register::<Actions>();
+    auto_setup(system);
+
+    ActionsID::spawn(user_interface, env, &mut system.world());
+}
+
+mod kay_auto;
+pub use self::kay_auto::*;
@@ -2,8 +2,11 @@ use kay::{ActorSystem, World, External};
 use monet::{RendererID, Movement};
 use descartes::{P2, P3, V3};
 use combo::Button::*;
-use super::combo::{Bindings, Combo2};
+use combo::GamepadButton::{LeftStickUp, LeftStickDown, LeftStickLeft, LeftStickRight};
+use super::combo::Combo2;
 use super::environment::Environment;
+use super::actions::{register_action, ActionId, ActionListener, ActionListenerID, ActionPhase,
+                     MSG_ActionListener_on_action};
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct CameraControlSettings {
@@ -11,8 +14,6 @@ pub struct CameraControlSettings {
     pub move_speed: f32,
     pub zoom_speed: f32,
     pub invert_y: bool,
-
-    pub bindings: Bindings,
 }
 
 impl Default for CameraControlSettings {
@@ -22,15 +23,6 @@ impl Default for CameraControlSettings {
             zoom_speed: 1.0f32,
             move_speed: 1.0f32,
             invert_y: false,
-            bindings: Bindings::new(vec![
-                ("Move Forward", Combo2::new(&[Up], &[W])),
-                ("Move Backward", Combo2::new(&[Down], &[S])),
-                ("Move Left", Combo2::new(&[Left], &[A])),
-                ("Move Right", Combo2::new(&[Right], &[D])),
-                ("Pan", Combo2::new(&[LShift], &[RShift])),
-                ("Yaw", Combo2::new(&[LAlt], &[RightMouseButton])),
-                ("Pitch", Combo2::new(&[LAlt], &[RightMouseButton])),
-            ]),
         }
     }
 }
@@ -67,6 +59,23 @@ impl CameraControl {
         ui_id.focus(id.into(), world);
         ui_id.add_2d(id.into(), world);
 
+        let bindings = [
+            ("Move Forward", Combo2::new(&[Up], &[W])),
+            ("Move Backward", Combo2::new(&[Down], &[S])),
+            ("Move Left", Combo2::new(&[Left], &[A])),
+            ("Move Right", Combo2::new(&[Right], &[D])),
+            ("Move Forward (Gamepad)", Combo2::new(&[Gamepad(LeftStickUp)], &[])),
+            ("Move Backward (Gamepad)", Combo2::new(&[Gamepad(LeftStickDown)], &[])),
+            ("Move Left (Gamepad)", Combo2::new(&[Gamepad(LeftStickLeft)], &[])),
+            ("Move Right (Gamepad)", Combo2::new(&[Gamepad(LeftStickRight)], &[])),
+            ("Pan", Combo2::new(&[LShift], &[RShift])),
+            ("Yaw", Combo2::new(&[LAlt], &[RightMouseButton])),
+            ("Pitch", Combo2::new(&[LAlt], &[RightMouseButton])),
+        ];
+        for &(action, default_binding) in &bindings {
+            register_action(action, default_binding, id.into(), world);
+        }
+
         CameraControl {
             id,
             renderer_id,
@@ -88,16 +97,6 @@ impl CameraControl {
 impl Interactable3d for CameraControl {
     fn on_event(&mut self, event: Event3d, world: &mut World) {
         match event {
-            Event3d::Combos(combos) => {
-                self.settings.bindings.do_rebinding(&combos.current);
-                self.forward = self.settings.bindings["Move Forward"].is_in(&combos);
-                self.backward = self.settings.bindings["Move Backward"].is_in(&combos);
-                self.left = self.settings.bindings["Move Left"].is_in(&combos);
-                self.right = self.settings.bindings["Move Right"].is_in(&combos);
-                self.pan_modifier = self.settings.bindings["Pan"].is_in(&combos);
-                self.yaw_modifier = self.settings.bindings["Yaw"].is_in(&combos);
-                self.pitch_modifier = self.settings.bindings["Pitch"].is_in(&combos);
-            }
             Event3d::MouseMove(cursor_2d) => {
                 let delta = cursor_2d - self.last_cursor_2d;
                 self.last_cursor_2d = cursor_2d;
@@ -190,6 +189,35 @@ impl Interactable3d for CameraControl {
     }
 }
 
+impl ActionListener for CameraControl {
+    fn on_action(&mut self, action: ActionId, phase: ActionPhase, _: &mut World) {
+        let active = phase == ActionPhase::Started;
+        if action == ActionId::named("Move Forward") ||
+            action == ActionId::named("Move Forward (Gamepad)")
+        {
+            self.forward = active;
+        } else if action == ActionId::named("Move Backward") ||
+                   action == ActionId::named("Move Backward (Gamepad)")
+        {
+            self.backward = active;
+        } else if action == ActionId::named("Move Left") ||
+                   action == ActionId::named("Move Left (Gamepad)")
+        {
+            self.left = active;
+        } else if action == ActionId::named("Move Right") ||
+                   action == ActionId::named("Move Right (Gamepad)")
+        {
+            self.right = active;
+        } else if action == ActionId::named("Pan") {
+            self.pan_modifier = active;
+        } else if action == ActionId::named("Yaw") {
+            self.yaw_modifier = active;
+        } else if action == ActionId::named("Pitch") {
+            self.pitch_modifier = active;
+        }
+    }
+}
+
 use user_interface::{Interactable2d, Interactable2dID, MSG_Interactable2d_draw_ui_2d};
 use imgui_sys::ImGuiSetCond_FirstUseEver;
 
@@ -220,8 +248,6 @@ impl Interactable2d for CameraControl {
                 settings_changed = settings_changed ||
                     ui.checkbox(im_str!("Invert Y"), &mut self.settings.invert_y);
 
-                settings_changed = settings_changed || self.settings.bindings.settings_ui(&ui);
-
                 ui.spacing();
 
             });
@@ -0,0 +1,157 @@
+//! Charts for statistics panels, drawn with imgui's plots as part of the user interface.
+//! All series of a chart share one scale, so they can be compared at a glance.
+use imgui::Ui;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ChartKind {
+    /// One line per series, usually over time
+    Line,
+    /// The series piled on top of each other, the topmost showing their total
+    StackedArea,
+    /// One bar per value, for values of different categories
+    Bar,
+    /// How the values of each series are distributed over this many bins,
+    /// between the smallest and largest value of all series
+    Histogram(usize),
+}
+
+/// A chart of one or several series, built up like imgui's own widgets:
+/// `Chart::new("Trips", ChartKind::Line).series("Generated", &generated).draw(&ui)`
+pub struct Chart<'a> {
+    title: &'a str,
+    kind: ChartKind,
+    series: Vec<(&'a str, &'a [f32])>,
+    height: f32,
+}
+
+fn range(values: &[f32]) -> Option<(f32, f32)> {
+    values.iter().fold(None, |range, &value| match range {
+        None => Some((value, value)),
+        Some((min, max)) => Some((min.min(value), max.max(value))),
+    })
+}
+
+fn histogram(values: &[f32], bins: usize, min: f32, max: f32) -> Vec<f32> {
+    let mut counts = vec![0.0; bins.max(1)];
+    let bin_width = (max - min) / counts.len() as f32;
+    for &value in values {
+        let bin = if bin_width > 0.0 {
+            (((value - min) / bin_width) as usize).min(counts.len() - 1)
+        } else {
+            0
+        };
+        counts[bin] += 1.0;
+    }
+    counts
+}
+
+impl<'a> Chart<'a> {
+    pub fn new(title: &'a str, kind: ChartKind) -> Chart<'a> {
+        Chart {
+            title,
+            kind,
+            series: Vec::new(),
+            height: 50.0,
+        }
+    }
+
+    pub fn series(mut self, name: &'a str, values: &'a [f32]) -> Chart<'a> {
+        self.series.push((name, values));
+        self
+    }
+
+    /// Height of each plot in pixels
+    pub fn height(mut self, height: f32) -> Chart<'a> {
+        self.height = height;
+        self
+    }
+
+    /// The values that are actually plotted for each series, after stacking or binning
+    fn plotted(&self) -> Vec<(&'a str, Vec<f32>)> {
+        match self.kind {
+            ChartKind::Line | ChartKind::Bar => {
+                self.series
+                    .iter()
+                    .map(|&(name, values)| (name, values.to_vec()))
+                    .collect()
+            }
+            ChartKind::StackedArea => {
+                let mut totals = Vec::<f32>::new();
+                let mut layers = self.series
+                    .iter()
+                    .map(|&(name, values)| {
+                        if totals.len() < values.len() {
+                            totals.resize(values.len(), 0.0);
+                        }
+                        for (total, value) in totals.iter_mut().zip(values) {
+                            *total += *value;
+                        }
+                        (name, totals.clone())
+                    })
+                    .collect::<Vec<_>>();
+                // the total first, like the top of the pile
+                layers.reverse();
+                layers
+            }
+            ChartKind::Histogram(bins) => {
+                let all_values = self.series
+                    .iter()
+                    .flat_map(|&(_, values)| values.iter().cloned())
+                    .collect::<Vec<_>>();
+                let (min, max) = range(&all_values).unwrap_or((0.0, 0.0));
+                self.series
+                    .iter()
+                    .map(|&(name, values)| (name, histogram(values, bins, min, max)))
+                    .collect()
+            }
+        }
+    }
+
+    /// Lines are scaled to the range of their values, everything else starts at zero
+    fn scale(&self, plotted: &[(&'a str, Vec<f32>)]) -> (f32, f32) {
+        let all_values = plotted
+            .iter()
+            .flat_map(|&(_, ref values)| values.iter().cloned())
+            .collect::<Vec<_>>();
+        let (min, max) = range(&all_values).unwrap_or((0.0, 1.0));
+        let min = if self.kind == ChartKind::Line {
+            min
+        } else {
+            min.min(0.0)
+        };
+        (min, if max > min { max } else { min + 1.0 })
+    }
+
+    fn overlay(&self, name: &str, values: &[f32]) -> String {
+        match (self.kind, values.last()) {
+            (ChartKind::Histogram(_), _) |
+            (ChartKind::Bar, _) |
+            (_, None) => name.to_owned(),
+            (_, Some(last)) => format!("{}: {:.1}", name, last),
+        }
+    }
+
+    pub fn draw(&self, ui: &Ui) {
+        ui.text(im_str!("{}", self.title));
+        let plotted = self.plotted();
+        let (scale_min, scale_max) = self.scale(&plotted);
+
+        for &(name, ref values) in &plotted {
+            if self.kind == ChartKind::Line {
+                ui.plot_lines(im_str!("##{}{}", self.title, name), values)
+                    .graph_size((0.0, self.height))
+                    .scale_min(scale_min)
+                    .scale_max(scale_max)
+                    .overlay_text(im_str!("{}", self.overlay(name, values)))
+                    .build();
+            } else {
+                ui.plot_histogram(im_str!("##{}{}", self.title, name), values)
+                    .graph_size((0.0, self.height))
+                    .scale_min(scale_min)
+                    .scale_max(scale_max)
+                    .overlay_text(im_str!("{}", self.overlay(name, values)))
+                    .build();
+            }
+        }
+    }
+}
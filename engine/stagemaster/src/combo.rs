@@ -23,7 +23,32 @@ pub enum Button {
     LeftMouseButton,
     MiddleMouseButton,
     RightMouseButton,
-    OtherMouseButton(u8)
+    OtherMouseButton(u8),
+    Gamepad(GamepadButton)
+}
+
+/// Buttons of any connected gamepad. Sticks count as pressed in a direction
+/// once they are pushed far enough into it
+#[derive(Serialize, Deserialize, Hash, PartialEq, Eq, Debug, Copy, Clone)]
+#[cfg_attr(rustfmt, rustfmt_skip)]
+pub enum GamepadButton {
+    South, East, North, West,
+    LeftTrigger, LeftTrigger2, RightTrigger, RightTrigger2,
+    Select, Start, Mode, LeftThumb, RightThumb,
+    DPadUp, DPadDown, DPadLeft, DPadRight,
+    LeftStickUp, LeftStickDown, LeftStickLeft, LeftStickRight,
+    RightStickUp, RightStickDown, RightStickLeft, RightStickRight
+}
+
+impl Button {
+    pub fn is_modifier(&self) -> bool {
+        match *self {
+            Button::LControl | Button::RControl | Button::LShift | Button::RShift |
+            Button::LAlt | Button::RAlt | Button::LWin | Button::RWin | Button::LMenu |
+            Button::RMenu => true,
+            _ => false,
+        }
+    }
 }
 
 pub const MAX_COMBO_LEN: usize = 10;
@@ -48,12 +73,19 @@ impl Combo {
         self.len() == 0
     }
 
+    /// Whether all buttons of this combo are pressed in `other`, without any modifiers
+    /// that aren't part of this combo. Other buttons might be pressed as well, but
+    /// holding Control + G doesn't count as pressing G, for example
     pub fn is_in(&self, other: &Combo) -> bool {
         !self.is_empty() &&
             self.0.iter().all(|opt| {
                 opt.map(|item| other.0.contains(&Some(item))).unwrap_or(
                     true,
                 )
+            }) &&
+            other.0.iter().all(|opt| {
+                opt.map(|item| !item.is_modifier() || self.0.contains(&Some(item)))
+                    .unwrap_or(true)
             })
     }
 
@@ -111,6 +143,10 @@ impl Combo2 {
     pub fn is_freshly_in(&self, other: &ComboListener) -> bool {
         self.0[0].is_freshly_in(other) || self.0[1].is_freshly_in(other)
     }
+
+    pub fn is_freshly_out(&self, other: &ComboListener) -> bool {
+        self.is_in(&other.previous) && !self.is_in(&other.current)
+    }
 }
 
 #[derive(Default, Copy, Clone)]
@@ -120,6 +156,7 @@ pub struct ComboListener {
 }
 
 use monet::glium::glutin::{Event, ElementState};
+use gilrs::{Button as GilrsButton, Axis, Event as GamepadEvent};
 
 impl From<VirtualKeyCode> for Button {
     fn from(source: VirtualKeyCode) -> Self {
@@ -289,7 +326,80 @@ impl From<MouseButton> for Button {
     }
 }
 
+/// How far a stick has to be pushed to count as pressed in that direction
+const STICK_THRESHOLD: f32 = 0.5;
+
+fn gamepad_button(source: GilrsButton) -> Option<GamepadButton> {
+    match source {
+        GilrsButton::South => Some(GamepadButton::South),
+        GilrsButton::East => Some(GamepadButton::East),
+        GilrsButton::North => Some(GamepadButton::North),
+        GilrsButton::West => Some(GamepadButton::West),
+        GilrsButton::LeftTrigger => Some(GamepadButton::LeftTrigger),
+        GilrsButton::LeftTrigger2 => Some(GamepadButton::LeftTrigger2),
+        GilrsButton::RightTrigger => Some(GamepadButton::RightTrigger),
+        GilrsButton::RightTrigger2 => Some(GamepadButton::RightTrigger2),
+        GilrsButton::Select => Some(GamepadButton::Select),
+        GilrsButton::Start => Some(GamepadButton::Start),
+        GilrsButton::Mode => Some(GamepadButton::Mode),
+        GilrsButton::LeftThumb => Some(GamepadButton::LeftThumb),
+        GilrsButton::RightThumb => Some(GamepadButton::RightThumb),
+        GilrsButton::DPadUp => Some(GamepadButton::DPadUp),
+        GilrsButton::DPadDown => Some(GamepadButton::DPadDown),
+        GilrsButton::DPadLeft => Some(GamepadButton::DPadLeft),
+        GilrsButton::DPadRight => Some(GamepadButton::DPadRight),
+        _ => None,
+    }
+}
+
+/// The directions along a stick axis, negative one first
+fn stick_directions(axis: Axis) -> Option<(GamepadButton, GamepadButton)> {
+    match axis {
+        Axis::LeftStickX => Some((GamepadButton::LeftStickLeft, GamepadButton::LeftStickRight)),
+        Axis::LeftStickY => Some((GamepadButton::LeftStickDown, GamepadButton::LeftStickUp)),
+        Axis::RightStickX => Some((GamepadButton::RightStickLeft, GamepadButton::RightStickRight)),
+        Axis::RightStickY => Some((GamepadButton::RightStickDown, GamepadButton::RightStickUp)),
+        _ => None,
+    }
+}
+
 impl ComboListener {
+    /// Returns whether the pressed buttons changed
+    pub fn update_gamepad(&mut self, event: &GamepadEvent) -> bool {
+        let old_current = self.current;
+        match *event {
+            GamepadEvent::ButtonPressed(button, _) => {
+                if let Some(button) = gamepad_button(button) {
+                    self.current.insert(Button::Gamepad(button));
+                }
+            }
+            GamepadEvent::ButtonReleased(button, _) => {
+                if let Some(button) = gamepad_button(button) {
+                    self.current.remove(&Button::Gamepad(button));
+                }
+            }
+            GamepadEvent::AxisChanged(axis, value, _) => {
+                if let Some((negative, positive)) = stick_directions(axis) {
+                    for &(direction, pushed) in
+                        &[(negative, value < -STICK_THRESHOLD), (positive, value > STICK_THRESHOLD)]
+                    {
+                        if pushed {
+                            self.current.insert(Button::Gamepad(direction));
+                        } else {
+                            self.current.remove(&Button::Gamepad(direction));
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+        let something_changed = self.current != old_current;
+        if something_changed {
+            self.previous = old_current;
+        }
+        something_changed
+    }
+
     pub fn update(&mut self, event: &Event) {
         let old_current = self.current;
         let something_changed = match *event {
@@ -362,7 +472,16 @@ impl Bindings {
         }
     }
 
-    fn pos_of(&self, name: &str) -> usize {
+    pub fn contains(&self, name: &str) -> bool {
+        self.bindings.iter().any(|&(ref item_name, _)| item_name == name)
+    }
+
+    pub fn add(&mut self, name: &str, combo: Combo2) {
+        self.bindings.push((name.to_owned(), combo));
+    }
+
+    /// Where the binding of `name` is, which stays the same since bindings are only added
+    pub fn position(&self, name: &str) -> usize {
         self.bindings
             .iter()
             .position(|&(ref item_name, _)| item_name == name)
@@ -436,13 +555,21 @@ impl<'a> ::std::ops::Index<&'a str> for Bindings {
     type Output = Combo2;
 
     fn index(&self, name: &'a str) -> &Combo2 {
-        &self.bindings[self.pos_of(name)].1
+        &self.bindings[self.position(name)].1
+    }
+}
+
+impl ::std::ops::Index<usize> for Bindings {
+    type Output = Combo2;
+
+    fn index(&self, position: usize) -> &Combo2 {
+        &self.bindings[position].1
     }
 }
 
 impl<'a> ::std::ops::IndexMut<&'a str> for Bindings {
     fn index_mut(&mut self, name: &'a str) -> &mut Combo2 {
-        let pos = self.pos_of(name);
+        let pos = self.position(name);
         &mut self.bindings[pos].1
     }
 }
@@ -1,5 +1,6 @@
 use std::fs::{File, OpenOptions, create_dir_all};
 use std::path::PathBuf;
+use std::time::SystemTime;
 use serde::{Serialize, Deserialize};
 
 #[derive(Copy, Clone)]
@@ -23,6 +24,23 @@ impl Environment {
     pub fn load_settings<S>(&self, category: &str) -> S
     where
         for<'a> S: Deserialize<'a> + Default,
+    {
+        self.try_load_settings(category).unwrap_or_else(|err| {
+            println!(
+                "Error loading {} settings: {} from {:?}",
+                category,
+                err,
+                self.setting_path(category)
+            );
+            S::default()
+        })
+    }
+
+    /// Like `load_settings`, but leaves it to the caller what to do if the settings
+    /// file is missing or can't be parsed, like when it is only half written
+    pub fn try_load_settings<S>(&self, category: &str) -> Result<S, String>
+    where
+        for<'a> S: Deserialize<'a>,
     {
         if let Err(err) = create_dir_all(self.setting_dir()) {
             println!(
@@ -31,23 +49,19 @@ impl Environment {
                 err
             );
         };
-        match File::open(self.setting_path(category))
+        File::open(self.setting_path(category))
             .as_mut()
             .map_err(|err| format!("{}", err))
             .and_then(|file| {
                 ::serde_json::from_reader(file).map_err(|err| format!("{}", err))
-            }) {
-            Ok(settings) => settings,
-            Err(err) => {
-                println!(
-                    "Error loading {} settings: {} from {:?}",
-                    category,
-                    err,
-                    self.setting_path(category)
-                );
-                S::default()
-            }
-        }
+            })
+    }
+
+    /// When the settings file of `category` was last modified, if it exists
+    pub fn settings_modified(&self, category: &str) -> Option<SystemTime> {
+        ::std::fs::metadata(self.setting_path(category))
+            .and_then(|metadata| metadata.modified())
+            .ok()
     }
 
     pub fn write_settings<S>(&self, category: &str, settings: &S)
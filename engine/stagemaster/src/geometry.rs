@@ -1,6 +1,6 @@
 use descartes::{Path, Band, Segment, P2, N, FiniteCurve, WithUniqueOrthogonal};
 use compact::{CVec, Compact};
-use monet::{Geometry, Vertex, RendererID, Instance};
+use monet::{Geometry, Vertex, RendererID, Instance, Decal, DecalPattern};
 
 #[derive(Compact, Clone)]
 pub struct CPath {
@@ -49,6 +49,13 @@ impl Compact for AnyShape {
         }
     }
 
+    fn unused_dynamic_bytes(&self) -> usize {
+        match *self {
+            AnyShape::Band(Band { ref path, .. }) => path.unused_dynamic_bytes(),
+            _ => 0,
+        }
+    }
+
     unsafe fn compact(source: *mut Self, dest: *mut Self, new_dynamic_part: *mut u8) {
         ::std::ptr::copy_nonoverlapping(source, dest, 1);
         if let AnyShape::Band(Band { ref mut path, width }) = *source {
@@ -174,6 +181,26 @@ pub fn dash_path<P: Path>(path: &P, dash_length: f32, gap_length: f32) -> Vec<P>
     dashes
 }
 
+/// Decals covering `path`, in pieces short enough to follow its curves
+pub fn path_to_decals<P: Path>(
+    path: &P,
+    width: N,
+    color: [f32; 3],
+    pattern: DecalPattern,
+) -> Vec<Decal> {
+    const MAX_PIECE_LENGTH: N = 4.0;
+    let n_pieces = (path.length() / MAX_PIECE_LENGTH).ceil().max(1.0) as usize;
+    let piece_length = path.length() / n_pieces as N;
+
+    (0..n_pieces)
+        .map(|i| {
+            let start = path.along(i as N * piece_length);
+            let end = path.along((i + 1) as N * piece_length);
+            Decal::between(start, end, width, color).with_pattern(pattern)
+        })
+        .collect()
+}
+
 static mut LAST_DEBUG_THING: u16 = 0;
 pub static mut DEBUG_RENDERER: Option<RendererID> = None;
 
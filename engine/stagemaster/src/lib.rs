@@ -17,12 +17,15 @@ extern crate serde_derive;
 extern crate serde;
 extern crate serde_json;
 extern crate app_dirs;
+extern crate gilrs;
 
 pub mod user_interface;
 pub mod geometry;
 pub mod environment;
 pub mod combo;
 pub mod camera_control;
+pub mod actions;
+pub mod charts;
 
 pub use user_interface::{UserInterface, UserInterfaceID, Interactable3d, Interactable3dID,
                          Event3d, Interactable2d, Interactable2dID, MSG_UserInterface_add,
@@ -1,10 +1,12 @@
 use kay::{ActorSystem, External, World};
 use compact::CVec;
 use descartes::{N, P2, V2, P3, Into2d, Shape};
-use monet::{RendererID, RenderableID, SceneDescription, Display};
+use monet::{RendererID, RenderableID, SceneDescription, Display, BackendKind, RenderWindow,
+            RenderTarget};
 use monet::glium::glutin::{ContextBuilder, Event, WindowBuilder, WindowEvent, MouseScrollDelta,
                            ElementState, MouseButton, KeyboardInput};
 use monet::glium::glutin::EventsLoop;
+use gilrs::Gilrs;
 pub use monet::glium::glutin::VirtualKeyCode;
 use std::collections::{HashMap, HashSet};
 use imgui::{ImGui, ImVec2, ImVec4, ImGuiSetCond_FirstUseEver, ImGuiKey};
@@ -65,6 +67,7 @@ pub struct UserInterface {
 
 pub struct UserInterfaceInner {
     events_loop: EventsLoop,
+    gamepads: Gilrs,
     window: Display,
     renderer_id: RendererID,
     camera_control_id: CameraControlID,
@@ -80,7 +83,7 @@ pub struct UserInterfaceInner {
     focused_interactables: HashSet<Interactable3dID>,
     interactables_2d: Vec<Interactable2dID>,
     interactables_2d_todo: Vec<Interactable2dID>,
-    parked_frame: Option<Box<::monet::glium::Frame>>,
+    parked_frame: Option<Box<RenderTarget>>,
     imgui: ImGui,
     imgui_capture_keyboard: bool,
     imgui_capture_mouse: bool,
@@ -182,6 +185,7 @@ impl UserInterface {
             inner: External::new(UserInterfaceInner {
                 window: *window.steal().into_box(),
                 events_loop: *events_loop.steal().into_box(),
+                gamepads: Gilrs::new(),
                 renderer_id: renderer_id,
                 camera_control_id: CameraControlID::spawn(renderer_id, id, env, world),
                 mouse_button_state: [false; 5],
@@ -387,6 +391,19 @@ impl UserInterface {
             }
         }
 
+        let gamepad_events = self.gamepads
+            .poll_events()
+            .map(|(_, event)| event)
+            .collect::<Vec<_>>();
+
+        for event in gamepad_events {
+            if self.combo_listener.update_gamepad(&event) {
+                for interactable in &self.focused_interactables {
+                    interactable.on_event(Event3d::Combos(self.combo_listener), world);
+                }
+            }
+        }
+
         for interactable in self.interactables.keys() {
             interactable.on_event(Event3d::Frame, world)
         }
@@ -429,10 +446,11 @@ impl UserInterface {
             let target = ::std::mem::replace(&mut self.parked_frame, None).expect(
                 "Should have parked target",
             );
-            target.finish().unwrap();
+            target.finish();
         }
 
-        let target = External::new(self.window.draw());
+        // imgui is drawn with glium, so the UI can only draw into OpenGL frames for now
+        let target = External::new(RenderTarget::OpenGL(self.window.draw()));
 
         self.renderer_id.submit(target, self.id.into(), world);
     }
@@ -495,10 +513,9 @@ impl ProjectionRequester for UserInterface {
 }
 
 use monet::{TargetProvider, TargetProviderID, MSG_TargetProvider_submitted};
-use monet::glium::Frame;
 
 impl TargetProvider for UserInterface {
-    fn submitted(&mut self, target: &External<Frame>, world: &mut World) {
+    fn submitted(&mut self, target: &External<RenderTarget>, world: &mut World) {
         self.parked_frame = Some(target.steal().into_box());
 
         let size_points = self.window.gl_window().get_inner_size_points().unwrap();
@@ -545,12 +562,15 @@ impl UserInterface {
             let mut target = ::std::mem::replace(&mut self.parked_frame, None).expect(
                 "Should have parked target",
             );
-            self.imgui_renderer
-                .render(&mut *target, unsafe {
-                    ::std::ptr::read(Box::into_raw(imgui_ui.steal().into_box()))
-                })
-                .unwrap();
-            target.finish().unwrap();
+            {
+                let RenderTarget::OpenGL(ref mut frame) = *target;
+                self.imgui_renderer
+                    .render(frame, unsafe {
+                        ::std::ptr::read(Box::into_raw(imgui_ui.steal().into_box()))
+                    })
+                    .unwrap();
+            }
+            target.finish();
         }
     }
 
@@ -575,6 +595,11 @@ impl UserInterface {
     }
 }
 
+#[derive(Serialize, Deserialize, Copy, Clone, Default)]
+pub struct RenderingSettings {
+    pub backend: BackendKind,
+}
+
 pub fn setup(
     system: &mut ActorSystem,
     renderables: CVec<RenderableID>,
@@ -585,6 +610,9 @@ pub fn setup(
     ::monet::setup(system);
     system.register::<UserInterface>();
     auto_setup(system);
+    // drawing the UI is interactive, so it doesn't wait for other messages
+    system.prioritize::<MSG_UserInterface_ui_drawn>();
+    system.prioritize::<MSG_Interactable2d_draw_ui_2d>();
 
     super::camera_control::setup(system);
 
@@ -592,10 +620,15 @@ pub fn setup(
     let events_loop = EventsLoop::new();
     let window = Display::new(window_builder, context, &events_loop).unwrap();
 
+    let rendering_settings: RenderingSettings = env.load_settings("Rendering");
+    let render_window = match rendering_settings.backend {
+        BackendKind::OpenGL => RenderWindow::OpenGL(window.clone()),
+    };
+
     let mut scene = SceneDescription::new(renderables);
     scene.eye.position *= 30.0;
     let renderer_id = RendererID::spawn(
-        External::new(window.clone()),
+        External::new(render_window),
         vec![scene].into(),
         clear_color,
         &mut system.world(),
@@ -609,6 +642,8 @@ pub fn setup(
         &mut system.world(),
     );
 
+    ::actions::setup(system, ui_id, env);
+
     (ui_id, renderer_id)
 }
 
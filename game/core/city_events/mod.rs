@@ -0,0 +1,239 @@
+use kay::{ActorSystem, World, External};
+use compact::CVec;
+use descartes::{P2, P3, V3};
+use monet::{RendererID, EyeListener, EyeListenerID, Eye, Movement, MSG_EyeListener_eye_moved};
+use stagemaster::{UserInterfaceID, Interactable2d, Interactable2dID,
+                  MSG_Interactable2d_draw_ui_2d};
+use imgui::ImGuiSetCond_FirstUseEver;
+use core::simulation::{Timestamp, TimeOfDay, Simulatable, SimulatableID, MSG_Simulatable_tick};
+
+const MAX_HISTORY: usize = 100;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum CityEventKind {
+    Gridlock,
+    BuildingAbandoned,
+    RoadsOpened,
+    SpecialEvent,
+    BusinessOpened,
+    BusinessClosed,
+    BusinessRelocated,
+}
+
+impl CityEventKind {
+    fn title(&self) -> &'static str {
+        match *self {
+            CityEventKind::Gridlock => "Gridlock",
+            CityEventKind::BuildingAbandoned => "Building abandoned",
+            CityEventKind::RoadsOpened => "Roads opened",
+            CityEventKind::SpecialEvent => "Special event",
+            CityEventKind::BusinessOpened => "Business opened",
+            CityEventKind::BusinessClosed => "Business closed",
+            CityEventKind::BusinessRelocated => "Business relocated",
+        }
+    }
+
+    /// Milestones are only announced the first time they happen,
+    /// later occurrences only show up in the history
+    fn is_milestone(&self) -> bool {
+        match *self {
+            CityEventKind::Gridlock => true,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Compact, Clone)]
+pub struct CityEvent {
+    pub kind: CityEventKind,
+    pub text: CVec<char>,
+    pub location: Option<P2>,
+    pub at: Timestamp,
+}
+
+pub trait CityEventListener {
+    fn on_city_event(&mut self, event: &CityEvent, world: &mut World);
+}
+
+/// Collects notable things that happen in the city, published by all kinds of subsystems,
+/// passes them on to subscribers and shows them as notifications and in a history log
+#[derive(Compact, Clone)]
+pub struct CityEvents {
+    id: CityEventsID,
+    renderer_id: RendererID,
+    current_tick: Timestamp,
+    listeners: CVec<CityEventListenerID>,
+    happened_kinds: CVec<CityEventKind>,
+    notifications: CVec<CityEvent>,
+    history: CVec<CityEvent>,
+    eye_target: P3,
+}
+
+impl CityEvents {
+    pub fn spawn(
+        id: CityEventsID,
+        user_interface: UserInterfaceID,
+        renderer_id: RendererID,
+        world: &mut World,
+    ) -> CityEvents {
+        user_interface.add_2d(id.into(), world);
+        renderer_id.add_eye_listener(0, id.into(), world);
+
+        CityEvents {
+            id,
+            renderer_id,
+            current_tick: Timestamp::new(0),
+            listeners: CVec::new(),
+            happened_kinds: CVec::new(),
+            notifications: CVec::new(),
+            history: CVec::new(),
+            eye_target: P3::new(0.0, 0.0, 0.0),
+        }
+    }
+
+    pub fn subscribe(&mut self, listener: CityEventListenerID, _: &mut World) {
+        self.listeners.push(listener);
+    }
+
+    pub fn publish(
+        &mut self,
+        kind: CityEventKind,
+        text: &CVec<char>,
+        location: Option<P2>,
+        world: &mut World,
+    ) {
+        let event = CityEvent {
+            kind,
+            text: text.clone(),
+            location,
+            at: self.current_tick,
+        };
+
+        for listener in &self.listeners {
+            listener.on_city_event(event.clone(), world);
+        }
+
+        let first_time = !self.happened_kinds.contains(&kind);
+        if first_time {
+            self.happened_kinds.push(kind);
+        }
+        if first_time || !kind.is_milestone() {
+            self.notifications.push(event.clone());
+        }
+
+        self.history.push(event);
+        if self.history.len() > MAX_HISTORY {
+            self.history.remove(0);
+        }
+    }
+}
+
+impl Simulatable for CityEvents {
+    fn tick(&mut self, _dt: f32, current_tick: Timestamp, _: &mut World) {
+        self.current_tick = current_tick;
+    }
+}
+
+impl EyeListener for CityEvents {
+    fn eye_moved(&mut self, eye: Eye, _movement: Movement, _: &mut World) {
+        self.eye_target = eye.target;
+    }
+}
+
+fn describe(event: &CityEvent) -> String {
+    let (h, m) = TimeOfDay::from_tick(event.at).hours_minutes();
+    format!(
+        "{:02}:{:02} {}: {}",
+        h,
+        m,
+        event.kind.title(),
+        event.text.iter().cloned().collect::<String>()
+    )
+}
+
+impl Interactable2d for CityEvents {
+    fn draw_ui_2d(
+        &mut self,
+        imgui_ui: &External<::imgui::Ui<'static>>,
+        return_to: UserInterfaceID,
+        world: &mut World,
+    ) {
+        let ui = imgui_ui.steal();
+
+        let mut jump_to = None;
+        let mut dismissed = None;
+        let mut dismiss_all = false;
+
+        ui.window(im_str!("Notifications"))
+            .size((350.0, 200.0), ImGuiSetCond_FirstUseEver)
+            .collapsible(true)
+            .build(|| {
+                if self.notifications.is_empty() {
+                    ui.text(im_str!("Nothing new"));
+                } else if ui.small_button(im_str!("Dismiss all")) {
+                    dismiss_all = true;
+                }
+
+                for (i, event) in self.notifications.iter().enumerate() {
+                    ui.text(im_str!("{}", describe(event)));
+                    if let Some(location) = event.location {
+                        if ui.small_button(im_str!("Go##notification{}", i)) {
+                            jump_to = Some(location);
+                        }
+                        ui.same_line(0.0);
+                    }
+                    if ui.small_button(im_str!("Dismiss##notification{}", i)) {
+                        dismissed = Some(i);
+                    }
+                }
+
+                if ui.collapsing_header(im_str!("History")).build() {
+                    for (i, event) in self.history.iter().enumerate().rev() {
+                        if let Some(location) = event.location {
+                            if ui.small_button(im_str!("Go##history{}", i)) {
+                                jump_to = Some(location);
+                            }
+                            ui.same_line(0.0);
+                        }
+                        ui.text(im_str!("{}", describe(event)));
+                    }
+                }
+            });
+
+        if dismiss_all {
+            self.notifications = CVec::new();
+        } else if let Some(i) = dismissed {
+            self.notifications.remove(i);
+        }
+
+        if let Some(location) = jump_to {
+            self.renderer_id.move_eye(
+                0,
+                Movement::ShiftAbsolute(V3::new(
+                    location.x - self.eye_target.x,
+                    location.y - self.eye_target.y,
+                    0.0,
+                )),
+                world,
+            );
+        }
+
+        return_to.ui_drawn(ui, world);
+    }
+}
+
+/// Publishes an event to the city's event bus
+pub fn publish(kind: CityEventKind, text: &str, location: Option<P2>, world: &mut World) {
+    CityEventsID::local_first(world).publish(kind, text.chars().collect(), location, world);
+}
+
+pub fn setup(system: &mut ActorSystem, user_interface: UserInterfaceID, renderer_id: RendererID) {
+    system.register::<CityEvents>();
+    system.make_restorable::<CityEvents>();
+    auto_setup(system);
+
+    CityEventsID::spawn(user_interface, renderer_id, &mut system.world());
+}
+
+mod kay_auto;
+pub use self::kay_auto::*;
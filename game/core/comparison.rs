@@ -0,0 +1,226 @@
+//! Comparison runs, for evaluating two versions of a city against each other.
+//!
+//! With `CITYBOUND_RUN_TICKS` set, the city (usually built from `CITYBOUND_SCENARIO`)
+//! is simulated as fast as possible and without rendering for that many ticks, then a
+//! report of travel times, congestion and emissions is written to
+//! `reports/<CITYBOUND_RUN_NAME>.json` and the game quits. If `CITYBOUND_COMPARE_WITH`
+//! names the report of an earlier run, a side-by-side comparison with it is written to
+//! `reports/<earlier>_vs_<CITYBOUND_RUN_NAME>.md` as well. For a fair comparison, both
+//! runs should use the same `CITYBOUND_SEED`.
+//!
+//! With `CITYBOUND_CHECK_CONSISTENCY` set as well, lanes are checked for violated
+//! invariants at the end of the run (see `transport::consistency`), and the game quits
+//! with an error code if there are any, so runs can catch simulation bugs in CI.
+//!
+//! With `CITYBOUND_EVENTS_FILE` set, every step of every driven trip is logged there, in
+//! the format of MATSim events (see `transport::event_log`).
+//!
+//! With `CITYBOUND_POPULATION` set, the families of the city are imported from the synthetic
+//! population in that directory (see `economy::synthetic_population`).
+//!
+//! There are no savegames yet, so the cities compared are scenarios or variants of them.
+use std::fs::{File, create_dir_all};
+use std::io::{Read, Write};
+use std::sync::atomic::Ordering;
+use serde_json;
+use core::metrics::{TRIPS_CREATED, TRIPS_SUCCEEDED, TRIPS_FAILED, ROAD_TRIPS_SUCCEEDED,
+                    ROAD_TRIP_TICKS, VEHICLE_METERS, VEHICLE_TICKS, STOPPED_VEHICLE_TICKS};
+use core::simulation::TICKS_PER_SIM_MINUTE;
+use kay::ActorSystem;
+use transport::consistency;
+
+const REPORT_DIR: &str = "reports";
+/// Ticks simulated per frame, instead of pacing them in real time
+const TICKS_PER_FRAME: usize = 200;
+/// CO2 emitted by an average passenger car per km driven
+const CO2_GRAMS_PER_KM: f64 = 170.0;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RunReport {
+    pub name: String,
+    pub ticks: usize,
+    pub trips_created: usize,
+    pub trips_succeeded: usize,
+    pub trips_failed: usize,
+    pub average_road_trip_minutes: f64,
+    pub vehicle_km: f64,
+    /// Share of the time cars spent standing (almost) still
+    pub stopped_share: f64,
+    /// Estimated from the distance driven
+    pub co2_kg: f64,
+}
+
+fn ratio(part: usize, whole: usize) -> f64 {
+    if whole == 0 {
+        0.0
+    } else {
+        part as f64 / whole as f64
+    }
+}
+
+impl RunReport {
+    /// Everything the metrics counted since the game started
+    pub fn collect(name: &str, ticks: usize) -> RunReport {
+        let vehicle_km = VEHICLE_METERS.load(Ordering::Relaxed) as f64 / 1000.0;
+
+        RunReport {
+            name: name.to_owned(),
+            ticks,
+            trips_created: TRIPS_CREATED.load(Ordering::Relaxed),
+            trips_succeeded: TRIPS_SUCCEEDED.load(Ordering::Relaxed),
+            trips_failed: TRIPS_FAILED.load(Ordering::Relaxed),
+            average_road_trip_minutes: ratio(
+                ROAD_TRIP_TICKS.load(Ordering::Relaxed),
+                ROAD_TRIPS_SUCCEEDED.load(Ordering::Relaxed),
+            ) / TICKS_PER_SIM_MINUTE as f64,
+            vehicle_km,
+            stopped_share: ratio(
+                STOPPED_VEHICLE_TICKS.load(Ordering::Relaxed),
+                VEHICLE_TICKS.load(Ordering::Relaxed),
+            ),
+            co2_kg: vehicle_km * CO2_GRAMS_PER_KM / 1000.0,
+        }
+    }
+
+    /// A markdown table with this run's numbers next to `other`'s, and the change
+    pub fn side_by_side(&self, other: &RunReport) -> String {
+        let rows = [
+            ("Ticks", self.ticks as f64, other.ticks as f64),
+            ("Trips created", self.trips_created as f64, other.trips_created as f64),
+            ("Trips succeeded", self.trips_succeeded as f64, other.trips_succeeded as f64),
+            ("Trips failed", self.trips_failed as f64, other.trips_failed as f64),
+            (
+                "Average road trip (min)",
+                self.average_road_trip_minutes,
+                other.average_road_trip_minutes,
+            ),
+            ("Vehicle km", self.vehicle_km, other.vehicle_km),
+            (
+                "Time cars stood still (%)",
+                self.stopped_share * 100.0,
+                other.stopped_share * 100.0,
+            ),
+            ("CO2 (kg)", self.co2_kg, other.co2_kg),
+        ];
+
+        let mut table = format!(
+            "| | {} | {} | Change |\n|---|---|---|---|\n",
+            self.name,
+            other.name
+        );
+        for &(label, this, that) in &rows {
+            let change = if this == 0.0 {
+                "-".to_owned()
+            } else {
+                format!("{:+.1}%", (that - this) / this * 100.0)
+            };
+            table.push_str(&format!("| {} | {:.2} | {:.2} | {} |\n", label, this, that, change));
+        }
+        table
+    }
+}
+
+fn write_file(file_name: &str, contents: &str) {
+    let path = format!("{}/{}", REPORT_DIR, file_name);
+    let result = create_dir_all(REPORT_DIR)
+        .and_then(|_| File::create(&path))
+        .and_then(|mut file| file.write_all(contents.as_bytes()));
+    match result {
+        Ok(()) => println!("Wrote {}", path),
+        Err(err) => println!("Error writing {}: {}", path, err),
+    }
+}
+
+fn read_report(name: &str) -> Option<RunReport> {
+    let path = format!("{}/{}.json", REPORT_DIR, name);
+    let mut contents = String::new();
+    match File::open(&path).and_then(|mut file| file.read_to_string(&mut contents)) {
+        Ok(_) => serde_json::from_str(&contents).ok(),
+        Err(err) => {
+            println!("Error reading {}: {}", path, err);
+            None
+        }
+    }
+}
+
+pub struct ComparisonRun {
+    name: String,
+    ticks: usize,
+    ticks_left: usize,
+    compare_with: Option<String>,
+    check_consistency: bool,
+}
+
+impl ComparisonRun {
+    /// Also seeds the simulation's random numbers if `CITYBOUND_SEED` is given,
+    /// so it has to be called before anything random happens
+    pub fn from_env() -> Option<ComparisonRun> {
+        if let Some(seed) = ::std::env::var("CITYBOUND_SEED").ok().and_then(|seed| {
+            seed.parse::<u32>().ok()
+        })
+        {
+            ::core::random::seed(seed);
+        }
+
+        ::std::env::var("CITYBOUND_RUN_TICKS")
+            .ok()
+            .and_then(|ticks| ticks.parse::<usize>().ok())
+            .map(|ticks| {
+                ComparisonRun {
+                    name: ::std::env::var("CITYBOUND_RUN_NAME").unwrap_or_else(
+                        |_| "run".to_owned(),
+                    ),
+                    ticks: ticks,
+                    ticks_left: ticks,
+                    compare_with: ::std::env::var("CITYBOUND_COMPARE_WITH").ok(),
+                    check_consistency: ::std::env::var("CITYBOUND_CHECK_CONSISTENCY").is_ok(),
+                }
+            })
+    }
+
+    pub fn ticks_due(&self) -> usize {
+        self.ticks_left.min(TICKS_PER_FRAME)
+    }
+
+    pub fn count_tick(&mut self) {
+        self.ticks_left -= 1;
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.ticks_left == 0
+    }
+
+    pub fn write_reports(&self) {
+        let report = RunReport::collect(&self.name, self.ticks);
+        match serde_json::to_string_pretty(&report) {
+            Ok(json) => write_file(&format!("{}.json", self.name), &json),
+            Err(err) => println!("Error serializing report: {}", err),
+        }
+
+        if let Some(earlier) = self.compare_with.as_ref().and_then(|name| read_report(name)) {
+            if earlier.ticks != report.ticks {
+                println!(
+                    "Warning: {} ran for {} ticks, {} for {}",
+                    earlier.name,
+                    earlier.ticks,
+                    report.name,
+                    report.ticks
+                );
+            }
+            let comparison = earlier.side_by_side(&report);
+            println!("{}", comparison);
+            write_file(&format!("{}_vs_{}.md", earlier.name, report.name), &comparison);
+        }
+    }
+
+    /// Whether the world passed the consistency check, if one was asked for
+    pub fn passes_consistency_check(&self, system: &ActorSystem) -> bool {
+        if !self.check_consistency {
+            return true;
+        }
+        let report = consistency::check(&system.snapshot());
+        print!("{}", report.summary());
+        write_file(&format!("{}_consistency.txt", self.name), &report.summary());
+        report.is_consistent()
+    }
+}
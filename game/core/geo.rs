@@ -0,0 +1,52 @@
+use descartes::{N, P2};
+
+const EARTH_RADIUS: f64 = 6_378_137.0;
+
+/// Maps WGS84 coordinates to the local metric plane used by the simulation and back.
+/// Uses an equirectangular projection around `origin`, which is accurate enough
+/// for city-sized areas and is shared by all importers and exporters,
+/// so imported data lines up.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct GeoReference {
+    pub origin_lat: f64,
+    pub origin_lon: f64,
+}
+
+impl Default for GeoReference {
+    fn default() -> Self {
+        GeoReference {
+            origin_lat: 0.0,
+            origin_lon: 0.0,
+        }
+    }
+}
+
+impl GeoReference {
+    pub fn to_local(&self, lat: f64, lon: f64) -> P2 {
+        let x = (lon - self.origin_lon).to_radians() * EARTH_RADIUS *
+            self.origin_lat.to_radians().cos();
+        let y = (lat - self.origin_lat).to_radians() * EARTH_RADIUS;
+        P2::new(x as N, y as N)
+    }
+
+    pub fn to_geo(&self, position: P2) -> (f64, f64) {
+        let lat = self.origin_lat + (f64::from(position.y) / EARTH_RADIUS).to_degrees();
+        let lon = self.origin_lon +
+            (f64::from(position.x) / (EARTH_RADIUS * self.origin_lat.to_radians().cos()))
+                .to_degrees();
+        (lat, lon)
+    }
+}
+
+#[test]
+fn test_geo_reference_roundtrip() {
+    let reference = GeoReference {
+        origin_lat: 52.52,
+        origin_lon: 13.405,
+    };
+    let local = reference.to_local(52.53, 13.42);
+    let (lat, lon) = reference.to_geo(local);
+    assert!((lat - 52.53).abs() < 1e-6);
+    assert!((lon - 13.42).abs() < 1e-6);
+    assert!(reference.to_local(52.52, 13.405).x.abs() < 1e-3);
+}
@@ -1,6 +1,7 @@
 extern crate open;
 
 use kay::{ActorSystem, World, Networking};
+use monet::FrameStats;
 use monet::glium::glutin::WindowBuilder;
 use stagemaster::UserInterfaceID;
 use std::any::Any;
@@ -82,6 +83,15 @@ pub fn networking_from_env_args() -> Networking {
 
 }
 
+/// Set `CITYBOUND_AUDIT_ID_CASTS` to check that untyped ID casts hit actors of the
+/// expected type, see `kay::ActorSystem::audit_id_casts`
+pub fn audit_id_casts_from_env(system: &mut ActorSystem) {
+    if ::std::env::var("CITYBOUND_AUDIT_ID_CASTS").is_ok() {
+        println!("Auditing untyped ID casts");
+        system.audit_id_casts(true);
+    }
+}
+
 pub fn build_window(machine_id: u8) -> WindowBuilder {
     WindowBuilder::new()
         .with_title(format!("Citybound (machine {})", machine_id))
@@ -109,6 +119,44 @@ pub fn print_instance_counts(system: &mut ActorSystem, user_interface: UserInter
     );
 }
 
+pub fn print_id_cast_counts(system: &mut ActorSystem, user_interface: UserInterfaceID) {
+    let counts = system.get_id_cast_counts();
+    if !counts.is_empty() {
+        user_interface.add_debug_text(
+            "Untyped ID casts".chars().collect(),
+            counts
+                .iter()
+                .take(10)
+                .map(|&(site, count)| format!("{}: {}\n", site, count))
+                .collect::<String>()
+                .chars()
+                .collect(),
+            [0.0, 0.0, 0.0, 1.0],
+            false,
+            &mut system.world(),
+        );
+    }
+}
+
+/// Draw calls of the last frame, to check that batching and culling keep them few
+pub fn print_render_stats(user_interface: UserInterfaceID, world: &mut World) {
+    let stats = FrameStats::last_frame();
+    user_interface.add_debug_text(
+        "Rendering".chars().collect(),
+        format!(
+            "{} draw calls\n{} state changes\n{} instances culled",
+            stats.draw_calls,
+            stats.state_changes,
+            stats.culled_instances
+        ).as_str()
+            .chars()
+            .collect(),
+        [0.0, 0.0, 0.0, 0.5],
+        false,
+        world,
+    );
+}
+
 pub fn print_network_turn(system: &mut ActorSystem, user_interface: UserInterfaceID) {
     user_interface.add_debug_text(
         "Networking turn".chars().collect(),
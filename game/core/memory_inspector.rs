@@ -0,0 +1,110 @@
+//! Shows how much memory the instances of each actor type use, and how much that grew
+//! since the game started, to track down growing obstacle lists or route tables in
+//! long sessions. Measuring looks at every actor instance, so it only happens every
+//! few seconds, and only if enabled in the "Memory Inspector" settings.
+use kay::{ActorSystem, MemoryUsage};
+use stagemaster::UserInterfaceID;
+use std::time::{Duration, Instant};
+
+/// Only this many of the largest actor types are shown
+const SHOWN_ACTOR_TYPES: usize = 12;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct MemoryInspectorSettings {
+    pub enabled: bool,
+    pub interval_seconds: u64,
+}
+
+impl Default for MemoryInspectorSettings {
+    fn default() -> Self {
+        MemoryInspectorSettings {
+            enabled: false,
+            interval_seconds: 5,
+        }
+    }
+}
+
+pub struct MemoryInspector {
+    settings: MemoryInspectorSettings,
+    first: Vec<MemoryUsage>,
+    latest: Vec<MemoryUsage>,
+    last_measured: Option<Instant>,
+}
+
+fn format_bytes(bytes: usize) -> String {
+    if bytes >= 1024 * 1024 {
+        format!("{:.1} MB", bytes as f32 / (1024.0 * 1024.0))
+    } else if bytes >= 1024 {
+        format!("{:.1} KB", bytes as f32 / 1024.0)
+    } else {
+        format!("{} B", bytes)
+    }
+}
+
+impl MemoryInspector {
+    pub fn from_settings() -> MemoryInspector {
+        MemoryInspector {
+            settings: ::ENV.load_settings("Memory Inspector"),
+            first: Vec::new(),
+            latest: Vec::new(),
+            last_measured: None,
+        }
+    }
+
+    /// Measures again if the last measurement is old enough
+    pub fn measure(&mut self, system: &ActorSystem) {
+        if !self.settings.enabled {
+            return;
+        }
+        let due = self.last_measured.map_or(true, |last_measured| {
+            last_measured.elapsed() >= Duration::from_secs(self.settings.interval_seconds)
+        });
+        if due {
+            self.latest = system.get_memory_usage();
+            if self.first.is_empty() {
+                self.first = self.latest.clone();
+            }
+            self.last_measured = Some(Instant::now());
+        }
+    }
+
+    fn growth_of(&self, usage: &MemoryUsage) -> isize {
+        let first_bytes = self.first
+            .iter()
+            .find(|first| first.actor_type == usage.actor_type)
+            .map_or(0, |first| first.total_bytes);
+        usage.total_bytes as isize - first_bytes as isize
+    }
+
+    pub fn print(&self, system: &mut ActorSystem, user_interface: UserInterfaceID) {
+        if !self.settings.enabled || self.latest.is_empty() {
+            return;
+        }
+
+        let mut text = format!(
+            "Total: {}\n",
+            format_bytes(self.latest.iter().map(|usage| usage.total_bytes).sum())
+        );
+        for usage in self.latest.iter().take(SHOWN_ACTOR_TYPES) {
+            let growth = self.growth_of(usage);
+            text.push_str(&format!(
+                "{}: {} x {} = {} ({} unused, {}{} since start)\n",
+                usage.actor_type,
+                usage.instances,
+                format_bytes(usage.bytes_per_instance()),
+                format_bytes(usage.total_bytes),
+                format_bytes(usage.unused_bytes),
+                if growth < 0 { "-" } else { "+" },
+                format_bytes(growth.abs() as usize)
+            ));
+        }
+
+        user_interface.add_debug_text(
+            "Memory per actor type".chars().collect(),
+            text.as_str().chars().collect(),
+            [0.0, 0.0, 0.0, 1.0],
+            false,
+            &mut system.world(),
+        );
+    }
+}
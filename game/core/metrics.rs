@@ -0,0 +1,264 @@
+use kay::ActorSystem;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub static TRIPS_CREATED: AtomicUsize = ATOMIC_USIZE_INIT;
+pub static TRIPS_SUCCEEDED: AtomicUsize = ATOMIC_USIZE_INIT;
+pub static TRIPS_FAILED: AtomicUsize = ATOMIC_USIZE_INIT;
+/// Each person travelling, whether they share a vehicle, have one to themselves or walk
+pub static PERSON_TRIPS_CREATED: AtomicUsize = ATOMIC_USIZE_INIT;
+/// Each vehicle driving through the network, no matter how many people are in it
+pub static VEHICLE_TRIPS_CREATED: AtomicUsize = ATOMIC_USIZE_INIT;
+/// Cars that passed any of the placed lane detectors
+pub static DETECTED_CARS: AtomicUsize = ATOMIC_USIZE_INIT;
+/// Trips that arrived by driving on lanes, and how many ticks they took in total
+pub static ROAD_TRIPS_SUCCEEDED: AtomicUsize = ATOMIC_USIZE_INIT;
+pub static ROAD_TRIP_TICKS: AtomicUsize = ATOMIC_USIZE_INIT;
+/// Trips to work that arrived, and how many ticks they took in total
+pub static COMMUTES_SUCCEEDED: AtomicUsize = ATOMIC_USIZE_INIT;
+pub static COMMUTE_TICKS: AtomicUsize = ATOMIC_USIZE_INIT;
+/// Whole meters driven by all cars
+pub static VEHICLE_METERS: AtomicUsize = ATOMIC_USIZE_INIT;
+/// Ticks spent by all cars on lanes, and the ones of those spent standing (almost) still
+pub static VEHICLE_TICKS: AtomicUsize = ATOMIC_USIZE_INIT;
+pub static STOPPED_VEHICLE_TICKS: AtomicUsize = ATOMIC_USIZE_INIT;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct MetricsSettings {
+    pub enabled: bool,
+    pub address: String,
+    pub publish_interval_ms: u64,
+}
+
+impl Default for MetricsSettings {
+    fn default() -> Self {
+        MetricsSettings {
+            enabled: false,
+            address: "127.0.0.1:9242".to_owned(),
+            publish_interval_ms: 1000,
+        }
+    }
+}
+
+/// Collects main loop timings and counters and serves them over HTTP
+/// in the Prometheus text exposition format (`GET /metrics`).
+///
+/// Trips in progress correspond to cars in the network, they are exposed as part
+/// of the actor instance counts (`citybound_actor_instances{actor="Trip"}`).
+pub struct Metrics {
+    enabled: bool,
+    publish_interval: Duration,
+    exposition: Arc<Mutex<String>>,
+    phase_start: Instant,
+    phase_seconds_total: Vec<(&'static str, f64)>,
+    frames_total: usize,
+    ticks_total: usize,
+    last_published: Instant,
+    ticks_at_last_publish: usize,
+    ticks_per_second: f64,
+}
+
+fn to_seconds(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) * 1e-9
+}
+
+impl Metrics {
+    pub fn from_settings() -> Metrics {
+        let settings: MetricsSettings = ::ENV.load_settings("Metrics");
+        let exposition = Arc::new(Mutex::new(String::new()));
+
+        let enabled = settings.enabled &&
+            match TcpListener::bind(settings.address.as_str()) {
+                Ok(listener) => {
+                    println!("Serving metrics on http://{}/metrics", settings.address);
+                    let exposition = exposition.clone();
+                    thread::spawn(move || serve(&listener, &exposition));
+                    true
+                }
+                Err(err) => {
+                    println!("Error serving metrics on {}: {}", settings.address, err);
+                    false
+                }
+            };
+
+        Metrics {
+            enabled,
+            publish_interval: Duration::from_millis(settings.publish_interval_ms),
+            exposition,
+            phase_start: Instant::now(),
+            phase_seconds_total: Vec::new(),
+            frames_total: 0,
+            ticks_total: 0,
+            last_published: Instant::now(),
+            ticks_at_last_publish: 0,
+            ticks_per_second: 0.0,
+        }
+    }
+
+    pub fn start_frame(&mut self) {
+        self.frames_total += 1;
+        self.phase_start = Instant::now();
+    }
+
+    /// Attributes the time since the previous phase (or the frame start) to `phase`
+    pub fn finish_phase(&mut self, phase: &'static str) {
+        if !self.enabled {
+            return;
+        }
+        let now = Instant::now();
+        let elapsed = to_seconds(now.duration_since(self.phase_start));
+        self.phase_start = now;
+
+        match self.phase_seconds_total.iter_mut().find(|&&mut (name, _)| name == phase) {
+            Some(&mut (_, ref mut total)) => *total += elapsed,
+            None => self.phase_seconds_total.push((phase, elapsed)),
+        }
+    }
+
+    pub fn count_tick(&mut self) {
+        self.ticks_total += 1;
+    }
+
+    pub fn publish(&mut self, system: &mut ActorSystem) {
+        if !self.enabled || self.last_published.elapsed() < self.publish_interval {
+            return;
+        }
+
+        let interval = to_seconds(self.last_published.elapsed());
+        self.ticks_per_second = (self.ticks_total - self.ticks_at_last_publish) as f64 / interval;
+        self.ticks_at_last_publish = self.ticks_total;
+        self.last_published = Instant::now();
+
+        let mut out = String::new();
+
+        out.push_str("# TYPE citybound_frames_total counter\n");
+        out.push_str(&format!("citybound_frames_total {}\n", self.frames_total));
+        out.push_str("# TYPE citybound_ticks_total counter\n");
+        out.push_str(&format!("citybound_ticks_total {}\n", self.ticks_total));
+        out.push_str("# TYPE citybound_ticks_per_second gauge\n");
+        out.push_str(&format!(
+            "citybound_ticks_per_second {:.3}\n",
+            self.ticks_per_second
+        ));
+
+        out.push_str("# TYPE citybound_trips_created_total counter\n");
+        out.push_str(&format!(
+            "citybound_trips_created_total {}\n",
+            TRIPS_CREATED.load(Ordering::Relaxed)
+        ));
+        out.push_str("# TYPE citybound_person_trips_created_total counter\n");
+        out.push_str(&format!(
+            "citybound_person_trips_created_total {}\n",
+            PERSON_TRIPS_CREATED.load(Ordering::Relaxed)
+        ));
+        out.push_str("# TYPE citybound_vehicle_trips_created_total counter\n");
+        out.push_str(&format!(
+            "citybound_vehicle_trips_created_total {}\n",
+            VEHICLE_TRIPS_CREATED.load(Ordering::Relaxed)
+        ));
+        out.push_str("# TYPE citybound_detected_cars_total counter\n");
+        out.push_str(&format!(
+            "citybound_detected_cars_total {}\n",
+            DETECTED_CARS.load(Ordering::Relaxed)
+        ));
+        out.push_str("# TYPE citybound_road_trips_succeeded_total counter\n");
+        out.push_str(&format!(
+            "citybound_road_trips_succeeded_total {}\n",
+            ROAD_TRIPS_SUCCEEDED.load(Ordering::Relaxed)
+        ));
+        out.push_str("# TYPE citybound_road_trip_ticks_total counter\n");
+        out.push_str(&format!(
+            "citybound_road_trip_ticks_total {}\n",
+            ROAD_TRIP_TICKS.load(Ordering::Relaxed)
+        ));
+        out.push_str("# TYPE citybound_commutes_succeeded_total counter\n");
+        out.push_str(&format!(
+            "citybound_commutes_succeeded_total {}\n",
+            COMMUTES_SUCCEEDED.load(Ordering::Relaxed)
+        ));
+        out.push_str("# TYPE citybound_commute_ticks_total counter\n");
+        out.push_str(&format!(
+            "citybound_commute_ticks_total {}\n",
+            COMMUTE_TICKS.load(Ordering::Relaxed)
+        ));
+        out.push_str("# TYPE citybound_vehicle_meters_total counter\n");
+        out.push_str(&format!(
+            "citybound_vehicle_meters_total {}\n",
+            VEHICLE_METERS.load(Ordering::Relaxed)
+        ));
+        out.push_str("# TYPE citybound_vehicle_ticks_total counter\n");
+        out.push_str(&format!(
+            "citybound_vehicle_ticks_total {}\n",
+            VEHICLE_TICKS.load(Ordering::Relaxed)
+        ));
+        out.push_str("# TYPE citybound_stopped_vehicle_ticks_total counter\n");
+        out.push_str(&format!(
+            "citybound_stopped_vehicle_ticks_total {}\n",
+            STOPPED_VEHICLE_TICKS.load(Ordering::Relaxed)
+        ));
+        out.push_str("# TYPE citybound_trips_total counter\n");
+        out.push_str(&format!(
+            "citybound_trips_total{{result=\"succeeded\"}} {}\n",
+            TRIPS_SUCCEEDED.load(Ordering::Relaxed)
+        ));
+        out.push_str(&format!(
+            "citybound_trips_total{{result=\"failed\"}} {}\n",
+            TRIPS_FAILED.load(Ordering::Relaxed)
+        ));
+
+        out.push_str("# TYPE citybound_phase_seconds_total counter\n");
+        for &(phase, seconds) in &self.phase_seconds_total {
+            out.push_str(&format!(
+                "citybound_phase_seconds_total{{phase=\"{}\"}} {:.6}\n",
+                phase,
+                seconds
+            ));
+        }
+
+        out.push_str("# TYPE citybound_actor_instances gauge\n");
+        for line in system.get_instance_counts().lines() {
+            let mut parts = line.splitn(2, ": ");
+            if let (Some(actor), Some(count)) = (parts.next(), parts.next()) {
+                out.push_str(&format!(
+                    "citybound_actor_instances{{actor=\"{}\"}} {}\n",
+                    actor,
+                    count
+                ));
+            }
+        }
+
+        *self.exposition.lock().expect("metrics lock poisoned") = out;
+    }
+}
+
+fn serve(listener: &TcpListener, exposition: &Arc<Mutex<String>>) {
+    for maybe_stream in listener.incoming() {
+        let mut stream = match maybe_stream {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+
+        let mut request_line = String::new();
+        if let Ok(stream_for_reading) = stream.try_clone() {
+            let _ = BufReader::new(stream_for_reading).read_line(&mut request_line);
+        }
+
+        let response = if request_line.starts_with("GET /metrics") {
+            let body = exposition.lock().expect("metrics lock poisoned").clone();
+            format!(
+                "HTTP/1.0 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\n\
+                 Content-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            )
+        } else {
+            "HTTP/1.0 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_owned()
+        };
+
+        let _ = stream.write_all(response.as_bytes());
+    }
+}
@@ -1,7 +1,20 @@
 pub mod init;
 pub mod colors;
 pub mod simulation;
+pub mod city_events;
 pub mod disjoint_sets;
 pub mod grid_accelerator;
 pub mod read_md_tables;
+pub mod packages;
 pub mod async_counter;
+pub mod geo;
+pub mod remote_control;
+pub mod metrics;
+pub mod random;
+pub mod comparison;
+pub mod time_travel;
+pub mod statistics_history;
+pub mod snapshot_diff;
+pub mod memory_inspector;
+pub mod strongly_connected;
+pub mod units;
@@ -0,0 +1,237 @@
+//! Packages of user-made content, which are loaded at startup without recompiling the game.
+//!
+//! Each package is a directory in `mods/`, with a `package.json` manifest:
+//!
+//! ```json
+//! {
+//!     "name": "dutch-shops",
+//!     "version": "1.2.0",
+//!     "game_version": "0.1.3",
+//!     "description": "Opening hours and goods like in the Netherlands",
+//!     "dependencies": { "more-resources": "1.0.0" }
+//! }
+//! ```
+//!
+//! Next to the manifest, a package can contain:
+//!
+//! * `data/<kind>/*.data.md`: parameter tables, read after the game's own tables of the same
+//!   kind (`resources`, `events` and `judgement`, see `economy/parameters`)
+//! * `scenarios/<name>.json`: road networks that can be built with
+//!   `CITYBOUND_SCENARIO=<name>`, see `transport::planning::scenarios`
+//! * `assets/buildings/<kind>/`: building models, see `economy::buildings::rendering::models`.
+//!   Road profiles are still generated in code
+//! * scripts, listed in the manifest as `"scripts": [...]`. They aren't run, since there is
+//!   no scripting yet, but listing them makes the game warn that the package is incomplete
+//!
+//! Packages are only loaded if the game is at least as new as their `game_version` (and
+//! of the same major version), and all their dependencies are loaded, in a compatible
+//! version. If several versions of a package are installed, only the newest is loaded.
+use std::collections::HashMap;
+use std::fs::{File, read_dir};
+use std::io::Result;
+use std::path::{Path, PathBuf};
+use serde_json;
+use core::read_md_tables::{self, Table};
+
+const PACKAGE_DIR: &str = "mods";
+const MANIFEST_FILE: &str = "package.json";
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct Version(pub u32, pub u32, pub u32);
+
+impl Version {
+    /// Parses `<major>.<minor>.<patch>`, missing parts count as 0
+    pub fn parse(version: &str) -> Option<Version> {
+        let mut parts = version.trim().split('.').map(|part| part.parse::<u32>().ok());
+        let mut next = || parts.next().unwrap_or(Some(0));
+        match (next(), next(), next()) {
+            (Some(major), Some(minor), Some(patch)) => Some(Version(major, minor, patch)),
+            _ => None,
+        }
+    }
+
+    /// Whether something that needs `required` works with this version: the major version
+    /// has to match (and the minor version too, before 1.0), and this can't be older
+    pub fn satisfies(&self, required: Version) -> bool {
+        let same_series = if required.0 == 0 {
+            self.0 == 0 && self.1 == required.1
+        } else {
+            self.0 == required.0
+        };
+        same_series && *self >= required
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct PackageManifest {
+    pub name: String,
+    pub version: String,
+    /// The oldest game version the package works with
+    pub game_version: String,
+    #[serde(default)]
+    pub description: String,
+    /// Names of other packages and the oldest version of each that this package works with
+    #[serde(default)]
+    pub dependencies: HashMap<String, String>,
+    #[serde(default)]
+    pub scripts: Vec<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Package {
+    pub manifest: PackageManifest,
+    pub version: Version,
+    pub dir: PathBuf,
+}
+
+fn read_package(dir: &Path) -> ::std::result::Result<Package, String> {
+    let manifest: PackageManifest = File::open(dir.join(MANIFEST_FILE))
+        .map_err(|err| format!("{}", err))
+        .and_then(|file| serde_json::from_reader(file).map_err(|err| format!("{}", err)))?;
+    let version = Version::parse(&manifest.version).ok_or_else(|| {
+        format!("weird version {}", manifest.version)
+    })?;
+    Ok(Package { manifest, version, dir: dir.to_owned() })
+}
+
+/// All packages in `dir`, whether they can be loaded or not
+pub fn scan(dir: &Path) -> Vec<Package> {
+    let entries = match read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.join(MANIFEST_FILE).exists())
+        .filter_map(|path| match read_package(&path) {
+            Ok(package) => Some(package),
+            Err(err) => {
+                println!("Skipping package in {:?}: {}", path, err);
+                None
+            }
+        })
+        .collect()
+}
+
+/// The packages that can be loaded with `game_version`, ordered so that every package comes
+/// after its dependencies, and the reasons why each of the others can't be loaded
+pub fn resolve(
+    packages: Vec<Package>,
+    game_version: Version,
+) -> (Vec<Package>, Vec<(String, String)>) {
+    let mut rejected = Vec::new();
+    let mut newest = HashMap::<String, Package>::new();
+
+    for package in packages {
+        let compatible = Version::parse(&package.manifest.game_version)
+            .map(|required| game_version.satisfies(required))
+            .unwrap_or(false);
+        if !compatible {
+            rejected.push((
+                package.manifest.name.clone(),
+                format!("needs game version {}", package.manifest.game_version),
+            ));
+            continue;
+        }
+        let is_newer = newest
+            .get(&package.manifest.name)
+            .map(|other| package.version > other.version)
+            .unwrap_or(true);
+        if is_newer {
+            newest.insert(package.manifest.name.clone(), package);
+        }
+    }
+
+    // load packages whose dependencies are all loaded, until no more can be
+    let mut loaded = Vec::<Package>::new();
+    let mut remaining = newest.into_iter().map(|(_, package)| package).collect::<Vec<_>>();
+    remaining.sort_by(|a, b| a.manifest.name.cmp(&b.manifest.name));
+    loop {
+        let loadable_idx = remaining.iter().position(|package| {
+            package.manifest.dependencies.iter().all(|(name, required)| {
+                loaded.iter().any(|dependency| {
+                    &dependency.manifest.name == name &&
+                        Version::parse(required)
+                            .map(|required| dependency.version.satisfies(required))
+                            .unwrap_or(false)
+                })
+            })
+        });
+        match loadable_idx {
+            Some(idx) => loaded.push(remaining.remove(idx)),
+            None => break,
+        }
+    }
+
+    for package in remaining {
+        rejected.push((
+            package.manifest.name.clone(),
+            "has missing, incompatible or circular dependencies".to_owned(),
+        ));
+    }
+
+    (loaded, rejected)
+}
+
+static mut PACKAGES: *const Vec<Package> = 0 as *const Vec<Package>;
+
+/// The loaded packages, dependencies first. Empty before `setup`
+pub fn loaded() -> &'static [Package] {
+    unsafe {
+        if PACKAGES.is_null() {
+            &[]
+        } else {
+            &*PACKAGES
+        }
+    }
+}
+
+/// The tables at `path`, followed by the tables of `kind` of all loaded packages
+pub fn read_data_tables<P: AsRef<Path>>(path: &P, kind: &str) -> Result<Vec<Table>> {
+    let mut tables = read_md_tables::read(path)?;
+    for package in loaded() {
+        let dir = package.dir.join("data").join(kind);
+        if dir.exists() {
+            match read_md_tables::read(&dir) {
+                Ok(package_tables) => tables.extend(package_tables),
+                Err(err) => {
+                    println!("Error reading {} of {}: {}", kind, package.manifest.name, err)
+                }
+            }
+        }
+    }
+    Ok(tables)
+}
+
+/// The scenario file with `name` from the last loaded package that has one
+pub fn scenario_path(name: &str) -> Option<PathBuf> {
+    loaded()
+        .iter()
+        .rev()
+        .map(|package| package.dir.join("scenarios").join(format!("{}.json", name)))
+        .find(|path| path.exists())
+}
+
+pub fn setup() {
+    let game_version = Version::parse(::ENV.version).expect("game version should be valid");
+    let (loaded, rejected) = resolve(scan(Path::new(PACKAGE_DIR)), game_version);
+
+    for package in &loaded {
+        println!(
+            "Loaded package {} {}",
+            package.manifest.name,
+            package.manifest.version
+        );
+        if !package.manifest.scripts.is_empty() {
+            println!(
+                "Package {} has scripts, which aren't supported yet and won't run",
+                package.manifest.name
+            );
+        }
+    }
+    for (name, reason) in rejected {
+        println!("Not loading package {}: it {}", name, reason);
+    }
+
+    unsafe { PACKAGES = Box::into_raw(Box::new(loaded)) };
+}
@@ -0,0 +1,37 @@
+//! The random numbers the simulation uses. Unless seeded, they're different every run;
+//! seeding makes runs of the same city repeatable, so they can be compared.
+use rand::{Rng, SeedableRng, XorShiftRng};
+use std::cell::RefCell;
+
+thread_local! {
+    static RNG: RefCell<XorShiftRng> = RefCell::new(
+        XorShiftRng::from_seed(::rand::thread_rng().gen::<[u32; 4]>())
+    );
+}
+
+pub fn seed(seed: u32) {
+    RNG.with(|rng| {
+        *rng.borrow_mut() = XorShiftRng::from_seed([seed, 0x9E37_79B9, 0x85EB_CA6B, 0xC2B2_AE35])
+    });
+}
+
+/// The generator's current state, to go back to it later with `restore`
+pub fn state() -> XorShiftRng {
+    RNG.with(|rng| rng.borrow().clone())
+}
+
+pub fn restore(state: XorShiftRng) {
+    RNG.with(|rng| *rng.borrow_mut() = state);
+}
+
+pub fn next_f32() -> f32 {
+    RNG.with(|rng| rng.borrow_mut().next_f32())
+}
+
+pub fn shuffle<T>(values: &mut [T]) {
+    RNG.with(|rng| rng.borrow_mut().shuffle(values))
+}
+
+pub fn gen_range(low: usize, high: usize) -> usize {
+    RNG.with(|rng| rng.borrow_mut().gen_range(low, high))
+}
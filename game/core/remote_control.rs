@@ -0,0 +1,284 @@
+use kay::ActorSystem;
+use serde_json::{self, Value};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{channel, Sender, Receiver};
+use std::thread;
+use core::simulation::SimulationID;
+use core::time_travel::TimeTravel;
+use transport::lane::LaneID;
+use transport::consistency;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RemoteControlSettings {
+    pub enabled: bool,
+    /// only bind to local addresses unless you trust everyone on your network
+    pub address: String,
+}
+
+impl Default for RemoteControlSettings {
+    fn default() -> Self {
+        RemoteControlSettings {
+            enabled: false,
+            address: "127.0.0.1:4242".to_owned(),
+        }
+    }
+}
+
+type PendingCommand = (String, Sender<String>);
+
+/// Accepts newline-delimited JSON-RPC 2.0 requests on a local TCP socket.
+/// Requests are queued by a background thread and executed on the main thread,
+/// between message processing steps, through `process_commands`.
+///
+/// Supported methods:
+///
+/// * `get_statistics` - actor instance counts, pause state and audited ID casts
+/// * `pause`, `resume`
+/// * `inject_trips` - `{"probability": 0.1}` makes each lane start a trip with that probability
+/// * `set_signal_timings` - `{"lane": <instance id>, "timings": [true, false, ...]}`
+/// * `place_detector` - `{"lane": <instance id>, "position": 12.5}` in m along the lane
+/// * `remove_detectors` - `{"lane": <instance id>}`
+/// * `rewind` - `{"minutes": 5}` restores the latest snapshot that is at least that many
+///   simulated minutes old and pauses, see `TimeTravel`
+/// * `diff_snapshots` - `{"minutes": 5}` reports how lanes and trips changed since the latest
+///   snapshot that is at least that many simulated minutes old
+/// * `check_consistency` - reports lanes violating microtraffic or connectivity invariants,
+///   see `transport::consistency`
+pub struct RemoteControl {
+    commands: Receiver<PendingCommand>,
+    paused: bool,
+}
+
+impl RemoteControl {
+    pub fn start_from_settings() -> Option<RemoteControl> {
+        let settings: RemoteControlSettings = ::ENV.load_settings("Remote Control");
+        if !settings.enabled {
+            return None;
+        }
+
+        let listener = match TcpListener::bind(settings.address.as_str()) {
+            Ok(listener) => listener,
+            Err(err) => {
+                println!(
+                    "Error starting remote control on {}: {}",
+                    settings.address,
+                    err
+                );
+                return None;
+            }
+        };
+        println!("Remote control listening on {}", settings.address);
+
+        let (command_sender, commands) = channel();
+
+        thread::spawn(move || for maybe_stream in listener.incoming() {
+            match maybe_stream {
+                Ok(stream) => {
+                    let command_sender = command_sender.clone();
+                    thread::spawn(move || serve_connection(stream, &command_sender));
+                }
+                Err(err) => println!("Remote control connection error: {}", err),
+            }
+        });
+
+        Some(RemoteControl { commands, paused: false })
+    }
+
+    pub fn process_commands(
+        &mut self,
+        system: &mut ActorSystem,
+        simulation: SimulationID,
+        time_travel: &mut TimeTravel,
+    ) {
+        while let Ok((request, respond)) = self.commands.try_recv() {
+            let response = match serde_json::from_str::<Value>(&request) {
+                Ok(request) => {
+                    let id = request.get("id").cloned().unwrap_or(Value::Null);
+                    let method = request.get("method").and_then(Value::as_str).unwrap_or("");
+                    let params = request.get("params").cloned().unwrap_or(Value::Null);
+
+                    match self.execute(method, &params, system, simulation, time_travel) {
+                        Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+                        Err((code, message)) => {
+                            json!({
+                                "jsonrpc": "2.0",
+                                "id": id,
+                                "error": {"code": code, "message": message}
+                            })
+                        }
+                    }
+                }
+                Err(err) => {
+                    json!({
+                        "jsonrpc": "2.0",
+                        "id": null,
+                        "error": {"code": -32700, "message": format!("{}", err)}
+                    })
+                }
+            };
+
+            // the connection might already be gone, which is fine
+            let _ = respond.send(response.to_string());
+        }
+    }
+
+    fn execute(
+        &mut self,
+        method: &str,
+        params: &Value,
+        system: &mut ActorSystem,
+        simulation: SimulationID,
+        time_travel: &mut TimeTravel,
+    ) -> Result<Value, (i64, String)> {
+        let world = &mut system.world();
+
+        match method {
+            "get_statistics" => {
+                let instance_counts = system
+                    .get_instance_counts()
+                    .lines()
+                    .filter_map(|line| {
+                        let mut parts = line.splitn(2, ": ");
+                        match (parts.next(), parts.next().and_then(|n| n.parse::<u64>().ok())) {
+                            (Some(name), Some(count)) => Some((name.to_owned(), json!(count))),
+                            _ => None,
+                        }
+                    })
+                    .collect::<serde_json::Map<_, _>>();
+                let id_casts = system
+                    .get_id_cast_counts()
+                    .into_iter()
+                    .map(|(site, count)| (site.to_owned(), json!(count)))
+                    .collect::<serde_json::Map<_, _>>();
+                Ok(json!({
+                    "paused": self.paused,
+                    "instance_counts": instance_counts,
+                    "id_casts": id_casts,
+                }))
+            }
+            "pause" => {
+                simulation.pause(world);
+                self.paused = true;
+                Ok(Value::Bool(true))
+            }
+            "resume" => {
+                simulation.resume(world);
+                self.paused = false;
+                Ok(Value::Bool(true))
+            }
+            "inject_trips" => {
+                let probability = params
+                    .get("probability")
+                    .and_then(Value::as_f64)
+                    .ok_or_else(|| (-32602, "expected probability".to_owned()))?;
+                LaneID::global_broadcast(world).join_random_trips(probability as f32, world);
+                Ok(Value::Bool(true))
+            }
+            "set_signal_timings" => {
+                let lane = params.get("lane").and_then(Value::as_u64).ok_or_else(|| {
+                    (-32602, "expected lane instance id".to_owned())
+                })?;
+                let timings = params
+                    .get("timings")
+                    .and_then(Value::as_array)
+                    .and_then(|timings| {
+                        timings.iter().map(Value::as_bool).collect::<Option<Vec<_>>>()
+                    })
+                    .ok_or_else(|| (-32602, "expected timings as booleans".to_owned()))?;
+                LaneID::global_broadcast(world).override_signal_timings(
+                    lane as u32,
+                    timings.into(),
+                    world,
+                );
+                Ok(Value::Bool(true))
+            }
+            "place_detector" => {
+                let lane = params.get("lane").and_then(Value::as_u64).ok_or_else(|| {
+                    (-32602, "expected lane instance id".to_owned())
+                })?;
+                let position = params
+                    .get("position")
+                    .and_then(Value::as_f64)
+                    .ok_or_else(|| (-32602, "expected position".to_owned()))?;
+                LaneID::global_broadcast(world).place_detector(
+                    lane as u32,
+                    position as f32,
+                    world,
+                );
+                Ok(Value::Bool(true))
+            }
+            "remove_detectors" => {
+                let lane = params.get("lane").and_then(Value::as_u64).ok_or_else(|| {
+                    (-32602, "expected lane instance id".to_owned())
+                })?;
+                LaneID::global_broadcast(world).remove_detectors(lane as u32, world);
+                Ok(Value::Bool(true))
+            }
+            "rewind" => {
+                let minutes = params.get("minutes").and_then(Value::as_u64).ok_or_else(|| {
+                    (-32602, "expected minutes".to_owned())
+                })?;
+                let rewound = time_travel.rewind(minutes as usize, system).map_err(
+                    |message| (-32000, message),
+                )?;
+                // the snapshot might have been taken while running
+                simulation.pause(world);
+                self.paused = true;
+                Ok(json!({
+                    "rewound_minutes": rewound,
+                    "available_minutes": time_travel.available_minutes(),
+                }))
+            }
+            "diff_snapshots" => {
+                let minutes = params.get("minutes").and_then(Value::as_u64).ok_or_else(|| {
+                    (-32602, "expected minutes".to_owned())
+                })?;
+                let diff = time_travel.diff(minutes as usize, system).map_err(
+                    |message| (-32000, message),
+                )?;
+                print!("{}", diff.summary());
+                serde_json::to_value(&diff).map_err(|err| (-32603, format!("{}", err)))
+            }
+            "check_consistency" => {
+                let report = consistency::check(&system.snapshot());
+                print!("{}", report.summary());
+                serde_json::to_value(&report).map_err(|err| (-32603, format!("{}", err)))
+            }
+            _ => Err((-32601, format!("unknown method {}", method))),
+        }
+    }
+}
+
+fn serve_connection(stream: TcpStream, command_sender: &Sender<PendingCommand>) {
+    let mut writer = match stream.try_clone() {
+        Ok(writer) => writer,
+        Err(err) => {
+            println!("Remote control connection error: {}", err);
+            return;
+        }
+    };
+
+    for maybe_line in BufReader::new(stream).lines() {
+        let line = match maybe_line {
+            Ok(line) => line,
+            Err(_) => return,
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let (respond, response) = channel();
+        if command_sender.send((line, respond)).is_err() {
+            return;
+        }
+        match response.recv() {
+            Ok(response) => {
+                if writeln!(writer, "{}", response).is_err() {
+                    return;
+                }
+            }
+            Err(_) => return,
+        }
+    }
+}
@@ -0,0 +1,150 @@
+use std::cell::Cell;
+use std::sync::RwLock;
+use std::time::SystemTime;
+use core::units::{Meters, Seconds};
+
+const CONFIG_CATEGORY: &'static str = "Simulation";
+
+/// Parameters of the car following model, see `intelligent_acceleration`
+#[derive(Serialize, Deserialize, Copy, Clone)]
+pub struct IDMParameters {
+    pub acceleration: f32,
+    pub max_deceleration: f32,
+    pub comfortable_deceleration: f32,
+    pub acceleration_exponent: f32,
+    pub minimum_spacing: f32,
+}
+
+impl Default for IDMParameters {
+    fn default() -> Self {
+        IDMParameters {
+            acceleration: 2.0,
+            max_deceleration: 8.0,
+            comfortable_deceleration: 3.0,
+            acceleration_exponent: 8.0,
+            minimum_spacing: 4.0,
+        }
+    }
+}
+
+/// Tunable constants of the simulation, loaded from the "Simulation" settings file
+/// at startup and reloaded whenever that file changes in debug builds
+#[derive(Serialize, Deserialize, Copy, Clone)]
+pub struct SimulationConfig {
+    /// How many ticks are done per second of real time, independent of the frame rate
+    pub ticks_per_real_second: f32,
+    /// How many times slower than real time microtraffic moves,
+    /// see `microtraffic_time`
+    pub microtraffic_slowdown: f32,
+    /// Traffic logic of each lane only runs every this many ticks
+    pub traffic_logic_throttling: usize,
+    /// Routes of each lane are only updated every this many ticks
+    pub pathfinding_throttling: usize,
+    /// After a change of the network, lanes wait this many route updates before
+    /// forgetting routes that weren't confirmed again
+    pub routing_timeout_after_change: u16,
+    /// Route cost of changing to the lane on the left, as if driving that much further
+    pub lane_change_cost_left: Meters,
+    pub lane_change_cost_right: Meters,
+    pub idm: IDMParameters,
+    /// Safe time headway to the car in front
+    pub car_headway: Seconds,
+    /// Safe time headway to obstacles from other lanes
+    pub obstacle_headway: Seconds,
+    /// Safe time headway when stopping in front of a red light
+    pub signal_headway: Seconds,
+    /// Safe time headway to cars on both sides of a transfer lane
+    pub transfer_headway: Seconds,
+    /// Gap left between cars that are forced to spawn on top of each other
+    pub spawn_spacing: Meters,
+    /// How many threads share the ticks of lanes, 1 ticks them on the main thread
+    pub tick_threads: usize,
+    /// Simulates all lanes with queues instead of car following, for large cities,
+    /// see `transport::microtraffic::mesoscopic`
+    pub mesoscopic_traffic: bool,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        SimulationConfig {
+            ticks_per_real_second: 60.0,
+            microtraffic_slowdown: 20.0,
+            traffic_logic_throttling: 30,
+            pathfinding_throttling: 10,
+            routing_timeout_after_change: 15,
+            lane_change_cost_left: Meters(5.0),
+            lane_change_cost_right: Meters(3.0),
+            idm: IDMParameters::default(),
+            car_headway: Seconds(2.0),
+            obstacle_headway: Seconds(4.0),
+            signal_headway: Seconds(2.0),
+            transfer_headway: Seconds(1.0),
+            spawn_spacing: Meters(2.0),
+            tick_threads: 1,
+            mesoscopic_traffic: false,
+        }
+    }
+}
+
+lazy_static! {
+    /// Shared by all threads, since lanes might tick on worker threads
+    static ref CURRENT_CONFIG: RwLock<SimulationConfig> =
+        RwLock::new(SimulationConfig::default());
+}
+
+thread_local! (
+    static CURRENT_CONFIG_MODIFIED: Cell<Option<SystemTime>> = Cell::new(None);
+);
+
+impl SimulationConfig {
+    /// The currently loaded config, cheap enough to call in every tick
+    pub fn current() -> SimulationConfig {
+        *CURRENT_CONFIG.read().expect("Config lock poisoned")
+    }
+
+    /// How much microtraffic time passes in `dt` simulated seconds. Microtraffic
+    /// moves slower than the rest of the simulation, so it can be followed with the eye
+    pub fn microtraffic_time(&self, dt: f32) -> Seconds {
+        Seconds(dt / self.microtraffic_slowdown)
+    }
+
+    /// Throttling happens every this many ticks, so it can't be less than one
+    fn validated(self) -> SimulationConfig {
+        SimulationConfig {
+            traffic_logic_throttling: self.traffic_logic_throttling.max(1),
+            pathfinding_throttling: self.pathfinding_throttling.max(1),
+            tick_threads: self.tick_threads.max(1),
+            ..self
+        }
+    }
+
+    fn set_current(config: SimulationConfig) {
+        *CURRENT_CONFIG.write().expect("Config lock poisoned") = config.validated();
+    }
+
+    pub fn load() {
+        Self::set_current(::ENV.load_settings(CONFIG_CATEGORY));
+        let modified = ::ENV.settings_modified(CONFIG_CATEGORY);
+        CURRENT_CONFIG_MODIFIED.with(|current| current.set(modified));
+    }
+
+    /// Reloads the config if its file was changed since it was last loaded
+    pub fn reload_if_changed() {
+        let modified = ::ENV.settings_modified(CONFIG_CATEGORY);
+        let changed = CURRENT_CONFIG_MODIFIED.with(|current| modified != current.get());
+        if changed {
+            CURRENT_CONFIG_MODIFIED.with(|current| current.set(modified));
+            // the file might be caught in the middle of being written,
+            // it will be read again once it changes the next time
+            match ::ENV.try_load_settings(CONFIG_CATEGORY) {
+                Ok(config) => {
+                    Self::set_current(config);
+                    println!("Reloaded {} settings", CONFIG_CATEGORY);
+                }
+                Err(err) => {
+                    println!("Kept previous {} settings: {}", CONFIG_CATEGORY, err);
+                }
+            }
+        }
+    }
+}
@@ -1,11 +1,22 @@
 use kay::{ActorSystem, World};
-use compact::CVec;
+use compact::{CVec, CHashMap};
 use stagemaster::UserInterfaceID;
+use stagemaster::combo::Combo2;
+use stagemaster::combo::Button::Space;
+use stagemaster::actions::{register_action, ActionId, ActionListener, ActionListenerID,
+                           ActionPhase, MSG_ActionListener_on_action};
 
 mod time;
+mod config;
+mod pacing;
 
-pub use self::time::{Timestamp, Ticks, Seconds, TICKS_PER_SIM_MINUTE, TICKS_PER_SIM_SECOND,
-                     TimeOfDay};
+pub use self::time::{Timestamp, Ticks, Seconds, Minutes, Hours, Days, TICKS_PER_SIM_MINUTE,
+                     TICKS_PER_SIM_SECOND, TICKS_PER_SIM_HOUR, TICKS_PER_SIM_DAY,
+                     MINUTES_PER_DAY, TimeOfDay};
+pub use self::config::{SimulationConfig, IDMParameters};
+pub use self::pacing::{TickPacer, tick_progress, microtraffic_time_since_tick, is_frozen};
+
+const CONFIG_RELOAD_INTERVAL: usize = 60;
 
 pub trait Simulatable {
     fn tick(&mut self, dt: f32, current_tick: Timestamp, world: &mut World);
@@ -15,29 +26,75 @@ pub trait Sleeper {
     fn wake(&mut self, current_tick: Timestamp, world: &mut World);
 }
 
+/// Tells apart the different things one actor asked to be reminded of,
+/// what each number stands for is up to that actor
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Reminder(pub u16);
+
+/// Gets reminded at the times it asked for with `Simulation::remind_at`, instead of
+/// checking in every tick whether something is due. Unlike a `Sleeper`, it can wait
+/// for several different things at once
+pub trait Reminded {
+    fn remind(&mut self, reminder: Reminder, current_tick: Timestamp, world: &mut World);
+}
+
+#[derive(Copy, Clone)]
+enum WakeUp {
+    Sleeper(SleeperID),
+    Reminded(RemindedID, Reminder),
+}
+
 #[derive(Compact, Clone)]
 pub struct Simulation {
     id: SimulationID,
     simulatables: CVec<SimulatableID>,
     current_tick: Timestamp,
-    sleepers: CVec<(Timestamp, SleeperID)>,
+    /// Everything to wake up in a tick, by tick, so scheduling doesn't depend on
+    /// how many others are waiting and ticks without wake ups cost nothing
+    wake_ups: CHashMap<Timestamp, CVec<WakeUp>>,
+    paused: bool,
 }
 
 impl Simulation {
     pub fn spawn(
         id: SimulationID,
         simulatables: &CVec<SimulatableID>,
-        _: &mut World,
+        world: &mut World,
     ) -> Simulation {
+        register_action(
+            "Toggle Pause",
+            Combo2::new(&[Space], &[]),
+            id.into(),
+            world,
+        );
+
         Simulation {
             id,
             simulatables: simulatables.clone(),
             current_tick: Timestamp::new(0),
-            sleepers: CVec::new(),
+            wake_ups: CHashMap::new(),
+            paused: false,
         }
     }
 
     pub fn do_tick(&mut self, world: &mut World) {
+        pacing::set_frozen(self.paused);
+
+        if self.paused {
+            UserInterfaceID::local_first(world).add_debug_text(
+                "Time".chars().collect(),
+                "Paused".chars().collect(),
+                [0.0, 0.0, 0.0, 1.0],
+                false,
+                world,
+            );
+            return;
+        }
+
+        if cfg!(debug_assertions) && self.current_tick.ticks() % CONFIG_RELOAD_INTERVAL == 0 {
+            SimulationConfig::reload_if_changed();
+        }
+
         for simulatable in &self.simulatables {
             simulatable.tick(
                 1.0 / (TICKS_PER_SIM_SECOND as f32),
@@ -45,15 +102,15 @@ impl Simulation {
                 world,
             );
         }
-        while self.sleepers
-            .last()
-            .map(|&(end, _)| end < self.current_tick)
-            .unwrap_or(false)
-        {
-            let (_, sleeper) = self.sleepers.pop().expect(
-                "just checked that there are sleepers",
-            );
-            sleeper.wake(self.current_tick, world);
+        if let Some(wake_ups) = self.wake_ups.remove(self.current_tick) {
+            for wake_up in wake_ups.iter() {
+                match *wake_up {
+                    WakeUp::Sleeper(sleeper) => sleeper.wake(self.current_tick, world),
+                    WakeUp::Reminded(reminded, reminder) => {
+                        reminded.remind(reminder, self.current_tick, world)
+                    }
+                }
+            }
         }
         self.current_tick += Ticks(1);
 
@@ -61,28 +118,94 @@ impl Simulation {
 
         UserInterfaceID::local_first(world).add_debug_text(
             "Time".chars().collect(),
-            format!("{:02}:{:02}", time.0, time.1).chars().collect(),
+            format!("Day {}, {:02}:{:02}", self.current_tick.day() + 1, time.0, time.1)
+                .chars()
+                .collect(),
             [0.0, 0.0, 0.0, 1.0],
             false,
             world,
         );
     }
 
+    pub fn pause(&mut self, _: &mut World) {
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self, _: &mut World) {
+        self.paused = false;
+    }
+
+    /// Wake-ups happen in the first tick after `at`, or in the next tick if that passed
+    fn schedule(&mut self, at: Timestamp, wake_up: WakeUp) {
+        let due = ::std::cmp::max(at + Ticks(1), self.current_tick);
+        self.wake_ups.push_at(due, wake_up);
+    }
+
     pub fn wake_up_in(&mut self, remaining_ticks: Ticks, sleeper_id: SleeperID, _: &mut World) {
-        let wake_up_at = self.current_tick + remaining_ticks;
-        let maybe_idx = self.sleepers.binary_search_by_key(
-            &wake_up_at.iticks(),
-            |&(t, _)| -(t.iticks()),
-        );
-        let insert_idx = match maybe_idx {
-            Ok(idx) | Err(idx) => idx,
-        };
-        self.sleepers.insert(insert_idx, (wake_up_at, sleeper_id));
+        let at = self.current_tick + remaining_ticks;
+        self.schedule(at, WakeUp::Sleeper(sleeper_id));
+    }
+
+    pub fn wake_up_at(&mut self, at: Timestamp, sleeper_id: SleeperID, _: &mut World) {
+        self.schedule(at, WakeUp::Sleeper(sleeper_id));
+    }
+
+    pub fn remind_in(
+        &mut self,
+        remaining_ticks: Ticks,
+        reminder: Reminder,
+        reminded: RemindedID,
+        _: &mut World,
+    ) {
+        let at = self.current_tick + remaining_ticks;
+        self.schedule(at, WakeUp::Reminded(reminded, reminder));
+    }
+
+    pub fn remind_at(
+        &mut self,
+        at: Timestamp,
+        reminder: Reminder,
+        reminded: RemindedID,
+        _: &mut World,
+    ) {
+        self.schedule(at, WakeUp::Reminded(reminded, reminder));
+    }
+
+    /// For sleepers that are about to die, so a wake up they still had pending doesn't
+    /// end up at whichever actor takes over their ID
+    pub fn forget_wake_ups(&mut self, sleeper: SleeperID, _: &mut World) {
+        for wake_ups in self.wake_ups.values_mut() {
+            wake_ups.retain(|wake_up| match *wake_up {
+                WakeUp::Sleeper(other) => other != sleeper,
+                WakeUp::Reminded(..) => true,
+            });
+        }
+    }
+
+    /// For actors that are about to die, so they don't get reminded anymore
+    pub fn forget_reminders(&mut self, reminded: RemindedID, _: &mut World) {
+        for wake_ups in self.wake_ups.values_mut() {
+            wake_ups.retain(|wake_up| match *wake_up {
+                WakeUp::Reminded(other, _) => other != reminded,
+                WakeUp::Sleeper(_) => true,
+            });
+        }
+    }
+}
+
+impl ActionListener for Simulation {
+    fn on_action(&mut self, action: ActionId, phase: ActionPhase, _: &mut World) {
+        if phase == ActionPhase::Started && action == ActionId::named("Toggle Pause") {
+            self.paused = !self.paused;
+        }
     }
 }
 
 pub fn setup(system: &mut ActorSystem, simulatables: Vec<SimulatableID>) -> SimulationID {
+    SimulationConfig::load();
+
     system.register::<Simulation>();
+    system.make_restorable::<Simulation>();
 
     auto_setup(system);
 
@@ -0,0 +1,76 @@
+use std::cell::Cell;
+use std::time::Instant;
+
+use super::config::SimulationConfig;
+use super::time::TICKS_PER_SIM_SECOND;
+use core::units::Seconds;
+
+// when a frame takes very long, don't try to catch up with all missed ticks at once
+const MAX_TICKS_PER_FRAME: usize = 4;
+
+thread_local! (
+    static TICK_PROGRESS: Cell<f32> = Cell::new(0.0);
+    static FROZEN: Cell<bool> = Cell::new(false);
+);
+
+/// How far (between 0.0 and 1.0) real time has already advanced into the next tick,
+/// always 0.0 while the simulation is paused
+pub fn tick_progress() -> f32 {
+    if FROZEN.with(|frozen| frozen.get()) {
+        0.0
+    } else {
+        TICK_PROGRESS.with(|progress| progress.get())
+    }
+}
+
+/// Microtraffic time that has passed since the last tick,
+/// used to extrapolate the motion of cars when rendering
+pub fn microtraffic_time_since_tick() -> Seconds {
+    SimulationConfig::current().microtraffic_time(tick_progress() / (TICKS_PER_SIM_SECOND as f32))
+}
+
+pub fn set_frozen(frozen: bool) {
+    FROZEN.with(|current| current.set(frozen));
+}
+
+/// Whether the last tick was skipped because the simulation is paused
+pub fn is_frozen() -> bool {
+    FROZEN.with(|frozen| frozen.get())
+}
+
+/// Decides how many ticks to do in each frame, so the simulation advances
+/// at a constant pace independent of the frame rate
+pub struct TickPacer {
+    last_frame: Instant,
+    accumulated: f32,
+}
+
+impl TickPacer {
+    pub fn new() -> TickPacer {
+        TickPacer {
+            last_frame: Instant::now(),
+            accumulated: 0.0,
+        }
+    }
+
+    /// Number of ticks that are due in this frame
+    pub fn ticks_due(&mut self) -> usize {
+        let elapsed = self.last_frame.elapsed();
+        self.last_frame = Instant::now();
+        self.accumulated += elapsed.as_secs() as f32 + elapsed.subsec_nanos() as f32 / 1e9;
+
+        let tick_duration = 1.0 / SimulationConfig::current().ticks_per_real_second;
+        let mut n_ticks = (self.accumulated / tick_duration).floor() as usize;
+        self.accumulated -= n_ticks as f32 * tick_duration;
+
+        if n_ticks > MAX_TICKS_PER_FRAME {
+            n_ticks = MAX_TICKS_PER_FRAME;
+            self.accumulated = 0.0;
+        }
+
+        let progress = self.accumulated / tick_duration;
+        TICK_PROGRESS.with(|current| current.set(progress));
+
+        n_ticks
+    }
+}
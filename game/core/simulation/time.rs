@@ -1,5 +1,10 @@
 pub const TICKS_PER_SIM_SECOND: usize = 1;
 pub const TICKS_PER_SIM_MINUTE: usize = 60 * TICKS_PER_SIM_SECOND;
+pub const TICKS_PER_SIM_HOUR: usize = 60 * TICKS_PER_SIM_MINUTE;
+pub const TICKS_PER_SIM_DAY: usize = 24 * TICKS_PER_SIM_HOUR;
+pub const MINUTES_PER_DAY: usize = 24 * 60;
+/// The time of day the simulation starts at, in minutes since midnight
+const START_MINUTE: usize = 7 * 60;
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Ticks(pub usize);
@@ -34,6 +39,33 @@ impl ::std::ops::AddAssign for Seconds {
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Minutes(pub usize);
+
+impl From<Minutes> for Ticks {
+    fn from(d_mins: Minutes) -> Ticks {
+        Ticks(d_mins.0 * TICKS_PER_SIM_MINUTE)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Hours(pub usize);
+
+impl From<Hours> for Ticks {
+    fn from(d_hours: Hours) -> Ticks {
+        Ticks(d_hours.0 * TICKS_PER_SIM_HOUR)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Days(pub usize);
+
+impl From<Days> for Ticks {
+    fn from(d_days: Days) -> Ticks {
+        Ticks(d_days.0 * TICKS_PER_SIM_DAY)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Timestamp(usize);
 
 impl Timestamp {
@@ -48,6 +80,21 @@ impl Timestamp {
     pub fn iticks(&self) -> isize {
         self.0 as isize
     }
+
+    /// The day of the simulation this is in, the first day being day 0
+    pub fn day(&self) -> usize {
+        (START_MINUTE * TICKS_PER_SIM_MINUTE + self.0) / TICKS_PER_SIM_DAY
+    }
+
+    /// Seconds since midnight before the first day of the simulation
+    pub fn seconds_since_start_of_day_0(&self) -> usize {
+        (START_MINUTE * TICKS_PER_SIM_MINUTE + self.0) / TICKS_PER_SIM_SECOND
+    }
+
+    /// Whether this is on a Saturday or Sunday, the simulation starting on a Monday
+    pub fn is_weekend(&self) -> bool {
+        self.day() % 7 >= 5
+    }
 }
 
 impl<D: Into<Ticks>> ::std::ops::Add<D> for Timestamp {
@@ -90,9 +137,23 @@ impl TimeOfDay {
     }
 
     pub fn from_tick(current_tick: Timestamp) -> Self {
-        TimeOfDay {
-            minutes_since_midnight: 7 * 60 + (current_tick.ticks() / TICKS_PER_SIM_MINUTE) as u16,
-        }
+        let minutes = START_MINUTE + current_tick.ticks() / TICKS_PER_SIM_MINUTE;
+        TimeOfDay { minutes_since_midnight: (minutes % MINUTES_PER_DAY) as u16 }
+    }
+
+    /// The start of the next minute after `tick` that has this time of day,
+    /// so between a minute and a day later
+    pub fn next_after(&self, tick: Timestamp) -> Timestamp {
+        let now = TimeOfDay::from_tick(tick).minutes_since_midnight as usize;
+        let target = self.minutes_since_midnight as usize % MINUTES_PER_DAY;
+        let minutes_until = (target + MINUTES_PER_DAY - now - 1) % MINUTES_PER_DAY + 1;
+        let minute_start = Timestamp(tick.0 - tick.0 % TICKS_PER_SIM_MINUTE);
+        minute_start + Minutes(minutes_until)
+    }
+
+    pub fn is_night(&self) -> bool {
+        let hours = (self.minutes_since_midnight / 60) % 24;
+        hours < 6 || hours >= 20
     }
 
     pub fn hours_minutes(&self) -> (usize, usize) {
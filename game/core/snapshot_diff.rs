@@ -0,0 +1,124 @@
+//! Compares two world snapshots (see `time_travel`), reporting per actor type which
+//! instances appeared, disappeared or changed, to catch unintended state drift.
+//!
+//! `SnapshotDiff::between` can be used directly in tests, the `diff_snapshots` remote
+//! control command compares an earlier snapshot with the current state of the world.
+use kay::{Actor, Snapshot};
+use transport::lane::{Lane, TransferLane};
+use transport::pathfinding::trip::Trip;
+
+/// Describes in words how an actor instance changed since an earlier state of it
+pub trait Diffable: Actor {
+    fn changes_since(&self, before: &Self) -> Vec<String>;
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct InstanceChanges {
+    pub instance_id: u32,
+    pub changes: Vec<String>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ActorTypeDiff {
+    pub actor_type: String,
+    pub appeared: Vec<u32>,
+    pub disappeared: Vec<u32>,
+    pub changed: Vec<InstanceChanges>,
+}
+
+impl ActorTypeDiff {
+    pub fn between<A: Diffable>(actor_type: &str, before: &[A], after: &[A]) -> ActorTypeDiff {
+        let mut diff = ActorTypeDiff {
+            actor_type: actor_type.to_owned(),
+            appeared: Vec::new(),
+            disappeared: Vec::new(),
+            changed: Vec::new(),
+        };
+
+        for instance in after {
+            // instance ids are reused, so the version has to match as well
+            match before.iter().find(|earlier| earlier.id() == instance.id()) {
+                Some(earlier) => {
+                    let changes = instance.changes_since(earlier);
+                    if !changes.is_empty() {
+                        diff.changed.push(InstanceChanges {
+                            instance_id: instance.id().instance_id,
+                            changes,
+                        });
+                    }
+                }
+                None => diff.appeared.push(instance.id().instance_id),
+            }
+        }
+
+        for earlier in before {
+            if !after.iter().any(|instance| instance.id() == earlier.id()) {
+                diff.disappeared.push(earlier.id().instance_id);
+            }
+        }
+
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.appeared.is_empty() && self.disappeared.is_empty() && self.changed.is_empty()
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct SnapshotDiff {
+    pub actor_types: Vec<ActorTypeDiff>,
+}
+
+impl SnapshotDiff {
+    pub fn between(before: &Snapshot, after: &Snapshot) -> SnapshotDiff {
+        SnapshotDiff {
+            actor_types: vec![
+                ActorTypeDiff::between(
+                    "Lane",
+                    before.instances::<Lane>(),
+                    after.instances::<Lane>()
+                ),
+                ActorTypeDiff::between(
+                    "TransferLane",
+                    before.instances::<TransferLane>(),
+                    after.instances::<TransferLane>()
+                ),
+                ActorTypeDiff::between(
+                    "Trip",
+                    before.instances::<Trip>(),
+                    after.instances::<Trip>()
+                ),
+            ],
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.actor_types.iter().all(ActorTypeDiff::is_empty)
+    }
+
+    /// One line per actor type, followed by the changes of each changed instance
+    pub fn summary(&self) -> String {
+        let mut summary = String::new();
+        for diff in &self.actor_types {
+            summary.push_str(&format!(
+                "{}: {} appeared, {} disappeared, {} changed\n",
+                diff.actor_type,
+                diff.appeared.len(),
+                diff.disappeared.len(),
+                diff.changed.len()
+            ));
+            for instance in &diff.changed {
+                for change in &instance.changes {
+                    summary.push_str(&format!(
+                        "  {} {}: {}\n",
+                        diff.actor_type,
+                        instance.instance_id,
+                        change
+                    ));
+                }
+            }
+        }
+        summary
+    }
+}
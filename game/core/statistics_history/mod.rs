@@ -0,0 +1,282 @@
+//! Long-term statistics of the city (population, trips per day, average commute and
+//! spending), sampled once per simulated day and charted over months and years.
+//!
+//! The history is kept in the state of an actor for as long as the city runs (there is
+//! no save system yet to keep it across sessions). To keep it compact over years,
+//! samples are stored in tiers of increasing resolution: the newest days each have their
+//! own sample, and whenever a tier is full, its two oldest samples are averaged into one
+//! sample of the next tier, which covers twice as many days per sample.
+use kay::{ActorSystem, World, External};
+use compact::CVec;
+use stagemaster::{UserInterfaceID, Interactable2d, Interactable2dID,
+                  MSG_Interactable2d_draw_ui_2d};
+use stagemaster::charts::{Chart, ChartKind};
+use imgui::ImGuiSetCond_FirstUseEver;
+use core::simulation::{SimulationID, Sleeper, SleeperID, MSG_Sleeper_wake, Timestamp, Ticks,
+                       TICKS_PER_SIM_DAY, TICKS_PER_SIM_MINUTE};
+use core::metrics::{TRIPS_CREATED, COMMUTES_SUCCEEDED, COMMUTE_TICKS};
+use std::sync::atomic::Ordering;
+
+/// Samples per tier, the newest tier covers this many days at daily resolution
+const TIER_CAPACITY: usize = 64;
+/// With each tier covering twice as many days per sample, this spans about 44 years.
+/// Samples falling out of the last tier are dropped
+const N_TIERS: usize = 8;
+const MAX_CHART_POINTS: usize = 120;
+
+/// The statistics of one or several consecutive days, averaged
+#[derive(Copy, Clone, Default)]
+pub struct StatisticsSample {
+    pub days: u32,
+    /// Households living in the city
+    pub population: f32,
+    pub trips: f32,
+    pub average_commute_minutes: f32,
+    /// Money spent by the city, so far only on road maintenance
+    pub spending: f32,
+}
+
+impl StatisticsSample {
+    fn merge(&self, other: &StatisticsSample) -> StatisticsSample {
+        let days = self.days + other.days;
+        let (weight, other_weight) = if days == 0 {
+            (0.5, 0.5)
+        } else {
+            (
+                self.days as f32 / days as f32,
+                other.days as f32 / days as f32,
+            )
+        };
+        let average = |value: f32, other_value: f32| weight * value + other_weight * other_value;
+        StatisticsSample {
+            days,
+            population: average(self.population, other.population),
+            trips: average(self.trips, other.trips),
+            average_commute_minutes: average(
+                self.average_commute_minutes,
+                other.average_commute_minutes,
+            ),
+            spending: average(self.spending, other.spending),
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum HistoryRange {
+    Month,
+    Year,
+    All,
+}
+
+impl HistoryRange {
+    fn days(&self) -> Option<usize> {
+        match *self {
+            HistoryRange::Month => Some(30),
+            HistoryRange::Year => Some(365),
+            HistoryRange::All => None,
+        }
+    }
+}
+
+/// A count lower than the last seen one means the counter was reset since,
+/// so everything it counted is new
+fn count_since(count: usize, last_count: usize) -> usize {
+    if count >= last_count {
+        count - last_count
+    } else {
+        count
+    }
+}
+
+#[derive(Compact, Clone)]
+pub struct StatisticsHistory {
+    id: StatisticsHistoryID,
+    simulation: SimulationID,
+    /// Oldest samples first in each tier, tiers with a finer resolution first
+    tiers: CVec<CVec<StatisticsSample>>,
+    population: usize,
+    spending_today: f32,
+    last_counts: (usize, usize, usize),
+    range: HistoryRange,
+}
+
+impl StatisticsHistory {
+    pub fn spawn(
+        id: StatisticsHistoryID,
+        simulation: SimulationID,
+        user_interface: UserInterfaceID,
+        world: &mut World,
+    ) -> StatisticsHistory {
+        user_interface.add_2d(id.into(), world);
+        simulation.wake_up_in(Ticks(TICKS_PER_SIM_DAY), id.into(), world);
+
+        StatisticsHistory {
+            id,
+            simulation,
+            tiers: vec![CVec::new(); N_TIERS].into(),
+            population: 0,
+            spending_today: 0.0,
+            last_counts: (0, 0, 0),
+            range: HistoryRange::Month,
+        }
+    }
+
+    /// Called by demographics after each census
+    pub fn record_population(&mut self, n_households: usize, _: &mut World) {
+        self.population = n_households;
+    }
+
+    /// Called by city services whenever they spend money
+    pub fn record_spending(&mut self, amount: f32, _: &mut World) {
+        self.spending_today += amount;
+    }
+
+    fn push_sample(&mut self, sample: StatisticsSample) {
+        let mut carried = Some(sample);
+        for tier in self.tiers.iter_mut() {
+            if let Some(sample) = carried.take() {
+                tier.push(sample);
+            }
+            if tier.len() > TIER_CAPACITY {
+                let oldest = tier.remove(0);
+                let second_oldest = tier.remove(0);
+                carried = Some(oldest.merge(&second_oldest));
+            }
+        }
+        // whatever is carried out of the last tier is too old to keep
+    }
+
+    /// One sample per day for the last `days` days (or all days) that are remembered,
+    /// oldest first. Days that were merged into one sample all get the merged values
+    fn daily_values(&self, days: Option<usize>) -> Vec<StatisticsSample> {
+        let mut newest_first = Vec::new();
+        'tiers: for tier in self.tiers.iter() {
+            for sample in tier.iter().rev() {
+                for _ in 0..sample.days {
+                    if days.map(|days| newest_first.len() >= days).unwrap_or(false) {
+                        break 'tiers;
+                    }
+                    newest_first.push(StatisticsSample { days: 1, ..*sample });
+                }
+            }
+        }
+        newest_first.reverse();
+        newest_first
+    }
+
+    fn chart_points(&self) -> Vec<StatisticsSample> {
+        let daily = self.daily_values(self.range.days());
+        let days_per_point = (daily.len() + MAX_CHART_POINTS - 1) / MAX_CHART_POINTS;
+        daily
+            .chunks(days_per_point.max(1))
+            .map(|days| {
+                days.iter().skip(1).fold(days[0], |merged, day| merged.merge(day))
+            })
+            .collect()
+    }
+}
+
+impl Sleeper for StatisticsHistory {
+    fn wake(&mut self, _current_tick: Timestamp, world: &mut World) {
+        let counts = (
+            TRIPS_CREATED.load(Ordering::Relaxed),
+            COMMUTES_SUCCEEDED.load(Ordering::Relaxed),
+            COMMUTE_TICKS.load(Ordering::Relaxed),
+        );
+        let trips = count_since(counts.0, self.last_counts.0);
+        let commutes = count_since(counts.1, self.last_counts.1);
+        let commute_ticks = count_since(counts.2, self.last_counts.2);
+        self.last_counts = counts;
+
+        let average_commute_minutes = if commutes == 0 {
+            0.0
+        } else {
+            commute_ticks as f32 / commutes as f32 / TICKS_PER_SIM_MINUTE as f32
+        };
+
+        let sample = StatisticsSample {
+            days: 1,
+            population: self.population as f32,
+            trips: trips as f32,
+            average_commute_minutes,
+            spending: self.spending_today,
+        };
+        self.push_sample(sample);
+        self.spending_today = 0.0;
+
+        self.simulation.wake_up_in(Ticks(TICKS_PER_SIM_DAY), self.id.into(), world);
+    }
+}
+
+impl Interactable2d for StatisticsHistory {
+    fn draw_ui_2d(
+        &mut self,
+        imgui_ui: &External<::imgui::Ui<'static>>,
+        return_to: UserInterfaceID,
+        world: &mut World,
+    ) {
+        let ui = imgui_ui.steal();
+        let mut range = self.range;
+
+        ui.window(im_str!("City History"))
+            .size((350.0, 450.0), ImGuiSetCond_FirstUseEver)
+            .collapsible(true)
+            .build(|| {
+                for (i, &(label, option)) in
+                    [
+                        ("Last month", HistoryRange::Month),
+                        ("Last year", HistoryRange::Year),
+                        ("Everything", HistoryRange::All),
+                    ].iter()
+                        .enumerate()
+                {
+                    if i > 0 {
+                        ui.same_line(0.0);
+                    }
+                    if ui.small_button(im_str!("{}", label)) {
+                        range = option;
+                    }
+                }
+
+                let points = self.chart_points();
+                let days = points.iter().map(|point| point.days as usize).sum::<usize>();
+                let days_per_point = points.first().map_or(0, |point| point.days);
+                ui.text(im_str!("{} days, {} per point", days, days_per_point));
+
+                let population = points.iter().map(|point| point.population).collect::<Vec<_>>();
+                let trips = points.iter().map(|point| point.trips).collect::<Vec<_>>();
+                let commute = points
+                    .iter()
+                    .map(|point| point.average_commute_minutes)
+                    .collect::<Vec<_>>();
+                let spending = points.iter().map(|point| point.spending).collect::<Vec<_>>();
+
+                Chart::new("Population", ChartKind::Line)
+                    .series("Households", &population)
+                    .draw(&ui);
+                Chart::new("Trips per day", ChartKind::Line)
+                    .series("Trips", &trips)
+                    .draw(&ui);
+                Chart::new("Average commute", ChartKind::Line)
+                    .series("Minutes", &commute)
+                    .draw(&ui);
+                Chart::new("Spending per day", ChartKind::Bar)
+                    .series("Road maintenance", &spending)
+                    .draw(&ui);
+            });
+
+        self.range = range;
+        return_to.ui_drawn(ui, world);
+    }
+}
+
+pub fn setup(system: &mut ActorSystem, user_interface: UserInterfaceID, simulation: SimulationID) {
+    system.register::<StatisticsHistory>();
+    system.make_restorable::<StatisticsHistory>();
+    auto_setup(system);
+
+    StatisticsHistoryID::spawn(simulation, user_interface, &mut system.world());
+}
+
+mod kay_auto;
+pub use self::kay_auto::*;
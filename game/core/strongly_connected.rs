@@ -0,0 +1,80 @@
+/// Finds the strongly connected components of a directed graph given as
+/// adjacency lists of node indices (iterative Tarjan, safe for large graphs).
+pub fn strongly_connected_components(successors: &[Vec<usize>]) -> Vec<Vec<usize>> {
+    const UNVISITED: usize = ::std::usize::MAX;
+
+    let n = successors.len();
+    let mut index = vec![UNVISITED; n];
+    let mut lowlink = vec![0; n];
+    let mut on_stack = vec![false; n];
+    let mut stack = Vec::new();
+    let mut components = Vec::new();
+    let mut next_index = 0;
+
+    for root in 0..n {
+        if index[root] != UNVISITED {
+            continue;
+        }
+
+        // (node, position in its successor list)
+        let mut call_stack = vec![(root, 0)];
+        index[root] = next_index;
+        lowlink[root] = next_index;
+        next_index += 1;
+        stack.push(root);
+        on_stack[root] = true;
+
+        loop {
+            let (node, successor_idx) = match call_stack.last().cloned() {
+                Some(top) => top,
+                None => break,
+            };
+            if let Some(&successor) = successors[node].get(successor_idx) {
+                call_stack.last_mut().expect("just checked").1 += 1;
+                if index[successor] == UNVISITED {
+                    index[successor] = next_index;
+                    lowlink[successor] = next_index;
+                    next_index += 1;
+                    stack.push(successor);
+                    on_stack[successor] = true;
+                    call_stack.push((successor, 0));
+                } else if on_stack[successor] {
+                    lowlink[node] = lowlink[node].min(index[successor]);
+                }
+            } else {
+                call_stack.pop();
+                if let Some(&(parent, _)) = call_stack.last() {
+                    lowlink[parent] = lowlink[parent].min(lowlink[node]);
+                }
+                if lowlink[node] == index[node] {
+                    let mut component = Vec::new();
+                    while let Some(member) = stack.pop() {
+                        on_stack[member] = false;
+                        component.push(member);
+                        if member == node {
+                            break;
+                        }
+                    }
+                    components.push(component);
+                }
+            }
+        }
+    }
+
+    components
+}
+
+#[test]
+fn test_strongly_connected_components() {
+    // 0 <-> 1 -> 2 <-> 3, 4 isolated
+    let graph = vec![vec![1], vec![0, 2], vec![3], vec![2], vec![]];
+    let mut components = strongly_connected_components(&graph)
+        .into_iter()
+        .map(|mut component| {
+            component.sort();
+            component
+        })
+        .collect::<Vec<_>>();
+    components.sort();
+    assert_eq!(components, vec![vec![0, 1], vec![2, 3], vec![4]]);
+}
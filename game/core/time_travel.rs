@@ -0,0 +1,123 @@
+//! Time-travel debugging: while enabled, compact snapshots of the simulation are kept
+//! for the last simulated minutes, so the city can be rewound to just before a bug
+//! manifests and the bug re-observed, for example with debug overlays enabled.
+//!
+//! Only restorable actors go back in time: everything the simulation consists of, like
+//! lanes, junctions, trips, households, dispatchers, city events and the simulation itself.
+//! Tools, renderers, analyses and the UI keep their state, so meshes of roads built after
+//! the snapshot might linger until they are rebuilt. Actors that the simulation depends on
+//! need to be made restorable when they are registered, otherwise they keep the IDs of
+//! actors that a rewind removed.
+use kay::{ActorSystem, Snapshot};
+use rand::XorShiftRng;
+use std::collections::VecDeque;
+use core::simulation::{TICKS_PER_SIM_MINUTE, is_frozen};
+use core::snapshot_diff::SnapshotDiff;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TimeTravelSettings {
+    pub enabled: bool,
+    pub snapshot_interval_minutes: usize,
+    /// Older snapshots are dropped, so this limits how far back one can rewind
+    pub max_snapshots: usize,
+}
+
+impl Default for TimeTravelSettings {
+    fn default() -> Self {
+        TimeTravelSettings {
+            enabled: false,
+            snapshot_interval_minutes: 1,
+            max_snapshots: 30,
+        }
+    }
+}
+
+struct TimedSnapshot {
+    tick: usize,
+    snapshot: Snapshot,
+    rng: XorShiftRng,
+}
+
+/// A ring buffer of periodic world snapshots, taken in the main loop
+pub struct TimeTravel {
+    settings: TimeTravelSettings,
+    snapshots: VecDeque<TimedSnapshot>,
+    /// Simulated ticks so far, not counting ticks while paused
+    tick: usize,
+}
+
+impl TimeTravel {
+    pub fn from_settings() -> TimeTravel {
+        TimeTravel {
+            settings: ::ENV.load_settings("Time Travel"),
+            snapshots: VecDeque::new(),
+            tick: 0,
+        }
+    }
+
+    /// Called after each tick's messages were processed
+    pub fn count_tick(&mut self, system: &mut ActorSystem) {
+        if !self.settings.enabled || is_frozen() {
+            return;
+        }
+        self.tick += 1;
+
+        let interval = self.settings.snapshot_interval_minutes.max(1) * TICKS_PER_SIM_MINUTE;
+        if self.tick % interval == 0 {
+            if self.snapshots.len() >= self.settings.max_snapshots.max(1) {
+                self.snapshots.pop_front();
+            }
+            self.snapshots.push_back(TimedSnapshot {
+                tick: self.tick,
+                snapshot: system.snapshot(),
+                rng: ::core::random::state(),
+            });
+        }
+    }
+
+    /// Restores the latest snapshot that is at least `minutes` simulated minutes old,
+    /// returning how many minutes were actually rewound. Newer snapshots are dropped,
+    /// since the simulation will take a different course from here.
+    pub fn rewind(&mut self, minutes: usize, system: &mut ActorSystem) -> Result<f32, String> {
+        if !self.settings.enabled {
+            return Err("time travel is not enabled in the settings".to_owned());
+        }
+        let target_tick = self.tick.saturating_sub(minutes * TICKS_PER_SIM_MINUTE);
+
+        while self.snapshots.back().map(|newest| newest.tick > target_tick).unwrap_or(false) {
+            self.snapshots.pop_back();
+        }
+
+        match self.snapshots.back() {
+            Some(timed) => {
+                system.restore(&timed.snapshot);
+                ::core::random::restore(timed.rng.clone());
+                let rewound_ticks = self.tick - timed.tick;
+                self.tick = timed.tick;
+                Ok(rewound_ticks as f32 / TICKS_PER_SIM_MINUTE as f32)
+            }
+            None => Err(format!("no snapshot from {} minutes ago or earlier", minutes)),
+        }
+    }
+
+    /// Compares the latest snapshot that is at least `minutes` simulated minutes old
+    /// with the current state of the world
+    pub fn diff(&self, minutes: usize, system: &ActorSystem) -> Result<SnapshotDiff, String> {
+        if !self.settings.enabled {
+            return Err("time travel is not enabled in the settings".to_owned());
+        }
+        let target_tick = self.tick.saturating_sub(minutes * TICKS_PER_SIM_MINUTE);
+
+        match self.snapshots.iter().rev().find(|timed| timed.tick <= target_tick) {
+            Some(timed) => Ok(SnapshotDiff::between(&timed.snapshot, &system.snapshot())),
+            None => Err(format!("no snapshot from {} minutes ago or earlier", minutes)),
+        }
+    }
+
+    /// Simulated minutes that can be rewound at most
+    pub fn available_minutes(&self) -> f32 {
+        self.snapshots.front().map_or(0.0, |oldest| {
+            (self.tick - oldest.tick) as f32 / TICKS_PER_SIM_MINUTE as f32
+        })
+    }
+}
@@ -0,0 +1,133 @@
+//! Strong types for continuous simulation quantities, so that mixing up units (like
+//! adding a time to a distance, or a microtraffic time slowed down by
+//! `SimulationConfig::microtraffic_slowdown` to a travel time estimate) doesn't compile.
+//!
+//! Whole simulated seconds that the simulation schedules in are `simulation::Seconds`.
+//! The wrapped values are public, for the few places that need to do raw arithmetic.
+use std::ops::{Add, Sub, AddAssign, SubAssign, Mul, Div, Neg};
+
+macro_rules! quantity {
+    ($unit:ident) => {
+        impl Add for $unit {
+            type Output = $unit;
+            fn add(self, rhs: $unit) -> $unit {
+                $unit(self.0 + rhs.0)
+            }
+        }
+
+        impl Sub for $unit {
+            type Output = $unit;
+            fn sub(self, rhs: $unit) -> $unit {
+                $unit(self.0 - rhs.0)
+            }
+        }
+
+        impl AddAssign for $unit {
+            fn add_assign(&mut self, rhs: $unit) {
+                self.0 += rhs.0;
+            }
+        }
+
+        impl SubAssign for $unit {
+            fn sub_assign(&mut self, rhs: $unit) {
+                self.0 -= rhs.0;
+            }
+        }
+
+        impl Neg for $unit {
+            type Output = $unit;
+            fn neg(self) -> $unit {
+                $unit(-self.0)
+            }
+        }
+
+        impl Mul<f32> for $unit {
+            type Output = $unit;
+            fn mul(self, factor: f32) -> $unit {
+                $unit(self.0 * factor)
+            }
+        }
+
+        impl Div<f32> for $unit {
+            type Output = $unit;
+            fn div(self, divisor: f32) -> $unit {
+                $unit(self.0 / divisor)
+            }
+        }
+
+        /// The ratio of two quantities of the same unit
+        impl Div for $unit {
+            type Output = f32;
+            fn div(self, rhs: $unit) -> f32 {
+                self.0 / rhs.0
+            }
+        }
+
+        impl $unit {
+            pub fn min(self, other: $unit) -> $unit {
+                $unit(self.0.min(other.0))
+            }
+
+            pub fn max(self, other: $unit) -> $unit {
+                $unit(self.0.max(other.0))
+            }
+
+            pub fn is_finite(&self) -> bool {
+                self.0.is_finite()
+            }
+        }
+    };
+}
+
+#[derive(Copy, Clone, PartialEq, PartialOrd, Default, Debug, Serialize, Deserialize)]
+pub struct Meters(pub f32);
+
+#[derive(Copy, Clone, PartialEq, PartialOrd, Default, Debug, Serialize, Deserialize)]
+pub struct MetersPerSecond(pub f32);
+
+#[derive(Copy, Clone, PartialEq, PartialOrd, Default, Debug, Serialize, Deserialize)]
+pub struct Seconds(pub f32);
+
+quantity!(Meters);
+quantity!(MetersPerSecond);
+quantity!(Seconds);
+
+impl Meters {
+    pub fn km(&self) -> f32 {
+        self.0 / 1000.0
+    }
+}
+
+impl Seconds {
+    pub fn minutes(&self) -> f32 {
+        self.0 / 60.0
+    }
+}
+
+impl Mul<Seconds> for MetersPerSecond {
+    type Output = Meters;
+    fn mul(self, duration: Seconds) -> Meters {
+        Meters(self.0 * duration.0)
+    }
+}
+
+impl Mul<MetersPerSecond> for Seconds {
+    type Output = Meters;
+    fn mul(self, velocity: MetersPerSecond) -> Meters {
+        Meters(self.0 * velocity.0)
+    }
+}
+
+impl Div<MetersPerSecond> for Meters {
+    type Output = Seconds;
+    fn div(self, velocity: MetersPerSecond) -> Seconds {
+        Seconds(self.0 / velocity.0)
+    }
+}
+
+impl Div<Seconds> for Meters {
+    type Output = MetersPerSecond;
+    fn div(self, duration: Seconds) -> MetersPerSecond {
+        MetersPerSecond(self.0 / duration.0)
+    }
+}
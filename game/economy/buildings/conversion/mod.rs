@@ -0,0 +1,195 @@
+use kay::{ActorSystem, World};
+use descartes::P2;
+use stagemaster::combo::Combo2;
+use stagemaster::combo::Button::{LControl, P, Y, A, F, H, S, K, N, U};
+use stagemaster::{UserInterfaceID, Event3d, Interactable3d, Interactable3dID,
+                  MSG_Interactable3d_on_event};
+use stagemaster::actions::{register_action, ActionId, ActionListener, ActionListenerID,
+                           ActionPhase, MSG_ActionListener_on_action};
+use economy::households::cargo_terminal::TerminalKind;
+use economy::households::venue::VenueKind;
+use transport::services::emergency::StationKind;
+use transport::planning::current_plan::set_tool_active;
+use super::BuildingID;
+
+/// What a building can be turned into, replacing whoever lives or works there
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Conversion {
+    Terminal(TerminalKind),
+    Station(StationKind),
+    School,
+    ParkAndRide,
+    Venue(VenueKind),
+    Park,
+}
+
+impl Conversion {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Conversion::Terminal(kind) => kind.name(),
+            Conversion::Station(kind) => kind.name(),
+            Conversion::School => "School",
+            Conversion::ParkAndRide => "Park & Ride",
+            Conversion::Venue(kind) => kind.name(),
+            Conversion::Park => "Park",
+        }
+    }
+
+    /// What the tool converts to after the action for `self` while converting to
+    /// `current`: the action toggles its conversion, except for venues, where it cycles
+    /// through the venue kinds (and back to inactive)
+    fn after(&self, current: Option<Conversion>) -> Option<Conversion> {
+        match (current, *self) {
+            (Some(Conversion::Venue(kind)), Conversion::Venue(_)) => {
+                kind.next().map(Conversion::Venue)
+            }
+            (Some(current), _) if current == *self => None,
+            _ => Some(*self),
+        }
+    }
+}
+
+/// Each action with its default binding and what it starts converting buildings to
+fn conversion_actions() -> [(&'static str, Combo2, Conversion); 9] {
+    [
+        ("Place Port", Combo2::new(&[LControl, P], &[]), Conversion::Terminal(TerminalKind::Port)),
+        (
+            "Place Rail Yard",
+            Combo2::new(&[LControl, Y], &[]),
+            Conversion::Terminal(TerminalKind::RailYard),
+        ),
+        (
+            "Place Airport",
+            Combo2::new(&[LControl, A], &[]),
+            Conversion::Terminal(TerminalKind::Airport),
+        ),
+        (
+            "Place Fire Station",
+            Combo2::new(&[LControl, F], &[]),
+            Conversion::Station(StationKind::FireStation),
+        ),
+        (
+            "Place Hospital",
+            Combo2::new(&[LControl, H], &[]),
+            Conversion::Station(StationKind::Hospital),
+        ),
+        ("Place School", Combo2::new(&[LControl, S], &[]), Conversion::School),
+        ("Place Park & Ride", Combo2::new(&[LControl, K], &[]), Conversion::ParkAndRide),
+        ("Place Park", Combo2::new(&[LControl, N], &[]), Conversion::Park),
+        ("Place Venue", Combo2::new(&[LControl, U], &[]), Conversion::Venue(VenueKind::Stadium)),
+    ]
+}
+
+/// Turns buildings into terminals, stations, schools, parks and the like: the action of
+/// a conversion activates the tool, clicking a building then converts it
+#[derive(Compact, Clone)]
+pub struct BuildingConverter {
+    id: BuildingConverterID,
+    user_interface: UserInterfaceID,
+    converting_to: Option<Conversion>,
+}
+
+impl BuildingConverter {
+    pub fn init(
+        id: BuildingConverterID,
+        user_interface: UserInterfaceID,
+        world: &mut World,
+    ) -> BuildingConverter {
+        for &(action, default_binding, _) in &conversion_actions() {
+            register_action(action, default_binding, id.into(), world);
+        }
+
+        BuildingConverter { id, user_interface, converting_to: None }
+    }
+}
+
+impl ActionListener for BuildingConverter {
+    fn on_action(&mut self, action: ActionId, phase: ActionPhase, world: &mut World) {
+        if phase != ActionPhase::Started {
+            return;
+        }
+
+        let maybe_conversion = conversion_actions()
+            .iter()
+            .find(|&&(name, _, _)| action == ActionId::named(name))
+            .map(|&(_, _, conversion)| conversion);
+
+        if let Some(conversion) = maybe_conversion {
+            let was_converting = self.converting_to.is_some();
+            self.converting_to = conversion.after(self.converting_to);
+
+            if was_converting != self.converting_to.is_some() {
+                set_tool_active(
+                    self.user_interface,
+                    self.id.into(),
+                    self.converting_to.is_some(),
+                    world,
+                );
+            }
+        }
+    }
+}
+
+impl Interactable3d for BuildingConverter {
+    fn on_event(&mut self, event: Event3d, world: &mut World) {
+        if let Some(conversion) = self.converting_to {
+            match event {
+                Event3d::DragFinished { to, .. } => {
+                    BuildingID::global_broadcast(world).convert(
+                        P2::new(to.x, to.y),
+                        conversion,
+                        world,
+                    );
+                }
+                Event3d::Frame => {
+                    self.user_interface.add_debug_text(
+                        format!("Placing {}", conversion.name()).chars().collect(),
+                        "click a building".chars().collect(),
+                        [0.0, 0.0, 0.0, 1.0],
+                        false,
+                        world,
+                    );
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+pub fn setup(system: &mut ActorSystem, user_interface: UserInterfaceID) {
+    system.register::<BuildingConverter>();
+    auto_setup(system);
+
+    BuildingConverterID::init(user_interface, &mut system.world());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn actions_toggle_their_conversion() {
+        let school = Conversion::School;
+        assert_eq!(school.after(None), Some(school));
+        assert_eq!(school.after(Some(school)), None);
+        assert_eq!(school.after(Some(Conversion::Park)), Some(school));
+    }
+
+    #[test]
+    fn venue_action_cycles_through_venue_kinds() {
+        let venue = Conversion::Venue(VenueKind::Stadium);
+        let mut converting_to = None;
+        let mut names = Vec::new();
+        loop {
+            converting_to = venue.after(converting_to);
+            match converting_to {
+                Some(conversion) => names.push(conversion.name()),
+                None => break,
+            }
+        }
+        assert_eq!(names, vec!["Stadium", "Festival Ground", "Race Course"]);
+    }
+}
+
+mod kay_auto;
+pub use self::kay_auto::*;
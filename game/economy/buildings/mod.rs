@@ -1,43 +1,438 @@
-use kay::{ActorSystem, World, External};
+use kay::{ActorSystem, World, Fate};
 use compact::CVec;
-use descartes::{P2, V2, Norm, Curve};
-use stagemaster::combo::{Bindings, Combo2};
-use stagemaster::{UserInterfaceID, Event3d, Interactable3d, Interactable3dID,
-                  MSG_Interactable3d_on_event};
+use descartes::{N, P2, V2, Norm, Curve};
+use stagemaster::combo::Combo2;
+use stagemaster::UserInterfaceID;
 use stagemaster::combo::Button::*;
-use stagemaster::geometry::AnyShape;
+use stagemaster::actions::{register_action, ActionId, ActionListener, ActionListenerID,
+                           ActionPhase, MSG_ActionListener_on_action};
 use transport::lane::{Lane, LaneID};
+use stagemaster::geometry::CPath;
 
 pub mod rendering;
+pub mod conversion;
+use self::conversion::Conversion;
 
 use super::households::HouseholdID;
+use super::households::satisfaction::SatisfactionSurveyID;
+use super::households::cargo_terminal::{CargoTerminalID, TerminalKind};
+use super::households::airport::AirportID;
+use super::households::emergency_station::EmergencyStationID;
+use super::households::school::SchoolID;
+use super::households::park_and_ride::ParkAndRideID;
+use super::households::park::{ParkID, WALKING_MINUTES};
+use super::households::venue::VenueID;
+use transport::services::ServiceKind;
+use transport::spatial_index::SpatialIndexID;
+use transport::utilities::UtilityNetworkID;
+use transport::services::emergency::{EmergencyDispatcherID, IncidentKind};
+use transport::services::delivery::DeliveryDispatcherID;
+use super::demographics::DemographicsID;
+use super::jobs_housing::JobsHousingBalanceID;
+use super::synthetic_population::PopulationImporterID;
+use super::businesses::BusinessRegistryID;
+use core::city_events::{self, CityEventKind};
+use terrain::TerrainID;
 
 #[derive(Compact, Clone)]
 pub struct Building {
     id: BuildingID,
     households: CVec<HouseholdID>,
     lot: Lot,
+    /// The lane cars use to reach the building, if it still has one
+    access: Option<LaneID>,
+    garbage: f32,
+    litter: f32,
+    /// The school whose catchment area the building is in, and how far away it is
+    school: Option<(SchoolID, f32)>,
+    /// The park closest to walk to, and how many minutes it takes
+    park: Option<(ParkID, f32)>,
+    /// Share of the utilities (water and power) that reach the building
+    utility_supply: f32,
+    /// How big the building has grown, between 1 and `MAX_GROWTH_LEVEL`
+    growth_level: u8,
+    /// How far the building is built, between 0 and 1. Construction starts when
+    /// the first household moves in, which then already lives there
+    construction_progress: f32,
 }
 
+const DEMOLITION_RADIUS: f32 = 10.0;
+const MAX_ACCESS_DISTANCE: f32 = 35.0;
+// per household and hour
+const GARBAGE_PRODUCTION: f32 = 1.0;
+const LITTER_PRODUCTION: f32 = 0.5;
+// this much uncollected garbage and litter make a building completely filthy
+const GARBAGE_FOR_FULL_FILTH: f32 = 48.0;
+const LITTER_FOR_FULL_FILTH: f32 = 48.0;
+// per household and hour
+const FIRE_CHANCE: f32 = 0.0002;
+pub const MAX_GROWTH_LEVEL: u8 = 4;
+/// Land value of a building right next to a park, compared to 1.0 without one nearby
+const MAX_PARK_LAND_VALUE: f32 = 1.5;
+/// Construction progresses by this much every `CONSTRUCTION_STEP_TICKS`
+const CONSTRUCTION_STEP: f32 = 0.05;
+const CONSTRUCTION_STEP_TICKS: Ticks = Ticks(50);
+
 impl Building {
     pub fn spawn(
         id: BuildingID,
         households: &CVec<HouseholdID>,
         lot: &Lot,
-        _: &mut World,
+        world: &mut World,
     ) -> Building {
+        SpatialIndexID::local_first(world).add_building(id, lot.position, world);
+        TerrainID::local_first(world).level_around(
+            lot.position,
+            lot_radius(lot),
+            true,
+            world,
+        );
         Building {
             id,
             households: households.clone(),
             lot: lot.clone(),
+            access: Some(lot.adjacent_lane),
+            garbage: 0.0,
+            litter: 0.0,
+            school: None,
+            park: None,
+            utility_supply: 1.0,
+            growth_level: 1,
+            construction_progress: 0.0,
+        }
+    }
+
+    pub fn demolish_at(&mut self, position: P2, world: &mut World) -> Fate {
+        if (position - self.lot.position).norm() < DEMOLITION_RADIUS {
+            for household in &self.households {
+                household.on_home_demolished(world);
+            }
+            rendering::on_demolish(self, world);
+            SpatialIndexID::local_first(world).remove_building(self.id, world);
+            TerrainID::local_first(world).level_around(
+                self.lot.position,
+                lot_radius(&self.lot),
+                false,
+                world,
+            );
+            if self.construction_progress < 1.0 {
+                // still under construction, with the next step pending
+                SimulationID::local_first(world).forget_wake_ups(self.id.into(), world);
+            }
+            Fate::Die
+        } else {
+            Fate::Live
+        }
+    }
+
+    /// Replaces whoever lives or works here with what `conversion` turns it into, if the
+    /// building is at `position`. Stations only work for buildings that can be reached,
+    /// since vehicles need to get out
+    pub fn convert(&mut self, position: P2, conversion: Conversion, world: &mut World) {
+        if (position - self.lot.position).norm() >= DEMOLITION_RADIUS {
+            return;
+        }
+        if let (Conversion::Station(_), None) = (conversion, self.access) {
+            return;
+        }
+
+        for household in &self.households {
+            household.on_home_demolished(world);
+        }
+        self.households = CVec::new();
+
+        let household: HouseholdID = match conversion {
+            Conversion::Terminal(TerminalKind::Airport) => AirportID::open(self.id, world).into(),
+            Conversion::Terminal(kind) => CargoTerminalID::move_into(kind, self.id, world).into(),
+            Conversion::Station(kind) => {
+                let station = EmergencyStationID::open(kind, self.id, world);
+                EmergencyDispatcherID::local_first(world).add_station(
+                    station,
+                    kind,
+                    self.id,
+                    self.access.expect("checked above"),
+                    self.lot.position,
+                    world,
+                );
+                station.into()
+            }
+            Conversion::School => SchoolID::open(self.id, self.lot.position, world).into(),
+            Conversion::ParkAndRide => {
+                ParkAndRideID::open(self.id, self.lot.position, world).into()
+            }
+            Conversion::Venue(kind) => {
+                VenueID::open(kind, self.id, self.lot.position, world).into()
+            }
+            Conversion::Park => ParkID::open(self.id, self.lot.position, world).into(),
+        };
+        self.add_household(household, world);
+    }
+
+    /// Remembers `park` if it can be walked to faster than our current one, from any of
+    /// the `reachable` points of the walking graph next to the building
+    pub fn on_park_access(&mut self, park: ParkID, reachable: &CVec<(P2, f32)>, _: &mut World) {
+        let minutes = reachable
+            .iter()
+            .filter(|&&(point, _)| (point - self.lot.position).norm() < MAX_ACCESS_DISTANCE)
+            .map(|&(_, minutes)| minutes)
+            .fold(None, |min: Option<f32>, minutes| {
+                Some(min.map(|min| min.min(minutes)).unwrap_or(minutes))
+            });
+
+        if let Some(minutes) = minutes {
+            let closer = match self.park {
+                Some((current, current_minutes)) => current == park || minutes < current_minutes,
+                None => true,
+            };
+            if closer {
+                self.park = Some((park, minutes));
+            }
+        } else if self.park.map(|(current, _)| current) == Some(park) {
+            // the way there was cut off
+            self.park = None;
+        }
+    }
+
+    pub fn leave_park_access(&mut self, park: ParkID, _: &mut World) {
+        if self.park.map(|(current, _)| current) == Some(park) {
+            self.park = None;
         }
     }
 
+    /// Between 0.0 (no park within walking distance) and 1.0 (a park right next to it)
+    fn green_space_access(&self) -> f32 {
+        self.park
+            .map(|(_, minutes)| (1.0 - minutes / WALKING_MINUTES).max(0.0))
+            .unwrap_or(0.0)
+    }
+
+    /// How much people would like to live here, compared to 1.0 for an average building.
+    /// Only depends on how close a park is for now
+    fn land_value(&self) -> f32 {
+        1.0 + (MAX_PARK_LAND_VALUE - 1.0) * self.green_space_access()
+    }
+
+    /// Joins the catchment area of `school` if it is closer than our current one
+    pub fn claim_for_catchment(&mut self, school: SchoolID, position: P2, _: &mut World) {
+        let distance = (position - self.lot.position).norm();
+        let closer = match self.school {
+            Some((current, current_distance)) => current == school || distance < current_distance,
+            None => true,
+        };
+        if closer {
+            self.school = Some((school, distance));
+        }
+    }
+
+    pub fn leave_catchment(&mut self, school: SchoolID, _: &mut World) {
+        if self.school.map(|(current, _)| current) == Some(school) {
+            self.school = None;
+        }
+    }
+
+    pub fn report_pupils(&mut self, school: SchoolID, world: &mut World) {
+        if let Some((our_school, distance)) = self.school {
+            if our_school == school {
+                // TODO: this is super hacky, like in rendering
+                let families = FamilyID::local_broadcast(world)._raw_id;
+                for household in &self.households {
+                    if household._raw_id.local_broadcast() == families {
+                        FamilyID::from(*household).report_children(school, distance, world);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Offers this building as a trip destination to `collector`, if it can be reached.
+    /// With `homes_only`, only buildings families live in are offered
+    pub fn report_as_destination(
+        &mut self,
+        collector: DestinationCollectorID,
+        homes_only: bool,
+        world: &mut World,
+    ) {
+        // TODO: this is super hacky, like in rendering
+        let families = FamilyID::local_broadcast(world)._raw_id;
+        let is_home = self.households.iter().any(|household| {
+            household._raw_id.local_broadcast() == families
+        });
+        if !self.households.is_empty() && self.access.is_some() && (is_home || !homes_only) {
+            collector.add_destination(self.id, world);
+        }
+    }
+
+    /// Tells `dispatcher` how many households here can order parcels
+    /// and whether vans can leave from here (from shops)
+    pub fn report_delivery_point(&mut self, dispatcher: DeliveryDispatcherID, world: &mut World) {
+        if let Some(access) = self.access {
+            // TODO: same hack as in `report_as_destination`
+            let families = FamilyID::local_broadcast(world)._raw_id;
+            let shops = GroceryShopID::local_broadcast(world)._raw_id;
+            let n_homes = self.households
+                .iter()
+                .filter(|household| household._raw_id.local_broadcast() == families)
+                .count();
+            let is_depot = self.households.iter().any(|household| {
+                household._raw_id.local_broadcast() == shops
+            });
+            if n_homes > 0 || is_depot {
+                dispatcher.add_delivery_point(access, self.lot.position, n_homes, is_depot, world);
+            }
+        }
+    }
+
+    pub fn on_lane_unbuilt(&mut self, lane: LaneID, _: &mut World) {
+        if self.access == Some(lane) {
+            self.access = None;
+        }
+    }
+
+    pub fn on_lane_opened(&mut self, lane: LaneID, path: &CPath, _: &mut World) {
+        if self.access.is_none() && path.distance_to(self.lot.position) < MAX_ACCESS_DISTANCE {
+            self.access = Some(lane);
+        }
+    }
+
+    pub fn remove_household(&mut self, household: HouseholdID, world: &mut World) {
+        self.households.retain(|other| *other != household);
+        if self.households.is_empty() {
+            city_events::publish(
+                CityEventKind::BuildingAbandoned,
+                "Everybody moved out of a building",
+                Some(self.lot.position),
+                world,
+            );
+        }
+    }
+
+    pub fn survey_households(&mut self, survey: SatisfactionSurveyID, world: &mut World) {
+        for household in &self.households {
+            household.report_satisfaction(survey, self.lot.position, world);
+        }
+        survey.add_filth(self.lot.position, self.filth(), world);
+        survey.add_utility_supply(self.lot.position, self.utility_supply, world);
+        survey.add_green_space(self.lot.position, self.green_space_access(), world);
+    }
+
+    pub fn report_commutes(&mut self, balance: JobsHousingBalanceID, world: &mut World) {
+        for household in &self.households {
+            household.report_commutes(balance, self.lot.position, world);
+        }
+    }
+
+    pub fn report_business_site(&mut self, registry: BusinessRegistryID, world: &mut World) {
+        registry.add_site(
+            self.id,
+            self.lot.position,
+            self.households.len(),
+            self.access.is_some(),
+            world,
+        );
+    }
+
+    pub fn report_utility_demand(&mut self, network: UtilityNetworkID, world: &mut World) {
+        if !self.households.is_empty() {
+            network.add_demand(self.lot.position, self.households.len(), world);
+        }
+    }
+
+    pub fn set_utility_supply(&mut self, water: &CVec<P2>, power: &CVec<P2>, _: &mut World) {
+        let supplied = |positions: &CVec<P2>| if positions.contains(&self.lot.position) {
+            0.5
+        } else {
+            0.0
+        };
+        self.utility_supply = supplied(water) + supplied(power);
+    }
+
+    pub fn produce_waste(&mut self, hours: f32, _: &mut World) {
+        self.garbage += self.households.len() as f32 * GARBAGE_PRODUCTION * hours;
+        self.litter += self.households.len() as f32 * LITTER_PRODUCTION * hours;
+    }
+
+    pub fn receive_service(&mut self, lane: LaneID, kind: ServiceKind, _: &mut World) {
+        if self.access == Some(lane) {
+            match kind {
+                ServiceKind::GarbageCollection => self.garbage = 0.0,
+                ServiceKind::StreetSweeping => self.litter = 0.0,
+                ServiceKind::SnowPlowing => {}
+            }
+        }
+    }
+
+    pub fn risk_fire(
+        &mut self,
+        dispatcher: EmergencyDispatcherID,
+        tick: Timestamp,
+        world: &mut World,
+    ) {
+        if let Some(access) = self.access {
+            let chance = self.households.len() as f32 * FIRE_CHANCE;
+            if chance > 0.0 && ::core::random::next_f32() < chance {
+                dispatcher.report_incident(
+                    IncidentKind::Fire,
+                    self.id.into(),
+                    access,
+                    self.lot.position,
+                    tick,
+                    world,
+                );
+            }
+        }
+    }
+
+    /// How dirty the building and the street in front of it are,
+    /// between 0.0 (spotless) and 1.0 (filthy)
+    fn filth(&self) -> f32 {
+        (0.5 * self.garbage / GARBAGE_FOR_FULL_FILTH + 0.5 * self.litter / LITTER_FOR_FULL_FILTH)
+            .min(1.0)
+    }
+
+    pub fn report_to_census(&mut self, demographics: DemographicsID, world: &mut World) {
+        if self.households.is_empty() {
+            demographics.add_vacant_building(self.id, self.land_value(), world);
+        }
+    }
+
+    pub fn report_for_population_import(
+        &mut self,
+        importer: PopulationImporterID,
+        world: &mut World,
+    ) {
+        importer.add_building(self.id, self.lot.position, self.households.is_empty(), world);
+    }
+
     pub fn add_household(&mut self, household: HouseholdID, world: &mut World) {
         self.households.push(household);
         // TODO: such a weird place to do this, but ok for now
         if self.households.len() == 1 {
             rendering::on_add(self, world);
+            if self.construction_progress == 0.0 {
+                SimulationID::local_first(world).wake_up_in(
+                    CONSTRUCTION_STEP_TICKS,
+                    self.id.into(),
+                    world,
+                );
+            }
+        } else {
+            // more households need a bigger building
+            let level = self.households.len().min(MAX_GROWTH_LEVEL as usize) as u8;
+            if level > self.growth_level {
+                self.set_growth_level(level, world);
+            }
+        }
+    }
+
+    /// Grows (or shrinks) the building to `level`, rebuilding its geometry,
+    /// for example when a shop on it hires more people
+    pub fn set_growth_level(&mut self, level: u8, world: &mut World) {
+        let level = level.max(1).min(MAX_GROWTH_LEVEL);
+        if level != self.growth_level {
+            self.growth_level = level;
+            if !self.households.is_empty() {
+                rendering::on_regrow(self, world);
+            }
         }
     }
 }
@@ -54,27 +449,35 @@ impl RoughLocation for Building {
         tick: Timestamp,
         world: &mut World,
     ) {
-        Into::<RoughLocationID>::into(self.lot.adjacent_lane)
-            .resolve_as_location(requester, rough_location, tick, world)
+        if let Some(lane) = self.access {
+            Into::<RoughLocationID>::into(lane)
+                .resolve_as_location(requester, rough_location, tick, world)
+        } else {
+            // cut off from the road network
+            requester.location_resolved(rough_location, None, tick, world);
+        }
     }
 }
 
+/// Gathers buildings that trips could go to, see `Building::report_as_destination`
+pub trait DestinationCollector {
+    fn add_destination(&mut self, destination: BuildingID, world: &mut World);
+}
+
 #[derive(Compact, Clone)]
 pub struct Lot {
     pub position: P2,
     pub orientation: V2,
     pub adjacent_lane: LaneID,
+    /// Width of the lot along the lane
+    pub frontage: N,
+    /// Extent of the lot away from the lane
+    pub depth: N,
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct BuildingSpawnerBindings(Bindings);
-
-impl Default for BuildingSpawnerBindings {
-    fn default() -> Self {
-        BuildingSpawnerBindings(Bindings::new(
-            vec![("Spawn Building", Combo2::new(&[B], &[]))],
-        ))
-    }
+/// Distance from the center of `lot` to its corners
+fn lot_radius(lot: &Lot) -> N {
+    (lot.frontage * lot.frontage + lot.depth * lot.depth).sqrt() / 2.0
 }
 
 #[derive(Compact, Clone)]
@@ -89,24 +492,20 @@ pub enum BuildingSpawnerState {
 pub struct BuildingSpawner {
     id: BuildingSpawnerID,
     simulation: SimulationID,
-    bindings: External<BuildingSpawnerBindings>,
     state: BuildingSpawnerState,
 }
 
 impl BuildingSpawner {
     pub fn init(
         id: BuildingSpawnerID,
-        user_interface: UserInterfaceID,
         simulation: SimulationID,
         world: &mut World,
     ) -> BuildingSpawner {
-        user_interface.add(id.into(), AnyShape::Everywhere, 0, world);
-        user_interface.focus(id.into(), world);
+        register_action("Spawn Building", Combo2::new(&[B], &[]), id.into(), world);
 
         BuildingSpawner {
             id,
             simulation,
-            bindings: External::new(::ENV.load_settings("Building Spawning")),
             state: BuildingSpawnerState::Idle,
         }
     }
@@ -144,19 +543,15 @@ impl BuildingSpawner {
     }
 }
 
-impl Interactable3d for BuildingSpawner {
-    fn on_event(&mut self, event: Event3d, world: &mut World) {
-        if let Event3d::Combos(combos) = event {
-            self.bindings.0.do_rebinding(&combos.current);
-
-            if self.bindings.0["Spawn Building"].is_freshly_in(&combos) {
-                if let BuildingSpawnerState::Idle = self.state {
-                    LaneID::global_broadcast(world).find_lot(self.id, world);
-                    self.simulation.wake_up_in(Ticks(10), self.id.into(), world);
-                    self.state = BuildingSpawnerState::Collecting(CVec::new());
-                }
+impl ActionListener for BuildingSpawner {
+    fn on_action(&mut self, action: ActionId, phase: ActionPhase, world: &mut World) {
+        if phase == ActionPhase::Started && action == ActionId::named("Spawn Building") {
+            if let BuildingSpawnerState::Idle = self.state {
+                LaneID::global_broadcast(world).find_lot(self.id, world);
+                self.simulation.wake_up_in(Ticks(10), self.id.into(), world);
+                self.state = BuildingSpawnerState::Collecting(CVec::new());
             }
-        };
+        }
     }
 }
 
@@ -206,6 +601,20 @@ impl LotConflictor for Lane {
 
 use core::simulation::{Sleeper, SleeperID, MSG_Sleeper_wake};
 
+impl Sleeper for Building {
+    fn wake(&mut self, _time: Timestamp, world: &mut World) {
+        self.construction_progress = (self.construction_progress + CONSTRUCTION_STEP).min(1.0);
+        rendering::on_construction_progress(self, world);
+        if self.construction_progress < 1.0 {
+            SimulationID::local_first(world).wake_up_in(
+                CONSTRUCTION_STEP_TICKS,
+                self.id.into(),
+                world,
+            );
+        }
+    }
+}
+
 impl Sleeper for BuildingSpawner {
     fn wake(&mut self, _time: Timestamp, world: &mut World) {
         self.state = match self.state {
@@ -238,7 +647,7 @@ impl Sleeper for BuildingSpawner {
                         None
                     })
                     .collect();
-                let lanes = LotConflictorID { _raw_id: world.global_broadcast::<Lane>() };
+                let lanes: LotConflictorID = LaneID::global_broadcast(world).into();
                 lanes.find_conflicts(new_lots.clone(), self.id, world);
                 self.simulation.wake_up_in(Ticks(10), self.id.into(), world);
 
@@ -268,11 +677,13 @@ use core::simulation::{SimulationID, Ticks};
 pub fn setup(system: &mut ActorSystem, user_interface: UserInterfaceID, simulation: SimulationID) {
     system.register::<Building>();
     system.register::<BuildingSpawner>();
+    system.make_restorable::<Building>();
     rendering::setup(system, user_interface);
+    conversion::setup(system, user_interface);
 
     kay_auto::auto_setup(system);
 
-    BuildingSpawnerID::init(user_interface, simulation, &mut system.world());
+    BuildingSpawnerID::init(simulation, &mut system.world());
 }
 
 mod kay_auto;
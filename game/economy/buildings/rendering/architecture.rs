@@ -1,47 +1,224 @@
-use descartes::{N, P2, Norm, WithUniqueOrthogonal};
+use descartes::{N, P2, V2, Norm, Dot, WithUniqueOrthogonal};
 use rand::Rng;
 use monet::{Vertex, Geometry};
 
-use super::super::Lot;
+use super::super::{Lot, MAX_GROWTH_LEVEL};
+
+/// What a building is used for, which decides its proportions, roof and windows
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum Zone {
+    Residential,
+    Commercial,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum RoofStyle {
+    Flat,
+    Gable,
+    Hip,
+}
+
+const FLOOR_HEIGHT: N = 3.0;
+/// Shops have a higher ground floor, with shop windows
+const SHOP_FLOOR_HEIGHT: N = 4.0;
+const ROOF_ANGLE: N = 0.3;
+/// Distance between the centers of neighbouring windows on a facade
+const WINDOW_SPACING: N = 3.0;
+const WINDOW_WIDTH: N = 1.2;
+const WINDOW_HEIGHT: N = 1.4;
+const WINDOW_SILL_HEIGHT: N = 0.9;
+const SHOP_WINDOW_HEIGHT: N = 2.6;
+const SHOP_WINDOW_SILL_HEIGHT: N = 0.4;
+/// Windows are drawn this far in front of the wall, so they don't flicker
+const WINDOW_OFFSET: N = 0.05;
+/// Free space kept between the footprint and the edges of the lot
+const LOT_MARGIN: N = 2.0;
+/// Lawns are drawn this far above the ground, so they don't flicker
+const LAWN_OFFSET: N = 0.05;
+const FOOTPATH_WIDTH: N = 2.0;
+/// Lot area per tree in a park
+const AREA_PER_TREE: N = 60.0;
+const TRUNK_HEIGHT: N = 2.5;
+/// Scaffolding poles stand at most this far apart around the lot
+const SCAFFOLDING_SPACING: N = 4.0;
+const SCAFFOLDING_POLE_SIZE: N = 0.1;
 
 #[derive(Compact, Clone)]
 pub struct BuildingGeometry {
     pub wall: Geometry,
     pub brick_roof: Geometry,
     pub flat_roof: Geometry,
+    pub windows: Geometry,
+    pub lawn: Geometry,
 }
 
-pub fn build_building<R: Rng>(lot: &Lot, is_shop: bool, rng: &mut R) -> BuildingGeometry {
-    let (main_footprint, entrance_footprint) = generate_house_footprint(lot, rng);
+/// Generates a building for `lot`: a footprint sized to the lot and extruded to a number
+/// of floors, a roof and grids of windows on each facade. Buildings with a higher
+/// `growth_level` (between 1 and `MAX_GROWTH_LEVEL`) cover more of their lot and have
+/// more floors. `rng` varies the details, so neighbouring buildings don't look the same
+pub fn build_building<R: Rng>(
+    lot: &Lot,
+    zone: Zone,
+    growth_level: u8,
+    rng: &mut R,
+) -> BuildingGeometry {
+    let level = growth_level.max(1).min(MAX_GROWTH_LEVEL);
+    let (main_footprint, entrance_footprint) = generate_house_footprint(lot, level, rng);
 
-    if is_shop {
-        let height = 3.0 + rng.next_f32();
-        let entrance_height = height - 0.7;
+    let (n_floors, ground_floor_height) = match zone {
+        Zone::Residential => {
+            let extra_floor = if level > 1 && rng.next_f32() < 0.5 {
+                1
+            } else {
+                0
+            };
+            (level as usize + extra_floor, FLOOR_HEIGHT)
+        }
+        Zone::Commercial => (level as usize, SHOP_FLOOR_HEIGHT),
+    };
+    let height = ground_floor_height + (n_floors - 1) as N * FLOOR_HEIGHT;
 
-        BuildingGeometry {
-            wall: main_footprint.wall_geometry(height) +
-                entrance_footprint.wall_geometry(entrance_height),
-            brick_roof: Geometry::new(vec![], vec![]),
-            flat_roof: main_footprint.flat_roof_geometry(height) +
-                entrance_footprint.flat_roof_geometry(entrance_height),
+    let roof_style = match zone {
+        Zone::Commercial => RoofStyle::Flat,
+        Zone::Residential if n_floors >= 3 => RoofStyle::Flat,
+        Zone::Residential => {
+            if rng.next_f32() < 0.5 {
+                RoofStyle::Gable
+            } else {
+                RoofStyle::Hip
+            }
         }
-    } else {
-        let height = 3.0 + 3.0 * rng.next_f32();
-        let entrance_height = 2.0 + rng.next_f32();
+    };
+
+    let mut geometry = BuildingGeometry {
+        wall: main_footprint.wall_geometry(height),
+        brick_roof: Geometry::new(vec![], vec![]),
+        flat_roof: Geometry::new(vec![], vec![]),
+        windows: main_footprint.window_geometry(n_floors, zone),
+        lawn: Geometry::new(vec![], vec![]),
+    };
 
-        let (roof_brick_geometry, roof_wall_geometry) =
-            main_footprint.open_gable_roof_geometry(height, 0.3);
-        let (entrance_roof_brick_geometry, entrance_roof_wall_geometry) =
-            entrance_footprint.open_gable_roof_geometry(entrance_height, 0.3);
-
-        BuildingGeometry {
-            wall: main_footprint.wall_geometry(height) +
-                entrance_footprint.wall_geometry(entrance_height) +
-                roof_wall_geometry + entrance_roof_wall_geometry,
-            brick_roof: roof_brick_geometry + entrance_roof_brick_geometry,
-            flat_roof: Geometry::new(vec![], vec![]),
+    match roof_style {
+        RoofStyle::Flat => geometry.flat_roof += main_footprint.flat_roof_geometry(height),
+        RoofStyle::Gable => {
+            let (roof_brick_geometry, roof_wall_geometry) =
+                main_footprint.open_gable_roof_geometry(height, ROOF_ANGLE);
+            geometry.brick_roof += roof_brick_geometry;
+            geometry.wall += roof_wall_geometry;
+        }
+        RoofStyle::Hip => {
+            geometry.brick_roof += main_footprint.hip_roof_geometry(height, ROOF_ANGLE)
+        }
+    }
+
+    // small houses get an entrance annex, which has the same kind of roof
+    if let Some(entrance_footprint) = entrance_footprint {
+        let entrance_height = 2.0 + rng.next_f32();
+        geometry.wall += entrance_footprint.wall_geometry(entrance_height);
+        match roof_style {
+            RoofStyle::Flat => {
+                geometry.flat_roof += entrance_footprint.flat_roof_geometry(entrance_height)
+            }
+            RoofStyle::Gable | RoofStyle::Hip => {
+                let (roof_brick_geometry, roof_wall_geometry) =
+                    entrance_footprint.open_gable_roof_geometry(entrance_height, ROOF_ANGLE);
+                geometry.brick_roof += roof_brick_geometry;
+                geometry.wall += roof_wall_geometry;
+            }
         }
     }
+
+    geometry
+}
+
+/// Generates a park for `lot`: a lawn crossed by two footpaths, with trees
+/// scattered around them. Uses the same parts as buildings: trunks are drawn like
+/// brick roofs and footpaths like flat roofs
+pub fn build_park<R: Rng>(lot: &Lot, rng: &mut R) -> BuildingGeometry {
+    let orientation_orth = lot.orientation.orthogonal();
+    let half_width = (lot.frontage / 2.0 - LOT_MARGIN).max(3.0);
+    let half_depth = (lot.depth / 2.0 - LOT_MARGIN).max(3.0);
+    let rectangle = |half_width: N, half_depth: N, z: N| {
+        let corner = |along: N, across: N| {
+            let point = lot.position + lot.orientation * along * half_width +
+                orientation_orth * across * half_depth;
+            Vertex { position: [point.x, point.y, z] }
+        };
+        Geometry::new(
+            vec![corner(1.0, -1.0), corner(-1.0, -1.0), corner(-1.0, 1.0), corner(1.0, 1.0)],
+            vec![0, 1, 3, 1, 2, 3],
+        )
+    };
+
+    let mut geometry = BuildingGeometry {
+        wall: Geometry::new(vec![], vec![]),
+        brick_roof: Geometry::new(vec![], vec![]),
+        flat_roof: rectangle(FOOTPATH_WIDTH / 2.0, half_depth, 2.0 * LAWN_OFFSET) +
+            rectangle(half_width, FOOTPATH_WIDTH / 2.0, 2.0 * LAWN_OFFSET),
+        windows: Geometry::new(vec![], vec![]),
+        lawn: rectangle(half_width, half_depth, LAWN_OFFSET),
+    };
+
+    let n_trees = (4.0 * half_width * half_depth / AREA_PER_TREE).floor() as usize;
+    for _ in 0..n_trees {
+        // stay off the footpaths
+        let along = (0.2 + 0.7 * rng.next_f32()) * if rng.gen() { 1.0 } else { -1.0 };
+        let across = (0.2 + 0.7 * rng.next_f32()) * if rng.gen() { 1.0 } else { -1.0 };
+        let position = lot.position + lot.orientation * along * half_width +
+            orientation_orth * across * half_depth;
+        let crown_size = 1.5 + rng.next_f32();
+        let crown_height = 2.0 + 2.0 * rng.next_f32();
+        geometry.brick_roof += cuboid(position, 0.25, 0.0, TRUNK_HEIGHT);
+        geometry.lawn += cuboid(position, crown_size, TRUNK_HEIGHT, TRUNK_HEIGHT + crown_height);
+    }
+
+    geometry
+}
+
+/// Poles around the lot of a building under construction, as high as it is built
+pub fn build_scaffolding(lot: &Lot, height: N) -> Geometry {
+    let orientation_orth = lot.orientation.orthogonal();
+    let half_width = (lot.frontage / 2.0 - LOT_MARGIN / 2.0).max(3.0);
+    let half_depth = (lot.depth / 2.0 - LOT_MARGIN / 2.0).max(3.0);
+    let n_along = (2.0 * half_width / SCAFFOLDING_SPACING).ceil() as usize;
+    let n_across = (2.0 * half_depth / SCAFFOLDING_SPACING).ceil() as usize;
+    let pole = |along: N, across: N| {
+        let position = lot.position + lot.orientation * along * half_width +
+            orientation_orth * across * half_depth;
+        cuboid(position, SCAFFOLDING_POLE_SIZE, 0.0, height)
+    };
+
+    let front_and_back = (0..(n_along + 1)).flat_map(|i| {
+        let along = -1.0 + 2.0 * i as N / n_along as N;
+        vec![pole(along, -1.0), pole(along, 1.0)]
+    });
+    let sides = (1..n_across).flat_map(|j| {
+        let across = -1.0 + 2.0 * j as N / n_across as N;
+        vec![pole(-1.0, across), pole(1.0, across)]
+    });
+    front_and_back.chain(sides).sum()
+}
+
+/// An axis-aligned box with a square base, without a bottom
+fn cuboid(center: P2, half_size: N, bottom: N, top: N) -> Geometry {
+    let corners = [(1.0, 1.0), (-1.0, 1.0), (-1.0, -1.0), (1.0, -1.0)];
+    let vertices = [bottom, top]
+        .iter()
+        .flat_map(|&z| {
+            corners.iter().map(move |&(x, y)| {
+                Vertex { position: [center.x + x * half_size, center.y + y * half_size, z] }
+            })
+        })
+        .collect();
+
+    let mut indices = Vec::new();
+    for side in 0..4 {
+        let next = (side + 1) % 4;
+        indices.extend_from_slice(&[side, next, side + 4, next, next + 4, side + 4]);
+    }
+    indices.extend_from_slice(&[4, 5, 6, 6, 7, 4]);
+
+    Geometry::new(vertices, indices)
 }
 
 pub struct Footprint {
@@ -134,13 +311,131 @@ impl Footprint {
             Geometry::new(vertices, wall_indices),
         )
     }
+
+    fn hip_roof_geometry(&self, base_height: N, angle: N) -> Geometry {
+        let depth = (self.back_right - self.front_right).norm();
+        let width = (self.back_right - self.back_left).norm();
+        let roof_height = depth * angle.sin();
+        let mid_right = (self.back_right + self.front_right.to_vector()) / 2.0;
+        let mid_left = (self.back_left + self.front_left.to_vector()) / 2.0;
+        // the ridge ends are as far from the gable ends as from the eaves,
+        // so all four sides have the same slope
+        let inset = (depth / 2.0).min(width / 2.0) / width.max(1.0);
+        let ridge_right = mid_right + (mid_left - mid_right) * inset;
+        let ridge_left = mid_left + (mid_right - mid_left) * inset;
+
+        let vertices =
+            vec![
+                Vertex { position: [self.back_right.x, self.back_right.y, base_height] },
+                Vertex { position: [self.back_left.x, self.back_left.y, base_height] },
+                Vertex { position: [self.front_left.x, self.front_left.y, base_height] },
+                Vertex { position: [self.front_right.x, self.front_right.y, base_height] },
+                Vertex { position: [ridge_left.x, ridge_left.y, base_height + roof_height] },
+                Vertex { position: [ridge_right.x, ridge_right.y, base_height + roof_height] },
+            ];
+
+        let indices = vec![0, 1, 4, 4, 5, 0, 2, 3, 5, 5, 4, 2, 1, 2, 4, 3, 0, 5];
+
+        Geometry::new(vertices, indices)
+    }
+
+    /// A grid of windows on each facade, one row per floor. Commercial buildings have
+    /// shop windows on the ground floor
+    fn window_geometry(&self, n_floors: usize, zone: Zone) -> Geometry {
+        let corners = [self.back_right, self.back_left, self.front_left, self.front_right];
+        let center = P2::new(
+            corners.iter().map(|corner| corner.x).sum::<N>() / 4.0,
+            corners.iter().map(|corner| corner.y).sum::<N>() / 4.0,
+        );
+
+        let mut windows = Geometry::new(vec![], vec![]);
+
+        for side in 0..4 {
+            let (start, end) = (corners[side], corners[(side + 1) % 4]);
+            let length = (end - start).norm();
+            let n_windows = (length / WINDOW_SPACING).floor() as usize;
+            if n_windows == 0 {
+                continue;
+            }
+            let direction = (end - start) / length;
+            let outward = if direction.orthogonal().dot(&(start - center)) > 0.0 {
+                direction.orthogonal()
+            } else {
+                -direction.orthogonal()
+            };
+
+            for floor in 0..n_floors {
+                let (floor_base, sill_height, window_height, window_width) = match zone {
+                    Zone::Commercial if floor == 0 => {
+                        (0.0, SHOP_WINDOW_SILL_HEIGHT, SHOP_WINDOW_HEIGHT, WINDOW_SPACING * 0.8)
+                    }
+                    Zone::Commercial => {
+                        let base = SHOP_FLOOR_HEIGHT + (floor - 1) as N * FLOOR_HEIGHT;
+                        (base, WINDOW_SILL_HEIGHT, WINDOW_HEIGHT, WINDOW_WIDTH)
+                    }
+                    Zone::Residential => {
+                        let base = floor as N * FLOOR_HEIGHT;
+                        (base, WINDOW_SILL_HEIGHT, WINDOW_HEIGHT, WINDOW_WIDTH)
+                    }
+                };
+                let bottom = floor_base + sill_height;
+                let top = bottom + window_height;
+
+                for i in 0..n_windows {
+                    let along = (i as N + 0.5) * length / n_windows as N;
+                    let window_center = start + direction * along + outward * WINDOW_OFFSET;
+                    windows += window_quad(window_center, direction, window_width, bottom, top);
+                }
+            }
+        }
+
+        windows
+    }
 }
 
-pub fn generate_house_footprint<R: Rng>(lot: &Lot, rng: &mut R) -> (Footprint, Footprint) {
+fn window_quad(center: P2, direction: V2, width: N, bottom: N, top: N) -> Geometry {
+    let left = center - direction * width / 2.0;
+    let right = center + direction * width / 2.0;
+
+    let vertices = vec![
+        Vertex { position: [left.x, left.y, bottom] },
+        Vertex { position: [right.x, right.y, bottom] },
+        Vertex { position: [right.x, right.y, top] },
+        Vertex { position: [left.x, left.y, top] },
+    ];
+
+    Geometry::new(vertices, vec![0, 1, 2, 2, 3, 0])
+}
+
+/// The main footprint of a building on `lot`, covering more of the lot at higher growth
+/// levels, and for small buildings the footprint of an entrance annex
+pub fn generate_house_footprint<R: Rng>(
+    lot: &Lot,
+    growth_level: u8,
+    rng: &mut R,
+) -> (Footprint, Option<Footprint>) {
     let orientation_orth = lot.orientation.orthogonal();
+    let coverage = growth_level as N / MAX_GROWTH_LEVEL as N;
 
-    let footprint_width = 10.0 + rng.next_f32() * 7.0;
-    let footprint_depth = 7.0 + rng.next_f32() * 5.0;
+    let max_width = (lot.frontage - 2.0 * LOT_MARGIN).max(6.0);
+    let max_depth = (lot.depth - 2.0 * LOT_MARGIN).max(6.0);
+    let footprint_width = max_width * (0.5 + 0.4 * coverage + 0.1 * rng.next_f32()).min(1.0);
+    let footprint_depth = (7.0 + rng.next_f32() * 5.0 + 10.0 * coverage).min(max_depth);
+
+    let footprint = Footprint {
+        back_right: lot.position + lot.orientation * footprint_width / 2.0 -
+            orientation_orth * footprint_depth / 2.0,
+        back_left: lot.position - lot.orientation * footprint_width / 2.0 -
+            orientation_orth * footprint_depth / 2.0,
+        front_left: lot.position - lot.orientation * footprint_width / 2.0 +
+            orientation_orth * footprint_depth / 2.0,
+        front_right: lot.position + lot.orientation * footprint_width / 2.0 +
+            orientation_orth * footprint_depth / 2.0,
+    };
+
+    if growth_level > 2 {
+        return (footprint, None);
+    }
 
     let entrance_position = lot.position +
         lot.orientation * (0.5 - 1.0 * rng.next_f32()) * footprint_width -
@@ -149,17 +444,8 @@ pub fn generate_house_footprint<R: Rng>(lot: &Lot, rng: &mut R) -> (Footprint, F
     let entrance_depth = 3.0 + rng.next_f32() * 3.0;
 
     (
-        Footprint {
-            back_right: lot.position + lot.orientation * footprint_width / 2.0 -
-                orientation_orth * footprint_depth / 2.0,
-            back_left: lot.position - lot.orientation * footprint_width / 2.0 -
-                orientation_orth * footprint_depth / 2.0,
-            front_left: lot.position - lot.orientation * footprint_width / 2.0 +
-                orientation_orth * footprint_depth / 2.0,
-            front_right: lot.position + lot.orientation * footprint_width / 2.0 +
-                orientation_orth * footprint_depth / 2.0,
-        },
-        Footprint {
+        footprint,
+        Some(Footprint {
             back_right: entrance_position + orientation_orth * entrance_width / 2.0 +
                 lot.orientation * entrance_depth / 2.0,
             back_left: entrance_position - orientation_orth * entrance_width / 2.0 +
@@ -168,6 +454,6 @@ pub fn generate_house_footprint<R: Rng>(lot: &Lot, rng: &mut R) -> (Footprint, F
                 lot.orientation * entrance_depth / 2.0,
             front_right: entrance_position + orientation_orth * entrance_width / 2.0 -
                 lot.orientation * entrance_depth / 2.0,
-        },
+        }),
     )
 }
@@ -0,0 +1,455 @@
+//! Loading building meshes from glTF 2.0 files, either `.gltf` with external or embedded
+//! (base64) buffers, or binary `.glb`.
+//!
+//! Only what monet can render is read: the triangles of all meshes in the default scene,
+//! with the transforms of their nodes applied, and the base color of their materials.
+//! Textures, normals and animations are ignored. glTF models are Y-up with the front
+//! facing +Z, they are turned to be Z-up like the rest of the world, so the model's
+//! X axis ends up along the street and its front facing away from it.
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use serde_json;
+use monet::{Vertex, Geometry};
+
+const COMPONENT_U8: u32 = 5121;
+const COMPONENT_U16: u32 = 5123;
+const COMPONENT_U32: u32 = 5125;
+const COMPONENT_F32: u32 = 5126;
+const MODE_TRIANGLES: u32 = 4;
+const GLB_MAGIC: u32 = 0x4654_6C67;
+const GLB_CHUNK_JSON: u32 = 0x4E4F_534A;
+const GLB_CHUNK_BIN: u32 = 0x004E_4942;
+const DEFAULT_COLOR: [f32; 3] = [0.8, 0.8, 0.8];
+
+#[derive(Deserialize)]
+struct Document {
+    #[serde(default)]
+    buffers: Vec<Buffer>,
+    #[serde(default, rename = "bufferViews")]
+    buffer_views: Vec<BufferView>,
+    #[serde(default)]
+    accessors: Vec<Accessor>,
+    #[serde(default)]
+    meshes: Vec<Mesh>,
+    #[serde(default)]
+    materials: Vec<Material>,
+    #[serde(default)]
+    nodes: Vec<Node>,
+    #[serde(default)]
+    scenes: Vec<Scene>,
+    scene: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct Buffer {
+    uri: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct BufferView {
+    buffer: usize,
+    #[serde(default, rename = "byteOffset")]
+    byte_offset: usize,
+    #[serde(rename = "byteStride")]
+    byte_stride: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct Accessor {
+    #[serde(rename = "bufferView")]
+    buffer_view: Option<usize>,
+    #[serde(default, rename = "byteOffset")]
+    byte_offset: usize,
+    #[serde(rename = "componentType")]
+    component_type: u32,
+    count: usize,
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+#[derive(Deserialize)]
+struct Mesh {
+    primitives: Vec<Primitive>,
+}
+
+#[derive(Deserialize)]
+struct Primitive {
+    attributes: HashMap<String, usize>,
+    indices: Option<usize>,
+    material: Option<usize>,
+    mode: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct Material {
+    #[serde(rename = "pbrMetallicRoughness")]
+    pbr_metallic_roughness: Option<PbrMetallicRoughness>,
+}
+
+#[derive(Deserialize)]
+struct PbrMetallicRoughness {
+    #[serde(rename = "baseColorFactor")]
+    base_color_factor: Option<[f32; 4]>,
+}
+
+#[derive(Deserialize)]
+struct Node {
+    mesh: Option<usize>,
+    #[serde(default)]
+    children: Vec<usize>,
+    matrix: Option<[f32; 16]>,
+    translation: Option<[f32; 3]>,
+    rotation: Option<[f32; 4]>,
+    scale: Option<[f32; 3]>,
+}
+
+#[derive(Deserialize)]
+struct Scene {
+    #[serde(default)]
+    nodes: Vec<usize>,
+}
+
+/// The triangles of a model, one geometry per material color
+pub struct Model {
+    pub parts: Vec<(Geometry, [f32; 3])>,
+}
+
+/// Column-major, like in glTF
+type Matrix = [f32; 16];
+
+const IDENTITY: Matrix = [
+    1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, 1.0, 0.0,
+    0.0, 0.0, 0.0, 1.0,
+];
+
+fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+    let mut product = [0.0; 16];
+    for column in 0..4 {
+        for row in 0..4 {
+            product[column * 4 + row] = (0..4).map(|k| a[k * 4 + row] * b[column * 4 + k]).sum();
+        }
+    }
+    product
+}
+
+fn transform_point(m: &Matrix, point: [f32; 3]) -> [f32; 3] {
+    let (x, y, z) = (point[0], point[1], point[2]);
+    [
+        m[0] * x + m[4] * y + m[8] * z + m[12],
+        m[1] * x + m[5] * y + m[9] * z + m[13],
+        m[2] * x + m[6] * y + m[10] * z + m[14],
+    ]
+}
+
+fn local_matrix(node: &Node) -> Matrix {
+    if let Some(matrix) = node.matrix {
+        return matrix;
+    }
+    let t = node.translation.unwrap_or([0.0, 0.0, 0.0]);
+    let (x, y, z, w) = match node.rotation {
+        Some(r) => (r[0], r[1], r[2], r[3]),
+        None => (0.0, 0.0, 0.0, 1.0),
+    };
+    let s = node.scale.unwrap_or([1.0, 1.0, 1.0]);
+    // translation * rotation * scale
+    [
+        (1.0 - 2.0 * (y * y + z * z)) * s[0],
+        (2.0 * (x * y + z * w)) * s[0],
+        (2.0 * (x * z - y * w)) * s[0],
+        0.0,
+        (2.0 * (x * y - z * w)) * s[1],
+        (1.0 - 2.0 * (x * x + z * z)) * s[1],
+        (2.0 * (y * z + x * w)) * s[1],
+        0.0,
+        (2.0 * (x * z + y * w)) * s[2],
+        (2.0 * (y * z - x * w)) * s[2],
+        (1.0 - 2.0 * (x * x + y * y)) * s[2],
+        0.0,
+        t[0],
+        t[1],
+        t[2],
+        1.0,
+    ]
+}
+
+fn decode_base64(encoded: &str) -> Result<Vec<u8>, String> {
+    let value = |c: u8| match c {
+        b'A'...b'Z' => Ok(u32::from(c - b'A')),
+        b'a'...b'z' => Ok(u32::from(c - b'a') + 26),
+        b'0'...b'9' => Ok(u32::from(c - b'0') + 52),
+        b'+' => Ok(62),
+        b'/' => Ok(63),
+        _ => Err(format!("invalid base64 character {:?}", c as char)),
+    };
+    let symbols = encoded.bytes().filter(|&c| c != b'=' && !(c as char).is_whitespace());
+    let mut bytes = Vec::new();
+    let mut bits = 0u32;
+    let mut n_bits = 0;
+    for symbol in symbols {
+        bits = (bits << 6) | value(symbol)?;
+        n_bits += 6;
+        if n_bits >= 8 {
+            n_bits -= 8;
+            bytes.push((bits >> n_bits) as u8);
+            bits &= (1 << n_bits) - 1;
+        }
+    }
+    Ok(bytes)
+}
+
+fn read_u16(bytes: &[u8], at: usize) -> u16 {
+    u16::from(bytes[at]) | u16::from(bytes[at + 1]) << 8
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from(read_u16(bytes, at)) | u32::from(read_u16(bytes, at + 2)) << 16
+}
+
+fn load_buffers(
+    document: &Document,
+    dir: &Path,
+    binary_chunk: Option<Vec<u8>>,
+) -> Result<Vec<Vec<u8>>, String> {
+    let mut binary_chunk = binary_chunk;
+    document
+        .buffers
+        .iter()
+        .map(|buffer| match buffer.uri {
+            None => binary_chunk.take().ok_or_else(|| "buffer without data".to_owned()),
+            Some(ref uri) if uri.starts_with("data:") => {
+                match uri.find(";base64,") {
+                    Some(start) => decode_base64(&uri[(start + 8)..]),
+                    None => Err("embedded buffer isn't base64".to_owned()),
+                }
+            }
+            Some(ref uri) => {
+                let mut bytes = Vec::new();
+                File::open(dir.join(uri))
+                    .and_then(|mut file| file.read_to_end(&mut bytes))
+                    .map_err(|err| format!("{}: {}", uri, err))?;
+                Ok(bytes)
+            }
+        })
+        .collect()
+}
+
+struct Reader<'a> {
+    document: &'a Document,
+    buffers: &'a [Vec<u8>],
+}
+
+impl<'a> Reader<'a> {
+    /// The components of each element of an accessor, converted to `f32`
+    fn read(&self, accessor_idx: usize) -> Result<Vec<Vec<f32>>, String> {
+        let accessor = self.document.accessors.get(accessor_idx).ok_or(
+            "missing accessor",
+        )?;
+        let n_components = match accessor.kind.as_str() {
+            "SCALAR" => 1,
+            "VEC2" => 2,
+            "VEC3" => 3,
+            "VEC4" => 4,
+            other => return Err(format!("unsupported accessor type {}", other)),
+        };
+        let component_size = match accessor.component_type {
+            COMPONENT_U8 => 1,
+            COMPONENT_U16 => 2,
+            COMPONENT_U32 | COMPONENT_F32 => 4,
+            other => return Err(format!("unsupported component type {}", other)),
+        };
+        let view = accessor
+            .buffer_view
+            .and_then(|idx| self.document.buffer_views.get(idx))
+            .ok_or("accessor without buffer view")?;
+        let bytes = self.buffers.get(view.buffer).ok_or("missing buffer")?;
+        let stride = view.byte_stride.unwrap_or(n_components * component_size);
+        let start = view.byte_offset + accessor.byte_offset;
+
+        let needed = if accessor.count == 0 {
+            0
+        } else {
+            start + (accessor.count - 1) * stride + n_components * component_size
+        };
+        if needed > bytes.len() {
+            return Err("accessor reaches beyond its buffer".to_owned());
+        }
+
+        Ok(
+            (0..accessor.count)
+                .map(|i| {
+                    (0..n_components)
+                        .map(|c| {
+                            let at = start + i * stride + c * component_size;
+                            match accessor.component_type {
+                                COMPONENT_U8 => f32::from(bytes[at]),
+                                COMPONENT_U16 => f32::from(read_u16(bytes, at)),
+                                COMPONENT_U32 => read_u32(bytes, at) as f32,
+                                _ => f32::from_bits(read_u32(bytes, at)),
+                            }
+                        })
+                        .collect()
+                })
+                .collect(),
+        )
+    }
+
+    fn color(&self, material: Option<usize>) -> [f32; 3] {
+        material
+            .and_then(|idx| self.document.materials.get(idx))
+            .and_then(|material| material.pbr_metallic_roughness.as_ref())
+            .and_then(|pbr| pbr.base_color_factor)
+            .map(|rgba| [rgba[0], rgba[1], rgba[2]])
+            .unwrap_or(DEFAULT_COLOR)
+    }
+
+    fn add_node(
+        &self,
+        node_idx: usize,
+        parent: &Matrix,
+        depth: usize,
+        parts: &mut Vec<(Geometry, [f32; 3])>,
+    ) -> Result<(), String> {
+        if depth > self.document.nodes.len() {
+            return Err("nodes form a cycle".to_owned());
+        }
+        let node = self.document.nodes.get(node_idx).ok_or("missing node")?;
+        let transform = multiply(parent, &local_matrix(node));
+
+        if let Some(mesh_idx) = node.mesh {
+            let mesh = self.document.meshes.get(mesh_idx).ok_or("missing mesh")?;
+            for primitive in &mesh.primitives {
+                if primitive.mode.unwrap_or(MODE_TRIANGLES) != MODE_TRIANGLES {
+                    continue;
+                }
+                let geometry = self.primitive_geometry(primitive, &transform)?;
+                let color = self.color(primitive.material);
+                match parts.iter().position(|&(_, part_color)| part_color == color) {
+                    Some(idx) => {
+                        if parts[idx].0.vertices.len() + geometry.vertices.len() >
+                            u16::max_value() as usize
+                        {
+                            return Err("too many vertices".to_owned());
+                        }
+                        parts[idx].0 += geometry;
+                    }
+                    None => parts.push((geometry, color)),
+                }
+            }
+        }
+
+        for &child in &node.children {
+            self.add_node(child, &transform, depth + 1, parts)?;
+        }
+        Ok(())
+    }
+
+    fn primitive_geometry(
+        &self,
+        primitive: &Primitive,
+        transform: &Matrix,
+    ) -> Result<Geometry, String> {
+        let position_idx = *primitive.attributes.get("POSITION").ok_or(
+            "primitive without positions",
+        )?;
+        let positions = self.read(position_idx)?;
+        if positions.len() > u16::max_value() as usize {
+            return Err("too many vertices".to_owned());
+        }
+        if positions.iter().any(|position| position.len() < 3) {
+            return Err("positions aren't 3D".to_owned());
+        }
+
+        let vertices = positions
+            .iter()
+            .map(|position| {
+                let point = transform_point(transform, [position[0], position[1], position[2]]);
+                // Y-up to Z-up
+                Vertex { position: [point[0], -point[2], point[1]] }
+            })
+            .collect::<Vec<_>>();
+
+        let indices = match primitive.indices {
+            Some(indices_idx) => {
+                self.read(indices_idx)?
+                    .iter()
+                    .map(|index| index[0] as usize)
+                    .collect::<Vec<_>>()
+            }
+            None => (0..vertices.len()).collect(),
+        };
+        if indices.iter().any(|&index| index >= vertices.len()) {
+            return Err("index out of range".to_owned());
+        }
+
+        Ok(Geometry::new(
+            vertices,
+            indices.into_iter().map(|index| index as u16).collect(),
+        ))
+    }
+}
+
+fn parse(json: &[u8], dir: &Path, binary_chunk: Option<Vec<u8>>) -> Result<Model, String> {
+    let document: Document = serde_json::from_slice(json).map_err(|err| format!("{}", err))?;
+    let buffers = load_buffers(&document, dir, binary_chunk)?;
+    let reader = Reader { document: &document, buffers: &buffers };
+
+    let root_nodes = match document.scenes.get(document.scene.unwrap_or(0)) {
+        Some(scene) => scene.nodes.clone(),
+        // without scenes, all nodes that aren't children are roots
+        None => {
+            (0..document.nodes.len())
+                .filter(|idx| !document.nodes.iter().any(|node| node.children.contains(idx)))
+                .collect()
+        }
+    };
+
+    let mut parts = Vec::new();
+    for node_idx in root_nodes {
+        reader.add_node(node_idx, &IDENTITY, 0, &mut parts)?;
+    }
+    if parts.is_empty() {
+        return Err("no triangles".to_owned());
+    }
+    Ok(Model { parts })
+}
+
+/// The chunks of a binary glTF file: the JSON and the binary buffer, if any
+fn split_glb(bytes: &[u8]) -> Result<(&[u8], Option<Vec<u8>>), String> {
+    if bytes.len() < 12 || read_u32(bytes, 0) != GLB_MAGIC {
+        return Err("not a binary glTF file".to_owned());
+    }
+    let mut json = None;
+    let mut binary = None;
+    let mut at = 12;
+    while at + 8 <= bytes.len() {
+        let length = read_u32(bytes, at) as usize;
+        let kind = read_u32(bytes, at + 4);
+        let chunk = bytes.get((at + 8)..(at + 8 + length)).ok_or("truncated chunk")?;
+        match kind {
+            GLB_CHUNK_JSON => json = Some(chunk),
+            GLB_CHUNK_BIN => binary = Some(chunk.to_vec()),
+            _ => {}
+        }
+        at += 8 + length;
+    }
+    Ok((json.ok_or("no JSON chunk")?, binary))
+}
+
+pub fn load(path: &Path) -> Result<Model, String> {
+    let mut bytes = Vec::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_end(&mut bytes))
+        .map_err(|err| format!("{}", err))?;
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+
+    if path.extension().map_or(false, |extension| extension == "glb") {
+        let (json, binary) = split_glb(&bytes)?;
+        parse(json, dir, binary)
+    } else {
+        parse(&bytes, dir, None)
+    }
+}
@@ -1,17 +1,22 @@
-use descartes::Circle;
+use descartes::{Circle, P2, P3, V2, Norm};
 use compact::CVec;
 use kay::{ActorSystem, World, External};
 use monet::{RendererID, Renderable, RenderableID, MSG_Renderable_setup_in_scene,
-            MSG_Renderable_render_to_scene, GrouperID, GrouperIndividualID};
+            MSG_Renderable_render_to_scene, GrouperID, GrouperIndividual, GrouperIndividualID,
+            MSG_GrouperIndividual_render_to_grouper, Instance, Eye, Movement, EyeListener,
+            EyeListenerID, MSG_EyeListener_eye_moved, Geometry, Animation, Decal, DecalPattern,
+            frame_progress};
 use stagemaster::geometry::AnyShape;
 use stagemaster::{UserInterfaceID, Event3d, Interactable3d, Interactable3dID, Interactable2d,
                   Interactable2dID, MSG_Interactable3d_on_event, MSG_Interactable2d_draw_ui_2d};
 use imgui::ImGuiSetCond_FirstUseEver;
 
-use super::{Building, BuildingID};
+use super::{Building, BuildingID, Lot};
 use economy::households::HouseholdID;
 
 mod architecture;
+mod gltf;
+pub mod models;
 
 #[derive(Compact, Clone)]
 pub struct BuildingInspector {
@@ -51,6 +56,15 @@ impl BuildingInspector {
         self.user_interface.add_2d(self.id.into(), world);
     }
 
+    pub fn on_building_demolished(&mut self, building: BuildingID, world: &mut World) {
+        if self.current_building == Some(building) {
+            self.current_building = None;
+            self.current_households.clear();
+            self.households_todo.clear();
+            self.user_interface.remove_2d(self.id.into(), world);
+        }
+    }
+
     pub fn ui_drawn(&mut self, imgui_ui: &External<::imgui::Ui<'static>>, world: &mut World) {
         let ui = imgui_ui.steal();
 
@@ -112,50 +126,324 @@ impl Interactable3d for Building {
     }
 }
 
+impl GrouperIndividual for Building {
+    /// The geometry of buildings is added to the groupers frozen right away (see
+    /// `BuildingRenderer::add_geometry`), so groupers never ask for it to be rendered again
+    fn render_to_grouper(&mut self, _: GrouperID, _: u16, _: &mut World) {}
+}
+
+const WALL_COLOR: [f32; 3] = [0.95, 0.95, 0.95];
+const FLAT_ROOF_COLOR: [f32; 3] = [0.5, 0.5, 0.5];
+const BRICK_ROOF_COLOR: [f32; 3] = [0.8, 0.5, 0.2];
+const WINDOW_COLOR: [f32; 3] = [0.3, 0.35, 0.45];
+const LAWN_COLOR: [f32; 3] = [0.45, 0.65, 0.3];
+const SCAFFOLDING_COLOR: [f32; 3] = [0.6, 0.5, 0.35];
+const DUST_COLOR: [f32; 3] = [0.7, 0.65, 0.55];
+
+/// Buildings being animated are drawn as individuals with these ids, a few per slot
+const ANIMATION_INDIVIDUAL_ID: u16 = 5600;
+const N_ANIMATION_SLOTS: usize = 32;
+/// The parts of `BuildingGeometry` and scaffolding
+const PARTS_PER_ANIMATION: usize = 6;
+/// Demolished buildings are gone already, so their collapse takes a fixed time
+const DEMOLITION_FRAMES: usize = 120;
+const DUST_DECAL_LAYER: u16 = 500;
+/// Scaffolding reaches this far above what is built already
+const SCAFFOLDING_HEADROOM: f32 = 1.5;
+
+/// A building rising during its construction or collapsing after its demolition.
+/// While animated, it is drawn from its own individuals instead of the groupers
+#[derive(Compact, Clone)]
+pub struct BuildingAnimation {
+    building: BuildingID,
+    animation: Animation,
+    geometry: architecture::BuildingGeometry,
+    lot: Lot,
+    progress: f32,
+    rendered_progress: f32,
+    /// Collapses are timed from the frame they are first drawn in
+    started_in_frame: Option<usize>,
+    slot: usize,
+}
+
+impl BuildingAnimation {
+    fn individual_id(&self, part: usize) -> u16 {
+        ANIMATION_INDIVIDUAL_ID + (self.slot * PARTS_PER_ANIMATION + part) as u16
+    }
+
+    fn render(&self, renderer_id: RendererID, scene_id: usize, world: &mut World) {
+        let parts = [
+            (&self.geometry.wall, WALL_COLOR),
+            (&self.geometry.flat_roof, FLAT_ROOF_COLOR),
+            (&self.geometry.brick_roof, BRICK_ROOF_COLOR),
+            (&self.geometry.windows, WINDOW_COLOR),
+            (&self.geometry.lawn, LAWN_COLOR),
+        ];
+        for (part, &(geometry, color)) in parts.iter().enumerate() {
+            renderer_id.update_individual(
+                scene_id,
+                self.individual_id(part),
+                self.animation.apply(geometry, self.progress),
+                Instance::with_color(color),
+                false,
+                world,
+            );
+        }
+
+        match self.animation {
+            Animation::Rise => {
+                let built_height = self.geometry
+                    .wall
+                    .vertices
+                    .iter()
+                    .map(|vertex| vertex.position[2])
+                    .fold(0.0, f32::max) * self.progress;
+                renderer_id.update_individual(
+                    scene_id,
+                    self.individual_id(PARTS_PER_ANIMATION - 1),
+                    architecture::build_scaffolding(
+                        &self.lot,
+                        built_height + SCAFFOLDING_HEADROOM,
+                    ),
+                    Instance::with_color(SCAFFOLDING_COLOR),
+                    false,
+                    world,
+                );
+            }
+            Animation::Collapse => {
+                let radius = self.lot.frontage.max(self.lot.depth) / 2.0;
+                let dust = Decal::between(
+                    self.lot.position - self.lot.orientation * radius,
+                    self.lot.position + self.lot.orientation * radius,
+                    2.0 * radius,
+                    DUST_COLOR,
+                ).with_pattern(DecalPattern::Speckles { density: 0.6 })
+                    .with_opacity(1.0 - self.progress);
+                renderer_id.set_decals(
+                    scene_id,
+                    DUST_DECAL_LAYER,
+                    self.building._raw_id.instance_id,
+                    vec![dust].into(),
+                    world,
+                );
+            }
+        }
+    }
+
+    fn clear(&self, renderer_id: RendererID, scene_id: usize, world: &mut World) {
+        for part in 0..PARTS_PER_ANIMATION {
+            renderer_id.update_individual(
+                scene_id,
+                self.individual_id(part),
+                Geometry::new(vec![], vec![]),
+                Instance::with_color([0.0, 0.0, 0.0]),
+                false,
+                world,
+            );
+        }
+        if self.animation == Animation::Collapse {
+            renderer_id.set_decals(
+                scene_id,
+                DUST_DECAL_LAYER,
+                self.building._raw_id.instance_id,
+                CVec::new(),
+                world,
+            );
+        }
+    }
+}
+
+/// A building drawn with a model from a package, see `models`
+#[derive(Copy, Clone)]
+pub struct ModelInstance {
+    building: BuildingID,
+    model: usize,
+    position: P2,
+    direction: V2,
+}
+
 #[derive(Compact, Clone)]
 pub struct BuildingRenderer {
     id: BuildingRendererID,
     wall_grouper: GrouperID,
     flat_roof_grouper: GrouperID,
     brick_roof_grouper: GrouperID,
+    window_grouper: GrouperID,
+    lawn_grouper: GrouperID,
+    model_instances: CVec<ModelInstance>,
+    animations: CVec<BuildingAnimation>,
+    eye_position: P3,
 }
 
 impl BuildingRenderer {
     pub fn spawn(id: BuildingRendererID, world: &mut World) -> BuildingRenderer {
         BuildingRenderer {
             id,
-            wall_grouper: GrouperID::spawn([0.95, 0.95, 0.95], 5000, false, world),
-            flat_roof_grouper: GrouperID::spawn([0.5, 0.5, 0.5], 5100, false, world),
-            brick_roof_grouper: GrouperID::spawn([0.8, 0.5, 0.2], 5200, false, world),
+            wall_grouper: GrouperID::spawn(WALL_COLOR, 5000, false, world),
+            flat_roof_grouper: GrouperID::spawn(FLAT_ROOF_COLOR, 5100, false, world),
+            brick_roof_grouper: GrouperID::spawn(BRICK_ROOF_COLOR, 5200, false, world),
+            window_grouper: GrouperID::spawn(WINDOW_COLOR, 5300, false, world),
+            lawn_grouper: GrouperID::spawn(LAWN_COLOR, 5400, false, world),
+            model_instances: CVec::new(),
+            animations: CVec::new(),
+            eye_position: P3::new(0.0, 0.0, 0.0),
+        }
+    }
+
+    pub fn remove_geometry(&mut self, id: BuildingID, world: &mut World) {
+        let as_individual: GrouperIndividualID = id.into();
+        self.wall_grouper.remove(as_individual, world);
+        self.flat_roof_grouper.remove(as_individual, world);
+        self.brick_roof_grouper.remove(as_individual, world);
+        self.window_grouper.remove(as_individual, world);
+        self.lawn_grouper.remove(as_individual, world);
+        self.model_instances.retain(|instance| instance.building != id);
+
+        let renderer_id = RendererID::local_first(world);
+        for animation in self.animations.iter().filter(|animation| {
+            animation.building == id && animation.animation == Animation::Rise
+        })
+        {
+            animation.clear(renderer_id, 0, world);
+        }
+        self.animations.retain(|animation| {
+            animation.building != id || animation.animation != Animation::Rise
+        });
+    }
+
+    fn free_animation_slot(&self) -> Option<usize> {
+        (0..N_ANIMATION_SLOTS).find(|&slot| {
+            !self.animations.iter().any(|animation| animation.slot == slot)
+        })
+    }
+
+    /// Lets the building rise as its construction progresses, or adds it right away
+    /// if too many buildings are animated already
+    pub fn animate_construction(
+        &mut self,
+        id: BuildingID,
+        geometry: &architecture::BuildingGeometry,
+        lot: &Lot,
+        progress: f32,
+        world: &mut World,
+    ) {
+        if let Some(slot) = self.free_animation_slot() {
+            self.animations.push(BuildingAnimation {
+                building: id,
+                animation: Animation::Rise,
+                geometry: geometry.clone(),
+                lot: lot.clone(),
+                progress,
+                rendered_progress: -1.0,
+                started_in_frame: None,
+                slot,
+            });
+        } else {
+            self.add_geometry(id, geometry, world);
         }
     }
 
+    pub fn on_construction_progress(&mut self, id: BuildingID, progress: f32, _: &mut World) {
+        for animation in self.animations.iter_mut().filter(|animation| {
+            animation.building == id && animation.animation == Animation::Rise
+        })
+        {
+            animation.progress = progress;
+        }
+    }
+
+    /// Lets a demolished building collapse in a cloud of dust
+    pub fn animate_demolition(
+        &mut self,
+        id: BuildingID,
+        geometry: &architecture::BuildingGeometry,
+        lot: &Lot,
+        _: &mut World,
+    ) {
+        if let Some(slot) = self.free_animation_slot() {
+            self.animations.push(BuildingAnimation {
+                building: id,
+                animation: Animation::Collapse,
+                geometry: geometry.clone(),
+                lot: lot.clone(),
+                progress: 0.0,
+                rendered_progress: -1.0,
+                started_in_frame: None,
+                slot,
+            });
+        }
+    }
+
+    fn render_animations(
+        &mut self,
+        renderer_id: RendererID,
+        scene_id: usize,
+        frame: usize,
+        world: &mut World,
+    ) {
+        for animation in self.animations.iter_mut() {
+            if animation.animation == Animation::Collapse {
+                let start_frame = animation.started_in_frame.unwrap_or(frame);
+                animation.started_in_frame = Some(start_frame);
+                animation.progress = frame_progress(start_frame, frame, DEMOLITION_FRAMES);
+            }
+            if Animation::changed_noticeably(animation.rendered_progress, animation.progress) {
+                animation.render(renderer_id, scene_id, world);
+                animation.rendered_progress = animation.progress;
+            }
+        }
+
+        let finished = self.animations
+            .iter()
+            .filter(|animation| animation.rendered_progress >= 1.0)
+            .cloned()
+            .collect::<Vec<_>>();
+        self.animations.retain(|animation| animation.rendered_progress < 1.0);
+        for animation in finished {
+            animation.clear(renderer_id, scene_id, world);
+            if animation.animation == Animation::Rise {
+                self.add_geometry(animation.building, &animation.geometry, world);
+            }
+        }
+    }
+
+    pub fn add_model_instance(
+        &mut self,
+        id: BuildingID,
+        model: usize,
+        position: P2,
+        direction: V2,
+        _: &mut World,
+    ) {
+        self.model_instances.push(ModelInstance { building: id, model, position, direction });
+    }
+
+    /// The level of detail of buildings at `position`, given how far they are from the eye
+    fn lod_at(&self, position: P2) -> usize {
+        let distance = (P3::new(position.x, position.y, 0.0) - self.eye_position).norm();
+        models::LOD_DISTANCES
+            .iter()
+            .position(|&lod_distance| distance < lod_distance)
+            .unwrap_or(models::LOD_DISTANCES.len())
+    }
+
     pub fn add_geometry(
         &mut self,
         id: BuildingID,
         geometry: &architecture::BuildingGeometry,
         world: &mut World,
     ) {
-        // TODO: ugly: Building is not really a GrouperIndividual
-        self.wall_grouper.add_frozen(
-            GrouperIndividualID { _raw_id: id._raw_id },
-            geometry.wall.clone(),
-            world,
-        );
-        self.flat_roof_grouper.add_frozen(
-            GrouperIndividualID { _raw_id: id._raw_id },
-            geometry.flat_roof.clone(),
-            world,
-        );
-        self.brick_roof_grouper.add_frozen(
-            GrouperIndividualID { _raw_id: id._raw_id },
-            geometry.brick_roof.clone(),
-            world,
-        );
+        let as_individual: GrouperIndividualID = id.into();
+        self.wall_grouper.add_frozen(as_individual, geometry.wall.clone(), world);
+        self.flat_roof_grouper.add_frozen(as_individual, geometry.flat_roof.clone(), world);
+        self.brick_roof_grouper.add_frozen(as_individual, geometry.brick_roof.clone(), world);
+        self.window_grouper.add_frozen(as_individual, geometry.windows.clone(), world);
+        self.lawn_grouper.add_frozen(as_individual, geometry.lawn.clone(), world);
     }
 }
 
 use economy::households::grocery_shop::GroceryShopID;
+use economy::households::park::ParkID;
 
 impl Renderable for BuildingRenderer {
     fn setup_in_scene(&mut self, renderer_id: RendererID, scene_id: usize, world: &mut World) {
@@ -178,6 +466,17 @@ impl Renderable for BuildingRenderer {
             .setup_in_scene(renderer_id, scene_id, world);
         Into::<RenderableID>::into(self.brick_roof_grouper)
             .setup_in_scene(renderer_id, scene_id, world);
+        Into::<RenderableID>::into(self.window_grouper)
+            .setup_in_scene(renderer_id, scene_id, world);
+        Into::<RenderableID>::into(self.lawn_grouper)
+            .setup_in_scene(renderer_id, scene_id, world);
+
+        for model in models::all_models() {
+            for part in model.lods.iter().flat_map(|parts| parts.iter()) {
+                renderer_id.add_batch(scene_id, part.batch_id, part.geometry.clone(), world);
+            }
+        }
+        renderer_id.add_eye_listener(scene_id, self.id.into(), world);
     }
 
     fn render_to_scene(
@@ -195,6 +494,48 @@ impl Renderable for BuildingRenderer {
             .render_to_scene(renderer_id, scene_id, frame, world);
         Into::<RenderableID>::into(self.brick_roof_grouper)
             .render_to_scene(renderer_id, scene_id, frame, world);
+        Into::<RenderableID>::into(self.window_grouper)
+            .render_to_scene(renderer_id, scene_id, frame, world);
+        Into::<RenderableID>::into(self.lawn_grouper)
+            .render_to_scene(renderer_id, scene_id, frame, world);
+        self.render_animations(renderer_id, scene_id, frame, world);
+
+        for (model_idx, model) in models::all_models().iter().enumerate() {
+            for (lod, parts) in model.lods.iter().enumerate() {
+                let placements = self.model_instances
+                    .iter()
+                    .filter(|instance| {
+                        instance.model == model_idx && self.lod_at(instance.position) == lod
+                    })
+                    .collect::<Vec<_>>();
+                // also sent when empty, to clear the instances of the last frame
+                for part in parts {
+                    let instances = placements
+                        .iter()
+                        .map(|instance| {
+                            Instance {
+                                instance_position: [instance.position.x, instance.position.y, 0.0],
+                                instance_direction: [instance.direction.x, instance.direction.y],
+                                instance_color: part.color,
+                            }
+                        })
+                        .collect();
+                    renderer_id.add_several_instances(
+                        scene_id,
+                        part.batch_id,
+                        frame,
+                        instances,
+                        world,
+                    );
+                }
+            }
+        }
+    }
+}
+
+impl EyeListener for BuildingRenderer {
+    fn eye_moved(&mut self, eye: Eye, _movement: Movement, _: &mut World) {
+        self.eye_position = eye.position;
     }
 }
 
@@ -230,16 +571,18 @@ impl Renderable for BuildingRenderer {
 // }
 
 pub fn setup(system: &mut ActorSystem, user_interface: UserInterfaceID) -> BuildingRendererID {
+    models::setup();
     system.register::<BuildingInspector>();
     system.register::<BuildingRenderer>();
     auto_setup(system);
+    system.prioritize::<MSG_BuildingInspector_ui_drawn>();
 
     BuildingInspectorID::spawn(user_interface, &mut system.world());
 
     BuildingRendererID::spawn(&mut system.world())
 }
 
-use rand::{XorShiftRng, SeedableRng};
+use rand::{Rng, XorShiftRng, SeedableRng};
 
 pub fn on_add(building: &Building, world: &mut World) {
     // TODO: not sure if correct
@@ -253,25 +596,106 @@ pub fn on_add(building: &Building, world: &mut World) {
         world,
     );
 
+    add_building_geometry(building, world);
+}
+
+/// Replaces the geometry of a building whose growth level changed
+pub fn on_regrow(building: &Building, world: &mut World) {
+    BuildingRendererID::local_first(world).remove_geometry(building.id, world);
+    add_building_geometry(building, world);
+}
+
+/// How a building looks: either generated geometry or a model from a package
+enum BuildingLook {
+    Generated(architecture::BuildingGeometry),
+    Model(usize),
+}
+
+fn add_building_geometry(building: &Building, world: &mut World) {
+    match building_look(building, world) {
+        BuildingLook::Generated(geometry) => {
+            if building.construction_progress < 1.0 {
+                BuildingRendererID::local_first(world).animate_construction(
+                    building.id,
+                    geometry,
+                    building.lot.clone(),
+                    building.construction_progress,
+                    world,
+                );
+            } else {
+                BuildingRendererID::local_first(world).add_geometry(building.id, geometry, world);
+            }
+        }
+        // models appear when the building is finished
+        BuildingLook::Model(model) => {
+            BuildingRendererID::local_first(world).add_model_instance(
+                building.id,
+                model,
+                building.lot.position,
+                building.lot.orientation,
+                world,
+            )
+        }
+    }
+}
+
+pub fn on_construction_progress(building: &Building, world: &mut World) {
+    BuildingRendererID::local_first(world).on_construction_progress(
+        building.id,
+        building.construction_progress,
+        world,
+    );
+}
+
+fn building_look(building: &Building, world: &mut World) -> BuildingLook {
     // TODO: this is super hacky
     let is_shop = building.households[0]._raw_id.local_broadcast() ==
         GroceryShopID::local_broadcast(world)._raw_id;
-    BuildingRendererID::local_first(world).add_geometry(
-        building.id,
-        architecture::build_building(
+    let is_park = building.households[0]._raw_id.local_broadcast() ==
+        ParkID::local_broadcast(world)._raw_id;
+    let mut rng = XorShiftRng::from_seed(
+        [
+            building.id._raw_id.instance_id * 1000,
+            u32::from(building.id._raw_id.machine),
+            building.id._raw_id.instance_id,
+            42,
+        ],
+    );
+
+    let available_models = models::models_for(if is_shop { "shop" } else { "house" });
+    if is_park {
+        BuildingLook::Generated(architecture::build_park(&building.lot, &mut rng))
+    } else if available_models.is_empty() {
+        let zone = if is_shop {
+            architecture::Zone::Commercial
+        } else {
+            architecture::Zone::Residential
+        };
+        BuildingLook::Generated(architecture::build_building(
             &building.lot,
-            is_shop,
-            &mut XorShiftRng::from_seed(
-                [
-                    building.id._raw_id.instance_id * 1000,
-                    u32::from(building.id._raw_id.machine),
-                    building.id._raw_id.instance_id,
-                    42,
-                ],
-            ),
-        ),
-        world,
-    )
+            zone,
+            building.growth_level,
+            &mut rng,
+        ))
+    } else {
+        BuildingLook::Model(available_models[rng.gen_range(0, available_models.len())])
+    }
+}
+
+pub fn on_demolish(building: &Building, world: &mut World) {
+    UserInterfaceID::local_first(world).remove(building.id.into(), world);
+    BuildingRendererID::local_first(world).remove_geometry(building.id, world);
+    if !building.households.is_empty() {
+        if let BuildingLook::Generated(geometry) = building_look(building, world) {
+            BuildingRendererID::local_first(world).animate_demolition(
+                building.id,
+                geometry,
+                building.lot.clone(),
+                world,
+            );
+        }
+    }
+    BuildingInspectorID::local_first(world).on_building_demolished(building.id, world);
 }
 
 mod kay_auto;
@@ -0,0 +1,217 @@
+//! Building models that packages bring along (see `core::packages`), used instead of the
+//! generated architecture for the kinds of buildings they are made for.
+//!
+//! A package provides models as `assets/buildings/<kind>/<model>.gltf` (or `.glb`), where
+//! the kind is `house` or `shop`. Lower levels of detail for far away buildings can be
+//! provided as `<model>.lod1.gltf` and `<model>.lod2.gltf`, otherwise they are generated
+//! by merging nearby vertices of the detailed model.
+use std::collections::HashMap;
+use std::fs::read_dir;
+use std::path::{Path, PathBuf};
+use descartes::N;
+use monet::{Vertex, Geometry};
+use super::gltf;
+
+/// Buildings further away from the eye than these distances use the next level of detail
+pub const LOD_DISTANCES: [N; 2] = [150.0, 400.0];
+pub const N_LODS: usize = 3;
+/// Generated levels of detail merge vertices closer than this share of the model's size
+const LOD_MERGE_SHARE: [f32; N_LODS] = [0.0, 0.05, 0.15];
+const FIRST_BATCH_ID: u16 = 12_000;
+
+pub struct ModelPart {
+    pub batch_id: u16,
+    pub geometry: Geometry,
+    pub color: [f32; 3],
+}
+
+pub struct BuildingModel {
+    pub name: String,
+    pub kind: String,
+    /// The parts for each level of detail, most detailed first
+    pub lods: Vec<Vec<ModelPart>>,
+}
+
+/// Merges all vertices within the same cube of `cell_size` into one at their average,
+/// dropping triangles that collapse
+pub fn simplify(geometry: &Geometry, cell_size: f32) -> Geometry {
+    if cell_size <= 0.0 {
+        return geometry.clone();
+    }
+    let cell_of = |vertex: &Vertex| {
+        (
+            (vertex.position[0] / cell_size).floor() as i32,
+            (vertex.position[1] / cell_size).floor() as i32,
+            (vertex.position[2] / cell_size).floor() as i32,
+        )
+    };
+
+    let mut cell_indices = HashMap::new();
+    let mut sums = Vec::<([f32; 3], usize)>::new();
+    let remapped = geometry
+        .vertices
+        .iter()
+        .map(|vertex| {
+            let next_index = sums.len();
+            let index = *cell_indices.entry(cell_of(vertex)).or_insert(next_index);
+            if index == sums.len() {
+                sums.push(([0.0; 3], 0));
+            }
+            for axis in 0..3 {
+                sums[index].0[axis] += vertex.position[axis];
+            }
+            sums[index].1 += 1;
+            index as u16
+        })
+        .collect::<Vec<_>>();
+
+    let vertices = sums.iter()
+        .map(|&(sum, n)| {
+            Vertex { position: [sum[0] / n as f32, sum[1] / n as f32, sum[2] / n as f32] }
+        })
+        .collect();
+
+    let mut indices = Vec::new();
+    for triangle in geometry.indices.chunks(3) {
+        if triangle.len() < 3 {
+            continue;
+        }
+        let (a, b, c) = (
+            remapped[triangle[0] as usize],
+            remapped[triangle[1] as usize],
+            remapped[triangle[2] as usize],
+        );
+        if a != b && b != c && c != a {
+            indices.extend_from_slice(&[a, b, c]);
+        }
+    }
+
+    Geometry::new(vertices, indices)
+}
+
+fn size_of(model: &gltf::Model) -> f32 {
+    let mut min = [::std::f32::INFINITY; 3];
+    let mut max = [::std::f32::NEG_INFINITY; 3];
+    for &(ref geometry, _) in &model.parts {
+        for vertex in geometry.vertices.iter() {
+            for axis in 0..3 {
+                min[axis] = min[axis].min(vertex.position[axis]);
+                max[axis] = max[axis].max(vertex.position[axis]);
+            }
+        }
+    }
+    (0..3).map(|axis| (max[axis] - min[axis]).max(0.0)).fold(0.0, f32::max)
+}
+
+fn lod_path(path: &Path, lod: usize) -> PathBuf {
+    let stem = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("");
+    let extension = path.extension().and_then(|extension| extension.to_str()).unwrap_or("");
+    path.with_file_name(format!("{}.lod{}.{}", stem, lod, extension))
+}
+
+fn is_model_file(path: &Path) -> bool {
+    let name = path.file_name().and_then(|name| name.to_str()).unwrap_or("");
+    (name.ends_with(".gltf") || name.ends_with(".glb")) && !name.contains(".lod")
+}
+
+/// Loads a model and its levels of detail, assigning batch ids from `next_batch_id` on
+fn load_model(path: &Path, kind: &str, next_batch_id: &mut u16) -> Result<BuildingModel, String> {
+    let detailed = gltf::load(path)?;
+    let size = size_of(&detailed);
+
+    let mut lods = Vec::new();
+    for lod in 0..N_LODS {
+        let explicit = if lod == 0 {
+            None
+        } else {
+            let path = lod_path(path, lod);
+            if path.exists() {
+                Some(gltf::load(&path)?)
+            } else {
+                None
+            }
+        };
+        let parts = match explicit {
+            Some(model) => model.parts,
+            None => {
+                detailed
+                    .parts
+                    .iter()
+                    .map(|&(ref geometry, color)| {
+                        (simplify(geometry, LOD_MERGE_SHARE[lod] * size), color)
+                    })
+                    .collect()
+            }
+        };
+        lods.push(
+            parts
+                .into_iter()
+                .map(|(geometry, color)| {
+                    let batch_id = *next_batch_id;
+                    *next_batch_id += 1;
+                    ModelPart { batch_id, geometry, color }
+                })
+                .collect(),
+        );
+    }
+
+    Ok(BuildingModel {
+        name: path.file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or("unnamed")
+            .to_owned(),
+        kind: kind.to_owned(),
+        lods,
+    })
+}
+
+static mut MODELS: *const Vec<BuildingModel> = 0 as *const Vec<BuildingModel>;
+
+pub fn all_models() -> &'static [BuildingModel] {
+    unsafe {
+        if MODELS.is_null() {
+            &[]
+        } else {
+            &*MODELS
+        }
+    }
+}
+
+/// Indices (into `all_models`) of the models for buildings of `kind`
+pub fn models_for(kind: &str) -> Vec<usize> {
+    all_models()
+        .iter()
+        .enumerate()
+        .filter(|&(_, model)| model.kind == kind)
+        .map(|(idx, _)| idx)
+        .collect()
+}
+
+pub fn setup() {
+    let mut models = Vec::new();
+    let mut next_batch_id = FIRST_BATCH_ID;
+
+    for package in ::core::packages::loaded() {
+        for kind in &["house", "shop"] {
+            let dir = package.dir.join("assets").join("buildings").join(kind);
+            let entries = match read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+            let mut paths = entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| is_model_file(path))
+                .collect::<Vec<_>>();
+            paths.sort();
+
+            for path in paths {
+                match load_model(&path, kind, &mut next_batch_id) {
+                    Ok(model) => models.push(model),
+                    Err(err) => println!("Error loading building model {:?}: {}", path, err),
+                }
+            }
+        }
+    }
+
+    unsafe { MODELS = Box::into_raw(Box::new(models)) };
+}
@@ -0,0 +1,255 @@
+//! Openings, growth, shrinking, closures and relocations of businesses, so that the
+//! places trips go to change as the city does.
+//!
+//! Once a day, every business reports its profit and every building its position and
+//! households. Businesses that lose money after their fixed costs shrink, and once they
+//! are too small they move to a better site or close. New businesses open on vacant,
+//! accessible sites with many households around and few competitors.
+//!
+//! Grocery shops are the only businesses so far.
+use kay::{ActorSystem, World};
+use compact::CVec;
+use descartes::{N, P2, Norm};
+use core::simulation::{SimulationID, Sleeper, SleeperID, MSG_Sleeper_wake, Timestamp, Ticks,
+                       TICKS_PER_SIM_DAY};
+use core::city_events::{self, CityEventKind};
+use super::buildings::BuildingID;
+use super::households::grocery_shop::GroceryShopID;
+
+const SETTINGS_CATEGORY: &'static str = "Businesses";
+const COLLECTION_TICKS: Ticks = Ticks(10);
+/// Businesses grow and shrink by this many jobs at a time
+const JOBS_STEP: usize = 2;
+const MIN_JOBS: usize = 4;
+const MAX_JOBS: usize = 20;
+/// A business moves instead of closing if another site is this much better than its own
+const RELOCATION_GAIN: f32 = 1.5;
+
+#[derive(Serialize, Deserialize, Copy, Clone)]
+pub struct BusinessSettings {
+    /// Rent and other costs that don't depend on sales, per job and day
+    pub daily_cost_per_job: f32,
+    /// Profit per job and day above which a business grows
+    pub growth_profit_per_job: f32,
+    /// Households around a site (per competitor) needed for a new business to open
+    pub households_per_business: f32,
+    /// Households and competitors this close to a site count for it
+    pub catchment_radius: N,
+}
+
+impl Default for BusinessSettings {
+    fn default() -> Self {
+        BusinessSettings {
+            daily_cost_per_job: 20.0,
+            growth_profit_per_job: 40.0,
+            households_per_business: 15.0,
+            catchment_radius: 300.0,
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Site {
+    building: BuildingID,
+    position: P2,
+    n_households: usize,
+    accessible: bool,
+}
+
+#[derive(Copy, Clone)]
+struct BusinessReport {
+    shop: GroceryShopID,
+    site: BuildingID,
+    profit: f32,
+    n_jobs: usize,
+}
+
+#[derive(Compact, Clone)]
+pub struct BusinessRegistry {
+    id: BusinessRegistryID,
+    simulation: SimulationID,
+    settings: BusinessSettings,
+    collecting: bool,
+    sites: CVec<Site>,
+    businesses: CVec<BusinessReport>,
+}
+
+impl BusinessRegistry {
+    pub fn spawn(
+        id: BusinessRegistryID,
+        simulation: SimulationID,
+        world: &mut World,
+    ) -> BusinessRegistry {
+        simulation.wake_up_in(Ticks(TICKS_PER_SIM_DAY), id.into(), world);
+
+        BusinessRegistry {
+            id,
+            simulation,
+            settings: ::ENV.load_settings(SETTINGS_CATEGORY),
+            collecting: false,
+            sites: CVec::new(),
+            businesses: CVec::new(),
+        }
+    }
+
+    pub fn add_site(
+        &mut self,
+        building: BuildingID,
+        position: P2,
+        n_households: usize,
+        accessible: bool,
+        _: &mut World,
+    ) {
+        if self.collecting {
+            self.sites.push(Site { building, position, n_households, accessible });
+        }
+    }
+
+    pub fn add_business(
+        &mut self,
+        shop: GroceryShopID,
+        site: BuildingID,
+        profit: f32,
+        n_jobs: usize,
+        _: &mut World,
+    ) {
+        if self.collecting {
+            self.businesses.push(BusinessReport { shop, site, profit, n_jobs });
+        }
+    }
+
+    fn position_of(&self, building: BuildingID) -> Option<P2> {
+        self.sites
+            .iter()
+            .find(|site| site.building == building)
+            .map(|site| site.position)
+    }
+
+    /// Households around `position` per business competing for them
+    fn site_score(&self, position: P2) -> f32 {
+        let nearby = |other: P2| (other - position).norm() < self.settings.catchment_radius;
+        let n_competitors = self.businesses
+            .iter()
+            .filter_map(|business| self.position_of(business.site))
+            .filter(|&other| nearby(other))
+            .count();
+        let n_households: usize = self.sites
+            .iter()
+            .filter(|site| nearby(site.position))
+            .filter(|site| {
+                !self.businesses.iter().any(|business| business.site == site.building)
+            })
+            .map(|site| site.n_households)
+            .sum();
+        n_households as f32 / (1 + n_competitors) as f32
+    }
+
+    /// The vacant, accessible site with the best score that isn't taken yet
+    fn best_vacant_site(&self, taken: &[BuildingID]) -> Option<(Site, f32)> {
+        self.sites
+            .iter()
+            .filter(|site| {
+                site.n_households == 0 && site.accessible && !taken.contains(&site.building)
+            })
+            .map(|site| (*site, self.site_score(site.position)))
+            .max_by(|&(_, a), &(_, b)| a.partial_cmp(&b).unwrap())
+    }
+
+    fn review(&mut self, world: &mut World) {
+        let mut taken = Vec::new();
+
+        for business in self.businesses.iter() {
+            let profit = business.profit -
+                business.n_jobs as f32 * self.settings.daily_cost_per_job;
+            let maybe_position = self.position_of(business.site);
+
+            if profit < 0.0 {
+                if business.n_jobs >= MIN_JOBS + JOBS_STEP {
+                    business.shop.resize(business.n_jobs - JOBS_STEP, world);
+                    continue;
+                }
+
+                let own_score = maybe_position.map(|position| self.site_score(position));
+                let maybe_better_site = self.best_vacant_site(&taken).and_then(
+                    |(site, score)| if score > own_score.unwrap_or(0.0) * RELOCATION_GAIN {
+                        Some(site)
+                    } else {
+                        None
+                    },
+                );
+
+                if let Some(site) = maybe_better_site {
+                    taken.push(site.building);
+                    business.shop.relocate(site.building, world);
+                    city_events::publish(
+                        CityEventKind::BusinessRelocated,
+                        "A struggling shop moved to a busier neighbourhood",
+                        Some(site.position),
+                        world,
+                    );
+                } else {
+                    business.shop.close(world);
+                    city_events::publish(
+                        CityEventKind::BusinessClosed,
+                        "A shop closed for lack of customers",
+                        maybe_position,
+                        world,
+                    );
+                }
+            } else if profit > business.n_jobs as f32 * self.settings.growth_profit_per_job &&
+                       business.n_jobs < MAX_JOBS
+            {
+                business.shop.resize(
+                    (business.n_jobs + JOBS_STEP).min(MAX_JOBS),
+                    world,
+                );
+            }
+        }
+
+        // at most one opening per day, so that competition can show its effect
+        if let Some((site, score)) = self.best_vacant_site(&taken) {
+            if score >= self.settings.households_per_business {
+                let shop = GroceryShopID::move_into(site.building, world);
+                site.building.add_household(shop.into(), world);
+                city_events::publish(
+                    CityEventKind::BusinessOpened,
+                    "A new shop opened",
+                    Some(site.position),
+                    world,
+                );
+            }
+        }
+    }
+}
+
+impl Sleeper for BusinessRegistry {
+    fn wake(&mut self, _current_tick: Timestamp, world: &mut World) {
+        if self.collecting {
+            self.collecting = false;
+            self.review(world);
+            self.simulation.wake_up_in(
+                Ticks(TICKS_PER_SIM_DAY - COLLECTION_TICKS.0),
+                self.id.into(),
+                world,
+            );
+        } else {
+            self.collecting = true;
+            self.sites = CVec::new();
+            self.businesses = CVec::new();
+            BuildingID::global_broadcast(world).report_business_site(self.id, world);
+            GroceryShopID::global_broadcast(world).report_performance(self.id, world);
+            self.simulation.wake_up_in(COLLECTION_TICKS, self.id.into(), world);
+        }
+    }
+}
+
+pub fn setup(system: &mut ActorSystem, simulation: SimulationID) {
+    system.register::<BusinessRegistry>();
+    system.make_restorable::<BusinessRegistry>();
+    auto_setup(system);
+
+    BusinessRegistryID::spawn(simulation, &mut system.world());
+}
+
+mod kay_auto;
+pub use self::kay_auto::*;
@@ -0,0 +1,279 @@
+use kay::{ActorSystem, World};
+use compact::CVec;
+use stagemaster::UserInterfaceID;
+use ordered_float::OrderedFloat;
+use core::statistics_history::StatisticsHistoryID;
+use core::simulation::{SimulationID, Sleeper, SleeperID, MSG_Sleeper_wake, Timestamp, Ticks};
+
+use super::buildings::BuildingID;
+use super::households::family::FamilyID;
+use super::households::grocery_shop::GroceryShopID;
+use super::households::airport::AirportID;
+
+const CENSUS_INTERVAL: Ticks = Ticks(1200);
+const COLLECTION_TICKS: Ticks = Ticks(10);
+const MEMBERS_PER_NEW_FAMILY: usize = 3;
+// how much more attractive a city gets by being connected to the outside world
+const EXTERNAL_CONNECTION_BONUS: f32 = 0.1;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub enum Difficulty {
+    Easy,
+    Normal,
+    Hard,
+}
+
+/// Points `(attractiveness, growth)` of a piecewise linear curve, where attractiveness
+/// goes from 0.0 to 1.0 and growth is the fraction of the current population
+/// that moves in (or, if negative, away) per census
+pub type GrowthCurve = Vec<(f32, f32)>;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DemographicsSettings {
+    pub difficulty: Difficulty,
+    pub easy_growth: GrowthCurve,
+    pub normal_growth: GrowthCurve,
+    pub hard_growth: GrowthCurve,
+    /// Families that move into an otherwise empty city per census
+    pub settlers: usize,
+}
+
+impl Default for DemographicsSettings {
+    fn default() -> Self {
+        DemographicsSettings {
+            difficulty: Difficulty::Normal,
+            easy_growth: vec![(0.0, -0.02), (0.3, 0.0), (1.0, 0.15)],
+            normal_growth: vec![(0.0, -0.05), (0.5, 0.0), (1.0, 0.1)],
+            hard_growth: vec![(0.0, -0.1), (0.7, 0.0), (1.0, 0.05)],
+            settlers: 2,
+        }
+    }
+}
+
+impl DemographicsSettings {
+    fn growth_curve(&self) -> &GrowthCurve {
+        match self.difficulty {
+            Difficulty::Easy => &self.easy_growth,
+            Difficulty::Normal => &self.normal_growth,
+            Difficulty::Hard => &self.hard_growth,
+        }
+    }
+}
+
+fn growth_rate(curve: &[(f32, f32)], attractiveness: f32) -> f32 {
+    match curve.iter().position(|&(x, _)| x >= attractiveness) {
+        None => curve.last().map(|&(_, y)| y).unwrap_or(0.0),
+        Some(0) => curve[0].1,
+        Some(i) => {
+            let ((x1, y1), (x2, y2)) = (curve[i - 1], curve[i]);
+            y1 + (y2 - y1) * (attractiveness - x1) / (x2 - x1)
+        }
+    }
+}
+
+#[derive(Compact, Clone)]
+struct Census {
+    families: CVec<FamilyID>,
+    /// With their land value, see `Building::land_value`
+    vacant_buildings: CVec<(BuildingID, f32)>,
+    jobs: usize,
+    external_connections: usize,
+}
+
+impl Census {
+    fn new() -> Self {
+        Census {
+            families: CVec::new(),
+            vacant_buildings: CVec::new(),
+            jobs: 0,
+            external_connections: 0,
+        }
+    }
+}
+
+/// Decides how many households move into or away from the city,
+/// based on how attractive it is to live in
+#[derive(Compact, Clone)]
+pub struct Demographics {
+    id: DemographicsID,
+    simulation: SimulationID,
+    /// The growth curve of the difficulty in the settings, see `GrowthCurve`
+    growth_curve: CVec<(f32, f32)>,
+    /// Families that move into an otherwise empty city per census
+    settlers: usize,
+    collecting: bool,
+    census: Census,
+    /// average commute score from the last satisfaction survey
+    accessibility: f32,
+    /// Set once a synthetic population was imported: nobody moves in or away on their own
+    frozen: bool,
+}
+
+impl Demographics {
+    pub fn spawn(
+        id: DemographicsID,
+        simulation: SimulationID,
+        world: &mut World,
+    ) -> Demographics {
+        simulation.wake_up_in(CENSUS_INTERVAL, id.into(), world);
+        let settings: DemographicsSettings = ::ENV.load_settings("Demographics");
+
+        Demographics {
+            id,
+            simulation,
+            growth_curve: settings.growth_curve().iter().cloned().collect(),
+            settlers: settings.settlers,
+            collecting: false,
+            census: Census::new(),
+            accessibility: 1.0,
+            frozen: false,
+        }
+    }
+
+    pub fn add_family(&mut self, family: FamilyID, _: &mut World) {
+        if self.collecting {
+            self.census.families.push(family);
+        }
+    }
+
+    pub fn add_vacant_building(&mut self, building: BuildingID, land_value: f32, _: &mut World) {
+        if self.collecting {
+            self.census.vacant_buildings.push((building, land_value));
+        }
+    }
+
+    pub fn add_jobs(&mut self, n_jobs: usize, _: &mut World) {
+        if self.collecting {
+            self.census.jobs += n_jobs;
+        }
+    }
+
+    pub fn add_external_connection(&mut self, _: &mut World) {
+        if self.collecting {
+            self.census.external_connections += 1;
+        }
+    }
+
+    pub fn freeze_population(&mut self, _: &mut World) {
+        self.frozen = true;
+    }
+
+    pub fn survey_completed(
+        &mut self,
+        accessibility: f32,
+        unhappy_families: &CVec<FamilyID>,
+        world: &mut World,
+    ) {
+        self.accessibility = accessibility;
+        if self.frozen {
+            return;
+        }
+        for family in unhappy_families {
+            family.move_out(world);
+        }
+    }
+
+    fn attractiveness(&self) -> f32 {
+        let population = self.census.families.len();
+        let job_availability = if population == 0 {
+            1.0
+        } else {
+            (self.census.jobs as f32 / population as f32).min(1.0)
+        };
+        let n_homes = population + self.census.vacant_buildings.len();
+        let vacancy = if n_homes == 0 {
+            0.0
+        } else {
+            self.census.vacant_buildings.len() as f32 / n_homes as f32
+        };
+        // some vacancy is good for the housing market, full vacancy is a ghost town
+        let housing = (4.0 * vacancy * (1.0 - vacancy)).min(1.0).max(0.3);
+
+        let external_connection = if self.census.external_connections > 0 {
+            EXTERNAL_CONNECTION_BONUS
+        } else {
+            0.0
+        };
+
+        (0.4 * job_availability + 0.2 * housing + 0.4 * self.accessibility +
+             external_connection)
+            .min(1.0)
+    }
+
+    fn evaluate_census(&mut self, world: &mut World) {
+        let population = self.census.families.len();
+        let attractiveness = self.attractiveness();
+        let growth_rate = growth_rate(&self.growth_curve, attractiveness);
+
+        let change = if self.frozen {
+            0
+        } else if population == 0 {
+            self.settlers as isize
+        } else {
+            (growth_rate * population as f32).round() as isize
+        };
+
+        if change > 0 {
+            // newcomers take the most valuable homes first
+            self.census.vacant_buildings.sort_by_key(
+                |&(_, land_value)| OrderedFloat(-land_value),
+            );
+            for &(building, _) in self.census.vacant_buildings.iter().take(change as usize) {
+                let family =
+                    FamilyID::move_into(MEMBERS_PER_NEW_FAMILY, building, self.simulation, world);
+                building.add_household(family.into(), world);
+            }
+        } else if change < 0 {
+            for family in self.census.families.iter().take((-change) as usize) {
+                family.move_out(world);
+            }
+        }
+
+        StatisticsHistoryID::local_first(world).record_population(population, world);
+
+        UserInterfaceID::local_first(world).add_debug_text(
+            "Population".chars().collect(),
+            format!(
+                "{} families, {} jobs, {} vacant, attractiveness {:.0}% ({:+})",
+                population,
+                self.census.jobs,
+                self.census.vacant_buildings.len(),
+                100.0 * attractiveness,
+                change
+            ).chars()
+                .collect(),
+            [0.0, 0.0, 0.0, 1.0],
+            true,
+            world,
+        );
+    }
+}
+
+impl Sleeper for Demographics {
+    fn wake(&mut self, _current_tick: Timestamp, world: &mut World) {
+        if self.collecting {
+            self.collecting = false;
+            self.evaluate_census(world);
+            self.census = Census::new();
+            self.simulation.wake_up_in(CENSUS_INTERVAL, self.id.into(), world);
+        } else {
+            self.collecting = true;
+            BuildingID::global_broadcast(world).report_to_census(self.id, world);
+            FamilyID::global_broadcast(world).report_to_census(self.id, world);
+            GroceryShopID::global_broadcast(world).report_to_census(self.id, world);
+            AirportID::global_broadcast(world).report_to_census(self.id, world);
+            self.simulation.wake_up_in(COLLECTION_TICKS, self.id.into(), world);
+        }
+    }
+}
+
+pub fn setup(system: &mut ActorSystem, simulation: SimulationID) {
+    system.register::<Demographics>();
+    system.make_restorable::<Demographics>();
+    auto_setup(system);
+
+    DemographicsID::spawn(simulation, &mut system.world());
+}
+
+mod kay_auto;
+pub use self::kay_auto::*;
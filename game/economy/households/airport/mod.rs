@@ -0,0 +1,274 @@
+use kay::{ActorSystem, World, External, Fate};
+use compact::CVec;
+use imgui::Ui;
+use descartes::P2;
+use core::simulation::{SimulationID, Sleeper, SleeperID, MSG_Sleeper_wake, Timestamp, Ticks,
+                       TimeOfDay, Seconds, MINUTES_PER_DAY};
+use economy::resources::r_id;
+use economy::market::{Deal, OfferID};
+use economy::buildings::{BuildingID, DestinationCollector, DestinationCollectorID,
+                         MSG_DestinationCollector_add_destination};
+use economy::buildings::rendering::BuildingInspectorID;
+use economy::demographics::DemographicsID;
+use transport::pathfinding::RoughLocationID;
+use transport::pathfinding::trip::{TripID, TripListener, TripListenerID,
+                                   MSG_TripListener_trip_created, MSG_TripListener_trip_result};
+use transport::restrictions::VehicleClass;
+
+use super::{Household, HouseholdID, MemberIdx, MSG_Household_decay, MSG_Household_inspect,
+            MSG_Household_provide_deal, MSG_Household_receive_deal, MSG_Household_task_succeeded,
+            MSG_Household_task_failed, MSG_Household_report_satisfaction,
+            MSG_Household_on_home_demolished, MSG_Household_on_offer_withdrawn,
+            MSG_Household_report_commutes};
+use super::satisfaction::SatisfactionSurveyID;
+use economy::jobs_housing::JobsHousingBalanceID;
+
+/// Times of day (hours, minutes) when a bank of flights lands and takes off
+const BANKS: [(usize, usize); 4] = [(6, 30), (11, 0), (16, 30), (21, 0)];
+/// Departing passengers leave home this many minutes before their bank
+const DEPARTURE_LEAD_MINUTES: usize = 90;
+const PASSENGERS_PER_BANK: usize = 25;
+const PARKING_SPACES: usize = 150;
+const JOBS_AT_AIRPORT: usize = 40;
+const COLLECTION_TICKS: Ticks = Ticks(10);
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum BankEvent {
+    Departures,
+    Arrivals,
+}
+
+/// The minute of the day of each bank event, sorted
+fn bank_events() -> Vec<(usize, BankEvent)> {
+    let mut events = BANKS
+        .iter()
+        .flat_map(|&(h, m)| {
+            let minute = h * 60 + m;
+            vec![
+                (
+                    (minute + MINUTES_PER_DAY - DEPARTURE_LEAD_MINUTES) % MINUTES_PER_DAY,
+                    BankEvent::Departures
+                ),
+                (minute, BankEvent::Arrivals),
+            ]
+        })
+        .collect::<Vec<_>>();
+    events.sort_by_key(|&(minute, _)| minute);
+    events
+}
+
+/// An airport in a building: passengers don't trickle in and out over the day,
+/// but arrive and leave in banks around a few flight times. Departing passengers
+/// drive from home and park at the airport, arriving ones pick their cars up again.
+/// As a connection to the outside world, it makes the city more attractive to live in
+#[derive(Compact, Clone)]
+pub struct Airport {
+    id: AirportID,
+    site: BuildingID,
+    job_offer: OfferID,
+    collecting_for: Option<BankEvent>,
+    homes: CVec<BuildingID>,
+    parked_cars: usize,
+    parking_overflow: usize,
+    passengers_departed: usize,
+    passengers_arrived: usize,
+}
+
+impl Airport {
+    pub fn open(id: AirportID, site: BuildingID, world: &mut World) -> Airport {
+        SimulationID::local_first(world).wake_up_in(Ticks(0), id.into(), world);
+
+        Airport {
+            id,
+            site,
+            job_offer: OfferID::register(
+                id.into(),
+                site.into(),
+                TimeOfDay::new(5, 0),
+                TimeOfDay::new(23, 0),
+                Deal::new((r_id("money"), 60.0), None, Seconds(8 * 60 * 60)),
+                world,
+            ),
+            collecting_for: None,
+            homes: CVec::new(),
+            parked_cars: 0,
+            parking_overflow: 0,
+            passengers_departed: 0,
+            passengers_arrived: 0,
+        }
+    }
+
+    pub fn report_to_census(&mut self, demographics: DemographicsID, world: &mut World) {
+        demographics.add_jobs(JOBS_AT_AIRPORT, world);
+        demographics.add_external_connection(world);
+    }
+
+    fn dispatch_passengers(&mut self, event: BankEvent, tick: Timestamp, world: &mut World) {
+        if self.homes.is_empty() {
+            return;
+        }
+
+        for _ in 0..PASSENGERS_PER_BANK {
+            let home = self.homes[::core::random::gen_range(0, self.homes.len())];
+            let (source, destination) = match event {
+                BankEvent::Departures => (home, self.site),
+                BankEvent::Arrivals => (self.site, home),
+            };
+            TripID::spawn(
+                source.into(),
+                destination.into(),
+                Some(self.id.into()),
+                VehicleClass::Car,
+                tick,
+                world,
+            );
+        }
+
+        if event == BankEvent::Arrivals {
+            // arriving passengers take their cars back home
+            self.parked_cars = self.parked_cars.saturating_sub(PASSENGERS_PER_BANK);
+            self.passengers_arrived += PASSENGERS_PER_BANK;
+        }
+    }
+
+    fn wake_up_for_next_event(&self, current_tick: Timestamp, world: &mut World) {
+        let next = bank_events()
+            .iter()
+            .map(|&(minute, _)| TimeOfDay::new(minute / 60, minute % 60).next_after(current_tick))
+            .min()
+            .expect("airports have bank events");
+
+        SimulationID::local_first(world).wake_up_at(next, self.id.into(), world);
+    }
+}
+
+impl DestinationCollector for Airport {
+    fn add_destination(&mut self, destination: BuildingID, _: &mut World) {
+        if self.collecting_for.is_some() {
+            self.homes.push(destination);
+        }
+    }
+}
+
+impl Sleeper for Airport {
+    fn wake(&mut self, current_tick: Timestamp, world: &mut World) {
+        if let Some(event) = self.collecting_for {
+            self.collecting_for = None;
+            self.dispatch_passengers(event, current_tick, world);
+            self.wake_up_for_next_event(current_tick, world);
+        } else {
+            let (h, m) = TimeOfDay::from_tick(current_tick).hours_minutes();
+            let now = h * 60 + m;
+
+            if let Some(&(_, event)) = bank_events().iter().find(|&&(minute, _)| minute == now) {
+                self.collecting_for = Some(event);
+                self.homes = CVec::new();
+                BuildingID::global_broadcast(world).report_as_destination(
+                    self.id.into(),
+                    true,
+                    world,
+                );
+                SimulationID::local_first(world).wake_up_in(
+                    COLLECTION_TICKS,
+                    self.id.into(),
+                    world,
+                );
+            } else {
+                // just opened, or woke up between events
+                self.wake_up_for_next_event(current_tick, world);
+            }
+        }
+    }
+}
+
+impl TripListener for Airport {
+    fn trip_created(&mut self, _trip: TripID, _: &mut World) {}
+
+    fn trip_result(
+        &mut self,
+        _trip: TripID,
+        location: RoughLocationID,
+        failed: bool,
+        _tick: Timestamp,
+        _: &mut World,
+    ) {
+        let site: RoughLocationID = self.site.into();
+        if !failed && location == site {
+            self.passengers_departed += 1;
+            if self.parked_cars < PARKING_SPACES {
+                self.parked_cars += 1;
+            } else {
+                self.parking_overflow += 1;
+            }
+        }
+    }
+}
+
+impl Household for Airport {
+    fn receive_deal(&mut self, _deal: &Deal, _member: MemberIdx, _: &mut World) {}
+
+    fn provide_deal(&mut self, _deal: &Deal, _: &mut World) {}
+
+    fn decay(&mut self, _dt: Seconds, _: &mut World) {}
+
+    fn task_succeeded(&mut self, _member: MemberIdx, _: &mut World) {}
+
+    fn task_failed(&mut self, _member: MemberIdx, _location: RoughLocationID, _: &mut World) {}
+
+    fn report_satisfaction(&mut self, _survey: SatisfactionSurveyID, _home: P2, _: &mut World) {}
+
+    fn on_home_demolished(&mut self, world: &mut World) -> Fate {
+        self.job_offer.withdraw(world);
+        SimulationID::local_first(world).forget_wake_ups(self.id.into(), world);
+        Fate::Die
+    }
+
+    fn on_offer_withdrawn(&mut self, _offer: OfferID, _: &mut World) {}
+
+    fn report_commutes(
+        &mut self,
+        balance: JobsHousingBalanceID,
+        position: P2,
+        world: &mut World,
+    ) {
+        balance.add_workplace(self.job_offer, position, JOBS_AT_AIRPORT, world);
+    }
+
+    fn inspect(
+        &mut self,
+        imgui_ui: &External<Ui<'static>>,
+        return_to: BuildingInspectorID,
+        world: &mut World,
+    ) {
+        let ui = imgui_ui.steal();
+
+        ui.window(im_str!("Building")).build(|| {
+            ui.text(im_str!("Airport ID: {:?}", self.id._raw_id));
+            let banks = BANKS
+                .iter()
+                .map(|&(h, m)| format!("{:02}:{:02}", h, m))
+                .collect::<Vec<_>>()
+                .join(", ");
+            ui.text(im_str!("Flight banks: {}", banks));
+            ui.text(im_str!("Passengers departed: {}", self.passengers_departed));
+            ui.text(im_str!("Passengers arrived: {}", self.passengers_arrived));
+            ui.text(im_str!(
+                "Parking structure: {}/{} ({} turned away)",
+                self.parked_cars,
+                PARKING_SPACES,
+                self.parking_overflow
+            ));
+        });
+
+        return_to.ui_drawn(ui, world);
+    }
+}
+
+pub fn setup(system: &mut ActorSystem) {
+    system.register::<Airport>();
+    system.make_restorable::<Airport>();
+    auto_setup(system);
+}
+
+mod kay_auto;
+pub use self::kay_auto::*;
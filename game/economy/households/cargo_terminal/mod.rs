@@ -0,0 +1,205 @@
+use kay::{ActorSystem, World, External, Fate};
+use compact::CVec;
+use imgui::Ui;
+use descartes::P2;
+use core::simulation::{SimulationID, Sleeper, SleeperID, MSG_Sleeper_wake, Timestamp, Ticks,
+                       Seconds, TICKS_PER_SIM_HOUR};
+use economy::market::{Deal, OfferID};
+use economy::buildings::{BuildingID, DestinationCollector, DestinationCollectorID,
+                         MSG_DestinationCollector_add_destination};
+use economy::buildings::rendering::BuildingInspectorID;
+use transport::pathfinding::RoughLocationID;
+use transport::pathfinding::trip::TripID;
+use transport::restrictions::VehicleClass;
+
+use super::{Household, HouseholdID, MemberIdx, MSG_Household_decay, MSG_Household_inspect,
+            MSG_Household_provide_deal, MSG_Household_receive_deal, MSG_Household_task_succeeded,
+            MSG_Household_task_failed, MSG_Household_report_satisfaction,
+            MSG_Household_on_home_demolished, MSG_Household_on_offer_withdrawn,
+            MSG_Household_report_commutes};
+use super::satisfaction::SatisfactionSurveyID;
+use economy::jobs_housing::JobsHousingBalanceID;
+
+const COLLECTION_TICKS: Ticks = Ticks(10);
+/// Share of the dispatched trucks that carry oversize loads, which bridges might not take
+const OVERSIZE_SHARE: f32 = 0.05;
+
+/// Where people or freight change from another mode of transport to the road network
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum TerminalKind {
+    /// Ships unload freight here
+    Port,
+    /// Freight trains unload here
+    RailYard,
+    /// Passengers fly in and out here, see `Airport`
+    Airport,
+}
+
+impl TerminalKind {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            TerminalKind::Port => "Port",
+            TerminalKind::RailYard => "Rail Yard",
+            TerminalKind::Airport => "Airport",
+        }
+    }
+
+    fn arrival_interval(&self) -> Ticks {
+        match *self {
+            TerminalKind::Port => Ticks(8 * TICKS_PER_SIM_HOUR),
+            TerminalKind::RailYard => Ticks(3 * TICKS_PER_SIM_HOUR),
+            TerminalKind::Airport => panic!("Airports don't handle freight"),
+        }
+    }
+
+    fn trucks_per_arrival(&self) -> usize {
+        match *self {
+            TerminalKind::Port => 40,
+            TerminalKind::RailYard => 15,
+            TerminalKind::Airport => panic!("Airports don't handle freight"),
+        }
+    }
+}
+
+/// A freight terminal in a building: on a fixed schedule, a ship or train arrives
+/// and its whole load leaves at once, as a burst of truck trips to buildings all over the city
+#[derive(Compact, Clone)]
+pub struct CargoTerminal {
+    id: CargoTerminalID,
+    site: BuildingID,
+    kind: TerminalKind,
+    collecting_destinations: bool,
+    destinations: CVec<BuildingID>,
+    arrivals: usize,
+    trucks_dispatched: usize,
+}
+
+impl CargoTerminal {
+    pub fn move_into(
+        id: CargoTerminalID,
+        kind: TerminalKind,
+        site: BuildingID,
+        world: &mut World,
+    ) -> CargoTerminal {
+        SimulationID::local_first(world).wake_up_in(kind.arrival_interval(), id.into(), world);
+
+        CargoTerminal {
+            id,
+            site,
+            kind,
+            collecting_destinations: false,
+            destinations: CVec::new(),
+            arrivals: 0,
+            trucks_dispatched: 0,
+        }
+    }
+
+    fn dispatch_trucks(&mut self, tick: Timestamp, world: &mut World) {
+        if self.destinations.is_empty() {
+            return;
+        }
+
+        for _ in 0..self.kind.trucks_per_arrival() {
+            let destination_idx = ::core::random::gen_range(0, self.destinations.len());
+            let destination = self.destinations[destination_idx];
+            let vehicle = if ::core::random::next_f32() < OVERSIZE_SHARE {
+                VehicleClass::OversizeTruck
+            } else {
+                VehicleClass::Truck
+            };
+            TripID::spawn(
+                self.site.into(),
+                destination.into(),
+                None,
+                vehicle,
+                tick,
+                world,
+            );
+        }
+        self.trucks_dispatched += self.kind.trucks_per_arrival();
+    }
+}
+
+impl DestinationCollector for CargoTerminal {
+    fn add_destination(&mut self, destination: BuildingID, _: &mut World) {
+        if self.collecting_destinations && destination != self.site {
+            self.destinations.push(destination);
+        }
+    }
+}
+
+impl Sleeper for CargoTerminal {
+    fn wake(&mut self, current_tick: Timestamp, world: &mut World) {
+        let simulation = SimulationID::local_first(world);
+
+        if self.collecting_destinations {
+            self.collecting_destinations = false;
+            self.arrivals += 1;
+            self.dispatch_trucks(current_tick, world);
+            simulation.wake_up_in(self.kind.arrival_interval(), self.id.into(), world);
+        } else {
+            self.collecting_destinations = true;
+            self.destinations = CVec::new();
+            BuildingID::global_broadcast(world).report_as_destination(
+                self.id.into(),
+                false,
+                world,
+            );
+            simulation.wake_up_in(COLLECTION_TICKS, self.id.into(), world);
+        }
+    }
+}
+
+impl Household for CargoTerminal {
+    fn receive_deal(&mut self, _deal: &Deal, _member: MemberIdx, _: &mut World) {}
+
+    fn provide_deal(&mut self, _deal: &Deal, _: &mut World) {}
+
+    fn decay(&mut self, _dt: Seconds, _: &mut World) {}
+
+    fn task_succeeded(&mut self, _member: MemberIdx, _: &mut World) {}
+
+    fn task_failed(&mut self, _member: MemberIdx, _location: RoughLocationID, _: &mut World) {}
+
+    fn report_satisfaction(&mut self, _survey: SatisfactionSurveyID, _home: P2, _: &mut World) {}
+
+    fn on_home_demolished(&mut self, world: &mut World) -> Fate {
+        SimulationID::local_first(world).forget_wake_ups(self.id.into(), world);
+        Fate::Die
+    }
+
+    fn on_offer_withdrawn(&mut self, _offer: OfferID, _: &mut World) {}
+
+    fn report_commutes(&mut self, _balance: JobsHousingBalanceID, _home: P2, _: &mut World) {}
+
+    fn inspect(
+        &mut self,
+        imgui_ui: &External<Ui<'static>>,
+        return_to: BuildingInspectorID,
+        world: &mut World,
+    ) {
+        let ui = imgui_ui.steal();
+
+        ui.window(im_str!("Building")).build(|| {
+            ui.text(im_str!("{} ID: {:?}", self.kind.name(), self.id._raw_id));
+            ui.text(im_str!("Arrivals so far: {}", self.arrivals));
+            ui.text(im_str!("Trucks dispatched: {}", self.trucks_dispatched));
+            ui.text(im_str!(
+                "Every {}h, {} trucks leave at once",
+                self.kind.arrival_interval().0 / TICKS_PER_SIM_HOUR,
+                self.kind.trucks_per_arrival()
+            ));
+        });
+
+        return_to.ui_drawn(ui, world);
+    }
+}
+
+pub fn setup(system: &mut ActorSystem) {
+    system.register::<CargoTerminal>();
+    system.make_restorable::<CargoTerminal>();
+    auto_setup(system);
+}
+
+mod kay_auto;
+pub use self::kay_auto::*;
@@ -0,0 +1,308 @@
+use kay::{ActorSystem, World, External};
+use compact::CVec;
+use descartes::{P2, P3, V3};
+use monet::{RendererID, EyeListener, EyeListenerID, Eye, Movement, MSG_EyeListener_eye_moved};
+use stagemaster::{UserInterfaceID, Interactable2d, Interactable2dID,
+                  MSG_Interactable2d_draw_ui_2d};
+use imgui::ImGuiSetCond_FirstUseEver;
+use core::simulation::{Timestamp, TimeOfDay};
+use transport::lane::LaneID;
+use transport::pathfinding::trip::{TripID, TripProgressListener, TripProgressListenerID,
+                                   MSG_TripProgressListener_on_trip_progress};
+use economy::resources::r_info;
+
+use super::MemberIdx;
+use super::family::FamilyID;
+use super::tasks::{Task, TaskState};
+
+/// While it isn't known which lane the followed car is on, all lanes
+/// are only asked for it every this many frames
+const FRAMES_BETWEEN_SEARCHES: usize = 30;
+
+/// Something scheduled to happen to a citizen
+#[derive(Copy, Clone)]
+pub enum Upcoming {
+    /// The end of the task they're doing
+    TaskEnd(Task),
+    /// Their family deciding what they do next
+    Decision,
+}
+
+#[derive(Compact, Clone)]
+pub struct CitizenInspector {
+    id: CitizenInspectorID,
+    user_interface: UserInterfaceID,
+    renderer_id: RendererID,
+    following: Option<(FamilyID, MemberIdx)>,
+    task: Option<Task>,
+    log: CVec<(Timestamp, Task)>,
+    upcoming: CVec<(Timestamp, Upcoming)>,
+    car_position: Option<P2>,
+    /// The lane the followed car was last seen on, which is asked for its progress
+    car_lane: Option<LaneID>,
+    frames_without_progress: usize,
+    remaining_distance: Option<f32>,
+    initial_distance: Option<f32>,
+    camera_follow: bool,
+    eye_target: P3,
+}
+
+impl CitizenInspector {
+    pub fn spawn(
+        id: CitizenInspectorID,
+        user_interface: UserInterfaceID,
+        renderer_id: RendererID,
+        world: &mut World,
+    ) -> CitizenInspector {
+        renderer_id.add_eye_listener(0, id.into(), world);
+
+        CitizenInspector {
+            id,
+            user_interface,
+            renderer_id,
+            following: None,
+            task: None,
+            log: CVec::new(),
+            upcoming: CVec::new(),
+            car_position: None,
+            car_lane: None,
+            frames_without_progress: 0,
+            remaining_distance: None,
+            initial_distance: None,
+            camera_follow: false,
+            eye_target: P3::new(0.0, 0.0, 0.0),
+        }
+    }
+
+    pub fn follow(&mut self, family: FamilyID, member: MemberIdx, world: &mut World) {
+        self.following = Some((family, member));
+        self.task = None;
+        self.log = CVec::new();
+        self.upcoming = CVec::new();
+        self.car_position = None;
+        self.car_lane = None;
+        self.frames_without_progress = 0;
+        self.remaining_distance = None;
+        self.initial_distance = None;
+        self.user_interface.add_2d(self.id.into(), world);
+    }
+
+    pub fn on_member_state(
+        &mut self,
+        family: FamilyID,
+        member: MemberIdx,
+        task: Task,
+        log: &CVec<(Timestamp, Task)>,
+        upcoming: &CVec<(Timestamp, Upcoming)>,
+        _: &mut World,
+    ) {
+        if self.following != Some((family, member)) {
+            return;
+        }
+
+        let trip_changed = match (self.task.map(|task| task.state), task.state) {
+            (Some(TaskState::InTrip(old_trip)), TaskState::InTrip(new_trip)) => {
+                old_trip != new_trip
+            }
+            _ => true,
+        };
+        if trip_changed {
+            self.car_position = None;
+            self.car_lane = None;
+            self.frames_without_progress = 0;
+            self.remaining_distance = None;
+            self.initial_distance = None;
+        }
+
+        self.task = Some(task);
+        self.log = log.clone();
+        self.upcoming = upcoming.clone();
+    }
+}
+
+impl TripProgressListener for CitizenInspector {
+    fn on_trip_progress(
+        &mut self,
+        trip: TripID,
+        lane: LaneID,
+        position: P2,
+        remaining_distance: Option<f32>,
+        _: &mut World,
+    ) {
+        if let Some(Task { state: TaskState::InTrip(current_trip), .. }) = self.task {
+            if current_trip == trip {
+                self.car_position = Some(position);
+                self.car_lane = Some(lane);
+                self.frames_without_progress = 0;
+                self.remaining_distance = remaining_distance;
+                if self.initial_distance.is_none() {
+                    self.initial_distance = remaining_distance;
+                }
+            }
+        }
+    }
+}
+
+impl EyeListener for CitizenInspector {
+    fn eye_moved(&mut self, eye: Eye, _movement: Movement, _: &mut World) {
+        self.eye_target = eye.target;
+    }
+}
+
+fn describe(task: &Task) -> String {
+    let goal = task.goal
+        .map(|(resource, _)| r_info(resource).0)
+        .unwrap_or_else(|| "nothing".to_owned());
+    match task.state {
+        TaskState::IdleAt(_) => "Idle".to_owned(),
+        TaskState::GettingReadyAt(_) => format!("Getting ready for {}", goal),
+        TaskState::InTrip(_) => format!("Travelling for {}", goal),
+        TaskState::StartedAt(start, _) => {
+            let (h, m) = TimeOfDay::from_tick(start + task.duration).hours_minutes();
+            format!("{} until {:02}:{:02}", goal, h, m)
+        }
+    }
+}
+
+fn describe_upcoming(upcoming: &Upcoming) -> String {
+    match *upcoming {
+        Upcoming::TaskEnd(task) => {
+            let goal = task.goal
+                .map(|(resource, _)| r_info(resource).0)
+                .unwrap_or_else(|| "nothing".to_owned());
+            format!("Done with {}", goal)
+        }
+        Upcoming::Decision => "Deciding what to do next".to_owned(),
+    }
+}
+
+impl Interactable2d for CitizenInspector {
+    fn draw_ui_2d(
+        &mut self,
+        imgui_ui: &External<::imgui::Ui<'static>>,
+        return_to: UserInterfaceID,
+        world: &mut World,
+    ) {
+        let ui = imgui_ui.steal();
+
+        if let Some((family, member)) = self.following {
+            let mut opened = true;
+            let mut toggle_camera_follow = false;
+
+            ui.window(im_str!("Citizen"))
+                .size((300.0, 400.0), ImGuiSetCond_FirstUseEver)
+                .collapsible(false)
+                .opened(&mut opened)
+                .build(|| {
+                    ui.text(im_str!(
+                        "Family {:?}, Member #{}",
+                        family._raw_id,
+                        member.0
+                    ));
+                    ui.separator();
+
+                    if let Some(task) = self.task {
+                        ui.text(im_str!("Now: {}", describe(&task)));
+
+                        if let TaskState::InTrip(_) = task.state {
+                            match (self.remaining_distance, self.initial_distance) {
+                                (Some(remaining), Some(initial)) if initial > 0.0 => {
+                                    let progress = (1.0 - remaining / initial).max(0.0);
+                                    ui.text(im_str!(
+                                        "Trip progress: {:.0}% ({:.0}m left)",
+                                        progress * 100.0,
+                                        remaining
+                                    ));
+                                }
+                                _ => ui.text(im_str!("Trip progress: unknown")),
+                            }
+                        }
+                    }
+
+                    if ui.small_button(if self.camera_follow {
+                        im_str!("Stop following with camera")
+                    } else {
+                        im_str!("Follow with camera")
+                    })
+                    {
+                        toggle_camera_follow = true;
+                    }
+
+                    ui.separator();
+                    ui.text(im_str!("Timeline"));
+                    // scheduled activities first, the latest on top like the log below
+                    for &(at, ref upcoming) in self.upcoming.iter().rev() {
+                        let (h, m) = TimeOfDay::from_tick(at).hours_minutes();
+                        ui.text(im_str!("{:02}:{:02}", h, m));
+                        ui.same_line(80.0);
+                        ui.text(im_str!("(upcoming) {}", describe_upcoming(upcoming)));
+                    }
+                    for &(at, ref task) in self.log.iter().rev() {
+                        let (h, m) = TimeOfDay::from_tick(at).hours_minutes();
+                        ui.text(im_str!("{:02}:{:02}", h, m));
+                        ui.same_line(80.0);
+                        ui.text(im_str!("{}", describe(task)));
+                    }
+                });
+
+            if toggle_camera_follow {
+                self.camera_follow = !self.camera_follow;
+            }
+
+            if opened {
+                family.report_member(member, self.id, world);
+
+                if let Some(Task { state: TaskState::InTrip(trip), .. }) = self.task {
+                    match self.car_lane {
+                        Some(lane) if self.frames_without_progress == 0 => {
+                            lane.report_trip_progress(trip, self.id.into(), world);
+                        }
+                        _ => {
+                            // the car left the lane it was last seen on, or wasn't seen yet
+                            let just_left_lane = self.car_lane.take().is_some();
+                            if just_left_lane ||
+                                self.frames_without_progress % FRAMES_BETWEEN_SEARCHES == 0
+                            {
+                                LaneID::local_broadcast(world).report_trip_progress(
+                                    trip,
+                                    self.id.into(),
+                                    world,
+                                );
+                            }
+                        }
+                    }
+                    self.frames_without_progress += 1;
+
+                    if let (true, Some(position)) = (self.camera_follow, self.car_position) {
+                        self.renderer_id.move_eye(
+                            0,
+                            Movement::ShiftAbsolute(V3::new(
+                                position.x - self.eye_target.x,
+                                position.y - self.eye_target.y,
+                                0.0,
+                            )),
+                            world,
+                        );
+                    }
+                }
+            } else {
+                self.following = None;
+                self.camera_follow = false;
+                self.user_interface.remove_2d(self.id.into(), world);
+            }
+        }
+
+        return_to.ui_drawn(ui, world);
+    }
+}
+
+pub fn setup(system: &mut ActorSystem, user_interface: UserInterfaceID, renderer_id: RendererID) {
+    system.register::<CitizenInspector>();
+    auto_setup(system);
+    system.prioritize::<MSG_CitizenInspector_on_member_state>();
+
+    CitizenInspectorID::spawn(user_interface, renderer_id, &mut system.world());
+}
+
+mod kay_auto;
+pub use self::kay_auto::*;
@@ -0,0 +1,121 @@
+use kay::{ActorSystem, World, External, Fate};
+use imgui::Ui;
+use descartes::P2;
+use core::simulation::Seconds;
+use economy::market::{Deal, OfferID};
+use economy::buildings::BuildingID;
+use economy::buildings::rendering::BuildingInspectorID;
+use transport::pathfinding::RoughLocationID;
+use transport::services::emergency::{EmergencyDispatcherID, IncidentKind, StationKind};
+
+use super::{Household, HouseholdID, MemberIdx, MSG_Household_decay, MSG_Household_inspect,
+            MSG_Household_provide_deal, MSG_Household_receive_deal, MSG_Household_task_succeeded,
+            MSG_Household_task_failed, MSG_Household_report_satisfaction,
+            MSG_Household_on_home_demolished, MSG_Household_on_offer_withdrawn,
+            MSG_Household_report_commutes};
+use super::satisfaction::SatisfactionSurveyID;
+use economy::jobs_housing::JobsHousingBalanceID;
+
+/// A fire station or hospital in a building. Its vehicles are dispatched
+/// by the `EmergencyDispatcher`, which tells it how each response went
+#[derive(Compact, Clone)]
+pub struct EmergencyStation {
+    id: EmergencyStationID,
+    site: BuildingID,
+    kind: StationKind,
+    responses: usize,
+    failed_responses: usize,
+    total_response_minutes: f32,
+    last_incident: Option<IncidentKind>,
+}
+
+impl EmergencyStation {
+    pub fn open(
+        id: EmergencyStationID,
+        kind: StationKind,
+        site: BuildingID,
+        _: &mut World,
+    ) -> EmergencyStation {
+        EmergencyStation {
+            id,
+            site,
+            kind,
+            responses: 0,
+            failed_responses: 0,
+            total_response_minutes: 0.0,
+            last_incident: None,
+        }
+    }
+
+    pub fn responded(&mut self, incident: IncidentKind, minutes: Option<f32>, _: &mut World) {
+        self.last_incident = Some(incident);
+        if let Some(minutes) = minutes {
+            self.responses += 1;
+            self.total_response_minutes += minutes;
+        } else {
+            self.failed_responses += 1;
+        }
+    }
+}
+
+impl Household for EmergencyStation {
+    fn receive_deal(&mut self, _deal: &Deal, _member: MemberIdx, _: &mut World) {}
+
+    fn provide_deal(&mut self, _deal: &Deal, _: &mut World) {}
+
+    fn decay(&mut self, _dt: Seconds, _: &mut World) {}
+
+    fn task_succeeded(&mut self, _member: MemberIdx, _: &mut World) {}
+
+    fn task_failed(&mut self, _member: MemberIdx, _location: RoughLocationID, _: &mut World) {}
+
+    fn report_satisfaction(&mut self, _survey: SatisfactionSurveyID, _home: P2, _: &mut World) {}
+
+    fn on_home_demolished(&mut self, world: &mut World) -> Fate {
+        EmergencyDispatcherID::local_first(world).remove_station(self.id, world);
+        Fate::Die
+    }
+
+    fn on_offer_withdrawn(&mut self, _offer: OfferID, _: &mut World) {}
+
+    fn report_commutes(&mut self, _balance: JobsHousingBalanceID, _home: P2, _: &mut World) {}
+
+    fn inspect(
+        &mut self,
+        imgui_ui: &External<Ui<'static>>,
+        return_to: BuildingInspectorID,
+        world: &mut World,
+    ) {
+        let ui = imgui_ui.steal();
+
+        ui.window(im_str!("Building")).build(|| {
+            ui.text(im_str!("{} ID: {:?}", self.kind.name(), self.id._raw_id));
+            ui.text(im_str!("Site: {:?}", self.site._raw_id));
+            ui.text(im_str!(
+                "Responses: {} ({} never arrived)",
+                self.responses,
+                self.failed_responses
+            ));
+            if self.responses > 0 {
+                ui.text(im_str!(
+                    "Average response time: {:.1} min",
+                    self.total_response_minutes / self.responses as f32
+                ));
+            }
+            if let Some(incident) = self.last_incident {
+                ui.text(im_str!("Last incident: {}", incident.name()));
+            }
+        });
+
+        return_to.ui_drawn(ui, world);
+    }
+}
+
+pub fn setup(system: &mut ActorSystem) {
+    system.register::<EmergencyStation>();
+    system.make_restorable::<EmergencyStation>();
+    auto_setup(system);
+}
+
+mod kay_auto;
+pub use self::kay_auto::*;
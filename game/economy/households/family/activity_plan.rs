@@ -0,0 +1,38 @@
+//! Daily activity plans, for members of families imported from a synthetic population
+//! (see `economy::synthetic_population`). Instead of deciding on tasks by their most
+//! pressing needs, such members do what their plan says, at the planned times and, if
+//! it offers what they need, at the planned place. Members without a plan decide for
+//! themselves like before.
+use core::simulation::{Timestamp, TimeOfDay};
+use economy::resources::ResourceId;
+use economy::buildings::BuildingID;
+
+#[derive(Copy, Clone)]
+pub struct PlannedActivity {
+    /// What the activity is for, like money for work
+    pub resource: ResourceId,
+    /// The building closest to where the activity is planned, if there is any
+    pub site: Option<BuildingID>,
+    pub start: TimeOfDay,
+    /// The day the activity was last started on
+    pub started_on_day: Option<usize>,
+}
+
+impl PlannedActivity {
+    pub fn new(resource: ResourceId, site: Option<BuildingID>, start: TimeOfDay) -> Self {
+        PlannedActivity { resource, site, start, started_on_day: None }
+    }
+}
+
+/// The activity of `plan` to start at `tick`: the one that started last before it, if it
+/// wasn't started today yet. Activities missed while busy with an earlier one are skipped.
+pub fn due_activity(plan: &[PlannedActivity], tick: Timestamp) -> Option<usize> {
+    let now = TimeOfDay::from_tick(tick);
+    plan.iter()
+        .rposition(|activity| activity.start <= now)
+        .and_then(|idx| if plan[idx].started_on_day == Some(tick.day()) {
+            None
+        } else {
+            Some(idx)
+        })
+}
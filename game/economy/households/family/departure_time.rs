@@ -0,0 +1,145 @@
+//! When commuters leave for work. Every working member chooses a departure slot each
+//! morning, weighing the travel time they expect for it (from their own past commutes)
+//! against arriving earlier or later than they'd like. The choice is random (logit), so
+//! commuters spread out over the morning where the peak is congested, and drift back
+//! towards their preferred time once the roads get faster.
+use core::simulation::{Timestamp, TimeOfDay};
+
+const FIRST_SLOT_MINUTES: usize = 6 * 60;
+const SLOT_MINUTES: usize = 15;
+const N_SLOTS: usize = 16;
+/// Preferred arrival times are spread out this many minutes after the earliest one
+const PREFERRED_ARRIVAL_EARLIEST: usize = 8 * 60;
+const PREFERRED_ARRIVAL_SPREAD: usize = 90;
+/// Cost of a minute of travel, of arriving a minute early and of arriving a minute late
+const TRAVEL_WEIGHT: f32 = 1.0;
+const EARLY_WEIGHT: f32 = 0.5;
+const LATE_WEIGHT: f32 = 2.0;
+/// How sharply commuters prefer the best slot over the others (lower: more random)
+const CHOICE_SHARPNESS: f32 = 0.2;
+/// Weight of the newest commute in a slot's expected travel time
+const LEARNING_RATE: f32 = 0.3;
+
+#[derive(Copy, Clone)]
+pub struct DepartureChoice {
+    preferred_arrival: usize,
+    /// Travel time (in minutes) experienced so far for each slot, if it was ever tried
+    expected_minutes: [Option<f32>; N_SLOTS],
+    /// The day and slot chosen for that day
+    planned: Option<(usize, usize)>,
+    last_commute_day: Option<usize>,
+}
+
+fn minutes_of(time: TimeOfDay) -> usize {
+    let (hours, minutes) = time.hours_minutes();
+    hours * 60 + minutes
+}
+
+fn slot_start(slot: usize) -> usize {
+    FIRST_SLOT_MINUTES + slot * SLOT_MINUTES
+}
+
+impl DepartureChoice {
+    /// A commuter who hasn't commuted yet, with a random preferred arrival time
+    pub fn random() -> DepartureChoice {
+        DepartureChoice {
+            preferred_arrival: PREFERRED_ARRIVAL_EARLIEST +
+                ::core::random::gen_range(0, PREFERRED_ARRIVAL_SPREAD + 1),
+            expected_minutes: [None; N_SLOTS],
+            planned: None,
+            last_commute_day: None,
+        }
+    }
+
+    /// Untried slots are expected to be as fast as the fastest tried one,
+    /// so commuters try them out now and then
+    fn expected_minutes(&self, slot: usize) -> f32 {
+        self.expected_minutes[slot].unwrap_or_else(|| {
+            self.expected_minutes
+                .iter()
+                .filter_map(|minutes| *minutes)
+                .fold(None, |min: Option<f32>, minutes| {
+                    Some(min.map(|min| min.min(minutes)).unwrap_or(minutes))
+                })
+                .unwrap_or(0.0)
+        })
+    }
+
+    fn cost(&self, slot: usize) -> f32 {
+        let travel = self.expected_minutes(slot);
+        let arrival = slot_start(slot) as f32 + travel;
+        let preferred = self.preferred_arrival as f32;
+        TRAVEL_WEIGHT * travel + EARLY_WEIGHT * (preferred - arrival).max(0.0) +
+            LATE_WEIGHT * (arrival - preferred).max(0.0)
+    }
+
+    fn choose_slot(&self) -> usize {
+        let costs = (0..N_SLOTS).map(|slot| self.cost(slot)).collect::<Vec<_>>();
+        let lowest = costs.iter().cloned().fold(::std::f32::INFINITY, f32::min);
+        let weights = costs
+            .iter()
+            .map(|cost| (-CHOICE_SHARPNESS * (cost - lowest)).exp())
+            .collect::<Vec<_>>();
+        let mut remaining = ::core::random::next_f32() * weights.iter().sum::<f32>();
+        for (slot, weight) in weights.into_iter().enumerate() {
+            if remaining < weight {
+                return slot;
+            }
+            remaining -= weight;
+        }
+        N_SLOTS - 1
+    }
+
+    /// The chosen departure time (in minutes of the day) for the day of `tick`,
+    /// choosing it first if that day doesn't have one yet
+    pub fn planned_departure(&mut self, tick: Timestamp) -> usize {
+        let day = tick.day();
+        let slot = match self.planned {
+            Some((planned_day, slot)) if planned_day == day => slot,
+            _ => {
+                let slot = self.choose_slot();
+                self.planned = Some((day, slot));
+                slot
+            }
+        };
+        slot_start(slot)
+    }
+
+    /// Whether a commuter at home should still wait before leaving for work
+    pub fn should_wait(&mut self, tick: Timestamp) -> bool {
+        let now = minutes_of(TimeOfDay::from_tick(tick));
+        let in_morning = now >= FIRST_SLOT_MINUTES && now < slot_start(N_SLOTS);
+        in_morning && self.last_commute_day != Some(tick.day()) &&
+            now < self.planned_departure(tick)
+    }
+
+    /// Whether the commuter is due to leave for work now
+    pub fn is_due(&mut self, tick: Timestamp) -> bool {
+        let now = minutes_of(TimeOfDay::from_tick(tick));
+        let in_morning = now >= FIRST_SLOT_MINUTES && now < slot_start(N_SLOTS);
+        in_morning && self.last_commute_day != Some(tick.day()) &&
+            now >= self.planned_departure(tick)
+    }
+
+    /// Remembers how long a commute that started at `start` took
+    pub fn learn(&mut self, start: Timestamp, trip_minutes: f32) {
+        self.last_commute_day = Some(start.day());
+        let start_minutes = minutes_of(TimeOfDay::from_tick(start));
+        if start_minutes < FIRST_SLOT_MINUTES || start_minutes >= slot_start(N_SLOTS) {
+            return;
+        }
+        let slot = (start_minutes - FIRST_SLOT_MINUTES) / SLOT_MINUTES;
+        self.expected_minutes[slot] = Some(match self.expected_minutes[slot] {
+            Some(expected) => expected + LEARNING_RATE * (trip_minutes - expected),
+            None => trip_minutes,
+        });
+    }
+
+    /// The departure time chosen last, as hours and minutes
+    pub fn last_planned(&self) -> Option<(usize, usize)> {
+        self.planned.map(|(_, slot)| {
+            let minutes = slot_start(slot);
+            (minutes / 60, minutes % 60)
+        })
+    }
+}
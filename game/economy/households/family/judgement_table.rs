@@ -1,6 +1,6 @@
 use economy::resources::{r_id, ResourceId, MAX_N_RESOURCE_TYPES};
 use core::simulation::TimeOfDay;
-use core::read_md_tables;
+use core::packages::read_data_tables;
 
 pub struct JudgementTable([[u8; 12]; MAX_N_RESOURCE_TYPES]);
 
@@ -25,9 +25,10 @@ pub fn judgement_table() -> &'static JudgementTable {
 pub fn setup() {
     let mut table = Box::<JudgementTable>::default();
 
-    for md_table in read_md_tables::read(&"game/economy/parameters/judgement/adult.data.md")
-        .expect("Expected judgement table to exist")
-    {
+    let md_tables =
+        read_data_tables(&"game/economy/parameters/judgement/adult.data.md", "judgement")
+            .expect("Expected judgement table to exist");
+    for md_table in md_tables {
         let c = &md_table.columns;
 
         for (idx, (resource, resource_id)) in
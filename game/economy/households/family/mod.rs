@@ -1,28 +1,46 @@
-use kay::{ActorSystem, World, External};
+use kay::{ActorSystem, World, External, Fate};
 use compact::{CVec, CDict};
 use imgui::Ui;
 use ordered_float::OrderedFloat;
-use core::simulation::{TimeOfDay, Timestamp, Seconds, Ticks, SimulationID, Simulatable,
-                       SimulatableID, MSG_Simulatable_tick};
+use std::sync::atomic::Ordering;
+use core::simulation::{TimeOfDay, Timestamp, Seconds, Ticks, SimulationID, Reminder, Reminded,
+                       RemindedID, MSG_Reminded_remind, TICKS_PER_SIM_MINUTE};
 use economy::resources::{ResourceId, ResourceAmount, ResourceMap, Entry};
 use economy::market::{Deal, MarketID, OfferID, EvaluatedDeal, EvaluationRequester,
                       EvaluationRequesterID, MSG_EvaluationRequester_expect_n_results,
                       MSG_EvaluationRequester_on_result, EvaluatedSearchResult};
+use economy::market::destination_choice;
 use economy::buildings::BuildingID;
 use economy::buildings::rendering::BuildingInspectorID;
 use transport::pathfinding::trip::{TripListenerID, MSG_TripListener_trip_created,
                                    MSG_TripListener_trip_result};
 use transport::pathfinding::RoughLocationID;
+use transport::pathfinding::mode_choice::ModeChooserID;
+use descartes::P2;
 
 mod judgement_table;
 use self::judgement_table::judgement_table;
 
+mod departure_time;
+use self::departure_time::DepartureChoice;
+
+pub mod activity_plan;
+use self::activity_plan::PlannedActivity;
+
 use core::async_counter::AsyncCounter;
 
 use super::{Household, HouseholdID, MemberIdx, MSG_Household_decay, MSG_Household_inspect,
             MSG_Household_provide_deal, MSG_Household_receive_deal, MSG_Household_task_succeeded,
-            MSG_Household_task_failed};
+            MSG_Household_task_failed, MSG_Household_report_satisfaction,
+            MSG_Household_on_home_demolished, MSG_Household_on_offer_withdrawn,
+            MSG_Household_report_commutes};
+use super::satisfaction::{SatisfactionSurveyID, SurveyResponse};
+use economy::jobs_housing::JobsHousingBalanceID;
 use super::tasks::{Task, TaskEndSchedulerID};
+use super::citizen_inspector::{CitizenInspectorID, Upcoming};
+use super::school::SchoolID;
+use economy::demographics::DemographicsID;
+use economy::trip_generation::{TripGenerationSettings, LandUse};
 
 #[derive(Compact, Clone)]
 struct DecisionResourceEntry {
@@ -33,7 +51,13 @@ struct DecisionResourceEntry {
 #[derive(Compact, Clone)]
 enum DecisionState {
     None,
-    Choosing(MemberIdx, Timestamp, CDict<ResourceId, DecisionResourceEntry>),
+    /// With the site of a planned activity, where the member goes if it can
+    Choosing(
+        MemberIdx,
+        Timestamp,
+        CDict<ResourceId, DecisionResourceEntry>,
+        Option<RoughLocationID>,
+    ),
     WaitingForTrip(MemberIdx),
 }
 
@@ -47,11 +71,36 @@ pub struct Family {
     decision_state: DecisionState,
     used_offers: ResourceMap<OfferID>,
     member_used_offers: CVec<ResourceMap<OfferID>>,
+    member_logs: CVec<CVec<(Timestamp, Task)>>,
+    /// When each member leaves for work, if they have a job
+    member_departures: CVec<DepartureChoice>,
+    /// How long each member expects trips for each kind of task to take
+    member_expected_trip_ticks: CVec<ResourceMap<f32>>,
+    /// Members whose last trip took much longer than expected,
+    /// so they look for a better route next time
+    member_reoptimizes: CVec<bool>,
+    /// What each member does when, empty for members who decide for themselves
+    member_plans: CVec<CVec<PlannedActivity>>,
+    average_trip_ticks: f32,
+    trip_failure_rate: f32,
+    /// When the family decides again what idle members do, if it is pausing until then
+    next_decision: Option<Timestamp>,
+    leaving: bool,
+    homeless: bool,
+    /// Children only go to school and back, they don't decide on tasks like members
+    n_children: usize,
 }
 
 const N_TOP_PROBLEMS: usize = 5;
 const DECISION_PAUSE: Ticks = Ticks(200);
 const UPDATE_EVERY_N_SECS: usize = 4;
+const DECAY: Reminder = Reminder(0);
+const MAX_LOG_ENTRIES: usize = 20;
+// weight of the newest trip in the running trip statistics
+const TRIP_STATISTICS_SMOOTHING: f32 = 0.2;
+// trips taking longer than expected by more than this share make members reconsider their route
+const TRIP_DELAY_TOLERANCE: f32 = 0.25;
+const MAX_CHILDREN: usize = 2;
 
 use economy::resources::r_properties;
 
@@ -67,7 +116,34 @@ impl Family {
         simulation: SimulationID,
         world: &mut World,
     ) -> Family {
+        let n_children = ::core::random::gen_range(0, MAX_CHILDREN + 1);
+        let plans = vec![CVec::new(); n_members].into();
+        Family::new(id, home, plans, n_children, simulation, world)
+    }
+
+    /// A family from a synthetic population, whose members follow their activity plans
+    pub fn move_in_with_plans(
+        id: FamilyID,
+        home: BuildingID,
+        plans: &CVec<CVec<PlannedActivity>>,
+        n_children: usize,
+        simulation: SimulationID,
+        world: &mut World,
+    ) -> Family {
+        Family::new(id, home, plans.clone(), n_children, simulation, world)
+    }
+
+    fn new(
+        id: FamilyID,
+        home: BuildingID,
+        plans: CVec<CVec<PlannedActivity>>,
+        n_children: usize,
+        simulation: SimulationID,
+        world: &mut World,
+    ) -> Family {
+        let n_members = plans.len();
         simulation.wake_up_in(Ticks(0), id.into(), world);
+        simulation.remind_in(Seconds(UPDATE_EVERY_N_SECS).into(), DECAY, id.into(), world);
 
         Family {
             id,
@@ -78,25 +154,193 @@ impl Family {
             decision_state: DecisionState::None,
             used_offers: ResourceMap::new(),
             member_used_offers: vec![ResourceMap::new(); n_members].into(),
+            member_logs: vec![CVec::new(); n_members].into(),
+            member_departures: (0..n_members).map(|_| DepartureChoice::random()).collect(),
+            member_expected_trip_ticks: vec![ResourceMap::new(); n_members].into(),
+            member_reoptimizes: vec![false; n_members].into(),
+            member_plans: plans,
+            average_trip_ticks: 0.0,
+            trip_failure_rate: 0.0,
+            next_decision: None,
+            leaving: false,
+            homeless: false,
+            n_children,
+        }
+    }
+}
+
+impl Family {
+    pub fn report_to_census(&mut self, demographics: DemographicsID, world: &mut World) {
+        demographics.add_family(self.id, world);
+    }
+
+    pub fn report_children(&mut self, school: SchoolID, distance: f32, world: &mut World) {
+        if self.n_children > 0 && !self.homeless {
+            school.enroll(self.home, self.n_children, distance, world);
+        }
+    }
+
+    /// Called by demographics for families that leave the city
+    pub fn move_out(&mut self, world: &mut World) -> Fate {
+        let everybody_idle = self.member_tasks.iter().all(|task| match task.state {
+            TaskState::IdleAt(_) => true,
+            _ => false,
+        });
+        let deciding = match self.decision_state {
+            DecisionState::None => false,
+            _ => true,
+        };
+        if !everybody_idle || deciding {
+            // wait until everybody is back, so no trip or task reports to a family that left
+            self.leaving = true;
+            return Fate::Live;
+        }
+
+        let used_offers = self.used_offers
+            .iter()
+            .map(|&Entry(_, offer)| (offer, None))
+            .chain(self.member_used_offers.iter().enumerate().flat_map(
+                |(i, member_used_offers)| {
+                    member_used_offers.iter().map(move |&Entry(_, offer)| {
+                        (offer, Some(MemberIdx(i)))
+                    })
+                },
+            ))
+            .collect::<Vec<_>>();
+        for (offer, maybe_member) in used_offers {
+            offer.stopped_using(self.id.into(), maybe_member, world);
+        }
+
+        if !self.homeless {
+            self.home.remove_household(self.id.into(), world);
+        }
+        SimulationID::local_first(world).forget_reminders(self.id.into(), world);
+        SimulationID::local_first(world).forget_wake_ups(self.id.into(), world);
+        Fate::Die
+    }
+
+    fn log_task(&mut self, member: MemberIdx, at: Timestamp) {
+        let log = &mut self.member_logs[member.0];
+        if log.len() >= MAX_LOG_ENTRIES {
+            log.remove(0);
+        }
+        log.push((at, self.member_tasks[member.0]));
+    }
+
+    /// Decides again what idle members do once `DECISION_PAUSE` after `tick` has passed
+    fn pause_decisions(&mut self, tick: Timestamp, world: &mut World) {
+        self.next_decision = Some(tick + DECISION_PAUSE);
+        SimulationID::local_first(world).wake_up_in(DECISION_PAUSE, self.id.into(), world);
+    }
+
+    /// What is scheduled for `member`: the end of their task, or for idle members,
+    /// the family's next decision
+    fn upcoming_for(&self, member: MemberIdx) -> CVec<(Timestamp, Upcoming)> {
+        let task = self.member_tasks[member.0];
+        match task.state {
+            TaskState::StartedAt(start, _) => {
+                vec![(start + task.duration, Upcoming::TaskEnd(task))].into()
+            }
+            TaskState::IdleAt(_) => {
+                self.next_decision
+                    .map(|at| vec![(at, Upcoming::Decision)])
+                    .unwrap_or_else(Vec::new)
+                    .into()
+            }
+            _ => CVec::new(),
         }
     }
+
+    pub fn report_member(
+        &mut self,
+        member: MemberIdx,
+        inspector: CitizenInspectorID,
+        world: &mut World,
+    ) {
+        inspector.on_member_state(
+            self.id,
+            member,
+            self.member_tasks[member.0],
+            self.member_logs[member.0].clone(),
+            self.upcoming_for(member),
+            world,
+        );
+    }
 }
 
 use core::simulation::{Sleeper, SleeperID, MSG_Sleeper_wake};
 
 impl Sleeper for Family {
     fn wake(&mut self, current_tick: Timestamp, world: &mut World) {
+        if self.leaving {
+            self.id.move_out(world);
+            return;
+        }
+
         if let DecisionState::None = self.decision_state {
-            let maybe_idle_idx_loc = self.member_tasks
-                .iter()
-                .enumerate()
-                .filter_map(|(idx, m)| match m.state {
-                    TaskState::IdleAt(loc) => Some((idx, loc)),
-                    _ => None,
-                })
-                .next();
-            if let Some((idle_member_idx, location)) = maybe_idle_idx_loc {
-                self.find_new_task_for(MemberIdx(idle_member_idx), current_tick, location, world);
+            self.next_decision = None;
+            let home: RoughLocationID = self.home.into();
+            let mut maybe_idle_idx_loc = None;
+            let mut maybe_planned = None;
+            let mut someone_waiting = false;
+            for (idx, task) in self.member_tasks.iter().enumerate() {
+                if let TaskState::IdleAt(location) = task.state {
+                    if !self.member_plans[idx].is_empty() {
+                        // members with a plan only do what it says, when it says so
+                        match activity_plan::due_activity(&self.member_plans[idx], current_tick) {
+                            Some(activity) => {
+                                maybe_planned = Some((idx, location, activity));
+                                break;
+                            }
+                            None => {
+                                someone_waiting = true;
+                                continue;
+                            }
+                        }
+                    }
+                    let commuter = location == home &&
+                        self.member_used_offers[idx].get(r_id("money")).is_some();
+                    let departure = &mut self.member_departures[idx];
+                    if commuter && departure.should_wait(current_tick) {
+                        // not leaving before their chosen departure time
+                        someone_waiting = true;
+                        continue;
+                    }
+                    let due = commuter && departure.is_due(current_tick);
+                    maybe_idle_idx_loc = Some((idx, location, due));
+                    break;
+                }
+            }
+
+            if let Some((member_idx, location, activity)) = maybe_planned {
+                self.find_planned_task_for(
+                    MemberIdx(member_idx),
+                    activity,
+                    current_tick,
+                    location,
+                    world,
+                );
+            } else if let Some((idle_member_idx, location, due_to_commute)) = maybe_idle_idx_loc {
+                let land_use = if location == home {
+                    LandUse::Residential
+                } else {
+                    LandUse::Commercial
+                };
+                let rate = TripGenerationSettings::current()
+                    .rate(land_use, TimeOfDay::from_tick(current_tick));
+
+                if due_to_commute || ::core::random::next_f32() < rate {
+                    self.find_new_task_for(
+                        MemberIdx(idle_member_idx),
+                        current_tick,
+                        location,
+                        world,
+                    );
+                } else {
+                    self.pause_decisions(current_tick, world);
+                }
+            } else if someone_waiting {
+                self.pause_decisions(current_tick, world);
             }
         };
     }
@@ -130,7 +374,7 @@ impl Family {
         let top_problems = self.top_problems(member, time);
 
         if top_problems.is_empty() {
-            SimulationID::local_first(world).wake_up_in(DECISION_PAUSE, self.id.into(), world);
+            self.pause_decisions(tick, world);
         } else {
             let mut decision_entries = CDict::<ResourceId, DecisionResourceEntry>::new();
 
@@ -141,7 +385,10 @@ impl Family {
                     r_info(resource).0,
                     graveness
                 );
-                let maybe_offer = if r_properties(resource).supplier_shared {
+                let maybe_offer = if destination_choice::applies_to(resource) {
+                    // choose among all offers again
+                    None
+                } else if r_properties(resource).supplier_shared {
                     self.used_offers.get(resource)
                 } else {
                     self.member_used_offers[member.0].get(resource)
@@ -174,9 +421,49 @@ impl Family {
                 );
             }
 
-            self.decision_state = DecisionState::Choosing(member, tick, decision_entries);
+            self.decision_state =
+                DecisionState::Choosing(member, tick, decision_entries, None);
         }
     }
+
+    /// Looks for offers for the planned `activity`, preferring the ones at its site
+    fn find_planned_task_for(
+        &mut self,
+        member: MemberIdx,
+        activity: usize,
+        tick: Timestamp,
+        location: RoughLocationID,
+        world: &mut World,
+    ) {
+        let (resource, site) = {
+            let planned = &mut self.member_plans[member.0][activity];
+            planned.started_on_day = Some(tick.day());
+            (planned.resource, planned.site)
+        };
+        println!(
+            "Member #{} of {:?} follows their plan: {}",
+            member.0,
+            self.id._raw_id,
+            r_info(resource).0
+        );
+
+        MarketID::global_first(world).search(tick, location, resource, self.id.into(), world);
+
+        let mut decision_entries = CDict::<ResourceId, DecisionResourceEntry>::new();
+        decision_entries.insert(
+            resource,
+            DecisionResourceEntry {
+                results_counter: AsyncCounter::new(),
+                deals: CVec::new(),
+            },
+        );
+        self.decision_state = DecisionState::Choosing(
+            member,
+            tick,
+            decision_entries,
+            site.map(|site| site.into()),
+        );
+    }
 }
 
 #[derive(Compact, Clone)]
@@ -187,7 +474,9 @@ enum ResultAspect {
 
 impl Family {
     fn update_results(&mut self, resource: ResourceId, update: ResultAspect, world: &mut World) {
-        let done = if let DecisionState::Choosing(_, _, ref mut entries) = self.decision_state {
+        let done = if let DecisionState::Choosing(_, _, ref mut entries, _) =
+            self.decision_state
+        {
             {
                 let entry = entries.get_mut(resource).expect(
                     "Should have an entry for queried resource",
@@ -238,9 +527,13 @@ impl Family {
     pub fn choose_deal(&mut self, world: &mut World) {
         println!("Choosing deal!");
         let maybe_best_info =
-            if let DecisionState::Choosing(member, tick, ref entries) = self.decision_state {
+            if let DecisionState::Choosing(member, tick, ref entries, preferred_site) =
+                self.decision_state
+            {
                 let time = TimeOfDay::from_tick(tick);
-                let maybe_best = most_useful_evaluated_deal(entries, time);
+                let maybe_best = preferred_site
+                    .and_then(|site| planned_evaluated_deal(entries, site, time))
+                    .or_else(|| most_useful_evaluated_deal(entries, time));
 
                 if let Some(best) = maybe_best {
                     let task = &mut self.member_tasks[member.0];
@@ -262,7 +555,13 @@ impl Family {
             } else {
                 panic!("Tried to choose deal while not deciding");
             };
+        let decision_tick = if let DecisionState::Choosing(_, tick, _, _) = self.decision_state {
+            tick
+        } else {
+            unreachable!()
+        };
         if let Some((member, tick, best_offer)) = maybe_best_info {
+            self.log_task(member, tick);
             self.decision_state = DecisionState::WaitingForTrip(member);
             best_offer.get_receivable_deal(self.id.into(), member, world);
             self.start_trip(member, tick, world);
@@ -272,45 +571,66 @@ impl Family {
                 self.id._raw_id
             );
             self.decision_state = DecisionState::None;
-            SimulationID::local_first(world).wake_up_in(DECISION_PAUSE, self.id.into(), world);
+            self.pause_decisions(decision_tick, world);
         }
 
-        fn most_useful_evaluated_deal(
+        fn planned_evaluated_deal(
             entries: &CDict<ResourceId, DecisionResourceEntry>,
+            site: RoughLocationID,
             time: TimeOfDay,
         ) -> Option<EvaluatedDeal> {
             entries
                 .values()
-                .flat_map(|entry| {
-                    entry
+                .flat_map(|entry| entry.deals.iter())
+                .find(|evaluated| {
+                    evaluated.location == site && evaluated.from < time && evaluated.to > time
+                })
+                .cloned()
+        }
+
+        fn most_useful_evaluated_deal(
+            entries: &CDict<ResourceId, DecisionResourceEntry>,
+            time: TimeOfDay,
+        ) -> Option<EvaluatedDeal> {
+            entries
+                .pairs()
+                .flat_map(|(&resource, entry)| {
+                    let open_deals = entry
                         .deals
                         .iter()
                         .filter(|evaluated| evaluated.from < time && evaluated.to > time)
-                        .map(|evaluated| {
-                            let give_alleviation = resource_graveness_helper(
-                                evaluated.deal.give.0,
-                                -evaluated.deal.give.1,
-                                time,
-                            );
-                            let take_graveness: f32 = evaluated
-                                .deal
-                                .take
-                                .iter()
-                                .map(|&Entry(resource, amount)| {
-                                    resource_graveness_helper(resource, -amount, time)
-                                })
-                                .sum();
-
-                            let usefulness = give_alleviation /
-                                (take_graveness * evaluated.deal.duration.seconds() as f32);
-
-                            (usefulness, evaluated)
-                        })
+                        .cloned()
+                        .collect::<Vec<_>>();
+                    let candidates = if destination_choice::applies_to(resource) {
+                        destination_choice::choose(&open_deals).into_iter().collect::<Vec<_>>()
+                    } else {
+                        open_deals
+                    };
+                    candidates.into_iter().map(|evaluated| {
+                        let give_alleviation = resource_graveness_helper(
+                            evaluated.deal.give.0,
+                            -evaluated.deal.give.1,
+                            time,
+                        );
+                        let take_graveness: f32 = evaluated
+                            .deal
+                            .take
+                            .iter()
+                            .map(|&Entry(resource, amount)| {
+                                resource_graveness_helper(resource, -amount, time)
+                            })
+                            .sum();
+
+                        let usefulness = give_alleviation /
+                            (take_graveness * evaluated.deal.duration.seconds() as f32);
+
+                        (usefulness, evaluated)
+                    })
                 })
                 .collect::<Vec<_>>()
                 .into_iter()
                 .max_by_key(|&(u, _e)| OrderedFloat(u))
-                .map(|(_, evaluated_deal)| evaluated_deal.clone())
+                .map(|(_, evaluated_deal)| evaluated_deal)
         }
     }
 
@@ -321,7 +641,8 @@ impl Family {
             ..
         } = self.member_tasks[member.0]
         {
-            TripID::spawn(source, offer.into(), Some(self.id.into()), tick, world);
+            // how to get there is decided by mode choice, which then starts the trip
+            ModeChooserID::spawn(source, offer.into(), Some(self.id.into()), tick, world);
         } else {
             panic!("Member should be getting ready before starting trip");
         }
@@ -332,9 +653,18 @@ use transport::pathfinding::trip::{TripListener, TripID};
 use super::tasks::TaskState;
 
 impl TripListener for Family {
-    fn trip_created(&mut self, trip: TripID, _: &mut World) {
+    fn trip_created(&mut self, trip: TripID, world: &mut World) {
         self.decision_state = if let DecisionState::WaitingForTrip(member) = self.decision_state {
             self.member_tasks[member.0].state = TaskState::InTrip(trip);
+            if self.member_reoptimizes[member.0] {
+                trip.reoptimize_route(world);
+                self.member_reoptimizes[member.0] = false;
+            }
+            // the trip starts in the same tick as getting ready
+            let started_at = self.member_logs[member.0].last().map(|&(at, _)| at).expect(
+                "getting ready should have been logged",
+            );
+            self.log_task(member, started_at);
             DecisionState::None
         } else {
             panic!("Should be in waiting for trip state")
@@ -393,11 +723,52 @@ impl TripListener for Family {
             }
         }
 
+        let maybe_trip_start = self.member_logs[matching_task_member.0]
+            .iter()
+            .rev()
+            .find(|&&(_, task)| task.state == TaskState::InTrip(trip))
+            .map(|&(at, _)| at);
+        if let Some(trip_start) = maybe_trip_start {
+            let trip_ticks = (tick.ticks() - trip_start.ticks()) as f32;
+            self.average_trip_ticks += TRIP_STATISTICS_SMOOTHING *
+                (trip_ticks - self.average_trip_ticks);
+
+            if !failed && matching_resource == r_id("money") {
+                self.member_departures[matching_task_member.0]
+                    .learn(trip_start, trip_ticks / TICKS_PER_SIM_MINUTE as f32);
+                ::core::metrics::COMMUTES_SUCCEEDED.fetch_add(1, Ordering::Relaxed);
+                ::core::metrics::COMMUTE_TICKS.fetch_add(
+                    tick.ticks() - trip_start.ticks(),
+                    Ordering::Relaxed,
+                );
+            }
+
+            if !failed {
+                let expected_trip_ticks =
+                    &mut self.member_expected_trip_ticks[matching_task_member.0];
+                let maybe_expected = expected_trip_ticks.get(matching_resource).cloned();
+                if let Some(expected) = maybe_expected {
+                    if trip_ticks > expected * (1.0 + TRIP_DELAY_TOLERANCE) {
+                        self.member_reoptimizes[matching_task_member.0] = true;
+                    }
+                    expected_trip_ticks.insert(
+                        matching_resource,
+                        expected + TRIP_STATISTICS_SMOOTHING * (trip_ticks - expected),
+                    );
+                } else {
+                    expected_trip_ticks.insert(matching_resource, trip_ticks);
+                }
+            }
+        }
+        self.trip_failure_rate += TRIP_STATISTICS_SMOOTHING *
+            (if failed { 1.0 } else { 0.0 } - self.trip_failure_rate);
+
         if failed {
             self.stop_task(matching_task_member, location, world);
         } else {
             self.start_task(matching_task_member, tick, location, world);
         }
+        self.log_task(matching_task_member, tick);
     }
 }
 
@@ -469,8 +840,10 @@ impl Household for Family {
 
     fn task_succeeded(&mut self, member: MemberIdx, world: &mut World) {
         println!("Task succeeded");
-        if let TaskState::StartedAt(_, location) = self.member_tasks[member.0].state {
+        if let TaskState::StartedAt(start, location) = self.member_tasks[member.0].state {
+            let end = start + self.member_tasks[member.0].duration;
             self.stop_task(member, location, world);
+            self.log_task(member, end);
         } else {
             panic!("Can't finish unstarted task");
         }
@@ -480,6 +853,55 @@ impl Household for Family {
         self.stop_task(member, location, world);
     }
 
+    fn on_home_demolished(&mut self, world: &mut World) -> Fate {
+        self.homeless = true;
+        self.move_out(world)
+    }
+
+    fn on_offer_withdrawn(&mut self, offer: OfferID, _: &mut World) {
+        let withdrawn_resources = |used_offers: &ResourceMap<OfferID>| {
+            used_offers
+                .iter()
+                .filter(|&&Entry(_, used_offer)| used_offer == offer)
+                .map(|&Entry(resource, _)| resource)
+                .collect::<Vec<_>>()
+        };
+
+        for resource in withdrawn_resources(&self.used_offers) {
+            self.used_offers.remove(resource);
+        }
+        for member_used_offers in self.member_used_offers.iter_mut() {
+            for resource in withdrawn_resources(member_used_offers) {
+                member_used_offers.remove(resource);
+            }
+        }
+    }
+
+    fn report_satisfaction(&mut self, survey: SatisfactionSurveyID, home: P2, world: &mut World) {
+        survey.add_response(
+            SurveyResponse {
+                family: self.id,
+                home,
+                average_trip_ticks: self.average_trip_ticks,
+                trip_failure_rate: self.trip_failure_rate,
+                has_grocery_shop: self.used_offers.get(r_id("groceries")).is_some(),
+            },
+            world,
+        );
+    }
+
+    fn report_commutes(&mut self, balance: JobsHousingBalanceID, home: P2, world: &mut World) {
+        if self.homeless {
+            return;
+        }
+        balance.add_resident_workers(home, self.member_tasks.len(), world);
+        for member_used_offers in self.member_used_offers.iter() {
+            if let Some(&job_offer) = member_used_offers.get(r_id("money")) {
+                balance.add_commuter(home, job_offer, self.average_trip_ticks, world);
+            }
+        }
+    }
+
     #[allow(useless_format)]
     fn inspect(
         &mut self,
@@ -488,18 +910,22 @@ impl Household for Family {
         world: &mut World,
     ) {
         let ui = imgui_ui.steal();
+        let mut follow_member = None;
 
         ui.window(im_str!("Building")).build(|| {
             ui.tree_node(im_str!("Family ID: {:?}", self.id._raw_id))
                 .build(|| {
                     ui.tree_node(im_str!("Shared")).build(|| {
+                        ui.text(im_str!("Children"));
+                        ui.same_line(250.0);
+                        ui.text(im_str!("{}", self.n_children));
                         ui.text(im_str!("State"));
                         ui.same_line(250.0);
                         ui.text(im_str!(
                             "{}",
                             match self.decision_state {
                                 DecisionState::None => "None",
-                                DecisionState::Choosing(_, _, _) => "Waiting for choice",
+                                DecisionState::Choosing(_, _, _, _) => "Waiting for choice",
                                 DecisionState::WaitingForTrip(_) => "Waiting for trip",
                             }
                         ));
@@ -531,6 +957,20 @@ impl Household for Family {
                                     TaskState::StartedAt(_, _) => "Started",
                                 }
                             ));
+                            let departure = self.member_departures[i].last_planned();
+                            if let Some((hours, minutes)) = departure {
+                                ui.text(im_str!("Leaves for work"));
+                                ui.same_line(250.0);
+                                ui.text(im_str!("{:02}:{:02}", hours, minutes));
+                            }
+                            if !self.member_plans[i].is_empty() {
+                                ui.text(im_str!("Planned activities"));
+                                ui.same_line(250.0);
+                                ui.text(im_str!("{}", self.member_plans[i].len()));
+                            }
+                            if ui.small_button(im_str!("Follow #{}", i)) {
+                                follow_member = Some(MemberIdx(i));
+                            }
 
                             for resource in all_resource_ids() {
                                 if !r_properties(resource).ownership_shared {
@@ -546,26 +986,44 @@ impl Household for Family {
                 })
         });
 
+        if let Some(member) = follow_member {
+            CitizenInspectorID::local_first(world).follow(self.id, member, world);
+        }
+
         return_to.ui_drawn(ui, world);
     }
 }
 
-use core::simulation::TICKS_PER_SIM_SECOND;
-
-impl Simulatable for Family {
-    fn tick(&mut self, _dt: f32, current_tick: Timestamp, world: &mut World) {
-        if current_tick.ticks() % (UPDATE_EVERY_N_SECS * TICKS_PER_SIM_SECOND) == 0 {
-            self.decay(Seconds(UPDATE_EVERY_N_SECS * TICKS_PER_SIM_SECOND), world);
+impl Reminded for Family {
+    fn remind(&mut self, reminder: Reminder, _: Timestamp, world: &mut World) {
+        if reminder == DECAY {
+            self.decay(Seconds(UPDATE_EVERY_N_SECS), world);
+            SimulationID::local_first(world).remind_in(
+                Seconds(UPDATE_EVERY_N_SECS).into(),
+                DECAY,
+                self.id.into(),
+                world,
+            );
         }
     }
 }
 
+/// Only for households that are known to be families, checked while ID casts are audited
+impl From<HouseholdID> for FamilyID {
+    fn from(id: HouseholdID) -> FamilyID {
+        FamilyID { _raw_id: cast_id_to_actor!(id._raw_id, Family) }
+    }
+}
+
 pub fn setup(system: &mut ActorSystem) {
     judgement_table::setup();
 
     system.register::<Family>();
+    system.make_restorable::<Family>();
 
     auto_setup(system);
+    // asked for by the citizen inspector
+    system.prioritize::<MSG_Family_report_member>();
 }
 
 mod kay_auto;
@@ -1,16 +1,31 @@
-use kay::{ActorSystem, World, External};
+use kay::{ActorSystem, World, External, Fate};
 use imgui::Ui;
 use core::simulation::{TimeOfDay, Seconds};
 use economy::resources::{ResourceAmount, ResourceMap, Entry, r_id, r_properties, r_info,
                          all_resource_ids};
 use economy::market::{Deal, OfferID};
-use economy::buildings::BuildingID;
+use economy::buildings::{BuildingID, MAX_GROWTH_LEVEL};
 use economy::buildings::rendering::BuildingInspectorID;
 use transport::pathfinding::RoughLocationID;
 
 use super::{Household, HouseholdID, MemberIdx, MSG_Household_decay, MSG_Household_inspect,
             MSG_Household_provide_deal, MSG_Household_receive_deal, MSG_Household_task_succeeded,
-            MSG_Household_task_failed};
+            MSG_Household_task_failed, MSG_Household_report_satisfaction,
+            MSG_Household_on_home_demolished, MSG_Household_on_offer_withdrawn,
+            MSG_Household_report_commutes};
+use super::satisfaction::SatisfactionSurveyID;
+use economy::jobs_housing::JobsHousingBalanceID;
+use economy::businesses::BusinessRegistryID;
+use descartes::P2;
+use economy::demographics::DemographicsID;
+
+const JOBS_PER_SHOP: usize = 8;
+/// Shops grow a level bigger with every this many jobs
+const JOBS_PER_GROWTH_LEVEL: usize = 6;
+
+fn growth_level(n_jobs: usize) -> u8 {
+    (1 + n_jobs / JOBS_PER_GROWTH_LEVEL).min(MAX_GROWTH_LEVEL as usize) as u8
+}
 
 #[derive(Compact, Clone)]
 pub struct GroceryShop {
@@ -19,38 +34,93 @@ pub struct GroceryShop {
     resources: ResourceMap<ResourceAmount>,
     grocery_offer: OfferID,
     job_offer: OfferID,
+    n_jobs: usize,
+    /// Money at the last business review, to tell the profit since then
+    money_at_review: f32,
+}
+
+fn register_offers(id: GroceryShopID, site: BuildingID, world: &mut World) -> (OfferID, OfferID) {
+    let grocery_offer = OfferID::register(
+        id.into(),
+        site.into(),
+        TimeOfDay::new(7, 0),
+        TimeOfDay::new(20, 0),
+        Deal::new(
+            (r_id("groceries"), 30.0),
+            vec![(r_id("money"), 40.0)],
+            Seconds(5 * 60),
+        ),
+        world,
+    );
+    let job_offer = OfferID::register(
+        id.into(),
+        site.into(),
+        TimeOfDay::new(7, 0),
+        TimeOfDay::new(20, 0),
+        Deal::new((r_id("money"), 50.0), None, Seconds(5 * 60 * 60)),
+        world,
+    );
+    (grocery_offer, job_offer)
 }
 
 impl GroceryShop {
     pub fn move_into(id: GroceryShopID, site: BuildingID, world: &mut World) -> GroceryShop {
+        let (grocery_offer, job_offer) = register_offers(id, site, world);
+        grocery_offer.set_attractiveness(JOBS_PER_SHOP as f32, world);
+        site.set_growth_level(growth_level(JOBS_PER_SHOP), world);
         GroceryShop {
             id,
             site,
             resources: ResourceMap::new(),
-            grocery_offer: OfferID::register(
-                id.into(),
-                site.into(),
-                TimeOfDay::new(7, 0),
-                TimeOfDay::new(20, 0),
-                Deal::new(
-                    (r_id("groceries"), 30.0),
-                    vec![(r_id("money"), 40.0)],
-                    Seconds(5 * 60),
-                ),
-                world,
-            ),
-            job_offer: OfferID::register(
-                id.into(),
-                site.into(),
-                TimeOfDay::new(7, 0),
-                TimeOfDay::new(20, 0),
-                Deal::new((r_id("money"), 50.0), None, Seconds(5 * 60 * 60)),
-                world,
-            ),
+            grocery_offer,
+            job_offer,
+            n_jobs: JOBS_PER_SHOP,
+            money_at_review: 0.0,
         }
     }
 }
 
+impl GroceryShop {
+    pub fn report_to_census(&mut self, demographics: DemographicsID, world: &mut World) {
+        demographics.add_jobs(self.n_jobs, world);
+    }
+
+    pub fn report_performance(&mut self, registry: BusinessRegistryID, world: &mut World) {
+        let money = self.resources.get(r_id("money")).cloned().unwrap_or(0.0);
+        let profit = money - self.money_at_review;
+        self.money_at_review = money;
+        registry.add_business(self.id, self.site, profit, self.n_jobs, world);
+    }
+
+    pub fn resize(&mut self, n_jobs: usize, world: &mut World) {
+        self.n_jobs = n_jobs;
+        // bigger shops draw customers from further away
+        self.grocery_offer.set_attractiveness(n_jobs as f32, world);
+        self.site.set_growth_level(growth_level(n_jobs), world);
+    }
+
+    pub fn close(&mut self, world: &mut World) -> Fate {
+        self.grocery_offer.withdraw(world);
+        self.job_offer.withdraw(world);
+        self.site.remove_household(self.id.into(), world);
+        Fate::Die
+    }
+
+    pub fn relocate(&mut self, new_site: BuildingID, world: &mut World) {
+        self.grocery_offer.withdraw(world);
+        self.job_offer.withdraw(world);
+        self.site.remove_household(self.id.into(), world);
+
+        let (grocery_offer, job_offer) = register_offers(self.id, new_site, world);
+        grocery_offer.set_attractiveness(self.n_jobs as f32, world);
+        new_site.set_growth_level(growth_level(self.n_jobs), world);
+        self.grocery_offer = grocery_offer;
+        self.job_offer = job_offer;
+        self.site = new_site;
+        new_site.add_household(self.id.into(), world);
+    }
+}
+
 impl Household for GroceryShop {
     fn receive_deal(&mut self, _deal: &Deal, _member: MemberIdx, _: &mut World) {
         unimplemented!()
@@ -69,10 +139,31 @@ impl Household for GroceryShop {
         unimplemented!()
     }
 
+    fn report_satisfaction(&mut self, _survey: SatisfactionSurveyID, _home: P2, _: &mut World) {
+        // shops don't have opinions (yet)
+    }
+
     fn task_failed(&mut self, _member: MemberIdx, _location: RoughLocationID, _: &mut World) {
         unimplemented!()
     }
 
+    fn on_home_demolished(&mut self, world: &mut World) -> Fate {
+        self.grocery_offer.withdraw(world);
+        self.job_offer.withdraw(world);
+        Fate::Die
+    }
+
+    fn on_offer_withdrawn(&mut self, _offer: OfferID, _: &mut World) {}
+
+    fn report_commutes(
+        &mut self,
+        balance: JobsHousingBalanceID,
+        position: P2,
+        world: &mut World,
+    ) {
+        balance.add_workplace(self.job_offer, position, self.n_jobs, world);
+    }
+
     fn decay(&mut self, dt: Seconds, _: &mut World) {
         let groceries = self.resources.mut_entry_or(r_id("groceries"), 0.0);
         *groceries += 0.001 * dt.seconds() as f32;
@@ -109,6 +200,7 @@ impl Household for GroceryShop {
 
 pub fn setup(system: &mut ActorSystem) {
     system.register::<GroceryShop>();
+    system.make_restorable::<GroceryShop>();
     auto_setup(system);
 }
 
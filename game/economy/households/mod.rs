@@ -1,20 +1,34 @@
-use kay::{ActorSystem, World};
-use core::simulation::Seconds;
+use kay::{ActorSystem, World, Fate};
+use core::simulation::{Seconds, SimulationID};
 
 use transport::pathfinding::RoughLocationID;
 
 pub mod tasks;
 pub mod family;
 pub mod grocery_shop;
+pub mod citizen_inspector;
+pub mod satisfaction;
+pub mod cargo_terminal;
+pub mod airport;
+pub mod emergency_station;
+pub mod school;
+pub mod park;
+pub mod park_and_ride;
+pub mod venue;
 
 #[derive(Copy, Clone, PartialEq, Eq)]
 pub struct MemberIdx(usize);
 
 use imgui::Ui;
 use kay::External;
+use stagemaster::UserInterfaceID;
+use monet::RendererID;
 
-use super::market::Deal;
+use super::market::{Deal, OfferID};
 use super::buildings::rendering::BuildingInspectorID;
+use self::satisfaction::SatisfactionSurveyID;
+use super::jobs_housing::JobsHousingBalanceID;
+use descartes::P2;
 
 pub trait Household {
     fn receive_deal(&mut self, deal: &Deal, member: MemberIdx, world: &mut World);
@@ -28,13 +42,34 @@ pub trait Household {
         return_to: BuildingInspectorID,
         world: &mut World,
     );
+    fn report_satisfaction(&mut self, survey: SatisfactionSurveyID, home: P2, world: &mut World);
+    /// Workers (for families) or jobs (for workplaces) at `position`
+    fn report_commutes(&mut self, balance: JobsHousingBalanceID, position: P2, world: &mut World);
+    fn on_home_demolished(&mut self, world: &mut World) -> Fate;
+    fn on_offer_withdrawn(&mut self, offer: OfferID, world: &mut World);
 }
 
-pub fn setup(system: &mut ActorSystem) {
+pub fn setup(
+    system: &mut ActorSystem,
+    user_interface: UserInterfaceID,
+    renderer_id: RendererID,
+    simulation: SimulationID,
+) {
     auto_setup(system);
+    // households draw themselves into the building inspector
+    system.prioritize::<MSG_Household_inspect>();
     tasks::setup(system);
     family::setup(system);
     grocery_shop::setup(system);
+    citizen_inspector::setup(system, user_interface, renderer_id);
+    satisfaction::setup(system, simulation);
+    cargo_terminal::setup(system);
+    airport::setup(system);
+    emergency_station::setup(system);
+    school::setup(system);
+    park::setup(system);
+    park_and_ride::setup(system);
+    venue::setup(system);
 }
 
 mod kay_auto;
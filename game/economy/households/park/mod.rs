@@ -0,0 +1,190 @@
+use kay::{ActorSystem, World, External, Fate};
+use compact::CVec;
+use imgui::Ui;
+use descartes::P2;
+use core::simulation::{SimulationID, Sleeper, SleeperID, MSG_Sleeper_wake, Timestamp, Ticks,
+                       TimeOfDay, Seconds};
+use economy::resources::r_id;
+use economy::market::{Deal, OfferID};
+use economy::buildings::BuildingID;
+use economy::buildings::rendering::BuildingInspectorID;
+use transport::pathfinding::RoughLocationID;
+use transport::pathfinding::active_modes::{ActiveModeGraphID, WalkingCatchmentRequester,
+                                           WalkingCatchmentRequesterID,
+                                           MSG_WalkingCatchmentRequester_on_walking_catchment};
+
+use super::{Household, HouseholdID, MemberIdx, MSG_Household_decay, MSG_Household_inspect,
+            MSG_Household_provide_deal, MSG_Household_receive_deal, MSG_Household_task_succeeded,
+            MSG_Household_task_failed, MSG_Household_report_satisfaction,
+            MSG_Household_on_home_demolished, MSG_Household_on_offer_withdrawn,
+            MSG_Household_report_commutes};
+use super::satisfaction::SatisfactionSurveyID;
+use economy::jobs_housing::JobsHousingBalanceID;
+
+/// Homes within this many minutes of walking count as having access to a park,
+/// which makes them more valuable and their residents happier
+pub const WALKING_MINUTES: f32 = 10.0;
+/// Every night, the park finds out again which homes can walk to it, to include new ones
+const ACCESS_UPDATE: (usize, usize) = (4, 0);
+const OPENING: (usize, usize) = (8, 0);
+const EVENING: (usize, usize) = (17, 0);
+const CLOSING: (usize, usize) = (22, 0);
+const BASE_ATTRACTIVENESS: f32 = 4.0;
+/// People have the most time for a walk in the park after work and on weekends
+const EVENING_BOOST: f32 = 2.0;
+const WEEKEND_BOOST: f32 = 2.5;
+const VISIT_MINUTES: usize = 60;
+
+/// A park in place of a building. Its visitors get entertainment and a nicer environment
+/// for free, mostly in the evening and on weekends, and homes that can walk to it are
+/// worth more and make their residents happier, see `Building::on_park_access`
+#[derive(Compact, Clone)]
+pub struct Park {
+    id: ParkID,
+    position: P2,
+    entertainment_offer: OfferID,
+    environment_offer: OfferID,
+    visits_today: usize,
+    visits_yesterday: usize,
+    /// Points of the walking graph from which the park is within `WALKING_MINUTES`
+    n_reachable: usize,
+}
+
+fn register_offer(id: ParkID, site: BuildingID, resource: &str, world: &mut World) -> OfferID {
+    OfferID::register(
+        id.into(),
+        site.into(),
+        TimeOfDay::new(OPENING.0, OPENING.1),
+        TimeOfDay::new(CLOSING.0, CLOSING.1),
+        Deal::new((r_id(resource), 3.0), None, Seconds(VISIT_MINUTES * 60)),
+        world,
+    )
+}
+
+impl Park {
+    pub fn open(id: ParkID, site: BuildingID, position: P2, world: &mut World) -> Park {
+        SimulationID::local_first(world).wake_up_in(Ticks(0), id.into(), world);
+
+        Park {
+            id,
+            position,
+            entertainment_offer: register_offer(id, site, "entertainment", world),
+            environment_offer: register_offer(id, site, "environment", world),
+            visits_today: 0,
+            visits_yesterday: 0,
+            n_reachable: 0,
+        }
+    }
+
+    fn update_attractiveness(&self, tick: Timestamp, world: &mut World) {
+        let now = TimeOfDay::from_tick(tick).hours_minutes();
+        let evening_boost = if now >= EVENING { EVENING_BOOST } else { 1.0 };
+        let weekend_boost = if tick.is_weekend() { WEEKEND_BOOST } else { 1.0 };
+        let attractiveness = BASE_ATTRACTIVENESS * evening_boost * weekend_boost;
+
+        self.entertainment_offer.set_attractiveness(attractiveness, world);
+        self.environment_offer.set_attractiveness(attractiveness, world);
+    }
+
+    fn wake_up_for_next_event(&self, current_tick: Timestamp, world: &mut World) {
+        let next = [ACCESS_UPDATE, OPENING, EVENING]
+            .iter()
+            .map(|&(h, m)| TimeOfDay::new(h, m).next_after(current_tick))
+            .min()
+            .expect("parks have events");
+
+        SimulationID::local_first(world).wake_up_at(next, self.id.into(), world);
+    }
+}
+
+impl Sleeper for Park {
+    fn wake(&mut self, current_tick: Timestamp, world: &mut World) {
+        let now = TimeOfDay::from_tick(current_tick).hours_minutes();
+
+        // also right after opening, so homes learn about the new park immediately
+        if now == ACCESS_UPDATE || self.n_reachable == 0 {
+            ActiveModeGraphID::local_first(world).find_walking_catchment(
+                self.position,
+                WALKING_MINUTES,
+                self.id.into(),
+                world,
+            );
+        }
+        if now == ACCESS_UPDATE {
+            self.visits_yesterday = self.visits_today;
+            self.visits_today = 0;
+        }
+
+        self.update_attractiveness(current_tick, world);
+        self.wake_up_for_next_event(current_tick, world);
+    }
+}
+
+impl WalkingCatchmentRequester for Park {
+    fn on_walking_catchment(&mut self, reachable: &CVec<(P2, f32)>, world: &mut World) {
+        self.n_reachable = reachable.len();
+        BuildingID::global_broadcast(world).on_park_access(self.id, reachable.clone(), world);
+    }
+}
+
+impl Household for Park {
+    fn receive_deal(&mut self, _deal: &Deal, _member: MemberIdx, _: &mut World) {}
+
+    fn provide_deal(&mut self, _deal: &Deal, _: &mut World) {
+        self.visits_today += 1;
+    }
+
+    fn decay(&mut self, _dt: Seconds, _: &mut World) {}
+
+    fn task_succeeded(&mut self, _member: MemberIdx, _: &mut World) {}
+
+    fn task_failed(&mut self, _member: MemberIdx, _location: RoughLocationID, _: &mut World) {}
+
+    fn report_satisfaction(&mut self, _survey: SatisfactionSurveyID, _home: P2, _: &mut World) {}
+
+    fn on_home_demolished(&mut self, world: &mut World) -> Fate {
+        self.entertainment_offer.withdraw(world);
+        self.environment_offer.withdraw(world);
+        BuildingID::global_broadcast(world).leave_park_access(self.id, world);
+        SimulationID::local_first(world).forget_wake_ups(self.id.into(), world);
+        Fate::Die
+    }
+
+    fn on_offer_withdrawn(&mut self, _offer: OfferID, _: &mut World) {}
+
+    fn report_commutes(&mut self, _balance: JobsHousingBalanceID, _home: P2, _: &mut World) {}
+
+    fn inspect(
+        &mut self,
+        imgui_ui: &External<Ui<'static>>,
+        return_to: BuildingInspectorID,
+        world: &mut World,
+    ) {
+        let ui = imgui_ui.steal();
+
+        ui.window(im_str!("Building")).build(|| {
+            ui.text(im_str!("Park ID: {:?}", self.id._raw_id));
+            ui.text(im_str!(
+                "Visits: {} today, {} yesterday",
+                self.visits_today,
+                self.visits_yesterday
+            ));
+            ui.text(im_str!(
+                "Sidewalks within {} min walk: {}",
+                WALKING_MINUTES,
+                self.n_reachable
+            ));
+        });
+
+        return_to.ui_drawn(ui, world);
+    }
+}
+
+pub fn setup(system: &mut ActorSystem) {
+    system.register::<Park>();
+    system.make_restorable::<Park>();
+    auto_setup(system);
+}
+
+mod kay_auto;
+pub use self::kay_auto::*;
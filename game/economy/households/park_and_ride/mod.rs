@@ -0,0 +1,105 @@
+use kay::{ActorSystem, World, External, Fate};
+use imgui::Ui;
+use descartes::P2;
+use core::simulation::Seconds;
+use economy::market::{Deal, OfferID};
+use economy::buildings::BuildingID;
+use economy::buildings::rendering::BuildingInspectorID;
+use transport::pathfinding::RoughLocationID;
+use transport::pathfinding::mode_choice::ModeChoiceID;
+use transport::export::NetworkExporterID;
+
+use super::{Household, HouseholdID, MemberIdx, MSG_Household_decay, MSG_Household_inspect,
+            MSG_Household_provide_deal, MSG_Household_receive_deal, MSG_Household_task_succeeded,
+            MSG_Household_task_failed, MSG_Household_report_satisfaction,
+            MSG_Household_on_home_demolished, MSG_Household_on_offer_withdrawn,
+            MSG_Household_report_commutes};
+use super::satisfaction::SatisfactionSurveyID;
+use economy::jobs_housing::JobsHousingBalanceID;
+
+const PARKING_SPACES: usize = 200;
+
+/// A parking lot at a transit station: travellers drive here, park
+/// and continue by transit. Mode choice keeps track of the free spaces,
+/// so it only offers park-and-ride as long as there are some
+#[derive(Compact, Clone)]
+pub struct ParkAndRide {
+    id: ParkAndRideID,
+    site: BuildingID,
+    position: P2,
+    parked: usize,
+}
+
+impl ParkAndRide {
+    pub fn open(
+        id: ParkAndRideID,
+        site: BuildingID,
+        position: P2,
+        world: &mut World,
+    ) -> ParkAndRide {
+        ModeChoiceID::local_first(world).add_park_and_ride(id, site, PARKING_SPACES, world);
+
+        ParkAndRide { id, site, position, parked: 0 }
+    }
+
+    pub fn parked_cars_changed(&mut self, parked: usize, _: &mut World) {
+        self.parked = parked;
+    }
+
+    pub fn report_for_export(&mut self, exporter: NetworkExporterID, world: &mut World) {
+        exporter.add_station(self.id._raw_id.instance_id, self.position, world);
+    }
+}
+
+impl Household for ParkAndRide {
+    fn receive_deal(&mut self, _deal: &Deal, _member: MemberIdx, _: &mut World) {}
+
+    fn provide_deal(&mut self, _deal: &Deal, _: &mut World) {}
+
+    fn decay(&mut self, _dt: Seconds, _: &mut World) {}
+
+    fn task_succeeded(&mut self, _member: MemberIdx, _: &mut World) {}
+
+    fn task_failed(&mut self, _member: MemberIdx, _location: RoughLocationID, _: &mut World) {}
+
+    fn report_satisfaction(&mut self, _survey: SatisfactionSurveyID, _home: P2, _: &mut World) {}
+
+    fn on_home_demolished(&mut self, world: &mut World) -> Fate {
+        ModeChoiceID::local_first(world).remove_park_and_ride(self.id, world);
+        Fate::Die
+    }
+
+    fn on_offer_withdrawn(&mut self, _offer: OfferID, _: &mut World) {}
+
+    fn report_commutes(&mut self, _balance: JobsHousingBalanceID, _home: P2, _: &mut World) {}
+
+    fn inspect(
+        &mut self,
+        imgui_ui: &External<Ui<'static>>,
+        return_to: BuildingInspectorID,
+        world: &mut World,
+    ) {
+        let ui = imgui_ui.steal();
+
+        ui.window(im_str!("Building")).build(|| {
+            ui.text(im_str!("Park & Ride ID: {:?}", self.id._raw_id));
+            ui.text(im_str!("Site: {:?}", self.site._raw_id));
+            ui.text(im_str!(
+                "Parked cars: {} of {}",
+                self.parked,
+                PARKING_SPACES
+            ));
+        });
+
+        return_to.ui_drawn(ui, world);
+    }
+}
+
+pub fn setup(system: &mut ActorSystem) {
+    system.register::<ParkAndRide>();
+    system.make_restorable::<ParkAndRide>();
+    auto_setup(system);
+}
+
+mod kay_auto;
+pub use self::kay_auto::*;
@@ -0,0 +1,272 @@
+use kay::{ActorSystem, World};
+use compact::{CVec, CDict};
+use descartes::{N, P2, FiniteCurve, Norm};
+use stagemaster::UserInterfaceID;
+use core::simulation::{SimulationID, Sleeper, SleeperID, MSG_Sleeper_wake, Timestamp, Ticks,
+                       TICKS_PER_SIM_MINUTE};
+use transport::lane::{Lane, LaneID};
+use economy::buildings::BuildingID;
+use economy::demographics::DemographicsID;
+
+use super::family::FamilyID;
+
+const SURVEY_INTERVAL: Ticks = Ticks(3000);
+const COLLECTION_TICKS: Ticks = Ticks(10);
+// a commute of this many minutes halves the commute score
+const COMFORTABLE_COMMUTE_MINUTES: f32 = 30.0;
+const NOISE_DISTANCE: N = 50.0;
+// the filth of buildings within this distance makes a neighbourhood less pleasant
+const FILTH_DISTANCE: N = 50.0;
+// this many cars within noise distance make a home unbearably loud
+const CARS_FOR_FULL_NOISE: f32 = 30.0;
+const DISTRICT_SIZE: N = 500.0;
+const MOVE_OUT_THRESHOLD: f32 = 0.25;
+
+#[derive(Copy, Clone)]
+pub struct SurveyResponse {
+    pub family: FamilyID,
+    pub home: P2,
+    pub average_trip_ticks: f32,
+    pub trip_failure_rate: f32,
+    pub has_grocery_shop: bool,
+}
+
+impl SurveyResponse {
+    fn commute_score(&self) -> f32 {
+        let commute_minutes = self.average_trip_ticks / TICKS_PER_SIM_MINUTE as f32;
+        1.0 / (1.0 + commute_minutes / COMFORTABLE_COMMUTE_MINUTES)
+    }
+
+    /// Overall satisfaction between 0.0 (miserable) and 1.0 (perfectly happy)
+    fn satisfaction(&self, noise: f32, filth: f32, utility_supply: f32, green_space: f32) -> f32 {
+        let commute = self.commute_score();
+        let reliability = 1.0 - self.trip_failure_rate;
+        let shop_access = if self.has_grocery_shop { 1.0 } else { 0.3 };
+        let quietness = 1.0 - noise;
+        let cleanliness = 1.0 - filth;
+
+        0.15 * commute + 0.15 * reliability + 0.15 * shop_access + 0.15 * quietness +
+            0.15 * cleanliness + 0.15 * utility_supply + 0.1 * green_space
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum SurveyPhase {
+    Idle,
+    CollectingTraffic,
+    CollectingResponses,
+}
+
+/// Periodically asks all households how happy they are, shows the results per district
+/// and tells demographics which families are unhappy enough to move away
+#[derive(Compact, Clone)]
+pub struct SatisfactionSurvey {
+    id: SatisfactionSurveyID,
+    simulation: SimulationID,
+    phase: SurveyPhase,
+    traffic: CVec<(P2, usize)>,
+    filth: CVec<(P2, f32)>,
+    utility_supply: CVec<(P2, f32)>,
+    green_space: CVec<(P2, f32)>,
+    responses: CVec<SurveyResponse>,
+}
+
+impl Lane {
+    pub fn report_traffic_to_survey(&mut self, survey: SatisfactionSurveyID, world: &mut World) {
+        if !self.microtraffic.cars.is_empty() {
+            survey.add_traffic(
+                self.construction.path.along(self.construction.length / 2.0),
+                self.microtraffic.cars.len(),
+                world,
+            );
+        }
+    }
+}
+
+impl SatisfactionSurvey {
+    pub fn spawn(
+        id: SatisfactionSurveyID,
+        simulation: SimulationID,
+        world: &mut World,
+    ) -> SatisfactionSurvey {
+        simulation.wake_up_in(SURVEY_INTERVAL, id.into(), world);
+
+        SatisfactionSurvey {
+            id,
+            simulation,
+            phase: SurveyPhase::Idle,
+            traffic: CVec::new(),
+            filth: CVec::new(),
+            utility_supply: CVec::new(),
+            green_space: CVec::new(),
+            responses: CVec::new(),
+        }
+    }
+
+    pub fn add_traffic(&mut self, position: P2, n_cars: usize, _: &mut World) {
+        if self.phase == SurveyPhase::CollectingTraffic {
+            self.traffic.push((position, n_cars));
+        }
+    }
+
+    pub fn add_response(&mut self, response: &SurveyResponse, _: &mut World) {
+        if self.phase == SurveyPhase::CollectingResponses {
+            self.responses.push(*response);
+        }
+    }
+
+    pub fn add_filth(&mut self, position: P2, filth: f32, _: &mut World) {
+        if self.phase == SurveyPhase::CollectingResponses {
+            self.filth.push((position, filth));
+        }
+    }
+
+    pub fn add_utility_supply(&mut self, position: P2, supply: f32, _: &mut World) {
+        if self.phase == SurveyPhase::CollectingResponses {
+            self.utility_supply.push((position, supply));
+        }
+    }
+
+    /// How close the building at `position` is to a park, between 0.0 (none within
+    /// walking distance) and 1.0 (right next to one), see `Building::green_space_access`
+    pub fn add_green_space(&mut self, position: P2, access: f32, _: &mut World) {
+        if self.phase == SurveyPhase::CollectingResponses {
+            self.green_space.push((position, access));
+        }
+    }
+
+    fn green_space_at(&self, home: P2) -> f32 {
+        self.green_space
+            .iter()
+            .find(|&&(position, _)| position == home)
+            .map(|&(_, access)| access)
+            .unwrap_or(0.0)
+    }
+
+    /// Utilities reaching the building at `home`
+    fn utility_supply_at(&self, home: P2) -> f32 {
+        self.utility_supply
+            .iter()
+            .find(|&&(position, _)| position == home)
+            .map(|&(_, supply)| supply)
+            .unwrap_or(1.0)
+    }
+
+    /// Average filth of the buildings around `home`
+    fn filth_at(&self, home: P2) -> f32 {
+        let (sum, n) = self.filth
+            .iter()
+            .filter(|&&(position, _)| (position - home).norm() < FILTH_DISTANCE)
+            .fold((0.0, 0), |(sum, n), &(_, filth)| (sum + filth, n + 1));
+        if n == 0 { 0.0 } else { sum / n as f32 }
+    }
+
+    fn noise_at(&self, home: P2) -> f32 {
+        let nearby_cars: usize = self.traffic
+            .iter()
+            .filter(|&&(position, _)| (position - home).norm() < NOISE_DISTANCE)
+            .map(|&(_, n_cars)| n_cars)
+            .sum();
+        (nearby_cars as f32 / CARS_FOR_FULL_NOISE).min(1.0)
+    }
+
+    fn analyze(&mut self, world: &mut World) {
+        let mut districts = CDict::<(i32, i32), (f32, usize)>::new();
+        let mut total = 0.0;
+        let mut total_commute_score = 0.0;
+        let mut unhappy_families = CVec::new();
+
+        for response in self.responses.iter() {
+            let satisfaction = response.satisfaction(
+                self.noise_at(response.home),
+                self.filth_at(response.home),
+                self.utility_supply_at(response.home),
+                self.green_space_at(response.home),
+            );
+            total += satisfaction;
+            total_commute_score += response.commute_score();
+
+            let district = (
+                (response.home.x / DISTRICT_SIZE).floor() as i32,
+                (response.home.y / DISTRICT_SIZE).floor() as i32,
+            );
+            let (sum, n) = districts.get(district).cloned().unwrap_or((0.0, 0));
+            districts.insert(district, (sum + satisfaction, n + 1));
+
+            if satisfaction < MOVE_OUT_THRESHOLD {
+                unhappy_families.push(response.family);
+            }
+        }
+
+        if !self.responses.is_empty() {
+            DemographicsID::local_first(world).survey_completed(
+                total_commute_score / self.responses.len() as f32,
+                unhappy_families,
+                world,
+            );
+        }
+
+        let text = if self.responses.is_empty() {
+            "no households".to_owned()
+        } else {
+            let worst_district = districts
+                .pairs()
+                .map(|(&district, &(sum, n))| (district, sum / n as f32))
+                .min_by(|&(_, a), &(_, b)| a.partial_cmp(&b).unwrap())
+                .expect("at least one district if there are responses");
+            format!(
+                "city: {:.0}%, worst district ({}, {}): {:.0}%",
+                100.0 * total / self.responses.len() as f32,
+                (worst_district.0).0,
+                (worst_district.0).1,
+                100.0 * worst_district.1
+            )
+        };
+
+        UserInterfaceID::local_first(world).add_debug_text(
+            "Satisfaction".chars().collect(),
+            text.chars().collect(),
+            [0.0, 0.0, 0.0, 1.0],
+            true,
+            world,
+        );
+    }
+}
+
+impl Sleeper for SatisfactionSurvey {
+    fn wake(&mut self, _current_tick: Timestamp, world: &mut World) {
+        match self.phase {
+            SurveyPhase::Idle => {
+                self.phase = SurveyPhase::CollectingTraffic;
+                self.traffic = CVec::new();
+                LaneID::global_broadcast(world).report_traffic_to_survey(self.id, world);
+                self.simulation.wake_up_in(COLLECTION_TICKS, self.id.into(), world);
+            }
+            SurveyPhase::CollectingTraffic => {
+                self.phase = SurveyPhase::CollectingResponses;
+                self.responses = CVec::new();
+                self.filth = CVec::new();
+                self.utility_supply = CVec::new();
+                self.green_space = CVec::new();
+                BuildingID::global_broadcast(world).survey_households(self.id, world);
+                self.simulation.wake_up_in(COLLECTION_TICKS, self.id.into(), world);
+            }
+            SurveyPhase::CollectingResponses => {
+                self.phase = SurveyPhase::Idle;
+                self.analyze(world);
+                self.simulation.wake_up_in(SURVEY_INTERVAL, self.id.into(), world);
+            }
+        }
+    }
+}
+
+pub fn setup(system: &mut ActorSystem, simulation: SimulationID) {
+    system.register::<SatisfactionSurvey>();
+    system.make_restorable::<SatisfactionSurvey>();
+    auto_setup(system);
+
+    SatisfactionSurveyID::spawn(simulation, &mut system.world());
+}
+
+mod kay_auto;
+pub use self::kay_auto::*;
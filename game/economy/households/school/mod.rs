@@ -0,0 +1,286 @@
+use kay::{ActorSystem, World, External, Fate};
+use compact::CVec;
+use imgui::Ui;
+use descartes::P2;
+use core::simulation::{SimulationID, Sleeper, SleeperID, MSG_Sleeper_wake, Timestamp, Ticks,
+                       TimeOfDay, Seconds};
+use economy::market::{Deal, OfferID};
+use economy::buildings::BuildingID;
+use economy::buildings::rendering::BuildingInspectorID;
+use transport::pathfinding::RoughLocationID;
+use transport::pathfinding::trip::{TripID, TripListener, TripListenerID,
+                                   MSG_TripListener_trip_created, MSG_TripListener_trip_result};
+use transport::restrictions::VehicleClass;
+
+use super::{Household, HouseholdID, MemberIdx, MSG_Household_decay, MSG_Household_inspect,
+            MSG_Household_provide_deal, MSG_Household_receive_deal, MSG_Household_task_succeeded,
+            MSG_Household_task_failed, MSG_Household_report_satisfaction,
+            MSG_Household_on_home_demolished, MSG_Household_on_offer_withdrawn,
+            MSG_Household_report_commutes};
+use super::satisfaction::SatisfactionSurveyID;
+use economy::jobs_housing::JobsHousingBalanceID;
+
+/// Pupils leave home for school at this time (hours, minutes), to be there for the bell
+const MORNING_DEPARTURE: (usize, usize) = (7, 30);
+/// School ends and pupils head home at this time (hours, minutes)
+const AFTERNOON_DISMISSAL: (usize, usize) = (15, 0);
+/// Every night, the school claims the buildings around it again, to include new ones
+const CATCHMENT_UPDATE: (usize, usize) = (3, 0);
+/// Pupils living closer than this walk to school
+const WALKING_DISTANCE: f32 = 800.0;
+/// Of the pupils living too far away to walk, this share takes the school bus,
+/// the others are brought and picked up by a parent
+const SCHOOL_BUS_SHARE: f32 = 0.4;
+const SCHOOL_BUS_CAPACITY: usize = 40;
+const COLLECTION_TICKS: Ticks = Ticks(10);
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Wave {
+    Morning,
+    Afternoon,
+}
+
+#[derive(Compact, Clone)]
+struct Pupils {
+    home: BuildingID,
+    n_children: usize,
+    distance: f32,
+}
+
+#[derive(Copy, Clone, Default)]
+struct WaveModes {
+    walked: usize,
+    driven: usize,
+    by_bus: usize,
+}
+
+/// A school in a building. Every building closer to it than to any other school
+/// is in its catchment area and sends its children here. All of them arrive in the
+/// morning and leave in the afternoon at the same time, either walking, being driven by
+/// a parent (who then drives back) or by school bus, which makes for sharp traffic peaks
+#[derive(Compact, Clone)]
+pub struct School {
+    id: SchoolID,
+    site: BuildingID,
+    position: P2,
+    collecting_for: Option<Wave>,
+    pupils: CVec<Pupils>,
+    /// Parents on their way to school, who will drive back home from there
+    parent_runs: CVec<(TripID, BuildingID)>,
+    enrolled: usize,
+    last_morning: WaveModes,
+    last_afternoon: WaveModes,
+}
+
+impl School {
+    pub fn open(id: SchoolID, site: BuildingID, position: P2, world: &mut World) -> School {
+        BuildingID::global_broadcast(world).claim_for_catchment(id, position, world);
+        SimulationID::local_first(world).wake_up_in(Ticks(0), id.into(), world);
+
+        School {
+            id,
+            site,
+            position,
+            collecting_for: None,
+            pupils: CVec::new(),
+            parent_runs: CVec::new(),
+            enrolled: 0,
+            last_morning: WaveModes::default(),
+            last_afternoon: WaveModes::default(),
+        }
+    }
+
+    pub fn enroll(&mut self, home: BuildingID, n_children: usize, distance: f32, _: &mut World) {
+        if self.collecting_for.is_some() {
+            self.pupils.push(Pupils { home, n_children, distance });
+        }
+    }
+
+    fn send_wave(&mut self, wave: Wave, tick: Timestamp, world: &mut World) {
+        let mut modes = WaveModes::default();
+        let mut bus_riders: Vec<BuildingID> = Vec::new();
+
+        for pupils in self.pupils.iter() {
+            if pupils.distance < WALKING_DISTANCE {
+                modes.walked += pupils.n_children;
+            } else if ::core::random::next_f32() < SCHOOL_BUS_SHARE {
+                modes.by_bus += pupils.n_children;
+                bus_riders.extend(::std::iter::repeat(pupils.home).take(pupils.n_children));
+            } else {
+                // siblings are driven together, the parent drives to school
+                // (to drop off or to pick up) and then back home
+                modes.driven += pupils.n_children;
+                let trip = TripID::spawn(
+                    pupils.home.into(),
+                    self.site.into(),
+                    Some(self.id.into()),
+                    VehicleClass::Car,
+                    tick,
+                    world,
+                );
+                self.parent_runs.push((trip, pupils.home));
+            }
+        }
+
+        // each bus goes between the school and the first home of its busload
+        for busload in bus_riders.chunks(SCHOOL_BUS_CAPACITY) {
+            let (source, destination) = match wave {
+                Wave::Morning => (busload[0], self.site),
+                Wave::Afternoon => (self.site, busload[0]),
+            };
+            TripID::spawn(
+                source.into(),
+                destination.into(),
+                None,
+                VehicleClass::Bus,
+                tick,
+                world,
+            );
+        }
+
+        self.enrolled = self.pupils.iter().map(|pupils| pupils.n_children).sum();
+        match wave {
+            Wave::Morning => self.last_morning = modes,
+            Wave::Afternoon => self.last_afternoon = modes,
+        }
+    }
+
+    fn wake_up_for_next_event(&self, current_tick: Timestamp, world: &mut World) {
+        let next = [CATCHMENT_UPDATE, MORNING_DEPARTURE, AFTERNOON_DISMISSAL]
+            .iter()
+            .map(|&(h, m)| TimeOfDay::new(h, m).next_after(current_tick))
+            .min()
+            .expect("schools have events");
+
+        SimulationID::local_first(world).wake_up_at(next, self.id.into(), world);
+    }
+}
+
+impl Sleeper for School {
+    fn wake(&mut self, current_tick: Timestamp, world: &mut World) {
+        if let Some(wave) = self.collecting_for {
+            self.collecting_for = None;
+            self.send_wave(wave, current_tick, world);
+            self.wake_up_for_next_event(current_tick, world);
+        } else {
+            let now = TimeOfDay::from_tick(current_tick).hours_minutes();
+
+            if now == CATCHMENT_UPDATE {
+                BuildingID::global_broadcast(world).claim_for_catchment(
+                    self.id,
+                    self.position,
+                    world,
+                );
+            }
+
+            self.collecting_for = if now == MORNING_DEPARTURE {
+                Some(Wave::Morning)
+            } else if now == AFTERNOON_DISMISSAL {
+                Some(Wave::Afternoon)
+            } else {
+                None
+            };
+
+            if self.collecting_for.is_some() {
+                self.pupils = CVec::new();
+                BuildingID::global_broadcast(world).report_pupils(self.id, world);
+                SimulationID::local_first(world).wake_up_in(
+                    COLLECTION_TICKS,
+                    self.id.into(),
+                    world,
+                );
+            } else {
+                // just opened, or updated the catchment
+                self.wake_up_for_next_event(current_tick, world);
+            }
+        }
+    }
+}
+
+impl TripListener for School {
+    fn trip_created(&mut self, _trip: TripID, _: &mut World) {}
+
+    fn trip_result(
+        &mut self,
+        trip: TripID,
+        _location: RoughLocationID,
+        _failed: bool,
+        tick: Timestamp,
+        world: &mut World,
+    ) {
+        if let Some(idx) = self.parent_runs.iter().position(|&(run, _)| run == trip) {
+            let (_, home) = self.parent_runs.remove(idx);
+            // even if they didn't make it, parents still want to get back home
+            TripID::spawn(
+                self.site.into(),
+                home.into(),
+                None,
+                VehicleClass::Car,
+                tick,
+                world,
+            );
+        }
+    }
+}
+
+impl Household for School {
+    fn receive_deal(&mut self, _deal: &Deal, _member: MemberIdx, _: &mut World) {}
+
+    fn provide_deal(&mut self, _deal: &Deal, _: &mut World) {}
+
+    fn decay(&mut self, _dt: Seconds, _: &mut World) {}
+
+    fn task_succeeded(&mut self, _member: MemberIdx, _: &mut World) {}
+
+    fn task_failed(&mut self, _member: MemberIdx, _location: RoughLocationID, _: &mut World) {}
+
+    fn report_satisfaction(&mut self, _survey: SatisfactionSurveyID, _home: P2, _: &mut World) {}
+
+    fn on_home_demolished(&mut self, world: &mut World) -> Fate {
+        BuildingID::global_broadcast(world).leave_catchment(self.id, world);
+        SimulationID::local_first(world).forget_wake_ups(self.id.into(), world);
+        Fate::Die
+    }
+
+    fn on_offer_withdrawn(&mut self, _offer: OfferID, _: &mut World) {}
+
+    fn report_commutes(&mut self, _balance: JobsHousingBalanceID, _home: P2, _: &mut World) {}
+
+    fn inspect(
+        &mut self,
+        imgui_ui: &External<Ui<'static>>,
+        return_to: BuildingInspectorID,
+        world: &mut World,
+    ) {
+        let ui = imgui_ui.steal();
+
+        ui.window(im_str!("Building")).build(|| {
+            ui.text(im_str!("School ID: {:?}", self.id._raw_id));
+            ui.text(im_str!("Pupils: {}", self.enrolled));
+            for &(name, modes) in &[
+                ("Morning", self.last_morning),
+                ("Afternoon", self.last_afternoon),
+            ]
+            {
+                ui.text(im_str!(
+                    "{}: {} walked, {} driven, {} by bus",
+                    name,
+                    modes.walked,
+                    modes.driven,
+                    modes.by_bus
+                ));
+            }
+        });
+
+        return_to.ui_drawn(ui, world);
+    }
+}
+
+pub fn setup(system: &mut ActorSystem) {
+    system.register::<School>();
+    system.make_restorable::<School>();
+    auto_setup(system);
+}
+
+mod kay_auto;
+pub use self::kay_auto::*;
@@ -79,6 +79,7 @@ impl Simulatable for TaskEndScheduler {
 
 pub fn setup(system: &mut ActorSystem) {
     system.register::<TaskEndScheduler>();
+    system.make_restorable::<TaskEndScheduler>();
 
     auto_setup(system);
 
@@ -0,0 +1,82 @@
+use descartes::N;
+use core::simulation::{TimeOfDay, Minutes, MINUTES_PER_DAY};
+use core::packages::read_data_tables;
+use super::VenueKind;
+
+/// Attendees leave home this long before an event starts
+pub const ARRIVAL_LEAD_MINUTES: usize = 60;
+
+/// One row of the event tables, something that can happen at every venue of a kind
+pub struct EventSpec {
+    pub venue_kind: VenueKind,
+    pub name: String,
+    pub start: TimeOfDay,
+    pub duration: Minutes,
+    /// How likely the event is to happen on any given day
+    pub chance: f32,
+    pub attendees: usize,
+    /// Lanes this close to the venue are closed during the event, none if 0
+    pub closure_radius: N,
+}
+
+impl EventSpec {
+    /// When attendees leave home
+    pub fn doors_open(&self) -> TimeOfDay {
+        let (h, m) = self.start.hours_minutes();
+        let minute = (h * 60 + m + MINUTES_PER_DAY - ARRIVAL_LEAD_MINUTES) % MINUTES_PER_DAY;
+        TimeOfDay::new(minute / 60, minute % 60)
+    }
+}
+
+static mut EVENT_TABLE: *const Vec<EventSpec> = 0 as *const Vec<EventSpec>;
+
+/// All events of all venue kinds, in the order of the tables
+pub fn event_specs() -> &'static [EventSpec] {
+    unsafe { &*EVENT_TABLE }
+}
+
+fn parse_time(text: &str) -> Option<TimeOfDay> {
+    let mut parts = text.splitn(2, ':');
+    let hours = parts.next().and_then(|hours| hours.parse::<usize>().ok());
+    let minutes = parts.next().and_then(|minutes| minutes.parse::<usize>().ok());
+    match (hours, minutes) {
+        (Some(h), Some(m)) if h < 24 && m < 60 => Some(TimeOfDay::new(h, m)),
+        _ => None,
+    }
+}
+
+pub fn setup() {
+    let mut specs = Box::new(Vec::new());
+
+    for md_table in read_data_tables(&"game/economy/parameters/events/default.data.md", "events")
+        .expect("Expected event table to exist")
+    {
+        let venue_kind = VenueKind::from_name(&md_table.subheader).expect(&format!(
+            "unknown venue kind {}",
+            md_table.subheader
+        ));
+        let c = &md_table.columns;
+        let column = |name: &str| {
+            c.get(name).expect(&format!("no {} column for {}", name, md_table.subheader))
+        };
+
+        for (idx, event) in column("event").iter().enumerate() {
+            let entry = |name: &str| column(name)[idx].as_str();
+            let weird = |name: &str| format!("weird {} for {}", name, event);
+
+            specs.push(EventSpec {
+                venue_kind,
+                name: event.clone(),
+                start: parse_time(entry("start")).expect(&weird("start")),
+                duration: Minutes(entry("duration").parse().expect(&weird("duration"))),
+                chance: entry("chance").parse().expect(&weird("chance")),
+                attendees: entry("attendees").parse().expect(&weird("attendees")),
+                closure_radius: entry("closure radius").parse().expect(
+                    &weird("closure radius"),
+                ),
+            });
+        }
+    }
+
+    unsafe { EVENT_TABLE = Box::into_raw(specs) };
+}
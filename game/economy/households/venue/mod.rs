@@ -0,0 +1,377 @@
+use kay::{ActorSystem, World, External, Fate};
+use compact::CVec;
+use imgui::Ui;
+use descartes::P2;
+use core::simulation::{SimulationID, Reminder, Reminded, RemindedID, MSG_Reminded_remind,
+                       Timestamp, Ticks, Minutes, Seconds};
+use core::city_events::{self, CityEventKind};
+use economy::market::{Deal, OfferID};
+use economy::buildings::{BuildingID, DestinationCollector, DestinationCollectorID,
+                         MSG_DestinationCollector_add_destination};
+use economy::buildings::rendering::BuildingInspectorID;
+use transport::lane::LaneID;
+use transport::pathfinding::RoughLocationID;
+use transport::pathfinding::trip::{TripID, TripListener, TripListenerID,
+                                   MSG_TripListener_trip_created, MSG_TripListener_trip_result};
+use transport::restrictions::VehicleClass;
+
+use super::{Household, HouseholdID, MemberIdx, MSG_Household_decay, MSG_Household_inspect,
+            MSG_Household_provide_deal, MSG_Household_receive_deal, MSG_Household_task_succeeded,
+            MSG_Household_task_failed, MSG_Household_report_satisfaction,
+            MSG_Household_on_home_demolished, MSG_Household_on_offer_withdrawn,
+            MSG_Household_report_commutes};
+use super::satisfaction::SatisfactionSurveyID;
+use economy::jobs_housing::JobsHousingBalanceID;
+
+mod event_table;
+use self::event_table::{event_specs, EventSpec, ARRIVAL_LEAD_MINUTES};
+
+const COLLECTION_TICKS: Ticks = Ticks(10);
+/// Asks a venue that just opened to schedule its events
+const SCHEDULE_EVENTS: Reminder = Reminder(::std::u16::MAX);
+
+/// What kind of special events a venue holds, see `events/default.data.md`
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum VenueKind {
+    /// Games and concerts in the evening
+    Stadium,
+    /// Festivals and markets that close the surrounding streets
+    FestivalGround,
+    /// Start and finish of road races, which close roads far around it
+    RaceCourse,
+}
+
+impl VenueKind {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            VenueKind::Stadium => "Stadium",
+            VenueKind::FestivalGround => "Festival Ground",
+            VenueKind::RaceCourse => "Race Course",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<VenueKind> {
+        [VenueKind::Stadium, VenueKind::FestivalGround, VenueKind::RaceCourse]
+            .iter()
+            .cloned()
+            .find(|kind| kind.name() == name)
+    }
+
+    /// The kind that the conversion tool switches to next
+    pub fn next(&self) -> Option<VenueKind> {
+        match *self {
+            VenueKind::Stadium => Some(VenueKind::FestivalGround),
+            VenueKind::FestivalGround => Some(VenueKind::RaceCourse),
+            VenueKind::RaceCourse => None,
+        }
+    }
+}
+
+/// The steps of an event, each scheduled as a reminder
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Phase {
+    /// Decide whether the event happens today and find out where attendees live
+    DoorsOpen,
+    /// Attendees leave home
+    Arrivals,
+    /// Attendees go back home and closed lanes reopen
+    End,
+}
+
+const PHASES: [Phase; 3] = [Phase::DoorsOpen, Phase::Arrivals, Phase::End];
+
+fn reminder(event_idx: usize, phase: Phase) -> Reminder {
+    let phase_idx = PHASES.iter().position(|&other| other == phase).expect(
+        "all phases are listed",
+    );
+    Reminder((event_idx * PHASES.len() + phase_idx) as u16)
+}
+
+fn event_and_phase(reminder: Reminder) -> (usize, Phase) {
+    let value = reminder.0 as usize;
+    (value / PHASES.len(), PHASES[value % PHASES.len()])
+}
+
+/// A building that holds special events: on the days an event happens, lots of
+/// attendees come from all over the city in a short window and leave together when
+/// it ends, while the lanes around the venue may be closed, see `Lane::close_near`
+#[derive(Compact, Clone)]
+pub struct Venue {
+    id: VenueID,
+    site: BuildingID,
+    kind: VenueKind,
+    position: P2,
+    /// The event collecting attendees or running right now, as index into `event_specs()`
+    current_event: Option<usize>,
+    attendee_homes: CVec<BuildingID>,
+    attendees_arrived: usize,
+    events_held: usize,
+    /// The last event that ended, with how many of its attendees made it there
+    last_event: Option<(usize, usize)>,
+}
+
+impl Venue {
+    pub fn open(
+        id: VenueID,
+        kind: VenueKind,
+        site: BuildingID,
+        position: P2,
+        world: &mut World,
+    ) -> Venue {
+        SimulationID::local_first(world).remind_in(Ticks(0), SCHEDULE_EVENTS, id.into(), world);
+
+        Venue {
+            id,
+            site,
+            kind,
+            position,
+            current_event: None,
+            attendee_homes: CVec::new(),
+            attendees_arrived: 0,
+            events_held: 0,
+            last_event: None,
+        }
+    }
+
+    fn events(&self) -> Vec<(usize, &'static EventSpec)> {
+        event_specs()
+            .iter()
+            .enumerate()
+            .filter(|&(_, spec)| spec.venue_kind == self.kind)
+            .collect()
+    }
+
+    fn schedule_doors_open(&self, event_idx: usize, tick: Timestamp, world: &mut World) {
+        SimulationID::local_first(world).remind_at(
+            event_specs()[event_idx].doors_open().next_after(tick),
+            reminder(event_idx, Phase::DoorsOpen),
+            self.id.into(),
+            world,
+        );
+    }
+
+    fn open_doors(&mut self, event_idx: usize, world: &mut World) {
+        let spec = &event_specs()[event_idx];
+        if self.current_event.is_some() || ::core::random::next_f32() >= spec.chance {
+            return;
+        }
+
+        self.current_event = Some(event_idx);
+        self.attendee_homes = CVec::new();
+        self.attendees_arrived = 0;
+        BuildingID::global_broadcast(world).report_as_destination(self.id.into(), true, world);
+
+        let simulation = SimulationID::local_first(world);
+        simulation.remind_in(
+            COLLECTION_TICKS,
+            reminder(event_idx, Phase::Arrivals),
+            self.id.into(),
+            world,
+        );
+        simulation.remind_in(
+            Minutes(ARRIVAL_LEAD_MINUTES + spec.duration.0).into(),
+            reminder(event_idx, Phase::End),
+            self.id.into(),
+            world,
+        );
+
+        if spec.closure_radius > 0.0 {
+            LaneID::global_broadcast(world).close_near(self.position, spec.closure_radius, world);
+        }
+
+        let (h, m) = spec.start.hours_minutes();
+        city_events::publish(
+            CityEventKind::SpecialEvent,
+            &format!(
+                "{} at the {} at {:02}:{:02}, {} attendees expected",
+                spec.name,
+                self.kind.name(),
+                h,
+                m,
+                spec.attendees
+            ),
+            Some(self.position),
+            world,
+        );
+    }
+
+    fn dispatch_attendees(&mut self, event_idx: usize, tick: Timestamp, world: &mut World) {
+        if self.attendee_homes.is_empty() {
+            return;
+        }
+
+        // from now on, the homes of the actual attendees, so they go back there
+        let homes = (0..event_specs()[event_idx].attendees)
+            .map(|_| {
+                self.attendee_homes[::core::random::gen_range(0, self.attendee_homes.len())]
+            })
+            .collect::<CVec<_>>();
+
+        for home in homes.iter() {
+            TripID::spawn(
+                (*home).into(),
+                self.site.into(),
+                Some(self.id.into()),
+                VehicleClass::Car,
+                tick,
+                world,
+            );
+        }
+
+        self.attendee_homes = homes;
+    }
+
+    fn end_event(&mut self, event_idx: usize, tick: Timestamp, world: &mut World) {
+        let spec = &event_specs()[event_idx];
+
+        for home in self.attendee_homes.iter() {
+            TripID::spawn(
+                self.site.into(),
+                (*home).into(),
+                None,
+                VehicleClass::Car,
+                tick,
+                world,
+            );
+        }
+
+        if spec.closure_radius > 0.0 {
+            LaneID::global_broadcast(world).reopen_near(self.position, spec.closure_radius, world);
+        }
+
+        self.current_event = None;
+        self.attendee_homes = CVec::new();
+        self.events_held += 1;
+        self.last_event = Some((event_idx, self.attendees_arrived));
+    }
+}
+
+impl Reminded for Venue {
+    fn remind(&mut self, reminder: Reminder, current_tick: Timestamp, world: &mut World) {
+        if reminder == SCHEDULE_EVENTS {
+            for (event_idx, _) in self.events() {
+                self.schedule_doors_open(event_idx, current_tick, world);
+            }
+            return;
+        }
+
+        let (event_idx, phase) = event_and_phase(reminder);
+        match phase {
+            Phase::DoorsOpen => {
+                self.open_doors(event_idx, world);
+                // the event may happen again tomorrow
+                self.schedule_doors_open(event_idx, current_tick, world);
+            }
+            Phase::Arrivals => self.dispatch_attendees(event_idx, current_tick, world),
+            Phase::End => self.end_event(event_idx, current_tick, world),
+        }
+    }
+}
+
+impl DestinationCollector for Venue {
+    fn add_destination(&mut self, destination: BuildingID, _: &mut World) {
+        if self.current_event.is_some() {
+            self.attendee_homes.push(destination);
+        }
+    }
+}
+
+impl TripListener for Venue {
+    fn trip_created(&mut self, _trip: TripID, _: &mut World) {}
+
+    fn trip_result(
+        &mut self,
+        _trip: TripID,
+        location: RoughLocationID,
+        failed: bool,
+        _tick: Timestamp,
+        _: &mut World,
+    ) {
+        let site: RoughLocationID = self.site.into();
+        if !failed && location == site {
+            self.attendees_arrived += 1;
+        }
+    }
+}
+
+impl Household for Venue {
+    fn receive_deal(&mut self, _deal: &Deal, _member: MemberIdx, _: &mut World) {}
+
+    fn provide_deal(&mut self, _deal: &Deal, _: &mut World) {}
+
+    fn decay(&mut self, _dt: Seconds, _: &mut World) {}
+
+    fn task_succeeded(&mut self, _member: MemberIdx, _: &mut World) {}
+
+    fn task_failed(&mut self, _member: MemberIdx, _location: RoughLocationID, _: &mut World) {}
+
+    fn report_satisfaction(&mut self, _survey: SatisfactionSurveyID, _home: P2, _: &mut World) {}
+
+    fn on_home_demolished(&mut self, world: &mut World) -> Fate {
+        if let Some(event_idx) = self.current_event {
+            let radius = event_specs()[event_idx].closure_radius;
+            if radius > 0.0 {
+                LaneID::global_broadcast(world).reopen_near(self.position, radius, world);
+            }
+        }
+        SimulationID::local_first(world).forget_reminders(self.id.into(), world);
+        Fate::Die
+    }
+
+    fn on_offer_withdrawn(&mut self, _offer: OfferID, _: &mut World) {}
+
+    fn report_commutes(&mut self, _balance: JobsHousingBalanceID, _home: P2, _: &mut World) {}
+
+    fn inspect(
+        &mut self,
+        imgui_ui: &External<Ui<'static>>,
+        return_to: BuildingInspectorID,
+        world: &mut World,
+    ) {
+        let ui = imgui_ui.steal();
+
+        ui.window(im_str!("Building")).build(|| {
+            ui.text(im_str!("{} ID: {:?}", self.kind.name(), self.id._raw_id));
+            for (_, spec) in self.events() {
+                let (h, m) = spec.start.hours_minutes();
+                ui.text(im_str!(
+                    "{} at {:02}:{:02}, {}% of days",
+                    spec.name,
+                    h,
+                    m,
+                    (spec.chance * 100.0) as usize
+                ));
+            }
+            if let Some(event_idx) = self.current_event {
+                ui.text(im_str!(
+                    "Now: {}, {} attendees arrived",
+                    event_specs()[event_idx].name,
+                    self.attendees_arrived
+                ));
+            }
+            ui.text(im_str!("Events held: {}", self.events_held));
+            if let Some((event_idx, arrived)) = self.last_event {
+                let spec = &event_specs()[event_idx];
+                ui.text(im_str!(
+                    "Last: {}, {} of {} attendees arrived",
+                    spec.name,
+                    arrived,
+                    spec.attendees
+                ));
+            }
+        });
+
+        return_to.ui_drawn(ui, world);
+    }
+}
+
+pub fn setup(system: &mut ActorSystem) {
+    event_table::setup();
+
+    system.register::<Venue>();
+    system.make_restorable::<Venue>();
+    auto_setup(system);
+}
+
+mod kay_auto;
+pub use self::kay_auto::*;
@@ -0,0 +1,265 @@
+//! Jobs-housing balance per district: how many jobs there are compared to the workers
+//! living there, and how far and long residents commute out and workers commute in.
+//! Districts with many more jobs than workers need housing nearby, districts with many
+//! more workers than jobs need commercial zoning.
+//!
+//! Workers are counted where they live, with the job they use on the job market.
+//! Commute times are their households' average trip times.
+use kay::{ActorSystem, World, External};
+use compact::{CVec, CDict};
+use descartes::{N, P2, Norm};
+use stagemaster::{UserInterfaceID, Interactable2d, Interactable2dID,
+                  MSG_Interactable2d_draw_ui_2d};
+use imgui::ImGuiSetCond_FirstUseEver;
+use core::simulation::{SimulationID, Sleeper, SleeperID, MSG_Sleeper_wake, Timestamp, Ticks,
+                       TICKS_PER_SIM_MINUTE};
+use transport::services::district_of;
+use super::buildings::BuildingID;
+use super::market::OfferID;
+
+const ANALYSIS_INTERVAL: Ticks = Ticks(3000);
+const COLLECTION_TICKS: Ticks = Ticks(10);
+/// Districts with a ratio of jobs to workers outside of these bounds count as unbalanced
+const MIN_BALANCED_RATIO: f32 = 0.67;
+const MAX_BALANCED_RATIO: f32 = 1.5;
+
+#[derive(Copy, Clone)]
+struct Workplace {
+    job_offer: OfferID,
+    position: P2,
+    n_jobs: usize,
+}
+
+#[derive(Copy, Clone)]
+struct Commuter {
+    home: P2,
+    job_offer: OfferID,
+    trip_ticks: f32,
+}
+
+/// Number, total distance and total duration of commutes
+#[derive(Copy, Clone, Default)]
+pub struct Commutes {
+    pub n: usize,
+    pub total_distance: N,
+    pub total_ticks: f32,
+}
+
+impl Commutes {
+    fn add(&mut self, distance: N, ticks: f32) {
+        self.n += 1;
+        self.total_distance += distance;
+        self.total_ticks += ticks;
+    }
+
+    fn describe(&self) -> String {
+        if self.n == 0 {
+            "none".to_owned()
+        } else {
+            format!(
+                "{}, avg. {:.1} km, {:.0} min",
+                self.n,
+                self.total_distance / self.n as f32 / 1000.0,
+                self.total_ticks / self.n as f32 / TICKS_PER_SIM_MINUTE as f32
+            )
+        }
+    }
+}
+
+#[derive(Copy, Clone, Default)]
+pub struct DistrictBalance {
+    pub jobs: usize,
+    pub resident_workers: usize,
+    /// Residents commuting to jobs (in this district or elsewhere)
+    pub commutes_out: Commutes,
+    /// Workers commuting to jobs in this district (from here or elsewhere)
+    pub commutes_in: Commutes,
+}
+
+impl DistrictBalance {
+    fn ratio(&self) -> f32 {
+        if self.resident_workers == 0 {
+            ::std::f32::INFINITY
+        } else {
+            self.jobs as f32 / self.resident_workers as f32
+        }
+    }
+
+    fn advice(&self) -> &'static str {
+        let ratio = self.ratio();
+        if ratio > MAX_BALANCED_RATIO {
+            "job-rich, zone more housing"
+        } else if ratio < MIN_BALANCED_RATIO {
+            "housing-rich, zone more workplaces"
+        } else {
+            "balanced"
+        }
+    }
+}
+
+#[derive(Compact, Clone)]
+pub struct JobsHousingBalance {
+    id: JobsHousingBalanceID,
+    simulation: SimulationID,
+    collecting: bool,
+    workplaces: CVec<Workplace>,
+    residents: CVec<(P2, usize)>,
+    commuters: CVec<Commuter>,
+    districts: CDict<(i32, i32), DistrictBalance>,
+}
+
+impl JobsHousingBalance {
+    pub fn spawn(
+        id: JobsHousingBalanceID,
+        user_interface: UserInterfaceID,
+        simulation: SimulationID,
+        world: &mut World,
+    ) -> JobsHousingBalance {
+        user_interface.add_2d(id.into(), world);
+        simulation.wake_up_in(ANALYSIS_INTERVAL, id.into(), world);
+
+        JobsHousingBalance {
+            id,
+            simulation,
+            collecting: false,
+            workplaces: CVec::new(),
+            residents: CVec::new(),
+            commuters: CVec::new(),
+            districts: CDict::new(),
+        }
+    }
+
+    pub fn add_workplace(
+        &mut self,
+        job_offer: OfferID,
+        position: P2,
+        n_jobs: usize,
+        _: &mut World,
+    ) {
+        if self.collecting {
+            self.workplaces.push(Workplace { job_offer, position, n_jobs });
+        }
+    }
+
+    pub fn add_resident_workers(&mut self, home: P2, n_workers: usize, _: &mut World) {
+        if self.collecting {
+            self.residents.push((home, n_workers));
+        }
+    }
+
+    pub fn add_commuter(
+        &mut self,
+        home: P2,
+        job_offer: OfferID,
+        trip_ticks: f32,
+        _: &mut World,
+    ) {
+        if self.collecting {
+            self.commuters.push(Commuter { home, job_offer, trip_ticks });
+        }
+    }
+
+    fn analyze(&mut self) {
+        let mut districts = CDict::<(i32, i32), DistrictBalance>::new();
+
+        for workplace in self.workplaces.iter() {
+            let district = district_of(workplace.position);
+            let mut balance = districts.get(district).cloned().unwrap_or_default();
+            balance.jobs += workplace.n_jobs;
+            districts.insert(district, balance);
+        }
+
+        for &(home, n_workers) in self.residents.iter() {
+            let district = district_of(home);
+            let mut balance = districts.get(district).cloned().unwrap_or_default();
+            balance.resident_workers += n_workers;
+            districts.insert(district, balance);
+        }
+
+        for commuter in self.commuters.iter() {
+            let maybe_workplace = self.workplaces.iter().find(|workplace| {
+                workplace.job_offer == commuter.job_offer
+            });
+            if let Some(workplace) = maybe_workplace {
+                let distance = (workplace.position - commuter.home).norm();
+
+                let home_district = district_of(commuter.home);
+                let mut balance = districts.get(home_district).cloned().unwrap_or_default();
+                balance.commutes_out.add(distance, commuter.trip_ticks);
+                districts.insert(home_district, balance);
+
+                let work_district = district_of(workplace.position);
+                let mut balance = districts.get(work_district).cloned().unwrap_or_default();
+                balance.commutes_in.add(distance, commuter.trip_ticks);
+                districts.insert(work_district, balance);
+            }
+        }
+
+        self.districts = districts;
+    }
+}
+
+impl Sleeper for JobsHousingBalance {
+    fn wake(&mut self, _current_tick: Timestamp, world: &mut World) {
+        if self.collecting {
+            self.collecting = false;
+            self.analyze();
+            self.simulation.wake_up_in(
+                Ticks(ANALYSIS_INTERVAL.0 - COLLECTION_TICKS.0),
+                self.id.into(),
+                world,
+            );
+        } else {
+            self.collecting = true;
+            self.workplaces = CVec::new();
+            self.residents = CVec::new();
+            self.commuters = CVec::new();
+            BuildingID::global_broadcast(world).report_commutes(self.id, world);
+            self.simulation.wake_up_in(COLLECTION_TICKS, self.id.into(), world);
+        }
+    }
+}
+
+impl Interactable2d for JobsHousingBalance {
+    fn draw_ui_2d(
+        &mut self,
+        imgui_ui: &External<::imgui::Ui<'static>>,
+        return_to: UserInterfaceID,
+        world: &mut World,
+    ) {
+        let ui = imgui_ui.steal();
+
+        ui.window(im_str!("Districts"))
+            .size((400.0, 300.0), ImGuiSetCond_FirstUseEver)
+            .collapsible(true)
+            .build(|| if self.districts.is_empty() {
+                ui.text(im_str!("No jobs or workers yet"));
+            } else {
+                for (&(x, y), balance) in self.districts.pairs() {
+                    ui.text(im_str!(
+                        "District ({}, {}): {} jobs, {} resident workers, {}",
+                        x,
+                        y,
+                        balance.jobs,
+                        balance.resident_workers,
+                        balance.advice()
+                    ));
+                    ui.text(im_str!("  Commuting out: {}", balance.commutes_out.describe()));
+                    ui.text(im_str!("  Commuting in: {}", balance.commutes_in.describe()));
+                }
+            });
+
+        return_to.ui_drawn(ui, world);
+    }
+}
+
+pub fn setup(system: &mut ActorSystem, user_interface: UserInterfaceID, simulation: SimulationID) {
+    system.register::<JobsHousingBalance>();
+    system.make_restorable::<JobsHousingBalance>();
+    auto_setup(system);
+
+    JobsHousingBalanceID::spawn(user_interface, simulation, &mut system.world());
+}
+
+mod kay_auto;
+pub use self::kay_auto::*;
@@ -0,0 +1,41 @@
+//! Where people go shopping, chosen with a gravity model: every open shop gets a chance
+//! that grows with its size and falls off with the travel time to it. So new shops draw
+//! customers in proportion to how easy they are to reach, taking them away from older
+//! ones, instead of everybody sticking to the first shop they found.
+use super::EvaluatedDeal;
+use economy::resources::{ResourceId, r_id};
+
+/// How strongly bigger shops attract more customers (1.0: proportionally)
+const SIZE_EXPONENT: f32 = 1.0;
+/// How quickly the attraction of a shop falls off, per minute of travel time
+const TRAVEL_TIME_DECAY_PER_MINUTE: f32 = 0.1;
+
+/// Resources for which the destination is chosen with the gravity model every time,
+/// instead of going back to the same supplier
+pub fn applies_to(resource: ResourceId) -> bool {
+    resource == r_id("groceries")
+}
+
+pub fn gravity_weight(evaluated: &EvaluatedDeal) -> f32 {
+    let travel_minutes = evaluated.travel_time.seconds() as f32 / 60.0;
+    evaluated.attractiveness.max(0.0).powf(SIZE_EXPONENT) *
+        (-TRAVEL_TIME_DECAY_PER_MINUTE * travel_minutes).exp()
+}
+
+/// Picks one of the candidates, each with a probability proportional to its gravity weight
+pub fn choose(candidates: &[EvaluatedDeal]) -> Option<EvaluatedDeal> {
+    let weights = candidates.iter().map(gravity_weight).collect::<Vec<_>>();
+    let total: f32 = weights.iter().sum();
+    if total <= 0.0 {
+        return candidates.first().cloned();
+    }
+
+    let mut remaining = ::core::random::next_f32() * total;
+    for (candidate, weight) in candidates.iter().zip(weights) {
+        if remaining < weight {
+            return Some(candidate.clone());
+        }
+        remaining -= weight;
+    }
+    candidates.last().cloned()
+}
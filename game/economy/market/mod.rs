@@ -3,6 +3,9 @@ use compact::{CVec, CDict};
 use super::resources::{ResourceMap, ResourceId, ResourceAmount};
 use super::households::{HouseholdID, MemberIdx};
 use core::simulation::{TimeOfDay, Seconds, Timestamp};
+use core::units::{Meters, MetersPerSecond};
+
+pub mod destination_choice;
 
 #[derive(Compact, Clone)]
 pub struct Deal {
@@ -33,6 +36,8 @@ pub struct Offer {
     from: TimeOfDay,
     to: TimeOfDay,
     deal: Deal,
+    /// How much the offer draws people in compared to others, like the size of a shop
+    attractiveness: f32,
     users: CVec<(HouseholdID, Option<MemberIdx>)>,
 }
 
@@ -55,14 +60,22 @@ impl Offer {
             from,
             to,
             deal: deal.clone(),
+            attractiveness: 1.0,
             users: CVec::new(),
         }
     }
 
+    pub fn set_attractiveness(&mut self, attractiveness: f32, _: &mut World) {
+        self.attractiveness = attractiveness;
+    }
+
     // The offer stays alive until the withdrawal is confirmed
     // to prevent offers being used while they're being withdrawn
     pub fn withdraw(&mut self, world: &mut World) {
-        // TODO: notify users and wait for their confirmation as well
+        // TODO: wait for the users' confirmation as well
+        for &(user, _) in self.users.iter() {
+            user.on_offer_withdrawn(self.id, world);
+        }
         MarketID::global_first(world).withdraw(self.deal.give.0, self.id, world);
     }
 
@@ -83,9 +96,12 @@ impl Offer {
                 evaluated_deals: vec![
                     EvaluatedDeal {
                         offer: self.id,
+                        location: self.location,
                         deal: self.deal.clone(),
                         from: self.from,
                         to: self.to,
+                        attractiveness: self.attractiveness,
+                        travel_time: Seconds(0),
                     },
                 ].into(),
             };
@@ -216,9 +232,14 @@ impl Market {
 #[derive(Compact, Clone)]
 pub struct EvaluatedDeal {
     pub offer: OfferID,
+    /// Where the offer is, usually its building
+    pub location: RoughLocationID,
     pub deal: Deal,
     pub from: TimeOfDay,
     pub to: TimeOfDay,
+    pub attractiveness: f32,
+    /// Estimated travel time to the offer, already included in the deal's duration
+    pub travel_time: Seconds,
 }
 
 #[derive(Compact, Clone)]
@@ -314,8 +335,8 @@ impl LocationRequester for TripCostEstimator {
 }
 
 impl DistanceRequester for TripCostEstimator {
-    fn on_distance(&mut self, maybe_distance: Option<f32>, world: &mut World) {
-        const ASSUMED_AVG_SPEED: f32 = 10.0; // m/s
+    fn on_distance(&mut self, maybe_distance: Option<Meters>, world: &mut World) {
+        const ASSUMED_AVG_SPEED: MetersPerSecond = MetersPerSecond(10.0);
 
         let result = if let Some(distance) = maybe_distance {
             EvaluatedSearchResult {
@@ -324,11 +345,12 @@ impl DistanceRequester for TripCostEstimator {
                     .iter()
                     .map(|evaluated_deal| {
                         let estimated_travel_time =
-                            Seconds((distance / ASSUMED_AVG_SPEED) as usize);
+                            Seconds((distance / ASSUMED_AVG_SPEED).0 as usize);
                         let mut new_deal = evaluated_deal.clone();
                         new_deal.deal.duration += estimated_travel_time;
                         new_deal.from -= estimated_travel_time;
                         new_deal.to -= estimated_travel_time;
+                        new_deal.travel_time = estimated_travel_time;
                         // TODO: adjust possible-until and resources
                         new_deal
                     })
@@ -356,6 +378,9 @@ pub fn setup(system: &mut ActorSystem) {
     system.register::<Offer>();
     system.register::<Market>();
     system.register::<TripCostEstimator>();
+    system.make_restorable::<Offer>();
+    system.make_restorable::<Market>();
+    system.make_restorable::<TripCostEstimator>();
 
     kay_auto::auto_setup(system);
 
@@ -4,13 +4,29 @@ pub mod resources;
 pub mod market;
 pub mod households;
 pub mod buildings;
+pub mod demographics;
+pub mod synthetic_population;
+pub mod trip_generation;
+pub mod jobs_housing;
+pub mod businesses;
 
 use stagemaster::UserInterfaceID;
+use monet::RendererID;
 use core::simulation::SimulationID;
 
-pub fn setup(system: &mut ActorSystem, user_interface: UserInterfaceID, simulation: SimulationID) {
+pub fn setup(
+    system: &mut ActorSystem,
+    user_interface: UserInterfaceID,
+    renderer_id: RendererID,
+    simulation: SimulationID,
+) {
     resources::setup();
     market::setup(system);
-    households::setup(system);
+    households::setup(system, user_interface, renderer_id, simulation);
     buildings::setup(system, user_interface, simulation);
+    demographics::setup(system, simulation);
+    synthetic_population::setup(system, simulation);
+    trip_generation::setup(system, user_interface, simulation);
+    jobs_housing::setup(system, user_interface, simulation);
+    businesses::setup(system, simulation);
 }
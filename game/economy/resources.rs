@@ -1,5 +1,5 @@
 use std::collections::HashMap;
-use core::read_md_tables::read;
+use core::packages::read_data_tables;
 use itertools::multizip;
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
@@ -97,7 +97,7 @@ pub fn all_resource_ids() -> Vec<ResourceId> {
 
 pub fn setup() {
     let mut resources = Box::new(ResourceRegistry::default());
-    let tables = read(&"game/economy/parameters/resources").unwrap();
+    let tables = read_data_tables(&"game/economy/parameters/resources", "resources").unwrap();
 
     for table in &tables {
         let c = &table.columns;
@@ -0,0 +1,432 @@
+//! Seeding the city with a synthetic population, as made by population synthesizers for
+//! travel demand models, instead of letting families move in on their own.
+//!
+//! A population is a directory of three CSV files, each with a header line:
+//!
+//! * `households.csv`: `household_id,x,y`, where the household lives
+//! * `persons.csv`: `person_id,household_id,age`
+//! * `plans.csv`: `person_id,activity,x,y,start`, one row per activity, with the start
+//!   time as `HH:MM`
+//!
+//! The "Import Population" action imports the population in the directory given in the
+//! settings, or `CITYBOUND_POPULATION` imports it on its own shortly after the start, for
+//! headless runs (see `core::comparison`). Every household moves into the vacant building
+//! closest to its home, with its adults as members following their plans (see
+//! `economy::households::family::activity_plan`) and its other persons as children. Kinds
+//! of activities are mapped to the resources they are for in the settings, others (like
+//! being at home) are left out. Afterwards, nobody moves in or away on their own anymore.
+use kay::{ActorSystem, World, External};
+use compact::CVec;
+use descartes::{P2, Norm};
+use ordered_float::OrderedFloat;
+use stagemaster::combo::Combo2;
+use stagemaster::combo::Button::{LControl, LShift, I};
+use stagemaster::actions::{register_action, ActionId, ActionListener, ActionListenerID,
+                           ActionPhase, MSG_ActionListener_on_action};
+use core::simulation::{SimulationID, Sleeper, SleeperID, MSG_Sleeper_wake, Timestamp, Ticks,
+                       TimeOfDay};
+use economy::resources::{ResourceId, r_id};
+use economy::buildings::BuildingID;
+use economy::households::family::FamilyID;
+use economy::households::family::activity_plan::PlannedActivity;
+use economy::demographics::DemographicsID;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::str::FromStr;
+
+const SETTINGS_CATEGORY: &str = "Population Import";
+const COLLECTION_TICKS: Ticks = Ticks(10);
+/// How long an import given in `CITYBOUND_POPULATION` waits for the city to be built
+const IMPORT_DELAY: Ticks = Ticks(600);
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PopulationImportSettings {
+    pub directory: String,
+    /// The resource that each kind of activity in the plans is for
+    pub activity_resources: Vec<(String, String)>,
+    /// Younger persons are children
+    pub adult_age: usize,
+}
+
+impl Default for PopulationImportSettings {
+    fn default() -> Self {
+        PopulationImportSettings {
+            directory: "population".to_owned(),
+            activity_resources: vec![
+                ("work".to_owned(), "money".to_owned()),
+                ("shopping".to_owned(), "groceries".to_owned()),
+                ("leisure".to_owned(), "entertainment".to_owned()),
+                ("recreation".to_owned(), "environment".to_owned()),
+            ],
+            adult_age: 18,
+        }
+    }
+}
+
+#[derive(Compact, Clone)]
+pub struct SyntheticActivity {
+    pub resource: ResourceId,
+    pub position: P2,
+    pub start: TimeOfDay,
+}
+
+#[derive(Compact, Clone)]
+pub struct SyntheticHousehold {
+    pub home: P2,
+    /// The activities of each adult, by start time
+    pub member_plans: CVec<CVec<SyntheticActivity>>,
+    pub n_children: usize,
+}
+
+pub struct SyntheticPopulation {
+    pub households: Vec<SyntheticHousehold>,
+    /// Households without adults, which can't live on their own
+    pub skipped_households: usize,
+    /// Activities that aren't for any resource
+    pub skipped_activities: usize,
+}
+
+fn rows(csv: &str) -> Vec<(usize, Vec<&str>)> {
+    csv.lines()
+        .enumerate()
+        .skip(1)
+        .filter(|&(_, line)| !line.trim().is_empty())
+        .map(|(idx, line)| (idx + 1, line.split(',').map(str::trim).collect()))
+        .collect()
+}
+
+fn field<T: FromStr>(row: &[&str], idx: usize, file: &str, line: usize) -> Result<T, String> {
+    row.get(idx).and_then(|text| text.parse().ok()).ok_or_else(|| {
+        format!("{} line {}: column {} is missing or invalid", file, line, idx + 1)
+    })
+}
+
+/// Parses `HH:MM` or `HH:MM:SS`, hours past midnight wrapping around to the next day
+pub fn parse_time(text: &str) -> Option<TimeOfDay> {
+    let mut parts = text.split(':').map(|part| part.parse::<usize>().ok());
+    match (parts.next(), parts.next()) {
+        (Some(Some(hours)), Some(Some(minutes))) if minutes < 60 => {
+            Some(TimeOfDay::new(hours % 24, minutes))
+        }
+        _ => None,
+    }
+}
+
+/// Parses the contents of the three files of a synthetic population, looking up the
+/// resource each activity is for with `resource_of`
+pub fn parse_population(
+    households_csv: &str,
+    persons_csv: &str,
+    plans_csv: &str,
+    adult_age: usize,
+    resource_of: &Fn(&str) -> Option<ResourceId>,
+) -> Result<SyntheticPopulation, String> {
+    let mut households = Vec::new();
+    let mut household_indices = HashMap::new();
+    for (line, row) in rows(households_csv) {
+        let file = "households.csv";
+        let position = P2::new(field(&row, 1, file, line)?, field(&row, 2, file, line)?);
+        household_indices.insert(row[0].to_owned(), households.len());
+        households.push((position, Vec::<Vec<SyntheticActivity>>::new(), 0));
+    }
+
+    // the household and member index of each adult
+    let mut adults = HashMap::new();
+    for (line, row) in rows(persons_csv) {
+        let file = "persons.csv";
+        let household_idx = *row.get(1)
+            .and_then(|household_id| household_indices.get(*household_id))
+            .ok_or_else(|| format!("{} line {}: unknown household", file, line))?;
+        let age: usize = field(&row, 2, file, line)?;
+        let household = &mut households[household_idx];
+        if age >= adult_age {
+            adults.insert(row[0].to_owned(), (household_idx, household.1.len()));
+            household.1.push(Vec::new());
+        } else {
+            household.2 += 1;
+        }
+    }
+
+    let mut skipped_activities = 0;
+    for (line, row) in rows(plans_csv) {
+        let file = "plans.csv";
+        // children only go to school, so their plans are left out
+        if let Some(&(household_idx, member_idx)) = adults.get(row[0]) {
+            let activity = row.get(1).cloned().unwrap_or("");
+            if let Some(resource) = resource_of(activity) {
+                let position = P2::new(field(&row, 2, file, line)?, field(&row, 3, file, line)?);
+                let start = row.get(4).and_then(|text| parse_time(text)).ok_or_else(|| {
+                    format!("{} line {}: start time is missing or invalid", file, line)
+                })?;
+                households[household_idx].1[member_idx].push(SyntheticActivity {
+                    resource,
+                    position,
+                    start,
+                });
+            } else {
+                skipped_activities += 1;
+            }
+        }
+    }
+
+    let n_households = households.len();
+    let households = households
+        .into_iter()
+        .filter(|&(_, ref member_plans, _)| !member_plans.is_empty())
+        .map(|(home, member_plans, n_children)| {
+            SyntheticHousehold {
+                home,
+                member_plans: member_plans
+                    .into_iter()
+                    .map(|mut plan| {
+                        plan.sort_by_key(|activity| activity.start.hours_minutes());
+                        plan.into()
+                    })
+                    .collect(),
+                n_children,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    Ok(SyntheticPopulation {
+        skipped_households: n_households - households.len(),
+        households,
+        skipped_activities,
+    })
+}
+
+fn read_file(directory: &str, name: &str) -> Result<String, String> {
+    let path = format!("{}/{}", directory, name);
+    let mut contents = String::new();
+    File::open(&path)
+        .and_then(|mut file| file.read_to_string(&mut contents))
+        .map(|_| contents)
+        .map_err(|err| format!("{}: {}", path, err))
+}
+
+#[derive(Compact, Clone)]
+pub struct PopulationImporter {
+    id: PopulationImporterID,
+    simulation: SimulationID,
+    settings: External<PopulationImportSettings>,
+    /// The households to move in, while the buildings report
+    importing: Option<CVec<SyntheticHousehold>>,
+    /// Every building, with where it is and whether it's vacant
+    buildings: CVec<(BuildingID, P2, bool)>,
+}
+
+impl PopulationImporter {
+    pub fn init(
+        id: PopulationImporterID,
+        simulation: SimulationID,
+        world: &mut World,
+    ) -> PopulationImporter {
+        register_action(
+            "Import Population",
+            Combo2::new(&[LControl, LShift, I], &[]),
+            id.into(),
+            world,
+        );
+
+        let mut settings: PopulationImportSettings = ::ENV.load_settings(SETTINGS_CATEGORY);
+        if let Ok(directory) = ::std::env::var("CITYBOUND_POPULATION") {
+            settings.directory = directory;
+            simulation.wake_up_in(IMPORT_DELAY, id.into(), world);
+        }
+
+        PopulationImporter {
+            id,
+            simulation,
+            settings: External::new(settings),
+            importing: None,
+            buildings: CVec::new(),
+        }
+    }
+
+    fn start_import(&mut self, world: &mut World) {
+        let directory = self.settings.directory.clone();
+        let parsed = {
+            let settings = &self.settings;
+            let resource_of = |activity: &str| {
+                settings
+                    .activity_resources
+                    .iter()
+                    .find(|&&(ref kind, _)| kind == activity)
+                    .map(|&(_, ref resource)| r_id(resource))
+            };
+            read_file(&directory, "households.csv").and_then(|households| {
+                let persons = read_file(&directory, "persons.csv")?;
+                let plans = read_file(&directory, "plans.csv")?;
+                parse_population(&households, &persons, &plans, settings.adult_age, &resource_of)
+            })
+        };
+
+        match parsed {
+            Ok(population) => {
+                println!(
+                    "Importing {} households from {} ({} without adults and {} activities \
+                     without a resource left out)",
+                    population.households.len(),
+                    directory,
+                    population.skipped_households,
+                    population.skipped_activities
+                );
+                self.importing = Some(population.households.into());
+                self.buildings = CVec::new();
+                BuildingID::global_broadcast(world).report_for_population_import(self.id, world);
+                self.simulation.wake_up_in(COLLECTION_TICKS, self.id.into(), world);
+            }
+            Err(err) => println!("Error importing population: {}", err),
+        }
+    }
+
+    pub fn add_building(
+        &mut self,
+        building: BuildingID,
+        position: P2,
+        vacant: bool,
+        _: &mut World,
+    ) {
+        if self.importing.is_some() {
+            self.buildings.push((building, position, vacant));
+        }
+    }
+
+    fn nearest_building(&self, position: P2) -> Option<BuildingID> {
+        self.buildings
+            .iter()
+            .min_by_key(|&&(_, building_position, _)| {
+                OrderedFloat((building_position - position).norm())
+            })
+            .map(|&(building, _, _)| building)
+    }
+
+    fn move_in(&mut self, households: &[SyntheticHousehold], world: &mut World) {
+        let mut vacant = self.buildings
+            .iter()
+            .filter(|&&(_, _, vacant)| vacant)
+            .map(|&(building, position, _)| (building, position))
+            .collect::<Vec<_>>();
+        let mut n_moved_in = 0;
+
+        for household in households {
+            let maybe_nearest_vacant = vacant
+                .iter()
+                .enumerate()
+                .min_by_key(|&(_, &(_, position))| {
+                    OrderedFloat((position - household.home).norm())
+                })
+                .map(|(idx, _)| idx);
+            let home = match maybe_nearest_vacant {
+                Some(idx) => vacant.swap_remove(idx).0,
+                None => break,
+            };
+
+            let plans = household
+                .member_plans
+                .iter()
+                .map(|plan| {
+                    plan.iter()
+                        .map(|activity| {
+                            PlannedActivity::new(
+                                activity.resource,
+                                self.nearest_building(activity.position),
+                                activity.start,
+                            )
+                        })
+                        .collect::<CVec<_>>()
+                })
+                .collect::<CVec<_>>();
+            let family = FamilyID::move_in_with_plans(
+                home,
+                plans,
+                household.n_children,
+                self.simulation,
+                world,
+            );
+            home.add_household(family.into(), world);
+            n_moved_in += 1;
+        }
+
+        DemographicsID::local_first(world).freeze_population(world);
+        println!(
+            "Imported {} households, {} found no vacant building",
+            n_moved_in,
+            households.len() - n_moved_in
+        );
+    }
+}
+
+impl Sleeper for PopulationImporter {
+    fn wake(&mut self, _current_tick: Timestamp, world: &mut World) {
+        if let Some(households) = self.importing.take() {
+            self.move_in(&households, world);
+            self.buildings = CVec::new();
+        } else {
+            self.start_import(world);
+        }
+    }
+}
+
+impl ActionListener for PopulationImporter {
+    fn on_action(&mut self, action: ActionId, phase: ActionPhase, world: &mut World) {
+        let is_import = action == ActionId::named("Import Population");
+        if phase == ActionPhase::Started && is_import && self.importing.is_none() {
+            self.start_import(world);
+        }
+    }
+}
+
+pub fn setup(system: &mut ActorSystem, simulation: SimulationID) {
+    system.register::<PopulationImporter>();
+    auto_setup(system);
+
+    PopulationImporterID::init(simulation, &mut system.world());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adults_follow_their_plans_and_the_others_are_children() {
+        let households = "household_id,x,y\n1,10.0,20.0\n2,0,0\n";
+        let persons = "person_id,household_id,age\na,1,40\nb,1,9\nc,2,12\n";
+        let plans = "person_id,activity,x,y,start\n\
+                     a,home,10,20,00:00\n\
+                     a,shopping,50,50,17:30\n\
+                     a,work,100,0,08:15:00\n\
+                     b,school,30,30,08:00\n";
+        let resource_of = |activity: &str| if activity == "work" || activity == "shopping" {
+            Some(ResourceId::default())
+        } else {
+            None
+        };
+
+        let population = parse_population(households, persons, plans, 18, &resource_of)
+            .expect("population should be valid");
+
+        assert_eq!(population.households.len(), 1);
+        assert_eq!(population.skipped_households, 1);
+        assert_eq!(population.skipped_activities, 1);
+        let household = &population.households[0];
+        assert_eq!(household.n_children, 1);
+        assert_eq!(household.member_plans.len(), 1);
+        let starts = household.member_plans[0]
+            .iter()
+            .map(|activity| activity.start.hours_minutes())
+            .collect::<Vec<_>>();
+        assert_eq!(starts, vec![(8, 15), (17, 30)]);
+    }
+
+    #[test]
+    fn unknown_households_are_errors() {
+        let persons = "person_id,household_id,age\np,7,30\n";
+        let result = parse_population("household_id,x,y\n", persons, "", 18, &|_| None);
+        assert!(result.is_err());
+    }
+}
+
+mod kay_auto;
+pub use self::kay_auto::*;
@@ -0,0 +1,188 @@
+use kay::{ActorSystem, World, External};
+use compact::CVec;
+use stagemaster::{UserInterfaceID, Interactable2d, Interactable2dID,
+                  MSG_Interactable2d_draw_ui_2d};
+use stagemaster::charts::{Chart, ChartKind};
+use imgui::ImGuiSetCond_FirstUseEver;
+use core::simulation::{SimulationID, Sleeper, SleeperID, MSG_Sleeper_wake, Timestamp, Ticks,
+                       TimeOfDay};
+use core::metrics::{TRIPS_CREATED, TRIPS_SUCCEEDED, TRIPS_FAILED};
+use std::cell::RefCell;
+use std::sync::atomic::Ordering;
+
+const SETTINGS_CATEGORY: &'static str = "Trip Generation";
+const SAMPLE_INTERVAL: Ticks = Ticks(600);
+// one sim day worth of samples
+const HISTORY_LENGTH: usize = 144;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum LandUse {
+    Residential,
+    Commercial,
+}
+
+/// Per land use and two-hour slot of the day: the probability that an idle
+/// household member there starts looking for something to do, when given the chance
+#[derive(Serialize, Deserialize, Copy, Clone)]
+pub struct TripGenerationSettings {
+    pub residential: [f32; 12],
+    pub commercial: [f32; 12],
+}
+
+impl Default for TripGenerationSettings {
+    fn default() -> Self {
+        TripGenerationSettings {
+            residential: [1.0; 12],
+            commercial: [1.0; 12],
+        }
+    }
+}
+
+thread_local! (
+    static CURRENT_SETTINGS: RefCell<TripGenerationSettings> =
+        RefCell::new(TripGenerationSettings::default());
+);
+
+impl TripGenerationSettings {
+    /// The currently used rates, cheap enough to call for every decision
+    pub fn current() -> TripGenerationSettings {
+        CURRENT_SETTINGS.with(|current| *current.borrow())
+    }
+
+    pub fn load() {
+        let settings = ::ENV.load_settings(SETTINGS_CATEGORY);
+        CURRENT_SETTINGS.with(|current| *current.borrow_mut() = settings);
+    }
+
+    fn set_and_write(settings: TripGenerationSettings) {
+        CURRENT_SETTINGS.with(|current| *current.borrow_mut() = settings);
+        ::ENV.write_settings(SETTINGS_CATEGORY, &settings);
+    }
+
+    pub fn rate(&self, land_use: LandUse, time: TimeOfDay) -> f32 {
+        let slot = time.hours_minutes().0 / 2;
+        match land_use {
+            LandUse::Residential => self.residential[slot],
+            LandUse::Commercial => self.commercial[slot],
+        }
+    }
+}
+
+/// A panel to tune trip generation while playing,
+/// next to charts of how many trips were generated and completed recently
+#[derive(Compact, Clone)]
+pub struct TripCalibration {
+    id: TripCalibrationID,
+    simulation: SimulationID,
+    last_counts: (usize, usize, usize),
+    generated: CVec<f32>,
+    completed: CVec<f32>,
+    failed: CVec<f32>,
+}
+
+impl TripCalibration {
+    pub fn spawn(
+        id: TripCalibrationID,
+        simulation: SimulationID,
+        user_interface: UserInterfaceID,
+        world: &mut World,
+    ) -> TripCalibration {
+        user_interface.add_2d(id.into(), world);
+        simulation.wake_up_in(SAMPLE_INTERVAL, id.into(), world);
+
+        TripCalibration {
+            id,
+            simulation,
+            last_counts: (0, 0, 0),
+            generated: CVec::new(),
+            completed: CVec::new(),
+            failed: CVec::new(),
+        }
+    }
+}
+
+fn push_sample(history: &mut CVec<f32>, sample: usize) {
+    history.push(sample as f32);
+    if history.len() > HISTORY_LENGTH {
+        history.remove(0);
+    }
+}
+
+impl Sleeper for TripCalibration {
+    fn wake(&mut self, _current_tick: Timestamp, world: &mut World) {
+        let counts = (
+            TRIPS_CREATED.load(Ordering::Relaxed),
+            TRIPS_SUCCEEDED.load(Ordering::Relaxed),
+            TRIPS_FAILED.load(Ordering::Relaxed),
+        );
+        push_sample(&mut self.generated, counts.0 - self.last_counts.0);
+        push_sample(&mut self.completed, counts.1 - self.last_counts.1);
+        push_sample(&mut self.failed, counts.2 - self.last_counts.2);
+        self.last_counts = counts;
+
+        self.simulation.wake_up_in(SAMPLE_INTERVAL, self.id.into(), world);
+    }
+}
+
+impl Interactable2d for TripCalibration {
+    fn draw_ui_2d(
+        &mut self,
+        imgui_ui: &External<::imgui::Ui<'static>>,
+        return_to: UserInterfaceID,
+        world: &mut World,
+    ) {
+        let ui = imgui_ui.steal();
+
+        let mut settings = TripGenerationSettings::current();
+        let mut settings_changed = false;
+
+        ui.window(im_str!("Trip Generation"))
+            .size((350.0, 400.0), ImGuiSetCond_FirstUseEver)
+            .collapsible(true)
+            .build(|| {
+                Chart::new("Trips per 10 minutes, last 24h", ChartKind::Line)
+                    .series("Generated", &self.generated)
+                    .draw(&ui);
+                Chart::new("Finished trips", ChartKind::StackedArea)
+                    .series("Completed", &self.completed)
+                    .series("Failed", &self.failed)
+                    .draw(&ui);
+                ui.separator();
+
+                for &mut (name, ref mut rates) in &mut [
+                    ("Residential", &mut settings.residential),
+                    ("Commercial", &mut settings.commercial),
+                ]
+                {
+                    if ui.collapsing_header(im_str!("{}", name)).build() {
+                        for (slot, rate) in rates.iter_mut().enumerate() {
+                            settings_changed |= ui.slider_float(
+                                im_str!("{:02}h-{:02}h##{}", 2 * slot, 2 * slot + 2, name),
+                                rate,
+                                0.0,
+                                1.0,
+                            ).build();
+                        }
+                    }
+                }
+            });
+
+        if settings_changed {
+            TripGenerationSettings::set_and_write(settings);
+        }
+
+        return_to.ui_drawn(ui, world);
+    }
+}
+
+pub fn setup(system: &mut ActorSystem, user_interface: UserInterfaceID, simulation: SimulationID) {
+    TripGenerationSettings::load();
+
+    system.register::<TripCalibration>();
+    auto_setup(system);
+
+    TripCalibrationID::spawn(simulation, user_interface, &mut system.world());
+}
+
+mod kay_auto;
+pub use self::kay_auto::*;
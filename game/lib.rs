@@ -0,0 +1,130 @@
+//! The game as a library: everything but the main loop (see `main.rs`), so that the
+//! benchmarks in `benches/` can set up and tick the same simulation.
+#![feature(custom_derive, conservative_impl_trait)]
+#![cfg_attr(feature="clippy", feature(plugin))]
+#![cfg_attr(feature="clippy", plugin(clippy))]
+#![allow(dead_code)]
+
+#[cfg(test)]
+#[macro_use]
+extern crate proptest;
+
+#[macro_use]
+extern crate lazy_static;
+extern crate ordered_float;
+extern crate itertools;
+extern crate rand;
+extern crate fnv;
+extern crate roaring;
+extern crate png;
+extern crate rodio;
+
+extern crate compact;
+#[macro_use]
+extern crate compact_macros;
+#[macro_use]
+extern crate kay;
+extern crate monet;
+extern crate descartes;
+extern crate stagemaster;
+#[macro_use]
+extern crate imgui;
+#[macro_use]
+extern crate serde_derive;
+#[macro_use]
+extern crate serde_json;
+
+use stagemaster::environment::Environment;
+
+pub const ENV: &'static Environment = &Environment {
+    name: "Citybound",
+    author: "ae play",
+    version: "0.1.3",
+};
+
+pub mod core;
+pub mod transport;
+pub mod economy;
+pub mod terrain;
+pub mod sound;
+
+use compact::CVec;
+use kay::ActorSystem;
+use monet::{GrouperID, RendererID};
+use monet::glium::glutin::WindowBuilder;
+use stagemaster::UserInterfaceID;
+use transport::lane::{LaneID, TransferLaneID};
+use transport::rendering::LaneRendererID;
+use transport::diagnostics::NetworkDiagnosticsID;
+use transport::turning_movements::TurningMovementsID;
+use transport::demand_forecast::DemandForecastID;
+use transport::services::ServiceVehicleID;
+use transport::services::emergency::EmergencyDispatcherID;
+use transport::maintenance::RoadMaintenanceID;
+use transport::services::winter::WinterServiceID;
+use transport::road_hierarchy::RoadHierarchyID;
+use transport::street_furniture::StreetFurnitureID;
+use transport::ferries::FerryNetworkID;
+use transport::analysis_zones::TrafficZonesID;
+use transport::pathfinding::active_modes::ActiveModeGraphID;
+use transport::planning::current_plan::CurrentPlanID;
+use economy::households::tasks::TaskEndSchedulerID;
+use economy::buildings::rendering::BuildingRendererID;
+use terrain::TerrainID;
+use core::city_events::CityEventsID;
+use core::simulation::SimulationID;
+
+/// Registers and spawns everything that makes up the game, opening its window
+pub fn setup(
+    system: &mut ActorSystem,
+    window_builder: WindowBuilder,
+) -> (SimulationID, UserInterfaceID, RendererID) {
+    let world = &mut system.world();
+
+    let simulatables = vec![
+        LaneID::local_broadcast(world).into(),
+        TransferLaneID::local_broadcast(world).into(),
+        TaskEndSchedulerID::local_first(world).into(),
+        CityEventsID::local_first(world).into(),
+        ServiceVehicleID::local_broadcast(world).into(),
+        FerryNetworkID::local_first(world).into(),
+    ].into();
+    let simulation = core::simulation::setup(system, simulatables);
+
+    let renderables: CVec<_> = vec![
+        LaneRendererID::global_broadcast(world).into(),
+        GrouperID::global_broadcast(world).into(),
+        CurrentPlanID::global_broadcast(world).into(),
+        BuildingRendererID::global_broadcast(world).into(),
+        TerrainID::global_broadcast(world).into(),
+        NetworkDiagnosticsID::global_broadcast(world).into(),
+        TurningMovementsID::global_broadcast(world).into(),
+        DemandForecastID::global_broadcast(world).into(),
+        ServiceVehicleID::global_broadcast(world).into(),
+        EmergencyDispatcherID::global_broadcast(world).into(),
+        RoadMaintenanceID::global_broadcast(world).into(),
+        WinterServiceID::global_broadcast(world).into(),
+        RoadHierarchyID::global_broadcast(world).into(),
+        StreetFurnitureID::global_broadcast(world).into(),
+        ActiveModeGraphID::global_broadcast(world).into(),
+        FerryNetworkID::global_broadcast(world).into(),
+        TrafficZonesID::global_broadcast(world).into(),
+    ].into();
+
+    let (user_interface, renderer) = stagemaster::setup(
+        system,
+        renderables,
+        *ENV,
+        window_builder,
+        (0.6, 0.75, 0.4, 1.0)
+    );
+
+    core::city_events::setup(system, user_interface, renderer);
+    core::statistics_history::setup(system, user_interface, simulation);
+    transport::setup(system, user_interface, renderer, simulation);
+    economy::setup(system, user_interface, renderer, simulation);
+    terrain::setup(system);
+    sound::setup(system, simulation, renderer);
+
+    (simulation, user_interface, renderer)
+}
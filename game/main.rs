@@ -1,126 +1,135 @@
-#![feature(custom_derive, conservative_impl_trait)]
 #![cfg_attr(feature="clippy", feature(plugin))]
 #![cfg_attr(feature="clippy", plugin(clippy))]
-#![allow(dead_code)]
 // Enable this for memory tracking with Instruments/MacOS
 // and for much better stacktraces for memory issues
 //#![feature(alloc_system)]
 //extern crate alloc_system;
 
-extern crate ordered_float;
-extern crate itertools;
-extern crate rand;
-extern crate fnv;
-extern crate roaring;
-
-extern crate compact;
-#[macro_use]
-extern crate compact_macros;
+extern crate citybound;
 extern crate kay;
-extern crate monet;
-extern crate descartes;
-extern crate stagemaster;
-#[macro_use]
-extern crate imgui;
-#[macro_use]
-extern crate serde_derive;
-
-use stagemaster::environment::Environment;
-
-pub const ENV: &'static Environment = &Environment {
-    name: "Citybound",
-    author: "ae play",
-    version: "0.1.3",
-};
-
-mod core;
-mod transport;
-mod economy;
-
-use compact::CVec;
-use monet::GrouperID;
-use transport::lane::{LaneID, TransferLaneID};
-use transport::rendering::LaneRendererID;
-use transport::planning::current_plan::CurrentPlanID;
-use economy::households::family::FamilyID;
-use economy::households::tasks::TaskEndSchedulerID;
-use economy::buildings::rendering::BuildingRendererID;
+
+use citybound::{core, transport};
+use transport::construction::materialized_reality::{self, MaterializedRealityID};
 
 fn main() {
     core::init::ensure_crossplatform_proper_thread(|| {
         core::init::first_time_open_wiki_release_page();
+        core::packages::setup();
+        transport::event_log::setup();
+
+        let mut comparison_run = core::comparison::ComparisonRun::from_env();
 
         let mut system = Box::new(kay::ActorSystem::new(
             core::init::create_init_callback(),
             core::init::networking_from_env_args(),
         ));
 
+        core::init::audit_id_casts_from_env(&mut system);
+
         let world = &mut system.world();
 
         system.networking_connect();
 
-        let simulatables = vec![
-            LaneID::local_broadcast(world).into(),
-            TransferLaneID::local_broadcast(world).into(),
-            FamilyID::local_broadcast(world).into(),
-            TaskEndSchedulerID::local_first(world).into(),
-        ].into();
-        let simulation = core::simulation::setup(&mut system, simulatables);
-
-        let renderables: CVec<_> = vec![
-            LaneRendererID::global_broadcast(world).into(),
-            GrouperID::global_broadcast(world).into(),
-            CurrentPlanID::global_broadcast(world).into(),
-            BuildingRendererID::global_broadcast(&mut system.world())
-                .into(),
-        ].into();
-
         let machine_id = system.networking_machine_id();
 
-        let (user_interface, renderer) = stagemaster::setup(
-            &mut system,
-            renderables,
-            *ENV,
-            core::init::build_window(machine_id),
-            (0.6, 0.75, 0.4, 1.0)
-        );
-
-        transport::setup(&mut system, user_interface, renderer, simulation);
-        economy::setup(&mut system, user_interface, simulation);
+        let (simulation, user_interface, renderer) =
+            citybound::setup(&mut system, core::init::build_window(machine_id));
 
         core::init::print_version(user_interface, world);
 
         system.process_all_messages();
 
+        let mut remote_control = core::remote_control::RemoteControl::start_from_settings();
+
+        let mut metrics = core::metrics::Metrics::from_settings();
+
         let mut frame_counter = core::init::FrameCounter::new();
 
+        let mut tick_pacer = core::simulation::TickPacer::new();
+
+        let mut time_travel = core::time_travel::TimeTravel::from_settings();
+
+        let mut memory_inspector = core::memory_inspector::MemoryInspector::from_settings();
+
         loop {
             frame_counter.start_frame();
             frame_counter.print_fps(user_interface, world);
+            core::init::print_render_stats(user_interface, world);
+            metrics.start_frame();
 
             core::init::print_instance_counts(&mut system, user_interface);
             core::init::print_network_turn(&mut system, user_interface);
+            core::init::print_id_cast_counts(&mut system, user_interface);
+            memory_inspector.measure(&system);
+            memory_inspector.print(&mut system, user_interface);
 
             user_interface.process_events(world);
 
-            system.process_all_messages();
-
-            simulation.do_tick(world);
+            if let Some(ref mut remote_control) = remote_control {
+                remote_control.process_commands(&mut system, simulation, &mut time_travel);
+            }
 
             system.process_all_messages();
+            metrics.finish_phase("events");
 
-            renderer.render(world);
+            MaterializedRealityID::global_first(world).build_streamed(world);
+            system.process_all_messages();
+            metrics.finish_phase("loading");
+
+            let ticks_due = match comparison_run {
+                Some(ref comparison_run) => comparison_run.ticks_due(),
+                None => tick_pacer.ticks_due(),
+            };
+            // the simulation only starts once the city is loaded completely
+            let ticks_due = if materialized_reality::is_loading() { 0 } else { ticks_due };
+
+            system.set_worker_threads(core::simulation::SimulationConfig::current().tick_threads);
+
+            for _ in 0..ticks_due {
+                // answers interactive queries between ticks, even if the simulation
+                // is saturated and takes up most of the frame
+                system.process_priority_messages();
+                simulation.do_tick(world);
+
+                system.process_all_messages();
+                transport::event_log::write_logged();
+                time_travel.count_tick(&mut system);
+                metrics.count_tick();
+                if let Some(ref mut comparison_run) = comparison_run {
+                    comparison_run.count_tick();
+                }
+            }
+            metrics.finish_phase("simulation");
+
+            if let Some(ref comparison_run) = comparison_run {
+                if comparison_run.is_finished() {
+                    comparison_run.write_reports();
+                    transport::event_log::finish();
+                    if !comparison_run.passes_consistency_check(&system) {
+                        ::std::process::exit(1);
+                    }
+                    return;
+                }
+            } else {
+                renderer.render(world);
+            }
 
             system.process_all_messages();
+            metrics.finish_phase("rendering");
 
             system.networking_send_and_receive();
             system.process_all_messages();
+            metrics.finish_phase("networking");
 
             user_interface.start_frame(world);
 
             system.process_all_messages();
+            metrics.finish_phase("ui");
 
             system.networking_finish_turn();
+            metrics.finish_phase("networking");
+
+            metrics.publish(&mut system);
         }
     });
 }
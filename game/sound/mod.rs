@@ -0,0 +1,192 @@
+use kay::{ActorSystem, World, External};
+use descartes::{N, P2, P3, Into3d, Norm, FiniteCurve};
+use monet::{RendererID, EyeListener, EyeListenerID, Eye, Movement, MSG_EyeListener_eye_moved};
+use rodio::{self, Endpoint, Sink, Source};
+use core::simulation::{SimulationID, Sleeper, SleeperID, MSG_Sleeper_wake, Timestamp, Ticks};
+use transport::lane::{Lane, LaneID};
+use std::time::Duration;
+
+mod synthesis;
+
+use self::synthesis::{TrafficHum, Tone};
+
+const LISTEN_INTERVAL: Ticks = Ticks(30);
+const COLLECTION_TICKS: Ticks = Ticks(5);
+const HARD_BRAKING: f32 = -4.0;
+// how many nearby cars make the ambient hum play at full volume
+const CARS_FOR_FULL_HUM: N = 40.0;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SoundSettings {
+    pub enabled: bool,
+    pub master_volume: f32,
+    /// beyond this distance (in meters) from the camera, nothing can be heard
+    pub hearing_distance: N,
+}
+
+impl Default for SoundSettings {
+    fn default() -> Self {
+        SoundSettings {
+            enabled: true,
+            master_volume: 0.5,
+            hearing_distance: 400.0,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum SoundEvent {
+    Construction,
+    Horn,
+    SignalTick,
+}
+
+pub struct AudioOutput {
+    endpoint: Endpoint,
+    hum: Sink,
+}
+
+#[derive(Compact, Clone)]
+pub struct SoundSystem {
+    id: SoundSystemID,
+    simulation: SimulationID,
+    settings: External<SoundSettings>,
+    output: External<Option<AudioOutput>>,
+    listener: P3,
+    collecting: bool,
+    nearby_traffic: N,
+}
+
+impl SoundSystem {
+    pub fn spawn(
+        id: SoundSystemID,
+        simulation: SimulationID,
+        renderer_id: RendererID,
+        world: &mut World,
+    ) -> SoundSystem {
+        let settings: SoundSettings = ::ENV.load_settings("Sound");
+
+        let output = if settings.enabled {
+            match rodio::get_default_endpoint() {
+                Some(endpoint) => {
+                    let hum = Sink::new(&endpoint);
+                    hum.append(TrafficHum::new());
+                    hum.set_volume(0.0);
+                    Some(AudioOutput { endpoint, hum })
+                }
+                None => {
+                    println!("No audio device found, sound disabled");
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        renderer_id.add_eye_listener(0, id.into(), world);
+        simulation.wake_up_in(LISTEN_INTERVAL, id.into(), world);
+
+        SoundSystem {
+            id,
+            simulation,
+            settings: External::new(settings),
+            output: External::new(output),
+            listener: P3::new(0.0, 0.0, 0.0),
+            collecting: false,
+            nearby_traffic: 0.0,
+        }
+    }
+
+    /// Volume falloff with distance from the camera, 0.0 when out of hearing range
+    fn attenuation(&self, position: P2) -> f32 {
+        let distance = (position.into_3d() - self.listener).norm();
+        let hearing_distance = self.settings.hearing_distance;
+        if distance >= hearing_distance {
+            0.0
+        } else {
+            let closeness = 1.0 - distance / hearing_distance;
+            closeness * closeness
+        }
+    }
+
+    pub fn add_traffic_noise(&mut self, position: P2, n_cars: usize, _: &mut World) {
+        if self.collecting {
+            self.nearby_traffic += n_cars as N * self.attenuation(position);
+        }
+    }
+
+    pub fn play_event(&mut self, event: SoundEvent, position: P2, _: &mut World) {
+        let volume = self.attenuation(position) * self.settings.master_volume;
+        if volume <= 0.0 {
+            return;
+        }
+
+        if let Some(ref output) = *self.output {
+            let tone = match event {
+                SoundEvent::Construction => Tone::new(180.0, 90.0, Duration::from_millis(250)),
+                SoundEvent::Horn => Tone::new(420.0, 400.0, Duration::from_millis(400)),
+                SoundEvent::SignalTick => Tone::new(1200.0, 1200.0, Duration::from_millis(40)),
+            };
+            rodio::play_raw(&output.endpoint, tone.amplify(volume));
+        }
+    }
+}
+
+impl EyeListener for SoundSystem {
+    fn eye_moved(&mut self, eye: Eye, _movement: Movement, _: &mut World) {
+        self.listener = eye.position;
+    }
+}
+
+impl Sleeper for SoundSystem {
+    fn wake(&mut self, _current_tick: Timestamp, world: &mut World) {
+        if self.collecting {
+            self.collecting = false;
+            let volume = (self.nearby_traffic / CARS_FOR_FULL_HUM).min(1.0) *
+                self.settings.master_volume;
+            if let Some(ref output) = *self.output {
+                output.hum.set_volume(volume);
+            }
+            self.simulation.wake_up_in(LISTEN_INTERVAL, self.id.into(), world);
+        } else {
+            self.collecting = true;
+            self.nearby_traffic = 0.0;
+            LaneID::local_broadcast(world).report_traffic_noise(self.id, world);
+            self.simulation.wake_up_in(COLLECTION_TICKS, self.id.into(), world);
+        }
+    }
+}
+
+impl Lane {
+    pub fn report_traffic_noise(&mut self, sound_system: SoundSystemID, world: &mut World) {
+        if self.microtraffic.cars.is_empty() {
+            return;
+        }
+
+        let position = self.construction.path.along(self.construction.length / 2.0);
+        sound_system.add_traffic_noise(position, self.microtraffic.cars.len(), world);
+
+        if let Some(car) = self.microtraffic.cars.iter().find(|car| {
+            car.acceleration < HARD_BRAKING
+        })
+        {
+            let car_position = self.construction.path.along(*car.position);
+            sound_system.play_event(SoundEvent::Horn, car_position, world);
+        }
+    }
+}
+
+/// Plays `event` at `position` if the sound system is running
+pub fn play_event(event: SoundEvent, position: P2, world: &mut World) {
+    SoundSystemID::local_first(world).play_event(event, position, world);
+}
+
+pub fn setup(system: &mut ActorSystem, simulation: SimulationID, renderer_id: RendererID) {
+    system.register::<SoundSystem>();
+    auto_setup(system);
+
+    SoundSystemID::spawn(simulation, renderer_id, &mut system.world());
+}
+
+mod kay_auto;
+pub use self::kay_auto::*;
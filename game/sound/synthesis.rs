@@ -0,0 +1,106 @@
+use rodio::Source;
+use rand::{Rng, XorShiftRng, SeedableRng};
+use std::time::Duration;
+
+const SAMPLE_RATE: u32 = 44_100;
+
+/// Endless low-passed noise, sounds like distant traffic
+pub struct TrafficHum {
+    rng: XorShiftRng,
+    last: f32,
+}
+
+impl TrafficHum {
+    pub fn new() -> Self {
+        TrafficHum {
+            rng: XorShiftRng::from_seed([0x1234_5678, 0x9ABC_DEF0, 0x0FED_CBA9, 0x8765_4321]),
+            last: 0.0,
+        }
+    }
+}
+
+impl Iterator for TrafficHum {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let white = self.rng.gen_range(-1.0, 1.0);
+        // leaky integration gives a deep, brown-ish rumble
+        self.last = (self.last + 0.02 * white) * 0.995;
+        Some(self.last * 3.0)
+    }
+}
+
+impl Source for TrafficHum {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn samples_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// A short tone, optionally sliding in pitch, with a linear fade out
+pub struct Tone {
+    frequency: f32,
+    end_frequency: f32,
+    n_samples: usize,
+    i: usize,
+    phase: f32,
+}
+
+impl Tone {
+    pub fn new(frequency: f32, end_frequency: f32, duration: Duration) -> Self {
+        let seconds = duration.as_secs() as f32 + duration.subsec_nanos() as f32 / 1e9;
+        Tone {
+            frequency,
+            end_frequency,
+            n_samples: (seconds * SAMPLE_RATE as f32) as usize,
+            i: 0,
+            phase: 0.0,
+        }
+    }
+}
+
+impl Iterator for Tone {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.i >= self.n_samples {
+            return None;
+        }
+        let progress = self.i as f32 / self.n_samples as f32;
+        let frequency = self.frequency + (self.end_frequency - self.frequency) * progress;
+        self.phase = (self.phase + frequency / SAMPLE_RATE as f32) % 1.0;
+        self.i += 1;
+        Some((self.phase * 2.0 * ::std::f32::consts::PI).sin() * (1.0 - progress))
+    }
+}
+
+impl Source for Tone {
+    fn current_frame_len(&self) -> Option<usize> {
+        Some(self.n_samples - self.i)
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn samples_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        Some(Duration::from_millis(
+            (self.n_samples as u64 * 1000) / SAMPLE_RATE as u64,
+        ))
+    }
+}
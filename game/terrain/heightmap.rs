@@ -0,0 +1,223 @@
+use descartes::{N, P2, V2};
+use compact::CVec;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use core::geo::GeoReference;
+
+#[derive(Compact, Clone)]
+pub struct Heightmap {
+    pub width: usize,
+    pub height: usize,
+    pub samples: CVec<N>,
+    /// local position of the top left sample
+    pub origin: P2,
+    /// local offset between two neighbouring samples, y is usually negative
+    pub cell_size: V2,
+}
+
+impl Heightmap {
+    pub fn flat() -> Heightmap {
+        Heightmap {
+            width: 0,
+            height: 0,
+            samples: CVec::new(),
+            origin: P2::new(0.0, 0.0),
+            cell_size: V2::new(1.0, -1.0),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    pub fn sample(&self, x: usize, y: usize) -> N {
+        self.samples[y.min(self.height - 1) * self.width + x.min(self.width - 1)]
+    }
+
+    pub fn position_of(&self, x: usize, y: usize) -> P2 {
+        self.origin + V2::new(x as N * self.cell_size.x, y as N * self.cell_size.y)
+    }
+
+    /// Position in (fractional) samples from the top left sample
+    pub fn grid_position(&self, position: P2) -> (N, N) {
+        (
+            (position.x - self.origin.x) / self.cell_size.x,
+            (position.y - self.origin.y) / self.cell_size.y,
+        )
+    }
+
+    pub fn covers(&self, position: P2) -> bool {
+        let (grid_x, grid_y) = self.grid_position(position);
+        !self.is_empty() && grid_x >= 0.0 && grid_y >= 0.0 &&
+            grid_x <= (self.width - 1) as N && grid_y <= (self.height - 1) as N
+    }
+
+    /// Bilinearly interpolated height at a local position, 0.0 outside of the heightmap
+    pub fn height_at(&self, position: P2) -> N {
+        if !self.covers(position) {
+            return 0.0;
+        }
+        let (grid_x, grid_y) = self.grid_position(position);
+        let (x, y) = (grid_x.floor() as usize, grid_y.floor() as usize);
+        let (fx, fy) = (grid_x.fract(), grid_y.fract());
+        let top = self.sample(x, y) * (1.0 - fx) + self.sample(x + 1, y) * fx;
+        let bottom = self.sample(x, y + 1) * (1.0 - fx) + self.sample(x + 1, y + 1) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+}
+
+#[derive(Debug)]
+pub enum HeightmapError {
+    Io(::std::io::Error),
+    Decoding(String),
+    UnsupportedFormat(String),
+}
+
+impl From<::std::io::Error> for HeightmapError {
+    fn from(err: ::std::io::Error) -> HeightmapError {
+        HeightmapError::Io(err)
+    }
+}
+
+/// Loads a grayscale PNG heightmap (8 or 16 bit).
+///
+/// Georeferencing is read from an ESRI world file next to the image (`.pgw` or `.pngw`),
+/// which is what GDAL writes when converting a GeoTIFF to PNG, e.g. with
+/// `gdal_translate -of PNG -ot UInt16 -co WORLDFILE=YES dem.tif dem.png`.
+/// The world file is expected in WGS84 degrees. Without a world file, the heightmap
+/// is centered on the local origin using `fallback_cell_size` meters per pixel.
+/// GeoTIFFs themselves aren't supported, they have to be converted like this first.
+pub fn load_png(
+    path: &Path,
+    vertical_scale: N,
+    base_height: N,
+    fallback_cell_size: N,
+    geo_reference: &GeoReference,
+) -> Result<Heightmap, HeightmapError> {
+    let is_tiff = path.extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| {
+            extension.eq_ignore_ascii_case("tif") || extension.eq_ignore_ascii_case("tiff")
+        })
+        .unwrap_or(false);
+    if is_tiff {
+        return Err(HeightmapError::UnsupportedFormat(
+            "GeoTIFFs aren't supported, convert them to a 16 bit PNG with a world file"
+                .to_owned(),
+        ));
+    }
+
+    let decoder = ::png::Decoder::new(File::open(path)?);
+    let (info, mut reader) = decoder.read_info().map_err(|err| {
+        HeightmapError::Decoding(format!("{}", err))
+    })?;
+    let mut buffer = vec![0; info.buffer_size()];
+    reader.next_frame(&mut buffer).map_err(|err| {
+        HeightmapError::Decoding(format!("{}", err))
+    })?;
+
+    let channels = match info.color_type {
+        ::png::ColorType::Grayscale => 1,
+        ::png::ColorType::GrayscaleAlpha => 2,
+        other => {
+            return Err(HeightmapError::UnsupportedFormat(
+                format!("expected a grayscale heightmap, got {:?}", other),
+            ))
+        }
+    };
+
+    let samples: CVec<N> = match info.bit_depth {
+        ::png::BitDepth::Eight => {
+            buffer
+                .chunks(channels)
+                .map(|pixel| base_height + vertical_scale * N::from(pixel[0]))
+                .collect()
+        }
+        ::png::BitDepth::Sixteen => {
+            buffer
+                .chunks(2 * channels)
+                .map(|pixel| {
+                    let raw = (u16::from(pixel[0]) << 8) | u16::from(pixel[1]);
+                    base_height + vertical_scale * N::from(raw)
+                })
+                .collect()
+        }
+        other => {
+            return Err(HeightmapError::UnsupportedFormat(
+                format!("unsupported bit depth {:?}", other),
+            ))
+        }
+    };
+
+    let (width, height) = (info.width as usize, info.height as usize);
+
+    let (origin, cell_size) = match read_world_file(path, geo_reference)? {
+        Some(georeferenced) => georeferenced,
+        None => {
+            (
+                P2::new(
+                    -(width as N) * fallback_cell_size / 2.0,
+                    height as N * fallback_cell_size / 2.0,
+                ),
+                V2::new(fallback_cell_size, -fallback_cell_size),
+            )
+        }
+    };
+
+    Ok(Heightmap {
+        width,
+        height,
+        samples,
+        origin,
+        cell_size,
+    })
+}
+
+fn read_world_file(
+    image_path: &Path,
+    geo_reference: &GeoReference,
+) -> Result<Option<(P2, V2)>, HeightmapError> {
+    let maybe_file = ["pgw", "pngw"]
+        .iter()
+        .map(|extension| image_path.with_extension(extension))
+        .find(|path| path.exists());
+
+    let world_file_path = match maybe_file {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+
+    let values = BufReader::new(File::open(&world_file_path)?)
+        .lines()
+        .take(6)
+        .map(|line| {
+            line.map_err(HeightmapError::Io).and_then(|line| {
+                line.trim().parse::<f64>().map_err(|err| {
+                    HeightmapError::Decoding(format!("{:?}: {}", world_file_path, err))
+                })
+            })
+        })
+        .collect::<Result<Vec<f64>, _>>()?;
+
+    if values.len() < 6 {
+        return Err(HeightmapError::Decoding(
+            format!("{:?}: expected 6 lines", world_file_path),
+        ));
+    }
+
+    if values[1] != 0.0 || values[2] != 0.0 {
+        return Err(HeightmapError::UnsupportedFormat(
+            "rotated world files are not supported".to_owned(),
+        ));
+    }
+
+    let (pixel_lon, pixel_lat, top_left_lon, top_left_lat) =
+        (values[0], values[3], values[4], values[5]);
+
+    let origin = geo_reference.to_local(top_left_lat, top_left_lon);
+    let one_pixel_further =
+        geo_reference.to_local(top_left_lat + pixel_lat, top_left_lon + pixel_lon);
+
+    Ok(Some((origin, one_pixel_further - origin)))
+}
@@ -0,0 +1,426 @@
+use kay::{ActorSystem, World};
+use compact::CVec;
+use descartes::{N, P2, V2, Norm, Segment, FiniteCurve};
+use monet::{RendererID, Renderable, RenderableID, MSG_Renderable_setup_in_scene,
+            MSG_Renderable_render_to_scene, Geometry, Vertex, Instance};
+use core::geo::GeoReference;
+use stagemaster::geometry::CPath;
+use transport::planning::current_plan::CurrentPlanID;
+use transport::planning::validation::{self, ValidationIssue};
+use transport::ferries::FerryNetworkID;
+use rand::{Rng, XorShiftRng, SeedableRng};
+use std::path::Path;
+
+pub mod heightmap;
+pub mod water;
+
+use self::heightmap::Heightmap;
+use self::water::WaterArea;
+
+const TERRAIN_INDIVIDUAL_ID: u16 = 1;
+const WATER_INDIVIDUAL_ID: u16 = 2;
+// keep below the u16 index limit of a single geometry
+const MAX_RENDERED_SAMPLES_PER_SIDE: usize = 200;
+const TERRAIN_COLOR: [f32; 3] = [0.55, 0.68, 0.4];
+const WATER_COLOR: [f32; 3] = [0.25, 0.45, 0.7];
+/// Docks can be built on land this close to water
+const DOCK_REACH: N = 30.0;
+/// Decorative boats cruise back and forth between two points at least this far apart,
+/// with only water in between
+const MIN_CRUISE_LENGTH: N = 100.0;
+const CRUISE_SAMPLE_DISTANCE: N = 10.0;
+const CRUISE_ATTEMPTS: usize = 50;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TerrainSettings {
+    /// path to a grayscale PNG heightmap, flat terrain if empty. GeoTIFFs have to be
+    /// converted first, see `heightmap::load_png`
+    pub heightmap_path: String,
+    /// meters per heightmap value
+    pub vertical_scale: N,
+    /// height in meters that a heightmap value of 0 corresponds to
+    pub base_height: N,
+    /// meters per pixel if the heightmap has no world file
+    pub fallback_cell_size: N,
+    /// the point mapped to the local origin, should match the one used for OSM imports
+    pub geo_reference: GeoReference,
+    /// heightmap areas lower than this (in meters) are under water, no water if unset
+    pub water_level: Option<N>,
+    /// outlines of lakes, rivers and bays as local (x, y) points, for flat terrain
+    /// or water that the heightmap doesn't show
+    pub water_areas: Vec<Vec<(N, N)>>,
+}
+
+impl Default for TerrainSettings {
+    fn default() -> Self {
+        TerrainSettings {
+            heightmap_path: String::new(),
+            vertical_scale: 0.1,
+            base_height: 0.0,
+            fallback_cell_size: 10.0,
+            geo_reference: GeoReference::default(),
+            water_level: None,
+            water_areas: Vec::new(),
+        }
+    }
+}
+
+#[derive(Compact, Clone)]
+pub struct Terrain {
+    id: TerrainID,
+    heightmap: Heightmap,
+    min_height: N,
+    water_level: Option<N>,
+    water_areas: CVec<WaterArea>,
+    /// For each rendered sample, how many lanes and buildings need it leveled
+    leveled: CVec<u16>,
+    rendered: bool,
+}
+
+/// Every how many heightmap samples one is rendered and how many columns and rows
+/// of them there are, rounded up so there are never more than the maximum per side
+fn rendered_grid(heightmap: &Heightmap) -> (usize, usize, usize) {
+    let longest_side = heightmap.width.max(heightmap.height);
+    let step = ((longest_side + MAX_RENDERED_SAMPLES_PER_SIDE - 1) /
+                    MAX_RENDERED_SAMPLES_PER_SIDE)
+        .max(1);
+    let columns = (heightmap.width + step - 1) / step;
+    let rows = (heightmap.height + step - 1) / step;
+    (step, columns, rows)
+}
+
+impl Terrain {
+    pub fn spawn(id: TerrainID, _: &mut World) -> Terrain {
+        let settings: TerrainSettings = ::ENV.load_settings("Terrain");
+
+        let heightmap = if settings.heightmap_path.is_empty() {
+            Heightmap::flat()
+        } else {
+            match heightmap::load_png(
+                Path::new(&settings.heightmap_path),
+                settings.vertical_scale,
+                settings.base_height,
+                settings.fallback_cell_size,
+                &settings.geo_reference,
+            ) {
+                Ok(heightmap) => {
+                    println!(
+                        "Loaded {}x{} heightmap from {}",
+                        heightmap.width,
+                        heightmap.height,
+                        settings.heightmap_path
+                    );
+                    heightmap
+                }
+                Err(err) => {
+                    println!(
+                        "Error loading heightmap {}: {:?}",
+                        settings.heightmap_path,
+                        err
+                    );
+                    Heightmap::flat()
+                }
+            }
+        };
+
+        let min_height = heightmap.samples.iter().cloned().fold(
+            ::std::f32::INFINITY,
+            N::min,
+        );
+
+        let (_, columns, rows) = rendered_grid(&heightmap);
+
+        Terrain {
+            id,
+            min_height: if min_height.is_finite() { min_height } else { 0.0 },
+            leveled: vec![0; columns * rows].into(),
+            heightmap,
+            water_level: settings.water_level,
+            water_areas: settings
+                .water_areas
+                .iter()
+                .filter(|outline| outline.len() >= 3)
+                .map(|outline| WaterArea::new(outline))
+                .collect(),
+            rendered: false,
+        }
+    }
+
+    fn is_water(&self, position: P2) -> bool {
+        let below_water_level = self.water_level
+            .map(|level| {
+                self.heightmap.covers(position) && self.heightmap.height_at(position) < level
+            })
+            .unwrap_or(false);
+        below_water_level || self.water_areas.iter().any(|area| area.contains(position))
+    }
+
+    fn geometry(&self) -> Geometry {
+        let (step, columns, rows) = rendered_grid(&self.heightmap);
+
+        let mut vertices = Vec::with_capacity(columns * rows);
+        for row in 0..rows {
+            for column in 0..columns {
+                let (x, y) = (column * step, row * step);
+                let position = self.heightmap.position_of(x, y);
+                // the lowest point sits slightly below the ground plane lanes are drawn on,
+                // which is also where the terrain is leveled to under lanes and buildings
+                let z = if self.leveled[row * columns + column] > 0 {
+                    -0.1
+                } else {
+                    self.heightmap.sample(x, y) - self.min_height - 0.1
+                };
+                vertices.push(Vertex { position: [position.x, position.y, z] });
+            }
+        }
+
+        let mut indices = Vec::with_capacity(columns * rows * 6);
+        for row in 0..(rows.max(1) - 1) {
+            for column in 0..(columns.max(1) - 1) {
+                let top_left = (row * columns + column) as u16;
+                let top_right = top_left + 1;
+                let bottom_left = top_left + columns as u16;
+                let bottom_right = bottom_left + 1;
+                indices.extend_from_slice(
+                    &[top_left, top_right, bottom_right, top_left, bottom_right, bottom_left],
+                );
+            }
+        }
+
+        Geometry::new(vertices, indices)
+    }
+
+    fn water_geometry(&self) -> Geometry {
+        let mut geometry: Geometry = self.water_areas
+            .iter()
+            .map(|area| area.geometry(-0.05))
+            .sum();
+        if let (Some(level), false) = (self.water_level, self.heightmap.is_empty()) {
+            // a plane across the whole heightmap, the terrain above the water covers it
+            let z = level - self.min_height - 0.1;
+            let corners = [
+                self.heightmap.position_of(0, 0),
+                self.heightmap.position_of(self.heightmap.width - 1, 0),
+                self.heightmap.position_of(self.heightmap.width - 1, self.heightmap.height - 1),
+                self.heightmap.position_of(0, self.heightmap.height - 1),
+            ];
+            geometry += Geometry::new(
+                corners
+                    .iter()
+                    .map(|corner| Vertex { position: [corner.x, corner.y, z] })
+                    .collect(),
+                vec![0, 1, 2, 0, 2, 3],
+            );
+        }
+        geometry
+    }
+
+    /// Lanes and buildings are drawn on a flat ground plane, so the terrain is leveled
+    /// down to it under them, or back up once they are gone (if not `leveled`)
+    fn level_around_points(&mut self, points: &[P2], radius: N, leveled: bool) {
+        if self.heightmap.is_empty() {
+            return;
+        }
+        let (step, columns, rows) = rendered_grid(&self.heightmap);
+        let rendered_cell_size = self.heightmap.cell_size * step as N;
+        // samples up to a cell further away are leveled as well,
+        // so that the terrain between samples doesn't rise above the ground plane
+        let reach = radius + rendered_cell_size.norm();
+        let span_x = (reach / rendered_cell_size.x.abs()).ceil() as isize;
+        let span_y = (reach / rendered_cell_size.y.abs()).ceil() as isize;
+
+        let mut indices = Vec::new();
+        for &point in points {
+            let (grid_x, grid_y) = self.heightmap.grid_position(point);
+            let (center_column, center_row) = (
+                (grid_x / step as N).round() as isize,
+                (grid_y / step as N).round() as isize,
+            );
+            for row in (center_row - span_y)..(center_row + span_y + 1) {
+                for column in (center_column - span_x)..(center_column + span_x + 1) {
+                    let in_grid = row >= 0 && column >= 0 && (row as usize) < rows &&
+                        (column as usize) < columns;
+                    if in_grid {
+                        let sample_position = self.heightmap
+                            .position_of(column as usize * step, row as usize * step);
+                        if (sample_position - point).norm() <= reach {
+                            indices.push(row as usize * columns + column as usize);
+                        }
+                    }
+                }
+            }
+        }
+        indices.sort();
+        indices.dedup();
+
+        for index in indices {
+            self.leveled[index] = if leveled {
+                self.leveled[index] + 1
+            } else {
+                self.leveled[index].saturating_sub(1)
+            };
+            self.rendered = false;
+        }
+    }
+
+    /// Levels the terrain along the path of a lane of the given `width`,
+    /// or undoes that if not `leveled`, see `level_around_points`
+    pub fn level_along(&mut self, path: &CPath, width: N, leveled: bool, _: &mut World) {
+        let spacing = (self.heightmap.cell_size.x.abs().min(self.heightmap.cell_size.y.abs()) /
+                           2.0)
+            .max(1.0);
+        let n_points = (path.length() / spacing).ceil().max(1.0) as usize;
+        let points = (0..(n_points + 1))
+            .map(|i| path.along(path.length() * i as N / n_points as N))
+            .collect::<Vec<_>>();
+        self.level_around_points(&points, width / 2.0, leveled);
+    }
+
+    /// Levels the terrain within `radius` of `position` (usually a building lot),
+    /// or undoes that if not `leveled`, see `level_around_points`
+    pub fn level_around(&mut self, position: P2, radius: N, leveled: bool, _: &mut World) {
+        self.level_around_points(&[position], radius, leveled);
+    }
+
+    /// Reports the places along `paths` that would be too steep, or that cross water
+    /// without being on one of `structures`, to `requester`. Bridges and tunnels don't
+    /// follow the terrain, so they can't be too steep
+    pub fn check_terrain(
+        &mut self,
+        paths: &CVec<CPath>,
+        structures: &CVec<CPath>,
+        requester: CurrentPlanID,
+        world: &mut World,
+    ) {
+        let mut issues = CVec::<ValidationIssue>::new();
+        for path in paths.iter() {
+            let grade_issues =
+                validation::check_grades(path, |position| self.heightmap.height_at(position));
+            issues.extend(grade_issues.into_iter().filter(|issue| {
+                !validation::on_structure(issue.position, structures)
+            }));
+            issues.extend(validation::check_water_crossings(
+                path,
+                |position| self.is_water(position),
+                structures,
+            ));
+        }
+        requester.on_terrain_issues(issues, world);
+    }
+
+    /// Tells `requester` whether a ferry dock can be built at `position`:
+    /// on land, but close to water
+    pub fn check_dock_site(&mut self, position: P2, requester: FerryNetworkID, world: &mut World) {
+        let n_directions = 8;
+        let near_water = !self.is_water(position) &&
+            (0..n_directions).any(|i| {
+                let angle = 2.0 * ::std::f32::consts::PI * i as N / n_directions as N;
+                let direction = V2::new(angle.cos(), angle.sin());
+                [DOCK_REACH / 3.0, DOCK_REACH * 2.0 / 3.0, DOCK_REACH]
+                    .iter()
+                    .any(|&distance| self.is_water(position + direction * distance))
+            });
+        requester.on_dock_site(position, near_water, world);
+    }
+
+    fn water_sample_point<R: Rng>(&self, rng: &mut R) -> Option<P2> {
+        let (min, max) = if self.water_areas.is_empty() {
+            if self.water_level.is_none() || self.heightmap.is_empty() {
+                return None;
+            }
+            let (a, b) = (
+                self.heightmap.position_of(0, 0),
+                self.heightmap.position_of(self.heightmap.width - 1, self.heightmap.height - 1),
+            );
+            (P2::new(a.x.min(b.x), a.y.min(b.y)), P2::new(a.x.max(b.x), a.y.max(b.y)))
+        } else {
+            self.water_areas[rng.gen_range(0, self.water_areas.len())].bounds()
+        };
+        let point = P2::new(
+            rng.gen_range(min.x, max.x.max(min.x + 1.0)),
+            rng.gen_range(min.y, max.y.max(min.y + 1.0)),
+        );
+        if self.is_water(point) {
+            Some(point)
+        } else {
+            None
+        }
+    }
+
+    /// Finds up to `n_routes` straight stretches of open water for decorative boats,
+    /// always the same ones for the same terrain, and sends them to `requester`
+    pub fn find_cruising_routes(
+        &mut self,
+        n_routes: usize,
+        requester: FerryNetworkID,
+        world: &mut World,
+    ) {
+        let mut rng = XorShiftRng::from_seed([0x5eed, 0xb0a7, 0xf1e7, 0x1a4e]);
+        let mut routes = CVec::<CPath>::new();
+        for _ in 0..(n_routes * CRUISE_ATTEMPTS) {
+            if routes.len() >= n_routes {
+                break;
+            }
+            let maybe_ends = self.water_sample_point(&mut rng).and_then(|start| {
+                self.water_sample_point(&mut rng).map(|end| (start, end))
+            });
+            if let Some((start, end)) = maybe_ends {
+                let length = (end - start).norm();
+                let n_samples = (length / CRUISE_SAMPLE_DISTANCE).ceil() as usize;
+                let open_water = (0..n_samples).all(|i| {
+                    self.is_water(start + (end - start) * (i as N / n_samples as N))
+                });
+                if length > MIN_CRUISE_LENGTH && open_water {
+                    routes.push(CPath::new(vec![Segment::line(start, end)]));
+                }
+            }
+        }
+        requester.on_cruising_routes(routes, world);
+    }
+}
+
+impl Renderable for Terrain {
+    fn setup_in_scene(&mut self, _renderer_id: RendererID, _scene_id: usize, _: &mut World) {
+        self.rendered = false;
+    }
+
+    fn render_to_scene(
+        &mut self,
+        renderer_id: RendererID,
+        scene_id: usize,
+        _frame: usize,
+        world: &mut World,
+    ) {
+        if !self.rendered {
+            if !self.heightmap.is_empty() {
+                renderer_id.update_individual(
+                    scene_id,
+                    TERRAIN_INDIVIDUAL_ID,
+                    self.geometry(),
+                    Instance::with_color(TERRAIN_COLOR),
+                    false,
+                    world,
+                );
+            }
+            renderer_id.update_individual(
+                scene_id,
+                WATER_INDIVIDUAL_ID,
+                self.water_geometry(),
+                Instance::with_color(WATER_COLOR),
+                false,
+                world,
+            );
+            self.rendered = true;
+        }
+    }
+}
+
+pub fn setup(system: &mut ActorSystem) -> TerrainID {
+    system.register::<Terrain>();
+    auto_setup(system);
+
+    TerrainID::spawn(&mut system.world())
+}
+
+mod kay_auto;
+pub use self::kay_auto::*;
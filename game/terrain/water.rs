@@ -0,0 +1,110 @@
+use descartes::{N, P2, V2};
+use compact::CVec;
+use monet::{Geometry, Vertex};
+
+/// A lake, river or bay, outlined by a polygon without holes
+#[derive(Compact, Clone)]
+pub struct WaterArea {
+    pub outline: CVec<P2>,
+}
+
+fn cross(a: V2, b: V2) -> N {
+    a.x * b.y - a.y * b.x
+}
+
+fn in_triangle(point: P2, a: P2, b: P2, c: P2) -> bool {
+    let ab = cross(b - a, point - a);
+    let bc = cross(c - b, point - b);
+    let ca = cross(a - c, point - c);
+    (ab >= 0.0 && bc >= 0.0 && ca >= 0.0) || (ab <= 0.0 && bc <= 0.0 && ca <= 0.0)
+}
+
+/// Whether `point` is inside the polygon with the corners `outline`. Uses the even-odd
+/// rule, so it works for either orientation of the outline
+pub fn polygon_contains(outline: &[P2], point: P2) -> bool {
+    let n = outline.len();
+    let mut inside = false;
+    for i in 0..n {
+        let (a, b) = (outline[i], outline[(i + 1) % n]);
+        if (a.y > point.y) != (b.y > point.y) &&
+            point.x < a.x + (point.y - a.y) / (b.y - a.y) * (b.x - a.x)
+        {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+impl WaterArea {
+    pub fn new(outline: &[(N, N)]) -> WaterArea {
+        WaterArea { outline: outline.iter().map(|&(x, y)| P2::new(x, y)).collect() }
+    }
+
+    pub fn contains(&self, point: P2) -> bool {
+        polygon_contains(&self.outline, point)
+    }
+
+    /// The lowest and the highest corner of the bounding box
+    pub fn bounds(&self) -> (P2, P2) {
+        self.outline.iter().fold(
+            (
+                P2::new(::std::f32::INFINITY, ::std::f32::INFINITY),
+                P2::new(::std::f32::NEG_INFINITY, ::std::f32::NEG_INFINITY),
+            ),
+            |(min, max), point| {
+                (
+                    P2::new(min.x.min(point.x), min.y.min(point.y)),
+                    P2::new(max.x.max(point.x), max.y.max(point.y)),
+                )
+            },
+        )
+    }
+
+    fn signed_area(&self) -> N {
+        let n = self.outline.len();
+        (0..n)
+            .map(|i| {
+                cross(self.outline[i].to_vector(), self.outline[(i + 1) % n].to_vector())
+            })
+            .sum::<N>() / 2.0
+    }
+
+    /// The outline as a flat surface at height `z`, triangulated by clipping ears
+    pub fn geometry(&self, z: N) -> Geometry {
+        let mut remaining = (0..self.outline.len()).collect::<Vec<_>>();
+        if self.signed_area() < 0.0 {
+            remaining.reverse();
+        }
+
+        let mut indices = Vec::new();
+        let mut attempts = 0;
+        while remaining.len() > 3 && attempts < remaining.len() {
+            let n = remaining.len();
+            let i = attempts;
+            let (prev, current, next) =
+                (remaining[(i + n - 1) % n], remaining[i], remaining[(i + 1) % n]);
+            let (a, b, c) = (self.outline[prev], self.outline[current], self.outline[next]);
+            let is_ear = cross(b - a, c - b) > 0.0 &&
+                !remaining.iter().any(|&other| {
+                    other != prev && other != current && other != next &&
+                        in_triangle(self.outline[other], a, b, c)
+                });
+            if is_ear {
+                indices.extend_from_slice(&[prev as u16, current as u16, next as u16]);
+                remaining.remove(i);
+                attempts = 0;
+            } else {
+                attempts += 1;
+            }
+        }
+        if remaining.len() == 3 {
+            indices.extend(remaining.iter().map(|&idx| idx as u16));
+        }
+
+        let vertices = self.outline
+            .iter()
+            .map(|point| Vertex { position: [point.x, point.y, z] })
+            .collect();
+        Geometry::new(vertices, indices)
+    }
+}
@@ -0,0 +1,508 @@
+//! Traffic analysis zones (TAZs), as used in four-step travel demand models. Zones are
+//! polygons, drawn with the "Draw Traffic Zone" action or listed in the settings. All car
+//! trips are recorded with the time slice of the day they started in, and can be exported
+//! as CSV: the zones' productions and attractions (trips starting and ending in them) and
+//! the assigned origin-destination matrix, both per time slice.
+use kay::{ActorSystem, World, External};
+use compact::{CVec, CDict, CHashMap};
+use descartes::{N, P2, Band, Segment, FiniteCurve};
+use monet::{RendererID, Renderable, RenderableID, MSG_Renderable_setup_in_scene,
+            MSG_Renderable_render_to_scene, Geometry, Instance};
+use stagemaster::{UserInterfaceID, Event3d, Interactable3d, Interactable3dID,
+                  MSG_Interactable3d_on_event, Interactable2d, Interactable2dID,
+                  MSG_Interactable2d_draw_ui_2d};
+use stagemaster::combo::Combo2;
+use stagemaster::combo::Button::{LControl, X};
+use stagemaster::actions::{register_action, ActionId, ActionListener, ActionListenerID,
+                           ActionPhase, MSG_ActionListener_on_action};
+use stagemaster::geometry::{CPath, band_to_geometry};
+use transport::planning::current_plan::set_tool_active;
+use imgui::ImGuiSetCond_FirstUseEver;
+use core::simulation::{SimulationID, Sleeper, SleeperID, MSG_Sleeper_wake, Timestamp, Ticks,
+                       TimeOfDay};
+use terrain::water::polygon_contains;
+use std::collections::BTreeMap;
+use std::fs::{File, create_dir_all};
+use std::io::Write;
+use super::lane::{Lane, LaneID};
+use super::pathfinding::NodeID;
+
+const SETTINGS_CATEGORY: &'static str = "Traffic Analysis Zones";
+const EXPORT_DIR: &str = "exports";
+const COLLECTION_TICKS: Ticks = Ticks(10);
+const ZONES_INDIVIDUAL_ID: u16 = 8114;
+const OUTLINE_WIDTH: N = 2.0;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TrafficZoneSettings {
+    /// The corners of each zone, zones are numbered from 1 in this order
+    pub zones: Vec<Vec<(N, N)>>,
+    /// Length of the time slices that trips are grouped into
+    pub slice_minutes: usize,
+}
+
+impl Default for TrafficZoneSettings {
+    fn default() -> Self {
+        TrafficZoneSettings {
+            zones: Vec::new(),
+            slice_minutes: 60,
+        }
+    }
+}
+
+#[derive(Compact, Clone)]
+pub struct TrafficZone {
+    pub outline: CVec<P2>,
+}
+
+impl TrafficZone {
+    fn outline_path(&self, closed: bool) -> Option<CPath> {
+        let n = self.outline.len();
+        let n_segments = if closed { n } else { n.saturating_sub(1) };
+        let segments = (0..n_segments)
+            .map(|i| Segment::line(self.outline[i], self.outline[(i + 1) % n]))
+            .collect::<Vec<_>>();
+        if segments.is_empty() {
+            None
+        } else {
+            Some(CPath::new(segments))
+        }
+    }
+}
+
+/// The index of the first zone that contains `point`
+pub fn zone_of(zones: &[TrafficZone], point: P2) -> Option<usize> {
+    zones.iter().position(|zone| polygon_contains(&zone.outline, point))
+}
+
+/// Trips per (time slice, origin zone, destination zone), for trips given as their origin,
+/// their destination, the time slice and how many there were. Trips starting or ending
+/// outside of all zones are left out
+pub fn od_matrix(
+    zones: &[TrafficZone],
+    trips: &[(P2, P2, usize, usize)],
+) -> BTreeMap<(usize, usize, usize), usize> {
+    let mut matrix = BTreeMap::new();
+    for &(origin, destination, slice, n_trips) in trips {
+        if let (Some(from), Some(to)) = (zone_of(zones, origin), zone_of(zones, destination)) {
+            *matrix.entry((slice, from, to)).or_insert(0) += n_trips;
+        }
+    }
+    matrix
+}
+
+fn slice_start(slice: usize, slice_minutes: usize) -> String {
+    let minutes = slice * slice_minutes;
+    format!("{:02}:{:02}", minutes / 60, minutes % 60)
+}
+
+/// One row per time slice and pair of zones that had trips between them
+pub fn od_csv(matrix: &BTreeMap<(usize, usize, usize), usize>, slice_minutes: usize) -> String {
+    let mut csv = "slice_start,origin_zone,destination_zone,trips\n".to_owned();
+    for (&(slice, from, to), n_trips) in matrix {
+        csv.push_str(&format!(
+            "{},{},{},{}\n",
+            slice_start(slice, slice_minutes),
+            from + 1,
+            to + 1,
+            n_trips
+        ));
+    }
+    csv
+}
+
+/// One row per time slice with trips and zone: how many trips started (productions)
+/// and ended (attractions) in the zone
+pub fn production_attraction_csv(
+    matrix: &BTreeMap<(usize, usize, usize), usize>,
+    n_zones: usize,
+    slice_minutes: usize,
+) -> String {
+    let mut totals = BTreeMap::<usize, Vec<(usize, usize)>>::new();
+    for (&(slice, from, to), &n_trips) in matrix {
+        let slice_totals = totals.entry(slice).or_insert_with(|| vec![(0, 0); n_zones]);
+        slice_totals[from].0 += n_trips;
+        slice_totals[to].1 += n_trips;
+    }
+
+    let mut csv = "slice_start,zone,productions,attractions\n".to_owned();
+    for (&slice, slice_totals) in &totals {
+        for (zone, &(productions, attractions)) in slice_totals.iter().enumerate() {
+            csv.push_str(&format!(
+                "{},{},{},{}\n",
+                slice_start(slice, slice_minutes),
+                zone + 1,
+                productions,
+                attractions
+            ));
+        }
+    }
+    csv
+}
+
+fn write_export(file_name: &str, contents: &str) {
+    let path = format!("{}/{}", EXPORT_DIR, file_name);
+    let result = create_dir_all(EXPORT_DIR)
+        .and_then(|_| File::create(&path))
+        .and_then(|mut file| file.write_all(contents.as_bytes()));
+    match result {
+        Ok(()) => println!("Exported {}", path),
+        Err(err) => println!("Error exporting {}: {}", path, err),
+    }
+}
+
+impl Lane {
+    pub fn report_zone_position(&mut self, zones: TrafficZonesID, world: &mut World) {
+        zones.add_node_position(
+            self.id.into(),
+            self.construction.path.start(),
+            self.construction.path.end(),
+            world,
+        );
+    }
+}
+
+/// Keeps the traffic analysis zones and counts the car trips between lanes per time slice,
+/// until they are exported. Which zones the lanes are in is only found out when exporting,
+/// since zones can still change while recording
+#[derive(Compact, Clone)]
+pub struct TrafficZones {
+    id: TrafficZonesID,
+    simulation: SimulationID,
+    user_interface: UserInterfaceID,
+    settings: External<TrafficZoneSettings>,
+    zones: CVec<TrafficZone>,
+    /// Trips per source lane, destination lane and time slice
+    trips: CHashMap<(NodeID, NodeID, usize), usize>,
+    n_trips: usize,
+    recording_since: Option<Timestamp>,
+    /// Start and end of the lanes trips went from and to, while exporting
+    node_positions: CHashMap<NodeID, (P2, P2)>,
+    exporting: bool,
+    /// The corners of the zone being drawn
+    drawing: Option<TrafficZone>,
+    rendered_in: CDict<RendererID, ()>,
+}
+
+impl TrafficZones {
+    pub fn spawn(
+        id: TrafficZonesID,
+        simulation: SimulationID,
+        user_interface: UserInterfaceID,
+        world: &mut World,
+    ) -> TrafficZones {
+        user_interface.add_2d(id.into(), world);
+        register_action(
+            "Draw Traffic Zone",
+            Combo2::new(&[LControl, X], &[]),
+            id.into(),
+            world,
+        );
+        let settings: TrafficZoneSettings = ::ENV.load_settings(SETTINGS_CATEGORY);
+        let zones = settings
+            .zones
+            .iter()
+            .map(|corners| {
+                TrafficZone { outline: corners.iter().map(|&(x, y)| P2::new(x, y)).collect() }
+            })
+            .collect();
+
+        TrafficZones {
+            id,
+            simulation,
+            user_interface,
+            settings: External::new(settings),
+            zones,
+            trips: CHashMap::new(),
+            n_trips: 0,
+            recording_since: None,
+            node_positions: CHashMap::new(),
+            exporting: false,
+            drawing: None,
+            rendered_in: CDict::new(),
+        }
+    }
+
+    pub fn add_trip(
+        &mut self,
+        source: NodeID,
+        destination: NodeID,
+        tick: Timestamp,
+        _: &mut World,
+    ) {
+        let (hours, minutes) = TimeOfDay::from_tick(tick).hours_minutes();
+        let slice = (hours * 60 + minutes) / self.settings.slice_minutes.max(1);
+        let n_trips = self.trips.get((source, destination, slice)).cloned().unwrap_or(0);
+        self.trips.insert((source, destination, slice), n_trips + 1);
+        self.n_trips += 1;
+        if self.recording_since.is_none() {
+            self.recording_since = Some(tick);
+        }
+    }
+
+    pub fn add_node_position(&mut self, node: NodeID, start: P2, end: P2, _: &mut World) {
+        if self.exporting {
+            self.node_positions.insert(node, (start, end));
+        }
+    }
+
+    /// Finds out where all recorded trips went from and to, then writes the CSV files
+    pub fn export(&mut self, world: &mut World) {
+        if self.exporting {
+            return;
+        }
+        self.exporting = true;
+        self.node_positions = CHashMap::new();
+        for &(source, destination, _) in self.trips.keys() {
+            for &node in &[source, destination] {
+                LaneID::from(node).report_zone_position(self.id, world);
+            }
+        }
+        self.simulation.wake_up_in(COLLECTION_TICKS, self.id.into(), world);
+    }
+
+    pub fn reset(&mut self, _: &mut World) {
+        self.trips = CHashMap::new();
+        self.n_trips = 0;
+        self.recording_since = None;
+    }
+
+    pub fn remove_zone(&mut self, zone_idx: usize, _: &mut World) {
+        if zone_idx < self.zones.len() {
+            self.zones.remove(zone_idx);
+            self.save_zones();
+        }
+    }
+
+    fn save_zones(&mut self) {
+        self.settings.zones = self.zones
+            .iter()
+            .map(|zone| zone.outline.iter().map(|point| (point.x, point.y)).collect())
+            .collect();
+        ::ENV.write_settings(SETTINGS_CATEGORY, &*self.settings);
+        self.rendered_in = CDict::new();
+    }
+
+    fn write_exports(&self, tick: Timestamp) {
+        if self.zones.is_empty() {
+            println!("No traffic analysis zones to export trips for, draw some first");
+            return;
+        }
+        let trips = self.trips
+            .pairs()
+            .filter_map(|(&(source, destination, slice), &n_trips)| {
+                match (self.node_positions.get(source), self.node_positions.get(destination)) {
+                    (Some(&(origin, _)), Some(&(_, destination))) => {
+                        Some((origin, destination, slice, n_trips))
+                    }
+                    // the lane is gone, and so is the knowledge of where it was
+                    _ => None,
+                }
+            })
+            .collect::<Vec<_>>();
+        let matrix = od_matrix(&self.zones, &trips);
+        let slice_minutes = self.settings.slice_minutes.max(1);
+
+        write_export(
+            &format!("taz_od_{}.csv", tick.ticks()),
+            &od_csv(&matrix, slice_minutes),
+        );
+        write_export(
+            &format!("taz_productions_attractions_{}.csv", tick.ticks()),
+            &production_attraction_csv(&matrix, self.zones.len(), slice_minutes),
+        );
+    }
+}
+
+impl Sleeper for TrafficZones {
+    fn wake(&mut self, current_tick: Timestamp, _: &mut World) {
+        if self.exporting {
+            self.exporting = false;
+            self.write_exports(current_tick);
+            self.node_positions = CHashMap::new();
+        }
+    }
+}
+
+impl ActionListener for TrafficZones {
+    fn on_action(&mut self, action: ActionId, phase: ActionPhase, world: &mut World) {
+        if phase == ActionPhase::Started && action == ActionId::named("Draw Traffic Zone") {
+            if let Some(zone) = self.drawing.take() {
+                if zone.outline.len() >= 3 {
+                    self.zones.push(zone);
+                    self.save_zones();
+                }
+                self.rendered_in = CDict::new();
+                set_tool_active(self.user_interface, self.id.into(), false, world);
+            } else {
+                self.drawing = Some(TrafficZone { outline: CVec::new() });
+                set_tool_active(self.user_interface, self.id.into(), true, world);
+            }
+        }
+    }
+}
+
+impl Interactable3d for TrafficZones {
+    fn on_event(&mut self, event: Event3d, world: &mut World) {
+        if let Some(ref mut zone) = self.drawing {
+            match event {
+                Event3d::DragFinished { to, .. } => {
+                    zone.outline.push(P2::new(to.x, to.y));
+                    self.rendered_in = CDict::new();
+                }
+                Event3d::Frame => {
+                    self.user_interface.add_debug_text(
+                        "Drawing traffic zone".chars().collect(),
+                        format!(
+                            "{} corners, click to add more, Draw Traffic Zone again to finish",
+                            zone.outline.len()
+                        ).chars()
+                            .collect(),
+                        [0.0, 0.0, 0.0, 1.0],
+                        false,
+                        world,
+                    );
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Renderable for TrafficZones {
+    fn setup_in_scene(&mut self, _renderer_id: RendererID, _scene_id: usize, _: &mut World) {
+        self.rendered_in = CDict::new();
+    }
+
+    fn render_to_scene(
+        &mut self,
+        renderer_id: RendererID,
+        scene_id: usize,
+        _frame: usize,
+        world: &mut World,
+    ) {
+        if self.rendered_in.get(renderer_id).is_none() {
+            let outlines = self.zones
+                .iter()
+                .filter_map(|zone| zone.outline_path(true))
+                .chain(self.drawing.iter().filter_map(|zone| zone.outline_path(false)))
+                .map(|path| band_to_geometry(&Band::new(path, OUTLINE_WIDTH), 0.2))
+                .sum::<Geometry>();
+            renderer_id.update_individual(
+                scene_id,
+                ZONES_INDIVIDUAL_ID,
+                outlines,
+                Instance::with_color([0.6, 0.2, 0.6]),
+                true,
+                world,
+            );
+            self.rendered_in.insert(renderer_id, ());
+        }
+    }
+}
+
+impl Interactable2d for TrafficZones {
+    fn draw_ui_2d(
+        &mut self,
+        imgui_ui: &External<::imgui::Ui<'static>>,
+        return_to: UserInterfaceID,
+        world: &mut World,
+    ) {
+        let ui = imgui_ui.steal();
+
+        ui.window(im_str!("Traffic Analysis Zones"))
+            .size((300.0, 250.0), ImGuiSetCond_FirstUseEver)
+            .collapsible(true)
+            .build(|| {
+                ui.text(im_str!(
+                    "{} zones, {} min time slices",
+                    self.zones.len(),
+                    self.settings.slice_minutes
+                ));
+                match self.recording_since {
+                    Some(since) => {
+                        ui.text(im_str!(
+                            "{} car trips recorded since day {}",
+                            self.n_trips,
+                            since.day() + 1
+                        ))
+                    }
+                    None => ui.text(im_str!("No car trips recorded yet")),
+                }
+
+                if self.exporting {
+                    ui.text(im_str!("Exporting..."));
+                } else if ui.small_button(im_str!("Export CSV")) {
+                    self.id.export(world);
+                }
+                ui.same_line(0.0);
+                if ui.small_button(im_str!("Reset")) {
+                    self.id.reset(world);
+                }
+
+                if ui.collapsing_header(im_str!("Zones")).build() {
+                    if self.zones.is_empty() {
+                        ui.text(im_str!("Draw Traffic Zone to add the first zone"));
+                    }
+                    for (i, zone) in self.zones.iter().enumerate() {
+                        ui.text(im_str!("Zone {}: {} corners", i + 1, zone.outline.len()));
+                        ui.same_line(0.0);
+                        if ui.small_button(im_str!("Remove##{}", i)) {
+                            self.id.remove_zone(i, world);
+                        }
+                    }
+                }
+            });
+
+        return_to.ui_drawn(ui, world);
+    }
+}
+
+pub fn setup(system: &mut ActorSystem, user_interface: UserInterfaceID, simulation: SimulationID) {
+    system.register::<TrafficZones>();
+    auto_setup(system);
+
+    TrafficZonesID::spawn(simulation, user_interface, &mut system.world());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(x: N, y: N, size: N) -> TrafficZone {
+        TrafficZone {
+            outline: vec![
+                P2::new(x, y),
+                P2::new(x + size, y),
+                P2::new(x + size, y + size),
+                P2::new(x, y + size),
+            ].into(),
+        }
+    }
+
+    #[test]
+    fn trips_are_counted_between_the_zones_they_start_and_end_in() {
+        let zones = vec![square(0.0, 0.0, 100.0), square(200.0, 0.0, 100.0)];
+        let trips = vec![
+            (P2::new(50.0, 50.0), P2::new(250.0, 50.0), 8, 3),
+            (P2::new(10.0, 10.0), P2::new(290.0, 90.0), 8, 2),
+            (P2::new(250.0, 50.0), P2::new(50.0, 50.0), 17, 4),
+            // ends outside of all zones
+            (P2::new(50.0, 50.0), P2::new(150.0, 50.0), 8, 7),
+        ];
+
+        let matrix = od_matrix(&zones, &trips);
+        assert_eq!(matrix.len(), 2);
+        assert_eq!(matrix[&(8, 0, 1)], 5);
+        assert_eq!(matrix[&(17, 1, 0)], 4);
+
+        let csv = production_attraction_csv(&matrix, zones.len(), 60);
+        assert!(csv.contains("08:00,1,5,0\n"));
+        assert!(csv.contains("08:00,2,0,5\n"));
+        assert!(csv.contains("17:00,1,0,4\n"));
+        assert!(od_csv(&matrix, 60).contains("17:00,2,1,4\n"));
+    }
+}
+
+mod kay_auto;
+pub use self::kay_auto::*;
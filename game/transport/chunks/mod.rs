@@ -0,0 +1,220 @@
+//! Partitions the map into square chunks, so traffic far away from the camera can be
+//! simulated at reduced fidelity while traffic on screen is simulated in full detail,
+//! which is what makes very large cities affordable.
+//!
+//! Each lane belongs to the chunk its midpoint is in. Whenever the camera moves so far
+//! that the set of chunks close to it changes, all lanes are told the fidelity of their
+//! chunk (see `Fidelity`). Far away chunks either throttle car following or use the
+//! mesoscopic queue model (see `microtraffic::mesoscopic`), depending on the settings.
+//! Cars on lanes that switch to or from the mesoscopic model are converted on the spot,
+//! so they vanish into queues when the camera leaves and reappear when it returns.
+//! Cars are handed over between lanes of different chunks like between any other lanes,
+//! the receiving lane making sure that a car coming from a lane at reduced fidelity
+//! doesn't end up overlapping the car ahead of it.
+//!
+//! Without a camera (like in headless comparison runs) everything is simulated in full,
+//! and lanes built after the last camera move start out in full fidelity as well.
+use kay::{ActorSystem, World, External};
+use compact::CVec;
+use descartes::{N, P2, V2, FiniteCurve};
+use monet::{RendererID, EyeListener, EyeListenerID, Eye, Movement, MSG_EyeListener_eye_moved};
+use super::lane::{Lane, LaneID};
+
+pub const CHUNK_SIZE: N = 500.0;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct ChunkCoord(pub i32, pub i32);
+
+impl ChunkCoord {
+    pub fn of(position: P2) -> ChunkCoord {
+        ChunkCoord(
+            (position.x / CHUNK_SIZE).floor() as i32,
+            (position.y / CHUNK_SIZE).floor() as i32,
+        )
+    }
+
+    /// The distance from `position` to the closest point of the chunk
+    pub fn distance_to(&self, position: P2) -> N {
+        let min_x = self.0 as N * CHUNK_SIZE;
+        let min_y = self.1 as N * CHUNK_SIZE;
+        let dx = (min_x - position.x).max(position.x - (min_x + CHUNK_SIZE)).max(0.0);
+        let dy = (min_y - position.y).max(position.y - (min_y + CHUNK_SIZE)).max(0.0);
+        (dx * dx + dy * dy).sqrt()
+    }
+}
+
+/// All chunks that are at least partly within `radius` of `center`
+pub fn chunks_within(center: P2, radius: N) -> Vec<ChunkCoord> {
+    let ChunkCoord(min_x, min_y) = ChunkCoord::of(center - V2::new(radius, radius));
+    let ChunkCoord(max_x, max_y) = ChunkCoord::of(center + V2::new(radius, radius));
+    (min_x..(max_x + 1))
+        .flat_map(|x| (min_y..(max_y + 1)).map(move |y| ChunkCoord(x, y)))
+        .filter(|chunk| chunk.distance_to(center) <= radius)
+        .collect()
+}
+
+/// How exactly the traffic on a lane is simulated
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Fidelity {
+    Full,
+    /// Car following and gridlock checks run this many times less often than in full
+    /// fidelity, cars are still moved every tick
+    Reduced(usize),
+    /// Cars aren't moved individually, but queue up, see `microtraffic::mesoscopic`
+    Mesoscopic,
+}
+
+impl Fidelity {
+    /// Ticks between two runs of the traffic logic, given the usual `throttling`
+    pub fn traffic_logic_throttling(&self, throttling: usize) -> usize {
+        match *self {
+            Fidelity::Full | Fidelity::Mesoscopic => throttling,
+            Fidelity::Reduced(factor) => throttling * factor.max(1),
+        }
+    }
+
+    /// Ticks between two runs of the traffic logic in whichever of both fidelities
+    /// runs it less often
+    pub fn slower_throttling(&self, other: Fidelity, throttling: usize) -> usize {
+        self.traffic_logic_throttling(throttling)
+            .max(other.traffic_logic_throttling(throttling))
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ChunkSettings {
+    pub enabled: bool,
+    /// Chunks closer than this (in meters) to where the camera looks are simulated in
+    /// full, the distance growing with the height of the camera
+    pub full_fidelity_distance: N,
+    /// How many times less often traffic logic runs in chunks at reduced fidelity
+    pub reduced_throttling: usize,
+    /// Whether chunks at reduced fidelity use the mesoscopic model instead
+    pub mesoscopic_far_away: bool,
+}
+
+impl Default for ChunkSettings {
+    fn default() -> Self {
+        ChunkSettings {
+            enabled: true,
+            full_fidelity_distance: 1000.0,
+            reduced_throttling: 4,
+            mesoscopic_far_away: false,
+        }
+    }
+}
+
+impl ChunkSettings {
+    fn reduced_fidelity(&self) -> Fidelity {
+        if self.mesoscopic_far_away {
+            Fidelity::Mesoscopic
+        } else {
+            Fidelity::Reduced(self.reduced_throttling)
+        }
+    }
+}
+
+#[derive(Compact, Clone)]
+pub struct SimulationChunks {
+    id: SimulationChunksID,
+    settings: External<ChunkSettings>,
+    /// Sorted like `chunks_within` returns them
+    full_chunks: CVec<ChunkCoord>,
+}
+
+impl SimulationChunks {
+    pub fn spawn(
+        id: SimulationChunksID,
+        renderer_id: RendererID,
+        world: &mut World,
+    ) -> SimulationChunks {
+        renderer_id.add_eye_listener(0, id.into(), world);
+
+        SimulationChunks {
+            id,
+            settings: External::new(::ENV.load_settings("Simulation Chunks")),
+            full_chunks: CVec::new(),
+        }
+    }
+}
+
+impl EyeListener for SimulationChunks {
+    fn eye_moved(&mut self, eye: Eye, _movement: Movement, world: &mut World) {
+        if !self.settings.enabled {
+            return;
+        }
+
+        let radius = self.settings.full_fidelity_distance + eye.position.z.max(0.0);
+        let full_chunks = chunks_within(P2::new(eye.target.x, eye.target.y), radius);
+
+        if !full_chunks.iter().eq(self.full_chunks.iter()) {
+            self.full_chunks = full_chunks.into();
+            LaneID::local_broadcast(world).on_chunk_fidelity(
+                self.full_chunks.clone(),
+                self.settings.reduced_fidelity(),
+                world,
+            );
+        }
+    }
+}
+
+impl Lane {
+    pub fn on_chunk_fidelity(
+        &mut self,
+        full_chunks: &CVec<ChunkCoord>,
+        reduced: Fidelity,
+        _: &mut World,
+    ) {
+        self.microtraffic.slowest_partner_fidelity = reduced;
+        let midpoint = self.construction.path.along(self.construction.length / 2.0);
+        let fidelity = if full_chunks.contains(&ChunkCoord::of(midpoint)) {
+            Fidelity::Full
+        } else {
+            reduced
+        };
+        if fidelity != self.microtraffic.fidelity {
+            self.set_traffic_fidelity(fidelity);
+        }
+    }
+}
+
+pub fn setup(system: &mut ActorSystem, renderer_id: RendererID) {
+    system.register::<SimulationChunks>();
+    auto_setup(system);
+
+    SimulationChunksID::spawn(renderer_id, &mut system.world());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks_within_radius_include_partly_covered_ones() {
+        let chunks = chunks_within(P2::new(250.0, 250.0), 300.0);
+        assert!(chunks.contains(&ChunkCoord(0, 0)));
+        assert!(chunks.contains(&ChunkCoord(1, 0)));
+        assert!(chunks.contains(&ChunkCoord(-1, 0)));
+        // the corners of the neighbouring diagonal chunks are 354m away
+        assert!(!chunks.contains(&ChunkCoord(1, 1)));
+        assert_eq!(chunks.len(), 5);
+    }
+
+    #[test]
+    fn reduced_fidelity_throttles_traffic_logic() {
+        assert_eq!(Fidelity::Full.traffic_logic_throttling(3), 3);
+        assert_eq!(Fidelity::Reduced(4).traffic_logic_throttling(3), 12);
+        assert_eq!(Fidelity::Reduced(0).traffic_logic_throttling(3), 3);
+        assert_eq!(Fidelity::Mesoscopic.traffic_logic_throttling(3), 3);
+    }
+
+    #[test]
+    fn slower_throttling_is_that_of_the_less_exact_fidelity() {
+        assert_eq!(Fidelity::Full.slower_throttling(Fidelity::Reduced(4), 3), 12);
+        assert_eq!(Fidelity::Reduced(4).slower_throttling(Fidelity::Full, 3), 12);
+        assert_eq!(Fidelity::Full.slower_throttling(Fidelity::Mesoscopic, 3), 3);
+    }
+}
+
+mod kay_auto;
+pub use self::kay_auto::*;
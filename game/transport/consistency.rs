@@ -0,0 +1,205 @@
+//! Walks all lanes of a world snapshot and checks invariants microtraffic and
+//! connectivity rely on, to catch corrupted state close to where it originates
+//! instead of seeing cars jump around or messages go to dead lanes much later.
+//!
+//! Used by the `check_consistency` remote control command and, with
+//! `CITYBOUND_CHECK_CONSISTENCY` set, at the end of a headless comparison run,
+//! which then exits with an error code if anything was violated.
+use std::collections::HashSet;
+use kay::{ID, Snapshot};
+use super::lane::{Lane, TransferLane};
+use super::lane::connectivity::InteractionKind;
+use super::microtraffic::LaneCar;
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Violation {
+    /// "Lane" or "TransferLane"
+    pub actor_type: &'static str,
+    pub instance_id: u32,
+    pub description: String,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ConsistencyReport {
+    pub lanes_checked: usize,
+    pub transfer_lanes_checked: usize,
+    pub violations: Vec<Violation>,
+}
+
+impl ConsistencyReport {
+    pub fn is_consistent(&self) -> bool {
+        self.violations.is_empty()
+    }
+
+    fn violation(&mut self, actor_type: &'static str, instance_id: u32, description: String) {
+        self.violations.push(Violation { actor_type, instance_id, description });
+    }
+
+    /// One line with the totals, followed by one line per violation
+    pub fn summary(&self) -> String {
+        let mut summary = format!(
+            "Checked {} lanes and {} transfer lanes: {} violations\n",
+            self.lanes_checked,
+            self.transfer_lanes_checked,
+            self.violations.len()
+        );
+        for violation in &self.violations {
+            summary.push_str(&format!(
+                "  {} {}: {}\n",
+                violation.actor_type,
+                violation.instance_id,
+                violation.description
+            ));
+        }
+        summary
+    }
+}
+
+fn car_problems(car: &LaneCar) -> Vec<String> {
+    let trip = car.trip._raw_id.instance_id;
+    let mut problems = Vec::new();
+    if !car.position.is_finite() {
+        problems.push(format!("car of trip {} has position {}", trip, *car.position));
+    }
+    if !car.velocity.is_finite() {
+        problems.push(format!("car of trip {} has velocity {}", trip, car.velocity));
+    }
+    if !car.acceleration.is_finite() {
+        problems.push(format!("car of trip {} has acceleration {}", trip, car.acceleration));
+    }
+    problems
+}
+
+fn check_lane(lane: &Lane, alive: &HashSet<ID>, report: &mut ConsistencyReport) {
+    let instance_id = lane.id._raw_id.instance_id;
+    let length = lane.construction.length;
+    let interactions = &lane.connectivity.interactions;
+
+    for (i, interaction) in interactions.iter().enumerate() {
+        let partner = interaction.partner_lane._raw_id;
+        if !alive.contains(&partner) {
+            report.violation(
+                "Lane",
+                instance_id,
+                format!("interaction {} refers to dead lane {}", i, partner.instance_id),
+            );
+        }
+        if let InteractionKind::Overlap { end, .. } = interaction.kind {
+            if interaction.start > end || end > length + 1.0 {
+                report.violation(
+                    "Lane",
+                    instance_id,
+                    format!(
+                        "interaction {} overlaps from {} to {} on a lane of length {}",
+                        i,
+                        interaction.start,
+                        end,
+                        length
+                    ),
+                );
+            }
+        }
+    }
+
+    for car in lane.microtraffic.cars.iter() {
+        for problem in car_problems(car) {
+            report.violation("Lane", instance_id, problem);
+        }
+        // on lanes without interactions, next hops are meaningless
+        if car.next_hop_interaction as usize >= interactions.len() && !interactions.is_empty() {
+            report.violation(
+                "Lane",
+                instance_id,
+                format!(
+                    "car of trip {} heads for interaction {}, but there are only {}",
+                    car.trip._raw_id.instance_id,
+                    car.next_hop_interaction,
+                    interactions.len()
+                ),
+            );
+        }
+    }
+
+    let cars = &lane.microtraffic.cars;
+    for (car, next_car) in cars.iter().zip(cars.iter().skip(1)) {
+        if car.position > next_car.position {
+            report.violation(
+                "Lane",
+                instance_id,
+                format!(
+                    "car of trip {} at {} is ahead of the next car (trip {}) at {}",
+                    car.trip._raw_id.instance_id,
+                    *car.position,
+                    next_car.trip._raw_id.instance_id,
+                    *next_car.position
+                ),
+            );
+        }
+    }
+}
+
+fn check_transfer_lane(
+    lane: &TransferLane,
+    alive: &HashSet<ID>,
+    report: &mut ConsistencyReport,
+) {
+    let instance_id = lane.id._raw_id.instance_id;
+
+    let sides = [("left", lane.connectivity.left), ("right", lane.connectivity.right)];
+    for &(side, maybe_partner) in &sides {
+        if let Some((partner, _)) = maybe_partner {
+            if !alive.contains(&partner._raw_id) {
+                report.violation(
+                    "TransferLane",
+                    instance_id,
+                    format!("{} lane {} is dead", side, partner._raw_id.instance_id),
+                );
+            }
+        }
+    }
+
+    for car in lane.microtraffic.cars.iter() {
+        for problem in car_problems(car) {
+            report.violation("TransferLane", instance_id, problem);
+        }
+        if !car.transfer_position.is_finite() || !car.transfer_velocity.is_finite() {
+            report.violation(
+                "TransferLane",
+                instance_id,
+                format!(
+                    "car of trip {} is at {} across the lanes, moving at {}",
+                    car.trip._raw_id.instance_id,
+                    car.transfer_position,
+                    car.transfer_velocity
+                ),
+            );
+        }
+    }
+}
+
+/// Checks all lanes and transfer lanes in `snapshot`
+pub fn check(snapshot: &Snapshot) -> ConsistencyReport {
+    let lanes = snapshot.instances::<Lane>();
+    let transfer_lanes = snapshot.instances::<TransferLane>();
+
+    let alive = lanes
+        .iter()
+        .map(|lane| lane.id._raw_id)
+        .chain(transfer_lanes.iter().map(|lane| lane.id._raw_id))
+        .collect::<HashSet<_>>();
+
+    let mut report = ConsistencyReport {
+        lanes_checked: lanes.len(),
+        transfer_lanes_checked: transfer_lanes.len(),
+        violations: Vec::new(),
+    };
+
+    for lane in lanes {
+        check_lane(lane, &alive, &mut report);
+    }
+    for lane in transfer_lanes {
+        check_transfer_lane(lane, &alive, &mut report);
+    }
+
+    report
+}
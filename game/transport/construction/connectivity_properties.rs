@@ -0,0 +1,305 @@
+//! Property tests for the connectivity that lanes establish after materialization.
+//! The `connect`, `connect_overlaps` and `disconnect` handlers are called directly on
+//! the lanes of random small road networks built through the planning API, once for
+//! each side, just like the messages between the lanes would call them.
+
+use kay::{ActorSystem, Networking, World, ID};
+use compact::CVec;
+use descartes::{P2, FiniteCurve, RoughlyComparable};
+use stagemaster::geometry::CPath;
+use proptest::prelude::*;
+use std::cell::RefCell;
+use super::{Unbuildable, MEMOIZED_BANDS_OUTLINES};
+use super::super::lane::{Lane, LaneID, TransferLane};
+use super::super::lane::connectivity::InteractionKind;
+use super::super::planning::plan::Plan;
+use super::super::planning::scenarios::two_way_road;
+
+thread_local! (
+    /// The replies that the handlers send are never delivered,
+    /// since the tests call the handlers of both sides themselves
+    static SYSTEM: RefCell<Option<Box<ActorSystem>>> = RefCell::new(None);
+);
+
+fn with_world<R, F: FnOnce(&mut World, ID) -> R>(f: F) -> R {
+    SYSTEM.with(|system_cell| {
+        let mut maybe_system = system_cell.borrow_mut();
+        if maybe_system.is_none() {
+            let mut system = Box::new(ActorSystem::new(
+                Box::new(|_, _: &mut World| {}),
+                Networking::new(0, Vec::new()),
+            ));
+            system.register::<Lane>();
+            system.register::<TransferLane>();
+            super::auto_setup(&mut system);
+            *maybe_system = Some(system);
+        }
+        let system = maybe_system.as_mut().expect("just created");
+        let lane_base_id = system.id::<Lane>();
+        f(&mut system.world(), lane_base_id)
+    })
+}
+
+struct Materialized {
+    lanes: Vec<Lane>,
+    /// The intersection that each lane is on, if any
+    intersections: Vec<Option<usize>>,
+    /// Where lanes enter each intersection that can also be left
+    incoming_positions: Vec<Vec<P2>>,
+}
+
+fn materialize(roads: &[(f32, f32, f32, f32)], lane_base_id: ID) -> Materialized {
+    // lane IDs are reused between test cases, so their bands have to be computed anew
+    MEMOIZED_BANDS_OUTLINES.with(|memoized| unsafe { (*memoized.get()).clear() });
+
+    let plan = Plan {
+        strokes: roads
+            .iter()
+            .flat_map(|&(x1, y1, x2, y2)| {
+                two_way_road(&[P2::new(x1, y1), P2::new(x2, y2)])
+            })
+            .collect(),
+    };
+    let result = plan.get_result();
+
+    let mut materialized = Materialized {
+        lanes: Vec::new(),
+        intersections: Vec::new(),
+        incoming_positions: Vec::new(),
+    };
+    let add_lane = |materialized: &mut Materialized, path: &CPath, intersection| {
+        let id = LaneID {
+            _raw_id: ID {
+                instance_id: materialized.lanes.len() as u32,
+                ..lane_base_id
+            },
+        };
+        materialized.lanes.push(Lane::new(id, path, intersection.is_some(), &CVec::new()));
+        materialized.intersections.push(intersection);
+    };
+
+    for stroke in result.trimmed_strokes.values() {
+        add_lane(&mut materialized, stroke.path(), None);
+    }
+    for (i, intersection) in result.intersections.values().enumerate() {
+        for stroke in intersection.strokes.iter() {
+            add_lane(&mut materialized, stroke.path(), Some(i));
+        }
+        let has_outgoing = !intersection.outgoing.is_empty();
+        materialized.incoming_positions.push(if has_outgoing {
+            intersection.incoming.values().map(|node| node.position).collect()
+        } else {
+            Vec::new()
+        });
+    }
+
+    materialized
+}
+
+/// Lets every lane connect to every other one, and lanes on the same intersection
+/// find their overlaps, like `MaterializedReality` does after building them
+fn connect_all(materialized: &mut Materialized, world: &mut World) {
+    for i in 0..materialized.lanes.len() {
+        for j in 0..materialized.lanes.len() {
+            if i == j {
+                continue;
+            }
+            let other_id = materialized.lanes[j].id;
+            let other_path = materialized.lanes[j].construction.path.clone();
+            let intersection = materialized.intersections[i];
+            let same_intersection =
+                intersection.is_some() && intersection == materialized.intersections[j];
+            let lane = &mut materialized.lanes[i];
+            lane.connect(
+                other_id,
+                other_path.start(),
+                other_path.end(),
+                other_path.length(),
+                false,
+                world,
+            );
+            if same_intersection {
+                lane.connect_overlaps(other_id, &other_path, None, false, world);
+            }
+        }
+    }
+}
+
+fn assert_next_and_previous_match(lanes: &[Lane]) {
+    for lane in lanes {
+        for interaction in lane.connectivity.interactions.iter() {
+            let partner = lanes.iter().find(|other| {
+                interaction.partner_lane == other.id.into()
+            });
+            let partner = match partner {
+                Some(partner) => partner,
+                None => panic!("Interaction with a lane that doesn't exist"),
+            };
+            match interaction.kind {
+                InteractionKind::Next { .. } => {
+                    assert!(interaction.start.is_roughly_within(lane.construction.length, 0.01));
+                    assert!(partner.connectivity.interactions.iter().any(|back| {
+                        back.partner_lane == lane.id.into() &&
+                            back.partner_start.is_roughly_within(
+                                lane.construction.length,
+                                0.01,
+                            ) &&
+                            match back.kind {
+                                InteractionKind::Previous => true,
+                                _ => false,
+                            }
+                    }));
+                }
+                InteractionKind::Previous => {
+                    assert!(partner.connectivity.interactions.iter().any(|back| {
+                        back.partner_lane == lane.id.into() &&
+                            match back.kind {
+                                InteractionKind::Next { .. } => true,
+                                _ => false,
+                            }
+                    }));
+                }
+                InteractionKind::Overlap { .. } => {}
+            }
+        }
+    }
+}
+
+fn n_next_and_previous(lanes: &[Lane]) -> usize {
+    lanes
+        .iter()
+        .map(|lane| {
+            lane.connectivity
+                .interactions
+                .iter()
+                .filter(|interaction| match interaction.kind {
+                    InteractionKind::Overlap { .. } => false,
+                    _ => true,
+                })
+                .count()
+        })
+        .sum::<usize>()
+}
+
+proptest! {
+    #[test]
+    fn every_next_has_a_matching_previous(roads in prop::collection::vec(road(), 1..4)) {
+        with_world(|world, lane_base_id| {
+            let mut materialized = materialize(&roads, lane_base_id);
+            connect_all(&mut materialized, world);
+            assert_next_and_previous_match(&materialized.lanes);
+        });
+    }
+
+    #[test]
+    fn connecting_again_adds_no_partners(roads in prop::collection::vec(road(), 1..4)) {
+        with_world(|world, lane_base_id| {
+            let mut materialized = materialize(&roads, lane_base_id);
+            connect_all(&mut materialized, world);
+            let n_connected = n_next_and_previous(&materialized.lanes);
+            for i in 0..materialized.lanes.len() {
+                for j in 0..materialized.lanes.len() {
+                    let other_id = materialized.lanes[j].id;
+                    let other_path = materialized.lanes[j].construction.path.clone();
+                    materialized.lanes[i].connect(
+                        other_id,
+                        other_path.start(),
+                        other_path.end(),
+                        other_path.length(),
+                        false,
+                        world,
+                    );
+                }
+            }
+            assert_eq!(n_next_and_previous(&materialized.lanes), n_connected);
+        });
+    }
+
+    #[test]
+    fn overlaps_are_symmetric(roads in prop::collection::vec(road(), 1..4)) {
+        with_world(|world, lane_base_id| {
+            let mut materialized = materialize(&roads, lane_base_id);
+            connect_all(&mut materialized, world);
+            let lanes = &materialized.lanes;
+            for lane in lanes {
+                for interaction in lane.connectivity.interactions.iter() {
+                    if let InteractionKind::Overlap { kind, .. } = interaction.kind {
+                        let partner = lanes
+                            .iter()
+                            .find(|other| interaction.partner_lane == other.id.into())
+                            .expect("Overlap with a lane that doesn't exist");
+                        assert!(partner.connectivity.interactions.iter().any(|back| {
+                            back.partner_lane == lane.id.into() &&
+                                match back.kind {
+                                    InteractionKind::Overlap { kind: back_kind, .. } => {
+                                        back_kind == kind
+                                    }
+                                    _ => false,
+                                }
+                        }));
+                    }
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn disconnecting_leaves_no_interactions_behind(
+        roads in prop::collection::vec(road(), 1..4),
+        removed in 0usize..1000,
+    ) {
+        with_world(|world, lane_base_id| {
+            let mut materialized = materialize(&roads, lane_base_id);
+            connect_all(&mut materialized, world);
+            let removed = materialized.lanes.remove(removed % materialized.lanes.len());
+
+            for lane in &mut materialized.lanes {
+                lane.disconnect(removed.id.into(), world);
+            }
+
+            for lane in &materialized.lanes {
+                assert!(lane.connectivity.interactions.iter().all(|interaction| {
+                    interaction.partner_lane != removed.id.into()
+                }));
+            }
+            assert_next_and_previous_match(&materialized.lanes);
+        });
+    }
+
+    #[test]
+    fn lanes_into_intersections_can_continue(roads in prop::collection::vec(road(), 1..4)) {
+        with_world(|world, lane_base_id| {
+            let mut materialized = materialize(&roads, lane_base_id);
+            connect_all(&mut materialized, world);
+            let incoming_positions = &materialized.incoming_positions;
+            for (lane, intersection) in
+                materialized.lanes.iter().zip(materialized.intersections.iter())
+            {
+                if intersection.is_some() {
+                    continue;
+                }
+                let ends_at_incoming = incoming_positions.iter().any(|positions| {
+                    positions.iter().any(|position| {
+                        position.is_roughly_within(lane.construction.path.end(), 0.1)
+                    })
+                });
+                if ends_at_incoming {
+                    assert!(lane.connectivity.interactions.iter().any(|interaction| {
+                        match interaction.kind {
+                            InteractionKind::Next { .. } => true,
+                            _ => false,
+                        }
+                    }));
+                }
+            }
+        });
+    }
+}
+
+fn road() -> BoxedStrategy<(f32, f32, f32, f32)> {
+    (-200.0f32..200.0, -200.0f32..200.0, -200.0f32..200.0, -200.0f32..200.0)
+        .prop_filter("road too short".to_owned(), |&(x1, y1, x2, y2)| {
+            (x2 - x1).hypot(y2 - y1) > 50.0
+        })
+        .boxed()
+}
@@ -0,0 +1,156 @@
+use kay::{ActorSystem, World};
+use compact::CVec;
+use descartes::{N, P2};
+use monet::DecalPattern;
+use stagemaster::geometry::path_to_decals;
+use core::city_events::{self, CityEventKind};
+use core::simulation::{Timestamp, TICKS_PER_SIM_MINUTE};
+use super::super::lane::{Lane, LaneID, TransferLane, TransferLaneID};
+use super::super::rendering::{set_lane_decals, CONSTRUCTION_DECAL_LAYER};
+
+const CONSTRUCTION_ZONE_COLOR: [f32; 3] = [1.0, 0.5, 0.0];
+/// How often a crew's truck stops on the open lanes next to its construction site
+const WORK_ZONE_CYCLE_TICKS: usize = 10 * TICKS_PER_SIM_MINUTE;
+/// How long the truck blocks the open lane each time, to unload
+const WORK_ZONE_BLOCKED_TICKS: usize = TICKS_PER_SIM_MINUTE;
+
+#[derive(Serialize, Deserialize, Copy, Clone)]
+pub struct ConstructionSettings {
+    /// how many meters of lane one crew builds per hour of simulation time
+    pub meters_per_hour: N,
+    /// how many lanes can be under construction at the same time,
+    /// more lanes wait for a free crew (0 means no limit)
+    pub max_simultaneous_projects: usize,
+    /// whether waiting short lanes get a crew before long ones
+    pub prioritize_short_projects: bool,
+}
+
+impl Default for ConstructionSettings {
+    fn default() -> Self {
+        ConstructionSettings {
+            meters_per_hour: 200.0,
+            max_simultaneous_projects: 12,
+            prioritize_short_projects: true,
+        }
+    }
+}
+
+/// Whether the crew working on the lane with `instance_id` starts (`Some(true)`) or stops
+/// (`Some(false)`) blocking the open lanes next to its site at `current_tick`. The sites
+/// of different lanes take turns, so not all of them block traffic at the same time
+pub fn work_zone_change(instance_id: u32, current_tick: Timestamp) -> Option<bool> {
+    match (current_tick.ticks() + instance_id as usize) % WORK_ZONE_CYCLE_TICKS {
+        0 => Some(true),
+        WORK_ZONE_BLOCKED_TICKS => Some(false),
+        _ => None,
+    }
+}
+
+/// Something that is built by one construction crew, like a lane or a transfer lane
+pub trait UnderConstruction {
+    /// Called once a crew is free for it, `build_rate` is in meters per second
+    fn start_construction(&mut self, build_rate: N, world: &mut World);
+}
+
+/// Assigns construction crews to newly built lanes,
+/// limited by how many projects can be worked on at the same time
+#[derive(Compact, Clone)]
+pub struct ConstructionCrews {
+    id: ConstructionCrewsID,
+    settings: ConstructionSettings,
+    waiting: CVec<(UnderConstructionID, N)>,
+    active: CVec<UnderConstructionID>,
+}
+
+impl ConstructionCrews {
+    pub fn spawn(id: ConstructionCrewsID, _: &mut World) -> ConstructionCrews {
+        ConstructionCrews {
+            id,
+            settings: ::ENV.load_settings("Construction"),
+            waiting: CVec::new(),
+            active: CVec::new(),
+        }
+    }
+
+    /// Projects without any `length` are finished right away and don't need a crew
+    pub fn request_crew(&mut self, project: UnderConstructionID, length: N, world: &mut World) {
+        if length > 0.0 {
+            self.waiting.push((project, length));
+            self.assign_crews(world);
+        }
+    }
+
+    /// Called when a project is finished (then with where it was `opened_at`),
+    /// or unbuilt before it was finished
+    pub fn project_done(
+        &mut self,
+        project: UnderConstructionID,
+        opened_at: Option<P2>,
+        world: &mut World,
+    ) {
+        self.active.retain(|active_project| *active_project != project);
+        self.waiting.retain(|&(waiting_project, _)| waiting_project != project);
+        self.assign_crews(world);
+
+        if let (Some(position), true) = (opened_at, self.active.is_empty()) {
+            city_events::publish(
+                CityEventKind::RoadsOpened,
+                "All road construction is finished",
+                Some(position),
+                world,
+            );
+        }
+    }
+
+    fn assign_crews(&mut self, world: &mut World) {
+        if self.settings.prioritize_short_projects {
+            self.waiting.sort_by(|&(_, length_a), &(_, length_b)| {
+                length_a.partial_cmp(&length_b).unwrap()
+            });
+        }
+
+        let build_rate = self.settings.meters_per_hour / 3600.0;
+
+        while !self.waiting.is_empty() &&
+            (self.settings.max_simultaneous_projects == 0 ||
+                 self.active.len() < self.settings.max_simultaneous_projects)
+        {
+            let (project, _) = self.waiting.remove(0);
+            project.start_construction(build_rate, world);
+            self.active.push(project);
+        }
+    }
+}
+
+impl UnderConstruction for Lane {
+    /// Marks the whole lane as a construction zone until it is finished
+    fn start_construction(&mut self, build_rate: N, world: &mut World) {
+        self.construction.build_rate = build_rate;
+        let zone = path_to_decals(
+            &self.construction.path,
+            4.0,
+            CONSTRUCTION_ZONE_COLOR,
+            DecalPattern::Hazard { period: 2.0 },
+        ).into_iter()
+            .map(|decal| decal.with_opacity(0.6))
+            .collect();
+        set_lane_decals(self, CONSTRUCTION_DECAL_LAYER, zone, world);
+    }
+}
+
+impl UnderConstruction for TransferLane {
+    fn start_construction(&mut self, build_rate: N, _: &mut World) {
+        self.construction.build_rate = build_rate;
+    }
+}
+
+pub fn setup(system: &mut ActorSystem) {
+    system.register::<ConstructionCrews>();
+    system.make_restorable::<ConstructionCrews>();
+    auto_setup(system);
+
+    ConstructionCrewsID::spawn(&mut system.world());
+}
+
+mod kay_auto;
+pub use self::kay_auto::*;
@@ -1,22 +1,149 @@
+//! Keeps the built lanes in line with the materialized plan. Applying a plan first
+//! unbuilds what it removes, then builds the new lanes a few per tick, so that large
+//! plans don't stall the simulation. Intersections are always built in one go, so there
+//! are no half-connected intersections while a plan is being built.
+//!
+//! Plans applied while another one is still being built are queued.
+//!
+//! A city that is loaded is streamed instead (so far only scenarios can be loaded, there
+//! is no save system yet): its roads are shown as a preview right away, then its lanes
+//! are built every frame with a progress bar, and the simulation only starts ticking
+//! once everything is built (see `is_loading`). Unlike the lanes of new plans, they are
+//! open right away instead of waiting for construction crews.
+use std::sync::atomic::{AtomicBool, ATOMIC_BOOL_INIT, Ordering};
 use compact::{CDict, CVec};
 use kay::{ActorSystem, World};
+use stagemaster::UserInterfaceID;
+use core::simulation::{SimulationID, Sleeper, SleeperID, MSG_Sleeper_wake, Timestamp, Ticks};
 use super::UnbuildableID;
-use super::super::lane::LaneID;
+use super::super::lane::{Lane, LaneID};
 use super::super::planning::plan::{Plan, PlanResult, PlanDelta, LaneStrokeRef, PlanResultDelta,
                                    IntersectionRef, TrimmedStrokeRef, TransferStrokeRef,
-                                   BuiltStrokes};
+                                   BuiltStrokes, Intersection};
+use super::super::planning::lane_stroke::LaneStroke;
 use super::super::planning::current_plan::CurrentPlanID;
 use super::super::microtraffic::LaneLikeID;
+use super::super::pathfinding::active_modes::ActiveModeGraphID;
+use super::super::demand_forecast::{DemandForecastID, ForecastLink};
+use super::super::utilities::UtilityNetworkID;
+use super::super::junctions::JunctionID;
+use super::super::junctions::protected::{ProtectedLayout, protected_layout,
+                                         compatible_with_crossings, with_bike_phase};
+
+/// About this many lanes are started per tick, intersections are never split up though
+const LANES_PER_TICK: usize = 30;
+/// While loading nothing is simulated yet, so many more lanes can be started per frame
+const LANES_PER_FRAME: usize = 300;
+const PROGRESS_BAR_WIDTH: usize = 30;
+
+static LOADING: AtomicBool = ATOMIC_BOOL_INIT;
+
+/// Whether a city is still being loaded, in which case the simulation shouldn't tick yet
+pub fn is_loading() -> bool {
+    LOADING.load(Ordering::SeqCst)
+}
+
+#[derive(Compact, Clone)]
+pub enum PendingBuild {
+    /// Whether it's built with the protected template, if it's signalized
+    Intersection(IntersectionRef, Intersection, bool),
+    TrimmedStroke(TrimmedStrokeRef, LaneStroke),
+    TransferStroke(TransferStrokeRef, LaneStroke),
+}
+
+impl PendingBuild {
+    fn n_lanes(&self) -> usize {
+        match *self {
+            PendingBuild::Intersection(_, ref intersection, _) => intersection.strokes.len(),
+            _ => 1,
+        }
+    }
+
+    /// Lanes that are `built_instantly` don't wait for construction crews
+    fn build(&self, report_to: MaterializedRealityID, built_instantly: bool, world: &mut World) {
+        match *self {
+            PendingBuild::Intersection(IntersectionRef(index), ref intersection, protected) => {
+                let paths = intersection.strokes.iter().map(LaneStroke::path).collect::<Vec<_>>();
+                let signalized = intersection.timings.iter().any(|timings| {
+                    timings.iter().any(|&green| !green)
+                });
+                let layout = if protected && signalized {
+                    protected_layout(intersection)
+                } else {
+                    ProtectedLayout::default()
+                };
+                let conflicts = layout.conflicts(&paths);
+                let timings = if layout.crossings.is_empty() {
+                    intersection.timings.to_vec()
+                } else {
+                    with_bike_phase(
+                        &intersection.timings,
+                        &compatible_with_crossings(&conflicts, paths.len()),
+                    )
+                };
+                let junction = JunctionID::spawn(conflicts.clone(), layout, world);
+                for (movement, (stroke, timings)) in
+                    intersection.strokes.iter().zip(timings).enumerate()
+                {
+                    stroke.build_intersection(
+                        report_to,
+                        BuildableRef::Intersection(index),
+                        timings,
+                        junction,
+                        movement,
+                        conflicts.row(movement),
+                        built_instantly,
+                        world,
+                    );
+                }
+            }
+            PendingBuild::TrimmedStroke(TrimmedStrokeRef(index), ref stroke) => {
+                stroke.build(
+                    report_to,
+                    BuildableRef::TrimmedStroke(index),
+                    built_instantly,
+                    world,
+                );
+            }
+            PendingBuild::TransferStroke(TransferStrokeRef(index), ref stroke) => {
+                stroke.build_transfer(
+                    report_to,
+                    BuildableRef::TransferStroke(index),
+                    built_instantly,
+                    world,
+                );
+            }
+        }
+    }
+}
+
+#[derive(Compact, Clone)]
+pub struct QueuedApply {
+    requester: CurrentPlanID,
+    delta: PlanDelta,
+}
 
 #[derive(Compact, Clone)]
 pub struct MaterializedReality {
     id: MaterializedRealityID,
+    simulation: SimulationID,
     current_plan: Plan,
     current_result: PlanResult,
     built_intersection_lanes: CDict<IntersectionRef, CVec<LaneID>>,
     built_trimmed_lanes: CDict<TrimmedStrokeRef, LaneLikeID>,
     built_transfer_lanes: CDict<TransferStrokeRef, LaneLikeID>,
     state: MaterializedRealityState,
+    /// Whose plan is being built right now
+    building_for: Option<CurrentPlanID>,
+    /// Stored last-first, so the next one can be popped
+    pending_builds: CVec<PendingBuild>,
+    n_lanes_to_build: usize,
+    n_lanes_built: usize,
+    queued_applies: CVec<QueuedApply>,
+    /// Whether the plans being built are a city being loaded, built every frame
+    streaming: bool,
+    /// Whether intersections of the plan being built get the protected template
+    protected_intersections: bool,
 }
 
 #[derive(Compact, Clone)]
@@ -28,15 +155,27 @@ pub enum MaterializedRealityState {
 use self::MaterializedRealityState::{Ready, WaitingForUnbuild};
 
 impl MaterializedReality {
-    pub fn spawn(id: MaterializedRealityID, _: &mut World) -> MaterializedReality {
+    pub fn spawn(
+        id: MaterializedRealityID,
+        simulation: SimulationID,
+        _: &mut World,
+    ) -> MaterializedReality {
         MaterializedReality {
             id,
+            simulation,
             current_plan: Plan::default(),
             current_result: PlanResult::default(),
             built_intersection_lanes: CDict::new(),
             built_trimmed_lanes: CDict::new(),
             built_transfer_lanes: CDict::new(),
             state: MaterializedRealityState::Ready(()),
+            building_for: None,
+            pending_builds: CVec::new(),
+            n_lanes_to_build: 0,
+            n_lanes_built: 0,
+            queued_applies: CVec::new(),
+            streaming: false,
+            protected_intersections: false,
         }
     }
 
@@ -47,10 +186,70 @@ impl MaterializedReality {
         requester.on_simulation_result(result_delta, world);
     }
 
+    /// Sends all lanes the network would have with `delta` applied to `requester`,
+    /// without building anything
+    pub fn forecast_network(
+        &mut self,
+        requester: DemandForecastID,
+        delta: &PlanDelta,
+        world: &mut World,
+    ) {
+        let (new_plan, _) = self.current_plan.with_delta(delta);
+        let result = new_plan.get_result();
+        let result_delta = result.delta(&self.current_result);
+
+        let trimmed_links = result.trimmed_strokes.pairs().map(|(stroke_ref, stroke)| {
+            ForecastLink {
+                path: stroke.path().clone(),
+                planned: result_delta.trimmed_strokes.to_create.contains_key(*stroke_ref),
+            }
+        });
+        let intersection_links = result.intersections.pairs().flat_map(
+            |(intersection_ref, intersection)| {
+                let planned = result_delta.intersections.to_create.contains_key(
+                    *intersection_ref,
+                );
+                intersection.strokes.iter().map(move |stroke| {
+                    ForecastLink { path: stroke.path().clone(), planned }
+                })
+            },
+        );
+
+        requester.on_forecast_network(trimmed_links.chain(intersection_links).collect(), world);
+    }
+
+    fn is_busy(&self) -> bool {
+        match self.state {
+            WaitingForUnbuild(..) => true,
+            Ready(()) => self.building_for.is_some(),
+        }
+    }
+
     pub fn apply(&mut self, requester: CurrentPlanID, delta: &PlanDelta, world: &mut World) {
+        if self.is_busy() {
+            self.queued_applies.push(QueuedApply { requester, delta: delta.clone() });
+            return;
+        }
+
         self.state = match self.state {
-            WaitingForUnbuild(..) => panic!("Already applying a plan"),
+            WaitingForUnbuild(..) => unreachable!(),
             Ready(()) => {
+                // roads with bike lanes get protected intersections
+                self.protected_intersections = delta.bike_lanes;
+                if !delta.new_paths.is_empty() {
+                    // paths don't become lanes, only the walking and cycling graph uses them
+                    ActiveModeGraphID::local_first(world).build_paths(
+                        delta.new_paths.clone(),
+                        world,
+                    );
+                }
+                if !delta.new_conduits.is_empty() {
+                    // conduits are underground, they leave the lanes as they are
+                    UtilityNetworkID::local_first(world).build_conduits(
+                        delta.new_conduits.clone(),
+                        world,
+                    );
+                }
                 let (new_plan, _) = self.current_plan.with_delta(delta);
                     let new_result = new_plan.get_result();
                     let result_delta = new_result.delta(&self.current_result);
@@ -78,11 +277,7 @@ impl MaterializedReality {
                     }
 
                     for &id in &ids_to_unbuild {
-                        // TODO: ugly: untyped ID shenanigans
-                        let id_as_unbuildable: UnbuildableID = UnbuildableID {
-                            _raw_id: id._raw_id
-                        };
-                        id_as_unbuildable.unbuild(self.id, world);
+                        UnbuildableID::from(id).unbuild(self.id, world);
                     }
 
                     self.id.on_lane_unbuilt(None, world);
@@ -97,6 +292,34 @@ impl MaterializedReality {
         }
     }
 
+    /// Like `apply`, but loads `delta` as a city: it's built as fast as possible, while
+    /// the simulation waits. Plans queued before it are loaded as part of the city
+    pub fn stream(&mut self, requester: CurrentPlanID, delta: &PlanDelta, world: &mut World) {
+        println!("Loading city with {} lane strokes", delta.new_strokes.len());
+        self.streaming = true;
+        LOADING.store(true, Ordering::SeqCst);
+        self.apply(requester, delta, world);
+    }
+
+    /// Starts building the next lanes of a city being loaded, to be called every frame
+    pub fn build_streamed(&mut self, world: &mut World) {
+        if !self.streaming {
+            return;
+        }
+
+        self.start_builds(LANES_PER_FRAME, world);
+
+        UserInterfaceID::local_first(world).add_debug_text(
+            "Loading City".chars().collect(),
+            progress_bar(self.n_lanes_built, self.n_lanes_to_build)
+                .chars()
+                .collect(),
+            [0.0, 0.0, 0.0, 1.0],
+            false,
+            world,
+        );
+    }
+
     pub fn on_lane_built(
         &mut self,
         id: LaneLikeID,
@@ -107,8 +330,7 @@ impl MaterializedReality {
             Ready(()) => {
                     match buildable_ref {
                         BuildableRef::Intersection(index) => {
-                            // TODO: ugly: raw ID shenanigans
-                            let id_as_lane: LaneID = LaneID{ _raw_id: id._raw_id};
+                            let id_as_lane = LaneID::from(id);
                             if let Some(other_intersection_lanes) =
                                 self.built_intersection_lanes.get(IntersectionRef(index))
                             {
@@ -135,6 +357,11 @@ impl MaterializedReality {
                             );
                         }
                     }
+
+                    self.n_lanes_built += 1;
+                    if self.n_lanes_built == self.n_lanes_to_build {
+                        self.finish_building(world);
+                    }
                 }
             WaitingForUnbuild(..) => {
                 panic!(
@@ -160,40 +387,30 @@ impl MaterializedReality {
                     ids_to_unbuild.remove(pos);
                 }
                 if ids_to_unbuild.is_empty() {
-                    for (&IntersectionRef(new_index), new_intersection) in
-                        result_delta.intersections.to_create.pairs()
-                    {
-                        for (stroke, timings) in
-                            new_intersection.strokes.iter().zip(
-                                new_intersection
-                                    .timings
-                                    .iter(),
+                    let mut pending_builds = result_delta
+                        .intersections
+                        .to_create
+                        .pairs()
+                        .map(|(&new_ref, intersection)| {
+                            PendingBuild::Intersection(
+                                new_ref,
+                                intersection.clone(),
+                                self.protected_intersections,
                             )
-                        {
-                            stroke.build_intersection(
-                                self.id,
-                                BuildableRef::Intersection(new_index),
-                                timings.clone(),
-                                world,
-                            );
-                        }
-                    }
-
-                    for (&TrimmedStrokeRef(new_index), new_stroke) in
-                        result_delta.trimmed_strokes.to_create.pairs()
-                    {
-                        new_stroke.build(self.id, BuildableRef::TrimmedStroke(new_index), world);
-                    }
-
-                    for (&TransferStrokeRef(new_index), new_stroke) in
-                        result_delta.transfer_strokes.to_create.pairs()
-                    {
-                        new_stroke.build_transfer(
-                            self.id,
-                            BuildableRef::TransferStroke(new_index),
-                            world,
-                        );
-                    }
+                        })
+                        .chain(result_delta.trimmed_strokes.to_create.pairs().map(
+                            |(&new_ref, stroke)| {
+                                PendingBuild::TrimmedStroke(new_ref, stroke.clone())
+                            },
+                        ))
+                        .chain(result_delta.transfer_strokes.to_create.pairs().map(
+                            |(&new_ref, stroke)| {
+                                PendingBuild::TransferStroke(new_ref, stroke.clone())
+                            },
+                        ))
+                        .collect::<Vec<_>>();
+                    pending_builds.reverse();
+                    let n_lanes_to_build = pending_builds.iter().map(PendingBuild::n_lanes).sum();
 
                     let new_built_intersection_lanes = self.built_intersection_lanes
                         .pairs()
@@ -246,15 +463,26 @@ impl MaterializedReality {
                     };
 
                     requester.built_strokes_changed(built_strokes, world);
+                    if self.streaming {
+                        requester.on_loading_started(result_delta.clone(), world);
+                    }
 
                     Some(MaterializedReality {
                         id: self.id,
+                        simulation: self.simulation,
                         current_plan: new_plan.clone(),
                         current_result: new_result.clone(),
                         built_intersection_lanes: new_built_intersection_lanes,
                         built_trimmed_lanes: new_built_trimmed_lanes,
                         built_transfer_lanes: new_built_transfer_lanes,
                         state: MaterializedRealityState::Ready(()),
+                        building_for: Some(requester),
+                        pending_builds: pending_builds.into(),
+                        n_lanes_to_build,
+                        n_lanes_built: 0,
+                        queued_applies: self.queued_applies.clone(),
+                        streaming: self.streaming,
+                        protected_intersections: self.protected_intersections,
                     })
                 } else {
                     None
@@ -264,6 +492,80 @@ impl MaterializedReality {
         };
         if let Some(new_self) = maybe_new_self {
             *self = new_self;
+            if self.n_lanes_to_build == 0 {
+                self.finish_building(world);
+            } else if !self.streaming {
+                self.simulation.wake_up_in(Ticks(1), self.id.into(), world);
+            }
+        }
+    }
+
+    fn finish_building(&mut self, world: &mut World) {
+        let maybe_requester = self.building_for.take();
+        if let Some(requester) = maybe_requester {
+            requester.on_materialization_progress(
+                self.n_lanes_built,
+                self.n_lanes_to_build,
+                world,
+            );
+        }
+        if !self.queued_applies.is_empty() {
+            let next = self.queued_applies.remove(0);
+            self.apply(next.requester, &next.delta, world);
+        } else if self.streaming {
+            self.streaming = false;
+            LOADING.store(false, Ordering::SeqCst);
+            println!("Finished loading city");
+            if let Some(requester) = maybe_requester {
+                requester.on_loading_finished(world);
+            }
+        }
+    }
+
+    fn start_builds(&mut self, max_lanes: usize, world: &mut World) {
+        let mut n_lanes_started = 0;
+        while n_lanes_started < max_lanes {
+            if let Some(pending) = self.pending_builds.pop() {
+                // a city being loaded is already built, only new plans need crews
+                pending.build(self.id, self.streaming, world);
+                n_lanes_started += pending.n_lanes();
+            } else {
+                break;
+            }
+        }
+    }
+}
+
+fn progress_bar(n_done: usize, n_total: usize) -> String {
+    let fraction = if n_total == 0 {
+        1.0
+    } else {
+        n_done as f32 / n_total as f32
+    };
+    let n_filled = (fraction * PROGRESS_BAR_WIDTH as f32).round() as usize;
+    format!(
+        "[{}{}] {} of {} lanes",
+        "#".repeat(n_filled),
+        "-".repeat(PROGRESS_BAR_WIDTH - n_filled),
+        n_done,
+        n_total
+    )
+}
+
+impl Sleeper for MaterializedReality {
+    fn wake(&mut self, _current_tick: Timestamp, world: &mut World) {
+        self.start_builds(LANES_PER_TICK, world);
+
+        if let Some(requester) = self.building_for {
+            requester.on_materialization_progress(
+                self.n_lanes_built,
+                self.n_lanes_to_build,
+                world,
+            );
+        }
+
+        if !self.pending_builds.is_empty() {
+            self.simulation.wake_up_in(Ticks(1), self.id.into(), world);
         }
     }
 }
@@ -275,15 +577,16 @@ pub enum BuildableRef {
     TransferStroke(usize),
 }
 
-pub fn setup(system: &mut ActorSystem) -> MaterializedRealityID {
+pub fn setup(system: &mut ActorSystem, simulation: SimulationID) -> MaterializedRealityID {
     system.register::<MaterializedReality>();
+    system.make_restorable::<MaterializedReality>();
 
     auto_setup(system);
 
     if system.networking_machine_id() > 0 {
         MaterializedRealityID::global_first(&mut system.world())
     } else {
-        MaterializedRealityID::spawn(&mut system.world())
+        MaterializedRealityID::spawn(simulation, &mut system.world())
     }
 }
 
@@ -9,17 +9,32 @@ use ordered_float::OrderedFloat;
 use super::lane::{Lane, LaneID, TransferLane, TransferLaneID};
 use super::lane::connectivity::{Interaction, InteractionKind, OverlapKind};
 use super::microtraffic::LaneLikeID;
+use super::pathfinding::shifted_interaction_idx;
+use super::pathfinding::trip::CancelReason;
+use super::spatial_index::SpatialIndexID;
+use super::street_furniture::StreetFurnitureID;
+use super::rendering::markings::LANE_WIDTH;
 
 pub mod materialized_reality;
+pub mod crews;
+#[cfg(test)]
+mod connectivity_properties;
 use self::materialized_reality::{MaterializedRealityID, BuildableRef};
+use self::crews::ConstructionCrewsID;
+use core::simulation::SimulationID;
+use terrain::TerrainID;
 
-const CONNECTION_TOLERANCE: f32 = 0.1;
+pub const CONNECTION_TOLERANCE: f32 = 0.1;
+pub const OVERLAP_BAND_WIDTH: f32 = 4.5;
 
 #[derive(Compact, Clone)]
 pub struct ConstructionInfo {
     pub length: f32,
     pub path: CPath,
+    /// meters built so far
     pub progress: f32,
+    /// meters built per second, zero while waiting for a construction crew
+    pub build_rate: f32,
     unbuilding_for: Option<MaterializedRealityID>,
     disconnects_remaining: u8,
 }
@@ -30,10 +45,21 @@ impl ConstructionInfo {
             length: path.length(),
             path: path,
             progress: 0.0,
+            build_rate: 0.0,
             unbuilding_for: None,
             disconnects_remaining: 0,
         }
     }
+
+    pub fn is_finished(&self) -> bool {
+        self.progress >= self.length
+    }
+
+    /// Unbuilding lanes don't take new cars and only wait
+    /// for their partners to confirm they dropped them
+    pub fn is_unbuilding(&self) -> bool {
+        self.unbuilding_for.is_some()
+    }
 }
 
 pub trait Unbuildable {
@@ -42,6 +68,67 @@ pub trait Unbuildable {
     fn on_confirm_disconnect(&mut self, world: &mut World) -> Fate;
 }
 
+/// Whether a lane with `own_path` leads into (`Next`) and/or
+/// comes from (`Previous`) a lane with the given endpoints
+pub fn endpoint_connection(own_path: &CPath, other_start: P2, other_end: P2) -> (bool, bool) {
+    (
+        other_start.is_roughly_within(own_path.end(), CONNECTION_TOLERANCE),
+        other_end.is_roughly_within(own_path.start(), CONNECTION_TOLERANCE),
+    )
+}
+
+/// Finds the overlap of two lanes, given as (band, band outline, path) each.
+/// Returns (start, end, partner start, partner end, kind) as seen from the first lane
+pub fn find_overlap(
+    (lane_band, lane_outline, lane_path): (&Band<CPath>, &CPath, &CPath),
+    (other_band, other_outline, other_path): (&Band<CPath>, &CPath, &CPath),
+) -> Option<(N, N, N, N, OverlapKind)> {
+    let intersections = (lane_outline, other_outline).intersect();
+    if intersections.len() < 2 {
+        return None;
+    }
+
+    if let ::itertools::MinMaxResult::MinMax((entry_intersection, entry_distance),
+                                             (exit_intersection, exit_distance)) =
+        intersections
+            .iter()
+            .map(|intersection| {
+                (
+                    intersection,
+                    lane_band.outline_distance_to_path_distance(intersection.along_a),
+                )
+            })
+            .minmax_by_key(|&(_, distance)| OrderedFloat(distance))
+    {
+        let other_entry_distance =
+            other_band.outline_distance_to_path_distance(entry_intersection.along_b);
+        let other_exit_distance =
+            other_band.outline_distance_to_path_distance(exit_intersection.along_b);
+
+        let overlap_kind = if other_path
+            .direction_along(other_entry_distance)
+            .is_roughly_within(lane_path.direction_along(entry_distance), 0.1) ||
+            other_path
+                .direction_along(other_exit_distance)
+                .is_roughly_within(lane_path.direction_along(exit_distance), 0.1)
+        {
+            OverlapKind::Parallel
+        } else {
+            OverlapKind::Conflicting
+        };
+
+        Some((
+            entry_distance,
+            exit_distance,
+            other_entry_distance.min(other_exit_distance),
+            other_exit_distance.max(other_entry_distance),
+            overlap_kind,
+        ))
+    } else {
+        panic!("both entry and exit should exist")
+    }
+}
+
 use fnv::FnvHashMap;
 use std::cell::UnsafeCell;
 thread_local! (
@@ -71,8 +158,15 @@ impl Lane {
     }
 
     pub fn start_connecting_overlaps(&mut self, lanes: &CVec<LaneID>, world: &mut World) {
+        let movement = self.connectivity.junction.map(|(_, movement)| movement);
         for &lane_id in lanes.iter() {
-            lane_id.connect_overlaps(self.id, self.construction.path.clone(), true, world);
+            lane_id.connect_overlaps(
+                self.id,
+                self.construction.path.clone(),
+                movement,
+                true,
+                world,
+            );
         }
     }
 
@@ -89,11 +183,10 @@ impl Lane {
             return;
         };
 
-        let mut connected = false;
-
-        if other_start.is_roughly_within(self.construction.path.end(), CONNECTION_TOLERANCE) {
-            connected = true;
+        let (is_next, is_previous) =
+            endpoint_connection(&self.construction.path, other_start, other_end);
 
+        if is_next {
             let already_a_partner = self.connectivity.interactions.iter().any(|interaction| {
                 match *interaction {
                     Interaction {
@@ -116,9 +209,7 @@ impl Lane {
             super::pathfinding::on_connect(self);
         }
 
-        if other_end.is_roughly_within(self.construction.path.start(), CONNECTION_TOLERANCE) {
-            connected = true;
-
+        if is_previous {
             let already_a_partner = self.connectivity.interactions.iter().any(|interaction| {
                 match *interaction {
                     Interaction {
@@ -141,7 +232,7 @@ impl Lane {
             super::pathfinding::on_connect(self);
         }
 
-        if reply_needed && connected {
+        if reply_needed && (is_next || is_previous) {
             let path = &self.construction.path;
             other_id.connect(
                 self.id,
@@ -158,15 +249,21 @@ impl Lane {
         &mut self,
         other_id: LaneID,
         other_path: &CPath,
+        other_movement: Option<usize>,
         reply_needed: bool,
         world: &mut World,
     ) {
+        // on junctions, how the movements relate decides how they overlap
+        let maybe_relation = other_movement.and_then(|movement| {
+            self.connectivity.movement_relations.get(movement).cloned()
+        });
+
         MEMOIZED_BANDS_OUTLINES.with(|memoized_bands_outlines_cell| {
             let memoized_bands_outlines = unsafe { &mut *memoized_bands_outlines_cell.get() };
             let &(ref lane_band, ref lane_outline) = memoized_bands_outlines
                 .entry(self.id.into())
                 .or_insert_with(|| {
-                    let band = Band::new(self.construction.path.clone(), 4.5);
+                    let band = Band::new(self.construction.path.clone(), OVERLAP_BAND_WIDTH);
                     let outline = band.outline();
                     (band, outline)
                 }) as &(Band<CPath>, CPath);
@@ -175,80 +272,39 @@ impl Lane {
             let &(ref other_band, ref other_outline) = memoized_bands_outlines
                 .entry(other_id.into())
                 .or_insert_with(|| {
-                    let band = Band::new(other_path.clone(), 4.5);
+                    let band = Band::new(other_path.clone(), OVERLAP_BAND_WIDTH);
                     let outline = band.outline();
                     (band, outline)
                 }) as &(Band<CPath>, CPath);
 
-            let intersections = (lane_outline, other_outline).intersect();
-            if intersections.len() >= 2 {
-                if let ::itertools::MinMaxResult::MinMax((entry_intersection, entry_distance),
-                                                         (exit_intersection, exit_distance)) =
-                    intersections
-                        .iter()
-                        .map(|intersection| {
-                            (
-                                intersection,
-                                lane_band.outline_distance_to_path_distance(intersection.along_a),
-                            )
-                        })
-                        .minmax_by_key(|&(_, distance)| OrderedFloat(distance))
-                {
-                    let other_entry_distance =
-                        other_band.outline_distance_to_path_distance(entry_intersection.along_b);
-                    let other_exit_distance =
-                        other_band.outline_distance_to_path_distance(exit_intersection.along_b);
-
-                    let overlap_kind = if other_path
-                        .direction_along(other_entry_distance)
-                        .is_roughly_within(
-                            self.construction.path.direction_along(entry_distance),
-                            0.1,
-                        ) ||
-                        other_path
-                            .direction_along(other_exit_distance)
-                            .is_roughly_within(
-                                self.construction.path.direction_along(exit_distance),
-                                0.1,
-                            )
-                    {
-                        // ::stagemaster::geometry::CPath::add_debug_path(
-                        //     self.construction.path
-                        //         .subsection(entry_distance, exit_distance).unwrap(),
-                        //     [1.0, 0.5, 0.0],
-                        //     0.3
-                        // );
-                        OverlapKind::Parallel
-                    } else {
-                        // ::stagemaster::geometry::CPath::add_debug_path(
-                        //     self.construction.path
-                        //         .subsection(entry_distance, exit_distance).unwrap(),
-                        //     [1.0, 0.0, 0.0],
-                        //     0.3
-                        // );
-                        OverlapKind::Conflicting
-                    };
-
+            if let Some((start, end, partner_start, partner_end, geometric_kind)) = find_overlap(
+                (lane_band, lane_outline, &self.construction.path),
+                (other_band, other_outline, other_path),
+            )
+            {
+                let maybe_overlap_kind = match maybe_relation {
+                    Some(relation) => relation.overlap_kind(),
+                    None => Some(geometric_kind),
+                };
+                if let Some(overlap_kind) = maybe_overlap_kind {
                     self.connectivity.interactions.push(Interaction {
                         partner_lane: other_id.into(),
-                        start: entry_distance,
-                        partner_start: other_entry_distance.min(other_exit_distance),
+                        start,
+                        partner_start,
                         kind: InteractionKind::Overlap {
-                            end: exit_distance,
-                            partner_end: other_exit_distance.max(other_entry_distance),
+                            end,
+                            partner_end,
                             kind: overlap_kind,
                         },
                     });
-                } else {
-                    panic!("both entry and exit should exist")
                 }
             }
 
-
             if reply_needed {
                 other_id.connect_overlaps(
                     self.id.into(),
                     self.construction.path.clone(),
+                    self.connectivity.junction.map(|(_, movement)| movement),
                     false,
                     world,
                 );
@@ -273,35 +329,78 @@ impl Lane {
 
 impl Unbuildable for Lane {
     fn disconnect(&mut self, other_id: UnbuildableID, world: &mut World) {
+        let other_as_lanelike: LaneLikeID = other_id.into();
         let interaction_indices_to_remove = self.connectivity
             .interactions
             .iter()
             .enumerate()
-            // TODO: ugly: untyped ID shenanigans
-            .filter_map(|(i, interaction)| if interaction.partner_lane._raw_id == other_id._raw_id {
+            .filter_map(|(i, interaction)| if interaction.partner_lane == other_as_lanelike {
                 Some(i)
             } else {
                 None
             })
             .collect::<Vec<_>>();
-        // TODO: Cancel trip
-        self.microtraffic.cars.retain(|car| {
-            !interaction_indices_to_remove.contains(&(car.next_hop_interaction as usize))
-        });
-        self.microtraffic.obstacles.retain(|&(_obstacle, from_id)| {
-            // TODO: ugly: untyped ID shenanigans
-            from_id._raw_id != other_id._raw_id
-        });
-        for idx in interaction_indices_to_remove.into_iter().rev() {
+        self.microtraffic.forget_obstacles_from(other_as_lanelike);
+        for &idx in interaction_indices_to_remove.iter().rev() {
             self.connectivity.interactions.remove(idx);
         }
-        // TODO: untyped ID shenanigans
-        let other_as_lanelike = LaneLikeID { _raw_id: other_id._raw_id };
-        super::pathfinding::on_disconnect(self, other_as_lanelike);
+        super::pathfinding::on_disconnect(self, other_as_lanelike, &interaction_indices_to_remove);
+
+        // cars that were about to use the disconnected lane need a new way,
+        // the rest only need their next hop index shifted
+        let mut cars_to_cancel = Vec::new();
+        let queued_cars = self.microtraffic.mesoscopic.queue.iter_mut().map(
+            |queued| &mut queued.car,
+        );
+        for car in self.microtraffic.cars.iter_mut().chain(queued_cars) {
+            let old_idx = car.next_hop_interaction as usize;
+            if interaction_indices_to_remove.contains(&old_idx) {
+                match self.pathfinding.route_for(car.destination, car.vehicle) {
+                    Some(routing_info) => car.next_hop_interaction = routing_info.outgoing_idx,
+                    None => cars_to_cancel.push(car.trip),
+                }
+            } else {
+                car.next_hop_interaction =
+                    shifted_interaction_idx(old_idx, &interaction_indices_to_remove) as u8;
+            }
+        }
+        self.microtraffic.cars.retain(
+            |car| !cars_to_cancel.contains(&car.trip),
+        );
+        self.microtraffic.mesoscopic.queue.retain(|queued| {
+            !cars_to_cancel.contains(&queued.car.trip)
+        });
+        for trip in cars_to_cancel {
+            trip.cancel(CancelReason::NoRouteLeft, world);
+        }
+
         other_id.on_confirm_disconnect(world);
     }
 
     fn unbuild(&mut self, report_to: MaterializedRealityID, world: &mut World) -> Fate {
+        self.construction.unbuilding_for = Some(report_to);
+        for car in self.microtraffic.cars.drain() {
+            car.trip.cancel(CancelReason::LaneRemoved, world);
+        }
+        for queued in self.microtraffic.mesoscopic.queue.drain() {
+            queued.car.trip.cancel(CancelReason::LaneRemoved, world);
+        }
+        if let Some((junction, _)) = self.connectivity.junction {
+            junction.remove_movement_lane(self.id, world);
+        }
+        BuildingID::global_broadcast(world).on_lane_unbuilt(self.id, world);
+        SpatialIndexID::local_first(world).remove_lane(self.id, world);
+        StreetFurnitureID::local_first(world).remove_lane(self.id, world);
+        if self.construction.is_finished() {
+            // only opened lanes leveled the terrain
+            TerrainID::local_first(world).level_along(
+                self.construction.path.clone(),
+                LANE_WIDTH,
+                false,
+                world,
+            );
+        }
+
         let mut disconnects_remaining = 0;
         for id in self.connectivity
             .interactions
@@ -309,12 +408,13 @@ impl Unbuildable for Lane {
             .map(|interaction| interaction.partner_lane)
             .unique()
         {
-            // TODO: untyped ID shenanigans
-            let id_as_unbuildable = UnbuildableID { _raw_id: id._raw_id };
-            id_as_unbuildable.disconnect(self.id.into(), world);
+            UnbuildableID::from(id).disconnect(self.id.into(), world);
             disconnects_remaining += 1;
         }
         super::rendering::on_unbuild(self, world);
+        if !self.construction.is_finished() {
+            ConstructionCrewsID::local_first(world).project_done(self.id.into(), None, world);
+        }
         MEMOIZED_BANDS_OUTLINES.with(|memoized_bands_outlines_cell| {
             let memoized_bands_outlines = unsafe { &mut *memoized_bands_outlines_cell.get() };
             memoized_bands_outlines.remove(&self.id.into())
@@ -324,7 +424,6 @@ impl Unbuildable for Lane {
             Fate::Die
         } else {
             self.construction.disconnects_remaining = disconnects_remaining;
-            self.construction.unbuilding_for = Some(report_to);
             Fate::Live
         }
     }
@@ -343,20 +442,21 @@ impl Unbuildable for Lane {
     }
 }
 
-use economy::buildings::{Lot, BuildingSpawnerID};
-use rand::Rng;
+use economy::buildings::{Lot, BuildingID, BuildingSpawnerID};
 
 impl Lane {
     // TODO: this is a horrible hack
     pub fn find_lot(&mut self, requester: BuildingSpawnerID, world: &mut World) {
         const BUILDING_DISTANCE: f32 = 15.0;
+        const ROAD_SIDE_DISTANCE: f32 = 5.0;
+        const MIN_FRONTAGE: f32 = 14.0;
 
         if !self.connectivity.on_intersection {
             let path = &self.construction.path;
-            let distance = ::rand::thread_rng().next_f32() * path.length();
+            let distance = ::core::random::next_f32() * path.length();
+            let setback = (1.0 + ::core::random::next_f32() * 1.0) * BUILDING_DISTANCE;
             let position = path.along(distance) +
-                (1.0 + ::rand::thread_rng().next_f32() * 1.0) * BUILDING_DISTANCE *
-                    path.direction_along(distance).orthogonal();
+                setback * path.direction_along(distance).orthogonal();
             let orientation = path.direction_along(distance);
 
             requester.found_lot(
@@ -364,6 +464,9 @@ impl Lane {
                     position,
                     orientation,
                     adjacent_lane: self.id,
+                    frontage: MIN_FRONTAGE + ::core::random::next_f32() * MIN_FRONTAGE,
+                    // the lot reaches from the side of the road as far behind the building
+                    depth: 2.0 * (setback - ROAD_SIDE_DISTANCE),
                 },
                 world,
             );
@@ -457,29 +560,26 @@ impl TransferLane {
 
 impl Unbuildable for TransferLane {
     fn disconnect(&mut self, other_id: UnbuildableID, world: &mut World) {
-        self.connectivity.left =
-            self.connectivity.left.and_then(
-                // TODO: ugly: untyped ID shenanigans
-                |(left_id, left_start)| if left_id._raw_id == other_id._raw_id {
-                    None
-                } else {
-                    Some((left_id, left_start))
-                },
-            );
-        self.connectivity.right = self.connectivity.right.and_then(
-            // TODO: ugly: untyped ID shenanigans
-            |(right_id, right_start)| if right_id._raw_id ==
-                other_id._raw_id
-            {
-                None
-            } else {
-                Some((right_id, right_start))
-            },
-        );
+        let is_other = |&(lane_id, _): &(LaneID, N)| UnbuildableID::from(lane_id) == other_id;
+        if self.connectivity.left.map_or(false, |left| is_other(&left)) {
+            self.microtraffic.left_obstacles = CVec::new();
+            self.connectivity.left = None;
+            self.microtraffic.work_zone = None;
+        }
+        if self.connectivity.right.map_or(false, |right| is_other(&right)) {
+            self.microtraffic.right_obstacles = CVec::new();
+            self.connectivity.right = None;
+            self.microtraffic.work_zone = None;
+        }
         other_id.on_confirm_disconnect(world);
     }
 
     fn unbuild(&mut self, report_to: MaterializedRealityID, world: &mut World) -> Fate {
+        self.construction.unbuilding_for = Some(report_to);
+        for car in self.microtraffic.cars.drain() {
+            car.as_lane_car.trip.cancel(CancelReason::LaneRemoved, world);
+        }
+
         if let Some((left_id, _)) = self.connectivity.left {
             Into::<UnbuildableID>::into(left_id).disconnect(self.id.into(), world);
         }
@@ -487,6 +587,9 @@ impl Unbuildable for TransferLane {
             Into::<UnbuildableID>::into(right_id).disconnect(self.id.into(), world);
         }
         super::rendering::on_unbuild_transfer(self, world);
+        if !self.construction.is_finished() {
+            ConstructionCrewsID::local_first(world).project_done(self.id.into(), None, world);
+        }
         if self.connectivity.left.is_none() && self.connectivity.right.is_none() {
             report_to.on_lane_unbuilt(Some(self.id.into()), world);
             Fate::Die
@@ -496,7 +599,6 @@ impl Unbuildable for TransferLane {
                 .into_iter()
                 .chain(self.connectivity.right)
                 .count() as u8;
-            self.construction.unbuilding_for = Some(report_to);
             Fate::Live
         }
     }
@@ -515,9 +617,10 @@ impl Unbuildable for TransferLane {
     }
 }
 
-pub fn setup(system: &mut ActorSystem) -> MaterializedRealityID {
+pub fn setup(system: &mut ActorSystem, simulation: SimulationID) -> MaterializedRealityID {
     auto_setup(system);
-    self::materialized_reality::setup(system)
+    self::crews::setup(system);
+    self::materialized_reality::setup(system, simulation)
 }
 
 mod kay_auto;
@@ -0,0 +1,482 @@
+//! Forecasts how much traffic a plan would get before it is built: the origins and
+//! destinations of all car trips of the last hour are assigned onto the network as it
+//! would be with the plan, using only the lane graph and a simple congestion function
+//! instead of microsimulation. Planned lanes are then drawn as wide as they'd be busy.
+use kay::{ActorSystem, World, External};
+use compact::{CVec, CDict, CHashMap};
+use ordered_float::OrderedFloat;
+use std::collections::BinaryHeap;
+use descartes::{N, P2, Band, FiniteCurve, RoughlyComparable};
+use monet::{RendererID, Renderable, RenderableID, MSG_Renderable_setup_in_scene,
+            MSG_Renderable_render_to_scene, Geometry, Instance};
+use stagemaster::{UserInterfaceID, Interactable2d, Interactable2dID,
+                  MSG_Interactable2d_draw_ui_2d};
+use stagemaster::combo::Combo2;
+use stagemaster::combo::Button::{LControl, O};
+use stagemaster::actions::{register_action, ActionId, ActionListener, ActionListenerID,
+                           ActionPhase, MSG_ActionListener_on_action};
+use stagemaster::geometry::{CPath, band_to_geometry};
+use imgui::ImGuiSetCond_FirstUseEver;
+use core::simulation::{SimulationID, Sleeper, SleeperID, MSG_Sleeper_wake, Timestamp, Ticks,
+                       TICKS_PER_SIM_MINUTE};
+use super::lane::{Lane, LaneID};
+use super::pathfinding::NodeID;
+use super::planning::current_plan::CurrentPlanID;
+
+const WINDOW_TICKS: usize = 15 * TICKS_PER_SIM_MINUTE;
+/// Trips are kept for this many windows, which make up the rolling hour
+const N_WINDOWS: usize = 4;
+const COLLECTION_TICKS: usize = 10;
+/// Lane ends closer than this are connected in the forecast network
+const ENDPOINT_TOLERANCE: N = 1.0;
+/// Trips from or to lanes that the plan moves further than this can't be routed
+const MAX_SNAP_DISTANCE: N = 10.0;
+/// Cars per hour one lane can carry
+const LANE_CAPACITY: f32 = 1800.0;
+/// Demand is assigned in this many steps, each seeing the congestion caused by the ones before
+const ASSIGNMENT_STEPS: usize = 4;
+/// The first individual ID of the forecast, one more for each congestion level
+const FORECAST_INDIVIDUAL_ID: u16 = 5511;
+const CONGESTION_COLORS: [[f32; 3]; 3] = [[0.2, 0.8, 0.2], [1.0, 0.8, 0.0], [1.0, 0.1, 0.1]];
+
+/// A lane in the network a plan would result in
+#[derive(Compact, Clone)]
+pub struct ForecastLink {
+    pub path: CPath,
+    /// Whether the lane is new or changed by the plan
+    pub planned: bool,
+}
+
+/// Travel time along a lane of `length` with `volume` cars per hour,
+/// in meters at free flow (BPR function)
+fn congested_cost(length: N, volume: f32) -> f32 {
+    length * (1.0 + 0.15 * (volume / LANE_CAPACITY).powi(4))
+}
+
+fn node_at(nodes: &mut Vec<P2>, position: P2) -> usize {
+    match nodes.iter().position(|node| node.is_roughly_within(position, ENDPOINT_TOLERANCE)) {
+        Some(node) => node,
+        None => {
+            nodes.push(position);
+            nodes.len() - 1
+        }
+    }
+}
+
+fn closest_node(nodes: &[P2], candidates: &[bool], position: P2) -> Option<usize> {
+    (0..nodes.len())
+        .filter(|&node| {
+            candidates[node] && nodes[node].is_roughly_within(position, MAX_SNAP_DISTANCE)
+        })
+        .min_by_key(|&node| OrderedFloat((nodes[node] - position).norm()))
+}
+
+/// Assigns `demand` (origin, destination, cars per hour) onto `links` (start, end, length),
+/// returning the volume of each link and the demand that couldn't be routed
+pub fn assign_demand(links: &[(P2, P2, N)], demand: &[(P2, P2, f32)]) -> (Vec<f32>, f32) {
+    let mut nodes: Vec<P2> = Vec::new();
+    let link_nodes = links
+        .iter()
+        .map(|&(start, end, _)| (node_at(&mut nodes, start), node_at(&mut nodes, end)))
+        .collect::<Vec<_>>();
+
+    let mut outgoing = vec![Vec::new(); nodes.len()];
+    let mut is_start = vec![false; nodes.len()];
+    let mut is_end = vec![false; nodes.len()];
+    for (link, &(start, end)) in link_nodes.iter().enumerate() {
+        outgoing[start].push(link);
+        is_start[start] = true;
+        is_end[end] = true;
+    }
+
+    let mut volumes = vec![0.0; links.len()];
+    let mut unassigned = 0.0;
+    let od_nodes = demand
+        .iter()
+        .map(|&(origin, destination, _)| {
+            (
+                closest_node(&nodes, &is_start, origin),
+                closest_node(&nodes, &is_end, destination),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    for _ in 0..ASSIGNMENT_STEPS {
+        let costs = links
+            .iter()
+            .zip(volumes.iter())
+            .map(|(&(_, _, length), &volume)| congested_cost(length, volume))
+            .collect::<Vec<_>>();
+        let mut step_volumes = vec![0.0; links.len()];
+
+        for (&(_, _, cars), &od) in demand.iter().zip(od_nodes.iter()) {
+            let (origin, destination) = match od {
+                (Some(origin), Some(destination)) => (origin, destination),
+                _ => {
+                    unassigned += cars / ASSIGNMENT_STEPS as f32;
+                    continue;
+                }
+            };
+
+            // Dijkstra, remembering the link each node was reached by
+            let mut distances = vec![::std::f32::INFINITY; nodes.len()];
+            let mut reached_by = vec![None; nodes.len()];
+            let mut queue = BinaryHeap::new();
+            distances[origin] = 0.0;
+            queue.push((OrderedFloat(-0.0), origin));
+            while let Some((OrderedFloat(negative_distance), node)) = queue.pop() {
+                if node == destination {
+                    break;
+                }
+                if -negative_distance > distances[node] {
+                    continue;
+                }
+                for &link in &outgoing[node] {
+                    let next = link_nodes[link].1;
+                    let distance = -negative_distance + costs[link];
+                    if distance < distances[next] {
+                        distances[next] = distance;
+                        reached_by[next] = Some(link);
+                        queue.push((OrderedFloat(-distance), next));
+                    }
+                }
+            }
+
+            if distances[destination].is_infinite() {
+                unassigned += cars / ASSIGNMENT_STEPS as f32;
+                continue;
+            }
+            let mut node = destination;
+            while let Some(link) = reached_by[node] {
+                step_volumes[link] += cars / ASSIGNMENT_STEPS as f32;
+                node = link_nodes[link].0;
+            }
+        }
+
+        for (volume, step_volume) in volumes.iter_mut().zip(step_volumes) {
+            *volume += step_volume;
+        }
+    }
+
+    (volumes, unassigned)
+}
+
+impl Lane {
+    pub fn report_forecast_position(&mut self, forecast: DemandForecastID, world: &mut World) {
+        forecast.add_node_position(
+            self.id.into(),
+            self.construction.path.start(),
+            self.construction.path.end(),
+            world,
+        );
+    }
+}
+
+/// Remembers between which lanes all car trips of the last hour went. The
+/// "Forecast Plan Demand" action then asks for the lane ends of all of those and for
+/// the network the current plan would result in, and assigns the trips onto it
+#[derive(Compact, Clone)]
+pub struct DemandForecast {
+    id: DemandForecastID,
+    simulation: SimulationID,
+    trips: CHashMap<(NodeID, NodeID), [usize; N_WINDOWS]>,
+    current_window: usize,
+    /// Start and end of the lanes trips went from and to
+    node_positions: CHashMap<NodeID, (P2, P2)>,
+    network_received: bool,
+    links: CVec<ForecastLink>,
+    volumes: CVec<f32>,
+    unassigned: f32,
+    forecasting: bool,
+    rendered_in: CDict<RendererID, ()>,
+}
+
+impl DemandForecast {
+    pub fn spawn(
+        id: DemandForecastID,
+        simulation: SimulationID,
+        user_interface: UserInterfaceID,
+        world: &mut World,
+    ) -> DemandForecast {
+        user_interface.add_2d(id.into(), world);
+        register_action(
+            "Forecast Plan Demand",
+            Combo2::new(&[LControl, O], &[]),
+            id.into(),
+            world,
+        );
+
+        DemandForecast {
+            id,
+            simulation,
+            trips: CHashMap::new(),
+            current_window: 0,
+            node_positions: CHashMap::new(),
+            network_received: false,
+            links: CVec::new(),
+            volumes: CVec::new(),
+            unassigned: 0.0,
+            forecasting: false,
+            rendered_in: CDict::new(),
+        }
+    }
+
+    pub fn add_trip(
+        &mut self,
+        source: NodeID,
+        destination: NodeID,
+        tick: Timestamp,
+        _: &mut World,
+    ) {
+        let window = tick.ticks() / WINDOW_TICKS;
+        if window != self.current_window {
+            // forget what happened an hour ago, in the windows we skipped
+            let n_skipped = (window - self.current_window).min(N_WINDOWS);
+            for trips in self.trips.values_mut() {
+                for skipped in 1..(n_skipped + 1) {
+                    trips[(self.current_window + skipped) % N_WINDOWS] = 0;
+                }
+            }
+            self.current_window = window;
+        }
+
+        let mut trips = self.trips.get((source, destination)).cloned().unwrap_or(
+            [0; N_WINDOWS],
+        );
+        trips[window % N_WINDOWS] += 1;
+        self.trips.insert((source, destination), trips);
+    }
+
+    pub fn add_node_position(&mut self, node: NodeID, start: P2, end: P2, _: &mut World) {
+        self.node_positions.insert(node, (start, end));
+    }
+
+    pub fn on_forecast_network(&mut self, links: &CVec<ForecastLink>, _: &mut World) {
+        self.links = links.clone();
+        self.network_received = true;
+    }
+
+    fn forecast(&mut self) {
+        let links = self.links
+            .iter()
+            .map(|link| (link.path.start(), link.path.end(), link.path.length()))
+            .collect::<Vec<_>>();
+        let demand = self.trips
+            .pairs()
+            .filter_map(|(&(source, destination), trips)| {
+                match (self.node_positions.get(source), self.node_positions.get(destination)) {
+                    (Some(&(origin, _)), Some(&(_, destination))) => {
+                        Some((origin, destination, trips.iter().sum::<usize>() as f32))
+                    }
+                    _ => None,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let (volumes, unassigned) = assign_demand(&links, &demand);
+        self.volumes = volumes.into();
+        self.unassigned = unassigned;
+        self.rendered_in = CDict::new();
+    }
+
+    /// Asks the current plan for the network to forecast, which is the plan itself
+    /// or one of its phases
+    pub fn start_forecast(&mut self, world: &mut World) {
+        if self.forecasting {
+            return;
+        }
+
+        self.forecasting = true;
+        self.network_received = false;
+        self.node_positions = CHashMap::new();
+        for &(source, destination) in self.trips.keys() {
+            for &node in &[source, destination] {
+                LaneID::from(node).report_forecast_position(self.id, world);
+            }
+        }
+        CurrentPlanID::local_first(world).forecast_demand(self.id, world);
+        self.simulation.wake_up_in(Ticks(COLLECTION_TICKS), self.id.into(), world);
+    }
+
+    fn clear(&mut self) {
+        self.links = CVec::new();
+        self.volumes = CVec::new();
+        self.unassigned = 0.0;
+        self.rendered_in = CDict::new();
+    }
+}
+
+impl Sleeper for DemandForecast {
+    fn wake(&mut self, _current_tick: Timestamp, world: &mut World) {
+        if self.network_received {
+            self.forecasting = false;
+            self.forecast();
+        } else {
+            self.simulation.wake_up_in(Ticks(COLLECTION_TICKS), self.id.into(), world);
+        }
+    }
+}
+
+impl ActionListener for DemandForecast {
+    fn on_action(&mut self, action: ActionId, phase: ActionPhase, world: &mut World) {
+        if phase == ActionPhase::Started &&
+            action == ActionId::named("Forecast Plan Demand") &&
+            !self.forecasting
+        {
+            if !self.volumes.is_empty() {
+                self.clear();
+                return;
+            }
+
+            self.start_forecast(world);
+        }
+    }
+}
+
+impl Renderable for DemandForecast {
+    fn setup_in_scene(&mut self, _renderer_id: RendererID, _scene_id: usize, _: &mut World) {}
+
+    fn render_to_scene(
+        &mut self,
+        renderer_id: RendererID,
+        scene_id: usize,
+        _frame: usize,
+        world: &mut World,
+    ) {
+        if self.rendered_in.get(renderer_id).is_some() {
+            return;
+        }
+
+        // planned lanes are drawn as wide as they'd be busy, colored by how congested
+        for (level, &color) in CONGESTION_COLORS.iter().enumerate() {
+            let geometry: Geometry = self.links
+                .iter()
+                .zip(self.volumes.iter())
+                .filter(|&(link, &volume)| {
+                    let saturation = volume / LANE_CAPACITY;
+                    let link_level = if saturation < 0.5 {
+                        0
+                    } else if saturation < 0.9 {
+                        1
+                    } else {
+                        2
+                    };
+                    link.planned && volume > 0.0 && link_level == level
+                })
+                .map(|(link, &volume)| {
+                    let width = (0.5 + volume / 300.0).min(4.0);
+                    band_to_geometry(&Band::new(link.path.clone(), width), 0.6)
+                })
+                .sum();
+            renderer_id.update_individual(
+                scene_id,
+                FORECAST_INDIVIDUAL_ID + level as u16,
+                geometry,
+                Instance::with_color(color),
+                true,
+                world,
+            );
+        }
+        self.rendered_in.insert(renderer_id, ());
+    }
+}
+
+impl Interactable2d for DemandForecast {
+    fn draw_ui_2d(
+        &mut self,
+        imgui_ui: &External<::imgui::Ui<'static>>,
+        return_to: UserInterfaceID,
+        world: &mut World,
+    ) {
+        let ui = imgui_ui.steal();
+        let mut close = false;
+
+        if !self.volumes.is_empty() {
+            ui.window(im_str!("Demand Forecast"))
+                .size((300.0, 200.0), ImGuiSetCond_FirstUseEver)
+                .collapsible(true)
+                .build(|| {
+                    let planned_volumes = self.links
+                        .iter()
+                        .zip(self.volumes.iter())
+                        .filter(|&(link, _)| link.planned)
+                        .map(|(_, &volume)| volume)
+                        .collect::<Vec<_>>();
+                    let busiest = planned_volumes.iter().cloned().fold(0.0, f32::max);
+                    let total_trips: usize = self.trips
+                        .values()
+                        .map(|trips| trips.iter().sum::<usize>())
+                        .sum();
+                    ui.text(im_str!("Car trips in the last hour: {}", total_trips));
+                    ui.text(im_str!(
+                        "Trips the planned network can't route: {:.0}",
+                        self.unassigned
+                    ));
+                    ui.text(im_str!(
+                        "Planned lanes used: {} of {}",
+                        planned_volumes.iter().filter(|&&volume| volume > 0.0).count(),
+                        planned_volumes.len()
+                    ));
+                    ui.text(im_str!(
+                        "Busiest planned lane: {:.0} cars/h ({:.0}% of capacity)",
+                        busiest,
+                        busiest / LANE_CAPACITY * 100.0
+                    ));
+                    if ui.small_button(im_str!("Close")) {
+                        close = true;
+                    }
+                });
+        }
+
+        if close {
+            self.clear();
+        }
+
+        return_to.ui_drawn(ui, world);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::assign_demand;
+    use descartes::P2;
+
+    #[test]
+    fn demand_follows_the_shortest_route() {
+        let a = P2::new(0.0, 0.0);
+        let b = P2::new(100.0, 0.0);
+        let c = P2::new(200.0, 0.0);
+        let links = [(a, b, 100.0), (b, c, 100.0), (c, a, 200.0)];
+        let (volumes, unassigned) = assign_demand(&links, &[(a, c, 100.0)]);
+
+        assert!((volumes[0] - 100.0).abs() < 0.01);
+        assert!((volumes[1] - 100.0).abs() < 0.01);
+        assert_eq!(volumes[2], 0.0);
+        assert_eq!(unassigned, 0.0);
+    }
+
+    #[test]
+    fn congestion_spreads_demand_over_alternatives() {
+        let a = P2::new(0.0, 0.0);
+        let detour = P2::new(50.0, 30.0);
+        let b = P2::new(100.0, 0.0);
+        let links = [(a, b, 100.0), (a, detour, 60.0), (detour, b, 60.0)];
+        let (volumes, _) = assign_demand(&links, &[(a, b, 8000.0)]);
+
+        assert!(volumes[0] > 0.0 && volumes[0] < 8000.0);
+        assert!(volumes[1] > 0.0);
+
+        let (_, unassigned) = assign_demand(&links, &[(b, a, 100.0)]);
+        assert!((unassigned - 100.0).abs() < 0.01);
+    }
+}
+
+pub fn setup(system: &mut ActorSystem, user_interface: UserInterfaceID, simulation: SimulationID) {
+    system.register::<DemandForecast>();
+    auto_setup(system);
+
+    DemandForecastID::spawn(simulation, user_interface, &mut system.world());
+}
+
+mod kay_auto;
+pub use self::kay_auto::*;
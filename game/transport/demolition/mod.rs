@@ -0,0 +1,81 @@
+use kay::{ActorSystem, World};
+use descartes::P2;
+use stagemaster::combo::Combo2;
+use stagemaster::combo::Button::{LControl, D};
+use stagemaster::{UserInterfaceID, Event3d, Interactable3d, Interactable3dID,
+                  MSG_Interactable3d_on_event};
+use stagemaster::actions::{register_action, ActionId, ActionListener, ActionListenerID,
+                           ActionPhase, MSG_ActionListener_on_action};
+use super::planning::current_plan::{CurrentPlanID, set_tool_active};
+use economy::buildings::BuildingID;
+
+/// Removes built roads and buildings: the "Demolish" action toggles demolition mode,
+/// clicking then demolishes whatever road stroke or building is under the cursor
+#[derive(Compact, Clone)]
+pub struct Demolisher {
+    id: DemolisherID,
+    user_interface: UserInterfaceID,
+    active: bool,
+}
+
+impl Demolisher {
+    pub fn init(
+        id: DemolisherID,
+        user_interface: UserInterfaceID,
+        world: &mut World,
+    ) -> Demolisher {
+        user_interface.focus(id.into(), world);
+        register_action("Demolish", Combo2::new(&[LControl, D], &[]), id.into(), world);
+
+        Demolisher {
+            id,
+            user_interface,
+            active: false,
+        }
+    }
+}
+
+impl ActionListener for Demolisher {
+    fn on_action(&mut self, action: ActionId, phase: ActionPhase, world: &mut World) {
+        if phase == ActionPhase::Started && action == ActionId::named("Demolish") {
+            self.active = !self.active;
+            set_tool_active(self.user_interface, self.id.into(), self.active, world);
+        }
+    }
+}
+
+impl Interactable3d for Demolisher {
+    fn on_event(&mut self, event: Event3d, world: &mut World) {
+        match event {
+            Event3d::DragFinished { to, .. } => {
+                if self.active {
+                    let position = P2::new(to.x, to.y);
+                    CurrentPlanID::local_first(world).demolish_at(position, world);
+                    BuildingID::global_broadcast(world).demolish_at(position, world);
+                }
+            }
+            Event3d::Frame => {
+                if self.active {
+                    self.user_interface.add_debug_text(
+                        "Demolishing".chars().collect(),
+                        "click a road or building".chars().collect(),
+                        [0.0, 0.0, 0.0, 1.0],
+                        false,
+                        world,
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+pub fn setup(system: &mut ActorSystem, user_interface: UserInterfaceID) {
+    system.register::<Demolisher>();
+    auto_setup(system);
+
+    DemolisherID::init(user_interface, &mut system.world());
+}
+
+mod kay_auto;
+pub use self::kay_auto::*;
@@ -0,0 +1,400 @@
+//! Virtual loop detectors: a position on a lane where passing cars are counted and
+//! their speeds measured, along with how much of the time a car stands over the loop
+//! (its occupancy). Lanes measure with their detectors as cars move and hand out readings
+//! to any `DetectorListener`, like signal controllers or the `Detectors` overview.
+use kay::{ActorSystem, World, External};
+use compact::CVec;
+use descartes::{P2, Curve};
+use stagemaster::{UserInterfaceID, Event3d, Interactable3d, Interactable3dID,
+                  MSG_Interactable3d_on_event, Interactable2d, Interactable2dID,
+                  MSG_Interactable2d_draw_ui_2d};
+use stagemaster::combo::Combo2;
+use stagemaster::combo::Button::{LControl, L};
+use stagemaster::actions::{register_action, ActionId, ActionListener, ActionListenerID,
+                           ActionPhase, MSG_ActionListener_on_action};
+use transport::planning::current_plan::set_tool_active;
+use stagemaster::charts::{Chart, ChartKind};
+use imgui::ImGuiSetCond_FirstUseEver;
+use core::simulation::{SimulationID, Sleeper, SleeperID, MSG_Sleeper_wake, Timestamp, Ticks,
+                       TICKS_PER_SIM_MINUTE};
+use core::metrics::DETECTED_CARS;
+use std::sync::atomic::Ordering;
+use super::lane::{Lane, LaneID};
+use super::spatial_index::{SpatialIndexID, NearestLaneRequester, NearestLaneRequesterID,
+                           MSG_NearestLaneRequester_on_nearest_lane};
+
+const READING_INTERVAL: Ticks = Ticks(TICKS_PER_SIM_MINUTE);
+/// Readings of the last hour are charted
+const HISTORY_LENGTH: usize = 60;
+const SPEED_BINS: usize = 10;
+/// Clicks closer than this to a lane place a detector on it
+const PLACEMENT_DISTANCE: f32 = 3.0;
+
+/// Measures at `position` along a lane, until its next reading
+#[derive(Copy, Clone, Debug)]
+pub struct Detector {
+    pub position: f32,
+    cars_passed: usize,
+    speed_sum: f32,
+    occupied_ticks: usize,
+    ticks: usize,
+    occupied_now: bool,
+}
+
+/// What a detector measured since its last reading
+#[derive(Copy, Clone, Debug)]
+pub struct DetectorReading {
+    pub lane: LaneID,
+    pub position: f32,
+    pub cars_passed: usize,
+    /// Fraction of the time a car stood over the detector
+    pub occupancy: f32,
+    /// Average speed of the passing cars in m/s, 0.0 if none passed
+    pub average_speed: f32,
+}
+
+impl Detector {
+    pub fn new(position: f32) -> Detector {
+        Detector {
+            position,
+            cars_passed: 0,
+            speed_sum: 0.0,
+            occupied_ticks: 0,
+            ticks: 0,
+            occupied_now: false,
+        }
+    }
+
+    /// Called for each car that moved its front from `from` to `to` this tick
+    pub fn observe(&mut self, from: f32, to: f32, velocity: f32, car_length: f32) {
+        if from < self.position && to >= self.position {
+            self.cars_passed += 1;
+            self.speed_sum += velocity;
+        }
+        if to >= self.position && to - car_length <= self.position {
+            self.occupied_now = true;
+        }
+    }
+
+    /// Called once per tick, after all cars were observed
+    pub fn finish_tick(&mut self) {
+        self.ticks += 1;
+        if self.occupied_now {
+            self.occupied_ticks += 1;
+        }
+        self.occupied_now = false;
+    }
+
+    /// What was measured since the last reading, starting a new measurement
+    pub fn read(&mut self, lane: LaneID) -> DetectorReading {
+        let reading = DetectorReading {
+            lane,
+            position: self.position,
+            cars_passed: self.cars_passed,
+            occupancy: if self.ticks > 0 {
+                self.occupied_ticks as f32 / self.ticks as f32
+            } else {
+                0.0
+            },
+            average_speed: if self.cars_passed > 0 {
+                self.speed_sum / self.cars_passed as f32
+            } else {
+                0.0
+            },
+        };
+        *self = Detector::new(self.position);
+        reading
+    }
+}
+
+/// Cars counted by one detector in its recent readings, oldest first
+#[derive(Compact, Clone)]
+struct DetectorHistory {
+    lane: LaneID,
+    position: f32,
+    cars_passed: CVec<f32>,
+}
+
+pub trait DetectorListener {
+    fn on_detector_readings(&mut self, readings: &CVec<DetectorReading>, world: &mut World);
+}
+
+impl Lane {
+    fn add_detector(&mut self, position: f32, world: &mut World) {
+        if !self.microtraffic.detectors.iter().any(|detector| {
+            (detector.position - position).abs() < 1.0
+        })
+        {
+            self.microtraffic.detectors.push(Detector::new(position));
+            DetectorsID::local_first(world).watch_lane(self.id, world);
+        }
+    }
+
+    pub fn place_detector_near(&mut self, point: P2, world: &mut World) {
+        if let Some(position) = self.construction.path.project_with_max_distance(
+            point,
+            PLACEMENT_DISTANCE,
+            PLACEMENT_DISTANCE,
+        )
+        {
+            self.add_detector(position, world);
+        }
+    }
+
+    pub fn place_detector(&mut self, instance_id: u32, position: f32, world: &mut World) {
+        if self.id._raw_id.instance_id == instance_id {
+            let position = position.max(0.0).min(self.construction.length);
+            self.add_detector(position, world);
+        }
+    }
+
+    pub fn remove_detectors(&mut self, instance_id: u32, _: &mut World) {
+        if self.id._raw_id.instance_id == instance_id {
+            self.microtraffic.detectors.clear();
+        }
+    }
+
+    pub fn read_detectors(&mut self, listener: DetectorListenerID, world: &mut World) {
+        let id = self.id;
+        let readings = self.microtraffic
+            .detectors
+            .iter_mut()
+            .map(|detector| detector.read(id))
+            .collect::<CVec<_>>();
+        if !readings.is_empty() {
+            listener.on_detector_readings(readings, world);
+        }
+    }
+}
+
+/// Keeps the latest readings of all detectors in the city and shows them.
+/// Detectors are placed with the "Place Detector" action, by clicking a lane
+#[derive(Compact, Clone)]
+pub struct Detectors {
+    id: DetectorsID,
+    simulation: SimulationID,
+    user_interface: UserInterfaceID,
+    lanes: CVec<LaneID>,
+    /// Lanes asked for readings last time
+    asked: CVec<LaneID>,
+    readings: CVec<DetectorReading>,
+    history: CVec<DetectorHistory>,
+    placing: bool,
+}
+
+impl Detectors {
+    pub fn spawn(
+        id: DetectorsID,
+        simulation: SimulationID,
+        user_interface: UserInterfaceID,
+        world: &mut World,
+    ) -> Detectors {
+        user_interface.add_2d(id.into(), world);
+        register_action(
+            "Place Detector",
+            Combo2::new(&[LControl, L], &[]),
+            id.into(),
+            world,
+        );
+        simulation.wake_up_in(READING_INTERVAL, id.into(), world);
+
+        Detectors {
+            id,
+            simulation,
+            user_interface,
+            lanes: CVec::new(),
+            asked: CVec::new(),
+            readings: CVec::new(),
+            history: CVec::new(),
+            placing: false,
+        }
+    }
+
+    pub fn watch_lane(&mut self, lane: LaneID, _: &mut World) {
+        if !self.lanes.contains(&lane) {
+            self.lanes.push(lane);
+        }
+    }
+}
+
+impl DetectorListener for Detectors {
+    fn on_detector_readings(&mut self, readings: &CVec<DetectorReading>, _: &mut World) {
+        for reading in readings.iter() {
+            DETECTED_CARS.fetch_add(reading.cars_passed, Ordering::Relaxed);
+            self.readings.push(*reading);
+
+            let existing = self.history.iter().position(|history| {
+                history.lane == reading.lane && history.position == reading.position
+            });
+            let idx = existing.unwrap_or_else(|| {
+                self.history.push(DetectorHistory {
+                    lane: reading.lane,
+                    position: reading.position,
+                    cars_passed: CVec::new(),
+                });
+                self.history.len() - 1
+            });
+            let history = &mut self.history[idx];
+            history.cars_passed.push(reading.cars_passed as f32);
+            if history.cars_passed.len() > HISTORY_LENGTH {
+                history.cars_passed.remove(0);
+            }
+        }
+    }
+}
+
+impl Sleeper for Detectors {
+    fn wake(&mut self, _current_tick: Timestamp, world: &mut World) {
+        // lanes that didn't answer since the last time are gone or lost their detectors
+        let answered = self.readings
+            .iter()
+            .map(|reading| reading.lane)
+            .collect::<Vec<_>>();
+        self.lanes = self.lanes
+            .iter()
+            .cloned()
+            .filter(|lane| !self.asked.contains(lane) || answered.contains(lane))
+            .collect();
+        let lanes = &self.lanes;
+        self.history.retain(|history| lanes.contains(&history.lane));
+        self.readings = CVec::new();
+
+        for &lane in self.lanes.iter() {
+            lane.read_detectors(self.id.into(), world);
+        }
+        self.asked = self.lanes.clone();
+
+        self.simulation.wake_up_in(READING_INTERVAL, self.id.into(), world);
+    }
+}
+
+impl ActionListener for Detectors {
+    fn on_action(&mut self, action: ActionId, phase: ActionPhase, world: &mut World) {
+        if phase == ActionPhase::Started && action == ActionId::named("Place Detector") {
+            self.placing = !self.placing;
+            set_tool_active(self.user_interface, self.id.into(), self.placing, world);
+        }
+    }
+}
+
+impl Interactable3d for Detectors {
+    fn on_event(&mut self, event: Event3d, world: &mut World) {
+        if self.placing {
+            match event {
+                Event3d::DragFinished { to, .. } => {
+                    SpatialIndexID::local_first(world).find_nearest_lane(
+                        P2::new(to.x, to.y),
+                        PLACEMENT_DISTANCE,
+                        self.id.into(),
+                        world,
+                    );
+                }
+                Event3d::Frame => {
+                    self.user_interface.add_debug_text(
+                        "Placing Detectors".chars().collect(),
+                        "click a lane".chars().collect(),
+                        [0.0, 0.0, 0.0, 1.0],
+                        false,
+                        world,
+                    );
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl NearestLaneRequester for Detectors {
+    fn on_nearest_lane(&mut self, point: P2, lane: Option<(LaneID, f32)>, world: &mut World) {
+        if let Some((lane, _distance)) = lane {
+            lane.place_detector_near(point, world);
+        }
+    }
+}
+
+impl Interactable2d for Detectors {
+    fn draw_ui_2d(
+        &mut self,
+        imgui_ui: &External<::imgui::Ui<'static>>,
+        return_to: UserInterfaceID,
+        world: &mut World,
+    ) {
+        let ui = imgui_ui.steal();
+
+        if !self.lanes.is_empty() {
+            ui.window(im_str!("Detectors"))
+                .size((300.0, 200.0), ImGuiSetCond_FirstUseEver)
+                .collapsible(true)
+                .build(|| {
+                    for reading in self.readings.iter() {
+                        ui.text(im_str!(
+                            "Lane {} at {:.0} m: {} cars/min, {:.0}% occupied, {:.1} m/s",
+                            reading.lane._raw_id.instance_id,
+                            reading.position,
+                            reading.cars_passed,
+                            reading.occupancy * 100.0,
+                            reading.average_speed
+                        ));
+                    }
+
+                    if ui.collapsing_header(im_str!("Last hour")).build() {
+                        for history in self.history.iter() {
+                            let title = format!(
+                                "Lane {} at {:.0} m",
+                                history.lane._raw_id.instance_id,
+                                history.position
+                            );
+                            Chart::new(&title, ChartKind::Line)
+                                .series("Cars/min", &history.cars_passed)
+                                .height(30.0)
+                                .draw(&ui);
+                        }
+                    }
+
+                    let speeds = self.readings
+                        .iter()
+                        .filter(|reading| reading.cars_passed > 0)
+                        .map(|reading| reading.average_speed)
+                        .collect::<Vec<_>>();
+                    if !speeds.is_empty() {
+                        Chart::new("Average speeds, m/s", ChartKind::Histogram(SPEED_BINS))
+                            .series("Detectors", &speeds)
+                            .draw(&ui);
+                    }
+                });
+        }
+
+        return_to.ui_drawn(ui, world);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Detector;
+
+    #[test]
+    fn detector_counts_passing_cars_and_occupancy() {
+        let mut detector = Detector::new(10.0);
+        let mut front = 0.0;
+        while front < 20.0 {
+            detector.observe(front, front + 1.0, 5.0, 4.0);
+            detector.finish_tick();
+            front += 1.0;
+        }
+
+        assert_eq!(detector.cars_passed, 1);
+        assert_eq!(detector.occupied_ticks, 5);
+        assert_eq!(detector.ticks, 20);
+        assert_eq!(detector.speed_sum, 5.0);
+    }
+}
+
+pub fn setup(system: &mut ActorSystem, user_interface: UserInterfaceID, simulation: SimulationID) {
+    system.register::<Detectors>();
+    auto_setup(system);
+
+    DetectorsID::spawn(simulation, user_interface, &mut system.world());
+}
+
+mod kay_auto;
+pub use self::kay_auto::*;
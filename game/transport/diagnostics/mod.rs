@@ -0,0 +1,210 @@
+use kay::{ActorSystem, World, ID};
+use compact::CVec;
+use descartes::{P2, FiniteCurve};
+use fnv::FnvHashMap;
+use monet::{RendererID, Renderable, RenderableID, MSG_Renderable_setup_in_scene,
+            MSG_Renderable_render_to_scene, Geometry, Vertex, Instance};
+use stagemaster::UserInterfaceID;
+use core::simulation::{SimulationID, Sleeper, SleeperID, MSG_Sleeper_wake, Timestamp, Ticks};
+use core::strongly_connected::strongly_connected_components;
+use super::lane::{Lane, LaneID, TransferLane, TransferLaneID};
+use super::pathfinding::successors;
+
+const ANALYSIS_INTERVAL: Ticks = Ticks(600);
+const COLLECTION_TICKS: Ticks = Ticks(10);
+const UNROUTABLE_MARKER_BATCH_ID: u16 = 8100;
+
+#[derive(Compact, Clone)]
+pub struct LaneGraphReport {
+    pub lane: ID,
+    pub successors: CVec<ID>,
+    pub position: P2,
+}
+
+impl Lane {
+    pub fn report_to_diagnostics(&mut self, diagnostics: NetworkDiagnosticsID, world: &mut World) {
+        diagnostics.add_report(
+            LaneGraphReport {
+                lane: self.id._raw_id,
+                successors: successors(self).map(|node| node._raw_id).collect(),
+                position: self.construction.path.along(self.construction.length / 2.0),
+            },
+            world,
+        );
+    }
+}
+
+impl TransferLane {
+    pub fn report_to_diagnostics(&mut self, diagnostics: NetworkDiagnosticsID, world: &mut World) {
+        diagnostics.add_report(
+            LaneGraphReport {
+                lane: self.id._raw_id,
+                successors: self.connectivity
+                    .left
+                    .iter()
+                    .chain(self.connectivity.right.iter())
+                    .map(|&(lane, _)| lane._raw_id)
+                    .collect(),
+                position: self.construction.path.along(self.construction.length / 2.0),
+            },
+            world,
+        );
+    }
+}
+
+#[derive(Compact, Clone)]
+pub struct NetworkDiagnostics {
+    id: NetworkDiagnosticsID,
+    simulation: SimulationID,
+    reports: CVec<LaneGraphReport>,
+    collecting: bool,
+    unroutable_positions: CVec<P2>,
+}
+
+impl NetworkDiagnostics {
+    pub fn spawn(
+        id: NetworkDiagnosticsID,
+        simulation: SimulationID,
+        world: &mut World,
+    ) -> NetworkDiagnostics {
+        simulation.wake_up_in(ANALYSIS_INTERVAL, id.into(), world);
+
+        NetworkDiagnostics {
+            id,
+            simulation,
+            reports: CVec::new(),
+            collecting: false,
+            unroutable_positions: CVec::new(),
+        }
+    }
+
+    pub fn add_report(&mut self, report: &LaneGraphReport, _: &mut World) {
+        if self.collecting {
+            self.reports.push(report.clone());
+        }
+    }
+
+    fn analyze(&mut self, world: &mut World) {
+        let index_of: FnvHashMap<ID, usize> = self.reports
+            .iter()
+            .enumerate()
+            .map(|(i, report)| (report.lane, i))
+            .collect();
+
+        let graph = self.reports
+            .iter()
+            .map(|report| {
+                report
+                    .successors
+                    .iter()
+                    .filter_map(|successor| index_of.get(successor).cloned())
+                    .collect()
+            })
+            .collect::<Vec<Vec<usize>>>();
+
+        let mut components = strongly_connected_components(&graph);
+        components.sort_by_key(|component| component.len());
+        // the largest component is considered the main network
+        components.pop();
+
+        self.unroutable_positions = components
+            .iter()
+            .flat_map(|component| component.iter())
+            .map(|&i| self.reports[i].position)
+            .collect();
+
+        let (text, color) = if self.unroutable_positions.is_empty() {
+            ("none".to_owned(), [0.0, 0.0, 0.0, 1.0])
+        } else {
+            (
+                format!(
+                    "{} lanes in {} islands can't reach or be reached from the network",
+                    self.unroutable_positions.len(),
+                    components.len()
+                ),
+                [1.0, 0.0, 0.0, 1.0],
+            )
+        };
+
+        UserInterfaceID::local_first(world).add_debug_text(
+            "Unroutable lanes".chars().collect(),
+            text.chars().collect(),
+            color,
+            true,
+            world,
+        );
+    }
+}
+
+impl Sleeper for NetworkDiagnostics {
+    fn wake(&mut self, _current_tick: Timestamp, world: &mut World) {
+        if self.collecting {
+            self.collecting = false;
+            self.analyze(world);
+            self.reports = CVec::new();
+            self.simulation.wake_up_in(ANALYSIS_INTERVAL, self.id.into(), world);
+        } else {
+            self.collecting = true;
+            LaneID::global_broadcast(world).report_to_diagnostics(self.id, world);
+            TransferLaneID::global_broadcast(world).report_to_diagnostics(self.id, world);
+            self.simulation.wake_up_in(COLLECTION_TICKS, self.id.into(), world);
+        }
+    }
+}
+
+impl Renderable for NetworkDiagnostics {
+    fn setup_in_scene(&mut self, renderer_id: RendererID, scene_id: usize, world: &mut World) {
+        renderer_id.add_batch(
+            scene_id,
+            UNROUTABLE_MARKER_BATCH_ID,
+            Geometry::new(
+                vec![
+                    Vertex { position: [0.0, 0.0, 1.0] },
+                    Vertex { position: [-1.5, 0.0, 4.0] },
+                    Vertex { position: [0.0, 1.5, 4.0] },
+                    Vertex { position: [1.5, 0.0, 4.0] },
+                    Vertex { position: [0.0, -1.5, 4.0] },
+                ],
+                vec![0, 1, 2, 0, 2, 3, 0, 3, 4, 0, 4, 1, 1, 2, 3, 1, 3, 4],
+            ),
+            world,
+        );
+    }
+
+    fn render_to_scene(
+        &mut self,
+        renderer_id: RendererID,
+        scene_id: usize,
+        frame: usize,
+        world: &mut World,
+    ) {
+        if !self.unroutable_positions.is_empty() {
+            renderer_id.add_several_instances(
+                scene_id,
+                UNROUTABLE_MARKER_BATCH_ID,
+                frame,
+                self.unroutable_positions
+                    .iter()
+                    .map(|position| {
+                        Instance {
+                            instance_position: [position.x, position.y, 0.0],
+                            instance_direction: [1.0, 0.0],
+                            instance_color: [1.0, 0.0, 0.0],
+                        }
+                    })
+                    .collect(),
+                world,
+            );
+        }
+    }
+}
+
+pub fn setup(system: &mut ActorSystem, simulation: SimulationID) {
+    system.register::<NetworkDiagnostics>();
+    auto_setup(system);
+
+    NetworkDiagnosticsID::spawn(simulation, &mut system.world());
+}
+
+mod kay_auto;
+pub use self::kay_auto::*;
@@ -0,0 +1,234 @@
+//! A log of what every driven trip does, in the format of MATSim events files, so
+//! analysis tools made for MATSim can be used on Citybound runs.
+//!
+//! Set `CITYBOUND_EVENTS_FILE` to a path ending in `.xml` for a MATSim events file or
+//! in `.csv` for one row per event, usually for a headless comparison run (see
+//! `core::comparison`). Each trip is both the person and the vehicle, and lanes are
+//! the links. Transfer lanes aren't links, so a car changing lanes leaves one link and
+//! enters the next a bit later. Walking and cycling trips aren't logged, since they
+//! aren't simulated on the network.
+//!
+//! Lanes tick in parallel (see `SimulationConfig::tick_threads`), so events are buffered
+//! by lane and only written after each tick (see `write_logged`), ordered by lane. That
+//! way, two runs with the same seed write identical files.
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::mem;
+use std::sync::Mutex;
+use core::simulation::Timestamp;
+use super::pathfinding::trip::TripID;
+use super::restrictions::VehicleClass;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum EventKind {
+    Departure,
+    VehicleEntersTraffic,
+    EnteredLink,
+    LeftLink,
+    VehicleLeavesTraffic,
+    Arrival,
+    /// The trip failed on the way
+    Stuck,
+}
+
+impl EventKind {
+    fn matsim_type(&self) -> &'static str {
+        match *self {
+            EventKind::Departure => "departure",
+            EventKind::VehicleEntersTraffic => "vehicle enters traffic",
+            EventKind::EnteredLink => "entered link",
+            EventKind::LeftLink => "left link",
+            EventKind::VehicleLeavesTraffic => "vehicle leaves traffic",
+            EventKind::Arrival => "arrival",
+            EventKind::Stuck => "stuckAndAbort",
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Event {
+    pub seconds: usize,
+    pub kind: EventKind,
+    pub trip: u32,
+    /// The lane, unknown for trips that got stuck before they found one
+    pub link: Option<u32>,
+    pub vehicle: VehicleClass,
+}
+
+fn mode(vehicle: VehicleClass) -> &'static str {
+    match vehicle {
+        VehicleClass::Car | VehicleClass::HighOccupancyCar | VehicleClass::Emergency => "car",
+        VehicleClass::Bus => "pt",
+        VehicleClass::Truck | VehicleClass::OversizeTruck => "truck",
+    }
+}
+
+/// One `<event>` element, with the attributes MATSim has for its kind
+pub fn to_xml(event: &Event) -> String {
+    let link = event.link.map(|link| link.to_string()).unwrap_or_default();
+    let attributes = match event.kind {
+        EventKind::Departure | EventKind::Arrival | EventKind::Stuck => {
+            format!(
+                "person=\"{}\" link=\"{}\" legMode=\"{}\"",
+                event.trip,
+                link,
+                mode(event.vehicle)
+            )
+        }
+        EventKind::VehicleEntersTraffic |
+        EventKind::VehicleLeavesTraffic => {
+            format!(
+                "person=\"{}\" link=\"{}\" vehicle=\"{}\" networkMode=\"{}\" \
+                 relativePosition=\"1.0\"",
+                event.trip,
+                link,
+                event.trip,
+                mode(event.vehicle)
+            )
+        }
+        EventKind::EnteredLink | EventKind::LeftLink => {
+            format!("vehicle=\"{}\" link=\"{}\"", event.trip, link)
+        }
+    };
+    format!(
+        "\t<event time=\"{}.0\" type=\"{}\" {} />\n",
+        event.seconds,
+        event.kind.matsim_type(),
+        attributes
+    )
+}
+
+pub const CSV_HEADER: &str = "time,type,person,vehicle,link,mode\n";
+
+pub fn to_csv(event: &Event) -> String {
+    format!(
+        "{},{},{},{},{},{}\n",
+        event.seconds,
+        event.kind.matsim_type(),
+        event.trip,
+        event.trip,
+        event.link.map(|link| link.to_string()).unwrap_or_default(),
+        mode(event.vehicle)
+    )
+}
+
+struct EventWriter {
+    file: BufWriter<File>,
+    xml: bool,
+    /// Events logged since they were last written, by second and lane
+    logged: BTreeMap<(usize, Option<u32>), Vec<Event>>,
+}
+
+impl EventWriter {
+    fn write_logged(&mut self) {
+        let logged = mem::replace(&mut self.logged, BTreeMap::new());
+        for event in logged.values().flat_map(|events| events.iter()) {
+            let line = if self.xml { to_xml(event) } else { to_csv(event) };
+            if let Err(err) = self.file.write_all(line.as_bytes()) {
+                println!("Error writing event: {}", err);
+            }
+        }
+    }
+}
+
+static mut WRITER: *const Mutex<EventWriter> = 0 as *const Mutex<EventWriter>;
+
+fn writer() -> Option<&'static Mutex<EventWriter>> {
+    unsafe { if WRITER.is_null() { None } else { Some(&*WRITER) } }
+}
+
+/// Logs an event of `trip`, if there is an events file. It is written with `write_logged`
+pub fn log(
+    tick: Timestamp,
+    kind: EventKind,
+    trip: TripID,
+    link: Option<u32>,
+    vehicle: VehicleClass,
+) {
+    if let Some(writer) = writer() {
+        let event = Event {
+            seconds: tick.seconds_since_start_of_day_0(),
+            kind,
+            trip: trip._raw_id.instance_id,
+            link,
+            vehicle,
+        };
+        let mut writer = writer.lock().expect("events file shouldn't be poisoned");
+        writer
+            .logged
+            .entry((event.seconds, link))
+            .or_insert_with(Vec::new)
+            .push(event);
+    }
+}
+
+/// Writes all events logged so far, to be called on the main thread after each tick
+pub fn write_logged() {
+    if let Some(writer) = writer() {
+        writer
+            .lock()
+            .expect("events file shouldn't be poisoned")
+            .write_logged();
+    }
+}
+
+/// Opens the events file given in `CITYBOUND_EVENTS_FILE`, if any
+pub fn setup() {
+    if let Ok(path) = ::std::env::var("CITYBOUND_EVENTS_FILE") {
+        let xml = !path.ends_with(".csv");
+        let header = if xml {
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<events version=\"1.0\">\n"
+        } else {
+            CSV_HEADER
+        };
+        let result = File::create(&path).and_then(|file| {
+            let mut file = BufWriter::new(file);
+            file.write_all(header.as_bytes()).map(|_| file)
+        });
+        match result {
+            Ok(file) => {
+                println!("Logging events to {}", path);
+                let writer = Mutex::new(EventWriter { file, xml, logged: BTreeMap::new() });
+                unsafe { WRITER = Box::into_raw(Box::new(writer)) };
+            }
+            Err(err) => println!("Error creating events file {}: {}", path, err),
+        }
+    }
+}
+
+/// Closes the events file, to be called at the end of a run
+pub fn finish() {
+    if let Some(writer) = writer() {
+        let mut writer = writer.lock().expect("events file shouldn't be poisoned");
+        writer.write_logged();
+        let footer = if writer.xml { "</events>\n" } else { "" };
+        let written = writer.file.write_all(footer.as_bytes());
+        if let Err(err) = written.and_then(|_| writer.file.flush()) {
+            println!("Error finishing events file: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_have_the_attributes_matsim_expects() {
+        let event = |kind| {
+            Event { seconds: 25200, kind, trip: 7, link: Some(42), vehicle: VehicleClass::Car }
+        };
+        assert_eq!(
+            to_xml(&event(EventKind::Departure)),
+            "\t<event time=\"25200.0\" type=\"departure\" person=\"7\" link=\"42\" \
+             legMode=\"car\" />\n"
+        );
+        assert_eq!(
+            to_xml(&event(EventKind::LeftLink)),
+            "\t<event time=\"25200.0\" type=\"left link\" vehicle=\"7\" link=\"42\" />\n"
+        );
+        assert!(to_xml(&event(EventKind::VehicleEntersTraffic)).contains("networkMode=\"car\""));
+        assert_eq!(to_csv(&event(EventKind::Arrival)), "25200,arrival,7,7,42,car\n");
+    }
+}
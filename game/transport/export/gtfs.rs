@@ -0,0 +1,190 @@
+//! Exports the transit of the city as a GTFS feed, for comparing it with real systems.
+//!
+//! Ferry lines are the only transit with lines and schedules so far: each line becomes
+//! a route between its two docks, with the trips its single ferry makes in a day, as if
+//! it left its first dock at midnight. Park-and-ride stations are exported as stops,
+//! but the generic transit continuing from them has no lines to export yet.
+use compact::CVec;
+use descartes::P2;
+use core::geo::GeoReference;
+
+const AGENCY_ID: &str = "city";
+const SERVICE_ID: &str = "daily";
+/// GTFS route type of ferries
+const FERRY_ROUTE_TYPE: u8 = 4;
+const SECONDS_PER_DAY: f32 = 24.0 * 60.0 * 60.0;
+
+#[derive(Compact, Clone)]
+pub struct ExportedFerryLine {
+    pub from_dock: u32,
+    pub to_dock: u32,
+    pub crossing_seconds: f32,
+    /// How long the ferry waits at each dock before crossing again
+    pub wait_seconds: f32,
+}
+
+/// The transit to export, see the module documentation
+#[derive(Compact, Clone)]
+pub struct ExportedTransit {
+    pub docks: CVec<P2>,
+    pub ferry_lines: CVec<ExportedFerryLine>,
+    /// Raw IDs and positions of park-and-ride stations
+    pub stations: CVec<(u32, P2)>,
+}
+
+impl ExportedTransit {
+    pub fn new() -> ExportedTransit {
+        ExportedTransit {
+            docks: CVec::new(),
+            ferry_lines: CVec::new(),
+            stations: CVec::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.docks.is_empty() && self.stations.is_empty()
+    }
+}
+
+fn gtfs_time(seconds: f32) -> String {
+    let seconds = seconds.round() as u32;
+    format!("{:02}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+fn stop_line(stop_id: &str, name: &str, position: P2, geo_reference: &GeoReference) -> String {
+    let (lat, lon) = geo_reference.to_geo(position);
+    format!("{},{},{:.7},{:.7}\n", stop_id, name, lat, lon)
+}
+
+/// The files of the feed as (file name, contents)
+pub fn to_gtfs(transit: &ExportedTransit, geo_reference: &GeoReference) -> Vec<(String, String)> {
+    let agency = format!(
+        "agency_id,agency_name,agency_url,agency_timezone\n\
+         {},{},https://github.com/citybound/citybound,Etc/UTC\n",
+        AGENCY_ID,
+        ::ENV.name
+    );
+
+    // the simulation has no calendar, so service runs every day
+    let calendar = format!(
+        "service_id,monday,tuesday,wednesday,thursday,friday,saturday,sunday,start_date,\
+         end_date\n{},1,1,1,1,1,1,1,20170101,20991231\n",
+        SERVICE_ID
+    );
+
+    let mut stops = String::from("stop_id,stop_name,stop_lat,stop_lon\n");
+    for (i, &dock) in transit.docks.iter().enumerate() {
+        stops.push_str(&stop_line(
+            &format!("dock_{}", i),
+            &format!("Ferry Dock {}", i + 1),
+            dock,
+            geo_reference,
+        ));
+    }
+    for &(raw_id, position) in transit.stations.iter() {
+        stops.push_str(&stop_line(
+            &format!("park_and_ride_{}", raw_id),
+            &format!("Park & Ride {}", raw_id),
+            position,
+            geo_reference,
+        ));
+    }
+
+    let mut routes =
+        String::from("route_id,agency_id,route_short_name,route_long_name,route_type\n");
+    let mut trips = String::from("route_id,service_id,trip_id,direction_id\n");
+    let mut stop_times =
+        String::from("trip_id,arrival_time,departure_time,stop_id,stop_sequence\n");
+
+    for (i, line) in transit.ferry_lines.iter().enumerate() {
+        let route_id = format!("ferry_{}", i);
+        routes.push_str(&format!(
+            "{},{},F{},Dock {} - Dock {},{}\n",
+            route_id,
+            AGENCY_ID,
+            i + 1,
+            line.from_dock + 1,
+            line.to_dock + 1,
+            FERRY_ROUTE_TYPE
+        ));
+
+        // the ferry crosses, waits, crosses back and waits again
+        let leg_seconds = line.crossing_seconds + line.wait_seconds;
+        let mut departure = 0.0;
+        let mut n_trip = 0;
+        while departure < SECONDS_PER_DAY {
+            let direction = n_trip % 2;
+            let (from_dock, to_dock) = if direction == 0 {
+                (line.from_dock, line.to_dock)
+            } else {
+                (line.to_dock, line.from_dock)
+            };
+            let trip_id = format!("{}_{}", route_id, n_trip);
+            trips.push_str(&format!("{},{},{},{}\n", route_id, SERVICE_ID, trip_id, direction));
+            let arrival = departure + line.crossing_seconds;
+            stop_times.push_str(&format!(
+                "{},{},{},dock_{},1\n{},{},{},dock_{},2\n",
+                trip_id,
+                gtfs_time(departure),
+                gtfs_time(departure),
+                from_dock,
+                trip_id,
+                gtfs_time(arrival),
+                gtfs_time(arrival),
+                to_dock
+            ));
+            departure += leg_seconds.max(1.0);
+            n_trip += 1;
+        }
+    }
+
+    vec![
+        ("agency.txt".to_owned(), agency),
+        ("calendar.txt".to_owned(), calendar),
+        ("stops.txt".to_owned(), stops),
+        ("routes.txt".to_owned(), routes),
+        ("trips.txt".to_owned(), trips),
+        ("stop_times.txt".to_owned(), stop_times),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file<'a>(feed: &'a [(String, String)], name: &str) -> &'a str {
+        &feed.iter().find(|&&(ref file_name, _)| file_name == name).unwrap().1
+    }
+
+    #[test]
+    fn ferry_line_shuttles_between_its_docks_all_day() {
+        let transit = ExportedTransit {
+            docks: vec![P2::new(0.0, 0.0), P2::new(600.0, 0.0)].into(),
+            ferry_lines: vec![
+                ExportedFerryLine {
+                    from_dock: 0,
+                    to_dock: 1,
+                    crossing_seconds: 100.0,
+                    wait_seconds: 500.0,
+                },
+            ].into(),
+            stations: vec![(7, P2::new(50.0, 50.0))].into(),
+        };
+        let feed = to_gtfs(&transit, &GeoReference::default());
+
+        let stops = file(&feed, "stops.txt");
+        assert!(stops.contains("dock_1,Ferry Dock 2,"));
+        assert!(stops.contains("park_and_ride_7,Park & Ride 7,"));
+        assert!(file(&feed, "routes.txt").contains("ferry_0,city,F1,Dock 1 - Dock 2,4\n"));
+
+        // a crossing every 10 minutes, alternating directions
+        let trips = file(&feed, "trips.txt");
+        assert_eq!(trips.lines().count(), 1 + 144);
+        assert!(trips.contains("ferry_0,daily,ferry_0_1,1\n"));
+
+        let stop_times = file(&feed, "stop_times.txt");
+        assert!(stop_times.contains("ferry_0_1,00:10:00,00:10:00,dock_1,1\n"));
+        assert!(stop_times.contains("ferry_0_1,00:11:40,00:11:40,dock_0,2\n"));
+        assert!(stop_times.contains("ferry_0_143,23:50:00,23:50:00,dock_1,1\n"));
+    }
+}
@@ -0,0 +1,689 @@
+use kay::{ActorSystem, World};
+use compact::CVec;
+use descartes::{N, P2, V2, Norm, Dot, FiniteCurve, WithUniqueOrthogonal, angle_to};
+use stagemaster::combo::Combo2;
+use stagemaster::combo::Button::{LControl, E};
+use stagemaster::actions::{register_action, ActionId, ActionListener, ActionListenerID,
+                           ActionPhase, MSG_ActionListener_on_action};
+use stagemaster::geometry::CPath;
+use core::simulation::{SimulationID, Sleeper, SleeperID, MSG_Sleeper_wake, Timestamp, Ticks};
+use core::geo::GeoReference;
+use core::disjoint_sets::DisjointSets;
+use terrain::TerrainSettings;
+use economy::households::park_and_ride::ParkAndRideID;
+use super::ferries::FerryNetworkID;
+use super::lane::{Lane, TransferLane};
+use super::lane::{LaneID, TransferLaneID};
+use super::lane::connectivity::{InteractionKind, OverlapKind};
+use super::restrictions::VehicleClass;
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, create_dir_all};
+use std::path::Path;
+use std::io::Write;
+
+pub mod gtfs;
+use self::gtfs::{ExportedTransit, ExportedFerryLine};
+
+const EXPORT_DIR: &str = "exports";
+const EXPORT_SAMPLE_DISTANCE: N = 5.0;
+
+#[derive(Compact, Clone)]
+pub struct ExportedLane {
+    pub raw_id: u32,
+    pub is_transfer: bool,
+    pub on_intersection: bool,
+    pub length: N,
+    pub points: CVec<P2>,
+    pub n_cars: u32,
+    pub mean_speed: N,
+    pub max_speed: N,
+    /// Infinite if the lane has no road class
+    pub speed_limit: N,
+    pub next_lanes: CVec<u32>,
+    pub conflicting_lanes: CVec<u32>,
+    /// Whether the lane is green, for each 10 ticks of its signal cycle
+    pub timings: CVec<bool>,
+}
+
+fn sample_path(path: &CPath) -> CVec<P2> {
+    let length = path.length();
+    let n_samples = (length / EXPORT_SAMPLE_DISTANCE).ceil().max(1.0) as usize;
+    (0..(n_samples + 1))
+        .map(|i| path.along(length * i as N / n_samples as N))
+        .collect()
+}
+
+impl Lane {
+    pub fn report_for_export(&mut self, exporter: NetworkExporterID, world: &mut World) {
+        let n_cars = self.microtraffic.cars.len();
+        let mean_speed = if n_cars == 0 {
+            0.0
+        } else {
+            self.microtraffic.cars.iter().map(|car| car.velocity).sum::<N>() / n_cars as N
+        };
+        let max_speed = self.microtraffic
+            .cars
+            .iter()
+            .map(|car| car.max_velocity)
+            .fold(0.0, N::max);
+        let mut next_lanes = CVec::new();
+        let mut conflicting_lanes = CVec::new();
+        for interaction in self.connectivity.interactions.iter() {
+            let partner = interaction.partner_lane._raw_id.instance_id;
+            match interaction.kind {
+                InteractionKind::Next { .. } => next_lanes.push(partner),
+                InteractionKind::Overlap { kind: OverlapKind::Conflicting, .. } => {
+                    conflicting_lanes.push(partner)
+                }
+                _ => {}
+            }
+        }
+
+        exporter.add_lane(
+            ExportedLane {
+                raw_id: self.id._raw_id.instance_id,
+                is_transfer: false,
+                on_intersection: self.connectivity.on_intersection,
+                length: self.construction.length,
+                points: sample_path(&self.construction.path),
+                n_cars: n_cars as u32,
+                mean_speed,
+                max_speed,
+                speed_limit: self.road_class.speed_limit(VehicleClass::Car),
+                next_lanes,
+                conflicting_lanes,
+                timings: self.microtraffic.timings().clone(),
+            },
+            world,
+        );
+    }
+}
+
+impl TransferLane {
+    pub fn report_for_export(&mut self, exporter: NetworkExporterID, world: &mut World) {
+        let n_cars = self.microtraffic.cars.len();
+        let mean_speed = if n_cars == 0 {
+            0.0
+        } else {
+            self.microtraffic.cars.iter().map(|car| car.velocity).sum::<N>() / n_cars as N
+        };
+
+        exporter.add_lane(
+            ExportedLane {
+                raw_id: self.id._raw_id.instance_id,
+                is_transfer: true,
+                on_intersection: false,
+                length: self.construction.length,
+                points: sample_path(&self.construction.path),
+                n_cars: n_cars as u32,
+                mean_speed,
+                max_speed: 0.0,
+                speed_limit: ::std::f32::INFINITY,
+                next_lanes: CVec::new(),
+                conflicting_lanes: CVec::new(),
+                timings: CVec::new(),
+            },
+            world,
+        );
+    }
+}
+
+#[derive(Compact, Clone)]
+pub struct NetworkExporter {
+    id: NetworkExporterID,
+    simulation: SimulationID,
+    collecting: Option<CVec<ExportedLane>>,
+    collecting_transit: Option<ExportedTransit>,
+}
+
+impl NetworkExporter {
+    pub fn init(
+        id: NetworkExporterID,
+        simulation: SimulationID,
+        world: &mut World,
+    ) -> NetworkExporter {
+        register_action("Export Network", Combo2::new(&[LControl, E], &[]), id.into(), world);
+
+        NetworkExporter {
+            id,
+            simulation,
+            collecting: None,
+            collecting_transit: None,
+        }
+    }
+
+    pub fn add_lane(&mut self, lane: &ExportedLane, _: &mut World) {
+        if let Some(ref mut lanes) = self.collecting {
+            lanes.push(lane.clone());
+        } else {
+            println!("Unexpected exported lane");
+        }
+    }
+
+    pub fn add_ferry_lines(
+        &mut self,
+        docks: &CVec<P2>,
+        lines: &CVec<ExportedFerryLine>,
+        _: &mut World,
+    ) {
+        if let Some(ref mut transit) = self.collecting_transit {
+            // dock indices of each ferry network start at zero
+            let offset = transit.docks.len() as u32;
+            transit.docks.extend(docks.iter().cloned());
+            transit.ferry_lines.extend(lines.iter().map(|line| {
+                ExportedFerryLine {
+                    from_dock: line.from_dock + offset,
+                    to_dock: line.to_dock + offset,
+                    ..line.clone()
+                }
+            }));
+        } else {
+            println!("Unexpected exported ferry lines");
+        }
+    }
+
+    pub fn add_station(&mut self, raw_id: u32, position: P2, _: &mut World) {
+        if let Some(ref mut transit) = self.collecting_transit {
+            transit.stations.push((raw_id, position));
+        } else {
+            println!("Unexpected exported station");
+        }
+    }
+}
+
+impl ActionListener for NetworkExporter {
+    fn on_action(&mut self, action: ActionId, phase: ActionPhase, world: &mut World) {
+        if phase == ActionPhase::Started && action == ActionId::named("Export Network") &&
+            self.collecting.is_none()
+        {
+            LaneID::global_broadcast(world).report_for_export(self.id, world);
+            TransferLaneID::global_broadcast(world).report_for_export(self.id, world);
+            FerryNetworkID::global_broadcast(world).report_for_export(self.id, world);
+            ParkAndRideID::global_broadcast(world).report_for_export(self.id, world);
+            self.simulation.wake_up_in(Ticks(10), self.id.into(), world);
+            self.collecting = Some(CVec::new());
+            self.collecting_transit = Some(ExportedTransit::new());
+        }
+    }
+}
+
+/// Writes `contents` to `path`, creating its directory, `what` says what was exported
+fn write_export(path: &str, contents: &str, what: &str) {
+    let result = create_dir_all(Path::new(path).parent().unwrap_or_else(|| Path::new(".")))
+        .and_then(|_| File::create(path))
+        .and_then(|mut file| file.write_all(contents.as_bytes()));
+
+    match result {
+        Ok(()) => println!("Exported {} to {}", what, path),
+        Err(err) => println!("Error exporting network to {}: {}", path, err),
+    }
+}
+
+impl Sleeper for NetworkExporter {
+    fn wake(&mut self, current_tick: Timestamp, _: &mut World) {
+        if let Some(lanes) = self.collecting.take() {
+            let geo_reference = ::ENV
+                .load_settings::<TerrainSettings>("Terrain")
+                .geo_reference;
+            let path = format!("{}/network_{}", EXPORT_DIR, current_tick.ticks());
+
+            let n_lanes = format!("{} lanes", lanes.len());
+
+            write_export(
+                &format!("{}.geojson", path),
+                &to_geojson(&lanes, &geo_reference),
+                &n_lanes,
+            );
+            write_export(&format!("{}.net.xml", path), &to_sumo_net(&lanes), &n_lanes);
+
+            if let Some(transit) = self.collecting_transit.take() {
+                if !transit.is_empty() {
+                    let feed_dir = format!("{}/gtfs_{}", EXPORT_DIR, current_tick.ticks());
+                    let what = format!(
+                        "{} ferry lines and {} park-and-ride stations",
+                        transit.ferry_lines.len(),
+                        transit.stations.len()
+                    );
+                    for (file_name, contents) in gtfs::to_gtfs(&transit, &geo_reference) {
+                        write_export(&format!("{}/{}", feed_dir, file_name), &contents, &what);
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub fn to_geojson(lanes: &[ExportedLane], geo_reference: &GeoReference) -> String {
+    let features = lanes
+        .iter()
+        .map(|lane| {
+            let coordinates = lane.points
+                .iter()
+                .map(|point| {
+                    let (lat, lon) = geo_reference.to_geo(*point);
+                    format!("[{:.7},{:.7}]", lon, lat)
+                })
+                .collect::<Vec<_>>()
+                .join(",");
+            let density = if lane.length > 0.0 {
+                lane.n_cars as N / lane.length * 1000.0
+            } else {
+                0.0
+            };
+            format!(
+                "{{\"type\":\"Feature\",\
+                 \"geometry\":{{\"type\":\"LineString\",\"coordinates\":[{}]}},\
+                 \"properties\":{{\"id\":{},\"kind\":\"{}\",\"on_intersection\":{},\
+                 \"length\":{:.2},\"cars\":{},\"density_per_km\":{:.2},\
+                 \"mean_speed\":{:.2},\"max_speed\":{:.2}}}}}",
+                coordinates,
+                lane.raw_id,
+                if lane.is_transfer { "transfer" } else { "lane" },
+                lane.on_intersection,
+                lane.length,
+                lane.n_cars,
+                density,
+                lane.mean_speed,
+                lane.max_speed
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(",\n");
+
+    format!(
+        "{{\"type\":\"FeatureCollection\",\"features\":[\n{}\n]}}\n",
+        features
+    )
+}
+
+/// Speed for lanes without a road class, SUMO's default for urban roads
+const SUMO_DEFAULT_SPEED: N = 13.89;
+/// Ends of lanes closer than this meet at the same junction
+const SUMO_JUNCTION_DISTANCE: N = 1.0;
+/// How long each entry of a lane's signal timings lasts
+const SIGNAL_SLOT_SECONDS: usize = 10;
+
+#[derive(Copy, Clone)]
+struct LaneEnd {
+    lane: u32,
+    is_end: bool,
+    position: P2,
+}
+
+/// A movement from one lane onto the next, usually along an intersection lane
+struct SumoLink {
+    from: u32,
+    via: Option<u32>,
+    to: u32,
+}
+
+fn sumo_shape(points: &[P2]) -> String {
+    points
+        .iter()
+        .map(|point| format!("{:.2},{:.2}", point.x, point.y))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn sumo_speed(lane: &ExportedLane) -> N {
+    if lane.speed_limit.is_finite() {
+        lane.speed_limit
+    } else {
+        SUMO_DEFAULT_SPEED
+    }
+}
+
+fn sumo_direction(from: &ExportedLane, to: &ExportedLane) -> &'static str {
+    let n_points = from.points.len();
+    let direction_in: V2 = from.points[n_points - 1] - from.points[n_points - 2];
+    let direction_out: V2 = to.points[1] - to.points[0];
+    let angle = angle_to(direction_in, direction_out);
+
+    if angle > 150.0f32.to_radians() {
+        "t"
+    } else if angle < 30.0f32.to_radians() {
+        "s"
+    } else if direction_out.dot(&direction_in.orthogonal()) > 0.0 {
+        "r"
+    } else {
+        "l"
+    }
+}
+
+/// Converts the lanes into a SUMO network (`.net.xml`), to run the same network in SUMO
+/// and compare its traffic with Citybound's. Each lane becomes an edge with a single lane,
+/// each intersection lane an internal lane of the junction it crosses and signal timings
+/// become static signal programs. Transfer lanes aren't exported, so cars can't change
+/// lanes in SUMO. Citybound lets whoever reaches a conflict first go, while SUMO needs
+/// fixed priorities, so each movement yields to the conflicting ones listed before it.
+pub fn to_sumo_net(lanes: &[ExportedLane]) -> String {
+    let by_id = lanes
+        .iter()
+        .filter(|lane| !lane.is_transfer)
+        .map(|lane| (lane.raw_id, lane))
+        .collect::<HashMap<_, _>>();
+    let is_road = |id: &u32| by_id.get(id).map(|lane| !lane.on_intersection).unwrap_or(false);
+    let roads = lanes
+        .iter()
+        .filter(|lane| !lane.is_transfer && !lane.on_intersection)
+        .collect::<Vec<_>>();
+
+    let mut links = Vec::new();
+    for road in &roads {
+        for next_id in road.next_lanes.iter() {
+            match by_id.get(next_id) {
+                Some(next) if next.on_intersection => {
+                    for after_id in next.next_lanes.iter().filter(|&after_id| is_road(after_id)) {
+                        links.push(SumoLink {
+                            from: road.raw_id,
+                            via: Some(next.raw_id),
+                            to: *after_id,
+                        });
+                    }
+                }
+                Some(_) => links.push(SumoLink { from: road.raw_id, via: None, to: *next_id }),
+                None => {}
+            }
+        }
+    }
+
+    // lane ends meet at a junction if they're close or connected by intersection lanes
+    let linked = links
+        .iter()
+        .map(|link| (link.from, link.to))
+        .collect::<HashSet<_>>();
+    let mut lane_ends = DisjointSets::from_individuals(
+        roads
+            .iter()
+            .flat_map(|road| {
+                vec![
+                    LaneEnd { lane: road.raw_id, is_end: false, position: road.points[0] },
+                    LaneEnd {
+                        lane: road.raw_id,
+                        is_end: true,
+                        position: road.points[road.points.len() - 1],
+                    },
+                ]
+            })
+            .collect(),
+    );
+    lane_ends.union_all_with(|a, b| {
+        (a.position - b.position).norm() < SUMO_JUNCTION_DISTANCE ||
+            (a.is_end && !b.is_end && linked.contains(&(a.lane, b.lane))) ||
+            (b.is_end && !a.is_end && linked.contains(&(b.lane, a.lane)))
+    });
+    let junctions = lane_ends.sets().map(|set| set.to_vec()).collect::<Vec<_>>();
+    let mut junction_of = HashMap::new();
+    for (idx, junction) in junctions.iter().enumerate() {
+        for lane_end in junction {
+            junction_of.insert((lane_end.lane, lane_end.is_end), idx);
+        }
+    }
+
+    let mut edges = String::new();
+    for road in &roads {
+        edges.push_str(&format!(
+            "    <edge id=\"l{}\" from=\"j{}\" to=\"j{}\" priority=\"-1\">\n        \
+             <lane id=\"l{}_0\" index=\"0\" speed=\"{:.2}\" length=\"{:.2}\" \
+             shape=\"{}\"/>\n    </edge>\n",
+            road.raw_id,
+            junction_of[&(road.raw_id, false)],
+            junction_of[&(road.raw_id, true)],
+            road.raw_id,
+            sumo_speed(road),
+            road.length,
+            sumo_shape(&road.points)
+        ));
+    }
+
+    let mut internal_edges = String::new();
+    let mut signal_programs = String::new();
+    let mut junction_elements = String::new();
+    let mut connections = String::new();
+
+    for (idx, junction) in junctions.iter().enumerate() {
+        // in the order of the incoming lanes, like SUMO numbers them
+        let junction_links = links
+            .iter()
+            .filter(|link| junction_of[&(link.from, true)] == idx)
+            .collect::<Vec<_>>();
+        let n_links = junction_links.len();
+        let timings = |link: &SumoLink| link.via.map(|via| &by_id[&via].timings);
+        let signalized = junction_links.iter().any(|&link| {
+            timings(link)
+                .map(|timings| timings.iter().any(|&green| !green))
+                .unwrap_or(false)
+        });
+        let foes = |i: usize, j: usize| match (junction_links[i].via, junction_links[j].via) {
+            (Some(via_i), Some(via_j)) => {
+                i != j &&
+                    (by_id[&via_i].conflicting_lanes.iter().any(|&lane| lane == via_j) ||
+                         by_id[&via_j].conflicting_lanes.iter().any(|&lane| lane == via_i))
+            }
+            _ => false,
+        };
+        // SUMO lists the last link first
+        let bits = |is_set: &Fn(usize) -> bool| {
+            (0..n_links)
+                .rev()
+                .map(|j| if is_set(j) { '1' } else { '0' })
+                .collect::<String>()
+        };
+
+        let mut requests = String::new();
+        let mut internal_lanes = Vec::new();
+        for (i, link) in junction_links.iter().enumerate() {
+            let yields = (0..i).any(|j| foes(i, j));
+            requests.push_str(&format!(
+                "        <request index=\"{}\" response=\"{}\" foes=\"{}\" cont=\"0\"/>\n",
+                i,
+                bits(&|j| j < i && foes(i, j)),
+                bits(&|j| foes(i, j))
+            ));
+
+            let state = if signalized {
+                "O"
+            } else if yields {
+                "m"
+            } else {
+                "M"
+            };
+            let direction = sumo_direction(by_id[&link.from], by_id[&link.to]);
+            let tl = if signalized {
+                format!(" tl=\"j{}\" linkIndex=\"{}\"", idx, i)
+            } else {
+                String::new()
+            };
+
+            if let Some(via) = link.via.map(|via| by_id[&via]) {
+                let internal = format!(":j{}_{}", idx, i);
+                internal_edges.push_str(&format!(
+                    "    <edge id=\"{}\" function=\"internal\">\n        \
+                     <lane id=\"{}_0\" index=\"0\" speed=\"{:.2}\" length=\"{:.2}\" \
+                     shape=\"{}\"/>\n    </edge>\n",
+                    internal,
+                    internal,
+                    sumo_speed(via),
+                    via.length,
+                    sumo_shape(&via.points)
+                ));
+                connections.push_str(&format!(
+                    "    <connection from=\"l{}\" to=\"l{}\" fromLane=\"0\" toLane=\"0\" \
+                     via=\"{}_0\"{} dir=\"{}\" state=\"{}\"/>\n",
+                    link.from,
+                    link.to,
+                    internal,
+                    tl,
+                    direction,
+                    state
+                ));
+                connections.push_str(&format!(
+                    "    <connection from=\"{}\" to=\"l{}\" fromLane=\"0\" toLane=\"0\" \
+                     dir=\"{}\" state=\"M\"/>\n",
+                    internal,
+                    link.to,
+                    direction
+                ));
+                internal_lanes.push(format!("{}_0", internal));
+            } else {
+                connections.push_str(&format!(
+                    "    <connection from=\"l{}\" to=\"l{}\" fromLane=\"0\" toLane=\"0\"{} \
+                     dir=\"{}\" state=\"{}\"/>\n",
+                    link.from,
+                    link.to,
+                    tl,
+                    direction,
+                    state
+                ));
+            }
+        }
+
+        if signalized {
+            let cycle = junction_links
+                .iter()
+                .filter_map(|&link| timings(link).map(|timings| timings.len()))
+                .max()
+                .unwrap_or(1)
+                .max(1);
+            let mut phases: Vec<(usize, String)> = Vec::new();
+            for slot in 0..cycle {
+                let state = junction_links
+                    .iter()
+                    .map(|&link| {
+                        let green = timings(link)
+                            .map(|timings| timings.is_empty() || timings[slot % timings.len()])
+                            .unwrap_or(true);
+                        if green { 'G' } else { 'r' }
+                    })
+                    .collect::<String>();
+                if phases.last().map(|&(_, ref last)| *last == state).unwrap_or(false) {
+                    phases.last_mut().expect("just checked").0 += SIGNAL_SLOT_SECONDS;
+                } else {
+                    phases.push((SIGNAL_SLOT_SECONDS, state));
+                }
+            }
+
+            signal_programs.push_str(&format!(
+                "    <tlLogic id=\"j{}\" type=\"static\" programID=\"0\" offset=\"0\">\n",
+                idx
+            ));
+            for (duration, state) in phases {
+                signal_programs.push_str(&format!(
+                    "        <phase duration=\"{}\" state=\"{}\"/>\n",
+                    duration,
+                    state
+                ));
+            }
+            signal_programs.push_str("    </tlLogic>\n");
+        }
+
+        let kind = if signalized {
+            "traffic_light"
+        } else if n_links == 0 {
+            "dead_end"
+        } else {
+            "priority"
+        };
+        let position = junction
+            .iter()
+            .fold(V2::new(0.0, 0.0), |sum, lane_end| sum + lane_end.position.to_vector()) /
+            junction.len() as N;
+        let incoming_lanes = roads
+            .iter()
+            .filter(|road| junction_of[&(road.raw_id, true)] == idx)
+            .map(|road| format!("l{}_0", road.raw_id))
+            .collect::<Vec<_>>();
+        junction_elements.push_str(&format!(
+            "    <junction id=\"j{}\" type=\"{}\" x=\"{:.2}\" y=\"{:.2}\" incLanes=\"{}\" \
+             intLanes=\"{}\">\n{}    </junction>\n",
+            idx,
+            kind,
+            position.x,
+            position.y,
+            incoming_lanes.join(" "),
+            internal_lanes.join(" "),
+            requests
+        ));
+    }
+
+    let (min, max) = roads
+        .iter()
+        .flat_map(|road| road.points.iter())
+        .fold(None, |bounds: Option<(P2, P2)>, point| match bounds {
+            Some((min, max)) => Some((
+                P2::new(min.x.min(point.x), min.y.min(point.y)),
+                P2::new(max.x.max(point.x), max.y.max(point.y)),
+            )),
+            None => Some((*point, *point)),
+        })
+        .unwrap_or((P2::new(0.0, 0.0), P2::new(0.0, 0.0)));
+    let boundary = format!("{:.2},{:.2},{:.2},{:.2}", min.x, min.y, max.x, max.y);
+
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<net version=\"1.9\">\n    \
+         <location netOffset=\"0.00,0.00\" convBoundary=\"{}\" origBoundary=\"{}\" \
+         projParameter=\"!\"/>\n{}{}{}{}{}</net>\n",
+        boundary,
+        boundary,
+        internal_edges,
+        edges,
+        signal_programs,
+        junction_elements,
+        connections
+    )
+}
+
+pub fn setup(system: &mut ActorSystem, simulation: SimulationID) {
+    system.register::<NetworkExporter>();
+    auto_setup(system);
+
+    NetworkExporterID::init(simulation, &mut system.world());
+}
+
+mod kay_auto;
+pub use self::kay_auto::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A straight lane, on an intersection if it has signal timings
+    fn lane(raw_id: u32, from: (N, N), to: (N, N), next: &[u32], timings: &[bool]) -> ExportedLane {
+        ExportedLane {
+            raw_id,
+            is_transfer: false,
+            on_intersection: !timings.is_empty(),
+            length: (P2::new(to.0, to.1) - P2::new(from.0, from.1)).norm(),
+            points: vec![P2::new(from.0, from.1), P2::new(to.0, to.1)].into(),
+            n_cars: 0,
+            mean_speed: 0.0,
+            max_speed: 0.0,
+            speed_limit: 11.0,
+            next_lanes: next.to_vec().into(),
+            conflicting_lanes: CVec::new(),
+            timings: timings.to_vec().into(),
+        }
+    }
+
+    #[test]
+    fn signalized_intersection_becomes_traffic_light_junction() {
+        let lanes = vec![
+            lane(1, (0.0, 0.0), (100.0, 0.0), &[2], &[]),
+            lane(2, (100.0, 0.0), (120.0, 0.0), &[3], &[true, true, false, false, false]),
+            lane(3, (120.0, 0.0), (200.0, 0.0), &[], &[]),
+        ];
+        let net = to_sumo_net(&lanes);
+
+        assert!(net.contains("<edge id=\"l1\" from=\"j0\" to=\"j1\""));
+        assert!(net.contains("<edge id=\"l3\" from=\"j1\" to=\"j2\""));
+        assert!(!net.contains("<edge id=\"l2\""));
+        assert!(net.contains("<edge id=\":j1_0\" function=\"internal\">"));
+        assert!(net.contains("<junction id=\"j1\" type=\"traffic_light\""));
+        assert!(net.contains("<phase duration=\"20\" state=\"G\"/>"));
+        assert!(net.contains("<phase duration=\"30\" state=\"r\"/>"));
+        assert!(net.contains(
+            "<connection from=\"l1\" to=\"l3\" fromLane=\"0\" toLane=\"0\" via=\":j1_0_0\" \
+             tl=\"j1\" linkIndex=\"0\" dir=\"s\" state=\"O\"/>"
+        ));
+    }
+}
@@ -0,0 +1,494 @@
+//! Ferries: walk-on transit across water. Docks are placed on the shore with the
+//! "Place Ferry Dock" action and lines connect two docks each. Every line becomes an edge
+//! of the `ActiveModeGraph`, so walking and cycling trips take the ferry where that is
+//! faster, waiting for half a headway at the dock.
+//!
+//! One ferry shuttles back and forth on each line and a few decorative boats cruise around
+//! on open water (found by the `Terrain`), neither of them interacts with other traffic.
+use kay::{ActorSystem, World, External};
+use compact::{CVec, CDict};
+use descartes::{N, P2, Norm, Band, Segment, FiniteCurve};
+use monet::{RendererID, Renderable, RenderableID, MSG_Renderable_setup_in_scene,
+            MSG_Renderable_render_to_scene, Geometry, Vertex, Instance};
+use stagemaster::{UserInterfaceID, Event3d, Interactable3d, Interactable3dID,
+                  MSG_Interactable3d_on_event, Interactable2d, Interactable2dID,
+                  MSG_Interactable2d_draw_ui_2d};
+use stagemaster::combo::Combo2;
+use stagemaster::combo::Button::{LControl, Q};
+use stagemaster::actions::{register_action, ActionId, ActionListener, ActionListenerID,
+                           ActionPhase, MSG_ActionListener_on_action};
+use stagemaster::geometry::{CPath, band_to_geometry};
+use transport::planning::current_plan::set_tool_active;
+use imgui::ImGuiSetCond_FirstUseEver;
+use core::simulation::{Simulatable, SimulatableID, MSG_Simulatable_tick, Timestamp,
+                       SimulationConfig};
+use core::units::MetersPerSecond;
+use terrain::TerrainID;
+use super::pathfinding::active_modes::{ActiveModeGraphID, FerryCrossing};
+use super::export::NetworkExporterID;
+use super::export::gtfs::ExportedFerryLine;
+
+const SETTINGS_CATEGORY: &'static str = "Ferries";
+const FERRY_VELOCITY: MetersPerSecond = MetersPerSecond(6.0);
+const CRUISING_VELOCITY: MetersPerSecond = MetersPerSecond(3.0);
+const DOCK_BATCH_ID: u16 = 8110;
+const FERRY_BATCH_ID: u16 = 8111;
+const BOAT_BATCH_ID: u16 = 8112;
+const LINES_INDIVIDUAL_ID: u16 = 8113;
+
+#[derive(Serialize, Deserialize, Copy, Clone)]
+pub struct FerrySettings {
+    /// Headway of newly created lines
+    pub headway_minutes: f32,
+    pub n_cruising_boats: usize,
+}
+
+impl Default for FerrySettings {
+    fn default() -> Self {
+        FerrySettings {
+            headway_minutes: 20.0,
+            n_cruising_boats: 8,
+        }
+    }
+}
+
+/// Where a boat is on its back-and-forth route
+#[derive(Copy, Clone, Default)]
+struct Shuttle {
+    /// Distance from the start of the route
+    position: N,
+    heading_back: bool,
+    /// Seconds left to wait at the current end of the route
+    waiting_for: f32,
+}
+
+impl Shuttle {
+    /// Moves `distance` along a route of `length`, turning around at its ends
+    /// and waiting there for `wait` seconds
+    fn advance(&mut self, distance: N, length: N, wait: f32, dt: f32) {
+        if self.waiting_for > 0.0 {
+            self.waiting_for -= dt;
+            return;
+        }
+        if self.heading_back {
+            self.position -= distance;
+            if self.position <= 0.0 {
+                self.position = 0.0;
+                self.heading_back = false;
+                self.waiting_for = wait;
+            }
+        } else {
+            self.position += distance;
+            if self.position >= length {
+                self.position = length;
+                self.heading_back = true;
+                self.waiting_for = wait;
+            }
+        }
+    }
+
+    fn instance(&self, path: &CPath, color: [f32; 3]) -> Instance {
+        let position = path.along(self.position);
+        let direction = if self.heading_back {
+            -path.direction_along(self.position)
+        } else {
+            path.direction_along(self.position)
+        };
+        Instance {
+            instance_position: [position.x, position.y, 0.0],
+            instance_direction: [direction.x, direction.y],
+            instance_color: color,
+        }
+    }
+}
+
+#[derive(Compact, Clone)]
+pub struct FerryLine {
+    from_dock: usize,
+    to_dock: usize,
+    path: CPath,
+    headway_minutes: f32,
+    ferry: Shuttle,
+}
+
+impl FerryLine {
+    fn crossing_seconds(&self) -> f32 {
+        self.path.length() / FERRY_VELOCITY.0
+    }
+
+    /// With a single ferry, it waits at each dock for what is left of the headway
+    fn wait_seconds(&self) -> f32 {
+        (self.headway_minutes * 60.0 / 2.0 - self.crossing_seconds()).max(0.0)
+    }
+
+    /// A ferry leaves at least every `headway_minutes`, or as soon as it is back
+    fn crossing(&self, from: P2, to: P2) -> FerryCrossing {
+        let round_trip_minutes = (2.0 * self.crossing_seconds() / 60.0).max(self.headway_minutes);
+        FerryCrossing {
+            from,
+            to,
+            minutes: round_trip_minutes / 2.0 + self.crossing_seconds() / 60.0,
+        }
+    }
+}
+
+#[derive(Compact, Clone)]
+struct Cruise {
+    route: CPath,
+    boat: Shuttle,
+}
+
+#[derive(Compact, Clone)]
+pub struct FerryNetwork {
+    id: FerryNetworkID,
+    user_interface: UserInterfaceID,
+    settings: FerrySettings,
+    docks: CVec<P2>,
+    lines: CVec<FerryLine>,
+    lines_rendered_in: CDict<RendererID, ()>,
+    cruises: CVec<Cruise>,
+    cruising_routes_requested: bool,
+    placing_dock: bool,
+    /// The dock a new line starts at, when the second one is yet to be picked
+    line_start: Option<usize>,
+}
+
+impl FerryNetwork {
+    pub fn spawn(
+        id: FerryNetworkID,
+        user_interface: UserInterfaceID,
+        world: &mut World,
+    ) -> FerryNetwork {
+        user_interface.add_2d(id.into(), world);
+        register_action(
+            "Place Ferry Dock",
+            Combo2::new(&[LControl, Q], &[]),
+            id.into(),
+            world,
+        );
+
+        FerryNetwork {
+            id,
+            user_interface,
+            settings: ::ENV.load_settings(SETTINGS_CATEGORY),
+            docks: CVec::new(),
+            lines: CVec::new(),
+            lines_rendered_in: CDict::new(),
+            cruises: CVec::new(),
+            cruising_routes_requested: false,
+            placing_dock: false,
+            line_start: None,
+        }
+    }
+
+    pub fn on_dock_site(&mut self, position: P2, is_shore: bool, _: &mut World) {
+        if is_shore {
+            self.docks.push(position);
+        } else {
+            println!("Ferry docks need to be built on land, right next to water");
+        }
+    }
+
+    pub fn on_cruising_routes(&mut self, routes: &CVec<CPath>, _: &mut World) {
+        self.cruises = routes
+            .iter()
+            .map(|route| {
+                Cruise {
+                    route: route.clone(),
+                    boat: Shuttle::default(),
+                }
+            })
+            .collect();
+    }
+
+    pub fn add_line(&mut self, from_dock: usize, to_dock: usize, world: &mut World) {
+        if from_dock == to_dock || from_dock >= self.docks.len() ||
+            to_dock >= self.docks.len()
+        {
+            return;
+        }
+        let (from, to) = (self.docks[from_dock], self.docks[to_dock]);
+        if (to - from).norm() < 1.0 {
+            return;
+        }
+        self.lines.push(FerryLine {
+            from_dock,
+            to_dock,
+            path: CPath::new(vec![Segment::line(from, to)]),
+            headway_minutes: self.settings.headway_minutes,
+            ferry: Shuttle::default(),
+        });
+        self.update_crossings(world);
+    }
+
+    pub fn set_headway(&mut self, line_idx: usize, headway_minutes: f32, world: &mut World) {
+        if let Some(line) = self.lines.get_mut(line_idx) {
+            line.headway_minutes = headway_minutes;
+        }
+        self.update_crossings(world);
+    }
+
+    pub fn remove_line(&mut self, line_idx: usize, world: &mut World) {
+        if line_idx < self.lines.len() {
+            self.lines.remove(line_idx);
+            self.update_crossings(world);
+        }
+    }
+
+    fn update_crossings(&mut self, world: &mut World) {
+        let crossings = self.lines
+            .iter()
+            .map(|line| line.crossing(self.docks[line.from_dock], self.docks[line.to_dock]))
+            .collect();
+        ActiveModeGraphID::local_first(world).set_ferries(crossings, world);
+        self.lines_rendered_in = CDict::new();
+    }
+
+    pub fn report_for_export(&mut self, exporter: NetworkExporterID, world: &mut World) {
+        let lines = self.lines
+            .iter()
+            .map(|line| {
+                ExportedFerryLine {
+                    from_dock: line.from_dock as u32,
+                    to_dock: line.to_dock as u32,
+                    crossing_seconds: line.crossing_seconds(),
+                    wait_seconds: line.wait_seconds(),
+                }
+            })
+            .collect();
+        exporter.add_ferry_lines(self.docks.clone(), lines, world);
+    }
+}
+
+impl Simulatable for FerryNetwork {
+    fn tick(&mut self, dt: f32, _current_tick: Timestamp, world: &mut World) {
+        if !self.cruising_routes_requested {
+            // the terrain is only set up after transport
+            TerrainID::local_first(world).find_cruising_routes(
+                self.settings.n_cruising_boats,
+                self.id,
+                world,
+            );
+            self.cruising_routes_requested = true;
+        }
+
+        let seconds = SimulationConfig::current().microtraffic_time(dt);
+        for line in self.lines.iter_mut() {
+            let (length, wait) = (line.path.length(), line.wait_seconds());
+            line.ferry.advance((FERRY_VELOCITY * seconds).0, length, wait, seconds.0);
+        }
+        for cruise in self.cruises.iter_mut() {
+            let length = cruise.route.length();
+            cruise.boat.advance((CRUISING_VELOCITY * seconds).0, length, 0.0, seconds.0);
+        }
+    }
+}
+
+/// A box that is longer along x, since instances point their x axis where they head
+fn hull(length: N, width: N, height: N) -> Geometry {
+    let corner = |x: bool, y: bool, z: bool| {
+        Vertex {
+            position: [
+                if x { length / 2.0 } else { -length / 2.0 },
+                if y { width / 2.0 } else { -width / 2.0 },
+                if z { height } else { 0.0 },
+            ],
+        }
+    };
+    Geometry::new(
+        vec![
+            corner(false, false, false),
+            corner(true, false, false),
+            corner(true, true, false),
+            corner(false, true, false),
+            corner(false, false, true),
+            corner(true, false, true),
+            corner(true, true, true),
+            corner(false, true, true),
+        ],
+        vec![
+            0, 1, 5, 5, 4, 0, 1, 2, 6, 6, 5, 1, 2, 3, 7, 7, 6, 2, 3, 0, 4, 4, 7, 3, 4, 5, 6, 6,
+            7, 4,
+        ],
+    )
+}
+
+impl Renderable for FerryNetwork {
+    fn setup_in_scene(&mut self, renderer_id: RendererID, scene_id: usize, world: &mut World) {
+        renderer_id.add_batch(scene_id, DOCK_BATCH_ID, hull(8.0, 8.0, 0.5), world);
+        renderer_id.add_batch(scene_id, FERRY_BATCH_ID, hull(20.0, 7.0, 2.5), world);
+        renderer_id.add_batch(scene_id, BOAT_BATCH_ID, hull(6.0, 2.5, 1.0), world);
+        self.lines_rendered_in = CDict::new();
+    }
+
+    fn render_to_scene(
+        &mut self,
+        renderer_id: RendererID,
+        scene_id: usize,
+        frame: usize,
+        world: &mut World,
+    ) {
+        if self.lines_rendered_in.get(renderer_id).is_none() {
+            let lines_geometry: Geometry = self.lines
+                .iter()
+                .map(|line| band_to_geometry(&Band::new(line.path.clone(), 1.0), 0.0))
+                .sum();
+            renderer_id.update_individual(
+                scene_id,
+                LINES_INDIVIDUAL_ID,
+                lines_geometry,
+                Instance::with_color([0.6, 0.75, 0.9]),
+                true,
+                world,
+            );
+            self.lines_rendered_in.insert(renderer_id, ());
+        }
+
+        let docks = self.docks
+            .iter()
+            .map(|dock| {
+                Instance {
+                    instance_position: [dock.x, dock.y, 0.0],
+                    instance_direction: [1.0, 0.0],
+                    instance_color: [0.45, 0.35, 0.25],
+                }
+            })
+            .collect();
+        renderer_id.add_several_instances(scene_id, DOCK_BATCH_ID, frame, docks, world);
+
+        let ferries = self.lines
+            .iter()
+            .map(|line| line.ferry.instance(&line.path, [0.9, 0.9, 0.85]))
+            .collect();
+        renderer_id.add_several_instances(scene_id, FERRY_BATCH_ID, frame, ferries, world);
+
+        let boats = self.cruises
+            .iter()
+            .map(|cruise| cruise.boat.instance(&cruise.route, [0.8, 0.3, 0.2]))
+            .collect();
+        renderer_id.add_several_instances(scene_id, BOAT_BATCH_ID, frame, boats, world);
+    }
+}
+
+impl ActionListener for FerryNetwork {
+    fn on_action(&mut self, action: ActionId, phase: ActionPhase, world: &mut World) {
+        if phase == ActionPhase::Started && action == ActionId::named("Place Ferry Dock") {
+            self.placing_dock = !self.placing_dock;
+            set_tool_active(self.user_interface, self.id.into(), self.placing_dock, world);
+        }
+    }
+}
+
+impl Interactable3d for FerryNetwork {
+    fn on_event(&mut self, event: Event3d, world: &mut World) {
+        if self.placing_dock {
+            match event {
+                Event3d::DragFinished { to, .. } => {
+                    TerrainID::local_first(world).check_dock_site(
+                        P2::new(to.x, to.y),
+                        self.id,
+                        world,
+                    );
+                    self.placing_dock = false;
+                    self.user_interface.remove(self.id.into(), world);
+                }
+                Event3d::Frame => {
+                    self.user_interface.add_debug_text(
+                        "Placing ferry dock".chars().collect(),
+                        "click on the shore".chars().collect(),
+                        [0.0, 0.0, 0.0, 1.0],
+                        false,
+                        world,
+                    );
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Interactable2d for FerryNetwork {
+    fn draw_ui_2d(
+        &mut self,
+        imgui_ui: &External<::imgui::Ui<'static>>,
+        return_to: UserInterfaceID,
+        world: &mut World,
+    ) {
+        let ui = imgui_ui.steal();
+        let mut line_start = self.line_start;
+
+        ui.window(im_str!("Ferries"))
+            .size((300.0, 250.0), ImGuiSetCond_FirstUseEver)
+            .collapsible(true)
+            .build(|| {
+                ui.text(im_str!(
+                    "{} docks, {} lines, {} boats cruising",
+                    self.docks.len(),
+                    self.lines.len(),
+                    self.cruises.len()
+                ));
+                if self.docks.is_empty() {
+                    ui.text(im_str!("Place Ferry Dock to build the first dock"));
+                }
+
+                if ui.collapsing_header(im_str!("Docks")).build() {
+                    for (i, dock) in self.docks.iter().enumerate() {
+                        ui.text(im_str!("Dock {} at ({:.0}, {:.0})", i + 1, dock.x, dock.y));
+                        ui.same_line(0.0);
+                        match line_start {
+                            None => {
+                                if ui.small_button(im_str!("Start line##{}", i)) {
+                                    line_start = Some(i);
+                                }
+                            }
+                            Some(start) if start == i => {
+                                if ui.small_button(im_str!("Cancel##{}", i)) {
+                                    line_start = None;
+                                }
+                            }
+                            Some(start) => {
+                                if ui.small_button(im_str!("End line here##{}", i)) {
+                                    self.id.add_line(start, i, world);
+                                    line_start = None;
+                                }
+                            }
+                        }
+                    }
+                }
+
+                if ui.collapsing_header(im_str!("Lines")).build() {
+                    for (i, line) in self.lines.iter().enumerate() {
+                        ui.text(im_str!(
+                            "Dock {} - Dock {}: {:.0} m, {:.1} min crossing",
+                            line.from_dock + 1,
+                            line.to_dock + 1,
+                            line.path.length(),
+                            line.crossing_seconds() / 60.0
+                        ));
+                        let mut headway = line.headway_minutes;
+                        let label = im_str!("Headway (min)##{}", i);
+                        if ui.slider_float(label, &mut headway, 5.0, 60.0).build() {
+                            self.id.set_headway(i, headway, world);
+                        }
+                        if ui.small_button(im_str!("Remove##line{}", i)) {
+                            self.id.remove_line(i, world);
+                        }
+                    }
+                }
+            });
+
+        self.line_start = line_start;
+        return_to.ui_drawn(ui, world);
+    }
+}
+
+pub fn setup(system: &mut ActorSystem, user_interface: UserInterfaceID) {
+    system.register::<FerryNetwork>();
+    system.make_restorable::<FerryNetwork>();
+    auto_setup(system);
+
+    FerryNetworkID::spawn(user_interface, &mut system.world());
+}
+
+mod kay_auto;
+pub use self::kay_auto::*;
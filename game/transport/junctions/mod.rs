@@ -0,0 +1,348 @@
+//! Junctions are the built counterparts of planned intersections. When an intersection is
+//! materialized, how each pair of its movements (the lanes across it) relates is worked
+//! out once from their paths and kept in the `ConflictMatrix` of its `Junction`: whether
+//! they cross, merge into the same lane, diverge from the same lane or don't meet at all.
+//!
+//! Lanes use the matrix to decide how they overlap with the other lanes of the junction,
+//! which is what gap acceptance is based on (see `microtraffic`), instead of guessing
+//! from the directions at both ends of a single overlap, which went wrong for movements
+//! that cross at shallow angles on complex multi-leg junctions. Adaptive signals use it
+//! to let movements also go during phases of other approaches when they don't conflict
+//! with anything that's green then (see `signal_control`).
+//!
+//! Junctions built with the protected template (see `protected`) also own its crossings
+//! and refuge islands, the crossings being movements of the conflict matrix as well.
+use kay::{ActorSystem, World, Fate};
+use compact::CVec;
+use descartes::{Band, FiniteCurve, Intersect, RoughlyComparable};
+use monet::{GrouperID, GrouperIndividual, GrouperIndividualID,
+            MSG_GrouperIndividual_render_to_grouper, Geometry};
+use stagemaster::geometry::{CPath, band_to_geometry};
+use super::lane::{Lane, LaneID};
+use super::lane::connectivity::OverlapKind;
+use super::construction::CONNECTION_TOLERANCE;
+use super::signal_control::SignalControllerID;
+use super::pathfinding::active_modes::ActiveModeGraphID;
+
+pub mod protected;
+use self::protected::ProtectedLayout;
+
+const ISLAND_THING_ID: u16 = 2800;
+const ISLAND_WIDTH: f32 = 1.5;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum MovementRelation {
+    Independent,
+    Conflicting,
+    /// Both movements end on the same lane
+    Merging,
+    /// Both movements start from the same lane
+    Diverging,
+}
+
+impl MovementRelation {
+    /// How lanes of two movements with this relation overlap, if they interact at all
+    pub fn overlap_kind(&self) -> Option<OverlapKind> {
+        match *self {
+            MovementRelation::Independent => None,
+            MovementRelation::Conflicting => Some(OverlapKind::Conflicting),
+            MovementRelation::Merging |
+            MovementRelation::Diverging => Some(OverlapKind::Parallel),
+        }
+    }
+
+    /// Whether two movements with this relation can have green at the same time
+    pub fn compatible(&self) -> bool {
+        match *self {
+            MovementRelation::Independent |
+            MovementRelation::Diverging => true,
+            MovementRelation::Conflicting |
+            MovementRelation::Merging => false,
+        }
+    }
+}
+
+pub fn movement_relation(path: &CPath, other_path: &CPath) -> MovementRelation {
+    if path.start().is_roughly_within(other_path.start(), CONNECTION_TOLERANCE) {
+        MovementRelation::Diverging
+    } else if path.end().is_roughly_within(other_path.end(), CONNECTION_TOLERANCE) {
+        MovementRelation::Merging
+    } else if !(path, other_path).intersect().is_empty() {
+        MovementRelation::Conflicting
+    } else {
+        MovementRelation::Independent
+    }
+}
+
+#[derive(Compact, Clone)]
+pub struct ConflictMatrix {
+    n_movements: usize,
+    /// Row by row, a movement is independent of itself
+    relations: CVec<MovementRelation>,
+}
+
+impl ConflictMatrix {
+    pub fn new<F: Fn(usize, usize) -> MovementRelation>(
+        n_movements: usize,
+        relation: F,
+    ) -> ConflictMatrix {
+        let mut relations = CVec::with_capacity(n_movements * n_movements);
+        for a in 0..n_movements {
+            for b in 0..n_movements {
+                relations.push(if a == b {
+                    MovementRelation::Independent
+                } else {
+                    relation(a, b)
+                });
+            }
+        }
+        ConflictMatrix { n_movements, relations }
+    }
+
+    pub fn from_paths(paths: &[&CPath]) -> ConflictMatrix {
+        ConflictMatrix::new(paths.len(), |a, b| movement_relation(paths[a], paths[b]))
+    }
+
+    pub fn n_movements(&self) -> usize {
+        self.n_movements
+    }
+
+    pub fn relation(&self, movement: usize, other_movement: usize) -> MovementRelation {
+        self.relations[movement * self.n_movements + other_movement]
+    }
+
+    /// How `movement` relates to each movement of the junction
+    pub fn row(&self, movement: usize) -> CVec<MovementRelation> {
+        (0..self.n_movements)
+            .map(|other_movement| self.relation(movement, other_movement))
+            .collect()
+    }
+}
+
+/// Signal `timings` (an entry per movement) with extra green for movements wherever they
+/// are `compatible` with all movements that are green then. Entries in which all movements
+/// are red stay that way, since they are for clearing the intersection
+pub fn with_compatible_greens<F: Fn(usize, usize) -> bool>(
+    timings: &[CVec<bool>],
+    compatible: F,
+) -> Vec<CVec<bool>> {
+    let mut new_timings = timings.to_vec();
+    let cycle_length = timings.iter().map(|timing| timing.len()).max().unwrap_or(0);
+
+    for t in 0..cycle_length {
+        let is_green = |timings: &[CVec<bool>], movement: usize| {
+            timings[movement].get(t).cloned().unwrap_or(false)
+        };
+        if !(0..timings.len()).any(|movement| is_green(timings, movement)) {
+            continue;
+        }
+        for movement in 0..timings.len() {
+            if t < new_timings[movement].len() && !is_green(&new_timings, movement) &&
+                (0..timings.len()).all(|other| {
+                    other == movement || !is_green(&new_timings, other) ||
+                        compatible(movement, other)
+                })
+            {
+                new_timings[movement][t] = true;
+            }
+        }
+    }
+
+    new_timings
+}
+
+#[derive(Compact, Clone)]
+pub struct Junction {
+    id: JunctionID,
+    conflicts: ConflictMatrix,
+    /// The lane of each movement, once it is built. Crossings of the protected
+    /// template come after these in the conflict matrix
+    lanes: CVec<Option<LaneID>>,
+    protected_layout: ProtectedLayout,
+}
+
+impl Junction {
+    pub fn spawn(
+        id: JunctionID,
+        conflicts: &ConflictMatrix,
+        protected_layout: &ProtectedLayout,
+        world: &mut World,
+    ) -> Junction {
+        if !protected_layout.islands.is_empty() {
+            JunctionRendererID::local_first(world).on_build(id.into(), world);
+        }
+        let n_lane_movements = conflicts.n_movements() - protected_layout.crossings.len();
+
+        Junction {
+            id,
+            conflicts: conflicts.clone(),
+            lanes: vec![None; n_lane_movements].into(),
+            protected_layout: protected_layout.clone(),
+        }
+    }
+
+    pub fn add_movement_lane(&mut self, movement: usize, lane: LaneID, _: &mut World) {
+        self.lanes[movement] = Some(lane);
+    }
+
+    pub fn remove_movement_lane(&mut self, lane: LaneID, world: &mut World) -> Fate {
+        for movement_lane in self.lanes.iter_mut() {
+            if *movement_lane == Some(lane) {
+                *movement_lane = None;
+            }
+        }
+        if self.lanes.iter().all(Option::is_none) {
+            if !self.protected_layout.islands.is_empty() {
+                JunctionRendererID::local_first(world).on_unbuild(self.id.into(), world);
+            }
+            Fate::Die
+        } else {
+            Fate::Live
+        }
+    }
+
+    pub fn report_conflicts(
+        &mut self,
+        controller: SignalControllerID,
+        key: u32,
+        world: &mut World,
+    ) {
+        controller.on_conflicts(key, self.lanes.clone(), self.conflicts.clone(), world);
+    }
+
+    /// Pedestrians and cyclists use the crossings and the bike lanes around the corners
+    /// of protected junctions, on top of the crossings along the lanes
+    pub fn report_to_active_modes(&mut self, graph: ActiveModeGraphID, world: &mut World) {
+        if !self.protected_layout.crossings.is_empty() {
+            let crossings = self.protected_layout
+                .crossings
+                .iter()
+                .chain(self.protected_layout.bike_connections.iter())
+                .cloned()
+                .collect();
+            graph.add_crossings(crossings, world);
+        }
+    }
+}
+
+impl GrouperIndividual for Junction {
+    fn render_to_grouper(
+        &mut self,
+        grouper: GrouperID,
+        _base_individual_id: u16,
+        world: &mut World,
+    ) {
+        let islands: Geometry = self.protected_layout
+            .islands
+            .iter()
+            .map(|island| band_to_geometry(&Band::new(island.clone(), ISLAND_WIDTH), 0.3))
+            .sum();
+        grouper.add_frozen(self.id.into(), islands, world);
+    }
+}
+
+/// Renders the refuge islands of all protected junctions
+#[derive(Compact, Clone)]
+pub struct JunctionRenderer {
+    id: JunctionRendererID,
+    island_grouper: GrouperID,
+}
+
+impl JunctionRenderer {
+    pub fn spawn(
+        id: JunctionRendererID,
+        island_grouper: GrouperID,
+        _: &mut World,
+    ) -> JunctionRenderer {
+        JunctionRenderer { id, island_grouper }
+    }
+
+    pub fn on_build(&mut self, junction: GrouperIndividualID, world: &mut World) {
+        self.island_grouper.initial_add(junction, world);
+    }
+
+    pub fn on_unbuild(&mut self, junction: GrouperIndividualID, world: &mut World) {
+        self.island_grouper.remove(junction, world);
+    }
+}
+
+impl Lane {
+    pub fn join_junction(
+        &mut self,
+        junction: JunctionID,
+        movement: usize,
+        relations: &CVec<MovementRelation>,
+        world: &mut World,
+    ) {
+        self.connectivity.junction = Some((junction, movement));
+        self.connectivity.movement_relations = relations.clone();
+        junction.add_movement_lane(movement, self.id, world);
+    }
+
+    pub fn report_conflicts(
+        &mut self,
+        controller: SignalControllerID,
+        key: u32,
+        world: &mut World,
+    ) {
+        if let Some((junction, _)) = self.connectivity.junction {
+            junction.report_conflicts(controller, key, world);
+        }
+    }
+}
+
+pub fn setup(system: &mut ActorSystem) {
+    system.register::<Junction>();
+    system.register::<JunctionRenderer>();
+    system.make_restorable::<Junction>();
+    auto_setup(system);
+
+    let island_grouper =
+        GrouperID::spawn([0.8, 0.8, 0.75], ISLAND_THING_ID, false, &mut system.world());
+    JunctionRendererID::spawn(island_grouper, &mut system.world());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use descartes::{P2, Segment};
+
+    fn line(start: P2, end: P2) -> CPath {
+        CPath::new(vec![Segment::line(start, end)])
+    }
+
+    #[test]
+    fn relations_follow_geometry() {
+        let west_east = line(P2::new(0.0, 0.0), P2::new(20.0, 0.0));
+        let south_north = line(P2::new(10.0, -10.0), P2::new(10.0, 10.0));
+        let west_north = line(P2::new(0.0, 0.0), P2::new(10.0, 10.0));
+        let east_west = line(P2::new(20.0, 5.0), P2::new(0.0, 5.0));
+        let matrix = ConflictMatrix::from_paths(&[&west_east, &south_north, &west_north]);
+
+        assert_eq!(matrix.relation(0, 1), MovementRelation::Conflicting);
+        assert_eq!(matrix.relation(0, 2), MovementRelation::Diverging);
+        assert_eq!(matrix.relation(1, 2), MovementRelation::Merging);
+        assert_eq!(matrix.relation(2, 2), MovementRelation::Independent);
+        assert_eq!(movement_relation(&west_east, &east_west), MovementRelation::Independent);
+    }
+
+    #[test]
+    fn compatible_movements_get_extra_green() {
+        // movement 0 is alone in its phase, 1 and 2 share the next one, then all red
+        let timings: Vec<CVec<bool>> = vec![
+            vec![true, false, false].into(),
+            vec![false, true, false].into(),
+            vec![false, true, false].into(),
+        ];
+        let new_timings = with_compatible_greens(&timings, |a, b| {
+            (a == 0 && b == 2) || (a == 2 && b == 0)
+        });
+
+        assert_eq!(&new_timings[0][..], &[true, false, false]);
+        assert_eq!(&new_timings[1][..], &[false, true, false]);
+        assert_eq!(&new_timings[2][..], &[true, true, false]);
+    }
+}
+
+mod kay_auto;
+pub use self::kay_auto::*;
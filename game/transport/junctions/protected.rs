@@ -0,0 +1,261 @@
+//! The template for protected intersections, which signalized intersections of roads
+//! with bike lanes get. All roads have sidewalks (see `pathfinding::active_modes`), with
+//! bike lanes running between them and the outermost car lanes.
+//!
+//! Instead of crossing along the way cars take, pedestrians and cyclists cross each leg
+//! a few meters away from the intersection, so turning cars can wait for them without
+//! blocking the intersection. Cyclists get around the corners behind refuge islands,
+//! which keep turning cars at a distance, and have their own signal phase in which all
+//! cars that would cross their way wait.
+use compact::CVec;
+use descartes::{N, P2, V2, Dot, Norm, Curve, FiniteCurve, Segment};
+use stagemaster::geometry::CPath;
+use super::{ConflictMatrix, MovementRelation, movement_relation};
+use super::super::planning::plan::Intersection;
+use super::super::signal_warrants::CLEARANCE_ENTRIES;
+
+/// How far from the intersection the crossings are
+const SETBACK: N = 5.0;
+const LANE_HALF_WIDTH: N = 3.0;
+const BIKE_LANE_WIDTH: N = 2.0;
+/// Lane ends pointing away from the intersection in these similar directions and at most
+/// this far from each other belong to the same leg
+const LEG_PARALLELITY: N = 0.95;
+const MAX_LEG_WIDTH: N = 30.0;
+/// Legs at steeper angles to each other than this (as a cosine) don't share a corner
+const MIN_CORNER_COS: N = -0.5;
+const ISLAND_LENGTH: N = 3.0;
+/// How far the middle of an island is from the bike lane around its corner
+const ISLAND_OFFSET: N = 2.0;
+/// Signal timing entries of the bike phase, without the clearance before and after it
+const BIKE_PHASE_ENTRIES: usize = 4;
+
+#[derive(Compact, Clone, Default)]
+pub struct ProtectedLayout {
+    /// Across each leg, set back from the intersection
+    pub crossings: CVec<CPath>,
+    /// Around each corner, from the end of one crossing to the start of the next
+    pub bike_connections: CVec<CPath>,
+    /// Along the middle of each corner refuge island
+    pub islands: CVec<CPath>,
+}
+
+struct Leg {
+    /// Where the leg meets the intersection, in the middle of its lanes
+    base: P2,
+    /// Pointing away from the intersection
+    outward: V2,
+    /// Pointing to the left, looking away from the intersection
+    normal: V2,
+    left_offset: N,
+    right_offset: N,
+}
+
+impl Leg {
+    fn point(&self, along: N, offset: N) -> P2 {
+        self.base + self.outward * along + self.normal * offset
+    }
+
+    fn angle(&self) -> N {
+        self.outward.y.atan2(self.outward.x)
+    }
+}
+
+/// The legs of `intersection`, sorted counterclockwise
+fn legs(intersection: &Intersection) -> Vec<Leg> {
+    let ends = intersection
+        .incoming
+        .values()
+        .map(|node| (node.position, -node.direction))
+        .chain(intersection.outgoing.values().map(
+            |node| (node.position, node.direction),
+        ));
+
+    let mut groups: Vec<(V2, Vec<P2>)> = Vec::new();
+    for (position, outward) in ends {
+        let maybe_idx = groups.iter().position(|&(group_outward, ref positions)| {
+            outward.dot(&group_outward) > LEG_PARALLELITY &&
+                (position - positions[0]).norm() < MAX_LEG_WIDTH
+        });
+        match maybe_idx {
+            Some(idx) => groups[idx].1.push(position),
+            None => groups.push((outward, vec![position])),
+        }
+    }
+
+    let mut legs = groups
+        .into_iter()
+        .map(|(outward, positions)| {
+            let outward = outward.normalize();
+            let normal = V2::new(-outward.y, outward.x);
+            let origin = positions[0];
+            let offsets = positions
+                .iter()
+                .map(|&position| (position - origin).dot(&normal))
+                .collect::<Vec<_>>();
+            let along = positions
+                .iter()
+                .map(|&position| (position - origin).dot(&outward))
+                .sum::<N>() / positions.len() as N;
+            let left = offsets.iter().cloned().fold(::std::f32::MIN, N::max);
+            let right = offsets.iter().cloned().fold(::std::f32::MAX, N::min);
+            let middle = (left + right) / 2.0;
+            Leg {
+                base: origin + outward * along + normal * middle,
+                outward,
+                normal,
+                left_offset: left - middle + LANE_HALF_WIDTH,
+                right_offset: right - middle - LANE_HALF_WIDTH,
+            }
+        })
+        .collect::<Vec<_>>();
+    legs.sort_by(|a, b| a.angle().partial_cmp(&b.angle()).unwrap());
+    legs
+}
+
+fn line(start: P2, end: P2) -> CPath {
+    CPath::new(vec![Segment::line(start, end)])
+}
+
+pub fn protected_layout(intersection: &Intersection) -> ProtectedLayout {
+    let legs = legs(intersection);
+    let mut layout = ProtectedLayout::default();
+    if legs.len() < 2 {
+        return layout;
+    }
+
+    let sum = legs.iter().fold(P2::new(0.0, 0.0), |sum, leg| sum + leg.base.to_vector());
+    let center = P2::new(sum.x / legs.len() as N, sum.y / legs.len() as N);
+
+    for leg in &legs {
+        layout.crossings.push(line(
+            leg.point(SETBACK, leg.right_offset - BIKE_LANE_WIDTH),
+            leg.point(SETBACK, leg.left_offset + BIKE_LANE_WIDTH),
+        ));
+    }
+
+    for (i, leg) in legs.iter().enumerate() {
+        let next_leg = &legs[(i + 1) % legs.len()];
+        let counterclockwise = leg.outward.x * next_leg.outward.y -
+            leg.outward.y * next_leg.outward.x > 0.0;
+        if !counterclockwise || leg.outward.dot(&next_leg.outward) < MIN_CORNER_COS {
+            continue;
+        }
+
+        let from = leg.point(SETBACK, leg.left_offset + BIKE_LANE_WIDTH);
+        let to = next_leg.point(SETBACK, next_leg.right_offset - BIKE_LANE_WIDTH);
+        layout.bike_connections.push(line(from, to));
+
+        let middle = from + (to - from) / 2.0;
+        let island_middle = middle + (center - middle).normalize() * ISLAND_OFFSET;
+        let island_direction = (to - from).normalize();
+        layout.islands.push(line(
+            island_middle - island_direction * ISLAND_LENGTH / 2.0,
+            island_middle + island_direction * ISLAND_LENGTH / 2.0,
+        ));
+    }
+
+    layout
+}
+
+/// A movement conflicts with the crossings of the legs it comes from or leads to
+fn crossing_relation(path: &CPath, crossing: &CPath) -> MovementRelation {
+    let reach = SETBACK + 1.0;
+    if crossing.distance_to(path.start()) < reach || crossing.distance_to(path.end()) < reach {
+        MovementRelation::Conflicting
+    } else {
+        MovementRelation::Independent
+    }
+}
+
+impl ProtectedLayout {
+    /// The conflict matrix of the movements along `paths`, followed by the crossings
+    pub fn conflicts(&self, paths: &[&CPath]) -> ConflictMatrix {
+        let n_lane_movements = paths.len();
+        let crossing = |movement: usize| &self.crossings[movement - n_lane_movements];
+        ConflictMatrix::new(n_lane_movements + self.crossings.len(), |a, b| {
+            match (a < n_lane_movements, b < n_lane_movements) {
+                (true, true) => movement_relation(paths[a], paths[b]),
+                (true, false) => crossing_relation(paths[a], crossing(b)),
+                (false, true) => crossing_relation(paths[b], crossing(a)),
+                (false, false) => MovementRelation::Independent,
+            }
+        })
+    }
+}
+
+/// For each of the first `n_lane_movements` movements of `conflicts`, whether it can be
+/// green together with all crossings that follow them
+pub fn compatible_with_crossings(
+    conflicts: &ConflictMatrix,
+    n_lane_movements: usize,
+) -> Vec<bool> {
+    (0..n_lane_movements)
+        .map(|movement| {
+            (n_lane_movements..conflicts.n_movements()).all(|crossing| {
+                conflicts.relation(movement, crossing).compatible()
+            })
+        })
+        .collect()
+}
+
+/// Signal `timings` with a bike phase added at the end of the cycle, in which only
+/// movements `green_during_bike_phase` are green, with all-red clearance around it
+pub fn with_bike_phase(
+    timings: &[CVec<bool>],
+    green_during_bike_phase: &[bool],
+) -> Vec<CVec<bool>> {
+    timings
+        .iter()
+        .zip(green_during_bike_phase)
+        .map(|(timing, &green)| {
+            timing
+                .iter()
+                .cloned()
+                .chain((0..CLEARANCE_ENTRIES).map(|_| false))
+                .chain((0..BIKE_PHASE_ENTRIES).map(|_| green))
+                .chain((0..CLEARANCE_ENTRIES).map(|_| false))
+                .collect()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crossings_only_conflict_with_movements_over_their_leg() {
+        let west = line(P2::new(-20.0, 0.0), P2::new(-20.0, 10.0));
+        let east = line(P2::new(20.0, 0.0), P2::new(20.0, 10.0));
+        let layout = ProtectedLayout {
+            crossings: vec![west].into(),
+            bike_connections: CVec::new(),
+            islands: CVec::new(),
+        };
+        let west_east = line(P2::new(-16.0, 5.0), P2::new(-4.0, 5.0));
+        let north_south = line(P2::new(-10.0, 15.0), P2::new(-10.0, -5.0));
+        let conflicts = layout.conflicts(&[&west_east, &north_south]);
+
+        assert_eq!(conflicts.relation(0, 2), MovementRelation::Conflicting);
+        assert_eq!(conflicts.relation(2, 0), MovementRelation::Conflicting);
+        assert_eq!(conflicts.relation(1, 2), MovementRelation::Independent);
+        assert_eq!(compatible_with_crossings(&conflicts, 2), vec![false, true]);
+        assert_eq!(crossing_relation(&west_east, &east), MovementRelation::Independent);
+    }
+
+    #[test]
+    fn bike_phase_is_surrounded_by_clearance() {
+        let timings: Vec<CVec<bool>> = vec![vec![true, false].into(), vec![false, true].into()];
+        let new_timings = with_bike_phase(&timings, &[false, true]);
+        let cycle_length = 2 + 2 * CLEARANCE_ENTRIES + BIKE_PHASE_ENTRIES;
+
+        assert_eq!(new_timings[0].len(), cycle_length);
+        assert!(new_timings[0][2..].iter().all(|&green| !green));
+        assert_eq!(
+            new_timings[1].iter().filter(|&&green| green).count(),
+            1 + BIKE_PHASE_ENTRIES
+        );
+        assert!(!new_timings[1][cycle_length - 1]);
+    }
+}
@@ -1,11 +1,16 @@
 use compact::CVec;
 use descartes::N;
 use super::LaneID;
+use super::super::junctions::{JunctionID, MovementRelation};
 
 #[derive(Compact, Clone)]
 pub struct ConnectivityInfo {
     pub interactions: CVec<Interaction>,
     pub on_intersection: bool,
+    /// For lanes across an intersection: its junction and the lane's movement there
+    pub junction: Option<(JunctionID, usize)>,
+    /// How the lane's movement relates to each movement of its junction
+    pub movement_relations: CVec<MovementRelation>,
 }
 
 impl ConnectivityInfo {
@@ -13,6 +18,8 @@ impl ConnectivityInfo {
         ConnectivityInfo {
             interactions: CVec::new(),
             on_intersection: on_intersection,
+            junction: None,
+            movement_relations: CVec::new(),
         }
     }
 }
@@ -46,7 +53,7 @@ pub enum InteractionKind {
     Previous,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum OverlapKind {
     Parallel,
     Transfer,
@@ -4,10 +4,19 @@ use descartes::{N, FiniteCurve};
 use stagemaster::geometry::CPath;
 
 use super::construction::ConstructionInfo;
+use super::construction::crews::ConstructionCrewsID;
 pub mod connectivity;
-use self::connectivity::{ConnectivityInfo, TransferConnectivityInfo};
-use super::microtraffic::{Microtraffic, TransferringMicrotraffic};
-use super::pathfinding::PathfindingInfo;
+use self::connectivity::{ConnectivityInfo, TransferConnectivityInfo, Interaction,
+                         InteractionKind};
+use super::microtraffic::{Microtraffic, TransferringMicrotraffic, LaneCar, LaneLikeID,
+                          MSG_LaneLike_add_car};
+use super::pathfinding::{PathfindingInfo, NodeID, MSG_Node_query_routes};
+use super::construction::{UnbuildableID, MSG_Unbuildable_disconnect};
+use super::pathfinding::route_learning::RouteLearningInfo;
+use super::restrictions::{LaneRestriction, LaneLimits};
+use super::road_hierarchy::RoadClassInfo;
+use sound::SoundEvent;
+use core::snapshot_diff::Diffable;
 
 
 #[derive(Compact, Clone)]
@@ -17,31 +26,73 @@ pub struct Lane {
     pub connectivity: ConnectivityInfo,
     pub microtraffic: Microtraffic,
     pub pathfinding: PathfindingInfo,
+    /// See `transport::pathfinding::route_learning`
+    pub route_learning: RouteLearningInfo,
+    pub restriction: LaneRestriction,
+    /// Set while the lane is closed, to restore its restriction when it reopens
+    pub restriction_before_closure: Option<LaneRestriction>,
+    /// How many closures the lane is in right now, see `Lane::close_near`
+    pub n_closures: u8,
+    /// Set for lanes on or under bridges, see `restrictions::LaneLimits`
+    pub limits: LaneLimits,
+    /// From 0 (new) to 1 (worn out), see `transport::maintenance`
+    pub wear: f32,
+    /// From 0 (clear) to 1 (deep snow), see `transport::services::winter`
+    pub snow: f32,
+    pub road_class: RoadClassInfo,
     pub hovered: bool,
     pub last_spawn_position: N,
 }
 
 impl Lane {
+    /// Lanes that are `built_instantly`, like the ones of a city being loaded, are open
+    /// right away, all others wait for a construction crew to build them
     pub fn spawn(
         id: LaneID,
         path: &CPath,
         on_intersection: bool,
         timings: &CVec<bool>,
+        built_instantly: bool,
         world: &mut World,
     ) -> Self {
-        let lane = Lane {
+        let mut lane = Lane::new(id, path, on_intersection, timings);
+
+        if built_instantly {
+            lane.construction.progress = lane.construction.length;
+            super::rendering::on_build(&lane, world);
+            lane.open(world);
+        } else {
+            super::rendering::on_build(&lane, world);
+            ::sound::play_event(SoundEvent::Construction, path.start(), world);
+            ConstructionCrewsID::local_first(world).request_crew(
+                id.into(),
+                lane.construction.length,
+                world,
+            );
+        }
+
+        lane
+    }
+
+    /// The state of a lane that nobody knows about yet, see `spawn`
+    pub fn new(id: LaneID, path: &CPath, on_intersection: bool, timings: &CVec<bool>) -> Lane {
+        Lane {
             id,
             last_spawn_position: path.length() / 2.0,
             construction: ConstructionInfo::from_path(path.clone()),
             connectivity: ConnectivityInfo::new(on_intersection),
             microtraffic: Microtraffic::new(timings.clone()),
             pathfinding: PathfindingInfo::default(),
+            route_learning: RouteLearningInfo::default(),
+            restriction: LaneRestriction::General,
+            restriction_before_closure: None,
+            n_closures: 0,
+            limits: LaneLimits::default(),
+            wear: 0.0,
+            snow: 0.0,
+            road_class: RoadClassInfo::default(),
             hovered: false,
-        };
-
-        super::rendering::on_build(&lane, world);
-
-        lane
+        }
     }
 }
 
@@ -54,10 +105,27 @@ pub struct TransferLane {
 }
 
 impl TransferLane {
-    pub fn spawn(id: TransferLaneID, path: &CPath, _: &mut World) -> TransferLane {
+    /// Like `Lane::spawn`
+    pub fn spawn(
+        id: TransferLaneID,
+        path: &CPath,
+        built_instantly: bool,
+        world: &mut World,
+    ) -> TransferLane {
+        let mut construction = ConstructionInfo::from_path(path.clone());
+        if built_instantly {
+            construction.progress = construction.length;
+        } else {
+            ConstructionCrewsID::local_first(world).request_crew(
+                id.into(),
+                construction.length,
+                world,
+            );
+        }
+
         TransferLane {
             id,
-            construction: ConstructionInfo::from_path(path.clone()),
+            construction,
             connectivity: TransferConnectivityInfo::default(),
             microtraffic: TransferringMicrotraffic::default(),
         }
@@ -121,9 +189,155 @@ impl TransferLane {
     }
 }
 
+fn car_changes(before: &[LaneCar], after: &[LaneCar]) -> Vec<String> {
+    let mut changes = Vec::new();
+    for car in after {
+        let trip = car.trip._raw_id.instance_id;
+        match before.iter().find(|earlier| earlier.trip == car.trip) {
+            Some(earlier) => {
+                if earlier.destination != car.destination {
+                    changes.push(format!(
+                        "car of trip {} now heads for node {}",
+                        trip,
+                        car.destination.node._raw_id.instance_id
+                    ));
+                } else if earlier.next_hop_interaction != car.next_hop_interaction {
+                    changes.push(format!(
+                        "car of trip {} rerouted from interaction {} to {}",
+                        trip,
+                        earlier.next_hop_interaction,
+                        car.next_hop_interaction
+                    ));
+                }
+            }
+            None => changes.push(format!("car of trip {} appeared", trip)),
+        }
+    }
+    for earlier in before {
+        if !after.iter().any(|car| car.trip == earlier.trip) {
+            changes.push(format!(
+                "car of trip {} disappeared",
+                earlier.trip._raw_id.instance_id
+            ));
+        }
+    }
+    changes
+}
+
+// signal states are left out, they change all the time
+fn describe_interaction(interaction: &Interaction) -> String {
+    let kind = match interaction.kind {
+        InteractionKind::Overlap { end, partner_end, kind } => {
+            format!("{:?} overlap until {:.1} ({:.1} on partner)", kind, end, partner_end)
+        }
+        InteractionKind::Next { .. } => "next".to_owned(),
+        InteractionKind::Previous => "previous".to_owned(),
+    };
+    format!(
+        "{} with {} from {:.1} ({:.1} on partner)",
+        kind,
+        interaction.partner_lane._raw_id.instance_id,
+        interaction.start,
+        interaction.partner_start
+    )
+}
+
+impl Diffable for Lane {
+    fn changes_since(&self, before: &Lane) -> Vec<String> {
+        let mut changes = car_changes(&before.microtraffic.cars, &self.microtraffic.cars);
+
+        let interactions = self.connectivity
+            .interactions
+            .iter()
+            .map(describe_interaction)
+            .collect::<Vec<_>>();
+        let earlier_interactions = before
+            .connectivity
+            .interactions
+            .iter()
+            .map(describe_interaction)
+            .collect::<Vec<_>>();
+        for interaction in &interactions {
+            if !earlier_interactions.contains(interaction) {
+                changes.push(format!("interaction added: {}", interaction));
+            }
+        }
+        for interaction in &earlier_interactions {
+            if !interactions.contains(interaction) {
+                changes.push(format!("interaction removed: {}", interaction));
+            }
+        }
+
+        changes
+    }
+}
+
+impl Diffable for TransferLane {
+    fn changes_since(&self, before: &TransferLane) -> Vec<String> {
+        let cars = self.microtraffic.cars.iter().map(|car| **car).collect::<Vec<_>>();
+        let earlier_cars = before
+            .microtraffic
+            .cars
+            .iter()
+            .map(|car| **car)
+            .collect::<Vec<_>>();
+        car_changes(&earlier_cars, &cars)
+    }
+}
+
+// Lanes and transfer lanes are the only `LaneLike`s, `Node`s and `Unbuildable`s,
+// so their trait IDs can be converted into each other. Converting to `LaneID` only
+// works for IDs that are known to refer to a `Lane`, like the next or previous
+// partners of a lane. Likewise, converting to `TransferLaneID` only works for the
+// partners of transfer overlaps. All of these are checked while ID casts are audited.
+
+impl From<LaneLikeID> for LaneID {
+    fn from(id: LaneLikeID) -> LaneID {
+        LaneID { _raw_id: cast_id_to_actor!(id._raw_id, Lane) }
+    }
+}
+
+impl From<LaneLikeID> for TransferLaneID {
+    fn from(id: LaneLikeID) -> TransferLaneID {
+        TransferLaneID { _raw_id: cast_id_to_actor!(id._raw_id, TransferLane) }
+    }
+}
+
+impl From<NodeID> for LaneID {
+    fn from(id: NodeID) -> LaneID {
+        LaneID { _raw_id: cast_id_to_actor!(id._raw_id, Lane) }
+    }
+}
+
+impl From<NodeID> for LaneLikeID {
+    fn from(id: NodeID) -> LaneLikeID {
+        LaneLikeID { _raw_id: cast_id_to_handler!(id._raw_id, MSG_LaneLike_add_car) }
+    }
+}
+
+impl From<UnbuildableID> for LaneLikeID {
+    fn from(id: UnbuildableID) -> LaneLikeID {
+        LaneLikeID { _raw_id: cast_id_to_handler!(id._raw_id, MSG_LaneLike_add_car) }
+    }
+}
+
+impl From<LaneLikeID> for NodeID {
+    fn from(id: LaneLikeID) -> NodeID {
+        NodeID { _raw_id: cast_id_to_handler!(id._raw_id, MSG_Node_query_routes) }
+    }
+}
+
+impl From<LaneLikeID> for UnbuildableID {
+    fn from(id: LaneLikeID) -> UnbuildableID {
+        UnbuildableID { _raw_id: cast_id_to_handler!(id._raw_id, MSG_Unbuildable_disconnect) }
+    }
+}
+
 pub fn setup(system: &mut ActorSystem) {
     system.register::<Lane>();
     system.register::<TransferLane>();
+    system.make_restorable::<Lane>();
+    system.make_restorable::<TransferLane>();
 
     auto_setup(system);
 }
@@ -0,0 +1,366 @@
+//! Road wear and its repair: every vehicle leaving a lane wears it down a little
+//! (heavy vehicles much more), badly worn lanes can only be driven slowly, and a daily
+//! maintenance budget is spent on bringing the worst lanes back into shape.
+//!
+//! There is no city-wide budget yet, so maintenance has its own daily budget
+//! (see the "Road Maintenance" settings).
+use kay::{ActorSystem, World, External};
+use compact::CVec;
+use descartes::{N, P2, Norm, FiniteCurve};
+use monet::{Renderable, RenderableID, MSG_Renderable_setup_in_scene,
+            MSG_Renderable_render_to_scene, RendererID, Geometry, Vertex, Instance,
+            DecalPattern};
+use stagemaster::geometry::path_to_decals;
+use stagemaster::{UserInterfaceID, Interactable2d, Interactable2dID,
+                  MSG_Interactable2d_draw_ui_2d};
+use stagemaster::charts::{Chart, ChartKind};
+use imgui::ImGuiSetCond_FirstUseEver;
+use core::simulation::{SimulationID, Sleeper, SleeperID, MSG_Sleeper_wake, Timestamp, Ticks,
+                       TICKS_PER_SIM_DAY};
+use core::statistics_history::StatisticsHistoryID;
+use super::lane::{Lane, LaneID};
+use super::restrictions::VehicleClass;
+use super::rendering::{set_lane_decals, DIRT_DECAL_LAYER};
+
+const SETTINGS_CATEGORY: &'static str = "Road Maintenance";
+const COLLECTION_TICKS: Ticks = Ticks(10);
+const CONDITION_MARKER_BATCH_ID: u16 = 8103;
+
+/// Wear caused by one car leaving a lane, other vehicles cause a multiple of this
+const WEAR_PER_CAR: f32 = 0.00005;
+/// Above this wear, a lane counts as badly degraded and can't be driven at full speed
+pub const DEGRADED_WEAR: f32 = 0.7;
+/// Fraction of their max velocity that cars still drive on a completely worn out lane
+const WORN_OUT_SPEED_FACTOR: f32 = 0.5;
+/// Lanes with less wear than this aren't worth repairing yet
+const MIN_REPAIR_WEAR: f32 = 0.2;
+/// Repairs find their lane by its midpoint, which is at most this far off
+const REPAIR_MATCH_DISTANCE: N = 0.1;
+/// Wear shows as dirt on lanes, which only changes in this many steps
+const DIRT_STEPS: f32 = 5.0;
+const DIRT_COLOR: [f32; 3] = [0.45, 0.42, 0.38];
+
+/// How much a single passage of a vehicle wears a lane down
+pub fn wear_per_passage(vehicle: VehicleClass) -> f32 {
+    let weight = match vehicle {
+        VehicleClass::Car | VehicleClass::HighOccupancyCar => 1.0,
+        VehicleClass::Emergency => 2.0,
+        VehicleClass::Bus => 4.0,
+        VehicleClass::Truck => 8.0,
+        VehicleClass::OversizeTruck => 16.0,
+    };
+    weight * WEAR_PER_CAR
+}
+
+/// Factor on the max velocity of cars on a lane with this much wear: 1 up to
+/// `DEGRADED_WEAR`, then linearly down to `WORN_OUT_SPEED_FACTOR`
+pub fn speed_factor(wear: f32) -> f32 {
+    if wear <= DEGRADED_WEAR {
+        1.0
+    } else {
+        let degradation = (wear - DEGRADED_WEAR) / (1.0 - DEGRADED_WEAR);
+        1.0 - degradation.min(1.0) * (1.0 - WORN_OUT_SPEED_FACTOR)
+    }
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone)]
+pub struct MaintenanceSettings {
+    /// Money units that may be spent on repairs per day
+    pub daily_budget: f32,
+    /// What fully repairing one meter of completely worn out lane costs
+    pub cost_per_meter: f32,
+}
+
+impl Default for MaintenanceSettings {
+    fn default() -> Self {
+        MaintenanceSettings {
+            daily_budget: 20_000.0,
+            cost_per_meter: 50.0,
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+struct LaneCondition {
+    midpoint: P2,
+    length: N,
+    wear: f32,
+}
+
+#[derive(Compact, Clone)]
+pub struct RoadMaintenance {
+    id: RoadMaintenanceID,
+    simulation: SimulationID,
+    settings: MaintenanceSettings,
+    collecting: bool,
+    conditions: CVec<LaneCondition>,
+    spent_last_round: f32,
+    repaired_last_round: usize,
+    overlay: bool,
+}
+
+impl RoadMaintenance {
+    pub fn spawn(
+        id: RoadMaintenanceID,
+        user_interface: UserInterfaceID,
+        simulation: SimulationID,
+        world: &mut World,
+    ) -> RoadMaintenance {
+        user_interface.add_2d(id.into(), world);
+        simulation.wake_up_in(Ticks(TICKS_PER_SIM_DAY), id.into(), world);
+
+        RoadMaintenance {
+            id,
+            simulation,
+            settings: ::ENV.load_settings(SETTINGS_CATEGORY),
+            collecting: false,
+            conditions: CVec::new(),
+            spent_last_round: 0.0,
+            repaired_last_round: 0,
+            overlay: false,
+        }
+    }
+
+    pub fn add_condition(&mut self, midpoint: P2, length: N, wear: f32, _: &mut World) {
+        self.conditions.push(LaneCondition { midpoint, length, wear });
+    }
+
+    /// Repairs the worst lanes first, as long as the daily budget suffices
+    fn repair(&mut self, world: &mut World) {
+        let mut by_wear = (0..self.conditions.len()).collect::<Vec<_>>();
+        by_wear.sort_by(|&a, &b| {
+            self.conditions[b].wear.partial_cmp(&self.conditions[a].wear).unwrap()
+        });
+
+        let mut remaining_budget = self.settings.daily_budget;
+        let mut repaired = CVec::new();
+
+        for idx in by_wear {
+            let condition = &mut self.conditions[idx];
+            if condition.wear < MIN_REPAIR_WEAR {
+                break;
+            }
+            let cost = condition.length * condition.wear * self.settings.cost_per_meter;
+            if cost <= remaining_budget {
+                remaining_budget -= cost;
+                repaired.push(condition.midpoint);
+                condition.wear = 0.0;
+            }
+        }
+
+        self.spent_last_round = self.settings.daily_budget - remaining_budget;
+        self.repaired_last_round = repaired.len();
+        StatisticsHistoryID::local_first(world).record_spending(self.spent_last_round, world);
+
+        if !repaired.is_empty() {
+            // found again by position, lanes might have been removed since they reported
+            LaneID::global_broadcast(world).repair_at(repaired, world);
+        }
+    }
+}
+
+impl Sleeper for RoadMaintenance {
+    fn wake(&mut self, _current_tick: Timestamp, world: &mut World) {
+        if self.collecting {
+            self.collecting = false;
+            self.repair(world);
+            self.simulation.wake_up_in(
+                Ticks(TICKS_PER_SIM_DAY - COLLECTION_TICKS.0),
+                self.id.into(),
+                world,
+            );
+        } else {
+            self.collecting = true;
+            self.conditions = CVec::new();
+            LaneID::global_broadcast(world).report_condition(self.id, world);
+            self.simulation.wake_up_in(COLLECTION_TICKS, self.id.into(), world);
+        }
+    }
+}
+
+impl Lane {
+    pub fn report_condition(&mut self, maintenance: RoadMaintenanceID, world: &mut World) {
+        if self.construction.is_finished() {
+            maintenance.add_condition(
+                self.construction.path.along(self.construction.length / 2.0),
+                self.construction.length,
+                self.wear,
+                world,
+            );
+        }
+    }
+
+    pub fn repair_at(&mut self, midpoints: &CVec<P2>, world: &mut World) {
+        let own_midpoint = self.construction.path.along(self.construction.length / 2.0);
+        if midpoints.iter().any(|midpoint| {
+            (*midpoint - own_midpoint).norm() < REPAIR_MATCH_DISTANCE
+        })
+        {
+            let old_wear = self.wear;
+            self.wear = 0.0;
+            update_dirt(self, old_wear, world);
+        }
+    }
+}
+
+/// Shows the wear of `lane` as dirt on it, once it changed by a step since `old_wear`
+pub fn update_dirt(lane: &Lane, old_wear: f32, world: &mut World) {
+    let dirt_step = |wear: f32| (wear * DIRT_STEPS) as usize;
+    if dirt_step(lane.wear) == dirt_step(old_wear) {
+        return;
+    }
+    let dirt = if dirt_step(lane.wear) == 0 {
+        CVec::new()
+    } else {
+        path_to_decals(
+            &lane.construction.path,
+            4.0,
+            DIRT_COLOR,
+            DecalPattern::Speckles { density: dirt_step(lane.wear) as f32 / DIRT_STEPS },
+        ).into()
+    };
+    set_lane_decals(lane, DIRT_DECAL_LAYER, dirt, world);
+}
+
+fn condition_color(wear: f32) -> [f32; 3] {
+    if wear < MIN_REPAIR_WEAR {
+        [0.0, 0.8, 0.2]
+    } else if wear < DEGRADED_WEAR {
+        [1.0, 0.8, 0.0]
+    } else {
+        [1.0, 0.0, 0.0]
+    }
+}
+
+impl Renderable for RoadMaintenance {
+    fn setup_in_scene(&mut self, renderer_id: RendererID, scene_id: usize, world: &mut World) {
+        renderer_id.add_batch(
+            scene_id,
+            CONDITION_MARKER_BATCH_ID,
+            Geometry::new(
+                vec![
+                    Vertex { position: [-1.5, -1.5, 0.2] },
+                    Vertex { position: [1.5, -1.5, 0.2] },
+                    Vertex { position: [1.5, 1.5, 0.2] },
+                    Vertex { position: [-1.5, 1.5, 0.2] },
+                ],
+                vec![0, 1, 2, 2, 3, 0],
+            ),
+            world,
+        );
+    }
+
+    fn render_to_scene(
+        &mut self,
+        renderer_id: RendererID,
+        scene_id: usize,
+        frame: usize,
+        world: &mut World,
+    ) {
+        if self.overlay && !self.conditions.is_empty() {
+            renderer_id.add_several_instances(
+                scene_id,
+                CONDITION_MARKER_BATCH_ID,
+                frame,
+                self.conditions
+                    .iter()
+                    .map(|condition| {
+                        Instance {
+                            instance_position: [
+                                condition.midpoint.x,
+                                condition.midpoint.y,
+                                0.0,
+                            ],
+                            instance_direction: [1.0, 0.0],
+                            instance_color: condition_color(condition.wear),
+                        }
+                    })
+                    .collect(),
+                world,
+            );
+        }
+    }
+}
+
+impl Interactable2d for RoadMaintenance {
+    fn draw_ui_2d(
+        &mut self,
+        imgui_ui: &External<::imgui::Ui<'static>>,
+        return_to: UserInterfaceID,
+        world: &mut World,
+    ) {
+        let ui = imgui_ui.steal();
+        let mut settings = self.settings;
+        let mut settings_changed = false;
+        let mut overlay = self.overlay;
+
+        ui.window(im_str!("Road Maintenance"))
+            .size((300.0, 300.0), ImGuiSetCond_FirstUseEver)
+            .collapsible(true)
+            .build(|| {
+                let wears = self.conditions
+                    .iter()
+                    .map(|condition| condition.wear)
+                    .collect::<Vec<_>>();
+                let n_degraded = wears.iter().filter(|&&wear| wear > DEGRADED_WEAR).count();
+                let average_condition = if wears.is_empty() {
+                    1.0
+                } else {
+                    1.0 - wears.iter().sum::<f32>() / wears.len() as f32
+                };
+                ui.text(im_str!(
+                    "{} lanes, average condition {:.0}%",
+                    wears.len(),
+                    100.0 * average_condition
+                ));
+                ui.text(im_str!("{} lanes badly degraded", n_degraded));
+                ui.text(im_str!(
+                    "Last repairs: {} lanes for {:.0}",
+                    self.repaired_last_round,
+                    self.spent_last_round
+                ));
+
+                settings_changed |= ui.slider_float(
+                    im_str!("Daily budget"),
+                    &mut settings.daily_budget,
+                    0.0,
+                    200_000.0,
+                ).build();
+
+                ui.text(im_str!("Condition overlay:"));
+                if ui.small_button(im_str!("On")) {
+                    overlay = true;
+                }
+                ui.same_line(0.0);
+                if ui.small_button(im_str!("Off")) {
+                    overlay = false;
+                }
+
+                if !wears.is_empty() {
+                    Chart::new("Wear", ChartKind::Histogram(10))
+                        .series("Lanes", &wears)
+                        .draw(&ui);
+                }
+            });
+
+        self.overlay = overlay;
+
+        if settings_changed {
+            self.settings = settings;
+            ::ENV.write_settings(SETTINGS_CATEGORY, &settings);
+        }
+
+        return_to.ui_drawn(ui, world);
+    }
+}
+
+pub fn setup(system: &mut ActorSystem, user_interface: UserInterfaceID, simulation: SimulationID) {
+    system.register::<RoadMaintenance>();
+    system.make_restorable::<RoadMaintenance>();
+    auto_setup(system);
+
+    RoadMaintenanceID::spawn(user_interface, simulation, &mut system.world());
+}
+
+mod kay_auto;
+pub use self::kay_auto::*;
@@ -1,29 +1,29 @@
 use super::Obstacle;
-
-pub const COMFORTABLE_BREAKING_DECELERATION: f32 = 3.0;
+use core::simulation::IDMParameters;
+use core::units::Seconds;
 
 pub fn intelligent_acceleration(
     car: &Obstacle,
     obstacle: &Obstacle,
-    safe_time_headway: f32,
+    safe_time_headway: Seconds,
+    idm: &IDMParameters,
 ) -> f32 {
     // http://en.wikipedia.org/wiki/Intelligent_driver_model
 
-    let car_length = 4.0;
-    let acceleration = 2.0;
-    let max_deceleration: f32 = 8.0;
+    let acceleration = idm.acceleration;
+    let max_deceleration = idm.max_deceleration;
     let desired_velocity = car.max_velocity;
-    let acceleration_exponent = 8.0;
-    let minimum_spacing = 4.0;
+    let acceleration_exponent = idm.acceleration_exponent;
+    let minimum_spacing = idm.minimum_spacing;
 
-    let net_distance = *obstacle.position - *car.position - car_length;
+    let net_distance = obstacle.rear() - *car.position;
     let velocity_difference = car.velocity - obstacle.velocity;
 
     let s_star = minimum_spacing +
         0.0f32.max(
-            car.velocity * safe_time_headway +
+            car.velocity * safe_time_headway.0 +
                 (car.velocity * velocity_difference /
-                     (2.0 * (acceleration * COMFORTABLE_BREAKING_DECELERATION).sqrt())),
+                     (2.0 * (acceleration * idm.comfortable_deceleration).sqrt())),
         );
 
     (-max_deceleration).max(
@@ -0,0 +1,298 @@
+//! A queue-based mesoscopic traffic model, used instead of car following for lanes far
+//! away from the camera (see `transport::chunks`) or for all lanes with
+//! `mesoscopic_traffic` set in the "Simulation" settings, when city scale matters more
+//! than seeing cars move.
+//!
+//! Each lane is a FIFO queue of cars. A car can leave the lane once it would have driven
+//! through it at free-flow speed, but only after the cars in front of it left, no faster
+//! than the flow capacity of the lane allows, on green and if there is room for it on
+//! the next lane. Cars are routed, handed over and finish their trips just like with car
+//! following. To other lanes the queue looks like cars driving and standing in line at
+//! its end, so congestion spills back over lanes of both models.
+//!
+//! When a lane switches models because the camera moved (see `set_traffic_fidelity`),
+//! its cars are converted right away: driven cars join the queue in the order they are
+//! in, and queued cars become driven ones again where the queue state says they are,
+//! keeping their trips and what was counted of them. When the global setting changes,
+//! cars on a lane are simulated with the model they entered with until they leave it.
+use compact::CVec;
+use ordered_float::OrderedFloat;
+use std::f32::INFINITY;
+use std::sync::atomic::Ordering;
+use kay::World;
+use core::simulation::{Timestamp, Ticks, SimulationConfig, TICKS_PER_SIM_SECOND};
+use super::{LaneCar, Obstacle};
+use super::super::lane::Lane;
+use super::super::lane::connectivity::{Interaction, InteractionKind, OverlapKind};
+use super::super::restrictions::VehicleClass;
+use super::super::chunks::Fidelity;
+use super::super::maintenance;
+use super::super::services::winter;
+
+/// Cars never go slower than this on an empty lane, even in deep snow
+const MIN_FREE_FLOW_VELOCITY: f32 = 1.0;
+
+#[derive(Copy, Clone)]
+pub struct QueuedCar {
+    pub car: LaneCar,
+    /// Where on the lane the car leaves it
+    pub exit_position: f32,
+    pub free_flow_velocity: f32,
+    /// The tick the car reaches `exit_position` driving at `free_flow_velocity`
+    pub ready_at: Timestamp,
+}
+
+#[derive(Compact, Clone)]
+pub struct MesoscopicTraffic {
+    /// The first car leaves first
+    pub queue: CVec<QueuedCar>,
+    last_exit: Timestamp,
+}
+
+impl MesoscopicTraffic {
+    pub fn new() -> Self {
+        MesoscopicTraffic { queue: CVec::new(), last_exit: Timestamp::new(0) }
+    }
+
+    /// The queued cars as if they were driven: driving towards the end of the lane at
+    /// free-flow speed, or standing in line behind the car in front of them.
+    /// Sorted by position, like the cars of a lane
+    pub fn cars_as_driven(&self, now: Timestamp, config: &SimulationConfig) -> Vec<LaneCar> {
+        let mut position_limit = INFINITY;
+        let mut cars = self.queue
+            .iter()
+            .map(|queued| {
+                let remaining_ticks = queued.ready_at.ticks().saturating_sub(now.ticks());
+                let free_flow_position = queued.exit_position -
+                    remaining_ticks as f32 *
+                        meters_per_tick(queued.free_flow_velocity, config.microtraffic_slowdown);
+                let (position, velocity) =
+                    if remaining_ticks > 0 && free_flow_position < position_limit {
+                        (free_flow_position, queued.free_flow_velocity)
+                    } else {
+                        (free_flow_position.min(position_limit), 0.0)
+                    };
+                position_limit = position - queued.car.length - config.idm.minimum_spacing;
+                LaneCar {
+                    as_obstacle: Obstacle {
+                        position: OrderedFloat(position),
+                        velocity,
+                        ..queued.car.as_obstacle
+                    },
+                    acceleration: 0.0,
+                    ..queued.car
+                }
+            })
+            .collect::<Vec<_>>();
+        cars.reverse();
+        cars
+    }
+}
+
+fn meters_per_tick(velocity: f32, slowdown: f32) -> f32 {
+    velocity / (slowdown * TICKS_PER_SIM_SECOND as f32)
+}
+
+/// Ticks it takes to drive `distance` at `velocity`, in slowed down microtraffic time
+pub fn free_flow_ticks(distance: f32, velocity: f32, slowdown: f32) -> usize {
+    (distance.max(0.0) / meters_per_tick(velocity, slowdown)).ceil().max(1.0) as usize
+}
+
+/// Ticks between two cars leaving a lane, so a lane lets through as many cars as
+/// cars following each other at the usual headway would
+pub fn flow_headway_ticks(config: &SimulationConfig) -> usize {
+    (config.car_headway.0 * config.microtraffic_slowdown * TICKS_PER_SIM_SECOND as f32)
+        .ceil()
+        .max(1.0) as usize
+}
+
+impl Lane {
+    /// How traffic on the lane is simulated, taking the global setting into account
+    pub fn traffic_fidelity(&self, config: &SimulationConfig) -> Fidelity {
+        if config.mesoscopic_traffic {
+            Fidelity::Mesoscopic
+        } else {
+            self.microtraffic.fidelity
+        }
+    }
+
+    /// Where a car with the next hop it has leaves the lane
+    fn exit_position(&self, car: &LaneCar) -> f32 {
+        match self.connectivity.interactions[car.next_hop_interaction as usize] {
+            Interaction {
+                start,
+                kind: InteractionKind::Overlap { end, kind: OverlapKind::Transfer, .. },
+                ..
+            } => start.max(end - 300.0),
+            Interaction { start, .. } => start,
+        }
+    }
+
+    /// Switches the lane to `fidelity`, converting its cars to the model that uses
+    pub fn set_traffic_fidelity(&mut self, fidelity: Fidelity) {
+        let config = SimulationConfig::current();
+        let was_mesoscopic = self.traffic_fidelity(&config) == Fidelity::Mesoscopic;
+        self.microtraffic.fidelity = fidelity;
+        let is_mesoscopic = self.traffic_fidelity(&config) == Fidelity::Mesoscopic;
+
+        let now = self.microtraffic.last_tick;
+        if is_mesoscopic && !was_mesoscopic {
+            self.queue_driven_cars(now, &config);
+        } else if was_mesoscopic && !is_mesoscopic {
+            self.drive_queued_cars(now, &config);
+        }
+    }
+
+    /// Moves all driven cars to the end of the queue, the first one first
+    fn queue_driven_cars(&mut self, now: Timestamp, config: &SimulationConfig) {
+        let cars = self.microtraffic.cars.clone();
+        self.microtraffic.cars.clear();
+        for car in cars.iter().rev() {
+            self.enqueue_car(*car, now, config);
+        }
+    }
+
+    /// Turns all queued cars into driven ones, where the queue state says they are
+    fn drive_queued_cars(&mut self, now: Timestamp, config: &SimulationConfig) {
+        let driven_cars = self.microtraffic.mesoscopic.cars_as_driven(now, config);
+        // meters driven are only counted when queued cars leave the lane otherwise
+        let entry_positions = self.microtraffic
+            .mesoscopic
+            .queue
+            .iter()
+            .map(|queued| *queued.car.position)
+            .sum::<f32>();
+        let driven_positions = driven_cars.iter().map(|car| *car.position).sum::<f32>();
+        self.microtraffic.meters_driven += (driven_positions - entry_positions).max(0.0);
+        self.microtraffic.mesoscopic.queue.clear();
+
+        let mut cars = self.microtraffic.cars.iter().cloned().collect::<Vec<_>>();
+        cars.extend(driven_cars);
+        cars.sort_by_key(|car| car.position);
+        if cars.len() > 1 {
+            for i in (0..cars.len() - 1).rev() {
+                cars[i] = cars[i].kept_behind(&cars[i + 1]);
+            }
+        }
+        self.microtraffic.cars = cars.into();
+    }
+
+    pub fn enqueue_car(&mut self, car: LaneCar, tick: Timestamp, config: &SimulationConfig) {
+        let condition_factor = maintenance::speed_factor(self.wear) *
+            winter::snow_speed_factor(self.snow);
+        let free_flow_velocity = (car.max_velocity * condition_factor)
+            .min(self.road_class.speed_limit(car.vehicle))
+            .max(MIN_FREE_FLOW_VELOCITY);
+        let exit_position = self.exit_position(&car);
+        let ticks = free_flow_ticks(
+            exit_position - *car.position,
+            free_flow_velocity,
+            config.microtraffic_slowdown,
+        );
+        self.microtraffic.mesoscopic.queue.push(QueuedCar {
+            car,
+            exit_position,
+            free_flow_velocity,
+            ready_at: tick + Ticks(ticks),
+        });
+    }
+
+    /// Whether the first car of the queue can leave the lane now
+    fn can_dequeue(
+        &self,
+        queued: &QueuedCar,
+        now: Timestamp,
+        config: &SimulationConfig,
+    ) -> bool {
+        let last_exit = self.microtraffic.mesoscopic.last_exit;
+        if queued.ready_at > now ||
+            (last_exit.ticks() > 0 &&
+                 now.ticks() < last_exit.ticks() + flow_headway_ticks(config))
+        {
+            return false;
+        }
+
+        let next_hop = queued.car.next_hop_interaction as usize;
+        if let InteractionKind::Next { green } = self.connectivity.interactions[next_hop].kind {
+            // emergency vehicles have priority and drive through red lights
+            if !green && queued.car.vehicle != VehicleClass::Emergency {
+                return false;
+            }
+        }
+
+        let needed_room = queued.exit_position + queued.car.length + config.idm.minimum_spacing;
+        !self.microtraffic.obstacles.iter().any(|&(ref obstacle, _)| {
+            *obstacle.position > queued.exit_position && obstacle.rear() < needed_room
+        })
+    }
+
+    pub fn tick_mesoscopic(
+        &mut self,
+        current_tick: Timestamp,
+        config: &SimulationConfig,
+        world: &mut World,
+    ) {
+        let n_queued = self.microtraffic.mesoscopic.queue.len();
+        let n_waiting = self.microtraffic
+            .mesoscopic
+            .queue
+            .iter()
+            .filter(|queued| queued.ready_at <= current_tick)
+            .count();
+        self.microtraffic.waiting_ticks += n_waiting;
+        ::core::metrics::STOPPED_VEHICLE_TICKS.fetch_add(n_waiting, Ordering::Relaxed);
+        ::core::metrics::VEHICLE_TICKS.fetch_add(n_queued, Ordering::Relaxed);
+
+        let maybe_first = self.microtraffic.mesoscopic.queue.first().cloned();
+        if let Some(first) = maybe_first {
+            if self.can_dequeue(&first, current_tick, config) {
+                self.microtraffic.mesoscopic.queue.remove(0);
+                self.microtraffic.mesoscopic.last_exit = current_tick;
+                self.microtraffic.meters_driven += first.exit_position - *first.car.position;
+
+                let interaction =
+                    self.connectivity.interactions[first.car.next_hop_interaction as usize];
+                let car = LaneCar {
+                    as_obstacle: Obstacle {
+                        position: OrderedFloat(first.exit_position),
+                        velocity: first.free_flow_velocity,
+                        ..first.car.as_obstacle
+                    },
+                    acceleration: 0.0,
+                    ..first.car
+                };
+                self.hand_over_car(
+                    car,
+                    interaction.partner_lane,
+                    interaction.start,
+                    interaction.partner_start,
+                    current_tick,
+                    world,
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::units::Seconds;
+
+    #[test]
+    fn free_flow_time_is_in_slowed_down_ticks() {
+        // 100m at 10m/s take 10s of microtraffic time, which is 200s when it's 20x slower
+        assert_eq!(free_flow_ticks(100.0, 10.0, 20.0), 200 * TICKS_PER_SIM_SECOND);
+        assert_eq!(free_flow_ticks(0.0, 10.0, 20.0), 1);
+    }
+
+    #[test]
+    fn flow_capacity_matches_car_headway() {
+        let config = SimulationConfig {
+            car_headway: Seconds(2.0),
+            microtraffic_slowdown: 20.0,
+            ..SimulationConfig::default()
+        };
+        assert_eq!(flow_headway_ticks(&config), 40 * TICKS_PER_SIM_SECOND);
+    }
+}
@@ -1,55 +1,268 @@
 use kay::{ActorSystem, World};
-use compact::CVec;
+use compact::{CVec, Compact};
 use ordered_float::OrderedFloat;
 use std::f32::INFINITY;
 use std::ops::{Deref, DerefMut};
+use std::sync::atomic::Ordering;
+use descartes::FiniteCurve;
 
 use super::lane::{Lane, LaneID, TransferLane, TransferLaneID};
 use super::lane::connectivity::{Interaction, InteractionKind, OverlapKind};
 use super::pathfinding;
+use super::detectors::Detector;
+use super::construction::crews::{ConstructionCrewsID, work_zone_change};
+use super::pathfinding::trip::CancelReason;
+use super::event_log::{self, EventKind};
+use super::spatial_index::SpatialIndexID;
+use super::street_furniture::StreetFurnitureID;
+use super::rendering::markings::LANE_WIDTH;
+use super::chunks::Fidelity;
+use super::maintenance;
+use super::services::winter;
+use economy::buildings::BuildingID;
+use sound::SoundEvent;
+use terrain::TerrainID;
+use core::city_events::{self, CityEventKind};
+use core::units::{Meters, MetersPerSecond};
 
 mod intelligent_acceleration;
 use self::intelligent_acceleration::intelligent_acceleration;
+pub mod mesoscopic;
+use self::mesoscopic::MesoscopicTraffic;
+
+/// Obstacles of a partner that didn't send new ones for this many traffic cycles
+/// are forgotten, since the partner might be gone without saying so
+const OBSTACLE_EXPIRY_CYCLES: usize = 3;
 
 #[derive(Compact, Clone)]
 pub struct Microtraffic {
     pub obstacles: CVec<(Obstacle, LaneLikeID)>,
+    /// When the latest batch of obstacles from each partner arrived
+    obstacle_batches: CVec<(LaneLikeID, Timestamp)>,
+    /// The tick the lane was last simulated in
+    last_tick: Timestamp,
     pub cars: CVec<LaneCar>,
     timings: CVec<bool>,
     pub green: bool,
     pub yellow_to_green: bool,
     pub yellow_to_red: bool,
+    pub headlights: bool,
+    jammed_since: Option<Timestamp>,
+    gridlock_reported: bool,
+    /// Cars that entered the lane since turning movements were last counted
+    pub cars_entered: usize,
+    /// Ticks that cars stood (almost) still on the lane, summed over all cars,
+    /// since turning movements were last counted
+    pub waiting_ticks: usize,
+    pub detectors: CVec<Detector>,
+    /// Meters driven on the lane that weren't counted in the metrics yet
+    meters_driven: f32,
+    /// Depends on how far the lane is from the camera, see `transport::chunks`
+    pub fidelity: Fidelity,
+    /// The least exact fidelity that partners might be simulated at, which is that of
+    /// far away chunks. Their obstacles only expire after that many slower cycles
+    pub slowest_partner_fidelity: Fidelity,
+    /// Cars simulated with the mesoscopic model instead of car following
+    pub mesoscopic: MesoscopicTraffic,
 }
 
 impl Microtraffic {
+    pub fn forget_obstacles_from(&mut self, partner: LaneLikeID) {
+        self.obstacles.retain(|&(_, received_from)| received_from != partner);
+        self.obstacle_batches.retain(|&(received_from, _)| received_from != partner);
+    }
+
+    /// Forgets the obstacles of partners that didn't send any for a while
+    fn expire_obstacles(&mut self, current_tick: Timestamp, ticks_per_cycle: usize) {
+        let max_age = OBSTACLE_EXPIRY_CYCLES * ticks_per_cycle;
+        let expired = self.obstacle_batches
+            .iter()
+            .filter(|&&(_, received)| current_tick.ticks() > received.ticks() + max_age)
+            .map(|&(partner, _)| partner)
+            .collect::<Vec<_>>();
+        for partner in expired {
+            self.forget_obstacles_from(partner);
+        }
+    }
+
     pub fn new(timings: CVec<bool>) -> Self {
         Microtraffic {
             obstacles: CVec::new(),
+            obstacle_batches: CVec::new(),
+            last_tick: Timestamp::new(0),
             cars: CVec::new(),
             timings: timings,
             green: false,
             yellow_to_green: false,
             yellow_to_red: false,
+            headlights: false,
+            jammed_since: None,
+            gridlock_reported: false,
+            cars_entered: 0,
+            waiting_ticks: 0,
+            detectors: CVec::new(),
+            meters_driven: 0.0,
+            fidelity: Fidelity::Full,
+            slowest_partner_fidelity: Fidelity::Full,
+            mesoscopic: MesoscopicTraffic::new(),
+        }
+    }
+}
+
+fn has_finite_state(car: &LaneCar) -> bool {
+    car.position.is_finite() && car.velocity.is_finite() && car.acceleration.is_finite()
+}
+
+/// Removes cars for which `is_broken` holds, cancelling their trips, so that one bad
+/// geometric input can't spread through obstacles to the rest of the lane and its
+/// partners. Debug builds stop right there instead, to be able to find the cause.
+fn despawn_broken_cars<C, F>(
+    cars: &mut CVec<C>,
+    is_broken: F,
+    lane: LaneLikeID,
+    world: &mut World,
+) where
+    C: Deref<Target = LaneCar> + Compact + Clone,
+    F: Fn(&C) -> bool,
+{
+    let broken_trips = cars.iter()
+        .filter(|car| is_broken(*car))
+        .map(|car| car.trip)
+        .collect::<Vec<_>>();
+    debug_assert!(
+        broken_trips.is_empty(),
+        "Cars of trips {:?} on lane {:?} have non-finite state",
+        broken_trips,
+        lane
+    );
+
+    if !broken_trips.is_empty() {
+        for trip in broken_trips {
+            println!(
+                "Despawning car of trip {:?} on lane {:?}, its state isn't finite",
+                trip,
+                lane
+            );
+            trip.cancel(CancelReason::BrokenCarState, world);
         }
+        cars.retain(|car| !is_broken(car));
     }
 }
 
-// makes "time pass slower" for traffic, so we can still use realistic
-// unit values while traffic happening at a slower pace to be visible
-const MICROTRAFFIC_UNREALISTIC_SLOWDOWN: f32 = 20.0;
+/// Lanes are split into stretches this long to see how traffic varies along them
+pub const TRAFFIC_SEGMENT_LENGTH: f32 = 20.0;
+
+/// How traffic flows in one stretch of a lane
+#[derive(Copy, Clone, Debug)]
+pub struct SegmentTraffic {
+    /// Cars per km
+    pub density: f32,
+    /// Average speed of the cars relative to how fast they'd like to go,
+    /// 1.0 if there are no cars
+    pub speed_ratio: f32,
+}
+
+impl Microtraffic {
+    /// Whether the lane ever gets a red light
+    pub fn is_signalized(&self) -> bool {
+        self.timings.iter().any(|&green| !green)
+    }
+
+    /// Whether the lane is green, for each 10 ticks of the signal cycle
+    pub fn timings(&self) -> &CVec<bool> {
+        &self.timings
+    }
+
+    /// Cars crawling or standing on the lane, as a loop detector would see them
+    pub fn queue_length(&self) -> usize {
+        self.cars
+            .iter()
+            .filter(|car| car.velocity < QUEUE_MAX_VELOCITY)
+            .count()
+    }
+
+    /// Traffic in each consecutive stretch of `TRAFFIC_SEGMENT_LENGTH` along a lane
+    /// of `length`, so stop-and-go waves moving backwards along the lane can be seen
+    pub fn segment_traffic(&self, length: f32) -> Vec<SegmentTraffic> {
+        let n_segments = (length / TRAFFIC_SEGMENT_LENGTH).ceil().max(1.0) as usize;
+        let mut sums = vec![(0, 0.0, 0.0); n_segments];
+
+        for car in self.cars.iter() {
+            let idx = ((*car.position).max(0.0) / TRAFFIC_SEGMENT_LENGTH) as usize;
+            let sum = &mut sums[idx.min(n_segments - 1)];
+            sum.0 += 1;
+            sum.1 += car.velocity;
+            sum.2 += car.max_velocity;
+        }
+
+        sums.iter()
+            .enumerate()
+            .map(|(i, &(n_cars, velocities, max_velocities))| {
+                // the last stretch is usually shorter
+                let segment_length = (length - i as f32 * TRAFFIC_SEGMENT_LENGTH)
+                    .min(TRAFFIC_SEGMENT_LENGTH)
+                    .max(1.0);
+                SegmentTraffic {
+                    density: n_cars as f32 / segment_length * 1000.0,
+                    speed_ratio: if max_velocities > 0.0 {
+                        (velocities / max_velocities).max(0.0).min(1.0)
+                    } else {
+                        1.0
+                    },
+                }
+            })
+            .collect()
+    }
+}
 
 #[derive(Compact, Clone, Default)]
 pub struct TransferringMicrotraffic {
     pub left_obstacles: CVec<Obstacle>,
     pub right_obstacles: CVec<Obstacle>,
     pub cars: CVec<TransferringLaneCar>,
+    pub headlights: bool,
+    /// Where a construction crew on one side (left if `true`) blocks the other side
+    pub work_zone: Option<(bool, f32)>,
+}
+
+pub const CAR_LENGTH: Meters = Meters(4.0);
+pub const BUS_LENGTH: Meters = Meters(12.0);
+pub const ARTICULATED_VEHICLE_LENGTH: Meters = Meters(18.0);
+
+pub fn vehicle_length(vehicle: VehicleClass) -> Meters {
+    match vehicle {
+        VehicleClass::Car | VehicleClass::HighOccupancyCar => CAR_LENGTH,
+        VehicleClass::Bus | VehicleClass::Emergency => BUS_LENGTH,
+        VehicleClass::Truck | VehicleClass::OversizeTruck => ARTICULATED_VEHICLE_LENGTH,
+    }
 }
 
+pub fn vehicle_max_velocity(vehicle: VehicleClass) -> MetersPerSecond {
+    match vehicle {
+        VehicleClass::Car | VehicleClass::HighOccupancyCar => MetersPerSecond(15.0),
+        VehicleClass::Bus | VehicleClass::Truck => MetersPerSecond(12.0),
+        VehicleClass::OversizeTruck => MetersPerSecond(8.0),
+        VehicleClass::Emergency => MetersPerSecond(20.0),
+    }
+}
+
+// a lane counts as gridlocked when at least this many cars
+// stood (almost) still on it for that many ticks
+const GRIDLOCK_MIN_CARS: usize = 3;
+const GRIDLOCK_MAX_VELOCITY: f32 = 0.5;
+const GRIDLOCK_TICKS: usize = 1800;
+/// Cars slower than this count as queued
+const QUEUE_MAX_VELOCITY: f32 = 2.0;
+
+/// `position` is the front of the obstacle, it extends `length` backwards from there.
+/// Positions and lengths are in m, velocities in m/s, kept as plain `f32` for integrating
+/// them every tick - typed `core::units` quantities are converted at the boundaries
 #[derive(Copy, Clone)]
 pub struct Obstacle {
     pub position: OrderedFloat<f32>,
     pub velocity: f32,
     pub max_velocity: f32,
+    pub length: f32,
 }
 
 impl Obstacle {
@@ -58,6 +271,7 @@ impl Obstacle {
             position: OrderedFloat(INFINITY),
             velocity: INFINITY,
             max_velocity: INFINITY,
+            length: 0.0,
         }
     }
     fn far_behind() -> Obstacle {
@@ -65,8 +279,20 @@ impl Obstacle {
             position: OrderedFloat(-INFINITY),
             velocity: 0.0,
             max_velocity: 20.0,
+            length: 0.0,
+        }
+    }
+    fn stop_at(position: f32) -> Obstacle {
+        Obstacle {
+            position: OrderedFloat(position),
+            velocity: 0.0,
+            max_velocity: 0.0,
+            length: 0.0,
         }
     }
+    pub fn rear(&self) -> f32 {
+        *self.position - self.length
+    }
     fn offset_by(&self, delta: f32) -> Obstacle {
         Obstacle {
             position: OrderedFloat(*self.position + delta),
@@ -76,7 +302,8 @@ impl Obstacle {
 }
 
 use super::pathfinding::trip::TripID;
-use super::pathfinding::{RoughLocationID, Node};
+use super::restrictions::VehicleClass;
+use super::pathfinding::{Node, NodeID};
 
 #[derive(Copy, Clone)]
 pub struct LaneCar {
@@ -85,6 +312,10 @@ pub struct LaneCar {
     pub acceleration: f32,
     pub destination: pathfinding::Location,
     pub next_hop_interaction: u8,
+    pub vehicle: VehicleClass,
+    /// Follows the routes lanes had at the start of the day,
+    /// see `transport::pathfinding::route_learning`
+    pub habitual: bool,
 }
 
 impl LaneCar {
@@ -94,6 +325,24 @@ impl LaneCar {
             ..*self
         }
     }
+
+    /// The car moved back behind `car_ahead` and slowed down to its velocity, if it
+    /// would overlap it otherwise. Cars coming from a lane at reduced fidelity
+    /// might not have braked in time for what's ahead of them on the new lane
+    fn kept_behind(&self, car_ahead: &Obstacle) -> LaneCar {
+        if *self.position > car_ahead.rear() {
+            LaneCar {
+                as_obstacle: Obstacle {
+                    position: OrderedFloat(car_ahead.rear()),
+                    velocity: self.velocity.min(car_ahead.velocity),
+                    ..self.as_obstacle
+                },
+                ..*self
+            }
+        } else {
+            *self
+        }
+    }
 }
 
 impl Deref for LaneCar {
@@ -148,10 +397,8 @@ pub trait LaneLike {
 
 use self::pathfinding::RoutingInfo;
 
-use core::simulation::{Simulatable, SimulatableID, MSG_Simulatable_tick};
-
-const TRAFFIC_LOGIC_THROTTLING: usize = 30;
-const PATHFINDING_THROTTLING: usize = 10;
+use core::simulation::{Simulatable, SimulatableID, MSG_Simulatable_tick, TimeOfDay,
+                       SimulationConfig};
 
 impl LaneLike for Lane {
     fn add_car(
@@ -164,16 +411,39 @@ impl LaneLike for Lane {
         // TODO: horrible hack to encode it like this
         let car_forcibly_spawned = *car.as_obstacle.position < 0.0;
 
-        let maybe_next_hop_interaction =
+        if !self.construction.is_finished() {
+            car.trip.fail_at(
+                self.id.into(),
+                tick,
+                world,
+            );
+            return;
+        }
+
+        if self.construction.is_unbuilding() {
+            car.trip.cancel(CancelReason::LaneRemoved, world);
+            return;
+        }
+
+        // cars starting out on a restricted lane may always leave it
+        if !car_forcibly_spawned && !self.restriction.permits(car.vehicle) {
+            car.trip.fail_at(
+                self.id.into(),
+                tick,
+                world,
+            );
+            return;
+        }
+
+        let maybe_habitual_next_hop = if car.habitual {
+            self.habitual_next_hop(car.destination)
+        } else {
+            None
+        };
+
+        let maybe_next_hop_interaction = maybe_habitual_next_hop.or_else(|| {
             self.pathfinding
-                .routes
-                .get(car.destination)
-                .or_else(|| {
-                    self.pathfinding.routes.get(
-                        car.destination
-                            .landmark_destination(),
-                    )
-                })
+                .route_for(car.destination, car.vehicle)
                 .or_else(|| {
                     println!("NO ROUTE!");
                     if car_forcibly_spawned || self.pathfinding.routes.is_empty() {
@@ -186,7 +456,8 @@ impl LaneLike for Lane {
                         )
                     }
                 })
-                .map(|&RoutingInfo { outgoing_idx, .. }| outgoing_idx as usize);
+                .map(|&RoutingInfo { outgoing_idx, .. }| outgoing_idx as usize)
+        });
 
         let spawn_possible = if car_forcibly_spawned {
             if self.last_spawn_position > 2.0 {
@@ -201,32 +472,59 @@ impl LaneLike for Lane {
         if let (Some(next_hop_interaction), Some(_)) =
             (maybe_next_hop_interaction, spawn_possible)
         {
+            debug_assert!(
+                next_hop_interaction < self.connectivity.interactions.len(),
+                "routed car to a nonexistent interaction"
+            );
             let routed_car = LaneCar {
                 next_hop_interaction: next_hop_interaction as u8,
                 as_obstacle: if car_forcibly_spawned {
-                    self.last_spawn_position -= 6.0;
+                    let spawn_spacing = car.length + SimulationConfig::current().spawn_spacing.0;
+                    self.last_spawn_position -= spawn_spacing;
                     car.as_obstacle
                         .offset_by(-*car.as_obstacle.position)
-                        .offset_by(self.last_spawn_position + 6.0)
+                        .offset_by(self.last_spawn_position + spawn_spacing)
                 } else {
                     car.as_obstacle
                 },
                 ..car
             };
 
-            // TODO: optimize using BinaryHeap?
-            let maybe_next_car_position = self.microtraffic.cars.iter().position(|other_car| {
-                other_car.as_obstacle.position > car.as_obstacle.position
-            });
-            match maybe_next_car_position {
-                Some(next_car_position) => {
-                    self.microtraffic.cars.insert(next_car_position, routed_car)
+            let config = SimulationConfig::current();
+            if self.traffic_fidelity(&config) == Fidelity::Mesoscopic {
+                self.enqueue_car(routed_car, tick, &config);
+            } else {
+                // TODO: optimize using BinaryHeap?
+                let maybe_next_car_position =
+                    self.microtraffic.cars.iter().position(|other_car| {
+                        other_car.as_obstacle.position > car.as_obstacle.position
+                    });
+                match maybe_next_car_position {
+                    Some(next_car_position) => {
+                        let routed_car = if car_forcibly_spawned {
+                            routed_car
+                        } else {
+                            routed_car.kept_behind(&self.microtraffic.cars[next_car_position])
+                        };
+                        self.microtraffic.cars.insert(next_car_position, routed_car)
+                    }
+                    None => self.microtraffic.cars.push(routed_car),
                 }
-                None => self.microtraffic.cars.push(routed_car),
+            }
+            self.microtraffic.cars_entered += 1;
+            self.road_class.vehicles_entered += 1;
+            if !car_forcibly_spawned {
+                event_log::log(
+                    tick,
+                    EventKind::EnteredLink,
+                    car.trip,
+                    Some(self.id._raw_id.instance_id),
+                    car.vehicle,
+                );
             }
         } else {
             car.trip.fail_at(
-                RoughLocationID { _raw_id: self.id._raw_id },
+                self.id.into(),
                 tick,
                 world,
             );
@@ -234,18 +532,43 @@ impl LaneLike for Lane {
     }
 
     fn add_obstacles(&mut self, obstacles: &CVec<Obstacle>, from: LaneLikeID, _: &mut World) {
-        self.microtraffic.obstacles.retain(|&(_, received_from)| {
-            received_from != from
-        });
-        self.microtraffic.obstacles.extend(obstacles.iter().map(
-            |obstacle| {
-                (*obstacle, from)
-            },
-        ));
+        self.microtraffic.forget_obstacles_from(from);
+        if !obstacles.is_empty() && !self.construction.is_unbuilding() {
+            self.microtraffic.obstacles.extend(obstacles.iter().map(
+                |obstacle| {
+                    (*obstacle, from)
+                },
+            ));
+            let received = self.microtraffic.last_tick;
+            self.microtraffic.obstacle_batches.push((from, received));
+        }
     }
 }
 
 impl Lane {
+    pub fn override_signal_timings(
+        &mut self,
+        instance_id: u32,
+        timings: &CVec<bool>,
+        _: &mut World,
+    ) {
+        if self.id._raw_id.instance_id == instance_id && !self.microtraffic.timings.is_empty() {
+            self.microtraffic.timings = timings.clone();
+        }
+    }
+
+    /// Turns the lane into a signalized one, or back into an unsignalized one
+    /// if `timings` are empty
+    pub fn set_signal_timings(&mut self, timings: &CVec<bool>, world: &mut World) {
+        let was_signalized = self.microtraffic.is_signalized();
+        self.microtraffic.timings = timings.clone();
+        if self.connectivity.on_intersection &&
+            self.microtraffic.is_signalized() != was_signalized
+        {
+            super::rendering::on_signalized_changed(self, world);
+        }
+    }
+
     pub fn on_signal_changed(&mut self, from: LaneLikeID, green: bool, _: &mut World) {
         if let Some(interaction) =
             self.connectivity.interactions.iter_mut().find(
@@ -268,14 +591,162 @@ impl Lane {
     }
 }
 
+impl Lane {
+    /// Passes `car`, which reached `start` of the interaction with `next_lane`, on to it,
+    /// or finishes its trip if this is where it wanted to go
+    fn hand_over_car(
+        &mut self,
+        car: LaneCar,
+        next_lane: LaneLikeID,
+        start: f32,
+        partner_start: f32,
+        current_tick: Timestamp,
+        world: &mut World,
+    ) {
+        let old_wear = self.wear;
+        self.wear = (self.wear + maintenance::wear_per_passage(car.vehicle)).min(1.0);
+        maintenance::update_dirt(self, old_wear, world);
+        if NodeID::from(self.id) == car.destination.node {
+            car.trip.succeed(current_tick, world);
+        } else {
+            event_log::log(
+                current_tick,
+                EventKind::LeftLink,
+                car.trip,
+                Some(self.id._raw_id.instance_id),
+                car.vehicle,
+            );
+            next_lane.add_car(
+                car.offset_by(partner_start - start),
+                Some(self.id.into()),
+                current_tick,
+                world,
+            );
+        }
+    }
+
+    fn check_gridlock(&mut self, current_tick: Timestamp, world: &mut World) {
+        let jammed = self.microtraffic.cars.len() >= GRIDLOCK_MIN_CARS &&
+            self.microtraffic.cars.iter().all(
+                |car| car.velocity < GRIDLOCK_MAX_VELOCITY,
+            );
+
+        if !jammed {
+            self.microtraffic.jammed_since = None;
+            self.microtraffic.gridlock_reported = false;
+        } else if let Some(since) = self.microtraffic.jammed_since {
+            if !self.microtraffic.gridlock_reported &&
+                current_tick.ticks() - since.ticks() > GRIDLOCK_TICKS
+            {
+                self.microtraffic.gridlock_reported = true;
+                city_events::publish(
+                    CityEventKind::Gridlock,
+                    "Traffic has come to a complete standstill",
+                    Some(self.construction.path.along(self.construction.length / 2.0)),
+                    world,
+                );
+            }
+        } else {
+            self.microtraffic.jammed_since = Some(current_tick);
+        }
+    }
+}
+
+impl Lane {
+    /// Lets everything that only deals with finished lanes know about this one
+    pub fn open(&mut self, world: &mut World) {
+        self.pathfinding.routes_changed = true;
+        SpatialIndexID::local_first(world).add_lane(
+            self.id,
+            self.construction.path.clone(),
+            world,
+        );
+        StreetFurnitureID::local_first(world).add_lane(
+            self.id,
+            self.construction.path.clone(),
+            self.connectivity.on_intersection,
+            self.road_class.class(),
+            world,
+        );
+        TerrainID::local_first(world).level_along(
+            self.construction.path.clone(),
+            LANE_WIDTH,
+            true,
+            world,
+        );
+        if !self.connectivity.on_intersection {
+            BuildingID::global_broadcast(world).on_lane_opened(
+                self.id,
+                self.construction.path.clone(),
+                world,
+            );
+        }
+    }
+
+    /// Lets the transfer lanes next to this construction site know whether the crew
+    /// is `blocking` the open lanes on their other side, where it is working right now
+    fn announce_work_zone(&self, blocking: bool, world: &mut World) {
+        for interaction in self.connectivity.interactions.iter() {
+            if let InteractionKind::Overlap { end, kind: OverlapKind::Transfer, .. } =
+                interaction.kind
+            {
+                let progress = self.construction.progress;
+                let spot = if blocking && interaction.start <= progress && progress <= end {
+                    Some(interaction.partner_start + (progress - interaction.start))
+                } else {
+                    None
+                };
+                TransferLaneID::from(interaction.partner_lane).work_zone(self.id, spot, world);
+            }
+        }
+    }
+}
+
 impl Simulatable for Lane {
     fn tick(&mut self, dt: f32, current_tick: Timestamp, world: &mut World) {
-        let dt = dt / MICROTRAFFIC_UNREALISTIC_SLOWDOWN;
+        // partners are dropping this lane, so it shouldn't send them anything anymore
+        if self.construction.is_unbuilding() {
+            return;
+        }
 
-        self.construction.progress += dt * 400.0;
+        if !self.construction.is_finished() {
+            // construction happens in unslowed simulation time
+            self.construction.progress += dt * self.construction.build_rate;
+            if self.construction.is_finished() {
+                super::rendering::set_lane_decals(
+                    self,
+                    super::rendering::CONSTRUCTION_DECAL_LAYER,
+                    CVec::new(),
+                    world,
+                );
+                ConstructionCrewsID::local_first(world).project_done(
+                    self.id.into(),
+                    Some(self.construction.path.start()),
+                    world,
+                );
+                self.announce_work_zone(false, world);
+                self.open(world);
+            } else if self.construction.build_rate > 0.0 {
+                let instance_id = self.id._raw_id.instance_id;
+                if let Some(blocking) = work_zone_change(instance_id, current_tick) {
+                    self.announce_work_zone(blocking, world);
+                }
+            }
+        }
 
-        let do_traffic = current_tick.ticks() % TRAFFIC_LOGIC_THROTTLING ==
-            self.id._raw_id.instance_id as usize % TRAFFIC_LOGIC_THROTTLING;
+        let config = SimulationConfig::current();
+        // makes "time pass slower" for traffic, so we can still use realistic
+        // unit values while traffic happening at a slower pace to be visible
+        let dt = config.microtraffic_time(dt).0;
+
+        self.microtraffic.last_tick = current_tick;
+        self.microtraffic.headlights = TimeOfDay::from_tick(current_tick).is_night();
+
+        let traffic_logic_throttling = self.traffic_fidelity(&config).traffic_logic_throttling(
+            config.traffic_logic_throttling,
+        );
+        let do_traffic = current_tick.ticks() % traffic_logic_throttling ==
+            self.id._raw_id.instance_id as usize % traffic_logic_throttling;
 
         let old_green = self.microtraffic.green;
         self.microtraffic.yellow_to_red = if self.microtraffic.timings.is_empty() {
@@ -297,6 +768,10 @@ impl Simulatable for Lane {
         };
 
         // TODO: this is just a hacky way to update new lanes about existing lane's green
+        if old_green != self.microtraffic.green && self.connectivity.on_intersection {
+            ::sound::play_event(SoundEvent::SignalTick, self.construction.path.start(), world);
+        }
+
         if old_green != self.microtraffic.green || do_traffic {
             for interaction in &self.connectivity.interactions {
                 if let Interaction {
@@ -305,7 +780,8 @@ impl Simulatable for Lane {
                     ..
                 } = *interaction
                 {
-                    LaneID { _raw_id: partner_lane._raw_id }.on_signal_changed(
+                    let previous_lane = LaneID::from(partner_lane);
+                    previous_lane.on_signal_changed(
                         self.id.into(),
                         self.microtraffic
                             .green,
@@ -315,13 +791,35 @@ impl Simulatable for Lane {
             }
         }
 
-        if current_tick.ticks() % PATHFINDING_THROTTLING ==
-            self.id._raw_id.instance_id as usize % PATHFINDING_THROTTLING
+        // lanes under construction don't advertise any routes, so nobody plans to use them
+        if self.construction.is_finished() &&
+            current_tick.ticks() % config.pathfinding_throttling ==
+                self.id._raw_id.instance_id as usize % config.pathfinding_throttling
         {
             self.update_routes(world);
         }
 
         if do_traffic {
+            self.check_gridlock(current_tick, world);
+            self.observe_route_delays(dt * traffic_logic_throttling as f32);
+            self.start_route_day_if_due(current_tick);
+            let n_waiting = self.microtraffic
+                .cars
+                .iter()
+                .filter(|car| car.velocity < GRIDLOCK_MAX_VELOCITY)
+                .count();
+            self.microtraffic.waiting_ticks += n_waiting * traffic_logic_throttling;
+            ::core::metrics::STOPPED_VEHICLE_TICKS.fetch_add(
+                n_waiting * traffic_logic_throttling,
+                Ordering::Relaxed,
+            );
+
+            let partner_throttling = self.microtraffic.slowest_partner_fidelity.slower_throttling(
+                self.traffic_fidelity(&config),
+                config.traffic_logic_throttling,
+            );
+            self.microtraffic.expire_obstacles(current_tick, partner_throttling);
+
             // TODO: optimize using BinaryHeap?
             self.microtraffic.obstacles.sort_by_key(
                 |&(ref obstacle, _id)| {
@@ -342,7 +840,12 @@ impl Simulatable for Lane {
                     |car| car.as_obstacle,
                 );
                 let car = &mut self.microtraffic.cars[c];
-                let next_car_acceleration = intelligent_acceleration(car, &next_obstacle, 2.0);
+                let next_car_acceleration = intelligent_acceleration(
+                    car,
+                    &next_obstacle,
+                    config.car_headway,
+                    &config.idm,
+                );
 
                 maybe_next_obstacle = maybe_next_obstacle.and_then(|obstacle| {
                     let mut following_obstacle = Some(obstacle);
@@ -355,7 +858,12 @@ impl Simulatable for Lane {
                 });
 
                 let next_obstacle_acceleration = if let Some(next_obstacle) = maybe_next_obstacle {
-                    intelligent_acceleration(car, next_obstacle, 4.0)
+                    intelligent_acceleration(
+                        car,
+                        next_obstacle,
+                        config.obstacle_headway,
+                        &config.idm,
+                    )
                 } else {
                     INFINITY
                 };
@@ -368,26 +876,60 @@ impl Simulatable for Lane {
                     ..
                 } = self.connectivity.interactions[car.next_hop_interaction as usize]
                 {
-                    if !green {
+                    // emergency vehicles have priority and drive through red lights
+                    if !green && car.vehicle != VehicleClass::Emergency {
                         car.acceleration = car.acceleration.min(intelligent_acceleration(
                             car,
-                            &Obstacle {
-                                position: OrderedFloat(start + 2.0),
-                                velocity: 0.0,
-                                max_velocity: 0.0,
-                            },
-                            2.0,
+                            &Obstacle::stop_at(start - 2.0),
+                            config.signal_headway,
+                            &config.idm,
                         ))
                     }
                 }
             }
         }
 
+        let condition_factor = maintenance::speed_factor(self.wear) *
+            winter::snow_speed_factor(self.snow);
+        let adhesion = winter::snow_adhesion(self.snow);
+
         for car in &mut self.microtraffic.cars {
+            let old_position = *car.position;
             *car.position += dt * car.velocity;
-            car.velocity = (car.velocity + dt * car.acceleration)
-                .min(car.max_velocity)
+            self.microtraffic.meters_driven += dt * car.velocity;
+            // on snow, cars speed up more carefully (braking is left as it is,
+            // so they still don't run into each other)
+            let acceleration = car.acceleration.min(car.acceleration * adhesion);
+            car.velocity = (car.velocity + dt * acceleration)
+                .min(car.max_velocity * condition_factor)
+                .min(self.road_class.speed_limit(car.vehicle))
                 .max(0.0);
+            for detector in self.microtraffic.detectors.iter_mut() {
+                detector.observe(
+                    old_position,
+                    *car.position,
+                    car.velocity,
+                    vehicle_length(car.vehicle).0,
+                );
+            }
+        }
+
+        despawn_broken_cars(
+            &mut self.microtraffic.cars,
+            |car| !has_finite_state(car),
+            self.id.into(),
+            world,
+        );
+
+        for detector in self.microtraffic.detectors.iter_mut() {
+            detector.finish_tick();
+        }
+
+        ::core::metrics::VEHICLE_TICKS.fetch_add(self.microtraffic.cars.len(), Ordering::Relaxed);
+        let whole_meters = self.microtraffic.meters_driven.floor();
+        if whole_meters > 0.0 {
+            ::core::metrics::VEHICLE_METERS.fetch_add(whole_meters as usize, Ordering::Relaxed);
+            self.microtraffic.meters_driven -= whole_meters;
         }
 
         for &mut (ref mut obstacle, _id) in &mut self.microtraffic.obstacles {
@@ -444,34 +986,47 @@ impl Simulatable for Lane {
 
             if let Some((idx_to_remove, next_lane, start, partner_start)) = maybe_switch_car {
                 let car = self.microtraffic.cars.remove(idx_to_remove);
-                // TODO: ugly: untyped ID shenanigans
-                if self.id._raw_id == car.destination.node._raw_id {
-                    car.trip.succeed(current_tick, world);
-                } else {
-                    next_lane.add_car(
-                        car.offset_by(partner_start - start),
-                        Some(self.id.into()),
-                        current_tick,
-                        world,
-                    );
-                }
+                self.hand_over_car(car, next_lane, start, partner_start, current_tick, world);
             } else {
                 break;
             }
         }
 
+        if !self.microtraffic.mesoscopic.queue.is_empty() {
+            self.tick_mesoscopic(current_tick, &config, world);
+        }
+
+        // queued cars are obstacles to other lanes just like driven ones
+        let cars_with_queued: Vec<LaneCar>;
+        let all_cars: &[LaneCar] = if self.microtraffic.mesoscopic.queue.is_empty() {
+            &self.microtraffic.cars
+        } else {
+            let mut cars = self.microtraffic
+                .mesoscopic
+                .cars_as_driven(current_tick, &config);
+            cars.extend(self.microtraffic.cars.iter().cloned());
+            cars.sort_by_key(|car| car.position);
+            cars_with_queued = cars;
+            &cars_with_queued
+        };
+
         // ASSUMPTION: only one interaction per Lane/Lane pair
         for interaction in self.connectivity.interactions.iter() {
-            let cars = self.microtraffic.cars.iter();
+            let cars = all_cars.iter();
 
-            if (current_tick.ticks() + 1) % TRAFFIC_LOGIC_THROTTLING ==
-                interaction.partner_lane._raw_id.instance_id as usize % TRAFFIC_LOGIC_THROTTLING
+            let partner_instance = interaction.partner_lane._raw_id.instance_id as usize;
+            if (current_tick.ticks() + 1) % config.traffic_logic_throttling ==
+                partner_instance % config.traffic_logic_throttling
             {
-                let maybe_obstacles = obstacles_for_interaction(
-                    interaction,
-                    cars,
-                    self.microtraffic.obstacles.iter(),
-                );
+                let maybe_obstacles = if self.construction.is_finished() {
+                    obstacles_for_interaction(
+                        interaction,
+                        cars,
+                        self.microtraffic.obstacles.iter(),
+                    )
+                } else {
+                    construction_site_obstacles(interaction)
+                };
 
                 if let Some(obstacles) = maybe_obstacles {
                     interaction.partner_lane.add_obstacles(
@@ -491,10 +1046,15 @@ impl LaneLike for TransferLane {
         car: LaneCar,
         maybe_from: Option<LaneLikeID>,
         _tick: Timestamp,
-        _: &mut World,
+        world: &mut World,
     ) {
         let from = maybe_from.expect("car has to come from somewhere on transfer lane");
 
+        if self.construction.is_unbuilding() {
+            car.trip.cancel(CancelReason::LaneRemoved, world);
+            return;
+        }
+
         let from_left = from ==
             self.connectivity
                 .left
@@ -518,8 +1078,7 @@ impl LaneLike for TransferLane {
 
     fn add_obstacles(&mut self, obstacles: &CVec<Obstacle>, from: LaneLikeID, _: &mut World) {
         if let (Some((left_id, _)), Some(_)) = (self.connectivity.left, self.connectivity.right) {
-            // TODO: ugly: untyped ID shenanigans
-            if left_id._raw_id == from._raw_id {
+            if LaneLikeID::from(left_id) == from {
                 self.microtraffic.left_obstacles = obstacles
                     .iter()
                     .map(|obstacle| {
@@ -546,14 +1105,46 @@ impl LaneLike for TransferLane {
     }
 }
 
+impl TransferLane {
+    /// The crew building the lane on `side` blocks the lane on the other side at `spot`
+    /// (as seen from `side`), or stops blocking it
+    pub fn work_zone(&mut self, side: LaneID, spot: Option<f32>, _: &mut World) {
+        let on_left = self.connectivity.left.map_or(false, |(left, _)| left == side);
+        self.microtraffic.work_zone = spot.map(|spot| {
+            (on_left, spot + self.interaction_to_self_offset(spot, on_left))
+        });
+    }
+}
+
 impl Simulatable for TransferLane {
     fn tick(&mut self, dt: f32, current_tick: Timestamp, world: &mut World) {
-        let dt = dt / MICROTRAFFIC_UNREALISTIC_SLOWDOWN;
+        if self.construction.is_unbuilding() {
+            return;
+        }
+
+        if !self.construction.is_finished() {
+            // construction happens in unslowed simulation time
+            self.construction.progress += dt * self.construction.build_rate;
+            if self.construction.is_finished() {
+                ConstructionCrewsID::local_first(world).project_done(
+                    self.id.into(),
+                    Some(self.construction.path.start()),
+                    world,
+                );
+            }
+        }
+
+        let config = SimulationConfig::current();
+        let dt = config.microtraffic_time(dt).0;
 
-        self.construction.progress += dt * 400.0;
+        self.microtraffic.headlights = TimeOfDay::from_tick(current_tick).is_night();
 
-        let do_traffic = current_tick.ticks() % TRAFFIC_LOGIC_THROTTLING ==
-            self.id._raw_id.instance_id as usize % TRAFFIC_LOGIC_THROTTLING;
+        // transfer lanes aren't part of simulation chunks, so they always run at full fidelity
+        let traffic_logic_throttling = Fidelity::Full.traffic_logic_throttling(
+            config.traffic_logic_throttling,
+        );
+        let do_traffic = current_tick.ticks() % traffic_logic_throttling ==
+            self.id._raw_id.instance_id as usize % traffic_logic_throttling;
 
         if do_traffic {
             // TODO: optimize using BinaryHeap?
@@ -601,7 +1192,12 @@ impl Simulatable for TransferLane {
                             dangerous = true;
                             None
                         } else {
-                            Some(OrderedFloat(intelligent_acceleration(car, obstacle, 1.0)))
+                            Some(OrderedFloat(intelligent_acceleration(
+                                car,
+                                obstacle,
+                                config.transfer_headway,
+                                &config.idm,
+                            )))
                         })
                         .min()
                         .unwrap();
@@ -639,6 +1235,16 @@ impl Simulatable for TransferLane {
             }
         }
 
+        despawn_broken_cars(
+            &mut self.microtraffic.cars,
+            |car| {
+                !has_finite_state(car) || !car.transfer_position.is_finite() ||
+                    !car.transfer_velocity.is_finite()
+            },
+            self.id.into(),
+            world,
+        );
+
         for obstacle in self.microtraffic.left_obstacles.iter_mut().chain(
             self.microtraffic
                 .right_obstacles
@@ -721,10 +1327,10 @@ impl Simulatable for TransferLane {
                 }
             }
 
-            if (current_tick.ticks() + 1) % TRAFFIC_LOGIC_THROTTLING ==
-                left._raw_id.instance_id as usize % TRAFFIC_LOGIC_THROTTLING
+            if (current_tick.ticks() + 1) % config.traffic_logic_throttling ==
+                left._raw_id.instance_id as usize % config.traffic_logic_throttling
             {
-                let obstacles = self.microtraffic
+                let mut obstacles: CVec<Obstacle> = self.microtraffic
                     .cars
                     .iter()
                     .filter_map(|car| if car.transfer_position < 0.3 ||
@@ -741,14 +1347,19 @@ impl Simulatable for TransferLane {
                         None
                     })
                     .collect();
+                if let Some((false, spot)) = self.microtraffic.work_zone {
+                    obstacles.push(Obstacle::stop_at(
+                        spot + left_start + self.self_to_interaction_offset(spot, true),
+                    ));
+                }
                 let left_as_lane: LaneLikeID = left.into();
                 left_as_lane.add_obstacles(obstacles, self.id.into(), world);
             }
 
-            if (current_tick.ticks() + 1) % TRAFFIC_LOGIC_THROTTLING ==
-                right._raw_id.instance_id as usize % TRAFFIC_LOGIC_THROTTLING
+            if (current_tick.ticks() + 1) % config.traffic_logic_throttling ==
+                right._raw_id.instance_id as usize % config.traffic_logic_throttling
             {
-                let obstacles = self.microtraffic
+                let mut obstacles: CVec<Obstacle> = self.microtraffic
                     .cars
                     .iter()
                     .filter_map(|car| if car.transfer_position > -0.3 ||
@@ -765,6 +1376,11 @@ impl Simulatable for TransferLane {
                         None
                     })
                     .collect();
+                if let Some((true, spot)) = self.microtraffic.work_zone {
+                    obstacles.push(Obstacle::stop_at(
+                        spot + right_start + self.self_to_interaction_offset(spot, false),
+                    ));
+                }
                 let right_as_lane: LaneLikeID = right.into();
                 right_as_lane.add_obstacles(obstacles, self.id.into(), world);
             }
@@ -774,6 +1390,41 @@ impl Simulatable for TransferLane {
 
 pub fn setup(system: &mut ActorSystem) {
     auto_setup(system);
+
+    // lanes only touch their own state when ticking and talk to other lanes by messages,
+    // so their ticks can be shared between threads, see `SimulationConfig::tick_threads`
+    system.parallelize::<Lane, MSG_Simulatable_tick>();
+    system.parallelize::<TransferLane, MSG_Simulatable_tick>();
+}
+
+/// A lane under construction blocks the area where it overlaps other lanes
+fn construction_site_obstacles(interaction: &Interaction) -> Option<CVec<Obstacle>> {
+    match *interaction {
+        Interaction {
+            start,
+            partner_start,
+            kind: InteractionKind::Overlap { end, kind: OverlapKind::Transfer, .. },
+            ..
+        } => {
+            // nobody may change into the construction site
+            Some(
+                vec![
+                    Obstacle {
+                        position: OrderedFloat(partner_start + (end - start)),
+                        velocity: 0.0,
+                        max_velocity: 0.0,
+                        length: end - start,
+                    },
+                ].into(),
+            )
+        }
+        Interaction {
+            partner_start,
+            kind: InteractionKind::Overlap { .. },
+            ..
+        } => Some(vec![Obstacle::stop_at(partner_start - CAR_LENGTH.0)].into()),
+        _ => None,
+    }
 }
 
 fn obstacles_for_interaction(
@@ -792,7 +1443,7 @@ fn obstacles_for_interaction(
             Some(match kind {
                 OverlapKind::Parallel => {
                     cars.skip_while(|car: &&LaneCar| *car.position + 2.0 * car.velocity < start)
-                        .take_while(|car: &&LaneCar| *car.position < end)
+                        .take_while(|car: &&LaneCar| car.rear() < end)
                         .map(|car| car.as_obstacle.offset_by(-start + partner_start))
                         .collect()
                 }
@@ -812,17 +1463,12 @@ fn obstacles_for_interaction(
                         .collect()
                 }
                 OverlapKind::Conflicting => {
+                    // the conflict is only released once the tail of a vehicle has passed
                     let in_overlap = |car: &LaneCar| {
-                        *car.position + 2.0 * car.velocity > start && *car.position - 2.0 < end
+                        *car.position + 2.0 * car.velocity > start && car.rear() - 2.0 < end
                     };
                     if cars.any(in_overlap) {
-                        vec![
-                            Obstacle {
-                                position: OrderedFloat(partner_start),
-                                velocity: 0.0,
-                                max_velocity: 0.0,
-                            },
-                        ].into()
+                        vec![Obstacle::stop_at(partner_start - CAR_LENGTH.0)].into()
                     } else {
                         CVec::new()
                     }
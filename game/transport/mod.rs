@@ -5,6 +5,28 @@ pub mod rendering;
 
 pub mod planning;
 pub mod pathfinding;
+pub mod export;
+pub mod diagnostics;
+pub mod turning_movements;
+pub mod signal_warrants;
+pub mod signal_control;
+pub mod detectors;
+pub mod demand_forecast;
+pub mod restrictions;
+pub mod demolition;
+pub mod services;
+pub mod spatial_index;
+pub mod consistency;
+pub mod event_log;
+pub mod traffic_cameras;
+pub mod maintenance;
+pub mod utilities;
+pub mod road_hierarchy;
+pub mod street_furniture;
+pub mod ferries;
+pub mod analysis_zones;
+pub mod chunks;
+pub mod junctions;
 
 use kay::ActorSystem;
 use stagemaster::UserInterfaceID;
@@ -18,9 +40,29 @@ pub fn setup(
     simulation: SimulationID,
 ) {
     self::lane::setup(system);
-    let materialized_reality = self::construction::setup(system);
+    self::junctions::setup(system);
+    self::spatial_index::setup(system);
+    let materialized_reality = self::construction::setup(system, simulation);
     self::microtraffic::setup(system);
-    self::pathfinding::setup(system, simulation);
+    self::pathfinding::setup(system, user_interface, simulation);
     self::rendering::setup(system);
-    self::planning::setup(system, user_interface, renderer_id, materialized_reality);
+    self::planning::setup(system, user_interface, renderer_id, simulation, materialized_reality);
+    self::export::setup(system, simulation);
+    self::diagnostics::setup(system, simulation);
+    self::turning_movements::setup(system, user_interface, simulation);
+    self::signal_warrants::setup(system, user_interface, simulation);
+    self::signal_control::setup(system, user_interface, simulation);
+    self::detectors::setup(system, user_interface, simulation);
+    self::demand_forecast::setup(system, user_interface, simulation);
+    self::restrictions::setup(system, user_interface);
+    self::demolition::setup(system, user_interface);
+    self::services::setup(system, user_interface, simulation);
+    self::traffic_cameras::setup(system, user_interface, renderer_id);
+    self::maintenance::setup(system, user_interface, simulation);
+    self::utilities::setup(system, user_interface, simulation, renderer_id);
+    self::road_hierarchy::setup(system, user_interface, simulation);
+    self::street_furniture::setup(system, user_interface);
+    self::ferries::setup(system, user_interface);
+    self::analysis_zones::setup(system, user_interface, simulation);
+    self::chunks::setup(system, renderer_id);
 }
@@ -0,0 +1,743 @@
+//! Walking and cycling don't use the car routing of `Node`s, but their own graph,
+//! built from the road network: sidewalks along both sides of every road lane,
+//! which can be walked in both directions, and crossings over intersections
+//! (set back from protected intersections, see `transport::junctions::protected`).
+//! Cyclists ride along with cars, but may also use paths without any car lanes.
+//! Ferry lines (see `transport::ferries`) link the graph across water.
+use kay::{ActorSystem, World, External};
+use compact::{CVec, CDict};
+use descartes::{P2, Norm, FiniteCurve, Segment, Path};
+use fnv::FnvHashMap;
+use monet::{RendererID, Renderable, RenderableID, MSG_Renderable_setup_in_scene,
+            MSG_Renderable_render_to_scene, Geometry, Vertex, Instance};
+use stagemaster::{UserInterfaceID, Event3d, Interactable3d, Interactable3dID,
+                  MSG_Interactable3d_on_event, Interactable2d, Interactable2dID,
+                  MSG_Interactable2d_draw_ui_2d};
+use stagemaster::combo::Combo2;
+use stagemaster::combo::Button::{LControl, I};
+use stagemaster::actions::{register_action, ActionId, ActionListener, ActionListenerID,
+                           ActionPhase, MSG_ActionListener_on_action};
+use stagemaster::geometry::CPath;
+use transport::planning::current_plan::set_tool_active;
+use imgui::ImGuiSetCond_FirstUseEver;
+use core::simulation::{SimulationID, Sleeper, SleeperID, MSG_Sleeper_wake, Timestamp, Ticks};
+use transport::lane::{Lane, LaneID};
+use transport::rendering::lane_mesh::{Profile, extrude_geometry};
+use transport::junctions::JunctionID;
+use ordered_float::OrderedFloat;
+use super::{Location, NodeID};
+use super::isochrones::travel_times;
+
+const REBUILD_INTERVAL: Ticks = Ticks(600);
+const COLLECTION_TICKS: Ticks = Ticks(10);
+/// Lane ends closer than this are considered the same place for walking and cycling
+const NODE_SNAP_DISTANCE: f32 = 2.0;
+/// Ends of pedestrian paths get connected to the closest sidewalk or crossing within this
+const PATH_CONNECTION_DISTANCE: f32 = 20.0;
+/// Ferry docks get connected to the closest sidewalk or crossing within this
+const DOCK_CONNECTION_DISTANCE: f32 = 60.0;
+const ISOCHRONE_MARKER_BATCH_ID: u16 = 8102;
+// isochrone bands, in minutes
+const NEAR_MINUTES: f32 = 5.0;
+const FAR_MINUTES: f32 = 15.0;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ActiveMode {
+    Walking,
+    Cycling,
+}
+
+impl ActiveMode {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ActiveMode::Walking => "Walking",
+            ActiveMode::Cycling => "Cycling",
+        }
+    }
+
+    /// In m/s
+    pub fn speed(&self) -> f32 {
+        match *self {
+            ActiveMode::Walking => 1.4,
+            ActiveMode::Cycling => 4.5,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ActiveEdgeKind {
+    /// Alongside a road lane
+    Sidewalk,
+    /// Across an intersection
+    Crossing,
+    /// A path without any car lanes
+    Path,
+    /// On a ferry, or between a ferry dock and the street
+    Ferry,
+}
+
+type NodeKey = (i32, i32);
+
+fn node_key(position: P2) -> NodeKey {
+    (
+        (position.x / NODE_SNAP_DISTANCE).round() as i32,
+        (position.y / NODE_SNAP_DISTANCE).round() as i32,
+    )
+}
+
+#[derive(Copy, Clone)]
+pub struct ActiveEdge {
+    /// The lane this edge runs along, if any
+    lane: Option<NodeID>,
+    from: NodeKey,
+    to: NodeKey,
+    from_position: P2,
+    middle: P2,
+    length: f32,
+    kind: ActiveEdgeKind,
+    walkable: bool,
+    cyclable: bool,
+    /// How long the edge takes in any mode, if that doesn't depend on the mode's speed
+    fixed_minutes: Option<f32>,
+}
+
+impl ActiveEdge {
+    fn along(path: &CPath, kind: ActiveEdgeKind, walkable: bool, cyclable: bool) -> ActiveEdge {
+        ActiveEdge {
+            lane: None,
+            from: node_key(path.start()),
+            to: node_key(path.end()),
+            from_position: path.start(),
+            middle: path.along(path.length() / 2.0),
+            length: path.length(),
+            kind,
+            walkable,
+            cyclable,
+            fixed_minutes: None,
+        }
+    }
+
+    fn reversed(&self) -> ActiveEdge {
+        ActiveEdge {
+            from: self.to,
+            to: self.from,
+            ..*self
+        }
+    }
+
+    fn usable_by(&self, mode: ActiveMode) -> bool {
+        match mode {
+            ActiveMode::Walking => self.walkable,
+            ActiveMode::Cycling => self.cyclable,
+        }
+    }
+
+    fn minutes(&self, meters_per_minute: f32) -> f32 {
+        self.fixed_minutes.unwrap_or(self.length / meters_per_minute)
+    }
+}
+
+/// A ferry line between two docks, as far as walking and cycling are concerned
+#[derive(Copy, Clone)]
+pub struct FerryCrossing {
+    pub from: P2,
+    pub to: P2,
+    /// Waiting for the ferry and the crossing itself
+    pub minutes: f32,
+}
+
+/// Edges in both directions from `end` to the closest node of the `existing` graph,
+/// if there is one within `max_distance`
+fn connection_edges(
+    existing: &[ActiveEdge],
+    end: P2,
+    max_distance: f32,
+    kind: ActiveEdgeKind,
+) -> Vec<ActiveEdge> {
+    let maybe_closest = existing
+        .iter()
+        .filter(|edge| edge.from != node_key(end) && edge.kind != ActiveEdgeKind::Ferry)
+        .map(|edge| (edge.from_position, (edge.from_position - end).norm()))
+        .filter(|&(_, distance)| distance < max_distance)
+        .min_by_key(|&(_, distance)| OrderedFloat(distance));
+
+    if let Some((closest, _)) = maybe_closest {
+        let connection = ActiveEdge::along(
+            &CPath::new(vec![Segment::line(end, closest)]),
+            kind,
+            true,
+            true,
+        );
+        vec![connection, connection.reversed()]
+    } else {
+        Vec::new()
+    }
+}
+
+/// Edges of `kind` in both directions along a path, plus connections from both of its
+/// ends to the closest node of the `existing` graph, if there is one close enough
+fn path_edges(existing: &[ActiveEdge], path: &CPath, kind: ActiveEdgeKind) -> Vec<ActiveEdge> {
+    let along = ActiveEdge::along(path, kind, true, true);
+    let mut edges = vec![along, along.reversed()];
+
+    for &end in &[path.start(), path.end()] {
+        edges.extend(connection_edges(existing, end, PATH_CONNECTION_DISTANCE, kind));
+    }
+
+    edges
+}
+
+/// Edges in both directions across a ferry line, plus connections from both docks
+/// to the closest node of the `existing` graph, if there is one close enough
+fn ferry_edges(existing: &[ActiveEdge], crossing: &FerryCrossing) -> Vec<ActiveEdge> {
+    let across = ActiveEdge {
+        fixed_minutes: Some(crossing.minutes),
+        ..ActiveEdge::along(
+            &CPath::new(vec![Segment::line(crossing.from, crossing.to)]),
+            ActiveEdgeKind::Ferry,
+            true,
+            true,
+        )
+    };
+    let mut edges = vec![across, across.reversed()];
+
+    for &dock in &[crossing.from, crossing.to] {
+        edges.extend(connection_edges(
+            existing,
+            dock,
+            DOCK_CONNECTION_DISTANCE,
+            ActiveEdgeKind::Ferry,
+        ));
+    }
+
+    edges
+}
+
+/// Successors of each edge (by index) that `mode` may use
+fn successors_for(edges: &[ActiveEdge], mode: ActiveMode) -> FnvHashMap<usize, Vec<usize>> {
+    let mut starting_at = FnvHashMap::<NodeKey, Vec<usize>>::default();
+    for (i, edge) in edges.iter().enumerate() {
+        if edge.usable_by(mode) {
+            starting_at.entry(edge.from).or_insert_with(Vec::new).push(i);
+        }
+    }
+
+    edges
+        .iter()
+        .enumerate()
+        .filter(|&(_, edge)| edge.usable_by(mode))
+        .map(|(i, edge)| {
+            (i, starting_at.get(&edge.to).cloned().unwrap_or_else(Vec::new))
+        })
+        .collect()
+}
+
+/// Travel times in minutes, when leaving from the graph node closest to `origin`,
+/// to every edge that `mode` can reach from there
+pub fn active_travel_minutes(
+    edges: &[ActiveEdge],
+    mode: ActiveMode,
+    origin: P2,
+) -> FnvHashMap<usize, f32> {
+    let maybe_start = edges
+        .iter()
+        .filter(|edge| edge.usable_by(mode))
+        .min_by_key(|edge| {
+            OrderedFloat((edge.from_position - origin).norm())
+        })
+        .map(|edge| edge.from);
+
+    if let Some(start) = maybe_start {
+        let starts = edges
+            .iter()
+            .enumerate()
+            .filter(|&(_, edge)| edge.from == start && edge.usable_by(mode))
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
+        let meters_per_minute = mode.speed() * 60.0;
+        travel_times(&starts, &successors_for(edges, mode), |i| {
+            edges[i].minutes(meters_per_minute)
+        })
+    } else {
+        FnvHashMap::default()
+    }
+}
+
+/// Travel time in minutes from the lane `source` to the lane `destination`,
+/// if both are part of the graph and `mode` can get from one to the other
+fn minutes_between(
+    edges: &[ActiveEdge],
+    mode: ActiveMode,
+    source: NodeID,
+    destination: NodeID,
+) -> Option<f32> {
+    edges
+        .iter()
+        .find(|edge| edge.lane == Some(source) && edge.usable_by(mode))
+        .and_then(|start| {
+            let times = active_travel_minutes(edges, mode, start.from_position);
+            edges
+                .iter()
+                .enumerate()
+                .filter(|&(_, edge)| edge.lane == Some(destination))
+                .filter_map(|(i, _)| times.get(&i).cloned())
+                .min_by_key(|&minutes| OrderedFloat(minutes))
+        })
+}
+
+pub trait ActiveTravelRequester {
+    fn on_active_travel_minutes(
+        &mut self,
+        walking: Option<f32>,
+        cycling: Option<f32>,
+        world: &mut World,
+    );
+}
+
+pub trait WalkingCatchmentRequester {
+    fn on_walking_catchment(&mut self, reachable: &CVec<(P2, f32)>, world: &mut World);
+}
+
+/// Keeps the walking and cycling graph up to date and shows isochrones for both modes,
+/// from a point picked with the "Pick Isochrone Origin" action.
+/// Also owns all pedestrian paths, since they only exist in this graph
+#[derive(Compact, Clone)]
+pub struct ActiveModeGraph {
+    id: ActiveModeGraphID,
+    simulation: SimulationID,
+    user_interface: UserInterfaceID,
+    collecting: bool,
+    collected: CVec<ActiveEdge>,
+    /// Crossings of protected junctions, connected to the rest once everything is collected
+    collected_crossings: CVec<CPath>,
+    edges: CVec<ActiveEdge>,
+    paths: CVec<CPath>,
+    paths_rendered_in: CDict<RendererID, ()>,
+    ferries: CVec<FerryCrossing>,
+    picking_origin: bool,
+    origin: Option<P2>,
+    isochrone_mode: Option<ActiveMode>,
+    isochrone: CVec<(P2, f32)>,
+}
+
+impl ActiveModeGraph {
+    pub fn spawn(
+        id: ActiveModeGraphID,
+        user_interface: UserInterfaceID,
+        simulation: SimulationID,
+        world: &mut World,
+    ) -> ActiveModeGraph {
+        user_interface.add_2d(id.into(), world);
+        register_action(
+            "Pick Isochrone Origin",
+            Combo2::new(&[LControl, I], &[]),
+            id.into(),
+            world,
+        );
+        simulation.wake_up_in(REBUILD_INTERVAL, id.into(), world);
+
+        ActiveModeGraph {
+            id,
+            simulation,
+            user_interface,
+            collecting: false,
+            collected: CVec::new(),
+            collected_crossings: CVec::new(),
+            edges: CVec::new(),
+            paths: CVec::new(),
+            paths_rendered_in: CDict::new(),
+            ferries: CVec::new(),
+            picking_origin: false,
+            origin: None,
+            isochrone_mode: None,
+            isochrone: CVec::new(),
+        }
+    }
+
+    pub fn add_edges(&mut self, edges: &CVec<ActiveEdge>, _: &mut World) {
+        if self.collecting {
+            self.collected.extend(edges.iter().cloned());
+        }
+    }
+
+    pub fn add_crossings(&mut self, crossings: &CVec<CPath>, _: &mut World) {
+        if self.collecting {
+            self.collected_crossings.extend(crossings.iter().cloned());
+        }
+    }
+
+    pub fn build_paths(&mut self, paths: &CVec<CPath>, _: &mut World) {
+        self.paths.extend(paths.iter().cloned());
+        self.add_path_edges(paths);
+        self.paths_rendered_in = CDict::new();
+        self.update_isochrone();
+    }
+
+    fn add_path_edges(&mut self, paths: &[CPath]) {
+        for path in paths {
+            let new_edges = path_edges(&self.edges, path, ActiveEdgeKind::Path);
+            self.edges.extend(new_edges);
+        }
+    }
+
+    /// Replaces all ferry lines, called by the ferry network whenever they change
+    pub fn set_ferries(&mut self, ferries: &CVec<FerryCrossing>, _: &mut World) {
+        self.ferries = ferries.clone();
+        self.edges = self.edges
+            .iter()
+            .filter(|edge| edge.kind != ActiveEdgeKind::Ferry)
+            .cloned()
+            .collect();
+        self.add_ferry_edges();
+        self.update_isochrone();
+    }
+
+    fn add_ferry_edges(&mut self) {
+        for crossing in self.ferries.iter() {
+            let new_edges = ferry_edges(&self.edges, crossing);
+            self.edges.extend(new_edges);
+        }
+    }
+
+    pub fn estimate_active_minutes(
+        &mut self,
+        source: Location,
+        destination: Location,
+        requester: ActiveTravelRequesterID,
+        world: &mut World,
+    ) {
+        let minutes = |mode| minutes_between(&self.edges, mode, source.node, destination.node);
+        let (walking, cycling) = (minutes(ActiveMode::Walking), minutes(ActiveMode::Cycling));
+        requester.on_active_travel_minutes(walking, cycling, world);
+    }
+
+    /// Tells `requester` which points of the graph can be walked to from `origin` within
+    /// `max_minutes`, each with the minutes it takes
+    pub fn find_walking_catchment(
+        &mut self,
+        origin: P2,
+        max_minutes: f32,
+        requester: WalkingCatchmentRequesterID,
+        world: &mut World,
+    ) {
+        let reachable = active_travel_minutes(&self.edges, ActiveMode::Walking, origin)
+            .into_iter()
+            .filter(|&(_, minutes)| minutes <= max_minutes)
+            .map(|(i, minutes)| (self.edges[i].middle, minutes))
+            .collect();
+        requester.on_walking_catchment(reachable, world);
+    }
+
+    fn update_isochrone(&mut self) {
+        let isochrone = match (self.isochrone_mode, self.origin) {
+            (Some(mode), Some(origin)) => {
+                active_travel_minutes(&self.edges, mode, origin)
+                    .into_iter()
+                    .map(|(i, minutes)| (self.edges[i].middle, minutes))
+                    .collect()
+            }
+            _ => CVec::new(),
+        };
+        self.isochrone = isochrone;
+    }
+}
+
+impl Lane {
+    pub fn report_to_active_modes(&mut self, graph: ActiveModeGraphID, world: &mut World) {
+        if !self.construction.is_finished() {
+            return;
+        }
+
+        let path = &self.construction.path;
+        let lane = Some(self.id.into());
+        let edges = if self.connectivity.on_intersection {
+            // pedestrians cross along the way cars take over the intersection
+            let crossing = ActiveEdge {
+                lane,
+                ..ActiveEdge::along(path, ActiveEdgeKind::Crossing, true, true)
+            };
+            vec![crossing, crossing.reversed()]
+        } else {
+            // cyclists have to follow the lane direction, pedestrians don't
+            let sidewalk = ActiveEdge {
+                lane,
+                ..ActiveEdge::along(path, ActiveEdgeKind::Sidewalk, true, true)
+            };
+            let mut back = sidewalk.reversed();
+            back.cyclable = false;
+            vec![sidewalk, back]
+        };
+
+        graph.add_edges(edges.into(), world);
+    }
+}
+
+impl Sleeper for ActiveModeGraph {
+    fn wake(&mut self, _current_tick: Timestamp, world: &mut World) {
+        if self.collecting {
+            self.collecting = false;
+            self.edges = ::std::mem::replace(&mut self.collected, CVec::new());
+            let crossings = ::std::mem::replace(&mut self.collected_crossings, CVec::new());
+            for crossing in crossings.iter() {
+                let new_edges = path_edges(&self.edges, crossing, ActiveEdgeKind::Crossing);
+                self.edges.extend(new_edges);
+            }
+            let paths = self.paths.clone();
+            self.add_path_edges(&paths);
+            self.add_ferry_edges();
+            self.update_isochrone();
+            self.simulation.wake_up_in(REBUILD_INTERVAL, self.id.into(), world);
+        } else {
+            self.collecting = true;
+            LaneID::global_broadcast(world).report_to_active_modes(self.id, world);
+            JunctionID::global_broadcast(world).report_to_active_modes(self.id, world);
+            self.simulation.wake_up_in(COLLECTION_TICKS, self.id.into(), world);
+        }
+    }
+}
+
+impl ActionListener for ActiveModeGraph {
+    fn on_action(&mut self, action: ActionId, phase: ActionPhase, world: &mut World) {
+        if phase == ActionPhase::Started && action == ActionId::named("Pick Isochrone Origin") {
+            self.picking_origin = !self.picking_origin;
+            set_tool_active(self.user_interface, self.id.into(), self.picking_origin, world);
+        }
+    }
+}
+
+impl Interactable3d for ActiveModeGraph {
+    fn on_event(&mut self, event: Event3d, world: &mut World) {
+        if self.picking_origin {
+            match event {
+                Event3d::DragFinished { to, .. } => {
+                    self.origin = Some(P2::new(to.x, to.y));
+                    if self.isochrone_mode.is_none() {
+                        self.isochrone_mode = Some(ActiveMode::Walking);
+                    }
+                    self.update_isochrone();
+                    self.picking_origin = false;
+                    self.user_interface.remove(self.id.into(), world);
+                }
+                Event3d::Frame => {
+                    self.user_interface.add_debug_text(
+                        "Picking isochrone origin".chars().collect(),
+                        "click anywhere".chars().collect(),
+                        [0.0, 0.0, 0.0, 1.0],
+                        false,
+                        world,
+                    );
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn isochrone_color(minutes: f32) -> [f32; 3] {
+    if minutes < NEAR_MINUTES {
+        [0.0, 0.8, 0.2]
+    } else if minutes < FAR_MINUTES {
+        [1.0, 0.8, 0.0]
+    } else {
+        [1.0, 0.0, 0.0]
+    }
+}
+
+impl Renderable for ActiveModeGraph {
+    fn setup_in_scene(&mut self, renderer_id: RendererID, scene_id: usize, world: &mut World) {
+        renderer_id.add_batch(
+            scene_id,
+            ISOCHRONE_MARKER_BATCH_ID,
+            Geometry::new(
+                vec![
+                    Vertex { position: [-1.0, -1.0, 0.3] },
+                    Vertex { position: [1.0, -1.0, 0.3] },
+                    Vertex { position: [1.0, 1.0, 0.3] },
+                    Vertex { position: [-1.0, 1.0, 0.3] },
+                ],
+                vec![0, 1, 2, 2, 3, 0],
+            ),
+            world,
+        );
+    }
+
+    fn render_to_scene(
+        &mut self,
+        renderer_id: RendererID,
+        scene_id: usize,
+        frame: usize,
+        world: &mut World,
+    ) {
+        if self.paths_rendered_in.get(renderer_id).is_none() {
+            let paths_geometry: Geometry = self.paths
+                .iter()
+                .map(|path| extrude_geometry(path, &Profile::sidewalk(2.0, 0.0)))
+                .sum();
+            renderer_id.update_individual(
+                scene_id,
+                5506,
+                paths_geometry,
+                Instance::with_color([0.5, 0.6, 0.4]),
+                true,
+                world,
+            );
+            self.paths_rendered_in.insert(renderer_id, ());
+        }
+
+        if !self.isochrone.is_empty() {
+            renderer_id.add_several_instances(
+                scene_id,
+                ISOCHRONE_MARKER_BATCH_ID,
+                frame,
+                self.isochrone
+                    .iter()
+                    .map(|&(position, minutes)| {
+                        Instance {
+                            instance_position: [position.x, position.y, 0.0],
+                            instance_direction: [1.0, 0.0],
+                            instance_color: isochrone_color(minutes),
+                        }
+                    })
+                    .collect(),
+                world,
+            );
+        }
+    }
+}
+
+impl Interactable2d for ActiveModeGraph {
+    fn draw_ui_2d(
+        &mut self,
+        imgui_ui: &External<::imgui::Ui<'static>>,
+        return_to: UserInterfaceID,
+        world: &mut World,
+    ) {
+        let ui = imgui_ui.steal();
+        let mut new_mode = self.isochrone_mode;
+
+        ui.window(im_str!("Walking & Cycling"))
+            .size((300.0, 150.0), ImGuiSetCond_FirstUseEver)
+            .collapsible(true)
+            .build(|| {
+                let count = |kind| {
+                    self.edges.iter().filter(|edge| edge.kind == kind).count() / 2
+                };
+                ui.text(im_str!(
+                    "{} sidewalks, {} crossings, {} paths, {} ferry lines",
+                    count(ActiveEdgeKind::Sidewalk),
+                    count(ActiveEdgeKind::Crossing),
+                    count(ActiveEdgeKind::Path),
+                    self.ferries.len()
+                ));
+
+                if self.origin.is_none() {
+                    ui.text(im_str!("Pick an isochrone origin to see isochrones"));
+                }
+                ui.text(im_str!("Isochrone:"));
+                for &mode in &[ActiveMode::Walking, ActiveMode::Cycling] {
+                    if ui.small_button(im_str!("{}", mode.name())) {
+                        new_mode = Some(mode);
+                    }
+                    ui.same_line(0.0);
+                }
+                if ui.small_button(im_str!("Off")) {
+                    new_mode = None;
+                }
+            });
+
+        if new_mode != self.isochrone_mode {
+            self.isochrone_mode = new_mode;
+            self.update_isochrone();
+        }
+
+        return_to.ui_drawn(ui, world);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ActiveEdge, ActiveEdgeKind, ActiveMode, FerryCrossing, active_travel_minutes,
+                node_key, path_edges, ferry_edges};
+    use descartes::{P2, Segment, Path};
+    use stagemaster::geometry::CPath;
+
+    fn one_way_road(from: P2, to: P2) -> Vec<ActiveEdge> {
+        let sidewalk = ActiveEdge {
+            lane: None,
+            from: node_key(from),
+            to: node_key(to),
+            from_position: from,
+            middle: from + (to - from) / 2.0,
+            length: (to - from).x.abs() + (to - from).y.abs(),
+            kind: ActiveEdgeKind::Sidewalk,
+            walkable: true,
+            cyclable: true,
+            fixed_minutes: None,
+        };
+        let mut back = sidewalk.reversed();
+        back.cyclable = false;
+        vec![sidewalk, back]
+    }
+
+    #[test]
+    fn pedestrians_walk_against_one_way_streets_but_cyclists_dont() {
+        let edges = one_way_road(P2::new(0.0, 0.0), P2::new(84.0, 0.0));
+        let origin = P2::new(84.0, 0.0);
+
+        let walking = active_travel_minutes(&edges, ActiveMode::Walking, origin);
+        assert_eq!(walking.get(&1), Some(&0.0));
+        assert_eq!(walking.get(&0), Some(&1.0));
+
+        let cycling = active_travel_minutes(&edges, ActiveMode::Cycling, origin);
+        assert!(cycling.get(&1).is_none());
+    }
+
+    #[test]
+    fn paths_connect_to_nearby_sidewalks() {
+        let mut edges = one_way_road(P2::new(0.0, 0.0), P2::new(84.0, 0.0));
+        let path = CPath::new(vec![
+            Segment::line(P2::new(84.0, 10.0), P2::new(84.0, 94.0)),
+        ]);
+        edges.extend(path_edges(&edges, &path, ActiveEdgeKind::Path));
+
+        // the path and a connection at its near end, in both directions each
+        assert_eq!(edges.len(), 2 + 4);
+        let walking = active_travel_minutes(&edges, ActiveMode::Walking, P2::new(84.0, 94.0));
+        assert!(walking.contains_key(&0));
+    }
+
+    #[test]
+    fn ferries_take_the_same_time_for_everyone() {
+        let mut edges = one_way_road(P2::new(0.0, 0.0), P2::new(84.0, 0.0));
+        let crossing = FerryCrossing {
+            from: P2::new(84.0, 30.0),
+            to: P2::new(84.0, 1000.0),
+            minutes: 12.0,
+        };
+        edges.extend(ferry_edges(&edges, &crossing));
+
+        let origin = P2::new(84.0, 1000.0);
+        let walking = active_travel_minutes(&edges, ActiveMode::Walking, origin);
+        let cycling = active_travel_minutes(&edges, ActiveMode::Cycling, origin);
+        // the connection from the dock to the road starts right after the crossing
+        let connection = edges
+            .iter()
+            .position(|edge| {
+                edge.from == node_key(crossing.from) && edge.kind == ActiveEdgeKind::Ferry &&
+                    edge.fixed_minutes.is_none()
+            })
+            .unwrap();
+        assert_eq!(walking.get(&connection), Some(&12.0));
+        assert_eq!(cycling.get(&connection), Some(&12.0));
+    }
+}
+
+pub fn setup(system: &mut ActorSystem, user_interface: UserInterfaceID, simulation: SimulationID) {
+    system.register::<ActiveModeGraph>();
+    auto_setup(system);
+
+    ActiveModeGraphID::spawn(user_interface, simulation, &mut system.world());
+}
+
+mod kay_auto;
+pub use self::kay_auto::*;
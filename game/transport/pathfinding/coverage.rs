@@ -0,0 +1,115 @@
+//! Planning routes that drive along every edge of a network at least once,
+//! as needed by service vehicles that have to pass every building, instead of
+//! the shortest route to one destination like in the rest of pathfinding.
+use fnv::{FnvHashMap, FnvHashSet};
+use std::collections::VecDeque;
+use std::hash::Hash;
+
+/// Plans a route starting at `start` that visits as many of the edges
+/// (in our case lanes) in `successors` as are reachable from it.
+///
+/// Greedily continues onto an unvisited successor while there is one,
+/// otherwise takes the shortest detour (in number of edges) to the nearest unvisited edge.
+/// Edges that are only mentioned as successors, but have no entry themselves,
+/// are outside of the area to cover and never driven on.
+pub fn coverage_route<E: Copy + Eq + Hash>(
+    start: E,
+    successors: &FnvHashMap<E, Vec<E>>,
+) -> Vec<E> {
+    let mut route = vec![start];
+    let mut visited = FnvHashSet::default();
+    visited.insert(start);
+    let mut current = start;
+
+    loop {
+        let maybe_unvisited_next = successors.get(&current).and_then(|nexts| {
+            nexts
+                .iter()
+                .find(|next| successors.contains_key(next) && !visited.contains(next))
+                .cloned()
+        });
+
+        if let Some(next) = maybe_unvisited_next {
+            visited.insert(next);
+            route.push(next);
+            current = next;
+        } else if let Some(detour) = detour_to_unvisited(current, successors, &visited) {
+            for &edge in &detour {
+                visited.insert(edge);
+            }
+            current = *detour.last().expect("detours are never empty");
+            route.extend(detour);
+        } else {
+            return route;
+        }
+    }
+}
+
+/// Shortest sequence of edges after `from` that ends on an unvisited edge
+fn detour_to_unvisited<E: Copy + Eq + Hash>(
+    from: E,
+    successors: &FnvHashMap<E, Vec<E>>,
+    visited: &FnvHashSet<E>,
+) -> Option<Vec<E>> {
+    let mut came_from = FnvHashMap::default();
+    let mut queue = VecDeque::new();
+    queue.push_back(from);
+    came_from.insert(from, from);
+
+    while let Some(edge) = queue.pop_front() {
+        if !visited.contains(&edge) {
+            let mut detour = vec![edge];
+            let mut backtrack = edge;
+            while came_from[&backtrack] != from {
+                backtrack = came_from[&backtrack];
+                detour.push(backtrack);
+            }
+            detour.reverse();
+            return Some(detour);
+        }
+
+        for &next in successors.get(&edge).into_iter().flat_map(|nexts| nexts.iter()) {
+            if successors.contains_key(&next) && !came_from.contains_key(&next) {
+                came_from.insert(next, edge);
+                queue.push_back(next);
+            }
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::coverage_route;
+    use fnv::FnvHashMap;
+
+    fn network(edges: &[(u32, &[u32])]) -> FnvHashMap<u32, Vec<u32>> {
+        edges.iter().map(|&(edge, nexts)| (edge, nexts.to_vec())).collect()
+    }
+
+    #[test]
+    fn covers_a_loop_in_one_go() {
+        let successors = network(&[(0, &[1]), (1, &[2]), (2, &[0])]);
+        assert_eq!(coverage_route(0, &successors), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn detours_over_visited_edges_to_reach_the_rest() {
+        // 0 branches into the dead end 1 (which leads back to 0) and into 2
+        let successors = network(&[(0, &[1, 2]), (1, &[0]), (2, &[])]);
+        assert_eq!(coverage_route(0, &successors), vec![0, 1, 0, 2]);
+    }
+
+    #[test]
+    fn ignores_edges_outside_of_the_covered_area() {
+        let successors = network(&[(0, &[7, 1]), (1, &[])]);
+        assert_eq!(coverage_route(0, &successors), vec![0, 1]);
+    }
+
+    #[test]
+    fn stops_when_nothing_else_is_reachable() {
+        let successors = network(&[(0, &[]), (1, &[0])]);
+        assert_eq!(coverage_route(0, &successors), vec![0]);
+    }
+}
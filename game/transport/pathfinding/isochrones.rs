@@ -0,0 +1,94 @@
+//! Travel times from a few starting edges to everywhere reachable from them,
+//! for drawing isochrones, like how fast emergency vehicles reach each part of the city.
+use fnv::FnvHashMap;
+use ordered_float::OrderedFloat;
+use std::collections::BinaryHeap;
+use std::cmp::Ordering;
+use std::hash::Hash;
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+struct Candidate<E> {
+    time: OrderedFloat<f32>,
+    edge: E,
+}
+
+// reversed, so the BinaryHeap pops the earliest candidate first
+impl<E: Eq> Ord for Candidate<E> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.time.cmp(&self.time)
+    }
+}
+
+impl<E: Eq> PartialOrd for Candidate<E> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// The earliest time each edge in `successors` can be entered when leaving from
+/// one of the `starts` at time 0.0, where driving along an edge takes `cost(edge)`.
+/// Edges that can't be reached are missing from the result.
+pub fn travel_times<E, F>(
+    starts: &[E],
+    successors: &FnvHashMap<E, Vec<E>>,
+    cost: F,
+) -> FnvHashMap<E, f32>
+where
+    E: Copy + Eq + Hash,
+    F: Fn(E) -> f32,
+{
+    let mut times = FnvHashMap::default();
+    let mut queue = starts
+        .iter()
+        .map(|&edge| Candidate { time: OrderedFloat(0.0), edge })
+        .collect::<BinaryHeap<_>>();
+
+    while let Some(Candidate { time, edge }) = queue.pop() {
+        if times.contains_key(&edge) {
+            continue;
+        }
+        times.insert(edge, *time);
+
+        let time_after = *time + cost(edge);
+        for &next in successors.get(&edge).into_iter().flat_map(|nexts| nexts.iter()) {
+            if successors.contains_key(&next) && !times.contains_key(&next) {
+                queue.push(Candidate { time: OrderedFloat(time_after), edge: next });
+            }
+        }
+    }
+
+    times
+}
+
+#[cfg(test)]
+mod tests {
+    use super::travel_times;
+    use fnv::FnvHashMap;
+
+    fn network(edges: &[(u32, &[u32])]) -> FnvHashMap<u32, Vec<u32>> {
+        edges.iter().map(|&(edge, nexts)| (edge, nexts.to_vec())).collect()
+    }
+
+    #[test]
+    fn prefers_the_faster_of_two_ways() {
+        // 0 leads to 3 either over the slow edge 1 or the fast edge 2
+        let successors = network(&[(0, &[1, 2]), (1, &[3]), (2, &[3]), (3, &[])]);
+        let times = travel_times(&[0], &successors, |edge| if edge == 1 { 10.0 } else { 1.0 });
+        assert_eq!(times[&3], 2.0);
+    }
+
+    #[test]
+    fn takes_the_nearest_of_several_starts() {
+        let successors = network(&[(0, &[1]), (1, &[2]), (2, &[]), (3, &[2])]);
+        let times = travel_times(&[0, 3], &successors, |_| 1.0);
+        assert_eq!(times[&2], 1.0);
+        assert_eq!(times[&1], 1.0);
+    }
+
+    #[test]
+    fn leaves_out_unreachable_edges() {
+        let successors = network(&[(0, &[]), (1, &[0])]);
+        let times = travel_times(&[0], &successors, |_| 1.0);
+        assert!(!times.contains_key(&1));
+    }
+}
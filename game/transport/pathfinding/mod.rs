@@ -2,19 +2,30 @@ use compact::{CDict, CVec, CHashMap};
 use kay::{ActorSystem, World};
 use super::lane::{Lane, LaneID, TransferLane, TransferLaneID};
 use super::lane::connectivity::{Interaction, InteractionKind, OverlapKind};
-use core::simulation::Timestamp;
+use super::restrictions::{LaneRestriction, VehicleClass, FreightClearance};
+use core::simulation::{Timestamp, SimulationConfig};
+use core::units::Meters;
 
 // TODO: MAKE TRANSFER LANE NOT PARTICIPATE AT ALL IN PATHFINDING -> MUCH SIMPLER
 
 pub mod trip;
+pub mod coverage;
+pub mod isochrones;
+pub mod tour;
+pub mod route_learning;
+pub mod active_modes;
+pub mod mode_choice;
 
 pub trait Node {
     fn update_routes(&mut self, world: &mut World);
     fn query_routes(&mut self, requester: NodeID, is_transfer: bool, world: &mut World);
     fn on_routes(
         &mut self,
-        new_routes: &CDict<Location, (f32, u8)>,
+        new_routes: &CDict<Location, (Meters, u8, FreightClearance)>,
+        restricted_routes: &CDict<Location, (Meters, u8, FreightClearance, LaneRestriction)>,
+        freight_routes: &CDict<Location, (Meters, u8)>,
         from: NodeID,
+        via: LaneRestriction,
         world: &mut World,
     );
     fn forget_routes(&mut self, forget: &CVec<Location>, from: NodeID, world: &mut World);
@@ -39,12 +50,69 @@ pub struct PathfindingInfo {
     pub hops_from_landmark: u8,
     pub learned_landmark_from: Option<NodeID>,
     pub routes: CHashMap<Location, RoutingInfo>,
+    /// Routes through restricted lanes, usable only by the vehicles they permit
+    pub restricted_routes: CHashMap<Location, (RoutingInfo, LaneRestriction)>,
+    /// The shortest routes that oversize trucks can take, which only differ from
+    /// `routes` where those cross lanes with limits (see `restrictions::LaneLimits`)
+    pub freight_routes: CHashMap<Location, RoutingInfo>,
     pub routes_changed: bool,
     pub tell_to_forget_next_tick: CVec<Location>,
     pub query_routes_next_tick: bool,
     pub routing_timeout: u16,
 }
 
+impl PathfindingInfo {
+    /// The shortest route to `destination` (or its landmark) that `vehicle` may take
+    /// and that is cleared for it, should it be a freight vehicle
+    pub fn route_for(&self, destination: Location, vehicle: VehicleClass) -> Option<&RoutingInfo> {
+        let needed_clearance = vehicle.required_clearance();
+        let general = match self.routes.get(destination).or_else(|| {
+            self.routes.get(destination.landmark_destination())
+        }) {
+            Some(routing_info) if routing_info.clearance < needed_clearance => {
+                self.freight_routes.get(destination).or_else(|| {
+                    self.freight_routes.get(destination.landmark_destination())
+                })
+            }
+            general => general,
+        };
+        let restricted = self.restricted_routes
+            .get(destination)
+            .or_else(|| {
+                self.restricted_routes.get(destination.landmark_destination())
+            })
+            .and_then(|&(ref routing_info, restriction)| if restriction.permits(vehicle) &&
+                routing_info.clearance >= needed_clearance
+            {
+                Some(routing_info)
+            } else {
+                None
+            });
+
+        match (general, restricted) {
+            (Some(general), Some(restricted)) => {
+                if restricted.distance < general.distance {
+                    Some(restricted)
+                } else {
+                    Some(general)
+                }
+            }
+            (general, restricted) => general.or(restricted),
+        }
+    }
+}
+
+impl PathfindingInfo {
+    /// Whether a route to `destination` that is this many hops long is worth keeping:
+    /// routes to single nodes are only kept close to them or within their landmark
+    fn keeps_route_to(&self, destination: Location, distance_hops: u8) -> bool {
+        destination.is_landmark() || distance_hops <= IDEAL_LANDMARK_RADIUS ||
+            self.location
+                .map(|self_dest| self_dest.landmark == destination.landmark)
+                .unwrap_or(false)
+    }
+}
+
 #[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
 pub struct Location {
     pub landmark: NodeID,
@@ -66,7 +134,9 @@ impl Location {
 #[derive(Copy, Clone)]
 pub struct RoutingInfo {
     pub outgoing_idx: u8,
-    pub distance: f32,
+    pub distance: Meters,
+    /// The freight that the most limited lane along the route still takes
+    pub clearance: FreightClearance,
     distance_hops: u8,
     learned_from: NodeID,
     fresh: bool,
@@ -89,34 +159,188 @@ pub struct RoutingInfo {
 // }
 
 pub fn on_connect(lane: &mut Lane) {
-    lane.pathfinding.routing_timeout = ROUTING_TIMEOUT_AFTER_CHANGE;
+    lane.pathfinding.routing_timeout = SimulationConfig::current().routing_timeout_after_change;
 }
 
 use super::microtraffic::LaneLikeID;
 
-pub fn on_disconnect(lane: &mut Lane, disconnected_id: LaneLikeID) {
-    let new_routes = lane.pathfinding
-        .routes
+/// Where an interaction at `idx` ends up after the interactions at
+/// `removed_indices` (sorted ascending) were removed
+pub fn shifted_interaction_idx(idx: usize, removed_indices: &[usize]) -> usize {
+    idx - removed_indices.iter().take_while(|&&removed| removed < idx).count()
+}
+
+fn routes_without_disconnected(
+    routes: &CHashMap<Location, RoutingInfo>,
+    disconnected_id: LaneLikeID,
+    removed_interactions: &[usize],
+) -> CHashMap<Location, RoutingInfo> {
+    let disconnected_node: NodeID = disconnected_id.into();
+    routes
+        .pairs()
+        .filter_map(|(destination, route)| if route.learned_from == disconnected_node {
+            None
+        } else {
+            Some((
+                *destination,
+                RoutingInfo {
+                    outgoing_idx: shifted_interaction_idx(
+                        route.outgoing_idx as usize,
+                        removed_interactions,
+                    ) as u8,
+                    ..*route
+                },
+            ))
+        })
+        .collect()
+}
+
+pub fn on_disconnect(
+    lane: &mut Lane,
+    disconnected_id: LaneLikeID,
+    removed_interactions: &[usize],
+) {
+    let new_routes = routes_without_disconnected(
+        &lane.pathfinding.routes,
+        disconnected_id,
+        removed_interactions,
+    );
+    lane.pathfinding.routes = new_routes;
+    let new_freight_routes = routes_without_disconnected(
+        &lane.pathfinding.freight_routes,
+        disconnected_id,
+        removed_interactions,
+    );
+    lane.pathfinding.freight_routes = new_freight_routes;
+    let disconnected_node: NodeID = disconnected_id.into();
+    let new_restricted_routes = lane.pathfinding
+        .restricted_routes
         .pairs()
-        // TODO: ugly: untyped ID shenanigans
-        .filter_map(|(destination, route)| if route.learned_from._raw_id ==
-            disconnected_id._raw_id
+        .filter_map(|(destination, &(route, restriction))| if route.learned_from ==
+            disconnected_node
         {
             None
         } else {
-            Some((*destination, *route))
+            let shifted_route = RoutingInfo {
+                outgoing_idx: shifted_interaction_idx(
+                    route.outgoing_idx as usize,
+                    removed_interactions,
+                ) as u8,
+                ..route
+            };
+            Some((*destination, (shifted_route, restriction)))
         })
         .collect();
-    lane.pathfinding.routes = new_routes;
+    lane.pathfinding.restricted_routes = new_restricted_routes;
     lane.pathfinding.routes_changed = true;
     lane.pathfinding.query_routes_next_tick = true;
 }
 
 const IDEAL_LANDMARK_RADIUS: u8 = 3;
 const MIN_LANDMARK_INCOMING: usize = 3;
-const ROUTING_TIMEOUT_AFTER_CHANGE: u16 = 15;
-const LANE_CHANGE_COST_LEFT: f32 = 5.0;
-const LANE_CHANGE_COST_RIGHT: f32 = 3.0;
+
+impl Lane {
+    /// Keeps a route that needs lanes with `restriction` if it's new, shorter or an update
+    /// from where we learned it. We pass it on if vehicles can reach it through us: from
+    /// general lanes or from lanes with the same restriction
+    fn learn_restricted_route(
+        &mut self,
+        destination: Location,
+        new_routing_info: RoutingInfo,
+        restriction: LaneRestriction,
+    ) {
+        let insert = self.pathfinding
+            .restricted_routes
+            .get(destination)
+            .map(|&(RoutingInfo { distance, learned_from, .. }, old_restriction)| {
+                new_routing_info.distance < distance ||
+                    (learned_from == new_routing_info.learned_from &&
+                         (new_routing_info.distance != distance ||
+                              old_restriction != restriction))
+            })
+            .unwrap_or(true);
+        if insert {
+            self.pathfinding.restricted_routes.insert(
+                destination,
+                (new_routing_info, restriction),
+            );
+            if self.restriction == LaneRestriction::General || self.restriction == restriction {
+                self.pathfinding.routes_changed = true;
+            }
+        }
+    }
+
+    /// Replaces what we learned about oversize routes from `from` with what it advertises
+    /// now: its general routes that are fully cleared, plus its own oversize routes.
+    /// Only general lanes take trucks, so nothing is learned through restricted ones
+    fn learn_freight_routes(
+        &mut self,
+        new_routes: &CDict<Location, (Meters, u8, FreightClearance)>,
+        freight_routes: &CDict<Location, (Meters, u8)>,
+        from: NodeID,
+        from_interaction_idx: u8,
+        via: LaneRestriction,
+    ) {
+        let previous = self.pathfinding
+            .freight_routes
+            .pairs()
+            .filter(|&(_, routing_info)| routing_info.learned_from == from)
+            .map(|(&destination, routing_info)| (destination, routing_info.distance))
+            .collect::<Vec<_>>();
+        for &(destination, _) in &previous {
+            self.pathfinding.freight_routes.remove(destination);
+        }
+
+        let mut changed = false;
+
+        if via == LaneRestriction::General {
+            let candidates = new_routes
+                .pairs()
+                .filter(|&(_, &(_, _, clearance))| {
+                    clearance == FreightClearance::OversizeTrucks
+                })
+                .map(|(&destination, &(distance, hops, _))| (destination, distance, hops))
+                .chain(freight_routes.pairs().map(|(&destination, &(distance, hops))| {
+                    (destination, distance, hops)
+                }))
+                .collect::<Vec<_>>();
+
+            for (destination, new_distance, new_distance_hops) in candidates {
+                let insert = self.pathfinding.keeps_route_to(destination, new_distance_hops) &&
+                    self.pathfinding
+                        .freight_routes
+                        .get(destination)
+                        .map(|&RoutingInfo { distance, .. }| new_distance < distance)
+                        .unwrap_or(true);
+                if insert {
+                    self.pathfinding.freight_routes.insert(
+                        destination,
+                        RoutingInfo {
+                            distance: new_distance,
+                            clearance: FreightClearance::OversizeTrucks,
+                            distance_hops: new_distance_hops,
+                            outgoing_idx: from_interaction_idx,
+                            learned_from: from,
+                            fresh: true,
+                        },
+                    );
+                    changed |= !previous.contains(&(destination, new_distance));
+                }
+            }
+        }
+
+        let lost = previous.iter().any(|&(destination, _)| {
+            !self.pathfinding.freight_routes.contains_key(destination)
+        });
+        if lost {
+            // other successors might still know a way
+            self.pathfinding.query_routes_next_tick = true;
+        }
+        if changed || lost {
+            self.pathfinding.routes_changed = true;
+        }
+    }
+}
 
 impl Node for Lane {
     fn update_routes(&mut self, world: &mut World) {
@@ -140,10 +364,12 @@ impl Node for Lane {
                 hops_from_landmark: 0,
                 learned_landmark_from: Some(self.id.into()),
                 routes: CHashMap::new(),
+                restricted_routes: CHashMap::new(),
+                freight_routes: CHashMap::new(),
                 routes_changed: true,
                 query_routes_next_tick: false,
                 tell_to_forget_next_tick: CVec::new(),
-                routing_timeout: ROUTING_TIMEOUT_AFTER_CHANGE,
+                routing_timeout: SimulationConfig::current().routing_timeout_after_change,
             }
         }
 
@@ -171,33 +397,18 @@ impl Node for Lane {
             if self.pathfinding.routes_changed {
                 for (_, predecessor, is_transfer) in predecessors(self) {
                     let self_cost = if is_transfer {
-                        0.0
+                        Meters(0.0)
                     } else {
-                        self.construction.length
+                        Meters(self.routing_cost())
                     };
-                    predecessor.on_routes(self.pathfinding
-                            .routes
-                            .pairs()
-                            .filter_map(|(&destination,
-                              &RoutingInfo { distance, distance_hops, .. })| {
-                                if true
-                                // fresh
-                                {
-                                    Some((destination, (distance + self_cost, distance_hops + 1)))
-                                } else {
-                                    None
-                                }
-                            })
-                            .chain(
-                                if self.connectivity.on_intersection {
-                                    None
-                                } else {
-                                    self.pathfinding
-                                        .location
-                                        .map(|destination| (destination, (self_cost, 0)))
-                                },
-                            )
-                            .collect(), self.id.into(), world);
+                    predecessor.on_routes(
+                        advertised_routes(self, self_cost),
+                        advertised_restricted_routes(self, self_cost),
+                        advertised_freight_routes(self, self_cost),
+                        self.id.into(),
+                        self.restriction,
+                        world,
+                    );
                 }
                 for routing_info in self.pathfinding.routes.values_mut() {
                     routing_info.fresh = false;
@@ -208,68 +419,130 @@ impl Node for Lane {
     }
 
     fn query_routes(&mut self, requester: NodeID, is_transfer: bool, world: &mut World) {
+        if !self.construction.is_finished() {
+            // we will advertise our routes once we're open
+            return;
+        }
         let self_cost = if is_transfer {
-            0.0
+            Meters(0.0)
         } else {
-            self.construction.length
+            Meters(self.routing_cost())
         };
         requester.on_routes(
-            self.pathfinding
-                .routes
-                .pairs()
-                .map(|(&destination,
-                  &RoutingInfo { distance, distance_hops, .. })| {
-                    (destination, (distance + self_cost, distance_hops + 1))
-                })
-                .chain(if self.connectivity.on_intersection {
-                    None
-                } else {
-                    self.pathfinding.location.map(|destination| {
-                        (destination, (self_cost, 0))
-                    })
-                })
-                .collect(),
+            advertised_routes(self, self_cost),
+            advertised_restricted_routes(self, self_cost),
+            advertised_freight_routes(self, self_cost),
             self.id.into(),
+            self.restriction,
             world,
         );
     }
 
-    fn on_routes(&mut self, new_routes: &CDict<Location, (f32, u8)>, from: NodeID, _: &mut World) {
+    fn on_routes(
+        &mut self,
+        new_routes: &CDict<Location, (Meters, u8, FreightClearance)>,
+        restricted_routes: &CDict<Location, (Meters, u8, FreightClearance, LaneRestriction)>,
+        freight_routes: &CDict<Location, (Meters, u8)>,
+        from: NodeID,
+        via: LaneRestriction,
+        _: &mut World,
+    ) {
         if let Some(from_interaction_idx) =
             self.connectivity.interactions.iter().position(
-                |interaction| {
-                    // TODO: ugly: untyped ID shenanigans
-                    interaction.partner_lane._raw_id == from._raw_id
-                },
+                |interaction| NodeID::from(interaction.partner_lane) == from,
             )
         {
-            for (&destination, &(new_distance, new_distance_hops)) in new_routes.pairs() {
-                if destination.is_landmark() || new_distance_hops <= IDEAL_LANDMARK_RADIUS ||
-                    self.pathfinding
-                        .location
-                        .map(|self_dest| self_dest.landmark == destination.landmark)
-                        .unwrap_or(false)
-                {
-                    let insert = self.pathfinding
-                        .routes
-                        .get(destination)
-                        .map(|&RoutingInfo { distance, .. }| new_distance < distance)
-                        .unwrap_or(true);
-                    if insert {
-                        self.pathfinding.routes.insert(
-                            destination,
-                            RoutingInfo {
-                                distance: new_distance,
-                                distance_hops: new_distance_hops,
-                                outgoing_idx: from_interaction_idx as u8,
-                                learned_from: from,
-                                fresh: true,
-                            },
-                        );
-                        self.pathfinding.routes_changed = true;
+            // the sender always advertises all of its restricted routes, so any we
+            // learned from it before that are missing now are gone (or were only
+            // restricted because the sender itself was, which it might no longer be)
+            let stale_restricted = self.pathfinding
+                .restricted_routes
+                .pairs()
+                .filter(|&(destination, &(ref routing_info, _))| {
+                    routing_info.learned_from == from &&
+                        !restricted_routes.contains_key(*destination) &&
+                        (via == LaneRestriction::General || !new_routes.contains_key(*destination))
+                })
+                .map(|(&destination, _)| destination)
+                .collect::<Vec<_>>();
+            for destination in stale_restricted {
+                self.pathfinding.restricted_routes.remove(destination);
+            }
+
+            // the sender might have been restricted since we learned from it
+            if via != LaneRestriction::General {
+                let stale = self.pathfinding
+                    .routes
+                    .pairs()
+                    .filter(|&(_, routing_info)| routing_info.learned_from == from)
+                    .map(|(&destination, _)| destination)
+                    .collect::<Vec<_>>();
+                for &destination in &stale {
+                    self.pathfinding.routes.remove(destination);
+                }
+                self.pathfinding.tell_to_forget_next_tick.extend(stale);
+            }
+
+            for (&destination, &(new_distance, new_distance_hops, new_clearance)) in
+                new_routes.pairs()
+            {
+                if self.pathfinding.keeps_route_to(destination, new_distance_hops) {
+                    let new_routing_info = RoutingInfo {
+                        distance: new_distance,
+                        clearance: new_clearance,
+                        distance_hops: new_distance_hops,
+                        outgoing_idx: from_interaction_idx as u8,
+                        learned_from: from,
+                        fresh: true,
+                    };
+
+                    if via == LaneRestriction::General {
+                        // also relearned when limits or congestion along the route changed
+                        let insert = self.pathfinding
+                            .routes
+                            .get(destination)
+                            .map(|&RoutingInfo { distance, clearance, learned_from, .. }| {
+                                new_distance < distance ||
+                                    (learned_from == from &&
+                                         (clearance != new_clearance || new_distance != distance))
+                            })
+                            .unwrap_or(true);
+                        if insert {
+                            self.pathfinding.routes.insert(destination, new_routing_info);
+                            self.pathfinding.routes_changed = true;
+                        }
+                    } else {
+                        self.learn_restricted_route(destination, new_routing_info, via);
                     }
                 }
             }
+
+            for (&destination,
+                 &(new_distance, new_distance_hops, new_clearance, restriction)) in
+                restricted_routes.pairs()
+            {
+                // a route can't switch from one restriction to another along the way
+                let usable = via == LaneRestriction::General || via == restriction;
+                if usable && self.pathfinding.keeps_route_to(destination, new_distance_hops) {
+                    let new_routing_info = RoutingInfo {
+                        distance: new_distance,
+                        clearance: new_clearance,
+                        distance_hops: new_distance_hops,
+                        outgoing_idx: from_interaction_idx as u8,
+                        learned_from: from,
+                        fresh: true,
+                    };
+                    self.learn_restricted_route(destination, new_routing_info, restriction);
+                }
+            }
+
+            self.learn_freight_routes(
+                new_routes,
+                freight_routes,
+                from,
+                from_interaction_idx as u8,
+                via,
+            );
         } else {
             println!(
                 "{:?} not yet connected to {:?}",
@@ -288,15 +561,37 @@ impl Node for Lane {
                 } else {
                     false
                 };
+            let forget_restricted = self.pathfinding
+                .restricted_routes
+                .get(*destination_to_forget)
+                .map(|&(ref routing_info, _)| routing_info.learned_from == from)
+                .unwrap_or(false);
+            if forget_restricted {
+                self.pathfinding.restricted_routes.remove(*destination_to_forget);
+            }
+            let forget_freight = self.pathfinding
+                .freight_routes
+                .get(*destination_to_forget)
+                .map(|routing_info| routing_info.learned_from == from)
+                .unwrap_or(false);
+            if forget_freight {
+                self.pathfinding.freight_routes.remove(*destination_to_forget);
+            }
             if forget {
                 self.pathfinding.routes.remove(*destination_to_forget);
                 if destination_to_forget.is_landmark() {
                     self.microtraffic.cars.retain(|car| {
                         car.destination.landmark != destination_to_forget.landmark
+                    });
+                    self.microtraffic.mesoscopic.queue.retain(|queued| {
+                        queued.car.destination.landmark != destination_to_forget.landmark
                     })
                 } else {
                     self.microtraffic.cars.retain(|car| {
                         &car.destination != destination_to_forget
+                    });
+                    self.microtraffic.mesoscopic.queue.retain(|queued| {
+                        &queued.car.destination != destination_to_forget
                     })
                 }
                 forgotten.push(*destination_to_forget);
@@ -332,6 +627,7 @@ impl Node for Lane {
             let tell_to_forget_next_tick = self.pathfinding
                 .routes
                 .keys()
+                .chain(self.pathfinding.freight_routes.keys())
                 .cloned()
                 .chain(self.pathfinding.location.into_iter())
                 .collect();
@@ -340,10 +636,12 @@ impl Node for Lane {
                 learned_landmark_from: Some(from),
                 hops_from_landmark: hops_from_landmark,
                 routes: CHashMap::new(),
+                restricted_routes: CHashMap::new(),
+                freight_routes: CHashMap::new(),
                 routes_changed: true,
                 query_routes_next_tick: true,
                 tell_to_forget_next_tick: tell_to_forget_next_tick,
-                routing_timeout: ROUTING_TIMEOUT_AFTER_CHANGE,
+                routing_timeout: SimulationConfig::current().routing_timeout_after_change,
             };
         }
     }
@@ -368,8 +666,99 @@ impl Node for Lane {
 }
 
 
+/// The routes a lane offers to its predecessors, including its own location.
+/// Each route carries the freight it clears, including the lane's own limits.
+/// Predecessors learn these as restricted routes if the lane itself is restricted.
+fn advertised_routes(
+    lane: &Lane,
+    self_cost: Meters,
+) -> CDict<Location, (Meters, u8, FreightClearance)> {
+    let own_clearance = lane.limits.clearance();
+    let mut advertised = lane.pathfinding
+        .routes
+        .pairs()
+        .map(|(&destination, &RoutingInfo { distance, distance_hops, clearance, .. })| {
+            (
+                destination,
+                (distance + self_cost, distance_hops + 1, clearance.min(own_clearance)),
+            )
+        })
+        .collect::<CDict<_, _>>();
+
+    if !lane.connectivity.on_intersection {
+        if let Some(location) = lane.pathfinding.location {
+            advertised.insert(location, (self_cost, 0, own_clearance));
+        }
+    }
+
+    advertised
+}
+
+/// The routes further ahead that need restricted lanes, tagged with their restriction,
+/// and only where they are shorter than what `advertised_routes` offers.
+/// General lanes pass on all of them, so that e.g. buses several lanes upstream of a
+/// bus lane still learn about it, while restricted lanes only pass on those with the
+/// same restriction, so that chains of e.g. bus lanes stay routable.
+fn advertised_restricted_routes(
+    lane: &Lane,
+    self_cost: Meters,
+) -> CDict<Location, (Meters, u8, FreightClearance, LaneRestriction)> {
+    let own_clearance = lane.limits.clearance();
+    lane.pathfinding
+        .restricted_routes
+        .pairs()
+        .filter(|&(&destination, &(RoutingInfo { distance, .. }, restriction))| {
+            let passable = lane.restriction == LaneRestriction::General ||
+                lane.restriction == restriction;
+            let shorter = lane.pathfinding
+                .routes
+                .get(destination)
+                .map(|routing_info| distance < routing_info.distance)
+                .unwrap_or(true);
+            passable && shorter
+        })
+        .map(|(&destination,
+               &(RoutingInfo { distance, distance_hops, clearance, .. }, restriction))| {
+            (
+                destination,
+                (
+                    distance + self_cost,
+                    distance_hops + 1,
+                    clearance.min(own_clearance),
+                    restriction,
+                ),
+            )
+        })
+        .collect()
+}
+
+/// The oversize routes a lane offers in addition to `advertised_routes`: only those
+/// to destinations where the shortest route isn't fully cleared, since predecessors
+/// can use all other shortest routes as they are. None at all if the lane itself
+/// doesn't take oversize trucks.
+fn advertised_freight_routes(lane: &Lane, self_cost: Meters) -> CDict<Location, (Meters, u8)> {
+    if lane.limits.clearance() < FreightClearance::OversizeTrucks {
+        return CDict::new();
+    }
+
+    lane.pathfinding
+        .freight_routes
+        .pairs()
+        .filter(|&(&destination, _)| {
+            lane.pathfinding
+                .routes
+                .get(destination)
+                .map(|routing_info| routing_info.clearance < FreightClearance::OversizeTrucks)
+                .unwrap_or(true)
+        })
+        .map(|(&destination, &RoutingInfo { distance, distance_hops, .. })| {
+            (destination, (distance + self_cost, distance_hops + 1))
+        })
+        .collect()
+}
+
 #[allow(needless_lifetimes)]
-fn successors<'a>(lane: &'a Lane) -> impl Iterator<Item = NodeID> + 'a {
+pub fn successors<'a>(lane: &'a Lane) -> impl Iterator<Item = NodeID> + 'a {
     lane.connectivity.interactions.iter().filter_map(
         |interaction| {
             match *interaction {
@@ -378,12 +767,11 @@ fn successors<'a>(lane: &'a Lane) -> impl Iterator<Item = NodeID> + 'a {
                     kind: InteractionKind::Overlap { kind: OverlapKind::Transfer, .. },
                     ..
                 } |
-                // TODO: ugly: untyped ID shenanigans
                 Interaction {
                     partner_lane,
                     kind: InteractionKind::Next { .. },
                     ..
-                } => Some(NodeID { _raw_id: partner_lane._raw_id }),
+                } => Some(partner_lane.into()),
                 _ => None,
             }
         },
@@ -397,18 +785,24 @@ fn predecessors<'a>(lane: &'a Lane) -> impl Iterator<Item = (u8, NodeID, bool)>
         .iter()
         .enumerate()
         .filter_map(|(i, interaction)| match *interaction {
-            // TODO: ugly: untyped ID shenanigans
             Interaction {
                 partner_lane,
                 kind: InteractionKind::Overlap { kind: OverlapKind::Transfer, .. },
                 ..
-            } => Some((i as u8, NodeID { _raw_id: partner_lane._raw_id }, true)),
-            // TODO: ugly: untyped ID shenanigans
+            } => Some((
+                i as u8,
+                partner_lane.into(),
+                true,
+            )),
             Interaction {
                 partner_lane,
                 kind: InteractionKind::Previous { .. },
                 ..
-            } => Some((i as u8, NodeID { _raw_id: partner_lane._raw_id }, false)),
+            } => Some((
+                i as u8,
+                partner_lane.into(),
+                false,
+            )),
             _ => None,
         })
 }
@@ -429,43 +823,53 @@ impl Node for TransferLane {
     fn update_routes(&mut self, _: &mut World) {}
 
     fn query_routes(&mut self, requester: NodeID, _is_transfer: bool, world: &mut World) {
-        // TODO: ugly: untyped ID shenanigans
-        let requester_lane = LaneID { _raw_id: requester._raw_id };
-        let other_lane: NodeID = self.other_side(requester_lane).into();
+        let other_lane: NodeID = self.other_side(requester.into()).into();
         other_lane.query_routes(self.id.into(), true, world);
     }
 
     fn on_routes(
         &mut self,
-        new_routes: &CDict<Location, (f32, u8)>,
+        new_routes: &CDict<Location, (Meters, u8, FreightClearance)>,
+        restricted_routes: &CDict<Location, (Meters, u8, FreightClearance, LaneRestriction)>,
+        freight_routes: &CDict<Location, (Meters, u8)>,
         from: NodeID,
+        via: LaneRestriction,
         world: &mut World,
     ) {
-        let from_lane = LaneID { _raw_id: from._raw_id };
-        let other_lane: NodeID = self.other_side(from_lane).into();
+        let config = SimulationConfig::current();
+        let other_lane: NodeID = self.other_side(from.into()).into();
+        let change_cost = if from == self.connectivity.left.expect("should have left").0.into() {
+            config.lane_change_cost_right
+        } else {
+            config.lane_change_cost_left
+        };
         other_lane.on_routes(
             new_routes
+                .pairs()
+                .map(|(&destination, &(distance, hops, clearance))| {
+                    (destination, (distance + change_cost, hops, clearance))
+                })
+                .collect(),
+            restricted_routes
+                .pairs()
+                .map(|(&destination, &(distance, hops, clearance, restriction))| {
+                    (destination, (distance + change_cost, hops, clearance, restriction))
+                })
+                .collect(),
+            freight_routes
                 .pairs()
                 .map(|(&destination, &(distance, hops))| {
-                    // TODO: ugly: untyped ID shenanigans
-                    let change_cost = if from._raw_id ==
-                        self.connectivity.left.expect("should have left").0._raw_id
-                    {
-                        LANE_CHANGE_COST_RIGHT
-                    } else {
-                        LANE_CHANGE_COST_LEFT
-                    };
                     (destination, (distance + change_cost, hops))
                 })
                 .collect(),
             self.id.into(),
+            via,
             world,
         );
     }
 
     fn forget_routes(&mut self, forget: &CVec<Location>, from: NodeID, world: &mut World) {
-        let from_lane = LaneID { _raw_id: from._raw_id };
-        let other_lane: NodeID = self.other_side(from_lane).into();
+        let other_lane: NodeID = self.other_side(from.into()).into();
         other_lane.forget_routes(forget.clone(), self.id.into(), world);
     }
 
@@ -476,8 +880,7 @@ impl Node for TransferLane {
         hops_from_landmark: u8,
         world: &mut World,
     ) {
-        let from_lane = LaneID { _raw_id: from._raw_id };
-        let other_lane: NodeID = self.other_side(from_lane).into();
+        let other_lane: NodeID = self.other_side(from.into()).into();
         other_lane.join_landmark(
             self.id.into(),
             Location {
@@ -520,14 +923,17 @@ pub trait LocationRequester {
 }
 
 pub trait DistanceRequester {
-    fn on_distance(&mut self, maybe_distance: Option<f32>, world: &mut World);
+    fn on_distance(&mut self, maybe_distance: Option<Meters>, world: &mut World);
 }
 
 use core::simulation::SimulationID;
+use stagemaster::UserInterfaceID;
 
-pub fn setup(system: &mut ActorSystem, simulation: SimulationID) {
+pub fn setup(system: &mut ActorSystem, user_interface: UserInterfaceID, simulation: SimulationID) {
     trip::setup(system, simulation);
     auto_setup(system);
+    active_modes::setup(system, user_interface, simulation);
+    mode_choice::setup(system, user_interface, simulation);
 }
 
 mod kay_auto;
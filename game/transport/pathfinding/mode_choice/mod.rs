@@ -0,0 +1,1106 @@
+//! Before a household member sets off, they pick how to travel: a multinomial logit
+//! model compares the estimated time and cost of driving, walking, cycling, transit
+//! and park-and-ride (driving to a station's parking lot, then continuing by transit).
+//! Driving times follow the current congestion in the whole network, walking and cycling
+//! times come from the `ActiveModeGraph`, transit waits for half a headway.
+//! Some drivers are willing to carpool: the `CarpoolMatcher` puts those starting
+//! at the same place and heading to the same area into one car.
+use kay::{ActorSystem, World, External, Fate};
+use compact::CVec;
+use stagemaster::{UserInterfaceID, Interactable2d, Interactable2dID,
+                  MSG_Interactable2d_draw_ui_2d};
+use imgui::ImGuiSetCond_FirstUseEver;
+use core::simulation::{SimulationID, Sleeper, SleeperID, MSG_Sleeper_wake, Timestamp, Ticks,
+                       TICKS_PER_SIM_MINUTE};
+use transport::lane::{Lane, LaneID};
+use transport::restrictions::VehicleClass;
+use economy::buildings::BuildingID;
+use economy::households::park_and_ride::ParkAndRideID;
+use fnv::FnvHashMap;
+use std::sync::atomic::Ordering;
+use core::units::{Meters, MetersPerSecond};
+use super::{Location, RoughLocationID, NodeID, LocationRequester, LocationRequesterID,
+            MSG_LocationRequester_location_resolved, DistanceRequester, DistanceRequesterID,
+            MSG_DistanceRequester_on_distance};
+use super::trip::{TripID, TripListener, TripListenerID, MSG_TripListener_trip_created,
+                  MSG_TripListener_trip_result};
+use super::active_modes::{ActiveModeGraphID, ActiveTravelRequester, ActiveTravelRequesterID,
+                          MSG_ActiveTravelRequester_on_active_travel_minutes};
+
+const SETTINGS_CATEGORY: &'static str = "Mode Choice";
+const SAMPLE_INTERVAL: Ticks = Ticks(600);
+const COLLECTION_TICKS: Ticks = Ticks(10);
+/// Average driving speed on empty roads
+const FREE_FLOW_SPEED: MetersPerSecond = MetersPerSecond(10.0);
+/// Average transit speed, including stops. Transit has its own right of way
+/// (like bus lanes), so congestion doesn't slow it down
+const TRANSIT_SPEED: MetersPerSecond = MetersPerSecond(6.0);
+/// Park-and-ride travellers only come back for their cars after this long
+const PARKED_MINUTES: usize = 8 * 60;
+/// Congestion never slows traffic down more than this in the estimates
+const MIN_SPEED_RATIO: f32 = 0.1;
+/// Ride requests are collected for this long before they're matched into carpools
+const POOLING_WINDOW: Ticks = Ticks(5 * TICKS_PER_SIM_MINUTE);
+/// Most people a carpool fits, including the driver
+const CAR_CAPACITY: usize = 4;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum TravelMode {
+    Drive,
+    Walk,
+    Bike,
+    Transit,
+    ParkAndRide,
+}
+
+const ALL_MODES: [TravelMode; 5] = [
+    TravelMode::Drive,
+    TravelMode::Walk,
+    TravelMode::Bike,
+    TravelMode::Transit,
+    TravelMode::ParkAndRide,
+];
+
+impl TravelMode {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            TravelMode::Drive => "Drive",
+            TravelMode::Walk => "Walk",
+            TravelMode::Bike => "Bike",
+            TravelMode::Transit => "Transit",
+            TravelMode::ParkAndRide => "Park & Ride",
+        }
+    }
+
+    fn idx(&self) -> usize {
+        match *self {
+            TravelMode::Drive => 0,
+            TravelMode::Walk => 1,
+            TravelMode::Bike => 2,
+            TravelMode::Transit => 3,
+            TravelMode::ParkAndRide => 4,
+        }
+    }
+}
+
+/// One way of doing a trip, with its estimated door-to-door time and out-of-pocket cost
+#[derive(Copy, Clone, Debug)]
+pub struct ModeOption {
+    pub mode: TravelMode,
+    pub minutes: f32,
+    pub cost: f32,
+}
+
+/// Coefficients of the logit model and the costs it uses. The utility of a mode is
+/// its constant plus the time coefficient per minute plus the cost coefficient per money unit
+#[derive(Serialize, Deserialize, Copy, Clone)]
+pub struct ModeChoiceSettings {
+    pub time_coefficient: f32,
+    pub cost_coefficient: f32,
+    /// Preference for each mode apart from time and cost, in the order of `ALL_MODES`
+    pub constants: [f32; 5],
+    pub driving_cost_per_km: f32,
+    pub parking_cost: f32,
+    pub transit_fare: f32,
+    /// Minutes between buses. There are no transit lines yet,
+    /// so this stands in for all of them, 0.0 means there is no transit at all
+    pub transit_headway_minutes: f32,
+    /// Share of drivers who are willing to wait a bit to share their car with others
+    pub carpool_willingness: f32,
+}
+
+impl Default for ModeChoiceSettings {
+    fn default() -> Self {
+        ModeChoiceSettings {
+            time_coefficient: -0.1,
+            cost_coefficient: -0.3,
+            constants: [0.0, -0.5, -1.0, -0.8, -1.0],
+            driving_cost_per_km: 0.3,
+            parking_cost: 1.0,
+            transit_fare: 2.0,
+            transit_headway_minutes: 0.0,
+            carpool_willingness: 0.3,
+        }
+    }
+}
+
+impl ModeChoiceSettings {
+    fn utility(&self, option: &ModeOption) -> f32 {
+        self.constants[option.mode.idx()] + self.time_coefficient * option.minutes +
+            self.cost_coefficient * option.cost
+    }
+
+    /// The probability of choosing each of the `options`, in the same order
+    pub fn probabilities(&self, options: &[ModeOption]) -> Vec<f32> {
+        let utilities = options
+            .iter()
+            .map(|option| self.utility(option))
+            .collect::<Vec<_>>();
+        // subtracting the best utility keeps the exponentials from overflowing
+        let best = utilities.iter().cloned().fold(::std::f32::NEG_INFINITY, f32::max);
+        let weights = utilities
+            .iter()
+            .map(|utility| (utility - best).exp())
+            .collect::<Vec<_>>();
+        let total: f32 = weights.iter().sum();
+        weights.iter().map(|weight| weight / total).collect()
+    }
+}
+
+/// What a `ModeChooser` found out about a trip
+#[derive(Copy, Clone)]
+pub struct TripEstimate {
+    pub driving_distance: Option<Meters>,
+    pub walking_minutes: Option<f32>,
+    pub cycling_minutes: Option<f32>,
+    /// The best park-and-ride lot, with the driving distance to it
+    /// and the transit distance from it to the destination
+    pub park_and_ride: Option<(ParkAndRideID, Meters, Meters)>,
+}
+
+/// The ways of doing the trip described by `estimate`, when traffic flows at
+/// `speed_ratio` of its desired speed. `park_and_ride` are the distances
+/// to and from the best park-and-ride lot, if it still has free spaces
+fn mode_options(
+    settings: &ModeChoiceSettings,
+    speed_ratio: f32,
+    estimate: &TripEstimate,
+    park_and_ride: Option<(Meters, Meters)>,
+) -> Vec<ModeOption> {
+    let mut options = Vec::new();
+    let driving_minutes =
+        |distance: Meters| (distance / (FREE_FLOW_SPEED * speed_ratio)).minutes();
+    let driving_cost = |distance: Meters| distance.km() * settings.driving_cost_per_km;
+    let transit_minutes = |distance: Meters| {
+        settings.transit_headway_minutes / 2.0 + (distance / TRANSIT_SPEED).minutes()
+    };
+    let transit = settings.transit_headway_minutes > 0.0;
+
+    if let Some(distance) = estimate.driving_distance {
+        options.push(ModeOption {
+            mode: TravelMode::Drive,
+            minutes: driving_minutes(distance),
+            cost: driving_cost(distance) + settings.parking_cost,
+        });
+
+        if transit {
+            options.push(ModeOption {
+                mode: TravelMode::Transit,
+                minutes: transit_minutes(distance),
+                cost: settings.transit_fare,
+            });
+        }
+    }
+    if let Some(minutes) = estimate.walking_minutes {
+        options.push(ModeOption { mode: TravelMode::Walk, minutes, cost: 0.0 });
+    }
+    if let Some(minutes) = estimate.cycling_minutes {
+        options.push(ModeOption { mode: TravelMode::Bike, minutes, cost: 0.0 });
+    }
+    if let Some((to_lot, from_lot)) = park_and_ride {
+        if transit {
+            // parking at the station is free
+            options.push(ModeOption {
+                mode: TravelMode::ParkAndRide,
+                minutes: driving_minutes(to_lot) + transit_minutes(from_lot),
+                cost: driving_cost(to_lot) + settings.transit_fare,
+            });
+        }
+    }
+
+    options
+}
+
+#[derive(Copy, Clone)]
+struct ParkingLot {
+    lot: ParkAndRideID,
+    site: BuildingID,
+    capacity: usize,
+    parked: usize,
+}
+
+/// A park-and-ride trip: driving to the lot first, then continuing by transit.
+/// The trip listener only ever hears about the first leg's trip
+#[derive(Copy, Clone)]
+struct Itinerary {
+    first_leg: TripID,
+    current_leg: TripID,
+    lot_site: BuildingID,
+    destination: RoughLocationID,
+    listener: Option<TripListenerID>,
+}
+
+/// Makes the mode choice for all trips: keeps track of how congested the network is
+/// and samples a mode for each trip, then starts the trip with it
+#[derive(Compact, Clone)]
+pub struct ModeChoice {
+    id: ModeChoiceID,
+    simulation: SimulationID,
+    settings: ModeChoiceSettings,
+    collecting: bool,
+    collected_speeds: (f32, f32),
+    /// Average speed of cars relative to how fast they'd like to go
+    speed_ratio: f32,
+    chosen: [usize; 5],
+    lots: CVec<ParkingLot>,
+    /// When cars parked at park-and-ride lots leave again
+    parked_until: CVec<(Timestamp, ParkAndRideID)>,
+    itineraries: CVec<Itinerary>,
+}
+
+impl ModeChoice {
+    pub fn spawn(
+        id: ModeChoiceID,
+        simulation: SimulationID,
+        user_interface: UserInterfaceID,
+        world: &mut World,
+    ) -> ModeChoice {
+        user_interface.add_2d(id.into(), world);
+        simulation.wake_up_in(SAMPLE_INTERVAL, id.into(), world);
+
+        ModeChoice {
+            id,
+            simulation,
+            settings: ::ENV.load_settings(SETTINGS_CATEGORY),
+            collecting: false,
+            collected_speeds: (0.0, 0.0),
+            speed_ratio: 1.0,
+            chosen: [0; 5],
+            lots: CVec::new(),
+            parked_until: CVec::new(),
+            itineraries: CVec::new(),
+        }
+    }
+
+    pub fn add_park_and_ride(
+        &mut self,
+        lot: ParkAndRideID,
+        site: BuildingID,
+        capacity: usize,
+        _: &mut World,
+    ) {
+        self.lots.push(ParkingLot { lot, site, capacity, parked: 0 });
+    }
+
+    pub fn remove_park_and_ride(&mut self, lot: ParkAndRideID, _: &mut World) {
+        self.lots.retain(|parking_lot| parking_lot.lot != lot);
+        self.parked_until.retain(|&(_, parked_at)| parked_at != lot);
+    }
+
+    /// Only lots with free spaces are worth considering, and only if there is transit
+    pub fn park_and_ride_candidates(&mut self, chooser: ModeChooserID, world: &mut World) {
+        let candidates = if self.settings.transit_headway_minutes > 0.0 {
+            self.lots
+                .iter()
+                .filter(|parking_lot| parking_lot.parked < parking_lot.capacity)
+                .map(|parking_lot| (parking_lot.lot, parking_lot.site))
+                .collect()
+        } else {
+            CVec::new()
+        };
+        chooser.consider_park_and_ride(candidates, world);
+    }
+
+    fn has_free_parking(&self, lot: ParkAndRideID) -> bool {
+        self.lots.iter().any(|parking_lot| {
+            parking_lot.lot == lot && parking_lot.parked < parking_lot.capacity
+        })
+    }
+
+    fn start_park_and_ride(
+        &mut self,
+        lot: ParkAndRideID,
+        source: RoughLocationID,
+        destination: RoughLocationID,
+        listener: Option<TripListenerID>,
+        tick: Timestamp,
+        world: &mut World,
+    ) {
+        let maybe_site = self.lots
+            .iter_mut()
+            .find(|parking_lot| parking_lot.lot == lot)
+            .map(|parking_lot| {
+                parking_lot.parked += 1;
+                lot.parked_cars_changed(parking_lot.parked, world);
+                parking_lot.site
+            });
+
+        if let Some(site) = maybe_site {
+            self.parked_until.push((
+                tick + Ticks(PARKED_MINUTES * TICKS_PER_SIM_MINUTE),
+                lot,
+            ));
+            let first_leg = TripID::spawn(
+                source,
+                site.into(),
+                Some(self.id.into()),
+                VehicleClass::Car,
+                tick,
+                world,
+            );
+            self.itineraries.push(Itinerary {
+                first_leg,
+                current_leg: first_leg,
+                lot_site: site,
+                destination,
+                listener,
+            });
+        }
+    }
+
+    pub fn add_speeds(&mut self, velocities: f32, max_velocities: f32, _: &mut World) {
+        if self.collecting {
+            self.collected_speeds.0 += velocities;
+            self.collected_speeds.1 += max_velocities;
+        }
+    }
+
+    pub fn choose(
+        &mut self,
+        estimate: TripEstimate,
+        locations: Option<(Location, Location)>,
+        source: RoughLocationID,
+        destination: RoughLocationID,
+        listener: Option<TripListenerID>,
+        tick: Timestamp,
+        world: &mut World,
+    ) {
+        let park_and_ride = estimate.park_and_ride.and_then(|(lot, to_lot, from_lot)| {
+            if self.has_free_parking(lot) {
+                Some((to_lot, from_lot))
+            } else {
+                None
+            }
+        });
+        let options = mode_options(&self.settings, self.speed_ratio, &estimate, park_and_ride);
+        let probabilities = self.settings.probabilities(&options);
+
+        let mut dice = ::core::random::next_f32();
+        let maybe_chosen = options.iter().zip(probabilities).find(|&(_, probability)| {
+            dice -= probability;
+            dice <= 0.0
+        });
+        // rounding might leave a tiny bit of the dice
+        let maybe_chosen = maybe_chosen.map(|(option, _)| *option).or_else(
+            || options.last().cloned(),
+        );
+
+        if let Some(chosen) = maybe_chosen {
+            self.chosen[chosen.mode.idx()] += 1;
+
+            let carpooling = ::core::random::next_f32() < self.settings.carpool_willingness;
+
+            match chosen.mode {
+                TravelMode::Drive => {
+                    match locations {
+                        Some((source_location, destination_location)) if carpooling => {
+                            CarpoolMatcherID::local_first(world).request_ride(
+                                RideRequest {
+                                    source_location,
+                                    destination_location,
+                                    source,
+                                    destination,
+                                    listener,
+                                },
+                                world,
+                            );
+                        }
+                        _ => {
+                            TripID::spawn(
+                                source,
+                                destination,
+                                listener,
+                                VehicleClass::Car,
+                                tick,
+                                world,
+                            );
+                        }
+                    }
+                }
+                TravelMode::Transit => {
+                    TripID::spawn(source, destination, listener, VehicleClass::Bus, tick, world);
+                }
+                TravelMode::Walk | TravelMode::Bike => {
+                    let ticks = (chosen.minutes * TICKS_PER_SIM_MINUTE as f32).ceil() as usize;
+                    TripID::spawn_off_road(source, destination, listener, Ticks(ticks), world);
+                }
+                TravelMode::ParkAndRide => {
+                    if let Some((lot, _, _)) = estimate.park_and_ride {
+                        self.start_park_and_ride(lot, source, destination, listener, tick, world);
+                    }
+                }
+            }
+        } else {
+            // nothing is known about this trip, so driving is as good a guess as any
+            // (this will most probably fail, as it did before there was mode choice)
+            self.chosen[TravelMode::Drive.idx()] += 1;
+            TripID::spawn(source, destination, listener, VehicleClass::Car, tick, world);
+        }
+    }
+}
+
+impl Lane {
+    pub fn report_speeds(&mut self, mode_choice: ModeChoiceID, world: &mut World) {
+        if !self.microtraffic.cars.is_empty() {
+            mode_choice.add_speeds(
+                self.microtraffic.cars.iter().map(|car| car.velocity).sum(),
+                self.microtraffic.cars.iter().map(|car| car.max_velocity).sum(),
+                world,
+            );
+        }
+    }
+}
+
+impl Sleeper for ModeChoice {
+    fn wake(&mut self, current_tick: Timestamp, world: &mut World) {
+        let (leaving, staying): (Vec<_>, Vec<_>) = self.parked_until
+            .iter()
+            .cloned()
+            .partition(|&(until, _)| until <= current_tick);
+        self.parked_until = staying.into();
+        for (_, lot) in leaving {
+            if let Some(parking_lot) = self.lots.iter_mut().find(|parking_lot| {
+                parking_lot.lot == lot
+            })
+            {
+                parking_lot.parked -= 1;
+                lot.parked_cars_changed(parking_lot.parked, world);
+            }
+        }
+
+        if self.collecting {
+            self.collecting = false;
+            let (velocities, max_velocities) = self.collected_speeds;
+            self.speed_ratio = if max_velocities > 0.0 {
+                (velocities / max_velocities).max(MIN_SPEED_RATIO).min(1.0)
+            } else {
+                1.0
+            };
+            self.simulation.wake_up_in(SAMPLE_INTERVAL, self.id.into(), world);
+        } else {
+            self.collecting = true;
+            self.collected_speeds = (0.0, 0.0);
+            LaneID::global_broadcast(world).report_speeds(self.id, world);
+            self.simulation.wake_up_in(COLLECTION_TICKS, self.id.into(), world);
+        }
+    }
+}
+
+impl TripListener for ModeChoice {
+    fn trip_created(&mut self, trip: TripID, world: &mut World) {
+        if let Some(itinerary) = self.itineraries.iter().find(|itinerary| {
+            itinerary.first_leg == trip
+        })
+        {
+            if let Some(listener) = itinerary.listener {
+                listener.trip_created(trip, world);
+            }
+        }
+    }
+
+    fn trip_result(
+        &mut self,
+        trip: TripID,
+        location: RoughLocationID,
+        failed: bool,
+        tick: Timestamp,
+        world: &mut World,
+    ) {
+        if let Some(idx) = self.itineraries.iter().position(|itinerary| {
+            itinerary.current_leg == trip
+        })
+        {
+            let itinerary = self.itineraries[idx];
+            if failed || trip != itinerary.first_leg {
+                self.itineraries.remove(idx);
+                if let Some(listener) = itinerary.listener {
+                    listener.trip_result(itinerary.first_leg, location, failed, tick, world);
+                }
+            } else {
+                // parked the car, now continue by transit
+                self.itineraries[idx].current_leg = TripID::spawn(
+                    itinerary.lot_site.into(),
+                    itinerary.destination,
+                    Some(self.id.into()),
+                    VehicleClass::Bus,
+                    tick,
+                    world,
+                );
+            }
+        }
+    }
+}
+
+impl Interactable2d for ModeChoice {
+    fn draw_ui_2d(
+        &mut self,
+        imgui_ui: &External<::imgui::Ui<'static>>,
+        return_to: UserInterfaceID,
+        world: &mut World,
+    ) {
+        let ui = imgui_ui.steal();
+        let mut settings = self.settings;
+        let mut settings_changed = false;
+
+        ui.window(im_str!("Mode Choice"))
+            .size((300.0, 400.0), ImGuiSetCond_FirstUseEver)
+            .collapsible(true)
+            .build(|| {
+                let total: usize = self.chosen.iter().sum();
+                for &mode in &ALL_MODES {
+                    let n = self.chosen[mode.idx()];
+                    ui.text(im_str!(
+                        "{}: {} trips ({:.0}%)",
+                        mode.name(),
+                        n,
+                        if total > 0 {
+                            100.0 * n as f32 / total as f32
+                        } else {
+                            0.0
+                        }
+                    ));
+                }
+                let person_trips = ::core::metrics::PERSON_TRIPS_CREATED.load(Ordering::Relaxed);
+                let vehicle_trips = ::core::metrics::VEHICLE_TRIPS_CREATED.load(Ordering::Relaxed);
+                ui.text(im_str!(
+                    "{} person trips, {} vehicle trips",
+                    person_trips,
+                    vehicle_trips
+                ));
+                ui.text(im_str!(
+                    "Traffic flows at {:.0}% of desired speed",
+                    100.0 * self.speed_ratio
+                ));
+                for parking_lot in self.lots.iter() {
+                    ui.text(im_str!(
+                        "Park & Ride {:?}: {} of {} spaces taken",
+                        parking_lot.lot._raw_id,
+                        parking_lot.parked,
+                        parking_lot.capacity
+                    ));
+                }
+                ui.separator();
+
+                if ui.collapsing_header(im_str!("Coefficients")).build() {
+                    settings_changed |= ui.slider_float(
+                        im_str!("per minute"),
+                        &mut settings.time_coefficient,
+                        -1.0,
+                        0.0,
+                    ).build();
+                    settings_changed |= ui.slider_float(
+                        im_str!("per money unit"),
+                        &mut settings.cost_coefficient,
+                        -1.0,
+                        0.0,
+                    ).build();
+                    for &mode in &ALL_MODES {
+                        settings_changed |= ui.slider_float(
+                            im_str!("{} constant", mode.name()),
+                            &mut settings.constants[mode.idx()],
+                            -3.0,
+                            3.0,
+                        ).build();
+                    }
+                }
+
+                if ui.collapsing_header(im_str!("Costs")).build() {
+                    settings_changed |= ui.slider_float(
+                        im_str!("Driving per km"),
+                        &mut settings.driving_cost_per_km,
+                        0.0,
+                        2.0,
+                    ).build();
+                    settings_changed |= ui.slider_float(
+                        im_str!("Parking"),
+                        &mut settings.parking_cost,
+                        0.0,
+                        10.0,
+                    ).build();
+                    settings_changed |= ui.slider_float(
+                        im_str!("Transit fare"),
+                        &mut settings.transit_fare,
+                        0.0,
+                        10.0,
+                    ).build();
+                    settings_changed |= ui.slider_float(
+                        im_str!("Transit headway (min)"),
+                        &mut settings.transit_headway_minutes,
+                        0.0,
+                        60.0,
+                    ).build();
+                }
+
+                if ui.collapsing_header(im_str!("Carpooling")).build() {
+                    settings_changed |= ui.slider_float(
+                        im_str!("Willing drivers"),
+                        &mut settings.carpool_willingness,
+                        0.0,
+                        1.0,
+                    ).build();
+                }
+            });
+
+        if settings_changed {
+            self.settings = settings;
+            ::ENV.write_settings(SETTINGS_CATEGORY, &settings);
+        }
+
+        return_to.ui_drawn(ui, world);
+    }
+}
+
+/// Finds out what a single trip would take with each mode and then
+/// asks `ModeChoice` to choose one of them and start the trip
+#[derive(Compact, Clone)]
+pub struct ModeChooser {
+    id: ModeChooserID,
+    rough_source: RoughLocationID,
+    rough_destination: RoughLocationID,
+    source: Option<Location>,
+    destination: Option<Location>,
+    listener: Option<TripListenerID>,
+    tick: Timestamp,
+    n_resolved: u8,
+    /// Estimates we're still waiting for
+    n_pending: usize,
+    estimate: TripEstimate,
+}
+
+impl ModeChooser {
+    pub fn spawn(
+        id: ModeChooserID,
+        rough_source: RoughLocationID,
+        rough_destination: RoughLocationID,
+        listener: Option<TripListenerID>,
+        tick: Timestamp,
+        world: &mut World,
+    ) -> ModeChooser {
+        rough_source.resolve_as_location(id.into(), rough_source, tick, world);
+        rough_destination.resolve_as_location(id.into(), rough_destination, tick, world);
+
+        ModeChooser {
+            id,
+            rough_source,
+            rough_destination,
+            source: None,
+            destination: None,
+            listener,
+            tick,
+            n_resolved: 0,
+            n_pending: 0,
+            estimate: TripEstimate {
+                driving_distance: None,
+                walking_minutes: None,
+                cycling_minutes: None,
+                park_and_ride: None,
+            },
+        }
+    }
+
+    fn estimated(&mut self, world: &mut World) {
+        self.n_pending -= 1;
+        if self.n_pending == 0 {
+            let locations = match (self.source, self.destination) {
+                (Some(source), Some(destination)) => Some((source, destination)),
+                _ => None,
+            };
+            ModeChoiceID::local_first(world).choose(
+                self.estimate,
+                locations,
+                self.rough_source,
+                self.rough_destination,
+                self.listener,
+                self.tick,
+                world,
+            );
+            self.id.done(world);
+        }
+    }
+
+    pub fn consider_park_and_ride(
+        &mut self,
+        candidates: &CVec<(ParkAndRideID, BuildingID)>,
+        world: &mut World,
+    ) {
+        if let (Some(source), Some(destination)) = (self.source, self.destination) {
+            for &(lot, site) in candidates.iter() {
+                ParkAndRideEstimatorID::spawn(
+                    lot,
+                    site,
+                    source,
+                    destination,
+                    self.id,
+                    self.tick,
+                    world,
+                );
+            }
+            self.n_pending += candidates.len();
+        }
+        self.estimated(world);
+    }
+
+    pub fn on_park_and_ride_estimate(
+        &mut self,
+        lot: ParkAndRideID,
+        distances: Option<(Meters, Meters)>,
+        world: &mut World,
+    ) {
+        if let Some((to_lot, from_lot)) = distances {
+            let duration = |to_lot: Meters, from_lot: Meters| {
+                to_lot / FREE_FLOW_SPEED + from_lot / TRANSIT_SPEED
+            };
+            let better = self.estimate.park_and_ride.map_or(true, |(_, best_to, best_from)| {
+                duration(to_lot, from_lot) < duration(best_to, best_from)
+            });
+            if better {
+                self.estimate.park_and_ride = Some((lot, to_lot, from_lot));
+            }
+        }
+        self.estimated(world);
+    }
+
+    pub fn done(&mut self, _: &mut World) -> Fate {
+        Fate::Die
+    }
+}
+
+impl LocationRequester for ModeChooser {
+    fn location_resolved(
+        &mut self,
+        rough_location: RoughLocationID,
+        location: Option<Location>,
+        _tick: Timestamp,
+        world: &mut World,
+    ) {
+        // source and destination can be the same
+        if self.rough_source == rough_location && self.source.is_none() {
+            self.source = location;
+        } else if self.rough_destination == rough_location {
+            self.destination = location;
+        } else {
+            panic!("Should have this rough source/destination")
+        }
+
+        self.n_resolved += 1;
+
+        if self.n_resolved == 2 {
+            if let (Some(source), Some(destination)) = (self.source, self.destination) {
+                self.n_pending = 3;
+                source.node.get_distance_to(destination, self.id.into(), world);
+                ActiveModeGraphID::local_first(world).estimate_active_minutes(
+                    source,
+                    destination,
+                    self.id.into(),
+                    world,
+                );
+                ModeChoiceID::local_first(world).park_and_ride_candidates(self.id, world);
+            } else {
+                // the trip will fail on its own, without knowing anything
+                self.n_pending = 1;
+                self.estimated(world);
+            }
+        }
+    }
+}
+
+impl DistanceRequester for ModeChooser {
+    fn on_distance(&mut self, maybe_distance: Option<Meters>, world: &mut World) {
+        self.estimate.driving_distance = maybe_distance;
+        self.estimated(world);
+    }
+}
+
+impl ActiveTravelRequester for ModeChooser {
+    fn on_active_travel_minutes(
+        &mut self,
+        walking: Option<f32>,
+        cycling: Option<f32>,
+        world: &mut World,
+    ) {
+        self.estimate.walking_minutes = walking;
+        self.estimate.cycling_minutes = cycling;
+        self.estimated(world);
+    }
+}
+
+/// Finds out how far it is from the trip source to a park-and-ride lot
+/// and from there to the trip destination, one after the other
+#[derive(Compact, Clone)]
+pub struct ParkAndRideEstimator {
+    id: ParkAndRideEstimatorID,
+    lot: ParkAndRideID,
+    source: Location,
+    destination: Location,
+    chooser: ModeChooserID,
+    lot_location: Option<Location>,
+    to_lot: Option<Meters>,
+}
+
+impl ParkAndRideEstimator {
+    pub fn spawn(
+        id: ParkAndRideEstimatorID,
+        lot: ParkAndRideID,
+        site: BuildingID,
+        source: Location,
+        destination: Location,
+        chooser: ModeChooserID,
+        tick: Timestamp,
+        world: &mut World,
+    ) -> ParkAndRideEstimator {
+        let site_location: RoughLocationID = site.into();
+        site_location.resolve_as_location(id.into(), site_location, tick, world);
+
+        ParkAndRideEstimator {
+            id,
+            lot,
+            source,
+            destination,
+            chooser,
+            lot_location: None,
+            to_lot: None,
+        }
+    }
+
+    fn report(&mut self, distances: Option<(Meters, Meters)>, world: &mut World) {
+        self.chooser.on_park_and_ride_estimate(self.lot, distances, world);
+        self.id.done(world);
+    }
+
+    pub fn done(&mut self, _: &mut World) -> Fate {
+        Fate::Die
+    }
+}
+
+impl LocationRequester for ParkAndRideEstimator {
+    fn location_resolved(
+        &mut self,
+        _rough_location: RoughLocationID,
+        location: Option<Location>,
+        _tick: Timestamp,
+        world: &mut World,
+    ) {
+        if let Some(lot_location) = location {
+            self.lot_location = Some(lot_location);
+            self.source.node.get_distance_to(lot_location, self.id.into(), world);
+        } else {
+            self.report(None, world);
+        }
+    }
+}
+
+impl DistanceRequester for ParkAndRideEstimator {
+    fn on_distance(&mut self, maybe_distance: Option<Meters>, world: &mut World) {
+        match (self.to_lot, maybe_distance, self.lot_location) {
+            (None, Some(to_lot), Some(lot_location)) => {
+                self.to_lot = Some(to_lot);
+                lot_location.node.get_distance_to(self.destination, self.id.into(), world);
+            }
+            (Some(to_lot), Some(from_lot), _) => self.report(Some((to_lot, from_lot)), world),
+            _ => self.report(None, world),
+        }
+    }
+}
+
+/// Someone who'd like to drive, but would rather share the car
+#[derive(Copy, Clone)]
+pub struct RideRequest {
+    pub source_location: Location,
+    pub destination_location: Location,
+    pub source: RoughLocationID,
+    pub destination: RoughLocationID,
+    pub listener: Option<TripListenerID>,
+}
+
+#[derive(Compact, Clone)]
+struct Carpool {
+    trip: TripID,
+    /// Everyone in the car, with where they're headed
+    riders: CVec<(Option<TripListenerID>, RoughLocationID)>,
+}
+
+/// Groups ride requests starting at the same lane and heading to the same
+/// neighbourhood into carpools, which are a single high occupancy car trip.
+/// Requests that find no match within the pooling window drive alone
+#[derive(Compact, Clone)]
+pub struct CarpoolMatcher {
+    id: CarpoolMatcherID,
+    simulation: SimulationID,
+    requests: CVec<RideRequest>,
+    carpools: CVec<Carpool>,
+    carpools_formed: usize,
+    pooled_riders: usize,
+}
+
+impl CarpoolMatcher {
+    pub fn spawn(
+        id: CarpoolMatcherID,
+        simulation: SimulationID,
+        _: &mut World,
+    ) -> CarpoolMatcher {
+        CarpoolMatcher {
+            id,
+            simulation,
+            requests: CVec::new(),
+            carpools: CVec::new(),
+            carpools_formed: 0,
+            pooled_riders: 0,
+        }
+    }
+
+    pub fn request_ride(&mut self, request: RideRequest, world: &mut World) {
+        if self.requests.is_empty() {
+            self.simulation.wake_up_in(POOLING_WINDOW, self.id.into(), world);
+        }
+        self.requests.push(request);
+    }
+}
+
+impl Sleeper for CarpoolMatcher {
+    fn wake(&mut self, current_tick: Timestamp, world: &mut World) {
+        let mut groups = FnvHashMap::<(NodeID, NodeID), Vec<RideRequest>>::default();
+        for request in self.requests.drain() {
+            groups
+                .entry((request.source_location.node, request.destination_location.landmark))
+                .or_insert_with(Vec::new)
+                .push(request);
+        }
+
+        for (_, group) in groups {
+            for riders in group.chunks(CAR_CAPACITY) {
+                let first = riders[0];
+                if riders.len() == 1 {
+                    TripID::spawn(
+                        first.source,
+                        first.destination,
+                        first.listener,
+                        VehicleClass::Car,
+                        current_tick,
+                        world,
+                    );
+                } else {
+                    // everyone gets picked up at the same lane, the car then heads
+                    // for the first rider's destination, which is close to all others
+                    let trip = TripID::spawn_carpool(
+                        first.source,
+                        first.destination,
+                        Some(self.id.into()),
+                        riders.len() as u8,
+                        current_tick,
+                        world,
+                    );
+                    self.carpools.push(Carpool {
+                        trip,
+                        riders: riders
+                            .iter()
+                            .map(|rider| (rider.listener, rider.destination))
+                            .collect(),
+                    });
+                    self.carpools_formed += 1;
+                    self.pooled_riders += riders.len();
+                }
+            }
+        }
+    }
+}
+
+impl TripListener for CarpoolMatcher {
+    fn trip_created(&mut self, trip: TripID, world: &mut World) {
+        if let Some(carpool) = self.carpools.iter().find(|carpool| carpool.trip == trip) {
+            for &(maybe_listener, _) in carpool.riders.iter() {
+                if let Some(listener) = maybe_listener {
+                    listener.trip_created(trip, world);
+                }
+            }
+        }
+    }
+
+    fn trip_result(
+        &mut self,
+        trip: TripID,
+        location: RoughLocationID,
+        failed: bool,
+        tick: Timestamp,
+        world: &mut World,
+    ) {
+        if let Some(idx) = self.carpools.iter().position(|carpool| carpool.trip == trip) {
+            let carpool = self.carpools.remove(idx);
+            for &(maybe_listener, destination) in carpool.riders.iter() {
+                if let Some(listener) = maybe_listener {
+                    let arrived_at = if failed { location } else { destination };
+                    listener.trip_result(trip, arrived_at, failed, tick, world);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ModeChoiceSettings, ModeOption, TravelMode, TripEstimate, mode_options};
+    use core::units::Meters;
+
+    #[test]
+    fn faster_modes_are_more_likely_and_probabilities_add_up() {
+        let settings = ModeChoiceSettings {
+            constants: [0.0; 5],
+            ..ModeChoiceSettings::default()
+        };
+        let options = [
+            ModeOption { mode: TravelMode::Drive, minutes: 10.0, cost: 0.0 },
+            ModeOption { mode: TravelMode::Walk, minutes: 30.0, cost: 0.0 },
+            ModeOption { mode: TravelMode::Bike, minutes: 10.0, cost: 0.0 },
+        ];
+        let probabilities = settings.probabilities(&options);
+
+        assert!(probabilities[0] > probabilities[1]);
+        assert!((probabilities[0] - probabilities[2]).abs() < 1e-6);
+        assert!((probabilities.iter().sum::<f32>() - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn park_and_ride_beats_driving_only_under_congestion() {
+        let settings = ModeChoiceSettings {
+            transit_headway_minutes: 10.0,
+            ..ModeChoiceSettings::default()
+        };
+        let estimate = TripEstimate {
+            driving_distance: Some(Meters(20_000.0)),
+            walking_minutes: None,
+            cycling_minutes: None,
+            park_and_ride: None,
+        };
+        let lot_distances = Some((Meters(2_000.0), Meters(18_000.0)));
+        let minutes = |speed_ratio, mode| {
+            mode_options(&settings, speed_ratio, &estimate, lot_distances)
+                .into_iter()
+                .find(|option| option.mode == mode)
+                .map(|option| option.minutes)
+                .unwrap()
+        };
+
+        assert!(minutes(1.0, TravelMode::Drive) < minutes(1.0, TravelMode::ParkAndRide));
+        assert!(minutes(0.2, TravelMode::Drive) > minutes(0.2, TravelMode::ParkAndRide));
+
+        let full = mode_options(&settings, 0.2, &estimate, None);
+        assert!(full.iter().all(|option| option.mode != TravelMode::ParkAndRide));
+    }
+}
+
+pub fn setup(system: &mut ActorSystem, user_interface: UserInterfaceID, simulation: SimulationID) {
+    system.register::<ModeChoice>();
+    system.register::<ModeChooser>();
+    system.register::<ParkAndRideEstimator>();
+    system.register::<CarpoolMatcher>();
+    system.make_restorable::<ModeChoice>();
+    system.make_restorable::<ModeChooser>();
+    system.make_restorable::<ParkAndRideEstimator>();
+    system.make_restorable::<CarpoolMatcher>();
+    auto_setup(system);
+
+    ModeChoiceID::spawn(simulation, user_interface, &mut system.world());
+    CarpoolMatcherID::spawn(simulation, &mut system.world());
+}
+
+mod kay_auto;
+pub use self::kay_auto::*;
@@ -0,0 +1,168 @@
+//! Day-to-day route learning. Instead of every car taking the currently shortest
+//! route, most drivers stick to the route they took yesterday, and only some of them
+//! (plus everybody whose last trip took much longer than expected) look for a better one.
+//!
+//! Each lane remembers which way it sent cars to each destination at the start of
+//! the day: its habitual routes, which habitual cars keep following all day. Lanes also
+//! measure how much slower than free flow cars drive on them, and once a day add that
+//! delay to the cost they advertise, so the current routes avoid congestion. Since only
+//! a fraction of drivers switches to them each day, routes don't all flip back and
+//! forth together when congestion moves, but settle down over several days.
+use compact::CHashMap;
+use core::simulation::Timestamp;
+use transport::lane::Lane;
+use transport::restrictions::VehicleClass;
+use super::Location;
+
+/// Share of the drivers that look for the currently best route on any trip
+pub const REOPTIMIZING_SHARE: f32 = 0.1;
+/// Lanes never count as more than this many times longer because of congestion
+const MAX_TRAVEL_TIME_FACTOR: f32 = 5.0;
+/// Weight of the newest day in a lane's congestion cost
+const LEARNING_RATE: f32 = 0.5;
+/// Congestion cost changes smaller than this share of the lane length aren't advertised,
+/// so routes aren't recomputed everywhere for tiny changes
+const MIN_ADVERTISED_CHANGE: f32 = 0.1;
+
+#[derive(Compact, Clone, Default)]
+pub struct RouteLearningInfo {
+    /// The outgoing interaction used for each destination at the start of the day
+    pub habitual_routes: CHashMap<Location, u8>,
+    /// Extra length (in meters) the lane counts as, for the time lost in congestion
+    pub congestion_cost: f32,
+    /// The congestion cost the lane last told its predecessors about
+    advertised_congestion_cost: f32,
+    delay_seconds: f32,
+    car_seconds: f32,
+    day: usize,
+}
+
+/// Whether a trip with `vehicle` follows habitual routes, unless told to re-optimize.
+/// Only cars do, other vehicles have fixed or restricted routes anyway
+pub fn starts_habitual(vehicle: VehicleClass) -> bool {
+    match vehicle {
+        VehicleClass::Car | VehicleClass::HighOccupancyCar => {
+            ::core::random::next_f32() >= REOPTIMIZING_SHARE
+        }
+        _ => false,
+    }
+}
+
+/// The new congestion cost of a lane of `length`, given yesterday's cost and the delay
+/// observed since: `delay_seconds` lost by cars that spent `car_seconds` on it in total
+pub fn learned_congestion_cost(
+    length: f32,
+    previous_cost: f32,
+    delay_seconds: f32,
+    car_seconds: f32,
+) -> f32 {
+    let observed_cost = if car_seconds > 0.0 {
+        let slowdown = (delay_seconds / car_seconds).max(0.0).min(1.0);
+        let travel_time_factor = (1.0 / (1.0 - slowdown)).min(MAX_TRAVEL_TIME_FACTOR);
+        length * (travel_time_factor - 1.0)
+    } else {
+        // nobody drove here, so nothing to learn but that it got emptier
+        0.0
+    };
+    previous_cost + LEARNING_RATE * (observed_cost - previous_cost)
+}
+
+impl Lane {
+    /// Counts how much slower than they could the cars on the lane drove for `seconds`
+    pub fn observe_route_delays(&mut self, seconds: f32) {
+        for car in self.microtraffic.cars.iter() {
+            let free_flow_velocity = car.max_velocity.min(
+                self.road_class.speed_limit(car.vehicle),
+            );
+            if free_flow_velocity > 0.0 {
+                let slowdown = 1.0 - (car.velocity / free_flow_velocity).min(1.0);
+                self.route_learning.delay_seconds += slowdown * seconds;
+                self.route_learning.car_seconds += seconds;
+            }
+        }
+    }
+
+    /// Remembers the current routes as habitual ones and learns the congestion cost
+    /// from the day before, once the day of `current_tick` has started
+    pub fn start_route_day_if_due(&mut self, current_tick: Timestamp) {
+        let day = current_tick.day();
+        if day == self.route_learning.day {
+            return;
+        }
+        self.route_learning.day = day;
+
+        self.route_learning.habitual_routes = self.pathfinding
+            .routes
+            .pairs()
+            .map(|(&destination, routing_info)| (destination, routing_info.outgoing_idx))
+            .collect();
+
+        let length = self.construction.length;
+        self.route_learning.congestion_cost = learned_congestion_cost(
+            length,
+            self.route_learning.congestion_cost,
+            self.route_learning.delay_seconds,
+            self.route_learning.car_seconds,
+        );
+        self.route_learning.delay_seconds = 0.0;
+        self.route_learning.car_seconds = 0.0;
+
+        let change = (self.route_learning.congestion_cost -
+                          self.route_learning.advertised_congestion_cost)
+            .abs();
+        if change > MIN_ADVERTISED_CHANGE * length {
+            self.route_learning.advertised_congestion_cost = self.route_learning.congestion_cost;
+            self.pathfinding.routes_changed = true;
+        }
+    }
+
+    /// The cost of driving along the lane that it advertises to its predecessors
+    pub fn routing_cost(&self) -> f32 {
+        self.construction.length + self.route_learning.advertised_congestion_cost
+    }
+
+    /// The outgoing interaction a habitual car to `destination` takes,
+    /// if there was a route to it at the start of the day that still exists
+    pub fn habitual_next_hop(&self, destination: Location) -> Option<usize> {
+        self.route_learning
+            .habitual_routes
+            .get(destination)
+            .or_else(|| {
+                self.route_learning.habitual_routes.get(
+                    destination.landmark_destination(),
+                )
+            })
+            .and_then(|&outgoing_idx| if (outgoing_idx as usize) <
+                self.connectivity.interactions.len()
+            {
+                Some(outgoing_idx as usize)
+            } else {
+                None
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::learned_congestion_cost;
+
+    #[test]
+    fn free_flowing_lanes_cost_nothing_extra() {
+        assert_eq!(learned_congestion_cost(100.0, 0.0, 0.0, 50.0), 0.0);
+    }
+
+    #[test]
+    fn congestion_is_learned_gradually() {
+        // cars drove at half their free flow speed: the lane takes twice as long
+        let first_day = learned_congestion_cost(100.0, 0.0, 25.0, 50.0);
+        assert_eq!(first_day, 50.0);
+        let second_day = learned_congestion_cost(100.0, first_day, 25.0, 50.0);
+        assert_eq!(second_day, 75.0);
+    }
+
+    #[test]
+    fn standstill_is_capped() {
+        let cost = learned_congestion_cost(100.0, 400.0, 50.0, 50.0);
+        assert_eq!(cost, 400.0);
+    }
+}
@@ -0,0 +1,189 @@
+//! Planning tours that start at a depot, pass by several stops in turn and come back,
+//! as needed by delivery vans, instead of a single trip from one place to another.
+//!
+//! The order of the stops is chosen greedily: always the nearest stop not served yet.
+use fnv::FnvHashMap;
+use ordered_float::OrderedFloat;
+use std::collections::BinaryHeap;
+use std::cmp::Ordering;
+use std::hash::Hash;
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+struct Candidate<E> {
+    cost: OrderedFloat<f32>,
+    edge: E,
+    came_from: Option<E>,
+}
+
+// reversed, so the BinaryHeap pops the cheapest candidate first
+impl<E: Eq> Ord for Candidate<E> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.cost.cmp(&self.cost)
+    }
+}
+
+impl<E: Eq> PartialOrd for Candidate<E> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// For every edge reachable from `start`: the cost to enter it and the edge before it
+fn shortest_routes_from<E, F>(
+    start: E,
+    successors: &FnvHashMap<E, Vec<E>>,
+    cost: &F,
+) -> FnvHashMap<E, (f32, Option<E>)>
+where
+    E: Copy + Eq + Hash,
+    F: Fn(E) -> f32,
+{
+    let mut reached = FnvHashMap::default();
+    let mut queue = BinaryHeap::new();
+    queue.push(Candidate { cost: OrderedFloat(0.0), edge: start, came_from: None });
+
+    while let Some(Candidate { cost: cost_so_far, edge, came_from }) = queue.pop() {
+        if reached.contains_key(&edge) {
+            continue;
+        }
+        reached.insert(edge, (*cost_so_far, came_from));
+
+        let cost_after = *cost_so_far + cost(edge);
+        for &next in successors.get(&edge).into_iter().flat_map(|nexts| nexts.iter()) {
+            if successors.contains_key(&next) && !reached.contains_key(&next) {
+                queue.push(Candidate {
+                    cost: OrderedFloat(cost_after),
+                    edge: next,
+                    came_from: Some(edge),
+                });
+            }
+        }
+    }
+
+    reached
+}
+
+/// The edges after the start of `reached` up to and including `to`
+fn route_to<E: Copy + Eq + Hash>(to: E, reached: &FnvHashMap<E, (f32, Option<E>)>) -> Vec<E> {
+    let mut route = vec![to];
+    let mut backtrack = to;
+    while let Some(&(_, Some(before))) = reached.get(&backtrack) {
+        if reached[&before].1.is_none() {
+            // `before` is the start itself
+            break;
+        }
+        route.push(before);
+        backtrack = before;
+    }
+    route.reverse();
+    route
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tour<E> {
+    /// All edges driven on, starting and ending with the depot edge
+    pub route: Vec<E>,
+    /// For each served stop, in the order they are served: its index in `route`
+    pub stop_indices: Vec<usize>,
+    /// Stops that can't be reached from the depot (or from which it can't be reached)
+    pub unreachable: Vec<E>,
+}
+
+/// Plans a tour from `depot` past all of `stops` and back to `depot`,
+/// where driving along an edge costs `cost(edge)`
+pub fn plan_tour<E, F>(
+    depot: E,
+    stops: &[E],
+    successors: &FnvHashMap<E, Vec<E>>,
+    cost: F,
+) -> Tour<E>
+where
+    E: Copy + Eq + Hash,
+    F: Fn(E) -> f32,
+{
+    let mut route = vec![depot];
+    let mut stop_indices = Vec::new();
+    let mut remaining = stops.to_vec();
+    let mut current = depot;
+
+    // stops from which the depot can't be reached would strand the van there
+    let mut unreachable = Vec::new();
+    remaining.retain(|&stop| {
+        let can_return = shortest_routes_from(stop, successors, &cost).contains_key(&depot);
+        if !can_return {
+            unreachable.push(stop);
+        }
+        can_return
+    });
+
+    while !remaining.is_empty() {
+        let reached = shortest_routes_from(current, successors, &cost);
+        let maybe_nearest = remaining
+            .iter()
+            .enumerate()
+            .filter_map(|(i, stop)| reached.get(stop).map(|&(cost, _)| (i, cost)))
+            .min_by_key(|&(_, cost)| OrderedFloat(cost))
+            .map(|(i, _)| i);
+
+        if let Some(nearest_idx) = maybe_nearest {
+            let stop = remaining.remove(nearest_idx);
+            if stop != current {
+                route.extend(route_to(stop, &reached));
+            }
+            stop_indices.push(route.len() - 1);
+            current = stop;
+        } else {
+            unreachable.extend(remaining.drain(..));
+        }
+    }
+
+    if current != depot {
+        let reached = shortest_routes_from(current, successors, &cost);
+        route.extend(route_to(depot, &reached));
+    }
+
+    Tour { route, stop_indices, unreachable }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::plan_tour;
+    use fnv::FnvHashMap;
+
+    fn network(edges: &[(u32, &[u32])]) -> FnvHashMap<u32, Vec<u32>> {
+        edges.iter().map(|&(edge, nexts)| (edge, nexts.to_vec())).collect()
+    }
+
+    #[test]
+    fn serves_nearest_stops_first_and_returns() {
+        // a loop 0 -> 1 -> 2 -> 3 -> 0
+        let successors = network(&[(0, &[1]), (1, &[2]), (2, &[3]), (3, &[0])]);
+        let tour = plan_tour(0, &[2, 1], &successors, |_| 1.0);
+        assert_eq!(tour.route, vec![0, 1, 2, 3, 0]);
+        assert_eq!(tour.stop_indices, vec![1, 2]);
+        assert!(tour.unreachable.is_empty());
+    }
+
+    #[test]
+    fn takes_the_cheaper_way_around() {
+        // 0 leads to 5 either directly over the expensive 1 or over 2 and 3
+        let successors = network(&[
+            (0, &[1, 2]),
+            (1, &[5]),
+            (2, &[3]),
+            (3, &[5]),
+            (5, &[0]),
+        ]);
+        let tour = plan_tour(0, &[5], &successors, |edge| if edge == 1 { 10.0 } else { 1.0 });
+        assert_eq!(tour.route, vec![0, 2, 3, 5, 0]);
+    }
+
+    #[test]
+    fn skips_stops_that_strand_the_van() {
+        // 2 is a dead end
+        let successors = network(&[(0, &[1, 2]), (1, &[0]), (2, &[])]);
+        let tour = plan_tour(0, &[1, 2], &successors, |_| 1.0);
+        assert_eq!(tour.route, vec![0, 1, 0]);
+        assert_eq!(tour.unreachable, vec![2]);
+    }
+}
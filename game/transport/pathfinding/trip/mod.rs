@@ -4,11 +4,28 @@ use ordered_float::OrderedFloat;
 use core::simulation::Timestamp;
 
 use transport::lane::LaneID;
+use transport::demand_forecast::DemandForecastID;
+use transport::analysis_zones::TrafficZonesID;
+use transport::event_log::{self, EventKind};
 use super::Location;
 use super::{RoughLocationID, LocationRequester, LocationRequesterID,
             MSG_LocationRequester_location_resolved};
 
 use itertools::Itertools;
+use descartes::{P2, FiniteCurve};
+use std::sync::atomic::Ordering;
+
+/// Why a driven trip had to be given up, reported when it fails
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CancelReason {
+    /// The lane the car was on or about to enter is being removed
+    LaneRemoved,
+    /// A lane ahead was disconnected and there is no other way to the destination
+    NoRouteLeft,
+    /// The car's position, velocity or acceleration stopped being finite,
+    /// usually because of bad lane geometry
+    BrokenCarState,
+}
 
 #[derive(Compact, Clone)]
 pub struct Trip {
@@ -18,6 +35,16 @@ pub struct Trip {
     source: Option<Location>,
     destination: Option<Location>,
     listener: Option<TripListenerID>,
+    vehicle: VehicleClass,
+    cancelled: Option<CancelReason>,
+    /// Walked or cycled, not driven on lanes
+    off_road: bool,
+    /// How many people share the vehicle
+    occupants: u8,
+    /// When the trip started, if it is driven on lanes
+    started: Option<Timestamp>,
+    /// Whether the car sticks to habitual routes, see `pathfinding::route_learning`
+    habitual: bool,
 }
 
 impl Trip {
@@ -26,9 +53,54 @@ impl Trip {
         rough_source: RoughLocationID,
         rough_destination: RoughLocationID,
         listener: Option<TripListenerID>,
+        vehicle: VehicleClass,
+        tick: Timestamp,
+        world: &mut World,
+    ) -> Self {
+        Trip::start(id, rough_source, rough_destination, listener, vehicle, 1, tick, world)
+    }
+
+    /// A car shared by several people going the same way,
+    /// which makes it a high occupancy vehicle
+    pub fn spawn_carpool(
+        id: TripID,
+        rough_source: RoughLocationID,
+        rough_destination: RoughLocationID,
+        listener: Option<TripListenerID>,
+        occupants: u8,
+        tick: Timestamp,
+        world: &mut World,
+    ) -> Self {
+        let vehicle = if occupants > 1 {
+            VehicleClass::HighOccupancyCar
+        } else {
+            VehicleClass::Car
+        };
+        Trip::start(
+            id,
+            rough_source,
+            rough_destination,
+            listener,
+            vehicle,
+            occupants,
+            tick,
+            world,
+        )
+    }
+
+    fn start(
+        id: TripID,
+        rough_source: RoughLocationID,
+        rough_destination: RoughLocationID,
+        listener: Option<TripListenerID>,
+        vehicle: VehicleClass,
+        occupants: u8,
         tick: Timestamp,
         world: &mut World,
     ) -> Self {
+        ::core::metrics::TRIPS_CREATED.fetch_add(1, Ordering::Relaxed);
+        ::core::metrics::VEHICLE_TRIPS_CREATED.fetch_add(1, Ordering::Relaxed);
+        ::core::metrics::PERSON_TRIPS_CREATED.fetch_add(occupants as usize, Ordering::Relaxed);
         rough_source.resolve_as_location(id.into(), rough_source, tick, world);
 
         if let Some(listener) = listener {
@@ -40,8 +112,70 @@ impl Trip {
             rough_source,
             rough_destination,
             listener,
+            vehicle,
             source: None,
             destination: None,
+            cancelled: None,
+            off_road: false,
+            occupants,
+            started: Some(tick),
+            habitual: super::route_learning::starts_habitual(vehicle),
+        }
+    }
+
+    /// A trip on foot or by bike. These aren't simulated,
+    /// the traveller just arrives after the estimated `duration`
+    pub fn spawn_off_road(
+        id: TripID,
+        rough_source: RoughLocationID,
+        rough_destination: RoughLocationID,
+        listener: Option<TripListenerID>,
+        duration: Ticks,
+        world: &mut World,
+    ) -> Self {
+        ::core::metrics::TRIPS_CREATED.fetch_add(1, Ordering::Relaxed);
+        ::core::metrics::PERSON_TRIPS_CREATED.fetch_add(1, Ordering::Relaxed);
+        SimulationID::local_first(world).wake_up_in(duration, id.into(), world);
+
+        if let Some(listener) = listener {
+            listener.trip_created(id, world);
+        }
+
+        Trip {
+            id: id,
+            rough_source,
+            rough_destination,
+            listener,
+            vehicle: VehicleClass::Car,
+            source: None,
+            destination: None,
+            cancelled: None,
+            off_road: true,
+            occupants: 1,
+            started: None,
+            habitual: false,
+        }
+    }
+
+    /// Makes the car look for the currently best route instead of the habitual one,
+    /// for travellers whose last trip took much longer than they expected
+    pub fn reoptimize_route(&mut self, _: &mut World) {
+        self.habitual = false;
+    }
+
+    /// Fails the trip in the next tick, for when the car's lane disappears.
+    /// The traveller ends up back where they started, since the lane is gone
+    pub fn cancel(&mut self, reason: CancelReason, world: &mut World) {
+        if self.cancelled.is_none() {
+            self.cancelled = Some(reason);
+            SimulationID::local_first(world).wake_up_in(Ticks(0), self.id.into(), world);
+        }
+    }
+
+    /// A cancelled trip can still end on the road before its wake up is due
+    fn forget_pending_cancellation(&self, world: &mut World) {
+        if self.cancelled.is_some() {
+            SimulationID::local_first(world).forget_wake_ups(self.id.into(), world);
         }
     }
 
@@ -52,21 +186,39 @@ impl Trip {
         world: &mut World,
     ) -> Fate {
         println!("Trip {:?} failed!", self.id);
+        ::core::metrics::TRIPS_FAILED.fetch_add(1, Ordering::Relaxed);
+        if self.started.is_some() {
+            let link = self.source.map(|source| source.node._raw_id.instance_id);
+            event_log::log(tick, EventKind::Stuck, self.id, link, self.vehicle);
+        }
 
         if let Some(listener) = self.listener {
             listener.trip_result(self.id, location, true, tick, world);
         }
 
+        self.forget_pending_cancellation(world);
         Fate::Die
     }
 
     pub fn succeed(&mut self, tick: Timestamp, world: &mut World) -> Fate {
         println!("Trip {:?} succeeded!", self.id);
+        ::core::metrics::TRIPS_SUCCEEDED.fetch_add(1, Ordering::Relaxed);
+        if let Some(started) = self.started {
+            let link = self.destination.map(|destination| destination.node._raw_id.instance_id);
+            event_log::log(tick, EventKind::VehicleLeavesTraffic, self.id, link, self.vehicle);
+            event_log::log(tick, EventKind::Arrival, self.id, link, self.vehicle);
+            ::core::metrics::ROAD_TRIPS_SUCCEEDED.fetch_add(1, Ordering::Relaxed);
+            ::core::metrics::ROAD_TRIP_TICKS.fetch_add(
+                tick.ticks() - started.ticks(),
+                Ordering::Relaxed,
+            );
+        }
 
         if let Some(listener) = self.listener {
             listener.trip_result(self.id, self.rough_destination, false, tick, world);
         }
 
+        self.forget_pending_cancellation(world);
         Fate::Die
     }
 }
@@ -100,19 +252,36 @@ impl LocationRequester for Trip {
             }
 
             if let (Some(source), Some(destination)) = (self.source, self.destination) {
-                // TODO: ugly: untyped ID shenanigans
-                let source_as_lane: LaneLikeID = LaneLikeID { _raw_id: source.node._raw_id };
+                DemandForecastID::local_first(world).add_trip(
+                    source.node,
+                    destination.node,
+                    tick,
+                    world,
+                );
+                TrafficZonesID::local_first(world).add_trip(
+                    source.node,
+                    destination.node,
+                    tick,
+                    world,
+                );
+                let link = Some(source.node._raw_id.instance_id);
+                event_log::log(tick, EventKind::Departure, self.id, link, self.vehicle);
+                event_log::log(tick, EventKind::VehicleEntersTraffic, self.id, link, self.vehicle);
+                let source_as_lane = LaneLikeID::from(source.node);
                 source_as_lane.add_car(
                     LaneCar {
                         trip: self.id,
                         as_obstacle: Obstacle {
                             position: OrderedFloat(-1.0),
                             velocity: 0.0,
-                            max_velocity: 15.0,
+                            max_velocity: vehicle_max_velocity(self.vehicle).0,
+                            length: vehicle_length(self.vehicle).0,
                         },
                         acceleration: 0.0,
                         destination: destination,
                         next_hop_interaction: 0,
+                        vehicle: self.vehicle,
+                        habitual: self.habitual,
                     },
                     None,
                     tick,
@@ -131,7 +300,48 @@ impl LocationRequester for Trip {
 
 use core::simulation::{SimulationID, Sleeper, SleeperID, MSG_Sleeper_wake};
 use core::simulation::Ticks;
-use super::super::microtraffic::{LaneLikeID, LaneCar, Obstacle};
+
+impl Sleeper for Trip {
+    fn wake(&mut self, current_tick: Timestamp, world: &mut World) {
+        if self.cancelled.is_some() {
+            self.id.fail_at(self.rough_source, current_tick, world);
+        } else if self.off_road {
+            self.id.succeed(current_tick, world);
+        }
+    }
+}
+use core::snapshot_diff::Diffable;
+
+impl Diffable for Trip {
+    fn changes_since(&self, before: &Trip) -> Vec<String> {
+        let mut changes = Vec::new();
+        if self.source != before.source {
+            changes.push("source changed".to_owned());
+        }
+        if self.destination != before.destination {
+            changes.push(match self.destination {
+                Some(destination) => {
+                    format!("now heads for node {}", destination.node._raw_id.instance_id)
+                }
+                None => "lost its destination".to_owned(),
+            });
+        }
+        if self.vehicle != before.vehicle {
+            changes.push(format!("switched to {:?}", self.vehicle));
+        }
+        if !self.habitual && before.habitual {
+            changes.push("looks for a better route".to_owned());
+        }
+        if let (Some(reason), None) = (self.cancelled, before.cancelled) {
+            changes.push(format!("was cancelled: {:?}", reason));
+        }
+        changes
+    }
+}
+
+use super::super::microtraffic::{LaneLikeID, LaneCar, Obstacle, vehicle_length,
+                                 vehicle_max_velocity};
+use super::super::restrictions::VehicleClass;
 
 pub trait TripListener {
     fn trip_created(&mut self, trip: TripID, world: &mut World);
@@ -166,15 +376,20 @@ impl TripCreator {
     }
 }
 
-use rand::Rng;
-
 impl Sleeper for TripCreator {
     fn wake(&mut self, current_tick: Timestamp, world: &mut World) {
-        ::rand::thread_rng().shuffle(&mut self.lanes);
+        ::core::random::shuffle(&mut self.lanes);
 
         for mut pair in &self.lanes.iter().chunks(2) {
             if let (Some(source), Some(dest)) = (pair.next(), pair.next()) {
-                TripID::spawn((*source).into(), (*dest).into(), None, current_tick, world);
+                TripID::spawn(
+                    (*source).into(),
+                    (*dest).into(),
+                    None,
+                    VehicleClass::Car,
+                    current_tick,
+                    world,
+                );
             }
         }
 
@@ -182,6 +397,50 @@ impl Sleeper for TripCreator {
     }
 }
 
+impl Lane {
+    pub fn join_random_trips(&mut self, probability: f32, world: &mut World) {
+        if !self.connectivity.on_intersection && ::core::random::next_f32() < probability {
+            TripCreatorID::local_first(world).add_lane_for_trip(self.id, world);
+        }
+    }
+}
+
+pub trait TripProgressListener {
+    fn on_trip_progress(
+        &mut self,
+        trip: TripID,
+        lane: LaneID,
+        position: P2,
+        remaining_distance: Option<f32>,
+        world: &mut World,
+    );
+}
+
+impl Lane {
+    /// Answers where the car of `trip` currently is, if it is on this lane
+    pub fn report_trip_progress(
+        &mut self,
+        trip: TripID,
+        listener: TripProgressListenerID,
+        world: &mut World,
+    ) {
+        if let Some(car) = self.microtraffic.cars.iter().find(|car| car.trip == trip) {
+            let remaining_distance = self.pathfinding
+                .route_for(car.destination, car.vehicle)
+                .map(|routing_info| {
+                    routing_info.distance.0 + self.construction.length - *car.position
+                });
+            listener.on_trip_progress(
+                trip,
+                self.id,
+                self.construction.path.along(*car.position),
+                remaining_distance,
+                world,
+            );
+        }
+    }
+}
+
 use stagemaster::{Interactable3d, Interactable3dID, MSG_Interactable3d_on_event};
 
 impl Interactable3d for Lane {
@@ -207,6 +466,8 @@ impl Interactable3d for Lane {
 pub fn setup(system: &mut ActorSystem, simulation: SimulationID) {
     system.register::<Trip>();
     system.register::<TripCreator>();
+    system.make_restorable::<Trip>();
+    system.make_restorable::<TripCreator>();
     auto_setup(system);
 
     TripCreatorID::spawn(simulation, &mut system.world());
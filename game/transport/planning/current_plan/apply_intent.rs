@@ -1,10 +1,12 @@
 use compact::{CVec, CDict};
-use descartes::{N, V2, P2, Segment, Norm, FiniteCurve, Curve, RelativeToBasis,
+use descartes::{N, V2, P2, Segment, Path, Norm, FiniteCurve, Curve, RelativeToBasis,
                 WithUniqueOrthogonal, RoughlyComparable, Dot};
+use stagemaster::geometry::CPath;
 
 use super::{PlanStep, Settings, LaneStrokeRef, SelectableStrokeRef, ContinuationMode};
-use super::super::plan::{PlanDelta, BuiltStrokes};
+use super::super::plan::{PlanDelta, BuiltStrokes, Structure};
 use super::super::lane_stroke::{LaneStroke, LaneStrokeNode};
+use super::super::super::utilities::{Conduit, UtilityKind};
 use itertools::Itertools;
 use ordered_float::OrderedFloat;
 
@@ -28,6 +30,14 @@ pub fn apply_intent(
             apply_new_road(points, current, still_built_strokes(), settings)
         }
 
+        Intent::NewPath(ref points) => apply_new_path(points, current),
+
+        Intent::NewConduit(kind, ref points) => {
+            apply_new_conduit(kind, points, current, still_built_strokes())
+        }
+
+        Intent::NewRoundabout(center, radius) => apply_new_roundabout(center, radius, current),
+
         Intent::ContinueRoad(ref continue_from, ref additional_points, start_reference_point) => {
             apply_continue_road(
                 continue_from,
@@ -72,6 +82,99 @@ pub fn apply_intent(
     }
 }
 
+const MIN_PATH_SEGMENT_LENGTH: N = 0.1;
+
+fn apply_new_path(points: &CVec<P2>, current: &PlanStep) -> PlanStep {
+    // paths are just walked along, so straight segments between the points are fine
+    let segments = points
+        .windows(2)
+        .filter(|pair| (pair[1] - pair[0]).norm() > MIN_PATH_SEGMENT_LENGTH)
+        .map(|pair| Segment::line(pair[0], pair[1]))
+        .collect::<Vec<_>>();
+
+    let mut new_paths = current.plan_delta.new_paths.clone();
+    if !segments.is_empty() {
+        new_paths.push(CPath::new(segments));
+    }
+
+    PlanStep {
+        plan_delta: PlanDelta { new_paths, ..current.plan_delta.clone() },
+        selections: current.selections.clone(),
+        intent: Intent::None,
+    }
+}
+
+/// Conduits can only be laid along roads, at most this far from the middle of a lane
+const RIGHT_OF_WAY_DISTANCE: N = 12.0;
+
+fn apply_new_conduit(
+    kind: UtilityKind,
+    points: &CVec<P2>,
+    current: &PlanStep,
+    still_built_strokes: &BuiltStrokes,
+) -> PlanStep {
+    let along_road = |point: P2| {
+        still_built_strokes
+            .mapping
+            .values()
+            .chain(current.plan_delta.new_strokes.iter())
+            .any(|stroke| stroke.path().distance_to(point) < RIGHT_OF_WAY_DISTANCE)
+    };
+
+    // parts of the stroke away from roads are left out, splitting it into several conduits
+    let mut runs = vec![Vec::new()];
+    for pair in points.windows(2) {
+        if (pair[1] - pair[0]).norm() > MIN_PATH_SEGMENT_LENGTH && along_road(pair[0]) &&
+            along_road(pair[1])
+        {
+            runs.last_mut().unwrap().push(Segment::line(pair[0], pair[1]));
+        } else if !runs.last().unwrap().is_empty() {
+            runs.push(Vec::new());
+        }
+    }
+
+    let mut new_conduits = current.plan_delta.new_conduits.clone();
+    for segments in runs {
+        if !segments.is_empty() {
+            new_conduits.push(Conduit { kind, path: CPath::new(segments) });
+        }
+    }
+
+    PlanStep {
+        plan_delta: PlanDelta { new_conduits, ..current.plan_delta.clone() },
+        selections: current.selections.clone(),
+        intent: Intent::None,
+    }
+}
+
+const ROUNDABOUT_NODES: usize = 8;
+
+fn apply_new_roundabout(center: P2, radius: N, current: &PlanStep) -> PlanStep {
+    // a single one-way lane going counterclockwise, closed by ending where it started.
+    // The roads it crosses get their own intersections with it
+    let nodes = (0..(ROUNDABOUT_NODES + 1))
+        .map(|i| {
+            let angle = 2.0 * ::std::f32::consts::PI * (i % ROUNDABOUT_NODES) as N /
+                ROUNDABOUT_NODES as N;
+            LaneStrokeNode {
+                position: center + V2::new(angle.cos(), angle.sin()) * radius,
+                direction: V2::new(-angle.sin(), angle.cos()),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let mut new_strokes = current.plan_delta.new_strokes.clone();
+    if let Ok(ring) = LaneStroke::new(nodes.into()) {
+        new_strokes.push(ring);
+    }
+
+    PlanStep {
+        plan_delta: PlanDelta { new_strokes, ..current.plan_delta.clone() },
+        selections: current.selections.clone(),
+        intent: Intent::None,
+    }
+}
+
 fn apply_new_road(
     points: &CVec<P2>,
     current: &PlanStep,
@@ -116,8 +219,22 @@ fn apply_new_road(
     let mut new_new_strokes = current.plan_delta.new_strokes.clone();
     new_new_strokes.extend(one_point_strokes);
 
+    // the whole road becomes the structure, even if only part of it needs to be one
+    let mut new_structures = current.plan_delta.new_structures.clone();
+    if let Some(kind) = settings.structure {
+        let segments = points
+            .windows(2)
+            .filter(|pair| (pair[1] - pair[0]).norm() > MIN_PATH_SEGMENT_LENGTH)
+            .map(|pair| Segment::line(pair[0], pair[1]))
+            .collect::<Vec<_>>();
+        if !segments.is_empty() {
+            new_structures.push(Structure { kind, path: CPath::new(segments) });
+        }
+    }
+
     let plan_delta_with_new_strokes = PlanDelta {
         new_strokes: new_new_strokes,
+        new_structures,
         ..current.plan_delta.clone()
     };
 
@@ -219,6 +219,12 @@ use super::ContinuationMode;
 impl Interactable3d for Selectable {
     fn on_event(&mut self, event: Event3d, world: &mut World) {
         match event {
+            Event3d::HoverStarted { .. } => {
+                self.current_plan.hover_stroke(self.stroke_ref, self.path.clone(), world);
+            }
+            Event3d::HoverStopped => {
+                self.current_plan.unhover_stroke(self.stroke_ref, world);
+            }
             Event3d::DragOngoing { from, to, .. } => {
                 if let (Some(selection_start), Some(selection_end)) =
                     (
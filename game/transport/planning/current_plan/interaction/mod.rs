@@ -1,11 +1,12 @@
-use compact::CVec;
+use compact::{CVec, COption, CDict};
 use kay::{ActorSystem, External, World};
 use super::{CurrentPlan, CurrentPlanID, SelectableStrokeRef};
 use transport::lane::LaneID;
-use stagemaster::geometry::AnyShape;
-use stagemaster::combo::{Bindings, Combo2};
+use stagemaster::geometry::{AnyShape, CPath};
+use stagemaster::combo::Combo2;
 use stagemaster::combo::Button::*;
-use descartes::{N, P2, FiniteCurve};
+use stagemaster::combo::GamepadButton::Start;
+use descartes::{N, P2, Norm, Into2d, FiniteCurve};
 
 use super::helper_interactables::{DeselecterID, AddableID, DraggableID, SelectableID,
                                   StrokeCanvasID, StrokeState};
@@ -18,35 +19,31 @@ pub struct Interaction {
     draggables: CVec<DraggableID>,
     pub stroke_canvas: StrokeCanvasID,
     deselecter: Option<DeselecterID>,
-    settings: External<InteractionSettings>,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
-struct InteractionSettings {
-    bindings: Bindings,
-}
+use monet::{RendererID, EyeListener, Eye, Movement, EyeListenerID, MSG_EyeListener_eye_moved};
+use stagemaster::UserInterfaceID;
 
-impl Default for InteractionSettings {
-    fn default() -> Self {
-        InteractionSettings {
-            bindings: Bindings::new(vec![
-                ("Materialize Plan", Combo2::new(&[Return], &[])),
-                ("Undo Step", Combo2::new(&[LControl, Z], &[LWin, Z])),
-                (
-                    "Redo Step",
-                    Combo2::new(&[LControl, LShift, Z], &[LWin, LShift, Z])
-                ),
-                ("Spawn Cars", Combo2::new(&[C], &[])),
-                ("Create Small Grid", Combo2::new(&[G], &[])),
-                ("Create Large Grid", Combo2::new(&[LShift, G], &[])),
-                ("Delete Selection", Combo2::new(&[Back], &[Delete])),
-            ]),
-        }
+/// Tools like placing buildings or painting lanes are added to the user interface
+/// with this z-index while they're active: above the plan canvas, so they get the clicks
+const TOOL_Z_INDEX: usize = 3;
+
+/// Makes `tool` receive all clicks instead of the plan canvas, or stop receiving them
+pub fn set_tool_active(
+    user_interface: UserInterfaceID,
+    tool: Interactable3dID,
+    active: bool,
+    world: &mut World,
+) {
+    if active {
+        user_interface.add(tool, AnyShape::Everywhere, TOOL_Z_INDEX, world);
+    } else {
+        user_interface.remove(tool, world);
     }
 }
 
-use monet::{RendererID, EyeListener, Eye, Movement, EyeListenerID, MSG_EyeListener_eye_moved};
-use stagemaster::UserInterfaceID;
+use stagemaster::actions::{register_action, ActionId, ActionListener, ActionListenerID,
+                           ActionPhase, MSG_ActionListener_on_action};
 
 impl Interaction {
     pub fn init(
@@ -59,8 +56,24 @@ impl Interaction {
         user_interface.add_2d(id.into(), world);
         user_interface.focus(id.into(), world);
         renderer_id.add_eye_listener(0, id.into(), world);
+        let bindings = [
+            ("Materialize Plan", Combo2::new(&[Return], &[Gamepad(Start)])),
+            ("Undo Step", Combo2::new(&[LControl, Z], &[LWin, Z])),
+            ("Redo Step", Combo2::new(&[LControl, LShift, Z], &[LWin, LShift, Z])),
+            ("Spawn Cars", Combo2::new(&[C], &[])),
+            ("Create Small Grid", Combo2::new(&[G], &[])),
+            ("Create Large Grid", Combo2::new(&[LControl, LShift, G], &[])),
+            ("Delete Selection", Combo2::new(&[Back], &[Delete])),
+            ("Toggle Pedestrian Paths", Combo2::new(&[LControl, LShift, P], &[])),
+            ("Toggle Bike Lanes", Combo2::new(&[LControl, LShift, K], &[])),
+            ("Cycle Utility Conduits", Combo2::new(&[LControl, LShift, U], &[])),
+            ("Cycle Bridge/Tunnel", Combo2::new(&[LControl, LShift, B], &[])),
+            ("Store Plan as Phase", Combo2::new(&[LControl, M], &[])),
+        ];
+        for &(action, default_binding) in &bindings {
+            register_action(action, default_binding, id.into(), world);
+        }
         Interaction {
-            settings: External::new(::ENV.load_settings("Plan Editing")),
             selectables: CVec::new(),
             addables: CVec::new(),
             draggables: CVec::new(),
@@ -87,11 +100,37 @@ impl EyeListener for CurrentPlan {
 }
 
 impl CurrentPlan {
+    pub fn hover_stroke(
+        &mut self,
+        stroke_ref: SelectableStrokeRef,
+        path: &CPath,
+        _: &mut World,
+    ) {
+        if self.hovered_stroke != Some(stroke_ref) {
+            self.set_hovered(Some(stroke_ref), Some(path.clone()));
+        }
+    }
+
+    /// Only stops highlighting `stroke_ref` if no other stroke was hovered since
+    pub fn unhover_stroke(&mut self, stroke_ref: SelectableStrokeRef, _: &mut World) {
+        if self.hovered_stroke == Some(stroke_ref) {
+            self.set_hovered(None, None);
+        }
+    }
+
+    fn set_hovered(&mut self, stroke_ref: Option<SelectableStrokeRef>, path: Option<CPath>) {
+        self.hovered_stroke = stroke_ref;
+        self.hovered_path = COption(path);
+        self.hover_rendered_in = CDict::new();
+    }
+
     pub fn invalidate_interactables(&mut self) {
         self.interactables_valid = false;
     }
 
     pub fn update_interactables(&mut self, world: &mut World) {
+        // the selectables that could be hovered are replaced
+        self.set_hovered(None, None);
         for selectable in self.interaction.selectables.drain() {
             selectable.clear(self.interaction.user_interface, world);
         }
@@ -119,6 +158,8 @@ impl CurrentPlan {
             match self.current.intent {
                 Intent::ContinueRoad(..) |
                 Intent::NewRoad(..) |
+                Intent::NewPath(..) |
+                Intent::NewConduit(..) |
                 Intent::ContinueRoadAround(..) => {}
                 _ => {
                     for (i, stroke) in self.current.plan_delta.new_strokes.iter().enumerate() {
@@ -170,95 +211,18 @@ impl CurrentPlan {
 use stagemaster::{Interactable3d, Interactable3dID, Interactable2d, Interactable2dID, Event3d,
                   MSG_Interactable3d_on_event, MSG_Interactable2d_draw_ui_2d};
 use super::{Intent, IntentProgress};
+use super::super::validation::Severity;
+use super::phases::DAYS_PER_MONTH;
+use imgui::ImGuiSetCond_FirstUseEver;
+
+/// Explanations of issues are shown when the mouse is this close to their marker
+const ISSUE_HOVER_DISTANCE: N = 5.0;
 
 impl Interactable3d for CurrentPlan {
     fn on_event(&mut self, event: Event3d, world: &mut World) {
         match event {
-            Event3d::Combos(combos) => {
-                self.interaction.settings.bindings.do_rebinding(
-                    &combos.current,
-                );
-                let bindings = &self.interaction.settings.bindings;
-
-                if bindings["Materialize Plan"].is_freshly_in(&combos) {
-                    self.id.materialize(world);
-                }
-
-                if bindings["Redo Step"].is_freshly_in(&combos) {
-                    self.id.redo(world);
-                } else if bindings["Undo Step"].is_freshly_in(&combos) {
-                    self.id.undo(world);
-                }
-
-                if bindings["Spawn Cars"].is_freshly_in(&combos) {
-                    // TODO: this is not supposed to be here!
-                    //       *but we have only one focusable!*
-                    //       WTF?! what's wrong with your UI model?
-                    //       *I uh.. I guess I should actually write a good one*
-                    //       When will you finally?!
-                    //       *Uh.. next week maybe?*
-                    use descartes::P3;
-                    let lanes_as_interactables: Interactable3dID = LaneID::global_broadcast(world)
-                        .into();
-                    for _i in 0..100 {
-                        lanes_as_interactables.on_event(
-                            Event3d::DragFinished {
-                                from: P3::new(0.0, 0.0, 0.0),
-                                from2d: P2::new(0.0, 0.0),
-                                to: P3::new(0.0, 0.0, 0.0),
-                                to2d: P2::new(0.0, 0.0),
-                            },
-                            world,
-                        );
-                    }
-                }
-
-                let maybe_grid_size = if bindings["Create Large Grid"].is_freshly_in(&combos) {
-                    Some(15usize)
-                } else if bindings["Create Small Grid"].is_freshly_in(&combos) {
-                    Some(10usize)
-                } else {
-                    None
-                };
-
-                if let Some(grid_size) = maybe_grid_size {
-                    const GRID_SPACING: N = 1000.0;
-                    for x in 0..grid_size {
-                        self.id.on_stroke(
-                            vec![
-                                P2::new((x as f32 + 0.5) * GRID_SPACING, 0.0),
-                                P2::new(
-                                    (x as f32 + 0.5) * GRID_SPACING,
-                                    grid_size as f32 * GRID_SPACING
-                                ),
-                            ].into(),
-                            StrokeState::Finished,
-                            world,
-                        );
-
-                    }
-                    for y in 0..grid_size {
-                        self.id.on_stroke(
-                            vec![
-                                P2::new(0.0, (y as f32 + 0.5) * GRID_SPACING),
-                                P2::new(
-                                    grid_size as f32 * GRID_SPACING,
-                                    (y as f32 + 0.5) * GRID_SPACING
-                                ),
-                            ].into(),
-                            StrokeState::Finished,
-                            world,
-                        );
-                    }
-                }
-
-                if bindings["Delete Selection"].is_freshly_in(&combos) {
-                    self.id.change_intent(
-                        Intent::DeleteSelection,
-                        IntentProgress::Immediate,
-                        world,
-                    );
-                }
+            Event3d::MouseMove3d(at) => {
+                self.cursor = at.into_2d();
             }
             Event3d::ButtonDown(NumberKey(num)) => {
                 if num == 0 {
@@ -272,6 +236,93 @@ impl Interactable3d for CurrentPlan {
     }
 }
 
+impl CurrentPlan {
+    fn create_grid(&mut self, grid_size: usize, world: &mut World) {
+        const GRID_SPACING: N = 1000.0;
+        for x in 0..grid_size {
+            self.id.on_stroke(
+                vec![
+                    P2::new((x as f32 + 0.5) * GRID_SPACING, 0.0),
+                    P2::new(
+                        (x as f32 + 0.5) * GRID_SPACING,
+                        grid_size as f32 * GRID_SPACING
+                    ),
+                ].into(),
+                StrokeState::Finished,
+                world,
+            );
+        }
+        for y in 0..grid_size {
+            self.id.on_stroke(
+                vec![
+                    P2::new(0.0, (y as f32 + 0.5) * GRID_SPACING),
+                    P2::new(
+                        grid_size as f32 * GRID_SPACING,
+                        (y as f32 + 0.5) * GRID_SPACING
+                    ),
+                ].into(),
+                StrokeState::Finished,
+                world,
+            );
+        }
+    }
+}
+
+impl ActionListener for CurrentPlan {
+    fn on_action(&mut self, action: ActionId, phase: ActionPhase, world: &mut World) {
+        if phase == ActionPhase::Started {
+            if action == ActionId::named("Materialize Plan") {
+                self.id.materialize(world);
+            } else if action == ActionId::named("Undo Step") {
+                self.id.undo(world);
+            } else if action == ActionId::named("Redo Step") {
+                self.id.redo(world);
+            } else if action == ActionId::named("Spawn Cars") {
+                // TODO: this is not supposed to be here!
+                //       *but we have only one focusable!*
+                //       WTF?! what's wrong with your UI model?
+                //       *I uh.. I guess I should actually write a good one*
+                //       When will you finally?!
+                //       *Uh.. next week maybe?*
+                use descartes::P3;
+                let lanes_as_interactables: Interactable3dID = LaneID::global_broadcast(world)
+                    .into();
+                for _i in 0..100 {
+                    lanes_as_interactables.on_event(
+                        Event3d::DragFinished {
+                            from: P3::new(0.0, 0.0, 0.0),
+                            from2d: P2::new(0.0, 0.0),
+                            to: P3::new(0.0, 0.0, 0.0),
+                            to2d: P2::new(0.0, 0.0),
+                        },
+                        world,
+                    );
+                }
+            } else if action == ActionId::named("Create Small Grid") {
+                self.create_grid(10, world);
+            } else if action == ActionId::named("Create Large Grid") {
+                self.create_grid(15, world);
+            } else if action == ActionId::named("Delete Selection") {
+                self.id.change_intent(
+                    Intent::DeleteSelection,
+                    IntentProgress::Immediate,
+                    world,
+                );
+            } else if action == ActionId::named("Toggle Pedestrian Paths") {
+                self.id.toggle_pedestrian_only(world);
+            } else if action == ActionId::named("Toggle Bike Lanes") {
+                self.id.toggle_bike_lanes(world);
+            } else if action == ActionId::named("Cycle Utility Conduits") {
+                self.id.cycle_utility(world);
+            } else if action == ActionId::named("Cycle Bridge/Tunnel") {
+                self.id.cycle_structure(world);
+            } else if action == ActionId::named("Store Plan as Phase") {
+                self.id.store_as_phase(world);
+            }
+        }
+    }
+}
+
 impl Interactable2d for CurrentPlan {
     fn draw_ui_2d(
         &mut self,
@@ -283,15 +334,90 @@ impl Interactable2d for CurrentPlan {
 
         ui.window(im_str!("Controls")).build(|| {
             ui.text(im_str!("Plan Editing"));
-            ui.separator();
-
-            if self.interaction.settings.bindings.settings_ui(&ui) {
-                ::ENV.write_settings("Plan Editing", &*self.interaction.settings)
+            let drawing = if let Some(kind) = self.settings.utility {
+                format!("{} conduits", kind.name())
+            } else if self.settings.pedestrian_only {
+                "pedestrian paths".to_owned()
+            } else if let Some(kind) = self.settings.structure {
+                format!("roads on a {}", kind.name())
+            } else {
+                "roads".to_owned()
+            };
+            let drawing_roads = self.settings.utility.is_none() && !self.settings.pedestrian_only;
+            let profile = if drawing_roads && self.settings.bike_lanes {
+                " with bike lanes"
+            } else {
+                ""
+            };
+            ui.text(im_str!("Drawing: {}{}", drawing, profile));
+            let n_errors = self.issues
+                .iter()
+                .filter(|issue| issue.severity() == Severity::Error)
+                .count();
+            ui.text(im_str!(
+                "Plan: {} errors, {} warnings",
+                n_errors,
+                self.issues.len() - n_errors
+            ));
+            let cursor = self.cursor;
+            let maybe_hovered_issue = self.issues
+                .iter()
+                .map(|issue| (issue, (issue.position - cursor).norm()))
+                .filter(|&(_, distance)| distance < ISSUE_HOVER_DISTANCE)
+                .min_by(|&(_, a), &(_, b)| a.partial_cmp(&b).unwrap());
+            if let Some((issue, _)) = maybe_hovered_issue {
+                ui.tooltip_text(im_str!("{}", issue.explanation()));
+            }
+            let (n_lanes_built, n_lanes_to_build) = self.materialization_progress;
+            if n_lanes_built < n_lanes_to_build {
+                ui.text(im_str!(
+                    "Materializing: {} of {} lanes built ({:.0}%)",
+                    n_lanes_built,
+                    n_lanes_to_build,
+                    100.0 * n_lanes_built as f32 / n_lanes_to_build as f32
+                ));
             }
 
             ui.spacing();
         });
 
+        if !self.phases.is_empty() {
+            ui.window(im_str!("Plan Phases"))
+                .size((350.0, 200.0), ImGuiSetCond_FirstUseEver)
+                .collapsible(true)
+                .build(|| for (i, phase) in self.phases.iter().enumerate() {
+                    ui.text(im_str!(
+                        "{}: {}",
+                        phase.name.iter().cloned().collect::<String>(),
+                        phase.summary()
+                    ));
+                    if let Some(days) = phase.days_until_opening {
+                        ui.text(im_str!(
+                            "Opens in {:.1} months",
+                            days as f32 / DAYS_PER_MONTH as f32
+                        ));
+                    } else {
+                        ui.text(im_str!("Not scheduled"));
+                    }
+                    if ui.small_button(im_str!("Open now##{}", i)) {
+                        self.id.open_phase(i, world);
+                    }
+                    ui.same_line(0.0);
+                    if ui.small_button(im_str!("+1 month##{}", i)) {
+                        self.id.postpone_phase(i, 1, world);
+                    }
+                    ui.same_line(0.0);
+                    if ui.small_button(im_str!("Forecast##{}", i)) {
+                        self.id.forecast_phase(i, world);
+                    }
+                    ui.same_line(0.0);
+                    if ui.small_button(im_str!("Discard##{}", i)) {
+                        self.id.discard_phase(i, world);
+                    }
+                    ui.separator();
+                });
+        }
+
         return_to.ui_drawn(ui, world);
     }
 }
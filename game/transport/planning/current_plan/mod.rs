@@ -1,12 +1,18 @@
 use kay::{ActorSystem, World};
 use compact::{COption, CVec, CDict};
-use descartes::{V2, N, P2};
+use descartes::{V2, N, P2, Curve};
 use stagemaster::UserInterfaceID;
+use stagemaster::geometry::CPath;
 use monet::RendererID;
+use core::simulation::{SimulationID, Ticks, TICKS_PER_SIM_DAY};
 
 use super::super::construction::materialized_reality::MaterializedRealityID;
+use super::super::demand_forecast::DemandForecastID;
+use super::super::utilities::UtilityKind;
 use super::lane_stroke::LaneStroke;
-use super::plan::{PlanDelta, PlanResultDelta, BuiltStrokes, LaneStrokeRef};
+use super::plan::{PlanDelta, PlanResultDelta, BuiltStrokes, LaneStrokeRef, StructureKind};
+use super::validation::{self, ValidationIssue, IssueKind, Severity};
+use terrain::TerrainID;
 
 mod apply_intent;
 use self::apply_intent::apply_intent;
@@ -17,6 +23,10 @@ use self::helper_interactables::StrokeState;
 
 mod interaction;
 use self::interaction::Interaction;
+pub use self::interaction::set_tool_active;
+
+mod phases;
+use self::phases::PlanPhase;
 
 #[derive(Compact, Clone, Default)]
 pub struct PlanStep {
@@ -58,6 +68,10 @@ pub enum ContinuationMode {
 pub enum Intent {
     None,
     NewRoad(CVec<P2>),
+    NewPath(CVec<P2>),
+    NewConduit(UtilityKind, CVec<P2>),
+    /// A one-way ring road around the center, with the given radius
+    NewRoundabout(P2, N),
     ContinueRoad(CVec<(SelectableStrokeRef, ContinuationMode)>, CVec<P2>, P2),
     ContinueRoadAround(SelectableStrokeRef, ContinuationMode, P2),
     Select(SelectableStrokeRef, N, N),
@@ -88,6 +102,14 @@ pub struct Settings {
     create_both_sides: bool,
     select_parallel: bool,
     select_opposite: bool,
+    /// Strokes become pedestrian-only paths instead of roads
+    pedestrian_only: bool,
+    /// Strokes become utility conduits of this kind instead of roads
+    utility: Option<UtilityKind>,
+    /// New roads are built as this kind of structure, so they can cross water
+    structure: Option<StructureKind>,
+    /// Roads get bike lanes next to their sidewalks, and protected intersections
+    bike_lanes: bool,
 }
 
 impl Default for Settings {
@@ -97,13 +119,20 @@ impl Default for Settings {
             n_lanes_per_side: 2,
             select_parallel: true,
             select_opposite: true,
+            pedestrian_only: false,
+            utility: None,
+            structure: None,
+            bike_lanes: false,
         }
     }
 }
 
+const DEMOLITION_DISTANCE: N = 3.0;
+
 #[derive(Compact, Clone)]
 pub struct CurrentPlan {
     id: CurrentPlanID,
+    simulation: SimulationID,
     materialized_reality: MaterializedRealityID,
     built_strokes: COption<BuiltStrokes>,
     undo_history: CVec<PlanStep>,
@@ -116,6 +145,22 @@ pub struct CurrentPlan {
     interactables_valid: bool,
     settings: Settings,
     interaction: Interaction,
+    /// Problems found in the preview, see `validation`
+    issues: CVec<ValidationIssue>,
+    issues_rendered_in: CDict<RendererID, ()>,
+    /// Where the mouse points to, for showing explanations of nearby issues
+    cursor: P2,
+    /// Lanes built and to be built of the last materialized plan
+    materialization_progress: (usize, usize),
+    /// Plans set aside to be opened later, see `phases`
+    phases: CVec<PlanPhase>,
+    phases_rendered_in: CDict<RendererID, ()>,
+    /// The phase the next demand forecast is for, instead of the current plan
+    forecast_phase_idx: Option<usize>,
+    /// The stroke under the mouse and its path, highlighted over the planned ghosts
+    hovered_stroke: Option<SelectableStrokeRef>,
+    hovered_path: COption<CPath>,
+    hover_rendered_in: CDict<RendererID, ()>,
 }
 
 impl CurrentPlan {
@@ -123,14 +168,17 @@ impl CurrentPlan {
         id: CurrentPlanID,
         user_interface: UserInterfaceID,
         renderer_id: RendererID,
+        simulation: SimulationID,
         materialized_reality: MaterializedRealityID,
         world: &mut World,
     ) -> CurrentPlan {
         // TODO: is there a nicer way to get initial built strokes?
         materialized_reality.apply(id, PlanDelta::default(), world);
+        simulation.wake_up_in(Ticks(TICKS_PER_SIM_DAY), id.into(), world);
 
         CurrentPlan {
             id: id,
+            simulation,
             settings: Settings::default(),
             materialized_reality,
             interaction: Interaction::init(world, user_interface, renderer_id, id),
@@ -143,6 +191,16 @@ impl CurrentPlan {
             preview_result_delta: COption(None),
             preview_result_delta_rendered_in: CDict::new(),
             interactables_valid: false,
+            issues: CVec::new(),
+            issues_rendered_in: CDict::new(),
+            cursor: P2::new(0.0, 0.0),
+            materialization_progress: (0, 0),
+            phases: CVec::new(),
+            phases_rendered_in: CDict::new(),
+            forecast_phase_idx: None,
+            hovered_stroke: None,
+            hovered_path: COption(None),
+            hover_rendered_in: CDict::new(),
         }
     }
 }
@@ -184,11 +242,30 @@ impl CurrentPlan {
                 preview.plan_delta.clone(),
                 world,
             );
+            self.replace_issues(
+                &[IssueKind::SharpCurve, IssueKind::ZeroLengthSegment],
+                validation::check_strokes(&preview.plan_delta),
+            );
+            TerrainID::local_first(world).check_terrain(
+                surface_paths(&preview.plan_delta),
+                structure_paths(&preview.plan_delta),
+                self.id,
+                world,
+            );
             self.preview = COption(Some(preview));
         }
         self.preview.as_ref().unwrap()
     }
 
+    /// Replaces the issues of the given kinds, each check only finds its own kinds
+    fn replace_issues(&mut self, kinds: &[IssueKind], new_issues: Vec<ValidationIssue>) {
+        self.issues.retain(|issue| !kinds.contains(&issue.kind));
+        for issue in new_issues {
+            self.issues.push(issue);
+        }
+        self.issues_rendered_in = CDict::new();
+    }
+
     fn commit(&mut self) {
         self.undo_history.push(self.current.clone());
         self.redo_history.clear();
@@ -234,7 +311,9 @@ impl CurrentPlan {
         self.interaction.stroke_canvas.set_points(
             match self.current.intent {
                 Intent::ContinueRoad(_, ref points, _) |
-                Intent::NewRoad(ref points) => points.clone(),
+                Intent::NewRoad(ref points) |
+                Intent::NewPath(ref points) |
+                Intent::NewConduit(_, ref points) => points.clone(),
                 _ => CVec::new(),
             },
             world,
@@ -250,7 +329,9 @@ impl CurrentPlan {
             self.interaction.stroke_canvas.set_points(
                 match self.current.intent {
                     Intent::ContinueRoad(_, ref points, _) |
-                    Intent::NewRoad(ref points) => points.clone(),
+                    Intent::NewRoad(ref points) |
+                    Intent::NewPath(ref points) |
+                    Intent::NewConduit(_, ref points) => points.clone(),
                     _ => CVec::new(),
                 },
                 world,
@@ -282,7 +363,13 @@ impl CurrentPlan {
             _ => {
                 if points.len() >= 2 {
                     self.invalidate_interactables();
-                    Some(Intent::NewRoad(points.clone()))
+                    if let Some(kind) = self.settings.utility {
+                        Some(Intent::NewConduit(kind, points.clone()))
+                    } else if self.settings.pedestrian_only {
+                        Some(Intent::NewPath(points.clone()))
+                    } else {
+                        Some(Intent::NewRoad(points.clone()))
+                    }
                 } else {
                     None
                 }
@@ -315,11 +402,81 @@ impl CurrentPlan {
         self.invalidate_preview();
     }
 
+    pub fn toggle_pedestrian_only(&mut self, _: &mut World) {
+        self.settings.pedestrian_only = !self.settings.pedestrian_only;
+        self.invalidate_preview();
+    }
+
+    pub fn toggle_bike_lanes(&mut self, _: &mut World) {
+        self.settings.bike_lanes = !self.settings.bike_lanes;
+        self.invalidate_preview();
+    }
+
+    pub fn cycle_utility(&mut self, _: &mut World) {
+        self.settings.utility = match self.settings.utility {
+            None => Some(UtilityKind::Water),
+            Some(kind) => kind.next(),
+        };
+        self.invalidate_preview();
+    }
+
+    pub fn cycle_structure(&mut self, _: &mut World) {
+        self.settings.structure = match self.settings.structure {
+            None => Some(StructureKind::Bridge),
+            Some(kind) => kind.next(),
+        };
+        self.invalidate_preview();
+    }
+
     pub fn on_simulation_result(&mut self, result_delta: &PlanResultDelta, _: &mut World) {
+        self.replace_issues(
+            &[IssueKind::IntersectionsTooClose],
+            validation::check_intersections(result_delta),
+        );
+        self.preview_result_delta = COption(Some(result_delta.clone()));
+        self.preview_result_delta_rendered_in = CDict::new();
+    }
+
+    pub fn on_terrain_issues(&mut self, issues: &CVec<ValidationIssue>, _: &mut World) {
+        self.replace_issues(
+            &[IssueKind::SteepGrade, IssueKind::UnbridgedWaterCrossing],
+            issues.iter().cloned().collect(),
+        );
+    }
+
+    /// Has the network as it would be with the plan (including what is being drawn
+    /// right now) sent to `forecast`, so traffic on it can be predicted. If a phase
+    /// was chosen for the forecast, it is done for that phase instead
+    pub fn forecast_demand(&mut self, forecast: DemandForecastID, world: &mut World) {
+        let delta = if let Some(phase_idx) = self.forecast_phase_idx.take() {
+            self.phases_delta(phase_idx)
+        } else {
+            self.update_preview(world).plan_delta.clone()
+        };
+        self.materialized_reality.forecast_network(forecast, delta, world);
+    }
+
+    pub fn on_materialization_progress(
+        &mut self,
+        n_lanes_built: usize,
+        n_lanes_to_build: usize,
+        _: &mut World,
+    ) {
+        self.materialization_progress = (n_lanes_built, n_lanes_to_build);
+    }
+
+    /// Shows the roads of a city being loaded, until its lanes are built
+    pub fn on_loading_started(&mut self, result_delta: &PlanResultDelta, _: &mut World) {
         self.preview_result_delta = COption(Some(result_delta.clone()));
         self.preview_result_delta_rendered_in = CDict::new();
     }
 
+    /// Goes back to showing the preview of the plan, replacing the loaded roads
+    pub fn on_loading_finished(&mut self, _: &mut World) {
+        self.invalidate_preview();
+        self.preview_rendered_in = CDict::new();
+    }
+
     pub fn built_strokes_changed(&mut self, built_strokes: &BuiltStrokes, _: &mut World) {
         self.built_strokes = COption(Some(built_strokes.clone()));
     }
@@ -329,7 +486,9 @@ impl CurrentPlan {
     pub fn materialize(&mut self, world: &mut World) {
         match self.current.intent {
             Intent::ContinueRoad(..) |
-            Intent::NewRoad(..) => {
+            Intent::NewRoad(..) |
+            Intent::NewPath(..) |
+            Intent::NewConduit(..) => {
                 self.commit();
                 self.interaction.stroke_canvas.set_points(
                     CVec::new(),
@@ -339,14 +498,81 @@ impl CurrentPlan {
             _ => {}
         }
 
+        // the committed plan is checked again, since the last preview might have been
+        // of an unfinished stroke. The terrain and intersections were checked on the preview
+        self.replace_issues(
+            &[IssueKind::SharpCurve, IssueKind::ZeroLengthSegment],
+            validation::check_strokes(&self.current.plan_delta),
+        );
+        let n_errors = self.issues
+            .iter()
+            .filter(|issue| issue.severity() == Severity::Error)
+            .count();
+        if n_errors > 0 {
+            println!("Can't materialize a plan with {} errors", n_errors);
+            return;
+        }
+
         self.materialized_reality.apply(
             self.id,
-            self.current.plan_delta.clone(),
+            PlanDelta {
+                bike_lanes: self.settings.bike_lanes,
+                ..self.current.plan_delta.clone()
+            },
             world,
         );
 
+        self.reset();
+    }
+
+    /// Immediately destroys the built stroke closest to `position`, if there is one
+    /// within a few meters. Only works while nothing else is being planned, since
+    /// the materialization would discard the rest of the plan
+    pub fn demolish_at(&mut self, position: P2, world: &mut World) {
+        if !self.current.plan_delta.new_strokes.is_empty() ||
+            !self.current.plan_delta.strokes_to_destroy.is_empty()
+        {
+            println!("Can't demolish while there are unmaterialized plans");
+            return;
+        }
+
+        let maybe_closest = self.built_strokes.as_ref().and_then(|built_strokes| {
+            built_strokes
+                .mapping
+                .pairs()
+                .map(|(&stroke_ref, stroke)| {
+                    (stroke_ref, stroke, stroke.path().distance_to(position))
+                })
+                .filter(|&(_, _, distance)| distance < DEMOLITION_DISTANCE)
+                .min_by(|&(_, _, a), &(_, _, b)| a.partial_cmp(&b).unwrap())
+                .map(|(stroke_ref, stroke, _)| (stroke_ref, stroke.clone()))
+        });
+
+        if let Some((stroke_ref, stroke)) = maybe_closest {
+            let mut strokes_to_destroy = CDict::new();
+            strokes_to_destroy.insert(stroke_ref, stroke);
+
+            self.materialized_reality.apply(
+                self.id,
+                PlanDelta {
+                    new_strokes: CVec::new(),
+                    strokes_to_destroy,
+                    new_paths: CVec::new(),
+                    new_conduits: CVec::new(),
+                    new_structures: CVec::new(),
+                    bike_lanes: self.settings.bike_lanes,
+                },
+                world,
+            );
+
+            self.reset();
+        }
+    }
+
+    fn reset(&mut self) {
         *self = CurrentPlan {
             id: self.id,
+            simulation: self.simulation,
             materialized_reality: self.materialized_reality,
             settings: self.settings.clone(),
             interaction: self.interaction.clone(),
@@ -359,14 +585,40 @@ impl CurrentPlan {
             preview_result_delta: COption(None),
             preview_result_delta_rendered_in: CDict::new(),
             interactables_valid: false,
+            issues: CVec::new(),
+            issues_rendered_in: CDict::new(),
+            cursor: self.cursor,
+            materialization_progress: self.materialization_progress,
+            phases: self.phases.clone(),
+            phases_rendered_in: self.phases_rendered_in.clone(),
+            forecast_phase_idx: self.forecast_phase_idx,
+            hovered_stroke: None,
+            hovered_path: COption(None),
+            hover_rendered_in: CDict::new(),
         };
     }
 }
 
+/// The paths of everything in `delta` that is built on the terrain surface
+fn surface_paths(delta: &PlanDelta) -> CVec<CPath> {
+    delta
+        .new_strokes
+        .iter()
+        .filter(|stroke| stroke.nodes().len() > 1 && stroke.well_formed())
+        .map(|stroke| stroke.path().clone())
+        .chain(delta.new_paths.iter().cloned())
+        .collect()
+}
+
+fn structure_paths(delta: &PlanDelta) -> CVec<CPath> {
+    delta.new_structures.iter().map(|structure| structure.path.clone()).collect()
+}
+
 pub fn setup(
     system: &mut ActorSystem,
     user_interface: UserInterfaceID,
     renderer_id: RendererID,
+    simulation: SimulationID,
     materialized_reality: MaterializedRealityID,
 ) {
     system.register::<CurrentPlan>();
@@ -374,10 +626,12 @@ pub fn setup(
     helper_interactables::setup(system);
     interaction::auto_setup(system);
     rendering::auto_setup(system);
+    phases::auto_setup(system);
 
     CurrentPlanID::spawn(
         user_interface,
         renderer_id,
+        simulation,
         materialized_reality,
         &mut system.world(),
     );
@@ -0,0 +1,155 @@
+//! Plans can be set aside as phases instead of being materialized right away. Phases
+//! open on their own once their scheduled time has come (or when opened by hand), so a
+//! large project can be delivered step by step, and each phase can be forecast together
+//! with the phases before it.
+//!
+//! Phases can only add to the network: changes to built roads refer to the built
+//! strokes of the moment and have to be materialized directly.
+use kay::World;
+use compact::{CVec, CDict};
+use core::simulation::{Sleeper, SleeperID, MSG_Sleeper_wake, Timestamp, Ticks,
+                       TICKS_PER_SIM_DAY};
+use super::{CurrentPlan, Intent};
+use super::super::plan::PlanDelta;
+use super::super::super::demand_forecast::DemandForecastID;
+
+pub const DAYS_PER_MONTH: usize = 30;
+
+#[derive(Compact, Clone)]
+pub struct PlanPhase {
+    pub name: CVec<char>,
+    pub plan_delta: PlanDelta,
+    /// Opens by itself after this many days, if scheduled
+    pub days_until_opening: Option<usize>,
+}
+
+impl PlanPhase {
+    pub fn summary(&self) -> String {
+        format!(
+            "{} roads, {} paths, {} conduits",
+            self.plan_delta.new_strokes.len(),
+            self.plan_delta.new_paths.len(),
+            self.plan_delta.new_conduits.len()
+        )
+    }
+}
+
+impl CurrentPlan {
+    pub fn store_as_phase(&mut self, world: &mut World) {
+        match self.current.intent {
+            Intent::ContinueRoad(..) |
+            Intent::NewRoad(..) |
+            Intent::NewPath(..) |
+            Intent::NewConduit(..) => {
+                self.commit();
+                self.interaction.stroke_canvas.set_points(
+                    CVec::new(),
+                    world,
+                );
+            }
+            _ => {}
+        }
+
+        let delta = self.current.plan_delta.clone();
+        if !delta.strokes_to_destroy.is_empty() {
+            println!("Changes to built roads can't be phased, materialize them directly");
+            return;
+        }
+        if delta.new_strokes.is_empty() && delta.new_paths.is_empty() &&
+            delta.new_conduits.is_empty()
+        {
+            return;
+        }
+
+        let name = format!("Phase {}", self.phases.len() + 1);
+        self.phases.push(PlanPhase {
+            name: name.chars().collect(),
+            plan_delta: delta,
+            days_until_opening: None,
+        });
+        self.phases_rendered_in = CDict::new();
+
+        // nothing gets built, so the built strokes stay the same
+        let built_strokes = self.built_strokes.clone();
+        self.reset();
+        self.built_strokes = built_strokes;
+    }
+
+    pub fn open_phase(&mut self, phase_idx: usize, world: &mut World) {
+        if phase_idx < self.phases.len() {
+            let phase = self.phases.remove(phase_idx);
+            self.materialized_reality.apply(self.id, phase.plan_delta, world);
+            self.phases_rendered_in = CDict::new();
+        }
+    }
+
+    /// Schedules the phase to open `n_months` later than it would so far
+    pub fn postpone_phase(&mut self, phase_idx: usize, n_months: usize, _: &mut World) {
+        if let Some(phase) = self.phases.get_mut(phase_idx) {
+            phase.days_until_opening =
+                Some(phase.days_until_opening.unwrap_or(0) + n_months * DAYS_PER_MONTH);
+        }
+    }
+
+    pub fn discard_phase(&mut self, phase_idx: usize, _: &mut World) {
+        if phase_idx < self.phases.len() {
+            self.phases.remove(phase_idx);
+            self.phases_rendered_in = CDict::new();
+        }
+    }
+
+    /// Forecasts the network with this and all earlier phases opened
+    pub fn forecast_phase(&mut self, phase_idx: usize, world: &mut World) {
+        if phase_idx < self.phases.len() {
+            self.forecast_phase_idx = Some(phase_idx);
+            DemandForecastID::local_first(world).start_forecast(world);
+        }
+    }
+
+    pub fn phases_delta(&self, up_to_idx: usize) -> PlanDelta {
+        let mut delta = PlanDelta::default();
+        for phase in self.phases.iter().take(up_to_idx + 1) {
+            for stroke in phase.plan_delta.new_strokes.iter() {
+                delta.new_strokes.push(stroke.clone());
+            }
+            for path in phase.plan_delta.new_paths.iter() {
+                delta.new_paths.push(path.clone());
+            }
+            for conduit in phase.plan_delta.new_conduits.iter() {
+                delta.new_conduits.push(conduit.clone());
+            }
+            for structure in phase.plan_delta.new_structures.iter() {
+                delta.new_structures.push(structure.clone());
+            }
+        }
+        delta
+    }
+}
+
+impl Sleeper for CurrentPlan {
+    fn wake(&mut self, _current_tick: Timestamp, world: &mut World) {
+        let mut phase_idx = 0;
+        while phase_idx < self.phases.len() {
+            let opens_today = if let Some(days) = self.phases[phase_idx].days_until_opening {
+                self.phases[phase_idx].days_until_opening = Some(days.saturating_sub(1));
+                days <= 1
+            } else {
+                false
+            };
+            if opens_today {
+                println!(
+                    "{} opens",
+                    self.phases[phase_idx].name.iter().cloned().collect::<String>()
+                );
+                self.open_phase(phase_idx, world);
+            } else {
+                phase_idx += 1;
+            }
+        }
+
+        self.simulation.wake_up_in(Ticks(TICKS_PER_SIM_DAY), self.id.into(), world);
+    }
+}
+
+mod kay_auto;
+pub use self::kay_auto::*;
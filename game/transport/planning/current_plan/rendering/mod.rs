@@ -1,15 +1,24 @@
 use kay::World;
-use compact::CDict;
+use compact::{CDict, CVec};
 use descartes::{N, Band, FiniteCurve};
-use monet::{Geometry, Instance, RendererID};
-use stagemaster::geometry::band_to_geometry;
+use monet::{Geometry, Vertex, Instance, RendererID, DecalPattern};
+use stagemaster::geometry::{CPath, band_to_geometry, path_to_decals};
 use super::{CurrentPlan, CurrentPlanID, SelectableStrokeRef};
-use super::super::plan::{PlanDelta, BuiltStrokes, PlanResultDelta};
+use super::phases::PlanPhase;
+use super::super::plan::{PlanDelta, BuiltStrokes, PlanResultDelta, StructureKind};
 use super::super::lane_stroke::LaneStroke;
+use super::super::validation::{ValidationIssue, Severity, STRUCTURE_DISTANCE};
+use super::super::super::utilities::UtilityKind;
 
 use monet::{Renderable, RenderableID, MSG_Renderable_setup_in_scene,
             MSG_Renderable_render_to_scene};
 
+const SELECTION_DECAL_LAYER: u16 = 400;
+/// Everything planned but not built yet is drawn see-through over the built network
+const GHOST_OPACITY: f32 = 0.5;
+const PHASE_GHOST_OPACITY: f32 = 0.3;
+const HOVER_OPACITY: f32 = 0.8;
+
 impl Renderable for CurrentPlan {
     fn setup_in_scene(&mut self, _renderer_id: RendererID, _scene_id: usize, _: &mut World) {}
 
@@ -56,6 +65,18 @@ impl Renderable for CurrentPlan {
                 render_transfer_lanes(result_delta, renderer_id, scene_id, world);
             }
         }
+        if self.hover_rendered_in.get(renderer_id).is_none() {
+            self.hover_rendered_in.insert(renderer_id, ());
+            render_hover(self.hovered_path.as_ref(), renderer_id, scene_id, world);
+        }
+        if self.issues_rendered_in.get(renderer_id).is_none() {
+            self.issues_rendered_in.insert(renderer_id, ());
+            render_issues(&self.issues, renderer_id, scene_id, world);
+        }
+        if self.phases_rendered_in.get(renderer_id).is_none() {
+            self.phases_rendered_in.insert(renderer_id, ());
+            render_phases(&self.phases, renderer_id, scene_id, world);
+        }
         if let Some(ref built_strokes) = *self.built_strokes {
             render_selections(
                 &self.preview.as_ref().unwrap().selections,
@@ -100,7 +121,7 @@ fn render_strokes(
             band_to_geometry(&Band::new(stroke.path().clone(), 6.0), 0.1)
         })
         .sum();
-    renderer_id.update_individual(
+    renderer_id.update_transparent_individual(
         scene_id,
         5498 + u16::from(world.local_machine_id()) * 10_000,
         stroke_base_geometry,
@@ -109,7 +130,7 @@ fn render_strokes(
         } else {
             [1.0, 0.5, 0.2]
         }),
-        true,
+        GHOST_OPACITY,
         world,
     );
     let stroke_geometry: Geometry = delta
@@ -118,14 +139,70 @@ fn render_strokes(
         .filter(|stroke| stroke.nodes().len() > 1)
         .map(LaneStroke::preview_geometry)
         .sum();
-    renderer_id.update_individual(
+    renderer_id.update_transparent_individual(
         scene_id,
         5499 + u16::from(world.local_machine_id()) * 10_000,
         stroke_geometry,
         Instance::with_color([0.6, 0.6, 0.6]),
-        true,
+        GHOST_OPACITY,
         world,
     );
+    let path_geometry: Geometry = delta
+        .new_paths
+        .iter()
+        .map(|path| band_to_geometry(&Band::new(path.clone(), 2.0), 0.1))
+        .sum();
+    renderer_id.update_transparent_individual(
+        scene_id,
+        5505 + u16::from(world.local_machine_id()) * 10_000,
+        path_geometry,
+        Instance::with_color([0.4, 0.7, 0.3]),
+        GHOST_OPACITY,
+        world,
+    );
+    for kind in &[StructureKind::Bridge, StructureKind::Tunnel] {
+        let structure_geometry: Geometry = delta
+            .new_structures
+            .iter()
+            .filter(|structure| structure.kind == *kind)
+            .map(|structure| {
+                let width = 2.0 * STRUCTURE_DISTANCE;
+                band_to_geometry(&Band::new(structure.path.clone(), width), 0.05)
+            })
+            .sum();
+        let (individual_id, color) = match *kind {
+            StructureKind::Bridge => (5521, [0.6, 0.55, 0.5]),
+            StructureKind::Tunnel => (5522, [0.3, 0.25, 0.2]),
+        };
+        renderer_id.update_transparent_individual(
+            scene_id,
+            individual_id + u16::from(world.local_machine_id()) * 10_000,
+            structure_geometry,
+            Instance::with_color(color),
+            GHOST_OPACITY,
+            world,
+        );
+    }
+    for kind in &[UtilityKind::Water, UtilityKind::Power] {
+        let conduit_geometry: Geometry = delta
+            .new_conduits
+            .iter()
+            .filter(|conduit| conduit.kind == *kind)
+            .map(|conduit| band_to_geometry(&Band::new(conduit.path.clone(), 1.0), 0.15))
+            .sum();
+        let individual_id = match *kind {
+            UtilityKind::Water => 5514,
+            UtilityKind::Power => 5515,
+        };
+        renderer_id.update_transparent_individual(
+            scene_id,
+            individual_id + u16::from(world.local_machine_id()) * 10_000,
+            conduit_geometry,
+            Instance::with_color(kind.color()),
+            GHOST_OPACITY,
+            world,
+        );
+    }
 }
 
 fn render_trimmed_strokes(
@@ -141,12 +218,12 @@ fn render_trimmed_strokes(
         .filter(|stroke| stroke.nodes().len() > 1)
         .map(LaneStroke::preview_geometry)
         .sum();
-    renderer_id.update_individual(
+    renderer_id.update_transparent_individual(
         scene_id,
         5500 + u16::from(world.local_machine_id()) * 10_000,
         trimmed_stroke_geometry,
         Instance::with_color([0.3, 0.3, 0.3]),
-        true,
+        GHOST_OPACITY,
         world,
     );
 }
@@ -181,12 +258,12 @@ fn render_intersections(
             i.strokes.iter().map(LaneStroke::preview_geometry).sum()
         })
         .sum();
-    renderer_id.update_individual(
+    renderer_id.update_transparent_individual(
         scene_id,
         5502 + u16::from(world.local_machine_id()) * 10_000,
         connecting_strokes_geometry,
         Instance::with_color([0.5, 0.5, 0.5]),
-        true,
+        GHOST_OPACITY,
         world,
     );
 }
@@ -205,12 +282,12 @@ fn render_transfer_lanes(
             band_to_geometry(&Band::new(lane_stroke.path().clone(), 0.3), 0.1)
         })
         .sum();
-    renderer_id.update_individual(
+    renderer_id.update_transparent_individual(
         scene_id,
         5503 + u16::from(world.local_machine_id()) * 10_000,
         transfer_strokes_geometry,
         Instance::with_color([1.0, 1.0, 1.0]),
-        true,
+        GHOST_OPACITY,
         world,
     );
 }
@@ -244,24 +321,110 @@ fn render_selections(
         true,
         world,
     );
-    let selection_geometry = selections
+    // selections change with every move of the mouse, so they are highlighted with decals
+    let selection_decals = selections
         .pairs()
         .filter_map(|(&selection_ref, &(start, end))| {
             let stroke = selection_ref.get_stroke(plan_delta, built_strokes);
             stroke.path().subsection(start, end).map(|subsection| {
-                band_to_geometry(&Band::new(subsection, 5.0), 0.1)
+                path_to_decals(&subsection, 5.0, [0.0, 0.0, 1.0], DecalPattern::Solid)
             })
         })
+        .flat_map(|decals| decals.into_iter().map(|decal| decal.with_opacity(0.7)))
+        .collect();
+    renderer_id.set_decals(
+        scene_id,
+        SELECTION_DECAL_LAYER,
+        u32::from(world.local_machine_id()),
+        selection_decals,
+        world,
+    );
+}
+
+/// The stroke under the mouse is drawn over the ghosts, in the same place
+fn render_hover(
+    hovered_path: Option<&CPath>,
+    renderer_id: RendererID,
+    scene_id: usize,
+    world: &mut World,
+) {
+    let hover_geometry = hovered_path
+        .map(|path| band_to_geometry(&Band::new(path.clone(), 6.0), 0.1))
+        .unwrap_or_else(|| Geometry::new(vec![], vec![]));
+    renderer_id.update_transparent_individual(
+        scene_id,
+        5523 + u16::from(world.local_machine_id()) * 10_000,
+        hover_geometry,
+        Instance::with_color([0.4, 0.8, 1.0]),
+        HOVER_OPACITY,
+        world,
+    );
+}
+
+/// Phases that aren't open yet are shown as faint outlines of their roads
+fn render_phases(
+    phases: &CVec<PlanPhase>,
+    renderer_id: RendererID,
+    scene_id: usize,
+    world: &mut World,
+) {
+    let phases_geometry: Geometry = phases
+        .iter()
+        .flat_map(|phase| phase.plan_delta.new_strokes.iter())
+        .filter(|stroke| stroke.nodes().len() > 1)
+        .map(|stroke| {
+            band_to_geometry(&Band::new(stroke.path().clone(), 6.0), 0.05)
+        })
         .sum();
-    renderer_id.update_individual(
+    renderer_id.update_transparent_individual(
         scene_id,
-        5504 + u16::from(world.local_machine_id()) * 10_000,
-        selection_geometry,
-        Instance::with_color([0.0, 0.0, 1.0]),
-        true,
+        5520 + u16::from(world.local_machine_id()) * 10_000,
+        phases_geometry,
+        Instance::with_color([0.6, 0.6, 0.9]),
+        PHASE_GHOST_OPACITY,
         world,
     );
 }
 
+const ISSUE_MARKER_SIZE: N = 1.5;
+
+fn render_issues(
+    issues: &CVec<ValidationIssue>,
+    renderer_id: RendererID,
+    scene_id: usize,
+    world: &mut World,
+) {
+    let markers = [
+        (Severity::Error, 5518, [1.0, 0.0, 0.0]),
+        (Severity::Warning, 5519, [1.0, 0.8, 0.0]),
+    ];
+    for &(severity, individual_id, color) in &markers {
+        let marker_geometry: Geometry = issues
+            .iter()
+            .filter(|issue| issue.severity() == severity)
+            .map(|issue| {
+                let (x, y) = (issue.position.x, issue.position.y);
+                Geometry::new(
+                    vec![
+                        Vertex { position: [x, y - ISSUE_MARKER_SIZE, 0.3] },
+                        Vertex { position: [x + ISSUE_MARKER_SIZE, y, 0.3] },
+                        Vertex { position: [x, y + ISSUE_MARKER_SIZE, 0.3] },
+                        Vertex { position: [x - ISSUE_MARKER_SIZE, y, 0.3] },
+                    ],
+                    vec![0, 1, 2, 2, 3, 0],
+                )
+            })
+            .sum();
+        renderer_id.update_individual(
+            scene_id,
+            individual_id + u16::from(world.local_machine_id()) * 10_000,
+            marker_geometry,
+            Instance::with_color(color),
+            true,
+            world,
+        );
+    }
+}
+
 mod kay_auto;
 pub use self::kay_auto::*;
@@ -0,0 +1,93 @@
+//! Fuzzing of plan materialization with random, often degenerate lane strokes:
+//! arbitrary multi-node strokes (which may self-intersect), nearly parallel pairs
+//! and nearly coincident nodes. Materialization must never panic and never produce
+//! NaN geometry. Run with more cases using `PROPTEST_CASES=100000 cargo test fuzz_`.
+
+use descartes::{N, P2, V2, Norm, FiniteCurve, WithUniqueOrthogonal};
+use compact::CVec;
+use proptest::prelude::*;
+use super::lane_stroke::{LaneStroke, LaneStrokeNode};
+use super::plan::Plan;
+
+fn node() -> BoxedStrategy<LaneStrokeNode> {
+    (-100.0f32..100.0, -100.0f32..100.0, -3.2f32..3.2)
+        .prop_map(|(x, y, angle)| {
+            LaneStrokeNode {
+                position: P2::new(x, y),
+                direction: V2::new(angle.cos(), angle.sin()),
+            }
+        })
+        .boxed()
+}
+
+fn arbitrary_stroke() -> BoxedStrategy<Option<LaneStroke>> {
+    prop::collection::vec(node(), 2..6)
+        .prop_map(|nodes| LaneStroke::new(nodes.into()).ok())
+        .boxed()
+}
+
+fn nearly_parallel_pair() -> BoxedStrategy<Vec<Option<LaneStroke>>> {
+    (node(), 20.0f32..150.0, 0.0f32..8.0, -0.05f32..0.05)
+        .prop_map(|(start, length, offset, angle)| {
+            let orthogonal = start.direction.orthogonal();
+            let other_direction = (start.direction + orthogonal * angle).normalize();
+            let other_start = start.position + orthogonal * offset;
+            vec![
+                straight(start.position, start.direction, length),
+                straight(other_start, other_direction, length),
+            ]
+        })
+        .boxed()
+}
+
+fn straight(start: P2, direction: V2, length: N) -> Option<LaneStroke> {
+    let nodes: CVec<_> = vec![
+        LaneStrokeNode { position: start, direction },
+        LaneStrokeNode { position: start + direction * length, direction },
+    ].into();
+    LaneStroke::new(nodes).ok()
+}
+
+fn strokes() -> BoxedStrategy<Vec<LaneStroke>> {
+    (
+        prop::collection::vec(arbitrary_stroke(), 0..5),
+        prop::collection::vec(nearly_parallel_pair(), 0..3),
+    ).prop_map(|(arbitrary, pairs)| {
+            arbitrary
+                .into_iter()
+                .chain(pairs.into_iter().flat_map(|pair| pair.into_iter()))
+                .filter_map(|stroke| stroke)
+                .collect()
+        })
+        .boxed()
+}
+
+fn assert_finite(stroke: &LaneStroke) {
+    for node in stroke.nodes().iter() {
+        assert!(node.position.x.is_finite() && node.position.y.is_finite());
+        assert!(node.direction.x.is_finite() && node.direction.y.is_finite());
+    }
+    let path = stroke.path();
+    assert!(path.length().is_finite());
+    for segment in path.segments().iter() {
+        assert!(segment.start().x.is_finite() && segment.start().y.is_finite());
+        assert!(segment.end().x.is_finite() && segment.end().y.is_finite());
+    }
+}
+
+proptest! {
+    #[test]
+    fn fuzz_materialization_never_panics_or_produces_nan(strokes in strokes()) {
+        let plan = Plan { strokes: strokes.into() };
+        let result = plan.get_result();
+
+        for stroke in result.trimmed_strokes.values().chain(result.transfer_strokes.values()) {
+            assert_finite(stroke);
+        }
+        for intersection in result.intersections.values() {
+            for stroke in intersection.strokes.iter() {
+                assert_finite(stroke);
+            }
+        }
+    }
+}
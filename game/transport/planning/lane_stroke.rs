@@ -5,6 +5,7 @@ use monet::Geometry;
 use stagemaster::geometry::{CPath, band_to_geometry};
 use super::super::construction::materialized_reality::{BuildableRef, MaterializedRealityID};
 use super::super::lane::{LaneID, TransferLaneID};
+use super::super::junctions::{JunctionID, MovementRelation};
 
 #[derive(Compact, Clone)]
 pub struct LaneStroke {
@@ -182,9 +183,10 @@ impl LaneStroke {
         &self,
         report_to: MaterializedRealityID,
         report_as: BuildableRef,
+        built_instantly: bool,
         world: &mut World,
     ) {
-        let lane = LaneID::spawn(self.path().clone(), false, CVec::new(), world);
+        let lane = LaneID::spawn(self.path().clone(), false, CVec::new(), built_instantly, world);
         lane.start_connecting_and_report(report_to, report_as, world);
     }
 
@@ -193,9 +195,14 @@ impl LaneStroke {
         report_to: MaterializedRealityID,
         report_as: BuildableRef,
         timings: CVec<bool>,
+        junction: JunctionID,
+        movement: usize,
+        relations: CVec<MovementRelation>,
+        built_instantly: bool,
         world: &mut World,
     ) {
-        let lane = LaneID::spawn(self.path().clone(), true, timings, world);
+        let lane = LaneID::spawn(self.path().clone(), true, timings, built_instantly, world);
+        lane.join_junction(junction, movement, relations, world);
         lane.start_connecting_and_report(report_to, report_as, world);
     }
 
@@ -203,9 +210,10 @@ impl LaneStroke {
         &self,
         report_to: MaterializedRealityID,
         report_as: BuildableRef,
+        built_instantly: bool,
         world: &mut World,
     ) {
-        let transfer_lane = TransferLaneID::spawn(self.path().clone(), world);
+        let transfer_lane = TransferLaneID::spawn(self.path().clone(), built_instantly, world);
         transfer_lane.start_connecting_and_report(report_to, report_as, world);
     }
 }
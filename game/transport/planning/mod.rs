@@ -2,18 +2,25 @@ use kay::ActorSystem;
 
 use stagemaster::UserInterfaceID;
 use monet::RendererID;
+use core::simulation::SimulationID;
 use super::construction::materialized_reality::MaterializedRealityID;
 
 pub mod plan;
 pub mod lane_stroke;
 pub mod plan_result_steps;
 pub mod current_plan;
+pub mod scenarios;
+pub mod validation;
+#[cfg(test)]
+mod fuzz_materialization;
 
 pub fn setup(
     system: &mut ActorSystem,
     user_interface: UserInterfaceID,
     renderer_id: RendererID,
+    simulation: SimulationID,
     materialized_reality: MaterializedRealityID,
 ) {
-    current_plan::setup(system, user_interface, renderer_id, materialized_reality);
+    current_plan::setup(system, user_interface, renderer_id, simulation, materialized_reality);
+    scenarios::setup_scenario_from_env(materialized_reality, &mut system.world());
 }
@@ -3,6 +3,7 @@ use compact::{CVec, CDict};
 use stagemaster::geometry::CPath;
 use itertools::Itertools;
 use super::lane_stroke::{LaneStroke, LaneStrokeNode};
+use super::super::utilities::Conduit;
 
 #[derive(Clone, Compact)]
 pub struct Plan {
@@ -18,10 +19,50 @@ impl Default for Plan {
 #[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
 pub struct LaneStrokeRef(pub usize);
 
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum StructureKind {
+    Bridge,
+    Tunnel,
+}
+
+impl StructureKind {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            StructureKind::Bridge => "bridge",
+            StructureKind::Tunnel => "tunnel",
+        }
+    }
+
+    /// The kind the planning tool switches to next, or none to go back to surface roads
+    pub fn next(&self) -> Option<StructureKind> {
+        match *self {
+            StructureKind::Bridge => Some(StructureKind::Tunnel),
+            StructureKind::Tunnel => None,
+        }
+    }
+}
+
+/// A stretch of road that doesn't follow the terrain, so it can cross water
+#[derive(Compact, Clone)]
+pub struct Structure {
+    pub kind: StructureKind,
+    /// Along the middle of the road
+    pub path: CPath,
+}
+
 #[derive(Compact, Clone)]
 pub struct PlanDelta {
     pub new_strokes: CVec<LaneStroke>,
     pub strokes_to_destroy: CDict<LaneStrokeRef, LaneStroke>,
+    /// Pedestrian-only paths, which don't get any car lanes
+    pub new_paths: CVec<CPath>,
+    /// Underground utility conduits along roads
+    pub new_conduits: CVec<Conduit>,
+    /// Bridges and tunnels that new roads are built on or in
+    pub new_structures: CVec<Structure>,
+    /// Whether roads have bike lanes, which gives the intersections built for this
+    /// delta the protected template (see `junctions::protected`)
+    pub bike_lanes: bool,
 }
 
 impl Default for PlanDelta {
@@ -29,6 +70,10 @@ impl Default for PlanDelta {
         PlanDelta {
             new_strokes: CVec::new(),
             strokes_to_destroy: CDict::new(),
+            new_paths: CVec::new(),
+            new_conduits: CVec::new(),
+            new_structures: CVec::new(),
+            bike_lanes: false,
         }
     }
 }
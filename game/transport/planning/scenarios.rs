@@ -0,0 +1,222 @@
+//! Procedurally generated road networks of configurable size, used for benchmarking
+//! plan materialization and for apples-to-apples performance comparisons of the
+//! tick loop (see `CITYBOUND_SCENARIO` in `setup_scenario_from_env` and the benchmarks
+//! in `benches/scenarios.rs`).
+//! Road networks can also come from packages, see `core::packages`.
+
+use descartes::{N, P2, V2, WithUniqueOrthogonal, Norm};
+use compact::CVec;
+use kay::World;
+use rand::{Rng, SeedableRng, XorShiftRng};
+use std::fs::File;
+use serde_json;
+use super::lane_stroke::{LaneStroke, LaneStrokeNode};
+use super::plan::{Plan, PlanDelta};
+use super::current_plan::CurrentPlanID;
+use super::super::construction::materialized_reality::MaterializedRealityID;
+
+const CENTER_LANE_DISTANCE: N = 6.0;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Scenario {
+    Grid { blocks: usize, block_size: N },
+    Radial { rings: usize, spokes: usize, ring_spacing: N },
+    Suburbs { streets: usize, extent: N, seed: u32 },
+}
+
+impl Scenario {
+    /// Parses `grid:<blocks>`, `radial:<rings>` or `suburbs:<streets>`
+    pub fn parse(description: &str) -> Option<Scenario> {
+        let mut parts = description.splitn(2, ':');
+        let kind = parts.next();
+        let size = match parts.next().and_then(|size| size.parse::<usize>().ok()) {
+            Some(size) => size,
+            None => return None,
+        };
+        match kind.unwrap_or("") {
+            "grid" => Some(Scenario::Grid { blocks: size, block_size: 100.0 }),
+            "radial" => Some(Scenario::Radial {
+                rings: size,
+                spokes: 8,
+                ring_spacing: 120.0,
+            }),
+            "suburbs" => Some(Scenario::Suburbs {
+                streets: size,
+                extent: 60.0 * size as N,
+                seed: 42,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn generate(&self) -> Plan {
+        let roads = match *self {
+            Scenario::Grid { blocks, block_size } => grid_roads(blocks, block_size),
+            Scenario::Radial { rings, spokes, ring_spacing } => {
+                radial_roads(rings, spokes, ring_spacing)
+            }
+            Scenario::Suburbs { streets, extent, seed } => suburb_roads(streets, extent, seed),
+        };
+
+        Plan {
+            strokes: roads
+                .iter()
+                .flat_map(|points| two_way_road(points))
+                .collect(),
+        }
+    }
+}
+
+fn grid_roads(blocks: usize, block_size: N) -> Vec<Vec<P2>> {
+    let extent = blocks as N * block_size;
+    let half = extent / 2.0;
+    (0..(blocks + 1))
+        .flat_map(|i| {
+            let offset = i as N * block_size - half;
+            vec![
+                vec![P2::new(offset, -half), P2::new(offset, half)],
+                vec![P2::new(-half, offset), P2::new(half, offset)],
+            ]
+        })
+        .collect()
+}
+
+fn radial_roads(rings: usize, spokes: usize, ring_spacing: N) -> Vec<Vec<P2>> {
+    let radius = rings as N * ring_spacing;
+    let angle = |i: usize| i as N / spokes as N * 2.0 * ::std::f32::consts::PI;
+
+    let mut roads: Vec<Vec<P2>> = (0..spokes)
+        .map(|i| {
+            let direction = V2::new(angle(i).cos(), angle(i).sin());
+            vec![
+                P2::new(0.0, 0.0) + direction * ring_spacing * 0.5,
+                P2::new(0.0, 0.0) + direction * radius,
+            ]
+        })
+        .collect();
+
+    for ring in 1..(rings + 1) {
+        let ring_radius = ring as N * ring_spacing;
+        // rings are approximated by straight pieces between spokes
+        roads.extend((0..spokes).map(|i| {
+            vec![
+                P2::new(0.0, 0.0) + V2::new(angle(i).cos(), angle(i).sin()) * ring_radius,
+                P2::new(0.0, 0.0) + V2::new(angle(i + 1).cos(), angle(i + 1).sin()) * ring_radius,
+            ]
+        }));
+    }
+
+    roads
+}
+
+fn suburb_roads(streets: usize, extent: N, seed: u32) -> Vec<Vec<P2>> {
+    let mut rng = XorShiftRng::from_seed([seed, 0x9E37_79B9, 0x85EB_CA6B, 0xC2B2_AE35]);
+    let half = extent / 2.0;
+    // a main road with winding residential streets branching off
+    let mut roads = vec![vec![P2::new(-half, 0.0), P2::new(half, 0.0)]];
+
+    for _ in 0..streets {
+        let start = P2::new(rng.gen_range(-half, half), 0.0);
+        let side = if rng.gen() { 1.0 } else { -1.0 };
+        let mut points = vec![start];
+        let mut position = start;
+        let mut direction = V2::new(0.0, side);
+        for _ in 0..rng.gen_range(2, 5) {
+            let turn: N = rng.gen_range(-0.5, 0.5);
+            direction = (direction + direction.orthogonal() * turn).normalize();
+            let length: N = rng.gen_range(40.0, 80.0);
+            position += direction * length;
+            points.push(position);
+        }
+        roads.push(points);
+    }
+
+    roads
+}
+
+pub fn two_way_road(points: &[P2]) -> Vec<LaneStroke> {
+    let nodes = |side: N| -> CVec<LaneStrokeNode> {
+        points
+            .iter()
+            .enumerate()
+            .map(|(i, point)| {
+                let direction = if i + 1 < points.len() {
+                    (points[i + 1] - *point).normalize()
+                } else {
+                    (*point - points[i - 1]).normalize()
+                };
+                let offset = direction.orthogonal() * (side * CENTER_LANE_DISTANCE / 2.0);
+                LaneStrokeNode { position: *point + offset, direction }
+            })
+            .collect()
+    };
+
+    let forward = nodes(1.0);
+    let backward: CVec<LaneStrokeNode> = nodes(-1.0)
+        .iter()
+        .rev()
+        .map(|node| LaneStrokeNode { direction: -node.direction, ..*node })
+        .collect();
+
+    vec![forward, backward]
+        .into_iter()
+        .filter_map(|nodes| LaneStroke::new(nodes).ok())
+        .collect()
+}
+
+/// A road network from a package: each road is a list of `[x, y]` points,
+/// built as a two-way road through them
+#[derive(Deserialize)]
+struct PackageScenario {
+    roads: Vec<Vec<(N, N)>>,
+}
+
+fn package_scenario_plan(name: &str) -> Option<Plan> {
+    let path = match ::core::packages::scenario_path(name) {
+        Some(path) => path,
+        None => return None,
+    };
+    let scenario: PackageScenario = match File::open(&path)
+        .map_err(|err| format!("{}", err))
+        .and_then(|file| serde_json::from_reader(file).map_err(|err| format!("{}", err))) {
+        Ok(scenario) => scenario,
+        Err(err) => {
+            println!("Error reading scenario {:?}: {}", path, err);
+            return None;
+        }
+    };
+
+    Some(Plan {
+        strokes: scenario
+            .roads
+            .iter()
+            .filter(|road| road.len() >= 2)
+            .flat_map(|road| {
+                let points = road.iter().map(|&(x, y)| P2::new(x, y)).collect::<Vec<_>>();
+                two_way_road(&points)
+            })
+            .collect(),
+    })
+}
+
+/// Loads the scenario given in the `CITYBOUND_SCENARIO` environment variable, if any,
+/// streaming it in before the simulation starts. Generated scenarios take precedence
+/// over scenarios from packages with the same name
+pub fn setup_scenario_from_env(materialized_reality: MaterializedRealityID, world: &mut World) {
+    if let Ok(description) = ::std::env::var("CITYBOUND_SCENARIO") {
+        let maybe_plan = Scenario::parse(&description)
+            .map(|scenario| scenario.generate())
+            .or_else(|| package_scenario_plan(&description));
+        match maybe_plan {
+            Some(plan) => {
+                println!("Loading scenario {}", description);
+                materialized_reality.stream(
+                    CurrentPlanID::local_first(world),
+                    PlanDelta { new_strokes: plan.strokes, ..PlanDelta::default() },
+                    world,
+                );
+            }
+            None => println!("Unknown scenario {}", description),
+        }
+    }
+}
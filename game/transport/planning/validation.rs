@@ -0,0 +1,337 @@
+//! Checks for plan geometry that can't be built (errors) or that makes for bad roads
+//! (warnings), done while planning so that problems can be shown where they are,
+//! instead of only surfacing when lanes are constructed.
+//!
+//! Plans with errors can't be materialized.
+use descartes::{N, P2, Norm, Path, FiniteCurve, Curve};
+use stagemaster::geometry::CPath;
+use super::plan::{PlanDelta, PlanResultDelta};
+use super::lane_stroke::MIN_NODE_DISTANCE;
+
+/// Curves tighter than this can't be driven at all
+const MIN_CURVE_RADIUS: N = 5.0;
+/// Curves tighter than this can only be driven slowly
+const COMFORTABLE_CURVE_RADIUS: N = 15.0;
+const MAX_GRADE: N = 0.15;
+const COMFORTABLE_GRADE: N = 0.08;
+/// Grades are measured between points this far apart along a stroke
+const GRADE_SAMPLE_DISTANCE: N = 10.0;
+/// Water crossings are looked for between points this far apart along a stroke
+const WATER_SAMPLE_DISTANCE: N = 5.0;
+/// Strokes this close to a planned bridge or tunnel are part of it
+pub const STRUCTURE_DISTANCE: N = 12.0;
+/// Intersections closer than this overlap
+const MIN_INTERSECTION_DISTANCE: N = 15.0;
+/// Intersections closer than this leave too little room for queues between them
+const COMFORTABLE_INTERSECTION_DISTANCE: N = 30.0;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum IssueKind {
+    SharpCurve,
+    SteepGrade,
+    IntersectionsTooClose,
+    ZeroLengthSegment,
+    /// Roads can only cross water on bridges or through tunnels
+    UnbridgedWaterCrossing,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct ValidationIssue {
+    pub kind: IssueKind,
+    pub position: P2,
+    /// What was measured: a radius, a grade, or a distance
+    pub value: N,
+}
+
+impl ValidationIssue {
+    pub fn severity(&self) -> Severity {
+        let is_error = match self.kind {
+            IssueKind::SharpCurve => self.value < MIN_CURVE_RADIUS,
+            IssueKind::SteepGrade => self.value > MAX_GRADE,
+            IssueKind::IntersectionsTooClose => self.value < MIN_INTERSECTION_DISTANCE,
+            IssueKind::ZeroLengthSegment => true,
+            IssueKind::UnbridgedWaterCrossing => true,
+        };
+        if is_error {
+            Severity::Error
+        } else {
+            Severity::Warning
+        }
+    }
+
+    pub fn explanation(&self) -> String {
+        match (self.kind, self.severity()) {
+            (IssueKind::SharpCurve, Severity::Error) => {
+                format!(
+                    "Curve radius of {:.1} m, roads need at least {:.0} m",
+                    self.value,
+                    MIN_CURVE_RADIUS
+                )
+            }
+            (IssueKind::SharpCurve, Severity::Warning) => {
+                format!(
+                    "Curve radius of {:.1} m, cars will have to slow down (below {:.0} m)",
+                    self.value,
+                    COMFORTABLE_CURVE_RADIUS
+                )
+            }
+            (IssueKind::SteepGrade, Severity::Error) => {
+                format!(
+                    "Grade of {:.0}%, at most {:.0}% can be built",
+                    self.value * 100.0,
+                    MAX_GRADE * 100.0
+                )
+            }
+            (IssueKind::SteepGrade, Severity::Warning) => {
+                format!(
+                    "Grade of {:.0}%, steeper than the recommended {:.0}%",
+                    self.value * 100.0,
+                    COMFORTABLE_GRADE * 100.0
+                )
+            }
+            (IssueKind::IntersectionsTooClose, Severity::Error) => {
+                format!(
+                    "Intersections {:.1} m apart would overlap, keep them {:.0} m apart",
+                    self.value,
+                    MIN_INTERSECTION_DISTANCE
+                )
+            }
+            (IssueKind::IntersectionsTooClose, Severity::Warning) => {
+                format!(
+                    "Intersections {:.1} m apart leave little room for queues \
+                     (recommended: {:.0} m)",
+                    self.value,
+                    COMFORTABLE_INTERSECTION_DISTANCE
+                )
+            }
+            (IssueKind::ZeroLengthSegment, _) => {
+                format!(
+                    "Points {:.2} m apart, a road segment needs to be at least {:.0} m long",
+                    self.value,
+                    MIN_NODE_DISTANCE
+                )
+            }
+            (IssueKind::UnbridgedWaterCrossing, _) => {
+                format!(
+                    "Crosses {:.0} m of water, plan a bridge or tunnel here (Shift+B)",
+                    self.value
+                )
+            }
+        }
+    }
+}
+
+/// Issues with the shape of the new strokes themselves: their curves and segment lengths
+pub fn check_strokes(delta: &PlanDelta) -> Vec<ValidationIssue> {
+    let mut issues = Vec::new();
+
+    for stroke in delta.new_strokes.iter() {
+        for window in stroke.nodes().windows(2) {
+            let distance = (window[1].position - window[0].position).norm();
+            if distance < MIN_NODE_DISTANCE {
+                issues.push(ValidationIssue {
+                    kind: IssueKind::ZeroLengthSegment,
+                    position: window[0].position,
+                    value: distance,
+                });
+            }
+        }
+
+        if stroke.nodes().len() < 2 || !stroke.well_formed() {
+            // no sensible path to look at
+            continue;
+        }
+
+        for segment in stroke.path().segments().iter() {
+            if !segment.is_linear() && segment.radius() < COMFORTABLE_CURVE_RADIUS {
+                issues.push(ValidationIssue {
+                    kind: IssueKind::SharpCurve,
+                    position: segment.along(segment.length() / 2.0),
+                    value: segment.radius(),
+                });
+            }
+        }
+    }
+
+    issues
+}
+
+/// Places along `path` that are steeper than recommended, given the terrain height
+/// at any point. The steepest place of each steep stretch is reported
+pub fn check_grades<F: Fn(P2) -> N>(path: &CPath, height_at: F) -> Vec<ValidationIssue> {
+    let mut issues = Vec::new();
+    let n_samples = (path.length() / GRADE_SAMPLE_DISTANCE).ceil().max(1.0) as usize;
+    let step = path.length() / n_samples as N;
+    let mut steepest: Option<ValidationIssue> = None;
+
+    for i in 0..n_samples {
+        let (from, to) = (path.along(i as N * step), path.along((i + 1) as N * step));
+        let grade = (height_at(to) - height_at(from)).abs() / step.max(MIN_NODE_DISTANCE);
+        if grade > COMFORTABLE_GRADE {
+            let steeper = steepest.map(|issue| grade > issue.value).unwrap_or(true);
+            if steeper {
+                steepest = Some(ValidationIssue {
+                    kind: IssueKind::SteepGrade,
+                    position: path.along((i as N + 0.5) * step),
+                    value: grade,
+                });
+            }
+        } else if let Some(issue) = steepest.take() {
+            issues.push(issue);
+        }
+    }
+    issues.extend(steepest);
+
+    issues
+}
+
+/// Whether `point` is on one of the planned bridges or tunnels
+pub fn on_structure(point: P2, structures: &[CPath]) -> bool {
+    structures.iter().any(|structure| structure.distance_to(point) < STRUCTURE_DISTANCE)
+}
+
+/// Stretches of `path` over water that aren't on any of `structures`,
+/// each reported at its middle, with its length
+pub fn check_water_crossings<F: Fn(P2) -> bool>(
+    path: &CPath,
+    is_water: F,
+    structures: &[CPath],
+) -> Vec<ValidationIssue> {
+    let mut issues = Vec::new();
+    let n_samples = (path.length() / WATER_SAMPLE_DISTANCE).ceil().max(1.0) as usize;
+    let step = path.length() / n_samples as N;
+    let mut stretch_start: Option<N> = None;
+
+    for i in 0..(n_samples + 1) {
+        let distance = i as N * step;
+        let point = path.along(distance);
+        let unbridged = is_water(point) && !on_structure(point, structures);
+        match (unbridged, stretch_start) {
+            (true, None) => stretch_start = Some(distance),
+            (false, Some(start)) => {
+                issues.push(ValidationIssue {
+                    kind: IssueKind::UnbridgedWaterCrossing,
+                    position: path.along((start + distance) / 2.0),
+                    value: distance - start,
+                });
+                stretch_start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(start) = stretch_start {
+        issues.push(ValidationIssue {
+            kind: IssueKind::UnbridgedWaterCrossing,
+            position: path.along((start + path.length()) / 2.0),
+            value: path.length() - start,
+        });
+    }
+
+    issues
+}
+
+fn center_of(shape: &CPath) -> Option<P2> {
+    let points = shape.segments().iter().map(|segment| segment.start).collect::<Vec<_>>();
+    if points.is_empty() {
+        None
+    } else {
+        let sum = points.iter().fold(P2::new(0.0, 0.0), |sum, point| {
+            sum + point.to_vector()
+        });
+        Some(P2::new(sum.x / points.len() as N, sum.y / points.len() as N))
+    }
+}
+
+/// Intersections that the plan creates or changes and that come too close to each other
+pub fn check_intersections(result_delta: &PlanResultDelta) -> Vec<ValidationIssue> {
+    let centers = result_delta
+        .intersections
+        .to_create
+        .values()
+        .filter_map(|intersection| center_of(&intersection.shape))
+        .collect::<Vec<_>>();
+
+    let mut issues = Vec::new();
+    for (i, center_a) in centers.iter().enumerate() {
+        for center_b in centers.iter().skip(i + 1) {
+            let distance = (*center_b - *center_a).norm();
+            if distance < COMFORTABLE_INTERSECTION_DISTANCE {
+                issues.push(ValidationIssue {
+                    kind: IssueKind::IntersectionsTooClose,
+                    position: P2::new(
+                        (center_a.x + center_b.x) / 2.0,
+                        (center_a.y + center_b.y) / 2.0,
+                    ),
+                    value: distance,
+                });
+            }
+        }
+    }
+    issues
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_strokes, check_grades, check_water_crossings, IssueKind, Severity};
+    use super::super::plan::PlanDelta;
+    use super::super::lane_stroke::{LaneStroke, LaneStrokeNode};
+    use descartes::{P2, V2, Path, Segment};
+    use stagemaster::geometry::CPath;
+
+    fn node(x: f32, y: f32, direction: V2) -> LaneStrokeNode {
+        LaneStrokeNode { position: P2::new(x, y), direction }
+    }
+
+    #[test]
+    fn tight_turns_and_stacked_points_are_found() {
+        let east = V2::new(1.0, 0.0);
+        let north = V2::new(0.0, 1.0);
+        let mut tight_turn =
+            LaneStroke::new(vec![node(0.0, 0.0, east), node(3.0, 3.0, north)].into()).unwrap();
+        let mut delta = PlanDelta::default();
+        delta.new_strokes.push(tight_turn.clone());
+
+        let issues = check_strokes(&delta);
+        assert!(issues.iter().any(|issue| {
+            issue.kind == IssueKind::SharpCurve && issue.severity() == Severity::Error
+        }));
+
+        tight_turn.nodes_mut().push(node(3.0, 3.2, north));
+        delta.new_strokes = vec![tight_turn].into();
+        let issues = check_strokes(&delta);
+        assert!(issues.iter().any(|issue| issue.kind == IssueKind::ZeroLengthSegment));
+    }
+
+    #[test]
+    fn only_steep_stretches_are_reported() {
+        let path = CPath::new(vec![Segment::line(P2::new(0.0, 0.0), P2::new(100.0, 0.0))]);
+        // flat, then climbing 20% between 40 and 60 m
+        let height_at = |point: P2| (point.x - 40.0).max(0.0).min(20.0) * 0.2;
+
+        let issues = check_grades(&path, height_at);
+        assert_eq!(issues.len(), 1);
+        assert!(issues[0].position.x > 40.0 && issues[0].position.x < 60.0);
+        assert_eq!(issues[0].severity(), Severity::Error);
+    }
+
+    #[test]
+    fn water_crossings_need_a_structure() {
+        let path = CPath::new(vec![Segment::line(P2::new(0.0, 0.0), P2::new(100.0, 0.0))]);
+        // a river between 30 and 50 m
+        let is_water = |point: P2| point.x > 30.0 && point.x < 50.0;
+
+        let issues = check_water_crossings(&path, &is_water, &[]);
+        assert_eq!(issues.len(), 1);
+        assert!(issues[0].position.x > 30.0 && issues[0].position.x < 50.0);
+        assert_eq!(issues[0].severity(), Severity::Error);
+
+        let bridge = CPath::new(vec![Segment::line(P2::new(25.0, 3.0), P2::new(55.0, 3.0))]);
+        assert!(check_water_crossings(&path, &is_water, &[bridge]).is_empty());
+    }
+}
@@ -0,0 +1,286 @@
+//! Meshes of everything that runs along a path, like road surfaces, sidewalks, rails and
+//! markings, built by sweeping a cross-section profile along the path.
+//!
+//! A profile is a polyline across the path, from its left to its right side, given as
+//! offsets from the path (positive to the right, like `Band` and `shift_orthogonally`)
+//! and heights. Each edge of the profile becomes a strip along the path, flat shaded
+//! with its own normal. Texture coordinates are in meters: `u` along the path, `v`
+//! across the profile, measured along its edges from its left end.
+//!
+//! Curved segments are cut into pieces short enough to look smooth, the same way
+//! `band_to_geometry` does it, so a flat profile covers exactly the same ground.
+use descartes::{N, P2, V2, FiniteCurve, WithUniqueOrthogonal};
+use monet::{Geometry, Vertex};
+use stagemaster::geometry::CPath;
+
+/// Curved segments get a new cross-section at least every time they turn this much
+const CURVE_MAX_ANGLE: N = 0.03;
+
+const CURB_HEIGHT: N = 0.15;
+const RAIL_WIDTH: N = 0.1;
+const RAIL_HEIGHT: N = 0.2;
+const RAIL_BED_HEIGHT: N = 0.05;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Profile {
+    /// `(offset, z)` from the left to the right end
+    pub points: Vec<(N, N)>,
+}
+
+impl Profile {
+    /// A flat strip of `width` at height `z`
+    pub fn flat(width: N, z: N) -> Profile {
+        Profile { points: vec![(-width / 2.0, z), (width / 2.0, z)] }
+    }
+
+    /// A slab of `width` rising `height` above `z`, with vertical sides
+    pub fn raised(width: N, z: N, height: N) -> Profile {
+        Profile {
+            points: vec![
+                (-width / 2.0, z),
+                (-width / 2.0, z + height),
+                (width / 2.0, z + height),
+                (width / 2.0, z),
+            ],
+        }
+    }
+
+    /// The asphalt of a road lane
+    pub fn road(width: N, z: N) -> Profile {
+        Profile::flat(width, z)
+    }
+
+    /// A sidewalk or footpath, raised by a curb
+    pub fn sidewalk(width: N, z: N) -> Profile {
+        Profile::raised(width, z, CURB_HEIGHT)
+    }
+
+    /// Paint of `width`, lying on whatever is at `z`
+    pub fn marking(width: N, z: N) -> Profile {
+        Profile::flat(width, z)
+    }
+
+    /// The bed and both rails of a track with the given `gauge`
+    pub fn rail_track(gauge: N, z: N) -> Vec<Profile> {
+        vec![
+            Profile::raised(gauge + 4.0 * RAIL_WIDTH, z, RAIL_BED_HEIGHT),
+            Profile::raised(RAIL_WIDTH, z + RAIL_BED_HEIGHT, RAIL_HEIGHT)
+                .shifted(-gauge / 2.0),
+            Profile::raised(RAIL_WIDTH, z + RAIL_BED_HEIGHT, RAIL_HEIGHT)
+                .shifted(gauge / 2.0),
+        ]
+    }
+
+    /// The same profile, moved `offset` to the right of the path
+    pub fn shifted(&self, offset: N) -> Profile {
+        Profile {
+            points: self.points
+                .iter()
+                .map(|&(point_offset, z)| (point_offset + offset, z))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MeshVertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub uv: [f32; 2],
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LaneMesh {
+    pub vertices: Vec<MeshVertex>,
+    pub indices: Vec<u16>,
+}
+
+/// A place along the path where the profile is laid out
+struct Station {
+    position: P2,
+    /// To the right of the path
+    orth_direction: V2,
+    distance: N,
+}
+
+fn stations(path: &CPath) -> Vec<Vec<Station>> {
+    let mut distance_before = 0.0;
+    path.segments()
+        .iter()
+        .map(|segment| {
+            let subdivisions = if segment.is_linear() {
+                1
+            } else {
+                (segment.length() / segment.radius() / CURVE_MAX_ANGLE)
+                    .max(1.0)
+                    .floor() as usize
+            };
+            let segment_stations = (0..(subdivisions + 1))
+                .map(|i| {
+                    let distance = segment.length() * i as N / subdivisions as N;
+                    Station {
+                        position: segment.along(distance),
+                        orth_direction: segment.direction_along(distance).orthogonal(),
+                        distance: distance_before + distance,
+                    }
+                })
+                .collect();
+            distance_before += segment.length();
+            segment_stations
+        })
+        .collect()
+}
+
+impl LaneMesh {
+    /// Sweeps `profile` along `path`
+    pub fn extrude(path: &CPath, profile: &Profile) -> LaneMesh {
+        let mut mesh = LaneMesh::default();
+        mesh.add(path, profile);
+        mesh
+    }
+
+    /// Adds a sweep of `profile` along `path` to the mesh
+    pub fn add(&mut self, path: &CPath, profile: &Profile) {
+        let edges = profile
+            .points
+            .iter()
+            .zip(profile.points.iter().skip(1))
+            .scan(0.0, |v_before, (&(offset_a, z_a), &(offset_b, z_b))| {
+                let length = ((offset_b - offset_a).powi(2) + (z_b - z_a).powi(2)).sqrt();
+                let v_start = *v_before;
+                *v_before += length;
+                Some(((offset_a, z_a), (offset_b, z_b), v_start, length))
+            })
+            .filter(|&(_, _, _, length)| length > 0.0)
+            .collect::<Vec<_>>();
+
+        for segment_stations in stations(path) {
+            for &((offset_a, z_a), (offset_b, z_b), v_start, length) in &edges {
+                // perpendicular to the edge, within the plane of the profile
+                let normal_across = -(z_b - z_a) / length;
+                let normal_up = (offset_b - offset_a) / length;
+                let first_new_vertex = self.vertices.len() as u16;
+
+                for station in &segment_stations {
+                    let normal = station.orth_direction * normal_across;
+                    let ends = [(offset_a, z_a, v_start), (offset_b, z_b, v_start + length)];
+                    for &(offset, z, v) in &ends {
+                        let position = station.position + station.orth_direction * offset;
+                        self.vertices.push(MeshVertex {
+                            position: [position.x, position.y, z],
+                            normal: [normal.x, normal.y, normal_up],
+                            uv: [station.distance, v],
+                        });
+                    }
+                }
+
+                for i in 0..(segment_stations.len() as u16 - 1) {
+                    let quad_start = first_new_vertex + 2 * i;
+                    self.indices.extend_from_slice(
+                        &[quad_start, quad_start + 1, quad_start + 2],
+                    );
+                    self.indices.extend_from_slice(
+                        &[quad_start + 1, quad_start + 3, quad_start + 2],
+                    );
+                }
+            }
+        }
+    }
+
+    /// Only the positions, which is all that monet draws for now
+    pub fn to_geometry(&self) -> Geometry {
+        Geometry::new(
+            self.vertices
+                .iter()
+                .map(|vertex| Vertex { position: vertex.position })
+                .collect(),
+            self.indices.clone(),
+        )
+    }
+}
+
+/// Shorthand for the geometry of a single sweep
+pub fn extrude_geometry(path: &CPath, profile: &Profile) -> Geometry {
+    LaneMesh::extrude(path, profile).to_geometry()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use descartes::Segment;
+
+    fn straight() -> CPath {
+        CPath::new(vec![Segment::line(P2::new(0.0, 0.0), P2::new(10.0, 0.0))])
+    }
+
+    #[test]
+    fn flat_profile_along_a_line() {
+        let mesh = LaneMesh::extrude(&straight(), &Profile::flat(2.0, 0.5));
+        let up = [0.0, 0.0, 1.0];
+        assert_eq!(
+            mesh.vertices,
+            vec![
+                MeshVertex { position: [0.0, 1.0, 0.5], normal: up, uv: [0.0, 0.0] },
+                MeshVertex { position: [0.0, -1.0, 0.5], normal: up, uv: [0.0, 2.0] },
+                MeshVertex { position: [10.0, 1.0, 0.5], normal: up, uv: [10.0, 0.0] },
+                MeshVertex { position: [10.0, -1.0, 0.5], normal: up, uv: [10.0, 2.0] },
+            ]
+        );
+        assert_eq!(mesh.indices, vec![0, 1, 2, 1, 3, 2]);
+    }
+
+    #[test]
+    fn raised_profile_along_a_line() {
+        let mesh = LaneMesh::extrude(&straight(), &Profile::raised(2.0, 0.0, 1.0));
+        let positions = mesh.vertices
+            .iter()
+            .map(|vertex| vertex.position)
+            .collect::<Vec<_>>();
+        let normals = mesh.vertices
+            .iter()
+            .map(|vertex| vertex.normal)
+            .collect::<Vec<_>>();
+        let vs = mesh.vertices.iter().map(|vertex| vertex.uv[1]).collect::<Vec<_>>();
+        assert_eq!(
+            positions,
+            vec![
+                [0.0, 1.0, 0.0], [0.0, 1.0, 1.0], [10.0, 1.0, 0.0], [10.0, 1.0, 1.0],
+                [0.0, 1.0, 1.0], [0.0, -1.0, 1.0], [10.0, 1.0, 1.0], [10.0, -1.0, 1.0],
+                [0.0, -1.0, 1.0], [0.0, -1.0, 0.0], [10.0, -1.0, 1.0], [10.0, -1.0, 0.0],
+            ]
+        );
+        let (left, up, right) = ([0.0, 1.0, 0.0], [0.0, 0.0, 1.0], [0.0, -1.0, 0.0]);
+        assert_eq!(
+            normals,
+            vec![left, left, left, left, up, up, up, up, right, right, right, right]
+        );
+        assert_eq!(
+            vs,
+            vec![0.0, 1.0, 0.0, 1.0, 1.0, 3.0, 1.0, 3.0, 3.0, 4.0, 3.0, 4.0]
+        );
+        assert_eq!(mesh.indices.len(), 3 * 6);
+    }
+
+    #[test]
+    fn flat_profile_covers_the_same_ground_as_a_band() {
+        use descartes::Band;
+        use stagemaster::geometry::band_to_geometry;
+        let curve = CPath::new(vec![
+            Segment::arc_with_direction(
+                P2::new(0.0, 0.0),
+                V2::new(1.0, 0.0),
+                P2::new(10.0, 10.0),
+            ),
+        ]);
+        let mesh = extrude_geometry(&curve, &Profile::flat(3.0, 0.0));
+        let band = band_to_geometry(&Band::new(curve, 3.0), 0.0);
+        assert_eq!(mesh.vertices.len(), band.vertices.len());
+        for vertex in &mesh.vertices {
+            assert!(band.vertices.iter().any(|band_vertex| {
+                (0..3).all(|axis| {
+                    (vertex.position[axis] - band_vertex.position[axis]).abs() < 0.001
+                })
+            }));
+        }
+    }
+}
@@ -0,0 +1,212 @@
+//! Geometry of the markings painted on lanes. Every lane has solid lines along both of
+//! its edges, which become dashed dividers wherever a transfer lane runs between two
+//! lanes, by drawing asphalt-colored gaps over them.
+//!
+//! Lanes across intersections are marked where they start and end instead: a stop line
+//! where cars wait for them (dashed, as a yield line, if they aren't signalized),
+//! crosswalk stripes just inside the intersection at both ends and an arrow for their
+//! turn on the approach, unless they are closed. Since all lanes starting from the same
+//! approach lane draw their arrow at the same spot, the arrows there add up to the
+//! movements allowed from it. Stop lines and crosswalks are decals, so a lane getting or
+//! losing its signal only replaces those.
+use compact::CVec;
+use descartes::{N, P2, V2, Dot, FiniteCurve, Segment};
+use monet::{Geometry, Vertex, Decal, DecalPattern};
+use stagemaster::geometry::{CPath, dash_path};
+use super::lane_mesh::{Profile, extrude_geometry};
+
+const MARKING_Z: N = 0.1;
+pub const LANE_WIDTH: N = 5.0;
+const EDGE_LINE_WIDTH: N = 0.6;
+const MARKING_COLOR: [f32; 3] = [1.0, 1.0, 1.0];
+const STOP_LINE_WIDTH: N = 0.5;
+/// Crosswalks start this far into the intersection and are this long
+const CROSSWALK_GAP: N = 0.5;
+const CROSSWALK_LENGTH: N = 3.0;
+const CROSSWALK_STRIPE_WIDTH: N = 0.5;
+const CROSSWALK_STRIPE_SPACING: N = 1.0;
+/// From the stop line to the tip of the turn arrow
+const ARROW_DISTANCE: N = 6.0;
+const ARROW_STEM_LENGTH: N = 3.0;
+const ARROW_STEM_WIDTH: N = 0.3;
+const ARROW_HEAD_LENGTH: N = 1.2;
+const ARROW_HEAD_WIDTH: N = 1.0;
+/// Sideways part of turning arrows
+const ARROW_TURN_LENGTH: N = 1.0;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Turn {
+    Left,
+    Straight,
+    Right,
+    UTurn,
+}
+
+fn left_of(direction: V2) -> V2 {
+    V2::new(-direction.y, direction.x)
+}
+
+pub fn turn_of(path: &CPath) -> Turn {
+    let start_direction = path.start_direction();
+    let end_direction = path.end_direction();
+    let forward = start_direction.dot(&end_direction);
+    let leftward = left_of(start_direction).dot(&end_direction);
+    if forward < -0.9 {
+        Turn::UTurn
+    } else if forward > 0.7 {
+        Turn::Straight
+    } else if leftward > 0.0 {
+        Turn::Left
+    } else {
+        Turn::Right
+    }
+}
+
+fn line(start: P2, end: P2) -> CPath {
+    CPath::new(vec![Segment::line(start, end)])
+}
+
+fn triangle(a: P2, b: P2, c: P2) -> Geometry {
+    Geometry::new(
+        vec![
+            Vertex { position: [a.x, a.y, MARKING_Z] },
+            Vertex { position: [b.x, b.y, MARKING_Z] },
+            Vertex { position: [c.x, c.y, MARKING_Z] },
+        ],
+        vec![0, 1, 2],
+    )
+}
+
+/// Solid lines along both edges of a lane
+pub fn edge_lines(path: &CPath) -> Geometry {
+    let edge_line = |offset: N| {
+        path.shift_orthogonally(offset)
+            .map(|edge| extrude_geometry(&edge, &Profile::marking(EDGE_LINE_WIDTH, MARKING_Z)))
+            .unwrap_or_else(|| Geometry::new(vec![], vec![]))
+    };
+    edge_line(LANE_WIDTH / 2.0) + edge_line(-LANE_WIDTH / 2.0)
+}
+
+/// Gaps in the edge lines along a transfer lane, making them dashed dividers
+pub fn divider_gaps(path: &CPath) -> Geometry {
+    dash_path(path, 2.0, 4.0)
+        .into_iter()
+        .map(|dash| extrude_geometry(&dash, &Profile::marking(0.8, 0.2)))
+        .sum()
+}
+
+/// Across a lane at `position`, solid or dashed
+pub fn stop_line(position: P2, direction: V2, dashed: bool) -> Decal {
+    let across = left_of(direction) * LANE_WIDTH / 2.0;
+    let solid =
+        Decal::between(position - across, position + across, STOP_LINE_WIDTH, MARKING_COLOR);
+    if dashed {
+        solid.with_pattern(DecalPattern::Stripes { period: 1.2, fill: 0.5 })
+    } else {
+        solid
+    }
+}
+
+/// Stripes along `direction`, across the whole lane, starting at `position`
+pub fn crosswalk(position: P2, direction: V2) -> Decal {
+    let middle = position + direction * CROSSWALK_LENGTH / 2.0;
+    let across = left_of(direction) * LANE_WIDTH / 2.0;
+    Decal::between(middle - across, middle + across, CROSSWALK_LENGTH, MARKING_COLOR)
+        .with_pattern(DecalPattern::Stripes {
+            period: CROSSWALK_STRIPE_SPACING,
+            fill: CROSSWALK_STRIPE_WIDTH / CROSSWALK_STRIPE_SPACING,
+        })
+}
+
+/// An arrow for `turn` with its tip at `tip`, for a lane going in `direction`.
+/// Arrows for turns bend sideways at their tip
+pub fn turn_arrow(tip: P2, direction: V2, turn: Turn) -> Geometry {
+    let head_direction = match turn {
+        Turn::Left => left_of(direction),
+        Turn::Right => -left_of(direction),
+        Turn::Straight | Turn::UTurn => direction,
+    };
+    let stem_end = tip - direction * ARROW_HEAD_LENGTH;
+    let stem_start = stem_end - direction * ARROW_STEM_LENGTH;
+    let stem = Profile::marking(ARROW_STEM_WIDTH, MARKING_Z);
+    let mut arrow = extrude_geometry(&line(stem_start, stem_end), &stem);
+
+    let head_base = if head_direction == direction {
+        stem_end
+    } else {
+        let turn_end = stem_end + head_direction * ARROW_TURN_LENGTH;
+        arrow += extrude_geometry(&line(stem_end, turn_end), &stem);
+        turn_end
+    };
+    let head_side = left_of(head_direction) * ARROW_HEAD_WIDTH / 2.0;
+    arrow +
+        triangle(
+            head_base + head_side,
+            head_base + head_direction * ARROW_HEAD_LENGTH,
+            head_base - head_side,
+        )
+}
+
+/// Stop line and crosswalks of a lane across an intersection
+pub fn intersection_lane_decals(path: &CPath, signalized: bool) -> CVec<Decal> {
+    let start = path.start();
+    let start_direction = path.start_direction();
+    let end_direction = path.end_direction();
+
+    vec![
+        stop_line(start, start_direction, !signalized),
+        crosswalk(start + start_direction * CROSSWALK_GAP, start_direction),
+        crosswalk(
+            path.end() - end_direction * (CROSSWALK_GAP + CROSSWALK_LENGTH),
+            end_direction,
+        ),
+    ].into()
+}
+
+/// The turn arrow of a lane across an intersection, on its approach
+pub fn intersection_lane_arrow(path: &CPath, open: bool) -> Geometry {
+    let turn = turn_of(path);
+    if open && turn != Turn::UTurn {
+        let start_direction = path.start_direction();
+        turn_arrow(path.start() - start_direction * ARROW_DISTANCE, start_direction, turn)
+    } else {
+        Geometry::new(vec![], vec![])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn arc_like(end_direction: V2) -> CPath {
+        let start = P2::new(0.0, 0.0);
+        let corner = P2::new(10.0, 0.0);
+        CPath::new(vec![
+            Segment::line(start, corner),
+            Segment::line(corner, corner + end_direction * 10.0),
+        ])
+    }
+
+    #[test]
+    fn turns_follow_the_direction_at_the_end() {
+        assert_eq!(turn_of(&arc_like(V2::new(1.0, 0.0))), Turn::Straight);
+        assert_eq!(turn_of(&arc_like(V2::new(0.0, 1.0))), Turn::Left);
+        assert_eq!(turn_of(&arc_like(V2::new(0.0, -1.0))), Turn::Right);
+    }
+
+    #[test]
+    fn closed_lanes_get_no_arrow() {
+        let path = arc_like(V2::new(0.0, 1.0));
+        assert!(!intersection_lane_arrow(&path, true).vertices.is_empty());
+        assert!(intersection_lane_arrow(&path, false).vertices.is_empty());
+    }
+
+    #[test]
+    fn unsignalized_lanes_get_a_yield_line() {
+        let path = arc_like(V2::new(1.0, 0.0));
+        let signalized = intersection_lane_decals(&path, true);
+        let unsignalized = intersection_lane_decals(&path, false);
+        assert_eq!(signalized[0].pattern, DecalPattern::Solid);
+        assert!(unsignalized[0].pattern != DecalPattern::Solid);
+    }
+}
@@ -1,10 +1,23 @@
 use descartes::{Band, FiniteCurve, WithUniqueOrthogonal, Norm, Path, Dot, RoughlyComparable};
 use compact::CVec;
 use kay::{ActorSystem, World};
-use monet::{Instance, Vertex, Geometry, RendererID};
+use monet::{Instance, Vertex, Geometry, RendererID, Decal};
 use stagemaster::geometry::{band_to_geometry, dash_path};
 use super::lane::{Lane, LaneID, TransferLane, TransferLaneID};
+use super::restrictions::{LaneRestriction, VehicleClass};
+use super::street_furniture::StreetFurnitureID;
+use super::microtraffic::{LaneCar, SegmentTraffic, TRAFFIC_SEGMENT_LENGTH};
+use core::simulation::microtraffic_time_since_tick;
+use core::units::{Seconds, MetersPerSecond};
 use itertools::Itertools;
+use stagemaster::combo::Combo2;
+use stagemaster::combo::Button::{LControl, V};
+use stagemaster::actions::{register_action, ActionId, ActionListener, ActionListenerID,
+                           ActionPhase, MSG_ActionListener_on_action};
+
+pub mod markings;
+pub mod lane_mesh;
+use self::lane_mesh::{Profile, extrude_geometry};
 
 #[path = "./resources/car.rs"]
 mod car;
@@ -12,12 +25,21 @@ mod car;
 #[path = "./resources/traffic_light.rs"]
 mod traffic_light;
 
+#[path = "./resources/construction_site.rs"]
+mod construction_site;
+
 use monet::{Renderable, RenderableID, GrouperID, GrouperIndividual, GrouperIndividualID,
             MSG_GrouperIndividual_render_to_grouper, MSG_Renderable_setup_in_scene};
 
 const LANE_ASPHALT_THING_ID: u16 = 2000;
 const LANE_MARKER_THING_ID: u16 = 2200;
 const LANE_MARKER_GAPS_THING_ID: u16 = 2400;
+const LANE_RESTRICTION_THING_ID: u16 = 2600;
+const LANE_MARKING_THING_ID: u16 = 3000;
+const SHOCKWAVE_SEGMENT_BATCH_ID: u16 = 8010;
+pub const MARKING_DECAL_LAYER: u16 = 100;
+pub const DIRT_DECAL_LAYER: u16 = 200;
+pub const CONSTRUCTION_DECAL_LAYER: u16 = 300;
 
 impl Renderable for Lane {
     fn setup_in_scene(&mut self, _renderer_id: RendererID, _scene_id: usize, _: &mut World) {}
@@ -29,20 +51,26 @@ impl Renderable for Lane {
         frame: usize,
         world: &mut World,
     ) {
-        let mut cars_iter = self.microtraffic.cars.iter();
+        let since_tick = microtraffic_time_since_tick();
+        let mut cars_iter = self.microtraffic.cars.iter().map(|car| {
+            (car, extrapolated_position(car, since_tick))
+        });
         let mut current_offset = 0.0;
         let mut car_instances = CVec::with_capacity(self.microtraffic.cars.len());
+        let mut light_instances = CarLightInstances::default();
         for segment in self.construction.path.segments().iter() {
-            for car in cars_iter.take_while_ref(|car| {
-                *car.position - current_offset < segment.length()
+            for (car, position) in cars_iter.take_while_ref(|&(_, position)| {
+                position - current_offset < segment.length()
             })
             {
-                let position2d = segment.along(*car.position - current_offset);
-                let direction = segment.direction_along(*car.position - current_offset);
+                let position2d = segment.along(position - current_offset);
+                let direction = segment.direction_along(position - current_offset);
                 car_instances.push(Instance {
                     instance_position: [position2d.x, position2d.y, 0.0],
                     instance_direction: [direction.x, direction.y],
-                    instance_color: if DEBUG_VIEW_LANDMARKS {
+                    instance_color: if car.vehicle == VehicleClass::Emergency {
+                        EMERGENCY_VEHICLE_COLOR
+                    } else if DEBUG_VIEW_LANDMARKS {
                         ::core::colors::RANDOM_COLORS[car.destination
                                                           .landmark
                                                           ._raw_id
@@ -53,7 +81,13 @@ impl Renderable for Lane {
                         ::core::colors::RANDOM_COLORS[car.trip._raw_id.instance_id as usize %
                                                           ::core::colors::RANDOM_COLORS.len()]
                     },
-                })
+                });
+                add_car_lights(
+                    car_instances.last().expect("just pushed"),
+                    car.acceleration,
+                    self.microtraffic.headlights,
+                    &mut light_instances,
+                );
             }
             current_offset += segment.length;
         }
@@ -80,6 +114,24 @@ impl Renderable for Lane {
         if !car_instances.is_empty() {
             renderer_id.add_several_instances(scene_id, 8000, frame, car_instances, world);
         }
+        light_instances.render(renderer_id, scene_id, frame, world);
+
+        if !self.construction.is_finished() {
+            let position = self.construction.path.along(self.construction.progress);
+            let direction = self.construction.path.direction_along(self.construction.progress);
+            let barrier = Instance {
+                instance_position: [position.x, position.y, 0.0],
+                instance_direction: [direction.x, direction.y],
+                instance_color: if self.construction.build_rate > 0.0 {
+                    [1.0, 0.5, 0.0]
+                } else {
+                    // still waiting for a crew
+                    [0.6, 0.6, 0.6]
+                },
+            };
+            renderer_id.add_instance(scene_id, 8009, frame, barrier, world);
+        }
+
         // no traffic light for u-turn
         if self.connectivity.on_intersection &&
             !self.construction.path.end_direction().is_roughly_within(
@@ -118,6 +170,31 @@ impl Renderable for Lane {
             };
             renderer_id.add_instance(scene_id, 8001, frame, instance, world);
 
+            let pole = Instance {
+                instance_position: [position.x, position.y, 0.0],
+                instance_direction: [direction.x, direction.y],
+                instance_color: [0.1, 0.1, 0.1],
+            };
+            renderer_id.add_instance(scene_id, 8006, frame, pole, world);
+
+            let stop_line_position = self.construction.path.start();
+            let stop_line = Instance {
+                instance_position: [stop_line_position.x, stop_line_position.y, 0.0],
+                instance_direction: [direction.x, direction.y],
+                instance_color: if !self.microtraffic.green {
+                    if self.microtraffic.yellow_to_green {
+                        [1.0, 0.8, 0.0]
+                    } else {
+                        [1.0, 0.0, 0.0]
+                    }
+                } else if self.microtraffic.yellow_to_red {
+                    [1.0, 0.8, 0.0]
+                } else {
+                    [0.0, 1.0, 0.2]
+                },
+            };
+            renderer_id.add_instance(scene_id, 8005, frame, stop_line, world);
+
             if self.microtraffic.yellow_to_red && self.microtraffic.green {
                 let instance = Instance {
                     instance_position: [position.x, position.y, 6.7],
@@ -245,6 +322,55 @@ impl Renderable for Lane {
     }
 }
 
+impl Lane {
+    /// Colors each stretch of the lane by how fast traffic flows in it
+    pub fn render_shockwaves(
+        &mut self,
+        renderer_id: RendererID,
+        scene_id: usize,
+        frame: usize,
+        world: &mut World,
+    ) {
+        let path = &self.construction.path;
+        let segment_instances: CVec<Instance> = self.microtraffic
+            .segment_traffic(self.construction.length)
+            .iter()
+            .enumerate()
+            .map(|(i, traffic)| {
+                let center = ((i as f32 + 0.5) * TRAFFIC_SEGMENT_LENGTH)
+                    .min(self.construction.length);
+                let position = path.along(center);
+                let direction = path.direction_along(center);
+                Instance {
+                    instance_position: [position.x, position.y, 0.0],
+                    instance_direction: [direction.x, direction.y],
+                    instance_color: shockwave_color(traffic),
+                }
+            })
+            .collect();
+
+        renderer_id.add_several_instances(
+            scene_id,
+            SHOCKWAVE_SEGMENT_BATCH_ID,
+            frame,
+            segment_instances,
+            world,
+        );
+    }
+}
+
+/// Green for free flow, through yellow to red for standing traffic.
+/// Empty stretches are only faintly green
+fn shockwave_color(traffic: &SegmentTraffic) -> [f32; 3] {
+    if traffic.density == 0.0 {
+        [0.6, 0.8, 0.6]
+    } else if traffic.speed_ratio > 0.5 {
+        [2.0 * (1.0 - traffic.speed_ratio), 0.8, 0.0]
+    } else {
+        [1.0, 1.6 * traffic.speed_ratio, 0.0]
+    }
+}
+
 impl GrouperIndividual for Lane {
     fn render_to_grouper(
         &mut self,
@@ -252,54 +378,62 @@ impl GrouperIndividual for Lane {
         base_individual_id: u16,
         world: &mut World,
     ) {
-        let maybe_path = if self.construction.progress - CONSTRUCTION_ANIMATION_DELAY <
-            self.construction.length
-        {
-            self.construction.path.subsection(
-                0.0,
-                (self.construction.progress - CONSTRUCTION_ANIMATION_DELAY)
-                    .max(0.0),
-            )
-        } else {
+        // lanes are rendered as far as they are built
+        let maybe_path = if self.construction.is_finished() {
             Some(self.construction.path.clone())
+        } else {
+            self.construction.path.subsection(0.0, self.construction.progress)
         };
         if base_individual_id == LANE_ASPHALT_THING_ID {
+            let width = self.road_class
+                .class()
+                .map(|class| class.rendering_width())
+                .unwrap_or(6.0);
             grouper.update(
                 self.id.into(),
                 maybe_path
                     .map(|path| {
-                        band_to_geometry(
-                            &Band::new(path, 6.0),
-                            if self.connectivity.on_intersection {
-                                0.2
-                            } else {
-                                0.0
-                            },
-                        )
+                        let z = if self.connectivity.on_intersection {
+                            0.2
+                        } else {
+                            0.0
+                        };
+                        extrude_geometry(&path, &Profile::road(width, z))
                     })
                     .unwrap_or_else(|| Geometry::new(vec![], vec![])),
                 world,
             );
-            if self.construction.progress - CONSTRUCTION_ANIMATION_DELAY >
-                self.construction.length
-            {
+            if self.construction.is_finished() {
                 grouper.freeze(self.id.into(), world);
             }
+        } else if base_individual_id == LANE_RESTRICTION_THING_ID {
+            // hatching across the lane, denser for stricter restrictions
+            let gap_length = match self.restriction {
+                LaneRestriction::Closed => 0.6,
+                LaneRestriction::BusOnly => 1.5,
+                LaneRestriction::HighOccupancy => 3.0,
+                LaneRestriction::NoTrucks | LaneRestriction::General => 6.0,
+            };
+            let hatching = dash_path(&self.construction.path, 0.4, gap_length)
+                .into_iter()
+                .map(|dash| extrude_geometry(&dash, &Profile::marking(4.0, 0.05)))
+                .sum();
+            grouper.add_frozen(self.id.into(), hatching, world);
+        } else if base_individual_id == LANE_MARKING_THING_ID {
+            let arrow = markings::intersection_lane_arrow(
+                &self.construction.path,
+                self.restriction != LaneRestriction::Closed,
+            );
+            grouper.add_frozen(self.id.into(), arrow, world);
         } else {
-            let left_marker = maybe_path
-                .clone()
-                .and_then(|path| path.shift_orthogonally(2.5))
-                .map(|path| band_to_geometry(&Band::new(path, 0.6), 0.1))
-                .unwrap_or_else(|| Geometry::new(vec![], vec![]));
-
-            let right_marker = maybe_path
-                .and_then(|path| path.shift_orthogonally(-2.5))
-                .map(|path| band_to_geometry(&Band::new(path, 0.6), 0.1))
-                .unwrap_or_else(|| Geometry::new(vec![], vec![]));
-            grouper.update(self.id.into(), left_marker + right_marker, world);
-            if self.construction.progress - CONSTRUCTION_ANIMATION_DELAY >
-                self.construction.length
-            {
+            grouper.update(
+                self.id.into(),
+                maybe_path
+                    .map(|path| markings::edge_lines(&path))
+                    .unwrap_or_else(|| Geometry::new(vec![], vec![])),
+                world,
+            );
+            if self.construction.is_finished() {
                 grouper.freeze(self.id.into(), world);
             }
         }
@@ -316,24 +450,32 @@ impl Renderable for TransferLane {
         frame: usize,
         world: &mut World,
     ) {
-        let mut cars_iter = self.microtraffic.cars.iter();
+        let since_tick = microtraffic_time_since_tick();
+        let mut cars_iter = self.microtraffic.cars.iter().map(|car| {
+            (car, extrapolated_position(car, since_tick))
+        });
         let mut current_offset = 0.0;
         let mut car_instances = CVec::with_capacity(self.microtraffic.cars.len());
+        let mut light_instances = CarLightInstances::default();
         for segment in self.construction.path.segments().iter() {
-            for car in cars_iter.take_while_ref(|car| {
-                *car.position - current_offset < segment.length()
+            for (car, position) in cars_iter.take_while_ref(|&(_, position)| {
+                position - current_offset < segment.length()
             })
             {
-                let position2d = segment.along(*car.position - current_offset);
-                let direction = segment.direction_along(*car.position - current_offset);
+                let position2d = segment.along(position - current_offset);
+                let direction = segment.direction_along(position - current_offset);
                 let rotated_direction =
                     (direction + 0.3 * car.transfer_velocity * direction.orthogonal()).normalize();
+                let transfer_position = car.transfer_position +
+                    car.transfer_velocity * since_tick.0;
                 let shifted_position2d = position2d +
-                    2.5 * direction.orthogonal() * car.transfer_position;
+                    2.5 * direction.orthogonal() * transfer_position;
                 car_instances.push(Instance {
                     instance_position: [shifted_position2d.x, shifted_position2d.y, 0.0],
                     instance_direction: [rotated_direction.x, rotated_direction.y],
-                    instance_color: if DEBUG_VIEW_LANDMARKS {
+                    instance_color: if car.vehicle == VehicleClass::Emergency {
+                        EMERGENCY_VEHICLE_COLOR
+                    } else if DEBUG_VIEW_LANDMARKS {
                         ::core::colors::RANDOM_COLORS[car.destination
                                                           .landmark
                                                           ._raw_id
@@ -344,7 +486,13 @@ impl Renderable for TransferLane {
                         ::core::colors::RANDOM_COLORS[car.trip._raw_id.instance_id as usize %
                                                           ::core::colors::RANDOM_COLORS.len()]
                     },
-                })
+                });
+                add_car_lights(
+                    car_instances.last().expect("just pushed"),
+                    car.acceleration,
+                    self.microtraffic.headlights,
+                    &mut light_instances,
+                );
             }
             current_offset += segment.length;
         }
@@ -398,6 +546,7 @@ impl Renderable for TransferLane {
         if !car_instances.is_empty() {
             renderer_id.add_several_instances(scene_id, 8000, frame, car_instances, world);
         }
+        light_instances.render(renderer_id, scene_id, frame, world);
 
         if self.connectivity.left.is_none() {
             let position = self.construction.path.along(self.construction.length / 2.0) +
@@ -445,33 +594,21 @@ impl GrouperIndividual for TransferLane {
         _base_individual_id: u16,
         world: &mut World,
     ) {
-        let maybe_path = if self.construction.progress - 2.0 * CONSTRUCTION_ANIMATION_DELAY <
-            self.construction.length
-        {
-            self.construction.path.subsection(
-                0.0,
-                (self.construction.progress - 2.0 * CONSTRUCTION_ANIMATION_DELAY)
-                    .max(0.0),
-            )
-        } else {
+        // like lanes, transfer lanes are rendered as far as they are built
+        let maybe_path = if self.construction.is_finished() {
             Some(self.construction.path.clone())
+        } else {
+            self.construction.path.subsection(0.0, self.construction.progress)
         };
 
         grouper.update(
             self.id.into(),
             maybe_path
-                .map(|path| {
-                    dash_path(&path, 2.0, 4.0)
-                        .into_iter()
-                        .map(|dash| band_to_geometry(&Band::new(dash, 0.8), 0.2))
-                        .sum()
-                })
+                .map(|path| markings::divider_gaps(&path))
                 .unwrap_or_else(|| Geometry::new(vec![], vec![])),
             world,
         );
-        if self.construction.progress - 2.0 * CONSTRUCTION_ANIMATION_DELAY >
-            self.construction.length
-        {
+        if self.construction.is_finished() {
             grouper.freeze(self.id.into(), world);
         }
     }
@@ -504,10 +641,29 @@ pub fn setup(system: &mut ActorSystem) {
         &mut system.world(),
     );
 
-    LaneRendererID::spawn(asphalt_group, marker_group, gaps_group, &mut system.world());
-}
+    let restriction_group = GrouperID::spawn(
+        [0.9, 0.5, 0.1],
+        LANE_RESTRICTION_THING_ID,
+        true,
+        &mut system.world(),
+    );
 
-const CONSTRUCTION_ANIMATION_DELAY: f32 = 120.0;
+    let marking_group = GrouperID::spawn(
+        [1.0, 1.0, 1.0],
+        LANE_MARKING_THING_ID,
+        true,
+        &mut system.world(),
+    );
+
+    LaneRendererID::spawn(
+        asphalt_group,
+        marker_group,
+        gaps_group,
+        restriction_group,
+        marking_group,
+        &mut system.world(),
+    );
+}
 
 use monet::MSG_Renderable_render_to_scene;
 
@@ -516,12 +672,65 @@ const DEBUG_VIEW_SIGNALS: bool = false;
 const DEBUG_VIEW_OBSTACLES: bool = false;
 const DEBUG_VIEW_TRANSFER_OBSTACLES: bool = false;
 
+const BRAKE_LIGHT_DECELERATION: f32 = -1.0;
+const EMERGENCY_VEHICLE_COLOR: [f32; 3] = [1.0, 1.0, 1.0];
+
+#[derive(Default)]
+struct CarLightInstances {
+    brake_lights: CVec<Instance>,
+    headlights: CVec<Instance>,
+}
+
+impl CarLightInstances {
+    fn render(self, renderer_id: RendererID, scene_id: usize, frame: usize, world: &mut World) {
+        if !self.brake_lights.is_empty() {
+            renderer_id.add_several_instances(scene_id, 8007, frame, self.brake_lights, world);
+        }
+        if !self.headlights.is_empty() {
+            renderer_id.add_several_instances(scene_id, 8008, frame, self.headlights, world);
+        }
+    }
+}
+
+fn add_car_lights(
+    car_instance: &Instance,
+    acceleration: f32,
+    headlights: bool,
+    light_instances: &mut CarLightInstances,
+) {
+    if acceleration < BRAKE_LIGHT_DECELERATION {
+        light_instances.brake_lights.push(Instance {
+            instance_color: [1.0, 0.0, 0.0],
+            ..*car_instance
+        });
+    }
+    if headlights {
+        light_instances.headlights.push(Instance {
+            instance_color: [1.0, 1.0, 0.8],
+            ..*car_instance
+        });
+    }
+}
+
+/// Where `car` is expected to be `since_tick` of microtraffic time after the last tick,
+/// so cars move smoothly even if several frames are rendered per tick
+fn extrapolated_position(car: &LaneCar, since_tick: Seconds) -> f32 {
+    let average_velocity = MetersPerSecond(
+        (car.velocity + 0.5 * car.acceleration * since_tick.0).max(0.0),
+    );
+    *car.position + (average_velocity * since_tick).0
+}
+
 #[derive(Compact, Clone)]
 pub struct LaneRenderer {
     id: LaneRendererID,
     asphalt_grouper: GrouperID,
     marker_grouper: GrouperID,
     gaps_grouper: GrouperID,
+    restriction_grouper: GrouperID,
+    /// Markings of lanes across intersections
+    marking_grouper: GrouperID,
+    shockwaves: bool,
 }
 
 impl Renderable for LaneRenderer {
@@ -531,6 +740,27 @@ impl Renderable for LaneRenderer {
         renderer_id.add_batch(scene_id, 8002, traffic_light::create_light(), world);
         renderer_id.add_batch(scene_id, 8003, traffic_light::create_light_left(), world);
         renderer_id.add_batch(scene_id, 8004, traffic_light::create_light_right(), world);
+        renderer_id.add_batch(scene_id, 8005, traffic_light::create_stop_line(), world);
+        renderer_id.add_batch(scene_id, 8006, traffic_light::create_pole(), world);
+        renderer_id.add_batch(scene_id, 8007, car::create_brake_lights(), world);
+        renderer_id.add_batch(scene_id, 8008, car::create_headlights(), world);
+        renderer_id.add_batch(scene_id, 8009, construction_site::create_barrier(), world);
+
+        let half_length = TRAFFIC_SEGMENT_LENGTH / 2.0;
+        renderer_id.add_batch(
+            scene_id,
+            SHOCKWAVE_SEGMENT_BATCH_ID,
+            Geometry::new(
+                vec![
+                    Vertex { position: [-half_length, -1.5, 0.15] },
+                    Vertex { position: [half_length, -1.5, 0.15] },
+                    Vertex { position: [half_length, 1.5, 0.15] },
+                    Vertex { position: [-half_length, 1.5, 0.15] },
+                ],
+                vec![0, 1, 2, 2, 3, 0],
+            ),
+            world,
+        );
 
         renderer_id.add_batch(
             scene_id,
@@ -558,6 +788,10 @@ impl Renderable for LaneRenderer {
         let lanes_as_renderables: RenderableID = LaneID::local_broadcast(world).into();
         lanes_as_renderables.render_to_scene(renderer_id, scene_id, frame, world);
 
+        if self.shockwaves {
+            LaneID::local_broadcast(world).render_shockwaves(renderer_id, scene_id, frame, world);
+        }
+
         let transfer_lanes_as_renderables: RenderableID = TransferLaneID::local_broadcast(world)
             .into();
         transfer_lanes_as_renderables.render_to_scene(renderer_id, scene_id, frame, world);
@@ -570,13 +804,25 @@ impl LaneRenderer {
         asphalt_grouper: GrouperID,
         marker_grouper: GrouperID,
         gaps_grouper: GrouperID,
-        _: &mut World,
+        restriction_grouper: GrouperID,
+        marking_grouper: GrouperID,
+        world: &mut World,
     ) -> LaneRenderer {
+        register_action(
+            "Toggle Shockwave View",
+            Combo2::new(&[LControl, V], &[]),
+            id.into(),
+            world,
+        );
+
         LaneRenderer {
             id,
             asphalt_grouper,
             marker_grouper,
             gaps_grouper,
+            restriction_grouper,
+            marking_grouper,
+            shockwaves: false,
         }
     }
 
@@ -588,7 +834,9 @@ impl LaneRenderer {
     ) {
         self.asphalt_grouper.initial_add(lane, world);
 
-        if !on_intersection {
+        if on_intersection {
+            self.marking_grouper.initial_add(lane, world);
+        } else {
             self.marker_grouper.initial_add(lane, world);
         }
     }
@@ -605,16 +853,53 @@ impl LaneRenderer {
     ) {
         self.asphalt_grouper.remove(lane, world);
 
-        if !on_intersection {
+        if on_intersection {
+            self.marking_grouper.remove(lane, world);
+        } else {
             self.marker_grouper.remove(lane, world);
+            self.restriction_grouper.remove(lane, world);
+        }
+    }
+
+    pub fn on_restriction_changed(
+        &mut self,
+        lane: GrouperIndividualID,
+        restricted: bool,
+        world: &mut World,
+    ) {
+        self.restriction_grouper.remove(lane, world);
+
+        if restricted {
+            self.restriction_grouper.initial_add(lane, world);
         }
     }
 
+    /// Draws the turn arrow of a lane across an intersection again, after it was closed
+    /// or reopened
+    pub fn on_markings_changed(&mut self, lane: GrouperIndividualID, world: &mut World) {
+        self.marking_grouper.remove(lane, world);
+        self.marking_grouper.initial_add(lane, world);
+    }
+
+    /// Draws the asphalt of the lane again, with the width of its new class
+    pub fn on_road_class_changed(&mut self, lane: GrouperIndividualID, world: &mut World) {
+        self.asphalt_grouper.remove(lane, world);
+        self.asphalt_grouper.initial_add(lane, world);
+    }
+
     pub fn on_unbuild_transfer(&mut self, lane: GrouperIndividualID, world: &mut World) {
         self.gaps_grouper.remove(lane, world);
     }
 }
 
+impl ActionListener for LaneRenderer {
+    fn on_action(&mut self, action: ActionId, phase: ActionPhase, _: &mut World) {
+        if phase == ActionPhase::Started && action == ActionId::named("Toggle Shockwave View") {
+            self.shockwaves = !self.shockwaves;
+        }
+    }
+}
+
 pub fn on_build(lane: &Lane, world: &mut World) {
     LaneRendererID::local_first(world).on_build(
         lane.id.into(),
@@ -622,6 +907,55 @@ pub fn on_build(lane: &Lane, world: &mut World) {
             .on_intersection,
         world,
     );
+
+    if lane.connectivity.on_intersection {
+        on_signalized_changed(lane, world);
+    }
+}
+
+/// Sets the decals that `lane` has in `layer`, removing them if `decals` is empty
+pub fn set_lane_decals(lane: &Lane, layer: u16, decals: CVec<Decal>, world: &mut World) {
+    RendererID::local_first(world).set_decals(
+        0,
+        layer,
+        lane.id._raw_id.instance_id,
+        decals,
+        world,
+    );
+}
+
+/// Switches between a stop line and a yield line
+pub fn on_signalized_changed(lane: &Lane, world: &mut World) {
+    let decals = markings::intersection_lane_decals(
+        &lane.construction.path,
+        lane.microtraffic.is_signalized(),
+    );
+    set_lane_decals(lane, MARKING_DECAL_LAYER, decals, world);
+}
+
+pub fn on_restriction_changed(lane: &Lane, world: &mut World) {
+    LaneRendererID::local_first(world).on_restriction_changed(
+        lane.id.into(),
+        lane.restriction != LaneRestriction::General,
+        world,
+    );
+
+    if lane.connectivity.on_intersection {
+        on_markings_changed(lane, world);
+    }
+}
+
+pub fn on_markings_changed(lane: &Lane, world: &mut World) {
+    LaneRendererID::local_first(world).on_markings_changed(lane.id.into(), world);
+}
+
+pub fn on_road_class_changed(lane: &Lane, world: &mut World) {
+    LaneRendererID::local_first(world).on_road_class_changed(lane.id.into(), world);
+    StreetFurnitureID::local_first(world).set_lane_class(
+        lane.id,
+        lane.road_class.class(),
+        world,
+    );
 }
 
 pub fn on_build_transfer(lane: &TransferLane, world: &mut World) {
@@ -636,6 +970,10 @@ pub fn on_unbuild(lane: &Lane, world: &mut World) {
         world,
     );
 
+    for &layer in &[MARKING_DECAL_LAYER, DIRT_DECAL_LAYER, CONSTRUCTION_DECAL_LAYER] {
+        set_lane_decals(lane, layer, CVec::new(), world);
+    }
+
     if DEBUG_VIEW_LANDMARKS {
         // TODO: move this to LaneRenderer
         RendererID::local_first(world).update_individual(
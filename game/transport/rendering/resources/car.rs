@@ -117,3 +117,35 @@ pub fn create() -> ::monet::Geometry {
         ],
     )
 }
+
+pub fn create_brake_lights() -> ::monet::Geometry {
+    ::monet::Geometry::new(
+        vec![
+            Vertex { position: [-2.26, -0.8, 0.55] }, // 0
+            Vertex { position: [-2.26, -0.5, 0.55] }, // 1
+            Vertex { position: [-2.26, -0.5, 0.75] }, // 2
+            Vertex { position: [-2.26, -0.8, 0.75] }, // 3
+            Vertex { position: [-2.26, 0.5, 0.55] }, // 4
+            Vertex { position: [-2.26, 0.8, 0.55] }, // 5
+            Vertex { position: [-2.26, 0.8, 0.75] }, // 6
+            Vertex { position: [-2.26, 0.5, 0.75] } /* 7 */,
+        ],
+        vec![0, 1, 2, 0, 2, 3, 4, 5, 6, 4, 6, 7],
+    )
+}
+
+pub fn create_headlights() -> ::monet::Geometry {
+    ::monet::Geometry::new(
+        vec![
+            Vertex { position: [2.26, -0.8, 0.45] }, // 0
+            Vertex { position: [2.26, -0.45, 0.45] }, // 1
+            Vertex { position: [2.26, -0.45, 0.7] }, // 2
+            Vertex { position: [2.26, -0.8, 0.7] }, // 3
+            Vertex { position: [2.26, 0.45, 0.45] }, // 4
+            Vertex { position: [2.26, 0.8, 0.45] }, // 5
+            Vertex { position: [2.26, 0.8, 0.7] }, // 6
+            Vertex { position: [2.26, 0.45, 0.7] } /* 7 */,
+        ],
+        vec![0, 1, 2, 0, 2, 3, 4, 5, 6, 4, 6, 7],
+    )
+}
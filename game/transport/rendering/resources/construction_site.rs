@@ -0,0 +1,18 @@
+use monet::Vertex;
+
+// a barrier standing across the lane at the point up to which it is built
+pub fn create_barrier() -> ::monet::Geometry {
+    ::monet::Geometry::new(
+        vec![
+            Vertex { position: [-0.2, -2.5, 0.0] }, // 0
+            Vertex { position: [-0.2, -2.5, 1.2] }, // 1
+            Vertex { position: [-0.2, 2.5, 1.2] }, // 2
+            Vertex { position: [-0.2, 2.5, 0.0] }, // 3
+            Vertex { position: [0.2, -2.5, 0.0] }, // 4
+            Vertex { position: [0.2, -2.5, 1.2] }, // 5
+            Vertex { position: [0.2, 2.5, 1.2] }, // 6
+            Vertex { position: [0.2, 2.5, 0.0] } /* 7 */,
+        ],
+        vec![0, 1, 2, 0, 2, 3, 4, 5, 1, 4, 1, 0, 7, 6, 5, 7, 5, 4, 3, 2, 6, 3, 6, 7, 1, 5, 6, 1, 6, 2],
+    )
+}
@@ -101,3 +101,32 @@ pub fn create_light_right() -> ::monet::Geometry {
         vec![0, 1, 2],
     )
 }
+
+pub fn create_pole() -> ::monet::Geometry {
+    ::monet::Geometry::new(
+        vec![
+            Vertex { position: [-0.1, -0.1, 0.0] }, // 0
+            Vertex { position: [-0.1, -0.1, 6.0] }, // 1
+            Vertex { position: [-0.1, 0.1, 6.0] }, // 2
+            Vertex { position: [-0.1, 0.1, 0.0] }, // 3
+            Vertex { position: [0.1, -0.1, 0.0] }, // 4
+            Vertex { position: [0.1, -0.1, 6.0] }, // 5
+            Vertex { position: [0.1, 0.1, 6.0] }, // 6
+            Vertex { position: [0.1, 0.1, 0.0] } /* 7 */,
+        ],
+        vec![0, 1, 2, 0, 2, 3, 4, 5, 1, 4, 1, 0, 7, 6, 5, 7, 5, 4, 3, 2, 6, 3, 6, 7],
+    )
+}
+
+// painted across the lane at the start of the intersection, colored with the signal state
+pub fn create_stop_line() -> ::monet::Geometry {
+    ::monet::Geometry::new(
+        vec![
+            Vertex { position: [-0.3, -2.5, 0.05] }, // 0
+            Vertex { position: [0.3, -2.5, 0.05] }, // 1
+            Vertex { position: [0.3, 2.5, 0.05] }, // 2
+            Vertex { position: [-0.3, 2.5, 0.05] } /* 3 */,
+        ],
+        vec![0, 1, 2, 0, 2, 3],
+    )
+}
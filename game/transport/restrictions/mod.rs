@@ -0,0 +1,376 @@
+use kay::{ActorSystem, World};
+use descartes::{N, P2, Curve, FiniteCurve};
+use stagemaster::combo::Combo2;
+use stagemaster::combo::Button::{LControl, R, B};
+use stagemaster::{UserInterfaceID, Event3d, Interactable3d, Interactable3dID,
+                  MSG_Interactable3d_on_event};
+use stagemaster::actions::{register_action, ActionId, ActionListener, ActionListenerID,
+                           ActionPhase, MSG_ActionListener_on_action};
+use stagemaster::geometry::CPath;
+use transport::planning::current_plan::set_tool_active;
+use core::units::Meters;
+use super::lane::{Lane, LaneID};
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum VehicleClass {
+    Car,
+    HighOccupancyCar,
+    Bus,
+    Truck,
+    /// Heavy or tall loads like machinery or prefab parts, which bridges might not carry
+    OversizeTruck,
+    /// Fire engines and ambulances, which may use every lane and don't stop at red lights
+    Emergency,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum LaneRestriction {
+    General,
+    BusOnly,
+    HighOccupancy,
+    NoTrucks,
+    /// Temporarily closed, for example for a special event, see `Lane::close`
+    Closed,
+}
+
+impl Default for LaneRestriction {
+    fn default() -> Self {
+        LaneRestriction::General
+    }
+}
+
+impl LaneRestriction {
+    pub fn permits(&self, vehicle: VehicleClass) -> bool {
+        vehicle == VehicleClass::Emergency ||
+            match *self {
+                LaneRestriction::General => true,
+                LaneRestriction::BusOnly => vehicle == VehicleClass::Bus,
+                LaneRestriction::HighOccupancy => {
+                    vehicle == VehicleClass::HighOccupancyCar || vehicle == VehicleClass::Bus
+                }
+                LaneRestriction::NoTrucks => {
+                    vehicle != VehicleClass::Truck && vehicle != VehicleClass::OversizeTruck
+                }
+                LaneRestriction::Closed => false,
+            }
+    }
+
+    /// The restriction that the painting tool switches to next
+    fn next(&self) -> Option<LaneRestriction> {
+        match *self {
+            LaneRestriction::General => Some(LaneRestriction::BusOnly),
+            LaneRestriction::BusOnly => Some(LaneRestriction::HighOccupancy),
+            LaneRestriction::HighOccupancy => Some(LaneRestriction::NoTrucks),
+            LaneRestriction::NoTrucks | LaneRestriction::Closed => None,
+        }
+    }
+}
+
+impl VehicleClass {
+    /// Weight in tons and height of the heaviest and tallest vehicles of this class
+    pub fn dimensions(&self) -> (f32, Meters) {
+        match *self {
+            VehicleClass::Car | VehicleClass::HighOccupancyCar => (2.5, Meters(2.0)),
+            VehicleClass::Bus | VehicleClass::Emergency => (18.0, Meters(3.5)),
+            VehicleClass::Truck => (40.0, Meters(4.0)),
+            VehicleClass::OversizeTruck => (80.0, Meters(5.0)),
+        }
+    }
+
+    /// The clearance a route needs to be usable by this class
+    pub fn required_clearance(&self) -> FreightClearance {
+        match *self {
+            VehicleClass::Truck => FreightClearance::Trucks,
+            VehicleClass::OversizeTruck => FreightClearance::OversizeTrucks,
+            _ => FreightClearance::NoFreight,
+        }
+    }
+}
+
+/// Which freight vehicles a lane (or a whole route) can take, ordered from least to most.
+/// Every limit that can be set still lets cars, buses and emergency vehicles through
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum FreightClearance {
+    NoFreight,
+    Trucks,
+    OversizeTrucks,
+}
+
+/// Weight and height limits of a lane, for lanes on or under bridges
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct LaneLimits {
+    pub max_weight_tons: f32,
+    pub max_height: Meters,
+}
+
+impl Default for LaneLimits {
+    fn default() -> Self {
+        LIMIT_PRESETS[0]
+    }
+}
+
+/// The limits the bridge limits tool cycles through, starting with no limits at all
+const LIMIT_PRESETS: [LaneLimits; 4] = [
+    LaneLimits {
+        max_weight_tons: ::std::f32::INFINITY,
+        max_height: Meters(::std::f32::INFINITY),
+    },
+    LaneLimits {
+        max_weight_tons: 40.0,
+        max_height: Meters(::std::f32::INFINITY),
+    },
+    LaneLimits {
+        max_weight_tons: 20.0,
+        max_height: Meters(::std::f32::INFINITY),
+    },
+    LaneLimits {
+        max_weight_tons: ::std::f32::INFINITY,
+        max_height: Meters(4.5),
+    },
+];
+
+impl LaneLimits {
+    pub fn carries(&self, vehicle: VehicleClass) -> bool {
+        let (weight_tons, height) = vehicle.dimensions();
+        weight_tons <= self.max_weight_tons && height <= self.max_height
+    }
+
+    pub fn clearance(&self) -> FreightClearance {
+        if self.carries(VehicleClass::OversizeTruck) {
+            FreightClearance::OversizeTrucks
+        } else if self.carries(VehicleClass::Truck) {
+            FreightClearance::Trucks
+        } else {
+            FreightClearance::NoFreight
+        }
+    }
+
+    fn describe(&self) -> String {
+        match (self.max_weight_tons.is_finite(), self.max_height.0.is_finite()) {
+            (false, false) => "No limits".to_owned(),
+            (true, false) => format!("Max. {:.0} t", self.max_weight_tons),
+            (false, true) => format!("Max. {:.1} m high", self.max_height.0),
+            (true, true) => {
+                format!(
+                    "Max. {:.0} t, {:.1} m high",
+                    self.max_weight_tons,
+                    self.max_height.0
+                )
+            }
+        }
+    }
+}
+
+const PAINTING_DISTANCE: f32 = 3.0;
+/// All lanes of a road this close to where the bridge limits tool was clicked get the limits
+const LIMITS_DISTANCE: f32 = 8.0;
+
+impl Lane {
+    pub fn paint_restriction(
+        &mut self,
+        position: P2,
+        restriction: LaneRestriction,
+        world: &mut World,
+    ) {
+        if !self.connectivity.on_intersection && self.restriction != restriction &&
+            self.restriction_before_closure.is_none() &&
+            self.construction.path.distance_to(position) < PAINTING_DISTANCE
+        {
+            self.set_restriction(restriction, world);
+        }
+    }
+
+    /// Sets the limits of all lanes of the road at `position`, as for a bridge.
+    /// Intersection lanes keep taking every vehicle
+    pub fn set_limits_near(&mut self, position: P2, limits: LaneLimits, _: &mut World) {
+        if !self.connectivity.on_intersection && self.limits != limits &&
+            self.construction.path.distance_to(position) < LIMITS_DISTANCE
+        {
+            self.limits = limits;
+            // predecessors need to relearn which freight our routes clear
+            self.pathfinding.routes_changed = true;
+        }
+    }
+
+    fn set_restriction(&mut self, restriction: LaneRestriction, world: &mut World) {
+        self.restriction = restriction;
+        // predecessors need to relearn our routes as (un)restricted
+        self.pathfinding.routes_changed = true;
+        super::rendering::on_restriction_changed(self, world);
+    }
+
+    /// Closes the lane to everything but emergency vehicles if it comes within `radius`
+    /// of `center`, until it is reopened. Cars already on it may still leave
+    pub fn close_near(&mut self, center: P2, radius: N, world: &mut World) {
+        if self.construction.path.distance_to(center) <= radius {
+            self.start_closure(world);
+        }
+    }
+
+    /// Ends the closure of `close_near` with the same area
+    pub fn reopen_near(&mut self, center: P2, radius: N, world: &mut World) {
+        if self.construction.path.distance_to(center) <= radius {
+            self.end_closure(world);
+        }
+    }
+
+    /// Like `close_near`, but for lanes running along `path`, as for roadworks.
+    /// Lanes on intersections stay open, so crossing traffic still gets through
+    pub fn close_along(&mut self, path: &CPath, distance: N, world: &mut World) {
+        if !self.connectivity.on_intersection && self.runs_along(path, distance) {
+            self.start_closure(world);
+        }
+    }
+
+    /// Ends the closure of `close_along` with the same path
+    pub fn reopen_along(&mut self, path: &CPath, distance: N, world: &mut World) {
+        if !self.connectivity.on_intersection && self.runs_along(path, distance) {
+            self.end_closure(world);
+        }
+    }
+
+    /// Closures of venues and roadworks can overlap, so the lane only reopens
+    /// (with the restriction it had before the first one) once the last one ends
+    fn start_closure(&mut self, world: &mut World) {
+        if self.n_closures == 0 {
+            self.restriction_before_closure = Some(self.restriction);
+            self.set_restriction(LaneRestriction::Closed, world);
+        }
+        self.n_closures += 1;
+    }
+
+    fn end_closure(&mut self, world: &mut World) {
+        // the lane might have been built while the closure was already going on
+        if self.n_closures == 0 {
+            return;
+        }
+        self.n_closures -= 1;
+        if self.n_closures == 0 {
+            if let Some(restriction) = self.restriction_before_closure.take() {
+                self.set_restriction(restriction, world);
+            }
+        }
+    }
+
+    fn runs_along(&self, path: &CPath, distance: N) -> bool {
+        let middle = self.construction.path.along(self.construction.length / 2.0);
+        path.distance_to(middle) < distance
+    }
+}
+
+/// Designates existing lanes as restricted: each press of the binding cycles through
+/// the restrictions (and back to inactive), clicking a lane then paints it.
+/// Bridge limits are set the same way, but for all lanes of the clicked road
+#[derive(Compact, Clone)]
+pub struct LanePainter {
+    id: LanePainterID,
+    user_interface: UserInterfaceID,
+    painting: Option<LaneRestriction>,
+    /// Index into `LIMIT_PRESETS`
+    setting_limits: Option<usize>,
+}
+
+impl LanePainter {
+    pub fn init(
+        id: LanePainterID,
+        user_interface: UserInterfaceID,
+        world: &mut World,
+    ) -> LanePainter {
+        register_action(
+            "Paint Lane Restrictions",
+            Combo2::new(&[LControl, R], &[]),
+            id.into(),
+            world,
+        );
+        register_action("Set Bridge Limits", Combo2::new(&[LControl, B], &[]), id.into(), world);
+
+        LanePainter {
+            id,
+            user_interface,
+            painting: None,
+            setting_limits: None,
+        }
+    }
+
+    fn update_interactable(&self, world: &mut World) {
+        let active = self.painting.is_some() || self.setting_limits.is_some();
+        set_tool_active(self.user_interface, self.id.into(), active, world);
+    }
+}
+
+impl ActionListener for LanePainter {
+    fn on_action(&mut self, action: ActionId, phase: ActionPhase, world: &mut World) {
+        if phase != ActionPhase::Started {
+            return;
+        }
+
+        if action == ActionId::named("Paint Lane Restrictions") {
+            self.painting = match self.painting {
+                None => Some(LaneRestriction::General),
+                Some(restriction) => restriction.next(),
+            };
+            self.setting_limits = None;
+            self.update_interactable(world);
+        } else if action == ActionId::named("Set Bridge Limits") {
+            self.setting_limits = match self.setting_limits {
+                None => Some(0),
+                Some(idx) if idx + 1 < LIMIT_PRESETS.len() => Some(idx + 1),
+                Some(_) => None,
+            };
+            self.painting = None;
+            self.update_interactable(world);
+        }
+    }
+}
+
+impl Interactable3d for LanePainter {
+    fn on_event(&mut self, event: Event3d, world: &mut World) {
+        match event {
+            Event3d::DragFinished { to, .. } => {
+                if let Some(restriction) = self.painting {
+                    LaneID::global_broadcast(world).paint_restriction(
+                        P2::new(to.x, to.y),
+                        restriction,
+                        world,
+                    );
+                } else if let Some(idx) = self.setting_limits {
+                    LaneID::global_broadcast(world).set_limits_near(
+                        P2::new(to.x, to.y),
+                        LIMIT_PRESETS[idx],
+                        world,
+                    );
+                }
+            }
+            Event3d::Frame => {
+                if let Some(restriction) = self.painting {
+                    self.user_interface.add_debug_text(
+                        "Painting Lanes".chars().collect(),
+                        format!("{:?}", restriction).chars().collect(),
+                        [0.0, 0.0, 0.0, 1.0],
+                        false,
+                        world,
+                    );
+                } else if let Some(idx) = self.setting_limits {
+                    self.user_interface.add_debug_text(
+                        "Setting Bridge Limits".chars().collect(),
+                        LIMIT_PRESETS[idx].describe().chars().collect(),
+                        [0.0, 0.0, 0.0, 1.0],
+                        false,
+                        world,
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+pub fn setup(system: &mut ActorSystem, user_interface: UserInterfaceID) {
+    system.register::<LanePainter>();
+    auto_setup(system);
+
+    LanePainterID::init(user_interface, &mut system.world());
+}
+
+mod kay_auto;
+pub use self::kay_auto::*;
@@ -0,0 +1,497 @@
+//! Functional classes of built roads, from quiet local streets to highways. Once a day,
+//! every road segment (the lanes running next to each other, in both directions) is
+//! classified from how many lanes it has and how many vehicles used it, unless the
+//! player set its class by hand.
+//!
+//! The class of a lane sets its speed limit, how much its approach counts for adaptive
+//! signals (see `signal_control`) and how wide it is drawn.
+use kay::{ActorSystem, World, External};
+use compact::CVec;
+use descartes::{N, P2, V2, Norm, Dot, Curve, FiniteCurve};
+use monet::{Renderable, RenderableID, MSG_Renderable_setup_in_scene,
+            MSG_Renderable_render_to_scene, RendererID, Geometry, Vertex, Instance};
+use stagemaster::combo::Combo2;
+use stagemaster::combo::Button::{LControl, J};
+use stagemaster::{UserInterfaceID, Event3d, Interactable3d, Interactable3dID,
+                  MSG_Interactable3d_on_event, Interactable2d, Interactable2dID,
+                  MSG_Interactable2d_draw_ui_2d};
+use stagemaster::actions::{register_action, ActionId, ActionListener, ActionListenerID,
+                           ActionPhase, MSG_ActionListener_on_action};
+use transport::planning::current_plan::set_tool_active;
+use imgui::ImGuiSetCond_FirstUseEver;
+use core::simulation::{SimulationID, Sleeper, SleeperID, MSG_Sleeper_wake, Timestamp, Ticks,
+                       TICKS_PER_SIM_HOUR, TICKS_PER_SIM_DAY};
+use core::units::MetersPerSecond;
+use super::lane::{Lane, LaneID};
+use super::restrictions::VehicleClass;
+
+const COLLECTION_TICKS: Ticks = Ticks(10);
+const CLASS_MARKER_BATCH_ID: u16 = 8105;
+/// Lanes with midpoints this close to each other (and running in the same or the
+/// opposite direction) count as one road segment
+const SEGMENT_RADIUS: N = 12.0;
+/// Minimum absolute cosine between the directions of two lanes of one segment
+const SEGMENT_PARALLELITY: N = 0.9;
+/// New classes find their lane by its midpoint, which is at most this far off
+const MATCH_DISTANCE: N = 0.1;
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum RoadClass {
+    Local,
+    Collector,
+    Arterial,
+    Highway,
+}
+
+const ALL_CLASSES: [RoadClass; 4] = [
+    RoadClass::Local,
+    RoadClass::Collector,
+    RoadClass::Arterial,
+    RoadClass::Highway,
+];
+
+impl RoadClass {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            RoadClass::Local => "Local",
+            RoadClass::Collector => "Collector",
+            RoadClass::Arterial => "Arterial",
+            RoadClass::Highway => "Highway",
+        }
+    }
+
+    pub fn speed_limit(&self) -> MetersPerSecond {
+        match *self {
+            RoadClass::Local => MetersPerSecond(8.5),
+            RoadClass::Collector => MetersPerSecond(11.0),
+            RoadClass::Arterial => MetersPerSecond(14.0),
+            RoadClass::Highway => MetersPerSecond(25.0),
+        }
+    }
+
+    /// How much queued cars on an approach of this class count for adaptive signals
+    pub fn signal_priority(&self) -> f32 {
+        match *self {
+            RoadClass::Local => 1.0,
+            RoadClass::Collector => 1.25,
+            RoadClass::Arterial => 1.5,
+            RoadClass::Highway => 2.0,
+        }
+    }
+
+    /// Width of the asphalt drawn for each lane
+    pub fn rendering_width(&self) -> N {
+        match *self {
+            RoadClass::Local | RoadClass::Collector => 6.0,
+            RoadClass::Arterial => 6.5,
+            RoadClass::Highway => 7.5,
+        }
+    }
+
+    fn color(&self) -> [f32; 3] {
+        match *self {
+            RoadClass::Local => [0.6, 0.6, 0.6],
+            RoadClass::Collector => [0.2, 0.6, 1.0],
+            RoadClass::Arterial => [1.0, 0.6, 0.0],
+            RoadClass::Highway => [0.8, 0.0, 0.2],
+        }
+    }
+
+    fn rank(&self) -> usize {
+        ALL_CLASSES.iter().position(|class| class == self).unwrap()
+    }
+}
+
+/// The class a road segment gets from its geometry and its traffic, counting both
+/// equally: a wide road without traffic, or a narrow one with a lot, ends up in between
+pub fn classify(lanes: usize, vehicles_per_hour: f32) -> RoadClass {
+    let by_geometry = match lanes {
+        0...2 => RoadClass::Local,
+        3...4 => RoadClass::Collector,
+        5...6 => RoadClass::Arterial,
+        _ => RoadClass::Highway,
+    };
+    let by_volume = if vehicles_per_hour < 150.0 {
+        RoadClass::Local
+    } else if vehicles_per_hour < 500.0 {
+        RoadClass::Collector
+    } else if vehicles_per_hour < 1500.0 {
+        RoadClass::Arterial
+    } else {
+        RoadClass::Highway
+    };
+    ALL_CLASSES[(by_geometry.rank() + by_volume.rank() + 1) / 2]
+}
+
+/// What a lane knows about its class
+#[derive(Copy, Clone, Default)]
+pub struct RoadClassInfo {
+    /// Set by the daily classification, none until the lane was classified once
+    pub classified: Option<RoadClass>,
+    /// Set by the player, takes precedence over `classified`
+    pub chosen: Option<RoadClass>,
+    /// Vehicles that entered the lane since the last classification
+    pub vehicles_entered: usize,
+}
+
+impl RoadClassInfo {
+    pub fn class(&self) -> Option<RoadClass> {
+        self.chosen.or(self.classified)
+    }
+
+    /// The speed limit for `vehicle`, emergency vehicles and unclassified lanes have none
+    pub fn speed_limit(&self, vehicle: VehicleClass) -> f32 {
+        match self.class() {
+            Some(class) if vehicle != VehicleClass::Emergency => class.speed_limit().0,
+            _ => ::std::f32::INFINITY,
+        }
+    }
+
+    pub fn signal_priority(&self) -> f32 {
+        self.class().map(|class| class.signal_priority()).unwrap_or(1.0)
+    }
+}
+
+#[derive(Copy, Clone)]
+struct LaneReport {
+    midpoint: P2,
+    direction: V2,
+    vehicles_entered: usize,
+    class: Option<RoadClass>,
+    chosen: bool,
+}
+
+#[derive(Compact, Clone)]
+pub struct RoadHierarchy {
+    id: RoadHierarchyID,
+    simulation: SimulationID,
+    user_interface: UserInterfaceID,
+    collecting: bool,
+    reports: CVec<LaneReport>,
+    /// The class the tool sets, with none meaning back to the classified one
+    choosing: Option<Option<RoadClass>>,
+    overlay: bool,
+}
+
+impl RoadHierarchy {
+    pub fn spawn(
+        id: RoadHierarchyID,
+        user_interface: UserInterfaceID,
+        simulation: SimulationID,
+        world: &mut World,
+    ) -> RoadHierarchy {
+        register_action("Set Road Class", Combo2::new(&[LControl, J], &[]), id.into(), world);
+        user_interface.add_2d(id.into(), world);
+        simulation.wake_up_in(Ticks(TICKS_PER_SIM_DAY), id.into(), world);
+
+        RoadHierarchy {
+            id,
+            simulation,
+            user_interface,
+            collecting: false,
+            reports: CVec::new(),
+            choosing: None,
+            overlay: false,
+        }
+    }
+
+    pub fn add_lane_report(
+        &mut self,
+        midpoint: P2,
+        direction: V2,
+        vehicles_entered: usize,
+        class: Option<RoadClass>,
+        chosen: bool,
+        _: &mut World,
+    ) {
+        if self.collecting {
+            self.reports.push(LaneReport {
+                midpoint,
+                direction,
+                vehicles_entered,
+                class,
+                chosen,
+            });
+        }
+    }
+
+    /// Classifies each lane together with the rest of its segment and tells
+    /// the lanes whose class changed
+    fn classify_all(&mut self, world: &mut World) {
+        let hours = (TICKS_PER_SIM_DAY / TICKS_PER_SIM_HOUR) as f32;
+        let mut changed = CVec::<(P2, RoadClass)>::new();
+
+        let new_classes = self.reports
+            .iter()
+            .map(|report| {
+                let segment = self.reports
+                    .iter()
+                    .filter(|other| {
+                        (other.midpoint - report.midpoint).norm() < SEGMENT_RADIUS &&
+                            other.direction.dot(&report.direction).abs() > SEGMENT_PARALLELITY
+                    })
+                    .collect::<Vec<_>>();
+                let vehicles_entered: usize = segment
+                    .iter()
+                    .map(|other| other.vehicles_entered)
+                    .sum();
+                classify(segment.len(), vehicles_entered as f32 / hours)
+            })
+            .collect::<Vec<_>>();
+
+        for (report, new_class) in self.reports.iter_mut().zip(new_classes) {
+            if report.chosen {
+                continue;
+            }
+            if report.class != Some(new_class) {
+                changed.push((report.midpoint, new_class));
+                report.class = Some(new_class);
+            }
+        }
+
+        if !changed.is_empty() {
+            // found again by position, lanes might have been removed since they reported
+            LaneID::global_broadcast(world).set_classified_road_class(changed, world);
+        }
+    }
+
+    fn class_counts(&self) -> Vec<usize> {
+        ALL_CLASSES
+            .iter()
+            .map(|class| {
+                self.reports
+                    .iter()
+                    .filter(|report| report.class == Some(*class))
+                    .count()
+            })
+            .collect()
+    }
+}
+
+impl Sleeper for RoadHierarchy {
+    fn wake(&mut self, _current_tick: Timestamp, world: &mut World) {
+        if self.collecting {
+            self.collecting = false;
+            self.classify_all(world);
+            self.simulation.wake_up_in(
+                Ticks(TICKS_PER_SIM_DAY - COLLECTION_TICKS.0),
+                self.id.into(),
+                world,
+            );
+        } else {
+            self.collecting = true;
+            self.reports = CVec::new();
+            LaneID::global_broadcast(world).report_road_class(self.id, world);
+            self.simulation.wake_up_in(COLLECTION_TICKS, self.id.into(), world);
+        }
+    }
+}
+
+fn midpoint(lane: &Lane) -> P2 {
+    lane.construction.path.along(lane.construction.length / 2.0)
+}
+
+impl Lane {
+    pub fn report_road_class(&mut self, hierarchy: RoadHierarchyID, world: &mut World) {
+        if self.construction.is_finished() && !self.connectivity.on_intersection {
+            hierarchy.add_lane_report(
+                midpoint(self),
+                self.construction.path.direction_along(self.construction.length / 2.0),
+                self.road_class.vehicles_entered,
+                self.road_class.class(),
+                self.road_class.chosen.is_some(),
+                world,
+            );
+            self.road_class.vehicles_entered = 0;
+        }
+    }
+
+    pub fn set_classified_road_class(
+        &mut self,
+        classes: &CVec<(P2, RoadClass)>,
+        world: &mut World,
+    ) {
+        let own_midpoint = midpoint(self);
+        if let Some(&(_, class)) = classes.iter().find(|&&(midpoint, _)| {
+            (midpoint - own_midpoint).norm() < MATCH_DISTANCE
+        })
+        {
+            let old_class = self.road_class.class();
+            self.road_class.classified = Some(class);
+            if self.road_class.class() != old_class {
+                super::rendering::on_road_class_changed(self, world);
+            }
+        }
+    }
+
+    /// Sets the class of all lanes of the road segment at `position`,
+    /// or lets them be classified again if `class` is none
+    pub fn choose_road_class_near(
+        &mut self,
+        position: P2,
+        class: Option<RoadClass>,
+        world: &mut World,
+    ) {
+        if !self.connectivity.on_intersection &&
+            self.construction.path.distance_to(position) < SEGMENT_RADIUS
+        {
+            let old_class = self.road_class.class();
+            self.road_class.chosen = class;
+            if self.road_class.class() != old_class {
+                super::rendering::on_road_class_changed(self, world);
+            }
+        }
+    }
+}
+
+impl Renderable for RoadHierarchy {
+    fn setup_in_scene(&mut self, renderer_id: RendererID, scene_id: usize, world: &mut World) {
+        renderer_id.add_batch(
+            scene_id,
+            CLASS_MARKER_BATCH_ID,
+            Geometry::new(
+                vec![
+                    Vertex { position: [-2.0, -1.0, 0.2] },
+                    Vertex { position: [2.0, -1.0, 0.2] },
+                    Vertex { position: [2.0, 1.0, 0.2] },
+                    Vertex { position: [-2.0, 1.0, 0.2] },
+                ],
+                vec![0, 1, 2, 2, 3, 0],
+            ),
+            world,
+        );
+    }
+
+    fn render_to_scene(
+        &mut self,
+        renderer_id: RendererID,
+        scene_id: usize,
+        frame: usize,
+        world: &mut World,
+    ) {
+        if self.overlay && !self.reports.is_empty() {
+            renderer_id.add_several_instances(
+                scene_id,
+                CLASS_MARKER_BATCH_ID,
+                frame,
+                self.reports
+                    .iter()
+                    .filter_map(|report| {
+                        report.class.map(|class| {
+                            Instance {
+                                instance_position: [report.midpoint.x, report.midpoint.y, 0.0],
+                                instance_direction: [report.direction.x, report.direction.y],
+                                instance_color: class.color(),
+                            }
+                        })
+                    })
+                    .collect(),
+                world,
+            );
+        }
+    }
+}
+
+impl ActionListener for RoadHierarchy {
+    fn on_action(&mut self, action: ActionId, phase: ActionPhase, world: &mut World) {
+        if phase == ActionPhase::Started && action == ActionId::named("Set Road Class") {
+            // cycles through "classified" and all classes, and back to inactive
+            self.choosing = match self.choosing {
+                None => Some(None),
+                Some(None) => Some(Some(ALL_CLASSES[0])),
+                Some(Some(class)) => ALL_CLASSES.get(class.rank() + 1).map(|&next| Some(next)),
+            };
+            set_tool_active(self.user_interface, self.id.into(), self.choosing.is_some(), world);
+        }
+    }
+}
+
+impl Interactable3d for RoadHierarchy {
+    fn on_event(&mut self, event: Event3d, world: &mut World) {
+        if let Some(class) = self.choosing {
+            match event {
+                Event3d::DragFinished { to, .. } => {
+                    LaneID::global_broadcast(world).choose_road_class_near(
+                        P2::new(to.x, to.y),
+                        class,
+                        world,
+                    );
+                }
+                Event3d::Frame => {
+                    self.user_interface.add_debug_text(
+                        "Setting Road Class".chars().collect(),
+                        class
+                            .map(|class| class.name())
+                            .unwrap_or("Classified")
+                            .chars()
+                            .collect(),
+                        [0.0, 0.0, 0.0, 1.0],
+                        false,
+                        world,
+                    );
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Interactable2d for RoadHierarchy {
+    fn draw_ui_2d(
+        &mut self,
+        imgui_ui: &External<::imgui::Ui<'static>>,
+        return_to: UserInterfaceID,
+        world: &mut World,
+    ) {
+        let ui = imgui_ui.steal();
+        let mut overlay = self.overlay;
+
+        ui.window(im_str!("Road Hierarchy"))
+            .size((250.0, 180.0), ImGuiSetCond_FirstUseEver)
+            .collapsible(true)
+            .build(|| {
+                for (class, count) in ALL_CLASSES.iter().zip(self.class_counts()) {
+                    ui.text(im_str!("{}: {} lanes", class.name(), count));
+                }
+                let n_chosen = self.reports.iter().filter(|report| report.chosen).count();
+                ui.text(im_str!("{} lanes set by hand", n_chosen));
+
+                ui.text(im_str!("Class overlay:"));
+                if ui.small_button(im_str!("On")) {
+                    overlay = true;
+                }
+                ui.same_line(0.0);
+                if ui.small_button(im_str!("Off")) {
+                    overlay = false;
+                }
+            });
+
+        self.overlay = overlay;
+
+        return_to.ui_drawn(ui, world);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RoadClass, classify};
+
+    #[test]
+    fn geometry_and_volume_count_equally() {
+        assert_eq!(classify(2, 0.0), RoadClass::Local);
+        assert_eq!(classify(2, 600.0), RoadClass::Collector);
+        assert_eq!(classify(4, 0.0), RoadClass::Collector);
+        assert_eq!(classify(6, 2000.0), RoadClass::Highway);
+        assert_eq!(classify(8, 2000.0), RoadClass::Highway);
+    }
+}
+
+pub fn setup(system: &mut ActorSystem, user_interface: UserInterfaceID, simulation: SimulationID) {
+    system.register::<RoadHierarchy>();
+    auto_setup(system);
+
+    RoadHierarchyID::spawn(user_interface, simulation, &mut system.world());
+}
+
+mod kay_auto;
+pub use self::kay_auto::*;
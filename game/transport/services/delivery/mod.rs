@@ -0,0 +1,494 @@
+//! Parcel deliveries: households order online every now and then, and every hour of the
+//! working day vans leave from the nearest shop with a tour past all of its orders.
+//! At each stop a van double-parks on the lane for a while, so traffic has to wait
+//! behind it, which makes for a different traffic pattern than trips from A to B.
+//!
+//! Orders aren't tied to the economy yet, they only generate van traffic.
+use kay::{ActorSystem, World, External, Fate};
+use compact::CVec;
+use descartes::{N, P2, Norm, Curve, FiniteCurve};
+use fnv::FnvHashMap;
+use ordered_float::OrderedFloat;
+use monet::{Instance, RendererID, Renderable, RenderableID, MSG_Renderable_setup_in_scene,
+            MSG_Renderable_render_to_scene};
+use stagemaster::{UserInterfaceID, Interactable2d, Interactable2dID,
+                  MSG_Interactable2d_draw_ui_2d};
+use stagemaster::geometry::CPath;
+use imgui::ImGuiSetCond_FirstUseEver;
+use core::simulation::{SimulationID, Simulatable, SimulatableID, MSG_Simulatable_tick, Sleeper,
+                       SleeperID, MSG_Sleeper_wake, Timestamp, Ticks, TimeOfDay,
+                       SimulationConfig, TICKS_PER_SIM_HOUR};
+use core::units::MetersPerSecond;
+use economy::buildings::BuildingID;
+use transport::lane::LaneID;
+use transport::microtraffic::{LaneLikeID, Obstacle, CAR_LENGTH};
+use transport::pathfinding::tour::plan_tour;
+use super::{LaneGraphCollector, LaneGraphCollectorID, MSG_LaneGraphCollector_add_graph_lane};
+
+const SETTINGS_CATEGORY: &'static str = "Deliveries";
+const COLLECTION_TICKS: Ticks = Ticks(10);
+const VAN_VELOCITY: MetersPerSecond = MetersPerSecond(10.0);
+
+#[derive(Serialize, Deserialize, Copy, Clone)]
+pub struct DeliverySettings {
+    /// Parcels each household orders per day, on average
+    pub orders_per_household_per_day: f32,
+    /// Vans leave from the first of these hours until before the second
+    pub delivery_hours: (usize, usize),
+    pub max_stops_per_tour: usize,
+    /// How long a van stays double-parked at each stop (in microtraffic time)
+    pub stop_seconds: f32,
+}
+
+impl Default for DeliverySettings {
+    fn default() -> Self {
+        DeliverySettings {
+            orders_per_household_per_day: 0.1,
+            delivery_hours: (8, 18),
+            max_stops_per_tour: 10,
+            stop_seconds: 60.0,
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+struct DeliveryPoint {
+    access: LaneID,
+    position: P2,
+    n_homes: usize,
+    is_depot: bool,
+}
+
+#[derive(Compact, Clone)]
+struct GraphLane {
+    lane: LaneID,
+    path: CPath,
+    successors: CVec<LaneID>,
+}
+
+#[derive(Copy, Clone)]
+pub struct DeliveryStop {
+    /// Index of the lane in the van's route
+    route_idx: usize,
+    /// Where along that lane the van parks
+    position: N,
+    n_parcels: usize,
+}
+
+/// Rolls for orders every hour of the working day and sends out a van from the
+/// nearest depot (any shop) for every few of them, on a tour past all their stops
+#[derive(Compact, Clone)]
+pub struct DeliveryDispatcher {
+    id: DeliveryDispatcherID,
+    simulation: SimulationID,
+    settings: DeliverySettings,
+    collecting: bool,
+    graph: CVec<GraphLane>,
+    points: CVec<DeliveryPoint>,
+    vans_sent_today: usize,
+    parcels_ordered_today: usize,
+    parcels_delivered_today: usize,
+    parcels_undeliverable_today: usize,
+}
+
+impl DeliveryDispatcher {
+    pub fn spawn(
+        id: DeliveryDispatcherID,
+        user_interface: UserInterfaceID,
+        simulation: SimulationID,
+        world: &mut World,
+    ) -> DeliveryDispatcher {
+        user_interface.add_2d(id.into(), world);
+        simulation.wake_up_in(Ticks(TICKS_PER_SIM_HOUR), id.into(), world);
+
+        DeliveryDispatcher {
+            id,
+            simulation,
+            settings: ::ENV.load_settings(SETTINGS_CATEGORY),
+            collecting: false,
+            graph: CVec::new(),
+            points: CVec::new(),
+            vans_sent_today: 0,
+            parcels_ordered_today: 0,
+            parcels_delivered_today: 0,
+            parcels_undeliverable_today: 0,
+        }
+    }
+
+    pub fn add_delivery_point(
+        &mut self,
+        access: LaneID,
+        position: P2,
+        n_homes: usize,
+        is_depot: bool,
+        _: &mut World,
+    ) {
+        if self.collecting {
+            self.points.push(DeliveryPoint { access, position, n_homes, is_depot });
+        }
+    }
+
+    pub fn parcels_delivered(&mut self, n_parcels: usize, _: &mut World) {
+        self.parcels_delivered_today += n_parcels;
+    }
+
+    fn n_delivery_hours(&self) -> usize {
+        let (first, last) = self.settings.delivery_hours;
+        last.saturating_sub(first).max(1)
+    }
+
+    fn send_out_vans(&mut self, world: &mut World) {
+        let depots = self.points
+            .iter()
+            .filter(|point| point.is_depot)
+            .cloned()
+            .collect::<Vec<_>>();
+        if depots.is_empty() {
+            return;
+        }
+
+        // orders grouped by depot, then by the lane they are delivered on
+        let order_chance = self.settings.orders_per_household_per_day /
+            self.n_delivery_hours() as f32;
+        let mut orders = FnvHashMap::<LaneID, FnvHashMap<LaneID, (P2, usize)>>::default();
+        for point in self.points.iter().filter(|point| point.n_homes > 0) {
+            let n_orders = (0..point.n_homes)
+                .filter(|_| ::core::random::next_f32() < order_chance)
+                .count();
+            if n_orders == 0 {
+                continue;
+            }
+            self.parcels_ordered_today += n_orders;
+
+            let nearest_depot = depots
+                .iter()
+                .min_by_key(|depot| OrderedFloat((depot.position - point.position).norm()))
+                .expect("there is at least one depot");
+            let stop = orders
+                .entry(nearest_depot.access)
+                .or_insert_with(FnvHashMap::default)
+                .entry(point.access)
+                .or_insert((point.position, 0));
+            stop.1 += n_orders;
+        }
+
+        let successors = self.graph
+            .iter()
+            .map(|graph_lane| (graph_lane.lane, graph_lane.successors.to_vec()))
+            .collect::<FnvHashMap<_, _>>();
+        let paths = self.graph
+            .iter()
+            .map(|graph_lane| (graph_lane.lane, &graph_lane.path))
+            .collect::<FnvHashMap<_, _>>();
+
+        for (&depot, stops) in &orders {
+            if !paths.contains_key(&depot) {
+                self.parcels_undeliverable_today += stops.values().map(|&(_, n)| n).sum::<usize>();
+                continue;
+            }
+
+            let stop_lanes = stops.keys().cloned().collect::<Vec<_>>();
+            for tour_lanes in stop_lanes.chunks(self.settings.max_stops_per_tour.max(1)) {
+                let tour = plan_tour(depot, tour_lanes, &successors, |lane| {
+                    paths.get(&lane).map(|path| path.length()).unwrap_or(0.0)
+                });
+
+                self.parcels_undeliverable_today += tour.unreachable
+                    .iter()
+                    .map(|lane| stops[lane].1)
+                    .sum::<usize>();
+                if tour.stop_indices.is_empty() {
+                    continue;
+                }
+
+                let route = tour.route
+                    .iter()
+                    .map(|lane| (*lane, paths[lane].clone()))
+                    .collect::<CVec<_>>();
+                let van_stops = tour.stop_indices
+                    .iter()
+                    .map(|&route_idx| {
+                        let (lane, ref path) = route[route_idx];
+                        let (position, n_parcels) = stops[&lane];
+                        DeliveryStop {
+                            route_idx,
+                            position: path.project(position).unwrap_or(path.length() / 2.0),
+                            n_parcels,
+                        }
+                    })
+                    .collect();
+
+                DeliveryVanID::spawn(
+                    self.id,
+                    route,
+                    van_stops,
+                    self.settings.stop_seconds,
+                    world,
+                );
+                self.vans_sent_today += 1;
+            }
+        }
+    }
+}
+
+impl LaneGraphCollector for DeliveryDispatcher {
+    fn add_graph_lane(
+        &mut self,
+        lane: LaneID,
+        path: &CPath,
+        successors: &CVec<LaneID>,
+        _: &mut World,
+    ) {
+        if self.collecting {
+            self.graph.push(GraphLane {
+                lane,
+                path: path.clone(),
+                successors: successors.clone(),
+            });
+        }
+    }
+}
+
+impl Sleeper for DeliveryDispatcher {
+    fn wake(&mut self, current_tick: Timestamp, world: &mut World) {
+        if self.collecting {
+            self.collecting = false;
+            self.send_out_vans(world);
+            self.graph = CVec::new();
+            self.points = CVec::new();
+            self.simulation.wake_up_in(
+                Ticks(TICKS_PER_SIM_HOUR - COLLECTION_TICKS.0),
+                self.id.into(),
+                world,
+            );
+        } else {
+            let hour = TimeOfDay::from_tick(current_tick).hours_minutes().0;
+            let (first, last) = self.settings.delivery_hours;
+            if hour == first {
+                self.vans_sent_today = 0;
+                self.parcels_ordered_today = 0;
+                self.parcels_delivered_today = 0;
+                self.parcels_undeliverable_today = 0;
+            }
+
+            if hour >= first && hour < last {
+                self.collecting = true;
+                LaneID::global_broadcast(world).report_to_graph_collector(self.id.into(), world);
+                BuildingID::global_broadcast(world).report_delivery_point(self.id, world);
+                self.simulation.wake_up_in(COLLECTION_TICKS, self.id.into(), world);
+            } else {
+                self.simulation.wake_up_in(Ticks(TICKS_PER_SIM_HOUR), self.id.into(), world);
+            }
+        }
+    }
+}
+
+impl Interactable2d for DeliveryDispatcher {
+    fn draw_ui_2d(
+        &mut self,
+        imgui_ui: &External<::imgui::Ui<'static>>,
+        return_to: UserInterfaceID,
+        world: &mut World,
+    ) {
+        let ui = imgui_ui.steal();
+
+        ui.window(im_str!("Deliveries"))
+            .size((250.0, 100.0), ImGuiSetCond_FirstUseEver)
+            .collapsible(true)
+            .build(|| {
+                ui.text(im_str!(
+                    "Today: {} parcels ordered, {} delivered",
+                    self.parcels_ordered_today,
+                    self.parcels_delivered_today
+                ));
+                ui.text(im_str!(
+                    "{} vans sent, {} parcels undeliverable",
+                    self.vans_sent_today,
+                    self.parcels_undeliverable_today
+                ));
+            });
+
+        return_to.ui_drawn(ui, world);
+    }
+}
+
+/// A delivery van on its tour. Like service vehicles, it isn't a car in microtraffic,
+/// but tells the lane it is on about itself as an obstacle, standing still while it
+/// is double-parked at a stop
+#[derive(Compact, Clone)]
+pub struct DeliveryVan {
+    id: DeliveryVanID,
+    dispatcher: DeliveryDispatcherID,
+    route: CVec<(LaneID, CPath)>,
+    stops: CVec<DeliveryStop>,
+    next_stop: usize,
+    current: usize,
+    position: N,
+    stop_seconds: f32,
+    /// Microtraffic seconds left to stay parked at the current stop
+    parked_for: f32,
+}
+
+/// Vans send their obstacles to lanes as if they were a partner lane, which the lanes
+/// only use to tell apart whose obstacles they have. They never get `LaneLike` messages
+impl From<DeliveryVanID> for LaneLikeID {
+    fn from(id: DeliveryVanID) -> LaneLikeID {
+        LaneLikeID { _raw_id: id._raw_id }
+    }
+}
+
+impl DeliveryVan {
+    pub fn spawn(
+        id: DeliveryVanID,
+        dispatcher: DeliveryDispatcherID,
+        route: &CVec<(LaneID, CPath)>,
+        stops: &CVec<DeliveryStop>,
+        stop_seconds: f32,
+        world: &mut World,
+    ) -> DeliveryVan {
+        let van = DeliveryVan {
+            id,
+            dispatcher,
+            route: route.clone(),
+            stops: stops.clone(),
+            next_stop: 0,
+            current: 0,
+            position: 0.0,
+            stop_seconds,
+            parked_for: 0.0,
+        };
+        van.announce_as_obstacle(world);
+        van
+    }
+
+    fn announce_as_obstacle(&self, world: &mut World) {
+        if let Some(&(lane, _)) = self.route.get(self.current) {
+            let velocity = if self.parked_for > 0.0 {
+                0.0
+            } else {
+                VAN_VELOCITY.0
+            };
+            Into::<LaneLikeID>::into(lane).add_obstacles(
+                vec![
+                    Obstacle {
+                        position: OrderedFloat(self.position),
+                        velocity,
+                        max_velocity: VAN_VELOCITY.0,
+                        length: CAR_LENGTH.0,
+                    },
+                ].into(),
+                self.id.into(),
+                world,
+            );
+        }
+    }
+
+    fn leave_lane(&self, lane: LaneID, world: &mut World) {
+        Into::<LaneLikeID>::into(lane).add_obstacles(CVec::new(), self.id.into(), world);
+    }
+
+    pub fn finish_tour(&mut self, _: &mut World) -> Fate {
+        Fate::Die
+    }
+}
+
+impl Simulatable for DeliveryVan {
+    fn tick(&mut self, dt: f32, current_tick: Timestamp, world: &mut World) {
+        let config = SimulationConfig::current();
+        let microtraffic_dt = config.microtraffic_time(dt);
+        let announce_tick = current_tick.ticks() % config.traffic_logic_throttling ==
+            self.id._raw_id.instance_id as usize % config.traffic_logic_throttling;
+
+        if self.parked_for > 0.0 {
+            self.parked_for -= microtraffic_dt.0;
+            if self.parked_for <= 0.0 {
+                // drive on
+                self.parked_for = 0.0;
+                self.announce_as_obstacle(world);
+            } else if announce_tick {
+                // lanes forget obstacles that aren't announced again every few cycles,
+                // even those of a van that is still parked
+                self.announce_as_obstacle(world);
+            }
+            return;
+        }
+
+        self.position += (VAN_VELOCITY * microtraffic_dt).0;
+
+        let mut changed_lane = false;
+        loop {
+            if let Some(stop) = self.stops.get(self.next_stop).cloned() {
+                if stop.route_idx == self.current && self.position >= stop.position {
+                    self.position = stop.position;
+                    self.next_stop += 1;
+                    self.parked_for = self.stop_seconds;
+                    self.dispatcher.parcels_delivered(stop.n_parcels, world);
+                    self.announce_as_obstacle(world);
+                    return;
+                }
+            }
+
+            let maybe_lane = self.route.get(self.current).map(
+                |&(lane, ref path)| (lane, path.length()),
+            );
+            if let Some((lane, length)) = maybe_lane {
+                if self.position < length {
+                    break;
+                }
+                self.position -= length;
+                self.current += 1;
+                changed_lane = true;
+                self.leave_lane(lane, world);
+            } else {
+                break;
+            }
+        }
+
+        if self.current >= self.route.len() {
+            self.id.finish_tour(world);
+        } else if changed_lane || announce_tick {
+            self.announce_as_obstacle(world);
+        }
+    }
+}
+
+impl Renderable for DeliveryVan {
+    fn setup_in_scene(&mut self, _renderer_id: RendererID, _scene_id: usize, _: &mut World) {}
+
+    fn render_to_scene(
+        &mut self,
+        renderer_id: RendererID,
+        scene_id: usize,
+        frame: usize,
+        world: &mut World,
+    ) {
+        if let Some(&(_, ref path)) = self.route.get(self.current) {
+            let position: P2 = path.along(self.position);
+            let direction = path.direction_along(self.position);
+            renderer_id.add_instance(
+                scene_id,
+                8000,
+                frame,
+                Instance {
+                    instance_position: [position.x, position.y, 0.0],
+                    instance_direction: [direction.x, direction.y],
+                    instance_color: [0.6, 0.4, 0.2],
+                },
+                world,
+            );
+        }
+    }
+}
+
+pub fn setup(system: &mut ActorSystem, user_interface: UserInterfaceID, simulation: SimulationID) {
+    system.register::<DeliveryDispatcher>();
+    system.register::<DeliveryVan>();
+    system.make_restorable::<DeliveryVan>();
+    system.make_restorable::<DeliveryDispatcher>();
+    auto_setup(system);
+
+    DeliveryDispatcherID::spawn(user_interface, simulation, &mut system.world());
+}
+
+mod kay_auto;
+pub use self::kay_auto::*;
@@ -0,0 +1,524 @@
+use kay::{ActorSystem, World, External};
+use compact::CVec;
+use descartes::{P2, Norm, FiniteCurve};
+use fnv::FnvHashMap;
+use monet::{RendererID, Renderable, RenderableID, MSG_Renderable_setup_in_scene,
+            MSG_Renderable_render_to_scene, Geometry, Vertex, Instance};
+use stagemaster::{UserInterfaceID, Interactable2d, Interactable2dID,
+                  MSG_Interactable2d_draw_ui_2d};
+use stagemaster::geometry::CPath;
+use imgui::ImGuiSetCond_FirstUseEver;
+use core::simulation::{SimulationID, Sleeper, SleeperID, MSG_Sleeper_wake, Timestamp, Ticks,
+                       TICKS_PER_SIM_MINUTE, TICKS_PER_SIM_HOUR};
+use economy::buildings::BuildingID;
+use economy::households::emergency_station::EmergencyStationID;
+use transport::lane::{Lane, LaneID};
+use transport::microtraffic::vehicle_max_velocity;
+use transport::restrictions::VehicleClass;
+use transport::pathfinding::RoughLocationID;
+use transport::pathfinding::isochrones::travel_times;
+use transport::pathfinding::trip::{TripID, TripListener, TripListenerID,
+                                   MSG_TripListener_trip_created, MSG_TripListener_trip_result};
+use super::{LaneGraphCollector, LaneGraphCollectorID, MSG_LaneGraphCollector_add_graph_lane,
+            district_of};
+
+const COLLECTION_TICKS: Ticks = Ticks(10);
+// per car on a lane and hour
+const CRASH_CHANCE: f32 = 0.0005;
+const COVERAGE_MARKER_BATCH_ID: u16 = 8101;
+// response times (in minutes) considered good and still acceptable in the overlay
+const GOOD_RESPONSE_MINUTES: f32 = 4.0;
+const ACCEPTABLE_RESPONSE_MINUTES: f32 = 8.0;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum IncidentKind {
+    Crash,
+    Fire,
+}
+
+impl IncidentKind {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            IncidentKind::Crash => "Crash",
+            IncidentKind::Fire => "Fire",
+        }
+    }
+
+    fn responder(&self) -> StationKind {
+        match *self {
+            IncidentKind::Crash => StationKind::Hospital,
+            IncidentKind::Fire => StationKind::FireStation,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum StationKind {
+    FireStation,
+    Hospital,
+}
+
+impl StationKind {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            StationKind::FireStation => "Fire Station",
+            StationKind::Hospital => "Hospital",
+        }
+    }
+}
+
+#[derive(Compact, Clone)]
+struct Station {
+    station: EmergencyStationID,
+    kind: StationKind,
+    site: BuildingID,
+    access: LaneID,
+    position: P2,
+}
+
+#[derive(Compact, Clone)]
+struct GraphLane {
+    lane: LaneID,
+    path: CPath,
+    successors: CVec<LaneID>,
+}
+
+/// How fast the nearest fire station and hospital can get to a lane, in minutes
+#[derive(Compact, Clone)]
+struct LaneCoverage {
+    lane: LaneID,
+    position: P2,
+    fire_minutes: Option<(f32, EmergencyStationID)>,
+    medical_minutes: Option<(f32, EmergencyStationID)>,
+}
+
+impl LaneCoverage {
+    fn for_kind(&self, kind: StationKind) -> Option<(f32, EmergencyStationID)> {
+        match kind {
+            StationKind::FireStation => self.fire_minutes,
+            StationKind::Hospital => self.medical_minutes,
+        }
+    }
+}
+
+#[derive(Compact, Clone)]
+struct Response {
+    trip: TripID,
+    station: EmergencyStationID,
+    incident: IncidentKind,
+    district: (i32, i32),
+    reported_at: Timestamp,
+}
+
+#[derive(Compact, Clone)]
+struct DistrictResponseTimes {
+    district: (i32, i32),
+    responses: usize,
+    total_minutes: f32,
+    worst_minutes: f32,
+    failed: usize,
+}
+
+/// Rolls for crashes and fires every hour, sends a vehicle from the best placed
+/// fire station or hospital to each of them with emergency priority and keeps
+/// track of how long they took to arrive, per district.
+/// Also computes isochrones around all stations, shown as a coverage overlay
+#[derive(Compact, Clone)]
+pub struct EmergencyDispatcher {
+    id: EmergencyDispatcherID,
+    simulation: SimulationID,
+    stations: CVec<Station>,
+    collecting_graph: bool,
+    graph: CVec<GraphLane>,
+    coverage: CVec<LaneCoverage>,
+    responses: CVec<Response>,
+    districts: CVec<DistrictResponseTimes>,
+    overlay: Option<StationKind>,
+}
+
+impl EmergencyDispatcher {
+    pub fn spawn(
+        id: EmergencyDispatcherID,
+        user_interface: UserInterfaceID,
+        simulation: SimulationID,
+        world: &mut World,
+    ) -> EmergencyDispatcher {
+        user_interface.add_2d(id.into(), world);
+        simulation.wake_up_in(Ticks(TICKS_PER_SIM_HOUR), id.into(), world);
+
+        EmergencyDispatcher {
+            id,
+            simulation,
+            stations: CVec::new(),
+            collecting_graph: false,
+            graph: CVec::new(),
+            coverage: CVec::new(),
+            responses: CVec::new(),
+            districts: CVec::new(),
+            overlay: None,
+        }
+    }
+
+    pub fn add_station(
+        &mut self,
+        station: EmergencyStationID,
+        kind: StationKind,
+        site: BuildingID,
+        access: LaneID,
+        position: P2,
+        _: &mut World,
+    ) {
+        self.stations.push(Station { station, kind, site, access, position });
+    }
+
+    pub fn remove_station(&mut self, station: EmergencyStationID, _: &mut World) {
+        self.stations.retain(|other| other.station != station);
+        for coverage in self.coverage.iter_mut() {
+            if coverage.fire_minutes.map(|(_, by)| by) == Some(station) {
+                coverage.fire_minutes = None;
+            }
+            if coverage.medical_minutes.map(|(_, by)| by) == Some(station) {
+                coverage.medical_minutes = None;
+            }
+        }
+    }
+
+    pub fn report_incident(
+        &mut self,
+        incident: IncidentKind,
+        location: RoughLocationID,
+        lane: LaneID,
+        position: P2,
+        tick: Timestamp,
+        world: &mut World,
+    ) {
+        let responder = incident.responder();
+        let stations = &self.stations;
+        let isochrone_station = self.coverage
+            .iter()
+            .find(|coverage| coverage.lane == lane)
+            .and_then(|coverage| coverage.for_kind(responder))
+            .map(|(_, station)| station);
+        // lanes that were built after the last isochrones get the nearest station
+        let maybe_station = isochrone_station
+            .and_then(|station| {
+                stations.iter().find(|other| other.station == station)
+            })
+            .or_else(|| {
+                stations
+                    .iter()
+                    .filter(|station| station.kind == responder)
+                    .min_by_key(|station| {
+                        ::ordered_float::OrderedFloat((station.position - position).norm())
+                    })
+            });
+
+        if let Some(station) = maybe_station {
+            let trip = TripID::spawn(
+                station.site.into(),
+                location,
+                Some(self.id.into()),
+                VehicleClass::Emergency,
+                tick,
+                world,
+            );
+            self.responses.push(Response {
+                trip,
+                station: station.station,
+                incident,
+                district: district_of(position),
+                reported_at: tick,
+            });
+        }
+    }
+
+    fn update_coverage(&mut self) {
+        let successors = self.graph
+            .iter()
+            .map(|graph_lane| (graph_lane.lane, graph_lane.successors.to_vec()))
+            .collect::<FnvHashMap<_, _>>();
+        let lengths = self.graph
+            .iter()
+            .map(|graph_lane| (graph_lane.lane, graph_lane.path.length()))
+            .collect::<FnvHashMap<_, _>>();
+        let meters_per_minute = vehicle_max_velocity(VehicleClass::Emergency).0 * 60.0;
+
+        let station_times = self.stations
+            .iter()
+            .map(|station| {
+                let times = travel_times(&[station.access], &successors, |lane| {
+                    lengths[&lane] / meters_per_minute
+                });
+                (station.station, station.kind, times)
+            })
+            .collect::<Vec<_>>();
+
+        let fastest = |lane: LaneID, kind: StationKind| {
+            station_times
+                .iter()
+                .filter(|&&(_, station_kind, _)| station_kind == kind)
+                .filter_map(|&(station, _, ref times)| {
+                    times.get(&lane).map(|&minutes| (minutes, station))
+                })
+                .min_by_key(|&(minutes, _)| ::ordered_float::OrderedFloat(minutes))
+        };
+
+        self.coverage = self.graph
+            .iter()
+            .map(|graph_lane| {
+                LaneCoverage {
+                    lane: graph_lane.lane,
+                    position: graph_lane.path.along(graph_lane.path.length() / 2.0),
+                    fire_minutes: fastest(graph_lane.lane, StationKind::FireStation),
+                    medical_minutes: fastest(graph_lane.lane, StationKind::Hospital),
+                }
+            })
+            .collect();
+    }
+
+    fn record_response(&mut self, district: (i32, i32), minutes: Option<f32>) {
+        if !self.districts.iter().any(|times| times.district == district) {
+            self.districts.push(DistrictResponseTimes {
+                district,
+                responses: 0,
+                total_minutes: 0.0,
+                worst_minutes: 0.0,
+                failed: 0,
+            });
+        }
+        let times = self.districts
+            .iter_mut()
+            .find(|times| times.district == district)
+            .expect("just made sure it exists");
+
+        if let Some(minutes) = minutes {
+            times.responses += 1;
+            times.total_minutes += minutes;
+            times.worst_minutes = times.worst_minutes.max(minutes);
+        } else {
+            times.failed += 1;
+        }
+    }
+}
+
+impl LaneGraphCollector for EmergencyDispatcher {
+    fn add_graph_lane(
+        &mut self,
+        lane: LaneID,
+        path: &CPath,
+        successors: &CVec<LaneID>,
+        _: &mut World,
+    ) {
+        if self.collecting_graph {
+            self.graph.push(GraphLane {
+                lane,
+                path: path.clone(),
+                successors: successors.clone(),
+            });
+        }
+    }
+}
+
+impl Sleeper for EmergencyDispatcher {
+    fn wake(&mut self, current_tick: Timestamp, world: &mut World) {
+        if self.collecting_graph {
+            self.collecting_graph = false;
+            self.update_coverage();
+            self.graph = CVec::new();
+            self.simulation.wake_up_in(
+                Ticks(TICKS_PER_SIM_HOUR - COLLECTION_TICKS.0),
+                self.id.into(),
+                world,
+            );
+        } else {
+            BuildingID::global_broadcast(world).risk_fire(self.id, current_tick, world);
+            LaneID::global_broadcast(world).risk_crash(self.id, current_tick, world);
+
+            self.collecting_graph = true;
+            LaneID::global_broadcast(world).report_to_graph_collector(self.id.into(), world);
+            self.simulation.wake_up_in(COLLECTION_TICKS, self.id.into(), world);
+        }
+    }
+}
+
+impl TripListener for EmergencyDispatcher {
+    fn trip_created(&mut self, _trip: TripID, _: &mut World) {}
+
+    fn trip_result(
+        &mut self,
+        trip: TripID,
+        _location: RoughLocationID,
+        failed: bool,
+        tick: Timestamp,
+        world: &mut World,
+    ) {
+        if let Some(idx) = self.responses.iter().position(
+            |response| response.trip == trip,
+        )
+        {
+            let response = self.responses.remove(idx);
+            let minutes = if failed {
+                None
+            } else {
+                Some(
+                    (tick.ticks() - response.reported_at.ticks()) as f32 /
+                        TICKS_PER_SIM_MINUTE as f32,
+                )
+            };
+            self.record_response(response.district, minutes);
+            response.station.responded(response.incident, minutes, world);
+        }
+    }
+}
+
+impl Lane {
+    pub fn risk_crash(
+        &mut self,
+        dispatcher: EmergencyDispatcherID,
+        tick: Timestamp,
+        world: &mut World,
+    ) {
+        let chance = self.microtraffic.cars.len() as f32 * CRASH_CHANCE;
+        if chance > 0.0 && ::core::random::next_f32() < chance {
+            let car = self.microtraffic.cars[0];
+            dispatcher.report_incident(
+                IncidentKind::Crash,
+                self.id.into(),
+                self.id,
+                self.construction.path.along(*car.position),
+                tick,
+                world,
+            );
+        }
+    }
+}
+
+fn coverage_color(minutes: Option<(f32, EmergencyStationID)>) -> [f32; 3] {
+    match minutes {
+        Some((minutes, _)) if minutes < GOOD_RESPONSE_MINUTES => [0.0, 0.8, 0.2],
+        Some((minutes, _)) if minutes < ACCEPTABLE_RESPONSE_MINUTES => [1.0, 0.8, 0.0],
+        _ => [1.0, 0.0, 0.0],
+    }
+}
+
+impl Renderable for EmergencyDispatcher {
+    fn setup_in_scene(&mut self, renderer_id: RendererID, scene_id: usize, world: &mut World) {
+        renderer_id.add_batch(
+            scene_id,
+            COVERAGE_MARKER_BATCH_ID,
+            Geometry::new(
+                vec![
+                    Vertex { position: [-2.0, -2.0, 0.2] },
+                    Vertex { position: [2.0, -2.0, 0.2] },
+                    Vertex { position: [2.0, 2.0, 0.2] },
+                    Vertex { position: [-2.0, 2.0, 0.2] },
+                ],
+                vec![0, 1, 2, 2, 3, 0],
+            ),
+            world,
+        );
+    }
+
+    fn render_to_scene(
+        &mut self,
+        renderer_id: RendererID,
+        scene_id: usize,
+        frame: usize,
+        world: &mut World,
+    ) {
+        if let Some(kind) = self.overlay {
+            if !self.coverage.is_empty() {
+                renderer_id.add_several_instances(
+                    scene_id,
+                    COVERAGE_MARKER_BATCH_ID,
+                    frame,
+                    self.coverage
+                        .iter()
+                        .map(|coverage| {
+                            Instance {
+                                instance_position: [coverage.position.x, coverage.position.y, 0.0],
+                                instance_direction: [1.0, 0.0],
+                                instance_color: coverage_color(coverage.for_kind(kind)),
+                            }
+                        })
+                        .collect(),
+                    world,
+                );
+            }
+        }
+    }
+}
+
+impl Interactable2d for EmergencyDispatcher {
+    fn draw_ui_2d(
+        &mut self,
+        imgui_ui: &External<::imgui::Ui<'static>>,
+        return_to: UserInterfaceID,
+        world: &mut World,
+    ) {
+        let ui = imgui_ui.steal();
+        let mut new_overlay = self.overlay;
+
+        ui.window(im_str!("Emergency Services"))
+            .size((300.0, 250.0), ImGuiSetCond_FirstUseEver)
+            .collapsible(true)
+            .build(|| {
+                let n_fire = self.stations
+                    .iter()
+                    .filter(|station| station.kind == StationKind::FireStation)
+                    .count();
+                ui.text(im_str!(
+                    "{} fire stations, {} hospitals",
+                    n_fire,
+                    self.stations.len() - n_fire
+                ));
+                ui.text(im_str!("Vehicles on their way: {}", self.responses.len()));
+
+                ui.text(im_str!("Coverage overlay:"));
+                if ui.small_button(im_str!("Fire")) {
+                    new_overlay = Some(StationKind::FireStation);
+                }
+                ui.same_line(0.0);
+                if ui.small_button(im_str!("Medical")) {
+                    new_overlay = Some(StationKind::Hospital);
+                }
+                ui.same_line(0.0);
+                if ui.small_button(im_str!("Off")) {
+                    new_overlay = None;
+                }
+
+                if ui.collapsing_header(im_str!("Response times per district")).build() {
+                    for times in self.districts.iter() {
+                        let average = if times.responses == 0 {
+                            0.0
+                        } else {
+                            times.total_minutes / times.responses as f32
+                        };
+                        ui.text(im_str!(
+                            "({}, {}): avg {:.1} min, worst {:.1} min, {} failed",
+                            times.district.0,
+                            times.district.1,
+                            average,
+                            times.worst_minutes,
+                            times.failed
+                        ));
+                    }
+                }
+            });
+
+        self.overlay = new_overlay;
+
+        return_to.ui_drawn(ui, world);
+    }
+}
+
+pub fn setup(system: &mut ActorSystem, user_interface: UserInterfaceID, simulation: SimulationID) {
+    system.register::<EmergencyDispatcher>();
+    system.make_restorable::<EmergencyDispatcher>();
+    auto_setup(system);
+
+    EmergencyDispatcherID::spawn(user_interface, simulation, &mut system.world());
+}
+
+mod kay_auto;
+pub use self::kay_auto::*;
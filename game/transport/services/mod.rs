@@ -0,0 +1,364 @@
+use kay::{ActorSystem, World, Fate};
+use stagemaster::UserInterfaceID;
+use compact::CVec;
+use descartes::{N, P2, FiniteCurve};
+use ordered_float::OrderedFloat;
+use fnv::{FnvHashMap, FnvHashSet};
+use monet::{Instance, RendererID, Renderable, RenderableID, MSG_Renderable_setup_in_scene,
+            MSG_Renderable_render_to_scene};
+use stagemaster::geometry::CPath;
+use core::simulation::{SimulationID, Simulatable, SimulatableID, MSG_Simulatable_tick, Sleeper,
+                       SleeperID, MSG_Sleeper_wake, Timestamp, Ticks, TimeOfDay,
+                       SimulationConfig, TICKS_PER_SIM_HOUR};
+use core::units::MetersPerSecond;
+use economy::buildings::BuildingID;
+use super::lane::{Lane, LaneID};
+use super::lane::connectivity::{Interaction, InteractionKind};
+use super::microtraffic::{LaneLikeID, Obstacle, BUS_LENGTH};
+use super::pathfinding::coverage::coverage_route;
+
+pub mod emergency;
+pub mod winter;
+pub mod delivery;
+
+const COLLECTION_TICKS: Ticks = Ticks(10);
+pub const DISTRICT_SIZE: N = 500.0;
+const MAX_VEHICLES_PER_DISTRICT: usize = 3;
+// service vehicles crawl along, holding up traffic behind them
+const SERVICE_VELOCITY: MetersPerSecond = MetersPerSecond(4.0);
+const GARBAGE_ROUND_HOUR: usize = 6;
+const SWEEPING_ROUND_HOUR: usize = 14;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ServiceKind {
+    /// Empties the garbage of all buildings along its route
+    GarbageCollection,
+    /// Sweeps up the litter in front of all buildings along its route
+    StreetSweeping,
+    /// Clears the snow off every lane along its route
+    SnowPlowing,
+}
+
+/// The square of `DISTRICT_SIZE` that `position` lies in
+pub fn district_of(position: P2) -> (i32, i32) {
+    (
+        (position.x / DISTRICT_SIZE).floor() as i32,
+        (position.y / DISTRICT_SIZE).floor() as i32,
+    )
+}
+
+pub trait LaneGraphCollector {
+    fn add_graph_lane(
+        &mut self,
+        lane: LaneID,
+        path: &CPath,
+        successors: &CVec<LaneID>,
+        world: &mut World,
+    );
+}
+
+impl Lane {
+    /// Tells `collector` about this lane and where one can drive from here
+    pub fn report_to_graph_collector(
+        &mut self,
+        collector: LaneGraphCollectorID,
+        world: &mut World,
+    ) {
+        if !self.construction.is_finished() {
+            return;
+        }
+
+        let successors = self.connectivity
+            .interactions
+            .iter()
+            .filter_map(|interaction| match *interaction {
+                Interaction { kind: InteractionKind::Next { .. }, partner_lane, .. } => {
+                    Some(partner_lane.into())
+                }
+                _ => None,
+            })
+            .collect();
+
+        collector.add_graph_lane(self.id, self.construction.path.clone(), successors, world);
+    }
+}
+
+#[derive(Compact, Clone)]
+struct CoverageLane {
+    lane: LaneID,
+    path: CPath,
+    successors: CVec<LaneID>,
+}
+
+/// Every hour lets buildings produce waste and a few times a day sends out
+/// service vehicles, one round per district, that drive along every lane there
+#[derive(Compact, Clone)]
+pub struct ServiceDispatcher {
+    id: ServiceDispatcherID,
+    simulation: SimulationID,
+    collecting_for: Option<ServiceKind>,
+    lanes: CVec<CoverageLane>,
+}
+
+impl ServiceDispatcher {
+    pub fn spawn(
+        id: ServiceDispatcherID,
+        simulation: SimulationID,
+        world: &mut World,
+    ) -> ServiceDispatcher {
+        simulation.wake_up_in(Ticks(TICKS_PER_SIM_HOUR), id.into(), world);
+
+        ServiceDispatcher {
+            id,
+            simulation,
+            collecting_for: None,
+            lanes: CVec::new(),
+        }
+    }
+
+    fn send_out_vehicles(&mut self, kind: ServiceKind, world: &mut World) {
+        let mut districts = FnvHashMap::<(i32, i32), Vec<&CoverageLane>>::default();
+        for coverage_lane in self.lanes.iter() {
+            let middle = coverage_lane.path.along(coverage_lane.path.length() / 2.0);
+            districts.entry(district_of(middle)).or_insert_with(Vec::new).push(
+                coverage_lane,
+            );
+        }
+
+        for district_lanes in districts.values() {
+            let successors = district_lanes
+                .iter()
+                .map(|coverage_lane| {
+                    (coverage_lane.lane, coverage_lane.successors.to_vec())
+                })
+                .collect::<FnvHashMap<_, _>>();
+            let paths = district_lanes
+                .iter()
+                .map(|coverage_lane| (coverage_lane.lane, &coverage_lane.path))
+                .collect::<FnvHashMap<_, _>>();
+
+            let mut covered = FnvHashSet::default();
+            let mut n_vehicles = 0;
+
+            for coverage_lane in district_lanes {
+                if n_vehicles >= MAX_VEHICLES_PER_DISTRICT {
+                    break;
+                }
+                if covered.contains(&coverage_lane.lane) {
+                    continue;
+                }
+
+                let route = coverage_route(coverage_lane.lane, &successors);
+                covered.extend(route.iter().cloned());
+                n_vehicles += 1;
+
+                ServiceVehicleID::spawn(
+                    kind,
+                    route
+                        .into_iter()
+                        .map(|lane| (lane, paths[&lane].clone()))
+                        .collect(),
+                    world,
+                );
+            }
+        }
+    }
+}
+
+impl LaneGraphCollector for ServiceDispatcher {
+    fn add_graph_lane(
+        &mut self,
+        lane: LaneID,
+        path: &CPath,
+        successors: &CVec<LaneID>,
+        _: &mut World,
+    ) {
+        if self.collecting_for.is_some() {
+            self.lanes.push(CoverageLane {
+                lane,
+                path: path.clone(),
+                successors: successors.clone(),
+            });
+        }
+    }
+}
+
+impl Sleeper for ServiceDispatcher {
+    fn wake(&mut self, current_tick: Timestamp, world: &mut World) {
+        if let Some(kind) = self.collecting_for {
+            self.collecting_for = None;
+            self.send_out_vehicles(kind, world);
+            self.lanes = CVec::new();
+            self.simulation.wake_up_in(
+                Ticks(TICKS_PER_SIM_HOUR - COLLECTION_TICKS.0),
+                self.id.into(),
+                world,
+            );
+        } else {
+            BuildingID::global_broadcast(world).produce_waste(1.0, world);
+
+            let hour = TimeOfDay::from_tick(current_tick).hours_minutes().0;
+            self.collecting_for = if hour == GARBAGE_ROUND_HOUR {
+                Some(ServiceKind::GarbageCollection)
+            } else if hour == SWEEPING_ROUND_HOUR {
+                Some(ServiceKind::StreetSweeping)
+            } else {
+                None
+            };
+
+            if self.collecting_for.is_some() {
+                LaneID::global_broadcast(world).report_to_graph_collector(self.id.into(), world);
+                self.simulation.wake_up_in(COLLECTION_TICKS, self.id.into(), world);
+            } else {
+                self.simulation.wake_up_in(Ticks(TICKS_PER_SIM_HOUR), self.id.into(), world);
+            }
+        }
+    }
+}
+
+/// A garbage truck, street sweeper or snow plow driving its coverage route at walking pace.
+/// It isn't a car in microtraffic, but tells the lane it is on about itself
+/// as an obstacle, so traffic has to queue up behind it
+#[derive(Compact, Clone)]
+pub struct ServiceVehicle {
+    id: ServiceVehicleID,
+    kind: ServiceKind,
+    route: CVec<(LaneID, CPath)>,
+    current: usize,
+    position: N,
+}
+
+/// Service vehicles send their obstacles to lanes as if they were a partner lane, which the lanes
+/// only use to tell apart whose obstacles they have. They never get `LaneLike` messages
+impl From<ServiceVehicleID> for LaneLikeID {
+    fn from(id: ServiceVehicleID) -> LaneLikeID {
+        LaneLikeID { _raw_id: id._raw_id }
+    }
+}
+
+impl ServiceVehicle {
+    pub fn spawn(
+        id: ServiceVehicleID,
+        kind: ServiceKind,
+        route: &CVec<(LaneID, CPath)>,
+        world: &mut World,
+    ) -> ServiceVehicle {
+        let vehicle = ServiceVehicle {
+            id,
+            kind,
+            route: route.clone(),
+            current: 0,
+            position: 0.0,
+        };
+        vehicle.announce_as_obstacle(world);
+        vehicle
+    }
+
+    fn announce_as_obstacle(&self, world: &mut World) {
+        if let Some(&(lane, _)) = self.route.get(self.current) {
+            Into::<LaneLikeID>::into(lane).add_obstacles(
+                vec![
+                    Obstacle {
+                        position: OrderedFloat(self.position),
+                        velocity: SERVICE_VELOCITY.0,
+                        max_velocity: SERVICE_VELOCITY.0,
+                        length: BUS_LENGTH.0,
+                    },
+                ].into(),
+                self.id.into(),
+                world,
+            );
+        }
+    }
+
+    fn leave_lane(&self, lane: LaneID, world: &mut World) {
+        Into::<LaneLikeID>::into(lane).add_obstacles(CVec::new(), self.id.into(), world);
+        if self.kind == ServiceKind::SnowPlowing {
+            lane.plow(world);
+        } else {
+            BuildingID::global_broadcast(world).receive_service(lane, self.kind, world);
+        }
+    }
+
+    pub fn finish_round(&mut self, _: &mut World) -> Fate {
+        Fate::Die
+    }
+}
+
+impl Simulatable for ServiceVehicle {
+    fn tick(&mut self, dt: f32, current_tick: Timestamp, world: &mut World) {
+        let config = SimulationConfig::current();
+        self.position += (SERVICE_VELOCITY * config.microtraffic_time(dt)).0;
+
+        let mut changed_lane = false;
+        while let Some((lane, length)) = self.route.get(self.current).map(
+            |&(lane, ref path)| (lane, path.length()),
+        )
+        {
+            if self.position < length {
+                break;
+            }
+            self.position -= length;
+            self.current += 1;
+            changed_lane = true;
+            self.leave_lane(lane, world);
+        }
+
+        if self.current >= self.route.len() {
+            self.id.finish_round(world);
+        } else if changed_lane ||
+                   current_tick.ticks() % config.traffic_logic_throttling ==
+                       self.id._raw_id.instance_id as usize % config.traffic_logic_throttling
+        {
+            self.announce_as_obstacle(world);
+        }
+    }
+}
+
+impl Renderable for ServiceVehicle {
+    fn setup_in_scene(&mut self, _renderer_id: RendererID, _scene_id: usize, _: &mut World) {}
+
+    fn render_to_scene(
+        &mut self,
+        renderer_id: RendererID,
+        scene_id: usize,
+        frame: usize,
+        world: &mut World,
+    ) {
+        if let Some(&(_, ref path)) = self.route.get(self.current) {
+            let position: P2 = path.along(self.position);
+            let direction = path.direction_along(self.position);
+            renderer_id.add_instance(
+                scene_id,
+                8000,
+                frame,
+                Instance {
+                    instance_position: [position.x, position.y, 0.0],
+                    instance_direction: [direction.x, direction.y],
+                    instance_color: match self.kind {
+                        ServiceKind::GarbageCollection => [0.9, 0.5, 0.0],
+                        ServiceKind::StreetSweeping => [1.0, 1.0, 1.0],
+                        ServiceKind::SnowPlowing => [0.2, 0.4, 1.0],
+                    },
+                },
+                world,
+            );
+        }
+    }
+}
+
+pub fn setup(system: &mut ActorSystem, user_interface: UserInterfaceID, simulation: SimulationID) {
+    system.register::<ServiceDispatcher>();
+    system.register::<ServiceVehicle>();
+    system.make_restorable::<ServiceVehicle>();
+    system.make_restorable::<ServiceDispatcher>();
+    auto_setup(system);
+
+    ServiceDispatcherID::spawn(simulation, &mut system.world());
+    emergency::setup(system, user_interface, simulation);
+    winter::setup(system, user_interface, simulation);
+    delivery::setup(system, user_interface, simulation);
+}
+
+mod kay_auto;
+pub use self::kay_auto::*;
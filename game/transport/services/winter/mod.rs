@@ -0,0 +1,507 @@
+//! Snow in winter and the plows that clear it: during snowstorms snow piles up on all
+//! lanes, making cars drive slower and speed up more carefully, until a plow from one
+//! of the depots drives along them. Districts are plowed in order of their priority.
+//!
+//! There is no weather simulation yet, so snowstorms just start and stop at random
+//! during the winter days of each year (see the "Winter Service" settings).
+use kay::{ActorSystem, World, External};
+use compact::CVec;
+use descartes::{N, P2, Norm, FiniteCurve};
+use fnv::FnvHashMap;
+use ordered_float::OrderedFloat;
+use monet::{Renderable, RenderableID, MSG_Renderable_setup_in_scene,
+            MSG_Renderable_render_to_scene, RendererID, Geometry, Vertex, Instance};
+use stagemaster::combo::Combo2;
+use stagemaster::combo::Button::{LControl, W};
+use stagemaster::{UserInterfaceID, Event3d, Interactable3d, Interactable3dID,
+                  MSG_Interactable3d_on_event, Interactable2d, Interactable2dID,
+                  MSG_Interactable2d_draw_ui_2d};
+use stagemaster::actions::{register_action, ActionId, ActionListener, ActionListenerID,
+                           ActionPhase, MSG_ActionListener_on_action};
+use stagemaster::geometry::CPath;
+use transport::planning::current_plan::set_tool_active;
+use imgui::ImGuiSetCond_FirstUseEver;
+use core::simulation::{SimulationID, Sleeper, SleeperID, MSG_Sleeper_wake, Timestamp, Ticks,
+                       TICKS_PER_SIM_HOUR};
+use transport::lane::{Lane, LaneID};
+use transport::pathfinding::coverage::coverage_route;
+use super::{LaneGraphCollector, LaneGraphCollectorID, MSG_LaneGraphCollector_add_graph_lane,
+            ServiceKind, ServiceVehicleID, district_of, DISTRICT_SIZE};
+
+const SETTINGS_CATEGORY: &'static str = "Winter Service";
+const COLLECTION_TICKS: Ticks = Ticks(10);
+const DEPOT_MARKER_BATCH_ID: u16 = 8104;
+/// Placing a depot this close to an existing one removes that one instead
+const DEPOT_REMOVAL_DISTANCE: N = 20.0;
+/// Snow melts away by itself this fast (per hour), even when nobody plows
+const SNOW_MELT_PER_HOUR: f32 = 0.01;
+/// Fraction of their max velocity that cars still drive in the deepest snow
+const DEEP_SNOW_SPEED_FACTOR: f32 = 0.4;
+/// Fraction of their usual acceleration that cars still manage in the deepest snow
+const DEEP_SNOW_ADHESION: f32 = 0.3;
+
+/// Factor on the max velocity of cars on a lane with this much snow
+pub fn snow_speed_factor(snow: f32) -> f32 {
+    1.0 - snow.min(1.0) * (1.0 - DEEP_SNOW_SPEED_FACTOR)
+}
+
+/// Factor on the acceleration of cars on a lane with this much snow
+pub fn snow_adhesion(snow: f32) -> f32 {
+    1.0 - snow.min(1.0) * (1.0 - DEEP_SNOW_ADHESION)
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone)]
+pub struct WinterSettings {
+    pub days_per_year: usize,
+    /// The first this many days of each year are winter
+    pub winter_days: usize,
+    /// Chance that a snowstorm starts (or, while snowing, goes on) in any winter hour
+    pub snowstorm_chance: f32,
+    /// Snow depth (1 being the deepest) added to every lane per hour of snowstorm
+    pub snowfall_per_hour: f32,
+    /// Plows that can be sent out per hour, the districts with the highest priority first
+    pub plows_per_hour: usize,
+}
+
+impl Default for WinterSettings {
+    fn default() -> Self {
+        WinterSettings {
+            days_per_year: 12,
+            winter_days: 3,
+            snowstorm_chance: 0.3,
+            snowfall_per_hour: 0.1,
+            plows_per_hour: 6,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum PlowPriority {
+    High,
+    Normal,
+    Low,
+    /// Left to melt by itself
+    Never,
+}
+
+impl PlowPriority {
+    fn name(&self) -> &'static str {
+        match *self {
+            PlowPriority::High => "High",
+            PlowPriority::Normal => "Normal",
+            PlowPriority::Low => "Low",
+            PlowPriority::Never => "Never",
+        }
+    }
+
+    /// The priority that clicking the priority button switches to
+    fn next(&self) -> PlowPriority {
+        match *self {
+            PlowPriority::High => PlowPriority::Normal,
+            PlowPriority::Normal => PlowPriority::Low,
+            PlowPriority::Low => PlowPriority::Never,
+            PlowPriority::Never => PlowPriority::High,
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+struct DistrictPriority {
+    district: (i32, i32),
+    priority: PlowPriority,
+}
+
+#[derive(Compact, Clone)]
+struct CoverageLane {
+    lane: LaneID,
+    path: CPath,
+    successors: CVec<LaneID>,
+}
+
+#[derive(Compact, Clone)]
+pub struct WinterService {
+    id: WinterServiceID,
+    simulation: SimulationID,
+    user_interface: UserInterfaceID,
+    settings: WinterSettings,
+    depots: CVec<P2>,
+    placing_depot: bool,
+    priorities: CVec<DistrictPriority>,
+    snowing: bool,
+    /// Set while the snow of the last storm hasn't been plowed everywhere yet
+    snow_lying: bool,
+    collecting: bool,
+    lanes: CVec<CoverageLane>,
+    plows_sent: usize,
+}
+
+impl WinterService {
+    pub fn spawn(
+        id: WinterServiceID,
+        user_interface: UserInterfaceID,
+        simulation: SimulationID,
+        world: &mut World,
+    ) -> WinterService {
+        register_action(
+            "Place Snow Plow Depot",
+            Combo2::new(&[LControl, W], &[]),
+            id.into(),
+            world,
+        );
+        user_interface.add_2d(id.into(), world);
+        simulation.wake_up_in(Ticks(TICKS_PER_SIM_HOUR), id.into(), world);
+
+        WinterService {
+            id,
+            simulation,
+            user_interface,
+            settings: ::ENV.load_settings(SETTINGS_CATEGORY),
+            depots: CVec::new(),
+            placing_depot: false,
+            priorities: CVec::new(),
+            snowing: false,
+            snow_lying: false,
+            collecting: false,
+            lanes: CVec::new(),
+            plows_sent: 0,
+        }
+    }
+
+    fn priority_of(&self, district: (i32, i32)) -> PlowPriority {
+        self.priorities
+            .iter()
+            .find(|district_priority| district_priority.district == district)
+            .map(|district_priority| district_priority.priority)
+            .unwrap_or(PlowPriority::Normal)
+    }
+
+    fn set_priority(&mut self, district: (i32, i32), priority: PlowPriority) {
+        for district_priority in self.priorities.iter_mut() {
+            if district_priority.district == district {
+                district_priority.priority = priority;
+            }
+        }
+    }
+
+    fn is_winter(&self, tick: Timestamp) -> bool {
+        tick.day() % self.settings.days_per_year.max(1) < self.settings.winter_days
+    }
+
+    /// Makes sure every district with lanes can be given a priority
+    fn add_new_districts(&mut self) {
+        let districts = self.lanes
+            .iter()
+            .map(|coverage_lane| {
+                district_of(coverage_lane.path.along(coverage_lane.path.length() / 2.0))
+            })
+            .collect::<Vec<_>>();
+        for district in districts {
+            if !self.priorities.iter().any(|known| known.district == district) {
+                self.priorities.push(DistrictPriority {
+                    district,
+                    priority: PlowPriority::Normal,
+                });
+            }
+        }
+    }
+
+    /// Sends plows from the nearest depot along every lane of the districts
+    /// with the highest priority, as many as there are for this hour
+    fn send_out_plows(&mut self, world: &mut World) {
+        let mut districts = FnvHashMap::<(i32, i32), Vec<&CoverageLane>>::default();
+        for coverage_lane in self.lanes.iter() {
+            let middle = coverage_lane.path.along(coverage_lane.path.length() / 2.0);
+            districts.entry(district_of(middle)).or_insert_with(Vec::new).push(
+                coverage_lane,
+            );
+        }
+
+        let mut by_priority = districts
+            .keys()
+            .map(|&district| (self.priority_of(district), district))
+            .filter(|&(priority, _)| priority != PlowPriority::Never)
+            .collect::<Vec<_>>();
+        by_priority.sort_by_key(|&(priority, district)| (priority as usize, district));
+        let n_to_plow = by_priority.len();
+        let mut n_plows = 0;
+
+        for (_, district) in by_priority {
+            if n_plows >= self.settings.plows_per_hour {
+                break;
+            }
+
+            let district_lanes = &districts[&district];
+            let district_middle = P2::new(
+                (district.0 as N + 0.5) * DISTRICT_SIZE,
+                (district.1 as N + 0.5) * DISTRICT_SIZE,
+            );
+            let depot = match self.depots.iter().min_by_key(|depot| {
+                OrderedFloat((**depot - district_middle).norm())
+            }) {
+                Some(depot) => *depot,
+                None => break,
+            };
+            let start = district_lanes
+                .iter()
+                .min_by_key(|coverage_lane| {
+                    OrderedFloat((coverage_lane.path.start() - depot).norm())
+                })
+                .expect("districts always have lanes")
+                .lane;
+
+            let successors = district_lanes
+                .iter()
+                .map(|coverage_lane| {
+                    (coverage_lane.lane, coverage_lane.successors.to_vec())
+                })
+                .collect::<FnvHashMap<_, _>>();
+            let paths = district_lanes
+                .iter()
+                .map(|coverage_lane| (coverage_lane.lane, &coverage_lane.path))
+                .collect::<FnvHashMap<_, _>>();
+
+            ServiceVehicleID::spawn(
+                ServiceKind::SnowPlowing,
+                coverage_route(start, &successors)
+                    .into_iter()
+                    .map(|lane| (lane, paths[&lane].clone()))
+                    .collect(),
+                world,
+            );
+            n_plows += 1;
+        }
+
+        self.plows_sent = n_plows;
+        // with enough plows for everything, all the snow will be gone after this round
+        if n_plows == n_to_plow && !self.snowing {
+            self.snow_lying = false;
+        }
+    }
+}
+
+impl LaneGraphCollector for WinterService {
+    fn add_graph_lane(
+        &mut self,
+        lane: LaneID,
+        path: &CPath,
+        successors: &CVec<LaneID>,
+        _: &mut World,
+    ) {
+        if self.collecting {
+            self.lanes.push(CoverageLane {
+                lane,
+                path: path.clone(),
+                successors: successors.clone(),
+            });
+        }
+    }
+}
+
+impl Sleeper for WinterService {
+    fn wake(&mut self, current_tick: Timestamp, world: &mut World) {
+        if self.collecting {
+            self.collecting = false;
+            self.add_new_districts();
+            if self.snow_lying {
+                self.send_out_plows(world);
+            } else {
+                self.plows_sent = 0;
+            }
+            self.lanes = CVec::new();
+            self.simulation.wake_up_in(
+                Ticks(TICKS_PER_SIM_HOUR - COLLECTION_TICKS.0),
+                self.id.into(),
+                world,
+            );
+        } else {
+            self.snowing = self.is_winter(current_tick) &&
+                ::core::random::next_f32() < self.settings.snowstorm_chance;
+            let snowfall = if self.snowing {
+                self.snow_lying = true;
+                self.settings.snowfall_per_hour
+            } else {
+                0.0
+            };
+            LaneID::global_broadcast(world).snow_hour(snowfall, world);
+
+            // also collected without snow, to know the districts to give priorities to
+            if !self.depots.is_empty() {
+                self.collecting = true;
+                LaneID::global_broadcast(world).report_to_graph_collector(self.id.into(), world);
+                self.simulation.wake_up_in(COLLECTION_TICKS, self.id.into(), world);
+            } else {
+                self.plows_sent = 0;
+                self.simulation.wake_up_in(Ticks(TICKS_PER_SIM_HOUR), self.id.into(), world);
+            }
+        }
+    }
+}
+
+impl Lane {
+    /// Lets it snow for an hour, or just melts the snow a bit
+    pub fn snow_hour(&mut self, snowfall: f32, _: &mut World) {
+        self.snow = (self.snow + snowfall - SNOW_MELT_PER_HOUR).max(0.0).min(1.0);
+    }
+
+    pub fn plow(&mut self, _: &mut World) {
+        self.snow = 0.0;
+    }
+}
+
+impl ActionListener for WinterService {
+    fn on_action(&mut self, action: ActionId, phase: ActionPhase, world: &mut World) {
+        if phase == ActionPhase::Started && action == ActionId::named("Place Snow Plow Depot") {
+            self.placing_depot = !self.placing_depot;
+            set_tool_active(self.user_interface, self.id.into(), self.placing_depot, world);
+        }
+    }
+}
+
+impl Interactable3d for WinterService {
+    fn on_event(&mut self, event: Event3d, world: &mut World) {
+        if self.placing_depot {
+            match event {
+                Event3d::DragFinished { to, .. } => {
+                    let position = P2::new(to.x, to.y);
+                    let existing = self.depots.iter().position(|depot| {
+                        (*depot - position).norm() < DEPOT_REMOVAL_DISTANCE
+                    });
+                    match existing {
+                        Some(idx) => {
+                            self.depots.remove(idx);
+                        }
+                        None => self.depots.push(position),
+                    }
+                }
+                Event3d::Frame => {
+                    self.user_interface.add_debug_text(
+                        "Placing Snow Plow Depots".chars().collect(),
+                        "click to place a depot, or a depot to remove it"
+                            .chars()
+                            .collect(),
+                        [0.0, 0.0, 0.0, 1.0],
+                        false,
+                        world,
+                    );
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Renderable for WinterService {
+    fn setup_in_scene(&mut self, renderer_id: RendererID, scene_id: usize, world: &mut World) {
+        renderer_id.add_batch(
+            scene_id,
+            DEPOT_MARKER_BATCH_ID,
+            Geometry::new(
+                vec![
+                    Vertex { position: [-5.0, -5.0, 0.3] },
+                    Vertex { position: [5.0, -5.0, 0.3] },
+                    Vertex { position: [5.0, 5.0, 0.3] },
+                    Vertex { position: [-5.0, 5.0, 0.3] },
+                ],
+                vec![0, 1, 2, 2, 3, 0],
+            ),
+            world,
+        );
+    }
+
+    fn render_to_scene(
+        &mut self,
+        renderer_id: RendererID,
+        scene_id: usize,
+        frame: usize,
+        world: &mut World,
+    ) {
+        if !self.depots.is_empty() {
+            renderer_id.add_several_instances(
+                scene_id,
+                DEPOT_MARKER_BATCH_ID,
+                frame,
+                self.depots
+                    .iter()
+                    .map(|depot| {
+                        Instance {
+                            instance_position: [depot.x, depot.y, 0.0],
+                            instance_direction: [1.0, 0.0],
+                            instance_color: [0.2, 0.4, 1.0],
+                        }
+                    })
+                    .collect(),
+                world,
+            );
+        }
+    }
+}
+
+impl Interactable2d for WinterService {
+    fn draw_ui_2d(
+        &mut self,
+        imgui_ui: &External<::imgui::Ui<'static>>,
+        return_to: UserInterfaceID,
+        world: &mut World,
+    ) {
+        let ui = imgui_ui.steal();
+        let mut changed_priority = None;
+
+        ui.window(im_str!("Winter Service"))
+            .size((250.0, 200.0), ImGuiSetCond_FirstUseEver)
+            .collapsible(true)
+            .build(|| {
+                ui.text(im_str!(
+                    "{}",
+                    if self.snowing {
+                        "Snowing"
+                    } else if self.snow_lying {
+                        "Snow on the roads"
+                    } else {
+                        "Roads clear"
+                    }
+                ));
+                ui.text(im_str!(
+                    "{} depots, {} plows sent this hour",
+                    self.depots.len(),
+                    self.plows_sent
+                ));
+
+                if ui.collapsing_header(im_str!("Plowing priorities")).build() {
+                    for district_priority in self.priorities.iter() {
+                        let (x, y) = district_priority.district;
+                        ui.text(im_str!("({}, {})", x, y));
+                        ui.same_line(0.0);
+                        if ui.small_button(im_str!(
+                            "{}##priority{}_{}",
+                            district_priority.priority.name(),
+                            x,
+                            y
+                        ))
+                        {
+                            changed_priority = Some((
+                                district_priority.district,
+                                district_priority.priority.next(),
+                            ));
+                        }
+                    }
+                }
+            });
+
+        if let Some((district, priority)) = changed_priority {
+            self.set_priority(district, priority);
+        }
+
+        return_to.ui_drawn(ui, world);
+    }
+}
+
+pub fn setup(system: &mut ActorSystem, user_interface: UserInterfaceID, simulation: SimulationID) {
+    system.register::<WinterService>();
+    system.make_restorable::<WinterService>();
+    auto_setup(system);
+
+    WinterServiceID::spawn(user_interface, simulation, &mut system.world());
+}
+
+mod kay_auto;
+pub use self::kay_auto::*;
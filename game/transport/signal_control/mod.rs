@@ -0,0 +1,530 @@
+//! Adaptive signal control, loosely like SCATS: the `SignalController` watches the queues
+//! on all approaches of the intersections it was switched on for, and after every interval
+//! gives busier approaches a bigger share of the cycle, lengthening the whole cycle when
+//! queues get long and shortening it when they're short. Before it adapts anything, it
+//! measures the queues under the fixed timings, so the effect can be compared.
+//! Queued cars on approaches of a higher road class count more (see `road_hierarchy`).
+//! Once it knows the conflict matrix of an intersection's junction (see `junctions`),
+//! movements also get green during phases of other approaches whenever they don't
+//! conflict with anything that's green then, like right turns next to opposing lefts.
+//! Protected junctions keep their bike phase at the end of every cycle.
+use kay::{ActorSystem, World, External};
+use compact::CVec;
+use descartes::{P2, RoughlyComparable};
+use stagemaster::{UserInterfaceID, Event3d, Interactable3d, Interactable3dID,
+                  MSG_Interactable3d_on_event, Interactable2d, Interactable2dID,
+                  MSG_Interactable2d_draw_ui_2d};
+use stagemaster::combo::Combo2;
+use stagemaster::combo::Button::{LControl, G};
+use stagemaster::actions::{register_action, ActionId, ActionListener, ActionListenerID,
+                           ActionPhase, MSG_ActionListener_on_action};
+use transport::planning::current_plan::set_tool_active;
+use imgui::ImGuiSetCond_FirstUseEver;
+use core::simulation::{SimulationID, Sleeper, SleeperID, MSG_Sleeper_wake, Timestamp, Ticks};
+use super::lane::{Lane, LaneID};
+use super::lane::connectivity::InteractionKind;
+use super::turning_movements::{TurningMovementsID, IntersectionCounts, IntersectionCountsRequester,
+                               IntersectionCountsRequesterID,
+                               MSG_IntersectionCountsRequester_on_intersections};
+use super::signal_warrants::{group_by_approach, phase_timings, GREEN_ENTRIES, CLEARANCE_ENTRIES};
+use super::junctions::{ConflictMatrix, with_compatible_greens};
+use super::junctions::protected::{compatible_with_crossings, with_bike_phase};
+
+/// Queues are sampled and timings adapted this often
+const ADAPT_INTERVAL: Ticks = Ticks(120);
+/// Intervals measured with the fixed timings before adapting
+const BASELINE_INTERVALS: usize = 10;
+/// Green per approach, in signal timing entries of 10 ticks
+const MIN_GREEN: usize = 2;
+const MAX_GREEN: usize = 9;
+/// The cycle gets longer if any approach queues more cars than this,
+/// and shorter if all of them queue fewer than `SHORT_QUEUE`
+const LONG_QUEUE: f32 = 6.0;
+const SHORT_QUEUE: f32 = 2.0;
+/// Clicks closer than this to an intersection's center pick it
+const PICK_DISTANCE: f32 = 40.0;
+
+/// New greens for each approach, given their current `greens` and `queues`
+pub fn adapt_greens(greens: &[usize], queues: &[f32]) -> Vec<usize> {
+    let n_approaches = greens.len();
+    let current_total: usize = greens.iter().sum();
+    let max_queue = queues.iter().cloned().fold(0.0, f32::max);
+
+    let total = if max_queue > LONG_QUEUE {
+        (current_total + n_approaches).min(n_approaches * MAX_GREEN)
+    } else if max_queue < SHORT_QUEUE {
+        current_total.saturating_sub(n_approaches).max(n_approaches * MIN_GREEN)
+    } else {
+        current_total
+    };
+
+    // every approach gets its minimum, the rest is split by queue length
+    let distributable = total.saturating_sub(n_approaches * MIN_GREEN);
+    let total_queue: f32 = queues.iter().sum();
+    let mut new_greens = queues
+        .iter()
+        .map(|&queue| {
+            let share = if total_queue > 0.0 {
+                queue / total_queue
+            } else {
+                1.0 / n_approaches as f32
+            };
+            MIN_GREEN + (share * distributable as f32).floor() as usize
+        })
+        .collect::<Vec<_>>();
+
+    // rounding leftovers go to the longest queue
+    let leftover = total.saturating_sub(new_greens.iter().sum());
+    if let Some(longest) = (0..n_approaches).max_by(|&a, &b| {
+        queues[a].partial_cmp(&queues[b]).unwrap()
+    })
+    {
+        new_greens[longest] += leftover;
+    }
+
+    new_greens
+}
+
+#[derive(Compact, Clone)]
+struct AdaptiveIntersection {
+    key: u32,
+    center: P2,
+    /// Lanes across the intersection, with their approach
+    movements: CVec<(LaneID, usize)>,
+    /// Lanes leading into the intersection, with their approach
+    approach_lanes: CVec<(LaneID, usize)>,
+    greens: CVec<usize>,
+    /// Queued cars on each approach, in the current interval
+    queues: CVec<usize>,
+    /// Weight of the queue on each approach, from the class of its highest road
+    priorities: CVec<f32>,
+    baseline_intervals_left: usize,
+    baseline_queued: usize,
+    baseline_intervals: usize,
+    adaptive_queued: usize,
+    adaptive_intervals: usize,
+    /// Whether two movements may have green at the same time, from the conflict matrix
+    /// of the junction. Empty until the junction reported it
+    compatible: CVec<CVec<bool>>,
+    /// For protected junctions, which movements are green during the bike phase
+    bike_phase_greens: CVec<bool>,
+    /// Whether the intersection had signals before it started adapting
+    signalized: bool,
+}
+
+impl AdaptiveIntersection {
+    fn apply_timings(&self, world: &mut World) {
+        let approach_of = self.movements
+            .iter()
+            .map(|&(_, approach)| approach)
+            .collect::<Vec<_>>();
+        let timings = phase_timings(&approach_of, &self.greens);
+        let timings = if self.compatible.is_empty() {
+            timings
+        } else {
+            with_compatible_greens(&timings, |a, b| self.compatible[a][b])
+        };
+        let timings = if self.bike_phase_greens.is_empty() {
+            timings
+        } else {
+            with_bike_phase(&timings, &self.bike_phase_greens)
+        };
+        for (&(lane, _), timings) in self.movements.iter().zip(timings) {
+            lane.set_signal_timings(timings, world);
+        }
+    }
+}
+
+impl Lane {
+    pub fn report_approach_lanes(
+        &mut self,
+        controller: SignalControllerID,
+        key: u32,
+        approach: usize,
+        world: &mut World,
+    ) {
+        for interaction in self.connectivity.interactions.iter() {
+            if let InteractionKind::Previous = interaction.kind {
+                let approach_lane = interaction.partner_lane.into();
+                controller.add_approach_lane(key, approach, approach_lane, world);
+            }
+        }
+    }
+
+    pub fn report_queue(
+        &mut self,
+        controller: SignalControllerID,
+        key: u32,
+        approach: usize,
+        world: &mut World,
+    ) {
+        controller.add_queue(
+            key,
+            approach,
+            self.microtraffic.queue_length(),
+            self.road_class.signal_priority(),
+            world,
+        );
+    }
+}
+
+#[derive(Compact, Clone)]
+pub struct SignalController {
+    id: SignalControllerID,
+    simulation: SimulationID,
+    user_interface: UserInterfaceID,
+    intersections: CVec<AdaptiveIntersection>,
+    next_key: u32,
+    picking: bool,
+    picked: Option<P2>,
+}
+
+impl SignalController {
+    pub fn spawn(
+        id: SignalControllerID,
+        simulation: SimulationID,
+        user_interface: UserInterfaceID,
+        world: &mut World,
+    ) -> SignalController {
+        user_interface.add_2d(id.into(), world);
+        register_action(
+            "Make Signals Adaptive",
+            Combo2::new(&[LControl, G], &[]),
+            id.into(),
+            world,
+        );
+        simulation.wake_up_in(ADAPT_INTERVAL, id.into(), world);
+
+        SignalController {
+            id,
+            simulation,
+            user_interface,
+            intersections: CVec::new(),
+            next_key: 0,
+            picking: false,
+            picked: None,
+        }
+    }
+
+    pub fn add_approach_lane(
+        &mut self,
+        key: u32,
+        approach: usize,
+        lane: LaneID,
+        _: &mut World,
+    ) {
+        if let Some(intersection) = self.intersections.iter_mut().find(
+            |intersection| intersection.key == key,
+        )
+        {
+            if !intersection.approach_lanes.iter().any(|&(known, _)| known == lane) {
+                intersection.approach_lanes.push((lane, approach));
+            }
+        }
+    }
+
+    pub fn add_queue(
+        &mut self,
+        key: u32,
+        approach: usize,
+        queue: usize,
+        priority: f32,
+        _: &mut World,
+    ) {
+        if let Some(intersection) = self.intersections.iter_mut().find(
+            |intersection| intersection.key == key,
+        )
+        {
+            intersection.queues[approach] += queue;
+            intersection.priorities[approach] = intersection.priorities[approach].max(priority);
+        }
+    }
+
+    pub fn on_conflicts(
+        &mut self,
+        key: u32,
+        lanes: &CVec<Option<LaneID>>,
+        conflicts: &ConflictMatrix,
+        world: &mut World,
+    ) {
+        if let Some(intersection) = self.intersections.iter_mut().find(
+            |intersection| intersection.key == key,
+        )
+        {
+            let movement_of = intersection
+                .movements
+                .iter()
+                .map(|&(lane, _)| lanes.iter().position(|&known| known == Some(lane)))
+                .collect::<Vec<_>>();
+            intersection.compatible = movement_of
+                .iter()
+                .map(|&a| {
+                    movement_of
+                        .iter()
+                        .map(|&b| match (a, b) {
+                            (Some(a), Some(b)) => conflicts.relation(a, b).compatible(),
+                            _ => false,
+                        })
+                        .collect()
+                })
+                .collect();
+            if conflicts.n_movements() > lanes.len() {
+                let with_crossings = compatible_with_crossings(conflicts, lanes.len());
+                intersection.bike_phase_greens = movement_of
+                    .iter()
+                    .map(|&movement| movement.map_or(false, |m| with_crossings[m]))
+                    .collect();
+            }
+            // intersections without signals only get them once they start adapting
+            if intersection.signalized || intersection.baseline_intervals_left == 0 {
+                intersection.apply_timings(world);
+            }
+        }
+    }
+
+    /// Goes back to fixed timings, with the same green for every approach
+    /// (and the bike phase of protected junctions)
+    fn stop_adapting(&mut self, idx: usize, world: &mut World) {
+        let mut intersection = self.intersections.remove(idx);
+        intersection.greens = vec![GREEN_ENTRIES; intersection.greens.len()].into();
+        intersection.compatible = CVec::new();
+        intersection.apply_timings(world);
+    }
+}
+
+impl IntersectionCountsRequester for SignalController {
+    fn on_intersections(&mut self, intersections: &CVec<IntersectionCounts>, world: &mut World) {
+        let position = match self.picked.take() {
+            Some(position) => position,
+            None => return,
+        };
+
+        let maybe_closest = intersections
+            .iter()
+            .filter(|intersection| {
+                intersection.center.is_roughly_within(position, PICK_DISTANCE) &&
+                    !self.intersections.iter().any(|adaptive| {
+                        adaptive.center.is_roughly_within(intersection.center, 1.0)
+                    })
+            })
+            .min_by(|a, b| {
+                let distance = |intersection: &IntersectionCounts| {
+                    (intersection.center - position).norm()
+                };
+                distance(a).partial_cmp(&distance(b)).unwrap()
+            });
+
+        if let Some(intersection) = maybe_closest {
+            let headings = intersection
+                .movements
+                .iter()
+                .map(|&(_, heading)| heading)
+                .collect::<Vec<_>>();
+            let (approach_of, n_approaches) = group_by_approach(&headings);
+            let key = self.next_key;
+            self.next_key += 1;
+
+            let adaptive = AdaptiveIntersection {
+                key,
+                center: intersection.center,
+                movements: intersection
+                    .movements
+                    .iter()
+                    .zip(approach_of)
+                    .map(|(&(lane, _), approach)| (lane, approach))
+                    .collect(),
+                approach_lanes: CVec::new(),
+                greens: vec![(MIN_GREEN + MAX_GREEN) / 2; n_approaches].into(),
+                queues: vec![0; n_approaches].into(),
+                priorities: vec![1.0; n_approaches].into(),
+                baseline_intervals_left: BASELINE_INTERVALS,
+                baseline_queued: 0,
+                baseline_intervals: 0,
+                adaptive_queued: 0,
+                adaptive_intervals: 0,
+                compatible: CVec::new(),
+                bike_phase_greens: CVec::new(),
+                signalized: intersection.signalized,
+            };
+
+            for &(lane, approach) in adaptive.movements.iter() {
+                lane.report_approach_lanes(self.id, key, approach, world);
+            }
+            if let Some(&(lane, _)) = adaptive.movements.first() {
+                lane.report_conflicts(self.id, key, world);
+            }
+            // unsignalized intersections only get signals once they start adapting
+            if intersection.signalized {
+                adaptive.apply_timings(world);
+            }
+            self.intersections.push(adaptive);
+        }
+    }
+}
+
+impl Sleeper for SignalController {
+    fn wake(&mut self, _current_tick: Timestamp, world: &mut World) {
+        for intersection in self.intersections.iter_mut() {
+            let total_queued: usize = intersection.queues.iter().sum();
+
+            if intersection.baseline_intervals_left > 0 {
+                intersection.baseline_intervals_left -= 1;
+                intersection.baseline_queued += total_queued;
+                intersection.baseline_intervals += 1;
+            } else {
+                intersection.adaptive_queued += total_queued;
+                intersection.adaptive_intervals += 1;
+                let queues = intersection
+                    .queues
+                    .iter()
+                    .zip(intersection.priorities.iter())
+                    .map(|(&queue, &priority)| queue as f32 * priority)
+                    .collect::<Vec<_>>();
+                intersection.greens = adapt_greens(&intersection.greens, &queues).into();
+                intersection.apply_timings(world);
+            }
+
+            for queue in intersection.queues.iter_mut() {
+                *queue = 0;
+            }
+            for priority in intersection.priorities.iter_mut() {
+                *priority = 1.0;
+            }
+            for &(lane, approach) in intersection.approach_lanes.iter() {
+                lane.report_queue(self.id, intersection.key, approach, world);
+            }
+        }
+
+        self.simulation.wake_up_in(ADAPT_INTERVAL, self.id.into(), world);
+    }
+}
+
+impl ActionListener for SignalController {
+    fn on_action(&mut self, action: ActionId, phase: ActionPhase, world: &mut World) {
+        if phase == ActionPhase::Started && action == ActionId::named("Make Signals Adaptive") {
+            self.picking = !self.picking;
+            set_tool_active(self.user_interface, self.id.into(), self.picking, world);
+        }
+    }
+}
+
+impl Interactable3d for SignalController {
+    fn on_event(&mut self, event: Event3d, world: &mut World) {
+        if self.picking {
+            match event {
+                Event3d::DragFinished { to, .. } => {
+                    self.picked = Some(P2::new(to.x, to.y));
+                    TurningMovementsID::local_first(world).report_intersections(
+                        self.id.into(),
+                        world,
+                    );
+                    self.picking = false;
+                    self.user_interface.remove(self.id.into(), world);
+                }
+                Event3d::Frame => {
+                    self.user_interface.add_debug_text(
+                        "Making signals adaptive".chars().collect(),
+                        "click an intersection".chars().collect(),
+                        [0.0, 0.0, 0.0, 1.0],
+                        false,
+                        world,
+                    );
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Interactable2d for SignalController {
+    fn draw_ui_2d(
+        &mut self,
+        imgui_ui: &External<::imgui::Ui<'static>>,
+        return_to: UserInterfaceID,
+        world: &mut World,
+    ) {
+        let ui = imgui_ui.steal();
+        let mut to_stop = None;
+
+        if !self.intersections.is_empty() {
+            ui.window(im_str!("Adaptive Signals"))
+                .size((300.0, 200.0), ImGuiSetCond_FirstUseEver)
+                .collapsible(true)
+                .build(|| for (i, intersection) in self.intersections.iter().enumerate() {
+                    ui.text(im_str!(
+                        "({:.0}, {:.0}): greens {:?}, cycle {} s",
+                        intersection.center.x,
+                        intersection.center.y,
+                        intersection.greens.iter().map(|green| green * 10).collect::<Vec<_>>(),
+                        intersection
+                            .greens
+                            .iter()
+                            .map(|green| (green + CLEARANCE_ENTRIES) * 10)
+                            .sum::<usize>()
+                    ));
+                    let average = |queued: usize, intervals: usize| if intervals > 0 {
+                        queued as f32 / intervals as f32
+                    } else {
+                        0.0
+                    };
+                    if intersection.baseline_intervals_left > 0 {
+                        ui.text(im_str!(
+                            "Measuring fixed timings, {} intervals left",
+                            intersection.baseline_intervals_left
+                        ));
+                    } else {
+                        ui.text(im_str!(
+                            "Cars queued: {:.1} with fixed timings, {:.1} adaptive",
+                            average(intersection.baseline_queued, intersection.baseline_intervals),
+                            average(intersection.adaptive_queued, intersection.adaptive_intervals)
+                        ));
+                    }
+                    if ui.small_button(im_str!("Back to fixed##{}", i)) {
+                        to_stop = Some(i);
+                    }
+                });
+        }
+
+        if let Some(idx) = to_stop {
+            self.stop_adapting(idx, world);
+        }
+
+        return_to.ui_drawn(ui, world);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{adapt_greens, MIN_GREEN, MAX_GREEN};
+
+    #[test]
+    fn busier_approaches_get_more_green() {
+        let greens = adapt_greens(&[5, 5], &[4.0, 1.0]);
+        assert!(greens[0] > greens[1]);
+        assert_eq!(greens.iter().sum::<usize>(), 10);
+    }
+
+    #[test]
+    fn cycle_follows_queue_length() {
+        let longer = adapt_greens(&[5, 5], &[10.0, 10.0]);
+        assert!(longer.iter().sum::<usize>() > 10);
+        let shorter = adapt_greens(&[5, 5], &[1.0, 0.0]);
+        assert!(shorter.iter().sum::<usize>() < 10);
+
+        let longest = adapt_greens(&[MAX_GREEN, MAX_GREEN], &[20.0, 20.0]);
+        assert_eq!(longest, vec![MAX_GREEN, MAX_GREEN]);
+        let shortest = adapt_greens(&[MIN_GREEN, MIN_GREEN], &[0.0, 0.0]);
+        assert_eq!(shortest, vec![MIN_GREEN, MIN_GREEN]);
+    }
+}
+
+pub fn setup(system: &mut ActorSystem, user_interface: UserInterfaceID, simulation: SimulationID) {
+    system.register::<SignalController>();
+    system.make_restorable::<SignalController>();
+    auto_setup(system);
+
+    SignalControllerID::spawn(simulation, user_interface, &mut system.world());
+}
+
+mod kay_auto;
+pub use self::kay_auto::*;
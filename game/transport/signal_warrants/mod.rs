@@ -0,0 +1,298 @@
+//! Watches unsignalized intersections for sustained high volumes and delays, using the
+//! turning movement counts, and suggests giving them traffic signals or a roundabout.
+//! Signals can be applied right away, roundabouts get drawn into the current plan.
+use kay::{ActorSystem, World, External};
+use compact::CVec;
+use descartes::{P2, V2, N, RoughlyComparable};
+use stagemaster::{UserInterfaceID, Interactable2d, Interactable2dID,
+                  MSG_Interactable2d_draw_ui_2d};
+use imgui::ImGuiSetCond_FirstUseEver;
+use core::simulation::{SimulationID, Sleeper, SleeperID, MSG_Sleeper_wake, Timestamp, Ticks,
+                       TICKS_PER_SIM_MINUTE};
+use super::lane::LaneID;
+use super::turning_movements::{TurningMovementsID, IntersectionCounts, IntersectionCountsRequester,
+                               IntersectionCountsRequesterID,
+                               MSG_IntersectionCountsRequester_on_intersections};
+use super::planning::current_plan::{CurrentPlanID, Intent, IntentProgress};
+
+const ANALYSIS_INTERVAL: Ticks = Ticks(15 * TICKS_PER_SIM_MINUTE);
+/// A warrant has to be met this many analyses in a row before it's suggested
+const SUSTAINED_ANALYSES: usize = 4;
+/// Cars per hour and average seconds of waiting per car that warrant signals
+const SIGNAL_VOLUME: usize = 500;
+const SIGNAL_DELAY: f32 = 30.0;
+/// Cars per hour and average seconds of waiting per car that warrant a roundabout
+const ROUNDABOUT_VOLUME: usize = 200;
+const ROUNDABOUT_DELAY: f32 = 15.0;
+const ROUNDABOUT_RADIUS: N = 20.0;
+/// Intersections whose centers are closer than this between analyses are the same one
+const SAME_INTERSECTION_DISTANCE: f32 = 5.0;
+/// Signal timings have one entry per 10 ticks, each approach gets green for this many
+pub const GREEN_ENTRIES: usize = 4;
+/// All red between approaches, so the intersection can clear
+pub const CLEARANCE_ENTRIES: usize = 1;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Control {
+    Signals,
+    Roundabout,
+}
+
+/// Which control, if any, an unsignalized intersection with that many cars
+/// per hour and that much waiting per car needs
+pub fn warranted_control(per_hour: usize, delay_per_car: f32) -> Option<Control> {
+    if per_hour >= SIGNAL_VOLUME && delay_per_car >= SIGNAL_DELAY {
+        Some(Control::Signals)
+    } else if per_hour >= ROUNDABOUT_VOLUME && delay_per_car >= ROUNDABOUT_DELAY {
+        Some(Control::Roundabout)
+    } else {
+        None
+    }
+}
+
+/// The approach of each movement entering an intersection with the given `headings`,
+/// and the number of approaches. Movements with the same heading share an approach
+pub fn group_by_approach(headings: &[V2]) -> (Vec<usize>, usize) {
+    let mut approaches: Vec<V2> = Vec::new();
+    let mut approach_of = Vec::with_capacity(headings.len());
+    for heading in headings {
+        let existing = approaches.iter().position(|approach| {
+            approach.is_roughly_within(*heading, 0.3)
+        });
+        approach_of.push(match existing {
+            Some(approach) => approach,
+            None => {
+                approaches.push(*heading);
+                approaches.len() - 1
+            }
+        });
+    }
+    (approach_of, approaches.len())
+}
+
+/// One phase per approach, in turn, each green for its entry in `greens` and followed
+/// by an all red clearance: timings for movements from the approaches in `approach_of`
+pub fn phase_timings(approach_of: &[usize], greens: &[usize]) -> Vec<CVec<bool>> {
+    let mut phase_starts = Vec::with_capacity(greens.len());
+    let mut cycle_length = 0;
+    for green in greens {
+        phase_starts.push(cycle_length);
+        cycle_length += green + CLEARANCE_ENTRIES;
+    }
+
+    approach_of
+        .iter()
+        .map(|&approach| {
+            let (start, green) = (phase_starts[approach], greens[approach]);
+            (0..cycle_length).map(|t| t >= start && t < start + green).collect()
+        })
+        .collect()
+}
+
+/// Every approach gets the same, fixed green
+pub fn split_phase_timings(headings: &[V2]) -> Vec<CVec<bool>> {
+    let (approach_of, n_approaches) = group_by_approach(headings);
+    phase_timings(&approach_of, &vec![GREEN_ENTRIES; n_approaches])
+}
+
+#[derive(Compact, Clone)]
+struct Warrant {
+    center: P2,
+    movements: CVec<(LaneID, V2)>,
+    control: Control,
+    per_hour: usize,
+    delay_per_car: f32,
+    sustained: usize,
+}
+
+/// Every analysis asks `TurningMovements` for the latest counts of all intersections
+/// and keeps those that meet a warrant, counting for how many analyses in a row they did
+#[derive(Compact, Clone)]
+pub struct SignalWarrants {
+    id: SignalWarrantsID,
+    simulation: SimulationID,
+    warrants: CVec<Warrant>,
+    /// Intersections the user already acted on or doesn't want suggestions for
+    dismissed: CVec<P2>,
+}
+
+impl SignalWarrants {
+    pub fn spawn(
+        id: SignalWarrantsID,
+        simulation: SimulationID,
+        user_interface: UserInterfaceID,
+        world: &mut World,
+    ) -> SignalWarrants {
+        user_interface.add_2d(id.into(), world);
+        simulation.wake_up_in(ANALYSIS_INTERVAL, id.into(), world);
+
+        SignalWarrants {
+            id,
+            simulation,
+            warrants: CVec::new(),
+            dismissed: CVec::new(),
+        }
+    }
+
+    fn apply(&mut self, idx: usize, world: &mut World) {
+        let warrant = self.warrants.remove(idx);
+        self.dismissed.push(warrant.center);
+
+        match warrant.control {
+            Control::Signals => {
+                let headings = warrant
+                    .movements
+                    .iter()
+                    .map(|&(_, heading)| heading)
+                    .collect::<Vec<_>>();
+                for (&(lane, _), timings) in
+                    warrant.movements.iter().zip(split_phase_timings(&headings))
+                {
+                    lane.set_signal_timings(timings, world);
+                }
+            }
+            Control::Roundabout => {
+                CurrentPlanID::local_first(world).change_intent(
+                    Intent::NewRoundabout(warrant.center, ROUNDABOUT_RADIUS),
+                    IntentProgress::Immediate,
+                    world,
+                );
+            }
+        }
+    }
+}
+
+impl IntersectionCountsRequester for SignalWarrants {
+    fn on_intersections(&mut self, intersections: &CVec<IntersectionCounts>, _: &mut World) {
+        let same = |a: P2, b: P2| a.is_roughly_within(b, SAME_INTERSECTION_DISTANCE);
+
+        let warrants = intersections
+            .iter()
+            .filter(|intersection| {
+                !intersection.signalized && intersection.per_hour > 0 &&
+                    !self.dismissed.iter().any(|&dismissed| same(dismissed, intersection.center))
+            })
+            .filter_map(|intersection| {
+                let delay_per_car = intersection.waiting_ticks as f32 /
+                    intersection.per_hour as f32;
+                warranted_control(intersection.per_hour, delay_per_car).map(|control| {
+                    let previously = self.warrants
+                        .iter()
+                        .find(|warrant| same(warrant.center, intersection.center))
+                        .map(|warrant| warrant.sustained)
+                        .unwrap_or(0);
+                    Warrant {
+                        center: intersection.center,
+                        movements: intersection.movements.clone(),
+                        control,
+                        per_hour: intersection.per_hour,
+                        delay_per_car,
+                        sustained: previously + 1,
+                    }
+                })
+            })
+            .collect();
+
+        self.warrants = warrants;
+    }
+}
+
+impl Sleeper for SignalWarrants {
+    fn wake(&mut self, _current_tick: Timestamp, world: &mut World) {
+        TurningMovementsID::local_first(world).report_intersections(self.id.into(), world);
+        self.simulation.wake_up_in(ANALYSIS_INTERVAL, self.id.into(), world);
+    }
+}
+
+impl Interactable2d for SignalWarrants {
+    fn draw_ui_2d(
+        &mut self,
+        imgui_ui: &External<::imgui::Ui<'static>>,
+        return_to: UserInterfaceID,
+        world: &mut World,
+    ) {
+        let ui = imgui_ui.steal();
+        let mut to_apply = None;
+        let mut to_dismiss = None;
+
+        ui.window(im_str!("Signal Warrants"))
+            .size((300.0, 200.0), ImGuiSetCond_FirstUseEver)
+            .collapsible(true)
+            .build(|| {
+                let mut any = false;
+                for (i, warrant) in self.warrants.iter().enumerate() {
+                    if warrant.sustained < SUSTAINED_ANALYSES {
+                        continue;
+                    }
+                    any = true;
+                    ui.text(im_str!(
+                        "({:.0}, {:.0}): {} cars/h, {:.0} s waiting per car",
+                        warrant.center.x,
+                        warrant.center.y,
+                        warrant.per_hour,
+                        warrant.delay_per_car
+                    ));
+                    let action = match warrant.control {
+                        Control::Signals => "Add Signals",
+                        Control::Roundabout => "Plan Roundabout",
+                    };
+                    if ui.small_button(im_str!("{}##{}", action, i)) {
+                        to_apply = Some(i);
+                    }
+                    ui.same_line(0.0);
+                    if ui.small_button(im_str!("Dismiss##{}", i)) {
+                        to_dismiss = Some(i);
+                    }
+                }
+                if !any {
+                    ui.text(im_str!("No intersection needs other control"));
+                }
+            });
+
+        if let Some(idx) = to_apply {
+            self.apply(idx, world);
+        } else if let Some(idx) = to_dismiss {
+            let warrant = self.warrants.remove(idx);
+            self.dismissed.push(warrant.center);
+        }
+
+        return_to.ui_drawn(ui, world);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Control, warranted_control, split_phase_timings};
+    use descartes::V2;
+
+    #[test]
+    fn busier_and_slower_intersections_warrant_more_control() {
+        assert_eq!(warranted_control(100, 60.0), None);
+        assert_eq!(warranted_control(300, 20.0), Some(Control::Roundabout));
+        assert_eq!(warranted_control(800, 20.0), Some(Control::Roundabout));
+        assert_eq!(warranted_control(800, 45.0), Some(Control::Signals));
+    }
+
+    #[test]
+    fn each_approach_gets_its_own_green() {
+        let east = V2::new(1.0, 0.0);
+        let north = V2::new(0.0, 1.0);
+        let timings = split_phase_timings(&[east, east, north]);
+
+        assert!(timings[0].iter().eq(timings[1].iter()));
+        for t in 0..timings[0].len() {
+            assert!(!(timings[0][t] && timings[2][t]));
+        }
+        assert!(timings[2].iter().any(|&green| green));
+    }
+}
+
+pub fn setup(system: &mut ActorSystem, user_interface: UserInterfaceID, simulation: SimulationID) {
+    system.register::<SignalWarrants>();
+    auto_setup(system);
+
+    SignalWarrantsID::spawn(simulation, user_interface, &mut system.world());
+}
+
+mod kay_auto;
+pub use self::kay_auto::*;
@@ -0,0 +1,235 @@
+//! A city-wide grid of lanes and buildings, so proximity queries only have to look at
+//! things in nearby cells instead of broadcasting to every lane or building.
+//!
+//! Lanes register once their construction is finished and buildings once they are
+//! spawned. There are no parked car actors yet, so they can't be indexed.
+use kay::{ActorSystem, World};
+use compact::{CVec, CHashMap};
+use descartes::{P2, N, Norm, Curve, Path, HasBoundingBox, BoundingBox};
+use stagemaster::geometry::CPath;
+use economy::buildings::BuildingID;
+use super::lane::LaneID;
+
+/// Width and height of a grid cell, in meters
+const CELL_SIZE: N = 100.0;
+
+type Cell = (i32, i32);
+
+fn cell_of(point: P2) -> Cell {
+    (
+        (point.x / CELL_SIZE).floor() as i32,
+        (point.y / CELL_SIZE).floor() as i32,
+    )
+}
+
+/// All cells touched by `bbox`
+fn cells_covering(bbox: &BoundingBox) -> Vec<Cell> {
+    let (x_start, y_start) = cell_of(bbox.min);
+    let (x_end, y_end) = cell_of(bbox.max);
+    let mut cells = Vec::new();
+    for x in x_start..(x_end + 1) {
+        for y in y_start..(y_end + 1) {
+            cells.push((x, y));
+        }
+    }
+    cells
+}
+
+fn path_cells(path: &CPath) -> Vec<Cell> {
+    let mut cells = path.segments()
+        .iter()
+        .flat_map(|segment| cells_covering(&segment.bounding_box()))
+        .collect::<Vec<_>>();
+    cells.sort();
+    cells.dedup();
+    cells
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Indexed {
+    Lane(LaneID),
+    Building(BuildingID),
+}
+
+#[derive(Compact, Clone)]
+pub struct SpatialIndex {
+    id: SpatialIndexID,
+    cells: CHashMap<Cell, CVec<Indexed>>,
+    lane_paths: CHashMap<LaneID, CPath>,
+    building_positions: CHashMap<BuildingID, P2>,
+}
+
+pub trait NearestLaneRequester {
+    /// `lane` is the nearest lane to `point` and its distance, if one was close enough
+    fn on_nearest_lane(&mut self, point: P2, lane: Option<(LaneID, N)>, world: &mut World);
+}
+
+pub trait BuildingsNearbyRequester {
+    fn on_buildings_nearby(
+        &mut self,
+        center: P2,
+        buildings: &CVec<BuildingID>,
+        world: &mut World,
+    );
+}
+
+pub trait LanesInAreaRequester {
+    fn on_lanes_in_area(&mut self, lanes: &CVec<LaneID>, world: &mut World);
+}
+
+impl SpatialIndex {
+    pub fn spawn(id: SpatialIndexID, _: &mut World) -> SpatialIndex {
+        SpatialIndex {
+            id,
+            cells: CHashMap::new(),
+            lane_paths: CHashMap::new(),
+            building_positions: CHashMap::new(),
+        }
+    }
+
+    fn remove_from_cells(&mut self, item: Indexed, cells: Vec<Cell>) {
+        for cell in cells {
+            let now_empty = match self.cells.get_mut(cell) {
+                Some(items) => {
+                    items.retain(|other| *other != item);
+                    items.is_empty()
+                }
+                None => false,
+            };
+            if now_empty {
+                self.cells.remove(cell);
+            }
+        }
+    }
+
+    /// Everything in any of `cells`, each only once even if it spans several of them
+    fn items_in(&self, cells: Vec<Cell>) -> Vec<Indexed> {
+        let mut items = Vec::new();
+        for cell in cells {
+            for item in self.cells.get_iter(cell) {
+                if !items.contains(item) {
+                    items.push(*item);
+                }
+            }
+        }
+        items
+    }
+
+    pub fn add_lane(&mut self, lane: LaneID, path: &CPath, world: &mut World) {
+        self.remove_lane(lane, world);
+        for cell in path_cells(path) {
+            self.cells.push_at(cell, Indexed::Lane(lane));
+        }
+        self.lane_paths.insert(lane, path.clone());
+    }
+
+    pub fn remove_lane(&mut self, lane: LaneID, _: &mut World) {
+        if let Some(path) = self.lane_paths.remove(lane) {
+            self.remove_from_cells(Indexed::Lane(lane), path_cells(&path));
+        }
+    }
+
+    pub fn add_building(&mut self, building: BuildingID, position: P2, world: &mut World) {
+        self.remove_building(building, world);
+        self.cells.push_at(cell_of(position), Indexed::Building(building));
+        self.building_positions.insert(building, position);
+    }
+
+    pub fn remove_building(&mut self, building: BuildingID, _: &mut World) {
+        if let Some(position) = self.building_positions.remove(building) {
+            self.remove_from_cells(Indexed::Building(building), vec![cell_of(position)]);
+        }
+    }
+
+    pub fn find_nearest_lane(
+        &mut self,
+        point: P2,
+        max_distance: N,
+        requester: NearestLaneRequesterID,
+        world: &mut World,
+    ) {
+        let search_area = BoundingBox::point(point).grown_by(max_distance);
+        let nearest = self.items_in(cells_covering(&search_area))
+            .into_iter()
+            .filter_map(|item| match item {
+                Indexed::Lane(lane) => {
+                    self.lane_paths.get(lane).map(
+                        |path| (lane, path.distance_to(point)),
+                    )
+                }
+                Indexed::Building(_) => None,
+            })
+            .filter(|&(_, distance)| distance <= max_distance)
+            .min_by(|&(_, a), &(_, b)| a.partial_cmp(&b).unwrap());
+
+        requester.on_nearest_lane(point, nearest, world);
+    }
+
+    pub fn find_buildings_nearby(
+        &mut self,
+        center: P2,
+        radius: N,
+        requester: BuildingsNearbyRequesterID,
+        world: &mut World,
+    ) {
+        let search_area = BoundingBox::point(center).grown_by(radius);
+        let buildings = self.items_in(cells_covering(&search_area))
+            .into_iter()
+            .filter_map(|item| match item {
+                Indexed::Building(building) => {
+                    self.building_positions.get(building).and_then(|position| {
+                        if (*position - center).norm() <= radius {
+                            Some(building)
+                        } else {
+                            None
+                        }
+                    })
+                }
+                Indexed::Lane(_) => None,
+            })
+            .collect();
+
+        requester.on_buildings_nearby(center, buildings, world);
+    }
+
+    pub fn find_lanes_in_area(
+        &mut self,
+        min: P2,
+        max: P2,
+        requester: LanesInAreaRequesterID,
+        world: &mut World,
+    ) {
+        let area = BoundingBox { min, max };
+        let lanes = self.items_in(cells_covering(&area))
+            .into_iter()
+            .filter_map(|item| match item {
+                Indexed::Lane(lane) => {
+                    self.lane_paths.get(lane).and_then(|path| {
+                        if path.segments().iter().any(|segment| {
+                            segment.bounding_box().overlaps(&area)
+                        })
+                        {
+                            Some(lane)
+                        } else {
+                            None
+                        }
+                    })
+                }
+                Indexed::Building(_) => None,
+            })
+            .collect();
+
+        requester.on_lanes_in_area(lanes, world);
+    }
+}
+
+pub fn setup(system: &mut ActorSystem) {
+    system.register::<SpatialIndex>();
+    system.make_restorable::<SpatialIndex>();
+    auto_setup(system);
+
+    SpatialIndexID::spawn(&mut system.world());
+}
+
+mod kay_auto;
+pub use self::kay_auto::*;
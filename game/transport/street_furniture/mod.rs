@@ -0,0 +1,599 @@
+//! Trees, street lights and benches along the edges of roads, placed automatically
+//! whenever a lane is finished. Which furniture a road gets and how densely depends on
+//! its class (highways only get lights, local streets get everything) and on the policy
+//! of its district, and all of it gets denser or sparser with the "Street Furniture"
+//! density setting.
+//!
+//! Furniture belongs to the lane it stands next to, so it is cleared when the lane is
+//! removed or upgraded, and placed anew for the lanes that replace it.
+use kay::{ActorSystem, World, External};
+use compact::CVec;
+use descartes::{N, P2, V2, Norm, Curve, FiniteCurve, WithUniqueOrthogonal};
+use monet::{Renderable, RenderableID, MSG_Renderable_setup_in_scene,
+            MSG_Renderable_render_to_scene, RendererID, Geometry, Vertex, Instance};
+use stagemaster::{UserInterfaceID, Interactable2d, Interactable2dID,
+                  MSG_Interactable2d_draw_ui_2d};
+use stagemaster::geometry::CPath;
+use imgui::ImGuiSetCond_FirstUseEver;
+use super::lane::LaneID;
+use super::road_hierarchy::RoadClass;
+use super::services::district_of;
+
+const SETTINGS_CATEGORY: &'static str = "Street Furniture";
+const TREE_TRUNK_BATCH_ID: u16 = 8106;
+const TREE_CROWN_BATCH_ID: u16 = 8107;
+const STREET_LIGHT_BATCH_ID: u16 = 8108;
+const BENCH_BATCH_ID: u16 = 8109;
+/// No furniture is placed closer than this to the ends of a lane
+const END_CLEARANCE: N = 5.0;
+/// Furniture needs this much space to the asphalt of other lanes
+const LANE_CLEARANCE: N = 1.0;
+/// Lanes whose midpoints are further apart than half their lengths and this
+/// can't be in each other's way
+const NEIGHBOUR_DISTANCE: N = 30.0;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum FurnitureKind {
+    Tree,
+    StreetLight,
+    Bench,
+}
+
+const ALL_KINDS: [FurnitureKind; 3] =
+    [FurnitureKind::Tree, FurnitureKind::StreetLight, FurnitureKind::Bench];
+
+impl FurnitureKind {
+    /// Where along the spacing the first item stands, so different kinds don't collide
+    fn phase(&self) -> N {
+        match *self {
+            FurnitureKind::Tree => 0.5,
+            FurnitureKind::StreetLight => 0.0,
+            FurnitureKind::Bench => 0.25,
+        }
+    }
+
+    /// How much further from the edge of the road than street lights this stands
+    fn setback(&self) -> N {
+        match *self {
+            FurnitureKind::StreetLight => 0.0,
+            FurnitureKind::Tree => 1.0,
+            FurnitureKind::Bench => 2.0,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match *self {
+            FurnitureKind::Tree => "Trees",
+            FurnitureKind::StreetLight => "Street lights",
+            FurnitureKind::Bench => "Benches",
+        }
+    }
+}
+
+/// How a district wants its streets furnished
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum FurniturePolicy {
+    /// Tree-lined avenues with plenty of benches
+    Lush,
+    Standard,
+    /// Only a few trees and no benches
+    Sparse,
+    /// Street lights only
+    Bare,
+}
+
+impl FurniturePolicy {
+    fn name(&self) -> &'static str {
+        match *self {
+            FurniturePolicy::Lush => "Lush",
+            FurniturePolicy::Standard => "Standard",
+            FurniturePolicy::Sparse => "Sparse",
+            FurniturePolicy::Bare => "Bare",
+        }
+    }
+
+    /// The policy that clicking the policy button switches to
+    fn next(&self) -> FurniturePolicy {
+        match *self {
+            FurniturePolicy::Lush => FurniturePolicy::Standard,
+            FurniturePolicy::Standard => FurniturePolicy::Sparse,
+            FurniturePolicy::Sparse => FurniturePolicy::Bare,
+            FurniturePolicy::Bare => FurniturePolicy::Lush,
+        }
+    }
+
+    /// Factor on the spacing of `kind`, none if the policy doesn't want it at all
+    fn spacing_factor(&self, kind: FurnitureKind) -> Option<N> {
+        match (*self, kind) {
+            (_, FurnitureKind::StreetLight) => Some(1.0),
+            (FurniturePolicy::Lush, FurnitureKind::Tree) => Some(0.6),
+            (FurniturePolicy::Lush, FurnitureKind::Bench) => Some(0.5),
+            (FurniturePolicy::Standard, _) => Some(1.0),
+            (FurniturePolicy::Sparse, FurnitureKind::Tree) => Some(2.0),
+            (FurniturePolicy::Sparse, FurnitureKind::Bench) |
+            (FurniturePolicy::Bare, _) => None,
+        }
+    }
+}
+
+/// Factor on the spacing of `kind` along roads of `class`, none if such roads don't
+/// get it at all. Unclassified roads are furnished like local streets
+fn profile_spacing_factor(class: Option<RoadClass>, kind: FurnitureKind) -> Option<N> {
+    match (class.unwrap_or(RoadClass::Local), kind) {
+        (RoadClass::Highway, FurnitureKind::StreetLight) => Some(1.0),
+        (RoadClass::Highway, _) => None,
+        (RoadClass::Arterial, FurnitureKind::StreetLight) => Some(0.8),
+        (RoadClass::Arterial, FurnitureKind::Bench) => Some(2.0),
+        (RoadClass::Local, FurnitureKind::StreetLight) => Some(1.5),
+        _ => Some(1.0),
+    }
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone)]
+pub struct FurnitureSettings {
+    /// Meters between trees on a standard local street
+    pub tree_spacing: N,
+    /// Meters between street lights on a standard collector road
+    pub light_spacing: N,
+    /// Meters between benches on a standard local street
+    pub bench_spacing: N,
+    /// Multiplies how many items of each kind are placed, 0 disables street furniture
+    pub density: f32,
+    /// Distance between the edge of the asphalt and the street lights
+    pub sidewalk_offset: N,
+}
+
+impl Default for FurnitureSettings {
+    fn default() -> Self {
+        FurnitureSettings {
+            tree_spacing: 15.0,
+            light_spacing: 30.0,
+            bench_spacing: 60.0,
+            density: 1.0,
+            sidewalk_offset: 1.0,
+        }
+    }
+}
+
+impl FurnitureSettings {
+    fn base_spacing(&self, kind: FurnitureKind) -> N {
+        match kind {
+            FurnitureKind::Tree => self.tree_spacing,
+            FurnitureKind::StreetLight => self.light_spacing,
+            FurnitureKind::Bench => self.bench_spacing,
+        }
+    }
+}
+
+/// The distance between items of `kind` along roads of `class` in a district with
+/// `policy`, none if there shouldn't be any
+pub fn spacing(
+    kind: FurnitureKind,
+    class: Option<RoadClass>,
+    policy: FurniturePolicy,
+    settings: &FurnitureSettings,
+) -> Option<N> {
+    if settings.density <= 0.0 {
+        return None;
+    }
+    profile_spacing_factor(class, kind).and_then(|profile_factor| {
+        policy.spacing_factor(kind).map(|policy_factor| {
+            settings.base_spacing(kind) * profile_factor * policy_factor / settings.density
+        })
+    })
+}
+
+/// Distances along a lane of `length` at which items `spacing` apart stand
+pub fn distances_along(length: N, spacing: N, phase: N) -> Vec<N> {
+    let mut distances = Vec::new();
+    if spacing <= 0.0 {
+        return distances;
+    }
+    let mut distance = END_CLEARANCE + phase * spacing;
+    while distance <= length - END_CLEARANCE {
+        distances.push(distance);
+        distance += spacing;
+    }
+    distances
+}
+
+#[derive(Copy, Clone)]
+pub struct FurnitureItem {
+    kind: FurnitureKind,
+    position: P2,
+    direction: V2,
+}
+
+#[derive(Copy, Clone)]
+struct DistrictPolicy {
+    district: (i32, i32),
+    policy: FurniturePolicy,
+}
+
+#[derive(Compact, Clone)]
+struct FurnishedLane {
+    lane: LaneID,
+    path: CPath,
+    on_intersection: bool,
+    class: Option<RoadClass>,
+    items: CVec<FurnitureItem>,
+}
+
+impl FurnishedLane {
+    fn midpoint(&self) -> P2 {
+        self.path.along(self.path.length() / 2.0)
+    }
+
+    fn width(&self) -> N {
+        self.class.map(|class| class.rendering_width()).unwrap_or(6.0)
+    }
+
+    fn is_near(&self, other: &FurnishedLane) -> bool {
+        (self.midpoint() - other.midpoint()).norm() <
+            (self.path.length() + other.path.length()) / 2.0 + NEIGHBOUR_DISTANCE
+    }
+}
+
+#[derive(Compact, Clone)]
+pub struct StreetFurniture {
+    id: StreetFurnitureID,
+    settings: FurnitureSettings,
+    lanes: CVec<FurnishedLane>,
+    policies: CVec<DistrictPolicy>,
+}
+
+impl StreetFurniture {
+    pub fn spawn(
+        id: StreetFurnitureID,
+        user_interface: UserInterfaceID,
+        world: &mut World,
+    ) -> StreetFurniture {
+        user_interface.add_2d(id.into(), world);
+
+        StreetFurniture {
+            id,
+            settings: ::ENV.load_settings(SETTINGS_CATEGORY),
+            lanes: CVec::new(),
+            policies: CVec::new(),
+        }
+    }
+
+    /// Called when a lane is finished, to put furniture along it
+    pub fn add_lane(
+        &mut self,
+        lane: LaneID,
+        path: &CPath,
+        on_intersection: bool,
+        class: Option<RoadClass>,
+        _: &mut World,
+    ) {
+        let furnished_lane = FurnishedLane {
+            lane,
+            path: path.clone(),
+            on_intersection,
+            class,
+            items: CVec::new(),
+        };
+        // a new lane can take the place of furniture along its neighbours
+        let affected = self.neighbours_of(&furnished_lane);
+        self.lanes.push(furnished_lane);
+        self.refurnish(&affected);
+        self.refurnish(&[lane]);
+    }
+
+    /// Called when a lane is removed (or replaced by an upgraded one), clearing its furniture
+    pub fn remove_lane(&mut self, lane: LaneID, _: &mut World) {
+        if let Some(idx) = self.lanes.iter().position(|furnished| furnished.lane == lane) {
+            let removed = self.lanes.remove(idx);
+            // its neighbours might have room for more furniture now
+            let affected = self.neighbours_of(&removed);
+            self.refurnish(&affected);
+        }
+    }
+
+    /// Called when the class of a lane changes, since that decides its furniture
+    pub fn set_lane_class(&mut self, lane: LaneID, class: Option<RoadClass>, _: &mut World) {
+        let mut changed = false;
+        for furnished in self.lanes.iter_mut() {
+            if furnished.lane == lane && furnished.class != class {
+                furnished.class = class;
+                changed = true;
+            }
+        }
+        if changed {
+            self.refurnish(&[lane]);
+        }
+    }
+
+    fn neighbours_of(&self, lane: &FurnishedLane) -> Vec<LaneID> {
+        self.lanes
+            .iter()
+            .filter(|other| other.lane != lane.lane && lane.is_near(other))
+            .map(|other| other.lane)
+            .collect()
+    }
+
+    fn policy_of(&self, district: (i32, i32)) -> FurniturePolicy {
+        self.policies
+            .iter()
+            .find(|district_policy| district_policy.district == district)
+            .map(|district_policy| district_policy.policy)
+            .unwrap_or(FurniturePolicy::Standard)
+    }
+
+    fn set_policy(&mut self, district: (i32, i32), policy: FurniturePolicy) {
+        self.policies.retain(|district_policy| district_policy.district != district);
+        if policy != FurniturePolicy::Standard {
+            self.policies.push(DistrictPolicy { district, policy });
+        }
+    }
+
+    /// Places the furniture along the right edge of `lane`, wherever
+    /// that isn't taken by the asphalt of another lane
+    fn place_items(&self, lane: &FurnishedLane) -> CVec<FurnitureItem> {
+        let mut items = CVec::new();
+        if lane.on_intersection {
+            return items;
+        }
+        let neighbours = self.lanes
+            .iter()
+            .filter(|other| other.lane != lane.lane && lane.is_near(other))
+            .collect::<Vec<_>>();
+        let policy = self.policy_of(district_of(lane.midpoint()));
+        let length = lane.path.length();
+
+        for kind in &ALL_KINDS {
+            let kind_spacing = match spacing(*kind, lane.class, policy, &self.settings) {
+                Some(kind_spacing) => kind_spacing,
+                None => continue,
+            };
+            let offset = lane.width() / 2.0 + self.settings.sidewalk_offset + kind.setback();
+
+            for distance in distances_along(length, kind_spacing, kind.phase()) {
+                let direction = lane.path.direction_along(distance);
+                let position = lane.path.along(distance) + offset * direction.orthogonal();
+                let is_free = neighbours.iter().all(|other| {
+                    other.path.distance_to(position) > other.width() / 2.0 + LANE_CLEARANCE
+                });
+                if is_free {
+                    items.push(FurnitureItem { kind: *kind, position, direction });
+                }
+            }
+        }
+
+        items
+    }
+
+    fn refurnish(&mut self, lanes: &[LaneID]) {
+        let new_items = self.lanes
+            .iter()
+            .map(|furnished| if lanes.contains(&furnished.lane) {
+                Some(self.place_items(furnished))
+            } else {
+                None
+            })
+            .collect::<Vec<_>>();
+        for (furnished, maybe_items) in self.lanes.iter_mut().zip(new_items) {
+            if let Some(items) = maybe_items {
+                furnished.items = items;
+            }
+        }
+    }
+
+    fn refurnish_all(&mut self) {
+        let all_lanes = self.lanes.iter().map(|furnished| furnished.lane).collect::<Vec<_>>();
+        self.refurnish(&all_lanes);
+    }
+
+    fn districts(&self) -> Vec<(i32, i32)> {
+        let mut districts = self.lanes
+            .iter()
+            .filter(|furnished| !furnished.on_intersection)
+            .map(|furnished| district_of(furnished.midpoint()))
+            .collect::<Vec<_>>();
+        districts.sort();
+        districts.dedup();
+        districts
+    }
+}
+
+fn cuboid(min: [N; 3], max: [N; 3]) -> Geometry {
+    let corner = |x: bool, y: bool, z: bool| {
+        Vertex {
+            position: [
+                if x { max[0] } else { min[0] },
+                if y { max[1] } else { min[1] },
+                if z { max[2] } else { min[2] },
+            ],
+        }
+    };
+    Geometry::new(
+        vec![
+            corner(false, false, false),
+            corner(true, false, false),
+            corner(true, true, false),
+            corner(false, true, false),
+            corner(false, false, true),
+            corner(true, false, true),
+            corner(true, true, true),
+            corner(false, true, true),
+        ],
+        vec![
+            0, 1, 5, 5, 4, 0, 1, 2, 6, 6, 5, 1, 2, 3, 7, 7, 6, 2, 3, 0, 4, 4, 7, 3, 4, 5, 6, 6,
+            7, 4,
+        ],
+    )
+}
+
+impl Renderable for StreetFurniture {
+    fn setup_in_scene(&mut self, renderer_id: RendererID, scene_id: usize, world: &mut World) {
+        renderer_id.add_batch(
+            scene_id,
+            TREE_TRUNK_BATCH_ID,
+            cuboid([-0.15, -0.15, 0.0], [0.15, 0.15, 2.5]),
+            world,
+        );
+        renderer_id.add_batch(
+            scene_id,
+            TREE_CROWN_BATCH_ID,
+            cuboid([-1.5, -1.5, 2.0], [1.5, 1.5, 5.5]),
+            world,
+        );
+        // a pole with a lamp reaching out over the road, which is to the left
+        renderer_id.add_batch(
+            scene_id,
+            STREET_LIGHT_BATCH_ID,
+            cuboid([-0.1, -0.1, 0.0], [0.1, 0.1, 7.0]) +
+                cuboid([-0.2, 0.0, 6.8], [0.2, 1.5, 7.0]),
+            world,
+        );
+        renderer_id.add_batch(
+            scene_id,
+            BENCH_BATCH_ID,
+            cuboid([-0.9, -0.25, 0.4], [0.9, 0.25, 0.5]) +
+                cuboid([-0.9, -0.25, 0.5], [0.9, -0.2, 0.9]),
+            world,
+        );
+    }
+
+    fn render_to_scene(
+        &mut self,
+        renderer_id: RendererID,
+        scene_id: usize,
+        frame: usize,
+        world: &mut World,
+    ) {
+        let batches = [
+            (TREE_TRUNK_BATCH_ID, FurnitureKind::Tree, [0.4, 0.3, 0.2]),
+            (TREE_CROWN_BATCH_ID, FurnitureKind::Tree, [0.2, 0.5, 0.15]),
+            (STREET_LIGHT_BATCH_ID, FurnitureKind::StreetLight, [0.3, 0.3, 0.3]),
+            (BENCH_BATCH_ID, FurnitureKind::Bench, [0.5, 0.35, 0.2]),
+        ];
+        for &(batch_id, kind, color) in &batches {
+            // also sent when empty, to clear the instances of the last frame
+            let instances = self.lanes
+                .iter()
+                .flat_map(|furnished| furnished.items.iter())
+                .filter(|item| item.kind == kind)
+                .map(|item| {
+                    Instance {
+                        instance_position: [item.position.x, item.position.y, 0.0],
+                        instance_direction: [item.direction.x, item.direction.y],
+                        instance_color: color,
+                    }
+                })
+                .collect();
+            renderer_id.add_several_instances(scene_id, batch_id, frame, instances, world);
+        }
+    }
+}
+
+impl Interactable2d for StreetFurniture {
+    fn draw_ui_2d(
+        &mut self,
+        imgui_ui: &External<::imgui::Ui<'static>>,
+        return_to: UserInterfaceID,
+        world: &mut World,
+    ) {
+        let ui = imgui_ui.steal();
+        let mut settings = self.settings;
+        let mut settings_changed = false;
+        let mut changed_policy = None;
+
+        ui.window(im_str!("Street Furniture"))
+            .size((250.0, 250.0), ImGuiSetCond_FirstUseEver)
+            .collapsible(true)
+            .build(|| {
+                for kind in &ALL_KINDS {
+                    let count = self.lanes
+                        .iter()
+                        .flat_map(|furnished| furnished.items.iter())
+                        .filter(|item| item.kind == *kind)
+                        .count();
+                    ui.text(im_str!("{}: {}", kind.name(), count));
+                }
+
+                settings_changed |= ui.slider_float(
+                    im_str!("Density"),
+                    &mut settings.density,
+                    0.0,
+                    3.0,
+                ).build();
+
+                if ui.collapsing_header(im_str!("District policies")).build() {
+                    for district in self.districts() {
+                        let (x, y) = district;
+                        let policy = self.policy_of(district);
+                        ui.text(im_str!("({}, {})", x, y));
+                        ui.same_line(0.0);
+                        if ui.small_button(im_str!("{}##furniture{}_{}", policy.name(), x, y)) {
+                            changed_policy = Some((district, policy.next()));
+                        }
+                    }
+                }
+            });
+
+        if let Some((district, policy)) = changed_policy {
+            self.set_policy(district, policy);
+            let in_district = self.lanes
+                .iter()
+                .filter(|furnished| district_of(furnished.midpoint()) == district)
+                .map(|furnished| furnished.lane)
+                .collect::<Vec<_>>();
+            self.refurnish(&in_district);
+        }
+
+        if settings_changed {
+            self.settings = settings;
+            ::ENV.write_settings(SETTINGS_CATEGORY, &settings);
+            self.refurnish_all();
+        }
+
+        return_to.ui_drawn(ui, world);
+    }
+}
+
+pub fn setup(system: &mut ActorSystem, user_interface: UserInterfaceID) {
+    system.register::<StreetFurniture>();
+    system.make_restorable::<StreetFurniture>();
+    auto_setup(system);
+
+    StreetFurnitureID::spawn(user_interface, &mut system.world());
+}
+
+mod kay_auto;
+pub use self::kay_auto::*;
+
+#[cfg(test)]
+mod tests {
+    use super::{spacing, distances_along, FurnitureKind, FurniturePolicy, FurnitureSettings};
+    use transport::road_hierarchy::RoadClass;
+
+    #[test]
+    fn highways_only_get_street_lights() {
+        let settings = FurnitureSettings::default();
+        let highway = Some(RoadClass::Highway);
+        let policy = FurniturePolicy::Lush;
+        assert!(spacing(FurnitureKind::Tree, highway, policy, &settings).is_none());
+        assert!(spacing(FurnitureKind::Bench, highway, policy, &settings).is_none());
+        assert!(spacing(FurnitureKind::StreetLight, highway, policy, &settings).is_some());
+    }
+
+    #[test]
+    fn density_scales_spacing() {
+        let mut settings = FurnitureSettings::default();
+        let standard = spacing(FurnitureKind::Tree, None, FurniturePolicy::Standard, &settings);
+        settings.density = 2.0;
+        let dense = spacing(FurnitureKind::Tree, None, FurniturePolicy::Standard, &settings);
+        assert_eq!(standard, dense.map(|dense| dense * 2.0));
+        settings.density = 0.0;
+        assert!(spacing(FurnitureKind::StreetLight, None, FurniturePolicy::Bare, &settings)
+            .is_none());
+    }
+
+    #[test]
+    fn items_keep_away_from_lane_ends() {
+        assert_eq!(distances_along(40.0, 10.0, 0.5), vec![10.0, 20.0, 30.0]);
+        assert!(distances_along(8.0, 10.0, 0.0).is_empty());
+    }
+}
@@ -0,0 +1,300 @@
+//! Fixed cameras at intersections, each shown as a small inset at the right edge of the
+//! screen, so hotspots can be watched while working elsewhere on the map.
+//!
+//! The insets are monet viewports, which are rendered into textures and only refreshed
+//! every few frames (see the "Traffic Cameras" settings).
+use kay::{ActorSystem, World, External};
+use compact::CVec;
+use descartes::{N, P2, P3, V2, V3, Norm, Curve, FiniteCurve};
+use monet::{RendererID, EyeListener, EyeListenerID, Eye, Movement, Viewport,
+            MSG_EyeListener_eye_moved};
+use stagemaster::combo::Combo2;
+use stagemaster::combo::Button::{LControl, C};
+use stagemaster::{UserInterfaceID, Event3d, Interactable3d, Interactable3dID,
+                  MSG_Interactable3d_on_event, Interactable2d, Interactable2dID,
+                  MSG_Interactable2d_draw_ui_2d};
+use stagemaster::actions::{register_action, ActionId, ActionListener, ActionListenerID,
+                           ActionPhase, MSG_ActionListener_on_action};
+use transport::planning::current_plan::set_tool_active;
+use imgui::ImGuiSetCond_FirstUseEver;
+use super::lane::Lane;
+
+/// Intersection lanes this close to where a camera was placed are what it looks at
+const SNAP_DISTANCE: N = 30.0;
+/// Placing a camera this close to an existing one removes that one instead
+const REMOVAL_DISTANCE: N = 15.0;
+/// Size of an inset, as fraction of the screen size
+const INSET_SIZE: f32 = 0.24;
+const INSET_MARGIN: f32 = 0.01;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TrafficCameraSettings {
+    pub max_cameras: usize,
+    /// Insets are rendered again every this many frames
+    pub refresh_interval: usize,
+}
+
+impl Default for TrafficCameraSettings {
+    fn default() -> Self {
+        TrafficCameraSettings { max_cameras: 4, refresh_interval: 3 }
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Camera {
+    viewport_id: u16,
+    placed_at: P2,
+    /// Summed up midpoints of the intersection lanes around `placed_at`
+    intersection_sum: V2,
+    intersection_lanes: u32,
+}
+
+impl Camera {
+    /// The middle of the intersection, once its lanes reported
+    fn target(&self) -> P2 {
+        if self.intersection_lanes == 0 {
+            self.placed_at
+        } else {
+            P2::new(0.0, 0.0) + self.intersection_sum / self.intersection_lanes as N
+        }
+    }
+
+    fn eye(&self) -> Eye {
+        let target = self.target();
+        Eye {
+            position: P3::new(target.x - 40.0, target.y - 40.0, 50.0),
+            target: P3::new(target.x, target.y, 0.0),
+            up: V3::new(0.0, 0.0, 1.0),
+            field_of_view: 0.25 * ::std::f32::consts::PI,
+        }
+    }
+}
+
+#[derive(Compact, Clone)]
+pub struct TrafficCameras {
+    id: TrafficCamerasID,
+    user_interface: UserInterfaceID,
+    renderer_id: RendererID,
+    settings: External<TrafficCameraSettings>,
+    placing: bool,
+    cameras: CVec<Camera>,
+    next_viewport_id: u16,
+    eye_target: P3,
+}
+
+impl TrafficCameras {
+    pub fn spawn(
+        id: TrafficCamerasID,
+        user_interface: UserInterfaceID,
+        renderer_id: RendererID,
+        world: &mut World,
+    ) -> TrafficCameras {
+        register_action(
+            "Place Traffic Camera",
+            Combo2::new(&[LControl, C], &[]),
+            id.into(),
+            world,
+        );
+        user_interface.add_2d(id.into(), world);
+        renderer_id.add_eye_listener(0, id.into(), world);
+
+        TrafficCameras {
+            id,
+            user_interface,
+            renderer_id,
+            settings: External::new(::ENV.load_settings("Traffic Cameras")),
+            placing: false,
+            cameras: CVec::new(),
+            next_viewport_id: 0,
+            eye_target: P3::new(0.0, 0.0, 0.0),
+        }
+    }
+
+    /// Insets are stacked from the top right corner downwards, in the order of the cameras
+    fn update_viewport(&self, idx: usize, world: &mut World) {
+        let camera = &self.cameras[idx];
+        self.renderer_id.set_viewport(
+            0,
+            camera.viewport_id,
+            Viewport {
+                eye: camera.eye(),
+                region: [
+                    1.0 - INSET_SIZE - INSET_MARGIN,
+                    1.0 - (INSET_SIZE + INSET_MARGIN) * (idx + 1) as f32,
+                    INSET_SIZE,
+                    INSET_SIZE,
+                ],
+                refresh_interval: self.settings.refresh_interval,
+            },
+            world,
+        );
+    }
+
+    fn place(&mut self, position: P2, world: &mut World) {
+        let viewport_id = self.next_viewport_id;
+        self.next_viewport_id = self.next_viewport_id.wrapping_add(1);
+        self.cameras.push(Camera {
+            viewport_id,
+            placed_at: position,
+            intersection_sum: V2::new(0.0, 0.0),
+            intersection_lanes: 0,
+        });
+        if self.cameras.len() > self.settings.max_cameras.max(1) {
+            self.remove(0, world);
+        }
+
+        let idx = self.cameras.len() - 1;
+        self.update_viewport(idx, world);
+        super::lane::LaneID::global_broadcast(world).report_intersection_near(
+            position,
+            viewport_id,
+            self.id,
+            world,
+        );
+    }
+
+    fn remove(&mut self, idx: usize, world: &mut World) {
+        let camera = self.cameras.remove(idx);
+        self.renderer_id.remove_viewport(0, camera.viewport_id, world);
+        // the ones below move up
+        for other_idx in idx..self.cameras.len() {
+            self.update_viewport(other_idx, world);
+        }
+    }
+
+    pub fn add_intersection_point(&mut self, viewport_id: u16, point: P2, world: &mut World) {
+        if let Some(idx) = self.cameras.iter().position(|camera| {
+            camera.viewport_id == viewport_id
+        })
+        {
+            {
+                let camera = &mut self.cameras[idx];
+                camera.intersection_sum += point.to_vector();
+                camera.intersection_lanes += 1;
+            }
+            self.update_viewport(idx, world);
+        }
+    }
+}
+
+impl Lane {
+    pub fn report_intersection_near(
+        &mut self,
+        position: P2,
+        viewport_id: u16,
+        cameras: TrafficCamerasID,
+        world: &mut World,
+    ) {
+        if self.connectivity.on_intersection &&
+            self.construction.path.distance_to(position) < SNAP_DISTANCE
+        {
+            let midpoint = self.construction.path.along(self.construction.length / 2.0);
+            cameras.add_intersection_point(viewport_id, midpoint, world);
+        }
+    }
+}
+
+impl ActionListener for TrafficCameras {
+    fn on_action(&mut self, action: ActionId, phase: ActionPhase, world: &mut World) {
+        if phase == ActionPhase::Started && action == ActionId::named("Place Traffic Camera") {
+            self.placing = !self.placing;
+            set_tool_active(self.user_interface, self.id.into(), self.placing, world);
+        }
+    }
+}
+
+impl Interactable3d for TrafficCameras {
+    fn on_event(&mut self, event: Event3d, world: &mut World) {
+        if self.placing {
+            match event {
+                Event3d::DragFinished { to, .. } => {
+                    let position = P2::new(to.x, to.y);
+                    let existing = self.cameras.iter().position(|camera| {
+                        (camera.target() - position).norm() < REMOVAL_DISTANCE
+                    });
+                    match existing {
+                        Some(idx) => self.remove(idx, world),
+                        None => self.place(position, world),
+                    }
+                }
+                Event3d::Frame => {
+                    self.user_interface.add_debug_text(
+                        "Placing Traffic Cameras".chars().collect(),
+                        "click an intersection, or a camera to remove it"
+                            .chars()
+                            .collect(),
+                        [0.0, 0.0, 0.0, 1.0],
+                        false,
+                        world,
+                    );
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl EyeListener for TrafficCameras {
+    fn eye_moved(&mut self, eye: Eye, _movement: Movement, _: &mut World) {
+        self.eye_target = eye.target;
+    }
+}
+
+impl Interactable2d for TrafficCameras {
+    fn draw_ui_2d(
+        &mut self,
+        imgui_ui: &External<::imgui::Ui<'static>>,
+        return_to: UserInterfaceID,
+        world: &mut World,
+    ) {
+        let ui = imgui_ui.steal();
+
+        let mut jump_to = None;
+        let mut removed = None;
+
+        if !self.cameras.is_empty() {
+            ui.window(im_str!("Traffic Cameras"))
+                .size((250.0, 150.0), ImGuiSetCond_FirstUseEver)
+                .collapsible(true)
+                .build(|| for (i, camera) in self.cameras.iter().enumerate() {
+                    let target = camera.target();
+                    ui.text(im_str!("Camera {} at {:.0}, {:.0}", i + 1, target.x, target.y));
+                    if ui.small_button(im_str!("Go##camera{}", i)) {
+                        jump_to = Some(target);
+                    }
+                    ui.same_line(0.0);
+                    if ui.small_button(im_str!("Remove##camera{}", i)) {
+                        removed = Some(i);
+                    }
+                });
+        }
+
+        if let Some(idx) = removed {
+            self.remove(idx, world);
+        }
+
+        if let Some(target) = jump_to {
+            self.renderer_id.move_eye(
+                0,
+                Movement::ShiftAbsolute(V3::new(
+                    target.x - self.eye_target.x,
+                    target.y - self.eye_target.y,
+                    0.0,
+                )),
+                world,
+            );
+        }
+
+        return_to.ui_drawn(ui, world);
+    }
+}
+
+pub fn setup(system: &mut ActorSystem, user_interface: UserInterfaceID, renderer_id: RendererID) {
+    system.register::<TrafficCameras>();
+    auto_setup(system);
+
+    TrafficCamerasID::spawn(user_interface, renderer_id, &mut system.world());
+}
+
+mod kay_auto;
+pub use self::kay_auto::*;
@@ -0,0 +1,517 @@
+//! Counts how many cars make each movement (left, through, right or u-turn from each
+//! approach) at intersections, over a rolling hour. Clicking an intersection shows its
+//! counts as a turning movement diagram, which is what signal phasing gets designed from.
+//! The counts, together with how long cars waited, also drive the `SignalWarrants`
+//! and the `SignalController`.
+use kay::{ActorSystem, World, External};
+use compact::{CVec, CDict};
+use fnv::FnvHashMap;
+use descartes::{P2, V2, Norm, Band, FiniteCurve, WithUniqueOrthogonal, Dot, RoughlyComparable};
+use monet::{RendererID, Renderable, RenderableID, MSG_Renderable_setup_in_scene,
+            MSG_Renderable_render_to_scene, Geometry, Instance};
+use stagemaster::{UserInterfaceID, Event3d, Interactable3d, Interactable3dID,
+                  MSG_Interactable3d_on_event, Interactable2d, Interactable2dID,
+                  MSG_Interactable2d_draw_ui_2d};
+use stagemaster::combo::Combo2;
+use stagemaster::combo::Button::{LControl, T};
+use stagemaster::actions::{register_action, ActionId, ActionListener, ActionListenerID,
+                           ActionPhase, MSG_ActionListener_on_action};
+use stagemaster::geometry::{CPath, band_to_geometry};
+use transport::planning::current_plan::set_tool_active;
+use imgui::ImGuiSetCond_FirstUseEver;
+use core::simulation::{SimulationID, Sleeper, SleeperID, MSG_Sleeper_wake, Timestamp, Ticks,
+                       TICKS_PER_SIM_MINUTE};
+use super::lane::{Lane, LaneID};
+
+const COLLECTION_TICKS: usize = 10;
+const WINDOW_TICKS: usize = 15 * TICKS_PER_SIM_MINUTE;
+/// Counts are kept for this many windows, which make up the rolling hour
+const N_WINDOWS: usize = 4;
+/// Movements with their middle this close to a click belong to the clicked intersection
+const INTERSECTION_PICK_RADIUS: f32 = 40.0;
+/// The first individual ID of the diagram, one more for each movement kind
+const DIAGRAM_INDIVIDUAL_ID: u16 = 5507;
+/// Lane ends closer than this are considered connected
+const ENDPOINT_TOLERANCE: f32 = 1.0;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum MovementKind {
+    Left,
+    Through,
+    Right,
+    UTurn,
+}
+
+const ALL_MOVEMENT_KINDS: [MovementKind; 4] = [
+    MovementKind::Left,
+    MovementKind::Through,
+    MovementKind::Right,
+    MovementKind::UTurn,
+];
+
+impl MovementKind {
+    /// Same distinction as for the traffic light arrows
+    pub fn of(start_direction: V2, end_direction: V2) -> MovementKind {
+        if end_direction.is_roughly_within(-start_direction, 0.1) {
+            MovementKind::UTurn
+        } else if end_direction.is_roughly_within(start_direction, 0.5) {
+            MovementKind::Through
+        } else if end_direction.dot(&start_direction.orthogonal()) > 0.0 {
+            MovementKind::Right
+        } else {
+            MovementKind::Left
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            MovementKind::Left => "left",
+            MovementKind::Through => "through",
+            MovementKind::Right => "right",
+            MovementKind::UTurn => "u-turn",
+        }
+    }
+
+    fn idx(&self) -> usize {
+        match *self {
+            MovementKind::Left => 0,
+            MovementKind::Through => 1,
+            MovementKind::Right => 2,
+            MovementKind::UTurn => 3,
+        }
+    }
+
+    fn color(&self) -> [f32; 3] {
+        match *self {
+            MovementKind::Left => [0.2, 0.4, 1.0],
+            MovementKind::Through => [0.1, 0.8, 0.2],
+            MovementKind::Right => [1.0, 0.6, 0.0],
+            MovementKind::UTurn => [0.8, 0.1, 0.8],
+        }
+    }
+}
+
+/// The compass direction traffic comes from into the intersection, by its heading
+pub fn approach_name(start_direction: V2) -> &'static str {
+    if start_direction.x.abs() > start_direction.y.abs() {
+        if start_direction.x > 0.0 {
+            "Eastbound"
+        } else {
+            "Westbound"
+        }
+    } else if start_direction.y > 0.0 {
+        "Northbound"
+    } else {
+        "Southbound"
+    }
+}
+
+#[derive(Compact, Clone)]
+struct Movement {
+    lane: LaneID,
+    path: CPath,
+    kind: MovementKind,
+    signalized: bool,
+    counts: [usize; N_WINDOWS],
+    /// Ticks cars waited on this movement and in front of it on its approach
+    waiting_ticks: [usize; N_WINDOWS],
+    reported: bool,
+}
+
+impl Movement {
+    fn per_hour(&self) -> usize {
+        self.counts.iter().sum()
+    }
+
+    fn touches(&self, other: &Movement) -> bool {
+        let ends = [self.path.start(), self.path.end()];
+        let other_ends = [other.path.start(), other.path.end()];
+        ends.iter().any(|end| {
+            other_ends.iter().any(|other_end| {
+                end.is_roughly_within(*other_end, ENDPOINT_TOLERANCE)
+            })
+        })
+    }
+}
+
+/// Groups movements that share lane ends, these make up one intersection each
+fn group_into_intersections(movements: &[Movement]) -> Vec<Vec<usize>> {
+    fn root(parents: &mut Vec<usize>, i: usize) -> usize {
+        let mut root = i;
+        while parents[root] != root {
+            root = parents[root];
+        }
+        parents[i] = root;
+        root
+    }
+
+    let mut parents = (0..movements.len()).collect::<Vec<_>>();
+    for i in 0..movements.len() {
+        for j in 0..i {
+            if movements[i].touches(&movements[j]) {
+                let (root_i, root_j) = (root(&mut parents, i), root(&mut parents, j));
+                parents[root_i] = root_j;
+            }
+        }
+    }
+
+    let mut groups = FnvHashMap::<usize, Vec<usize>>::default();
+    for i in 0..movements.len() {
+        groups.entry(root(&mut parents, i)).or_insert_with(Vec::new).push(i);
+    }
+    groups.into_iter().map(|(_, group)| group).collect()
+}
+
+/// The movements of one intersection, summed up over the last hour
+#[derive(Compact, Clone)]
+pub struct IntersectionCounts {
+    pub center: P2,
+    /// Each movement's lane with its heading when entering the intersection
+    pub movements: CVec<(LaneID, V2)>,
+    pub per_hour: usize,
+    pub waiting_ticks: usize,
+    pub signalized: bool,
+}
+
+pub trait IntersectionCountsRequester {
+    fn on_intersections(&mut self, intersections: &CVec<IntersectionCounts>, world: &mut World);
+}
+
+impl Lane {
+    pub fn report_turning_movement(&mut self, counter: TurningMovementsID, world: &mut World) {
+        let entered = ::std::mem::replace(&mut self.microtraffic.cars_entered, 0);
+        let waiting_ticks = ::std::mem::replace(&mut self.microtraffic.waiting_ticks, 0);
+        if !self.construction.is_finished() {
+            return;
+        }
+
+        if self.connectivity.on_intersection {
+            counter.add_count(
+                self.id,
+                self.construction.path.clone(),
+                entered,
+                waiting_ticks,
+                self.microtraffic.is_signalized(),
+                world,
+            );
+        } else if waiting_ticks > 0 {
+            counter.add_approach_waiting(self.construction.path.end(), waiting_ticks, world);
+        }
+    }
+}
+
+/// Collects the counts of all intersection lanes every window
+/// and shows them for the intersection picked by the user
+#[derive(Compact, Clone)]
+pub struct TurningMovements {
+    id: TurningMovementsID,
+    simulation: SimulationID,
+    user_interface: UserInterfaceID,
+    collecting: bool,
+    current_window: usize,
+    movements: CVec<Movement>,
+    picking: bool,
+    intersection: Option<P2>,
+    rendered_in: CDict<RendererID, ()>,
+}
+
+impl TurningMovements {
+    pub fn spawn(
+        id: TurningMovementsID,
+        simulation: SimulationID,
+        user_interface: UserInterfaceID,
+        world: &mut World,
+    ) -> TurningMovements {
+        user_interface.add_2d(id.into(), world);
+        register_action(
+            "Count Turning Movements",
+            Combo2::new(&[LControl, T], &[]),
+            id.into(),
+            world,
+        );
+        simulation.wake_up_in(Ticks(WINDOW_TICKS), id.into(), world);
+
+        TurningMovements {
+            id,
+            simulation,
+            user_interface,
+            collecting: false,
+            current_window: 0,
+            movements: CVec::new(),
+            picking: false,
+            intersection: None,
+            rendered_in: CDict::new(),
+        }
+    }
+
+    pub fn add_count(
+        &mut self,
+        lane: LaneID,
+        path: &CPath,
+        entered: usize,
+        waiting_ticks: usize,
+        signalized: bool,
+        _: &mut World,
+    ) {
+        if !self.collecting {
+            return;
+        }
+
+        let window = self.current_window;
+        if let Some(movement) = self.movements.iter_mut().find(
+            |movement| movement.lane == lane,
+        )
+        {
+            movement.counts[window] = entered;
+            movement.waiting_ticks[window] += waiting_ticks;
+            movement.signalized = signalized;
+            movement.reported = true;
+            return;
+        }
+
+        let mut counts = [0; N_WINDOWS];
+        counts[window] = entered;
+        let mut waiting = [0; N_WINDOWS];
+        waiting[window] = waiting_ticks;
+        self.movements.push(Movement {
+            lane,
+            kind: MovementKind::of(path.start_direction(), path.end_direction()),
+            path: path.clone(),
+            signalized,
+            counts,
+            waiting_ticks: waiting,
+            reported: true,
+        });
+    }
+
+    /// Cars waiting in front of an intersection count for the first movement they
+    /// might make, so they're counted once for the whole intersection
+    pub fn add_approach_waiting(&mut self, end: P2, waiting_ticks: usize, _: &mut World) {
+        if !self.collecting {
+            return;
+        }
+
+        let window = self.current_window;
+        if let Some(movement) = self.movements.iter_mut().find(|movement| {
+            movement.path.start().is_roughly_within(end, ENDPOINT_TOLERANCE)
+        })
+        {
+            movement.waiting_ticks[window] += waiting_ticks;
+        }
+    }
+
+    pub fn report_intersections(
+        &mut self,
+        requester: IntersectionCountsRequesterID,
+        world: &mut World,
+    ) {
+        let intersections = group_into_intersections(&self.movements)
+            .into_iter()
+            .map(|group| {
+                let movements = group.iter().map(|&i| &self.movements[i]).collect::<Vec<_>>();
+                let middles = movements
+                    .iter()
+                    .map(|movement| movement.path.along(movement.path.length() / 2.0))
+                    .collect::<Vec<_>>();
+                let center = middles.iter().fold(P2::new(0.0, 0.0), |sum, middle| {
+                    P2::new(sum.x + middle.x, sum.y + middle.y)
+                }) / middles.len() as f32;
+
+                IntersectionCounts {
+                    center,
+                    movements: movements
+                        .iter()
+                        .map(|movement| (movement.lane, movement.path.start_direction()))
+                        .collect(),
+                    per_hour: movements.iter().map(|movement| movement.per_hour()).sum(),
+                    waiting_ticks: movements
+                        .iter()
+                        .map(|movement| movement.waiting_ticks.iter().sum::<usize>())
+                        .sum(),
+                    signalized: movements.iter().any(|movement| movement.signalized),
+                }
+            })
+            .collect();
+
+        requester.on_intersections(intersections, world);
+    }
+
+    fn is_picked(&self, movement: &Movement) -> bool {
+        self.intersection.map_or(false, |position| {
+            let middle = movement.path.along(movement.path.length() / 2.0);
+            (middle - position).norm() < INTERSECTION_PICK_RADIUS
+        })
+    }
+}
+
+impl Sleeper for TurningMovements {
+    fn wake(&mut self, _current_tick: Timestamp, world: &mut World) {
+        if self.collecting {
+            self.collecting = false;
+            // lanes that didn't report were demolished
+            self.movements.retain(|movement| movement.reported);
+            self.rendered_in = CDict::new();
+            self.simulation.wake_up_in(
+                Ticks(WINDOW_TICKS - COLLECTION_TICKS),
+                self.id.into(),
+                world,
+            );
+        } else {
+            self.collecting = true;
+            self.current_window = (self.current_window + 1) % N_WINDOWS;
+            let window = self.current_window;
+            for movement in self.movements.iter_mut() {
+                movement.reported = false;
+                movement.counts[window] = 0;
+                movement.waiting_ticks[window] = 0;
+            }
+            LaneID::global_broadcast(world).report_turning_movement(self.id, world);
+            self.simulation.wake_up_in(Ticks(COLLECTION_TICKS), self.id.into(), world);
+        }
+    }
+}
+
+impl ActionListener for TurningMovements {
+    fn on_action(&mut self, action: ActionId, phase: ActionPhase, world: &mut World) {
+        if phase == ActionPhase::Started && action == ActionId::named("Count Turning Movements") {
+            self.picking = !self.picking;
+            set_tool_active(self.user_interface, self.id.into(), self.picking, world);
+        }
+    }
+}
+
+impl Interactable3d for TurningMovements {
+    fn on_event(&mut self, event: Event3d, world: &mut World) {
+        if self.picking {
+            match event {
+                Event3d::DragFinished { to, .. } => {
+                    self.intersection = Some(P2::new(to.x, to.y));
+                    self.rendered_in = CDict::new();
+                    self.picking = false;
+                    self.user_interface.remove(self.id.into(), world);
+                }
+                Event3d::Frame => {
+                    self.user_interface.add_debug_text(
+                        "Counting turning movements".chars().collect(),
+                        "click an intersection".chars().collect(),
+                        [0.0, 0.0, 0.0, 1.0],
+                        false,
+                        world,
+                    );
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Renderable for TurningMovements {
+    fn setup_in_scene(&mut self, _renderer_id: RendererID, _scene_id: usize, _: &mut World) {}
+
+    fn render_to_scene(
+        &mut self,
+        renderer_id: RendererID,
+        scene_id: usize,
+        _frame: usize,
+        world: &mut World,
+    ) {
+        if self.rendered_in.get(renderer_id).is_some() {
+            return;
+        }
+
+        // each movement is drawn as wide as it is busy
+        for &kind in &ALL_MOVEMENT_KINDS {
+            let geometry: Geometry = self.movements
+                .iter()
+                .filter(|movement| movement.kind == kind && self.is_picked(movement))
+                .map(|movement| {
+                    let width = (0.3 + movement.per_hour() as f32 / 100.0).min(3.0);
+                    band_to_geometry(&Band::new(movement.path.clone(), width), 0.5)
+                })
+                .sum();
+            renderer_id.update_individual(
+                scene_id,
+                DIAGRAM_INDIVIDUAL_ID + kind.idx() as u16,
+                geometry,
+                Instance::with_color(kind.color()),
+                true,
+                world,
+            );
+        }
+        self.rendered_in.insert(renderer_id, ());
+    }
+}
+
+impl Interactable2d for TurningMovements {
+    fn draw_ui_2d(
+        &mut self,
+        imgui_ui: &External<::imgui::Ui<'static>>,
+        return_to: UserInterfaceID,
+        world: &mut World,
+    ) {
+        let ui = imgui_ui.steal();
+        let mut close = false;
+
+        if self.intersection.is_some() {
+            ui.window(im_str!("Turning Movements"))
+                .size((300.0, 200.0), ImGuiSetCond_FirstUseEver)
+                .collapsible(true)
+                .build(|| {
+                    ui.text(im_str!("Cars in the last hour, by approach:"));
+                    for &approach in &["Northbound", "Eastbound", "Southbound", "Westbound"] {
+                        let mut per_kind = [0; 4];
+                        for movement in self.movements.iter().filter(|movement| {
+                            self.is_picked(movement) &&
+                                approach_name(movement.path.start_direction()) == approach
+                        })
+                        {
+                            per_kind[movement.kind.idx()] += movement.per_hour();
+                        }
+                        if per_kind.iter().any(|&count| count > 0) {
+                            let counts = ALL_MOVEMENT_KINDS
+                                .iter()
+                                .map(|kind| format!("{} {}", per_kind[kind.idx()], kind.name()))
+                                .collect::<Vec<_>>();
+                            ui.text(im_str!("{}: {}", approach, counts.join(", ")));
+                        }
+                    }
+                    if ui.small_button(im_str!("Close")) {
+                        close = true;
+                    }
+                });
+        }
+
+        if close {
+            self.intersection = None;
+            self.rendered_in = CDict::new();
+        }
+
+        return_to.ui_drawn(ui, world);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MovementKind, approach_name};
+    use descartes::V2;
+
+    #[test]
+    fn movements_are_told_apart_by_their_directions() {
+        let east = V2::new(1.0, 0.0);
+        assert_eq!(MovementKind::of(east, east), MovementKind::Through);
+        assert_eq!(MovementKind::of(east, V2::new(0.0, 1.0)), MovementKind::Left);
+        assert_eq!(MovementKind::of(east, V2::new(0.0, -1.0)), MovementKind::Right);
+        assert_eq!(MovementKind::of(east, -east), MovementKind::UTurn);
+        assert_eq!(approach_name(east), "Eastbound");
+    }
+}
+
+pub fn setup(system: &mut ActorSystem, user_interface: UserInterfaceID, simulation: SimulationID) {
+    system.register::<TurningMovements>();
+    auto_setup(system);
+
+    TurningMovementsID::spawn(simulation, user_interface, &mut system.world());
+}
+
+mod kay_auto;
+pub use self::kay_auto::*;
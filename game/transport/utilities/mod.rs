@@ -0,0 +1,342 @@
+//! Underground water and power conduits, laid along roads with the planning tools.
+//! Every conduit can supply a number of households (depending on its length) in the
+//! buildings around it, and households without supply are less satisfied.
+//! Until the first conduit of a kind is built, buildings don't need that utility.
+//!
+//! Laying a conduit means roadworks: the lanes along it are closed for a few hours.
+use kay::{ActorSystem, World, External};
+use compact::CVec;
+use descartes::{N, P2, Band, Curve, FiniteCurve};
+use monet::{RendererID, Geometry, Instance};
+use stagemaster::{UserInterfaceID, Interactable2d, Interactable2dID,
+                  MSG_Interactable2d_draw_ui_2d};
+use stagemaster::geometry::{CPath, band_to_geometry};
+use imgui::ImGuiSetCond_FirstUseEver;
+use core::simulation::{SimulationID, Sleeper, SleeperID, MSG_Sleeper_wake, Timestamp, Ticks,
+                       TICKS_PER_SIM_HOUR};
+use economy::buildings::BuildingID;
+use super::lane::LaneID;
+
+const SETTINGS_CATEGORY: &'static str = "Utilities";
+const COLLECTION_TICKS: Ticks = Ticks(10);
+/// Buildings this close to a conduit can be supplied by it
+const SUPPLY_DISTANCE: N = 40.0;
+/// Lanes this close to a conduit being laid are closed for the roadworks
+const ROADWORKS_DISTANCE: N = 8.0;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum UtilityKind {
+    Water,
+    Power,
+}
+
+const ALL_KINDS: [UtilityKind; 2] = [UtilityKind::Water, UtilityKind::Power];
+
+impl UtilityKind {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            UtilityKind::Water => "water",
+            UtilityKind::Power => "power",
+        }
+    }
+
+    /// The kind the planning tool switches to next, or none to go back to drawing roads
+    pub fn next(&self) -> Option<UtilityKind> {
+        match *self {
+            UtilityKind::Water => Some(UtilityKind::Power),
+            UtilityKind::Power => None,
+        }
+    }
+
+    pub fn color(&self) -> [f32; 3] {
+        match *self {
+            UtilityKind::Water => [0.1, 0.4, 0.9],
+            UtilityKind::Power => [0.9, 0.8, 0.1],
+        }
+    }
+
+    fn overlay_individual_id(&self) -> u16 {
+        match *self {
+            UtilityKind::Water => 5516,
+            UtilityKind::Power => 5517,
+        }
+    }
+}
+
+#[derive(Compact, Clone)]
+pub struct Conduit {
+    pub kind: UtilityKind,
+    pub path: CPath,
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone)]
+pub struct UtilitySettings {
+    /// Households a conduit can supply per meter of its length
+    pub water_households_per_meter: f32,
+    pub power_households_per_meter: f32,
+    /// How long lanes along a new conduit stay closed
+    pub roadworks_hours: usize,
+}
+
+impl Default for UtilitySettings {
+    fn default() -> Self {
+        UtilitySettings {
+            water_households_per_meter: 0.5,
+            power_households_per_meter: 1.0,
+            roadworks_hours: 4,
+        }
+    }
+}
+
+impl UtilitySettings {
+    fn households_per_meter(&self, kind: UtilityKind) -> f32 {
+        match kind {
+            UtilityKind::Water => self.water_households_per_meter,
+            UtilityKind::Power => self.power_households_per_meter,
+        }
+    }
+}
+
+#[derive(Compact, Clone)]
+struct Roadworks {
+    path: CPath,
+    hours_left: usize,
+}
+
+#[derive(Copy, Clone)]
+struct UtilityDemand {
+    position: P2,
+    households: usize,
+}
+
+#[derive(Compact, Clone)]
+pub struct UtilityNetwork {
+    id: UtilityNetworkID,
+    simulation: SimulationID,
+    renderer_id: RendererID,
+    settings: UtilitySettings,
+    conduits: CVec<Conduit>,
+    roadworks: CVec<Roadworks>,
+    collecting: bool,
+    demands: CVec<UtilityDemand>,
+    households: usize,
+    /// Supplied households, per kind in the order of `ALL_KINDS`
+    supplied: CVec<usize>,
+    overlay: bool,
+}
+
+impl UtilityNetwork {
+    pub fn spawn(
+        id: UtilityNetworkID,
+        user_interface: UserInterfaceID,
+        simulation: SimulationID,
+        renderer_id: RendererID,
+        world: &mut World,
+    ) -> UtilityNetwork {
+        user_interface.add_2d(id.into(), world);
+        simulation.wake_up_in(Ticks(TICKS_PER_SIM_HOUR), id.into(), world);
+
+        UtilityNetwork {
+            id,
+            simulation,
+            renderer_id,
+            settings: ::ENV.load_settings(SETTINGS_CATEGORY),
+            conduits: CVec::new(),
+            roadworks: CVec::new(),
+            collecting: false,
+            demands: CVec::new(),
+            households: 0,
+            supplied: vec![0; ALL_KINDS.len()].into(),
+            overlay: false,
+        }
+    }
+
+    pub fn build_conduits(&mut self, conduits: &CVec<Conduit>, world: &mut World) {
+        for conduit in conduits.iter() {
+            LaneID::global_broadcast(world).close_along(
+                conduit.path.clone(),
+                ROADWORKS_DISTANCE,
+                world,
+            );
+            self.roadworks.push(Roadworks {
+                path: conduit.path.clone(),
+                hours_left: self.settings.roadworks_hours,
+            });
+            self.conduits.push(conduit.clone());
+        }
+        self.update_overlay(world);
+    }
+
+    pub fn add_demand(&mut self, position: P2, households: usize, _: &mut World) {
+        if self.collecting {
+            self.demands.push(UtilityDemand { position, households });
+        }
+    }
+
+    /// Positions of the buildings that `kind` reaches, each conduit supplying the
+    /// closest buildings first until its capacity is used up
+    fn supply(&self, kind: UtilityKind) -> (CVec<P2>, usize) {
+        let conduits = self.conduits
+            .iter()
+            .filter(|conduit| conduit.kind == kind)
+            .collect::<Vec<_>>();
+
+        if conduits.is_empty() {
+            return (
+                self.demands.iter().map(|demand| demand.position).collect(),
+                self.households,
+            );
+        }
+
+        let mut capacities = conduits
+            .iter()
+            .map(|conduit| {
+                conduit.path.length() * self.settings.households_per_meter(kind)
+            })
+            .collect::<Vec<_>>();
+
+        let mut candidates = self.demands
+            .iter()
+            .flat_map(|demand| {
+                conduits.iter().enumerate().map(move |(i, conduit)| {
+                    (conduit.path.distance_to(demand.position), i, *demand)
+                })
+            })
+            .filter(|&(distance, _, _)| distance < SUPPLY_DISTANCE)
+            .collect::<Vec<_>>();
+        candidates.sort_by(|&(a, _, _), &(b, _, _)| a.partial_cmp(&b).unwrap());
+
+        let mut supplied = CVec::<P2>::new();
+        let mut n_households = 0;
+        for (_, i, demand) in candidates {
+            let households = demand.households as f32;
+            if capacities[i] >= households && !supplied.contains(&demand.position) {
+                capacities[i] -= households;
+                supplied.push(demand.position);
+                n_households += demand.households;
+            }
+        }
+
+        (supplied, n_households)
+    }
+
+    fn update_overlay(&self, world: &mut World) {
+        for kind in &ALL_KINDS {
+            let geometry: Geometry = self.conduits
+                .iter()
+                .filter(|conduit| self.overlay && conduit.kind == *kind)
+                .map(|conduit| {
+                    band_to_geometry(&Band::new(conduit.path.clone(), 1.0), 0.05)
+                })
+                .sum();
+            self.renderer_id.update_individual(
+                0,
+                kind.overlay_individual_id(),
+                geometry,
+                Instance::with_color(kind.color()),
+                true,
+                world,
+            );
+        }
+    }
+}
+
+impl Sleeper for UtilityNetwork {
+    fn wake(&mut self, _current_tick: Timestamp, world: &mut World) {
+        if self.collecting {
+            self.collecting = false;
+            self.households = self.demands.iter().map(|demand| demand.households).sum();
+            let (water, n_water) = self.supply(UtilityKind::Water);
+            let (power, n_power) = self.supply(UtilityKind::Power);
+            self.supplied = vec![n_water, n_power].into();
+            BuildingID::global_broadcast(world).set_utility_supply(water, power, world);
+            self.simulation.wake_up_in(
+                Ticks(TICKS_PER_SIM_HOUR - COLLECTION_TICKS.0),
+                self.id.into(),
+                world,
+            );
+        } else {
+            for roadworks in self.roadworks.iter_mut() {
+                roadworks.hours_left = roadworks.hours_left.saturating_sub(1);
+                if roadworks.hours_left == 0 {
+                    LaneID::global_broadcast(world).reopen_along(
+                        roadworks.path.clone(),
+                        ROADWORKS_DISTANCE,
+                        world,
+                    );
+                }
+            }
+            self.roadworks.retain(|roadworks| roadworks.hours_left > 0);
+
+            self.collecting = true;
+            self.demands = CVec::new();
+            BuildingID::global_broadcast(world).report_utility_demand(self.id, world);
+            self.simulation.wake_up_in(COLLECTION_TICKS, self.id.into(), world);
+        }
+    }
+}
+
+impl Interactable2d for UtilityNetwork {
+    fn draw_ui_2d(
+        &mut self,
+        imgui_ui: &External<::imgui::Ui<'static>>,
+        return_to: UserInterfaceID,
+        world: &mut World,
+    ) {
+        let ui = imgui_ui.steal();
+        let mut overlay = self.overlay;
+
+        ui.window(im_str!("Utilities"))
+            .size((250.0, 180.0), ImGuiSetCond_FirstUseEver)
+            .collapsible(true)
+            .build(|| {
+                for (i, kind) in ALL_KINDS.iter().enumerate() {
+                    let length: N = self.conduits
+                        .iter()
+                        .filter(|conduit| conduit.kind == *kind)
+                        .map(|conduit| conduit.path.length())
+                        .sum();
+                    ui.text(im_str!(
+                        "{}: {:.1} km, {} of {} households",
+                        kind.name(),
+                        length / 1000.0,
+                        self.supplied[i],
+                        self.households
+                    ));
+                }
+                ui.text(im_str!("{} roadworks going on", self.roadworks.len()));
+
+                ui.text(im_str!("Conduit overlay:"));
+                if ui.small_button(im_str!("On")) {
+                    overlay = true;
+                }
+                ui.same_line(0.0);
+                if ui.small_button(im_str!("Off")) {
+                    overlay = false;
+                }
+            });
+
+        if overlay != self.overlay {
+            self.overlay = overlay;
+            self.update_overlay(world);
+        }
+
+        return_to.ui_drawn(ui, world);
+    }
+}
+
+pub fn setup(
+    system: &mut ActorSystem,
+    user_interface: UserInterfaceID,
+    simulation: SimulationID,
+    renderer_id: RendererID,
+) {
+    system.register::<UtilityNetwork>();
+    system.make_restorable::<UtilityNetwork>();
+    auto_setup(system);
+
+    UtilityNetworkID::spawn(user_interface, simulation, renderer_id, &mut system.world());
+}
+
+mod kay_auto;
+pub use self::kay_auto::*;